@@ -0,0 +1,63 @@
+//! Fault injection into durable consumers. With `CHAOS_ENABLED` set, the messages of a
+//! stream's consumer are delayed before they reach the handler, dropped without an ack so
+//! the server redelivers them after the ack wait, or nacked as if their handler had failed,
+//! at the rates set for the stream's target, e.g. `CHAOS_RAW_TEXT_DROP_RATE`.
+
+use async_nats::jetstream::{self, AckKind, consumer};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use log::warn;
+use shared_resilience::{Chaos, Fault};
+use std::sync::Arc;
+
+/// Messages pulled from a durable consumer, see [`crate::durable_messages`].
+pub type DurableMessages =
+    BoxStream<'static, Result<jetstream::Message, consumer::pull::MessagesError>>;
+
+/// `messages` with the faults of `chaos` injected; as they are when it is `None`.
+pub(crate) fn inject_faults(
+    messages: consumer::pull::Stream,
+    chaos: Option<Chaos>,
+) -> DurableMessages {
+    let Some(chaos) = chaos else {
+        return messages.boxed();
+    };
+    let chaos = Arc::new(chaos);
+    messages
+        .filter_map(move |next| {
+            let chaos = Arc::clone(&chaos);
+            async move {
+                let message = match &next {
+                    Ok(message) => message,
+                    Err(_) => return Some(next),
+                };
+                let Some(fault) = chaos.next_fault() else {
+                    return Some(next);
+                };
+                match fault {
+                    Fault::Delay(delay) => {
+                        tokio::time::sleep(delay).await;
+                        Some(next)
+                    }
+                    Fault::Drop => {
+                        warn!(
+                            "[CHAOS] Dropped a message on {}; it is redelivered after the ack wait.",
+                            message.subject
+                        );
+                        None
+                    }
+                    Fault::Error => {
+                        warn!(
+                            "[CHAOS] Failed a message on {}; it is redelivered now.",
+                            message.subject
+                        );
+                        if let Err(e) = message.ack_with(AckKind::Nak(None)).await {
+                            warn!("[CHAOS] Failed to nack message: {}", e);
+                        }
+                        None
+                    }
+                }
+            }
+        })
+        .boxed()
+}
@@ -0,0 +1,113 @@
+//! Connecting to NATS with the authentication and TLS options of [`NatsSettings`]: user
+//! and password, a token, an NKey seed or a credentials file, and server and client
+//! certificates.
+
+use async_nats::{Client, ConnectOptions};
+use log::info;
+use shared_config::NatsSettings;
+use std::fmt;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum ConnectError {
+    /// The credentials file could not be read or parsed.
+    Credentials {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// A client certificate was given without its key, or a key without its certificate.
+    ClientCertificate,
+    Connect {
+        url: String,
+        source: async_nats::ConnectError,
+    },
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectError::Credentials { path, source } => write!(
+                f,
+                "failed to load NATS credentials from {}: {}",
+                path.display(),
+                source
+            ),
+            ConnectError::ClientCertificate => write!(
+                f,
+                "NATS_TLS_CERT_FILE and NATS_TLS_KEY_FILE must be set together"
+            ),
+            ConnectError::Connect { url, source } => {
+                write!(f, "failed to connect to NATS at {}: {}", url, source)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConnectError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConnectError::Credentials { source, .. } => Some(source),
+            ConnectError::ClientCertificate => None,
+            ConnectError::Connect { source, .. } => Some(source),
+        }
+    }
+}
+
+/// Connects to `settings.url` with every credential `settings` holds; the server uses the
+/// ones its authorization needs.
+pub async fn connect(settings: &NatsSettings) -> Result<Client, ConnectError> {
+    let mut options = ConnectOptions::new();
+    let mut auth = Vec::new();
+    if let Some(path) = &settings.credentials_file {
+        let with_credentials = options.credentials_file(path).await;
+        options = with_credentials.map_err(|source| ConnectError::Credentials {
+            path: path.clone(),
+            source,
+        })?;
+        auth.push("credentials file");
+    }
+    if let Some(seed) = &settings.nkey {
+        options = options.nkey(seed.clone());
+        auth.push("nkey");
+    }
+    if let Some(token) = &settings.token {
+        options = options.token(token.clone());
+        auth.push("token");
+    }
+    if let Some(user) = &settings.user {
+        options =
+            options.user_and_password(user.clone(), settings.password.clone().unwrap_or_default());
+        auth.push("user and password");
+    }
+
+    let tls = &settings.tls;
+    options = options.require_tls(tls.required);
+    if let Some(ca_file) = &tls.ca_file {
+        options = options.add_root_certificates(ca_file.clone());
+    }
+    match (&tls.cert_file, &tls.key_file) {
+        (Some(cert_file), Some(key_file)) => {
+            options = options.add_client_certificate(cert_file.clone(), key_file.clone());
+        }
+        (None, None) => {}
+        _ => return Err(ConnectError::ClientCertificate),
+    }
+
+    info!(
+        "[NATS_CONNECT] Connecting to {} (auth: {}, TLS required: {})",
+        settings.url,
+        if auth.is_empty() {
+            "none".to_string()
+        } else {
+            auth.join(", ")
+        },
+        tls.required
+    );
+    options
+        .connect(&settings.url)
+        .await
+        .map_err(|source| ConnectError::Connect {
+            url: settings.url.clone(),
+            source,
+        })
+}
@@ -0,0 +1,64 @@
+//! Publishing to the dead-letter stream, see [`shared_models::dead_letter_subject`].
+
+use crate::publish_durable;
+use async_nats::HeaderMap;
+use async_nats::jetstream::{self, AckKind};
+use log::{error, warn};
+use serde::Serialize;
+use shared_models::{DeadLetterMessage, Envelope, UndecodedPayload, dead_letter_subject};
+
+/// Publishes a message `service` gave up on to its [`dead_letter_subject`], where
+/// [`crate::DEAD_LETTERS_STREAM`] keeps it for inspection and replay.
+pub async fn publish_dead_letter<T: Serialize>(
+    jetstream: &jetstream::Context,
+    service: &str,
+    cause: Option<&Envelope<()>>,
+    dead_letter: &DeadLetterMessage<T>,
+) {
+    let subject = dead_letter_subject(service, &dead_letter.original_subject);
+    match Envelope::following(cause, service, dead_letter).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) =
+                publish_durable(jetstream, &subject, HeaderMap::new(), payload_json).await
+            {
+                error!(
+                    "[DLQ_PUBLISH_FAIL] Failed to dead-letter a message to {}: {}. It is lost.",
+                    subject, e
+                );
+            } else {
+                warn!(
+                    "[DLQ_PUBLISHED] Dead-lettered a message to {}: {}",
+                    subject, dead_letter.error_message
+                );
+            }
+        }
+        Err(e) => error!(
+            "[DLQ_SERIALIZE_FAIL] Failed to serialize dead letter: {}",
+            e
+        ),
+    }
+}
+
+/// Dead-letters a durable message whose payload could not be decoded, kept as `undecoded`,
+/// and terminates it: it would not decode on redelivery either.
+pub async fn dead_letter_undecodable(
+    jetstream: &jetstream::Context,
+    service: &str,
+    message: &jetstream::Message,
+    undecoded: UndecodedPayload,
+    error_message: impl Into<String>,
+) {
+    publish_dead_letter(
+        jetstream,
+        service,
+        None,
+        &DeadLetterMessage::new(message.subject.as_str(), undecoded, error_message, 1),
+    )
+    .await;
+    if let Err(e) = message.ack_with(AckKind::Term).await {
+        error!(
+            "[NATS_ACK_FAIL] Failed to terminate undecodable message on {}: {}",
+            message.subject, e
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Deduplication of pipeline messages on both ends. Publishers mark what they publish
+//! while handling a message with a `Nats-Msg-Id` derived from that message (see
+//! [`insert_message_id`]), so handling a redelivery publishes the same ids and the stream
+//! drops the repeats within its duplicate window. Consumers remember what they recently
+//! handled ([`RecentMessages`]), so a redelivery whose ack was lost is acked again instead
+//! of being stored twice.
+
+use async_nats::HeaderMap;
+use async_nats::header::NATS_MESSAGE_ID;
+use async_nats::jetstream;
+use log::{info, warn};
+use shared_config::env_parse_or;
+use shared_models::Envelope;
+use std::collections::{HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+
+/// Namespace of the derived message ids. Changing it lets one copy of every message in
+/// the duplicate window through again.
+const MESSAGE_ID_NAMESPACE: uuid::Uuid =
+    uuid::Uuid::from_u128(0x2d7e_91c4_0b3a_4f65_a8d1_5c9e_7f20_3b46);
+
+const DEFAULT_RECENT_MESSAGES: usize = 10_000;
+
+/// Sets the `Nats-Msg-Id` of the message published on `subject` for `key` while handling
+/// `cause`. Use a `key` per message when handling one cause publishes several on a subject,
+/// e.g. the document id.
+pub fn insert_message_id<C>(
+    headers: &mut HeaderMap,
+    cause: &Envelope<C>,
+    subject: &str,
+    key: &str,
+) {
+    headers.insert(
+        NATS_MESSAGE_ID,
+        derive_message_id(&cause.message_id, subject, key).as_str(),
+    );
+}
+
+fn derive_message_id(cause_message_id: &str, subject: &str, key: &str) -> String {
+    uuid::Uuid::new_v5(
+        &MESSAGE_ID_NAMESPACE,
+        format!("{}/{}/{}", cause_message_id, subject, key).as_bytes(),
+    )
+    .to_string()
+}
+
+enum Claim {
+    New(ClaimGuard),
+    /// Another delivery of the message is still being handled; its ack covers this one.
+    InProgress,
+    Handled,
+}
+
+/// The ids of the messages a consumer is handling and of the last ones it handled. Clones
+/// share the same record.
+#[derive(Clone)]
+pub struct RecentMessages {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    capacity: usize,
+    in_progress: HashSet<String>,
+    handled: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl RecentMessages {
+    /// Remembers the last `capacity` handled messages; 0 disables deduplication.
+    pub fn new(capacity: usize) -> Self {
+        RecentMessages {
+            state: Arc::new(Mutex::new(State {
+                capacity,
+                in_progress: HashSet::new(),
+                handled: HashSet::new(),
+                order: VecDeque::new(),
+            })),
+        }
+    }
+
+    /// Capacity from `NATS_DEDUP_CAPACITY`, 10000 when unset.
+    pub fn from_env() -> Self {
+        RecentMessages::new(env_parse_or("NATS_DEDUP_CAPACITY", DEFAULT_RECENT_MESSAGES))
+    }
+
+    /// Claims a delivery of `envelope` for handling; call [`ClaimGuard::handled`] once it
+    /// is acked. A repeat of a message already handled is acked here, and one of a message
+    /// still being handled is left to that delivery's ack; both give `None`. Messages are
+    /// told apart by their `Nats-Msg-Id`, so a publisher's retry counts as the same
+    /// message, or else by their envelope's id.
+    pub async fn claim_delivery<C>(
+        &self,
+        message: &jetstream::Message,
+        envelope: &Envelope<C>,
+    ) -> Option<ClaimGuard> {
+        let message_id = message
+            .headers
+            .as_ref()
+            .and_then(|headers| headers.get(NATS_MESSAGE_ID))
+            .map(|value| value.as_str())
+            .unwrap_or(&envelope.message_id);
+        match self.claim_id(message_id) {
+            Claim::New(guard) => Some(guard),
+            Claim::InProgress => {
+                info!(
+                    "[NATS_DEDUP] Message {} on {} is still being handled; skipping its redelivery.",
+                    message_id, message.subject
+                );
+                None
+            }
+            Claim::Handled => {
+                info!(
+                    "[NATS_DEDUP] Message {} on {} was already handled; acking its redelivery.",
+                    message_id, message.subject
+                );
+                if let Err(e) = message.ack().await {
+                    warn!("[NATS_DEDUP] Failed to ack redelivered message: {}", e);
+                }
+                None
+            }
+        }
+    }
+
+    fn claim_id(&self, message_id: &str) -> Claim {
+        let mut state = self.lock();
+        if state.capacity == 0 {
+            return Claim::New(ClaimGuard {
+                state: None,
+                message_id: String::new(),
+                handled: false,
+            });
+        }
+        if state.handled.contains(message_id) {
+            return Claim::Handled;
+        }
+        if !state.in_progress.insert(message_id.to_string()) {
+            return Claim::InProgress;
+        }
+        Claim::New(ClaimGuard {
+            state: Some(Arc::clone(&self.state)),
+            message_id: message_id.to_string(),
+            handled: false,
+        })
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A claimed delivery. Dropped without [`ClaimGuard::handled`], e.g. when its handler
+/// panics, it releases the message for the next delivery.
+#[must_use = "the message is released as soon as the guard is dropped"]
+pub struct ClaimGuard {
+    state: Option<Arc<Mutex<State>>>,
+    message_id: String,
+    handled: bool,
+}
+
+impl ClaimGuard {
+    /// Records the message as handled, once its delivery has been acked.
+    pub fn handled(mut self) {
+        self.handled = true;
+    }
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        let Some(state) = &self.state else {
+            return;
+        };
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        state.in_progress.remove(&self.message_id);
+        if !self.handled {
+            return;
+        }
+        let message_id = std::mem::take(&mut self.message_id);
+        if state.handled.insert(message_id.clone()) {
+            state.order.push_back(message_id);
+        }
+        while state.order.len() > state.capacity {
+            if let Some(oldest) = state.order.pop_front() {
+                state.handled.remove(&oldest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_derived_message_ids_are_stable_per_cause_subject_and_key() {
+        let id = derive_message_id("cause", "data.raw_text.discovered", "");
+        assert_eq!(
+            id,
+            derive_message_id("cause", "data.raw_text.discovered", "")
+        );
+        assert_ne!(
+            id,
+            derive_message_id("other", "data.raw_text.discovered", "")
+        );
+        assert_ne!(
+            id,
+            derive_message_id("cause", "data.text.with_embeddings", "")
+        );
+        assert_ne!(
+            id,
+            derive_message_id("cause", "data.raw_text.discovered", "doc")
+        );
+    }
+
+    #[test]
+    fn test_claims() {
+        let recent = RecentMessages::new(2);
+        let Claim::New(first) = recent.claim_id("a") else {
+            panic!("a is new");
+        };
+        assert!(matches!(recent.claim_id("a"), Claim::InProgress));
+        first.handled();
+        assert!(matches!(recent.claim_id("a"), Claim::Handled));
+
+        // A claim dropped unhandled lets the next delivery through.
+        let Claim::New(released) = recent.claim_id("b") else {
+            panic!("b is new");
+        };
+        drop(released);
+        let Claim::New(second) = recent.claim_id("b") else {
+            panic!("b was released");
+        };
+        second.handled();
+
+        let Claim::New(third) = recent.claim_id("c") else {
+            panic!("c is new");
+        };
+        third.handled();
+        assert!(matches!(recent.claim_id("a"), Claim::New(_)));
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_deduplication() {
+        let recent = RecentMessages::new(0);
+        let Claim::New(first) = recent.claim_id("a") else {
+            panic!("a is new");
+        };
+        first.handled();
+        assert!(matches!(recent.claim_id("a"), Claim::New(_)));
+    }
+}
@@ -0,0 +1,96 @@
+//! Answering the health checks of the `health.<service>` convention (see
+//! [`shared_models::health_subject`]). [`serve_health`] checks the NATS connection itself
+//! and adds the service's own dependency checks; the same report backs `/healthz` on the
+//! metrics endpoint.
+
+use async_nats::{Client, SubscribeError};
+use futures::StreamExt;
+use log::{error, info};
+use shared_models::{DependencyCheck, Envelope, ServiceHealthResult, health_subject};
+use shared_telemetry::HealthReport;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long the NATS check waits for the server to answer a ping.
+const NATS_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Times a ping round trip to the NATS server.
+pub async fn check_nats(client: &Client) -> DependencyCheck {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(NATS_CHECK_TIMEOUT, client.flush()).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err(format!(
+            "no answer from the server within {:?}",
+            NATS_CHECK_TIMEOUT
+        )),
+    };
+    DependencyCheck::timed("nats", started.elapsed(), result)
+}
+
+/// Answers requests on the [`health_subject`] of `service` and `GET /healthz` with a
+/// [`ServiceHealthResult`] of the NATS connection and `dependency_checks()`. Call it once
+/// startup is done, so `/healthz` only reports ready when the service can take work.
+pub async fn serve_health<F, Fut>(
+    client: Client,
+    service: &'static str,
+    dependency_checks: F,
+) -> Result<(), SubscribeError>
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Vec<DependencyCheck>> + Send + 'static,
+{
+    let subject = health_subject(service);
+    let mut requests = client.subscribe(subject.clone()).await?;
+    info!(
+        "[HEALTH] Answering health checks on {} and /healthz",
+        subject
+    );
+
+    let check = {
+        let client = client.clone();
+        Arc::new(move || {
+            let client = client.clone();
+            let dependency_checks = dependency_checks();
+            async move {
+                let started = Instant::now();
+                let mut checks = vec![check_nats(&client).await];
+                checks.extend(dependency_checks.await);
+                ServiceHealthResult::from_checks(service, checks, started.elapsed())
+            }
+        })
+    };
+
+    let http_check = Arc::clone(&check);
+    shared_telemetry::set_health_check(move || {
+        let result = http_check();
+        async move {
+            let result = result.await;
+            HealthReport {
+                available: result.is_available(),
+                body: serde_json::to_string(&result).unwrap_or_default(),
+            }
+        }
+    });
+
+    tokio::spawn(async move {
+        while let Some(message) = requests.next().await {
+            let Some(reply_to) = message.reply else {
+                continue;
+            };
+            let result = check().await;
+            match Envelope::new(service, &result).to_vec() {
+                Ok(payload) => {
+                    if let Err(e) = client.publish(reply_to, payload.into()).await {
+                        error!("[HEALTH] Failed to reply to health check: {}", e);
+                    }
+                }
+                Err(e) => {
+                    error!("[HEALTH] Failed to serialize ServiceHealthResult: {}", e);
+                }
+            }
+        }
+        info!("[HEALTH] Health check subscription on {} ended.", subject);
+    });
+    Ok(())
+}
@@ -0,0 +1,654 @@
+//! JetStream plumbing shared by the services, so pipeline messages survive a restart of
+//! their consumer: the streams that persist the `data.*` subjects and the fire-and-forget
+//! `tasks.*` subjects, durable pull consumers with explicit acks on them, and publishing
+//! that waits until the stream has stored a message. The streams drop a message published
+//! again within their duplicate window, and consumers skip the ones they already handled
+//! (see [`insert_message_id`] and [`RecentMessages`]). Dead letters of every service are
+//! published with [`publish_dead_letter`], kept in [`DEAD_LETTERS_STREAM`] and read back with
+//! [`stored_messages`] for inspection and replay.
+//! Services connect with the credentials and TLS options of their settings through
+//! [`connect`]. Messages carry the trace context of their publisher (see
+//! [`receive_span`]), handlers run in a bounded [`WorkerPool`], and every service answers
+//! health checks through [`serve_health`] and stops through [`Shutdown`]. Faults can be
+//! injected into durable consumers for resilience testing (see [`durable_messages`]), and
+//! submissions are held to the ingestion quotas of their tenant (see [`Quotas`]).
+//!
+//! Request/reply subjects such as `tasks.search.semantic.request` stay on core NATS: a
+//! stream capturing them would answer every request with its publish ack. Replicas share
+//! them through a queue group (see [`subscribe_shared`]), and requests to them go through a
+//! circuit breaker per subject (see [`request_guarded`]); responders answer through
+//! [`publish_reply`].
+
+use async_nats::jetstream::{self, consumer, context, stream};
+use async_nats::{HeaderMap, header};
+use log::{info, warn};
+use shared_config::{env_parse_or, env_var};
+use shared_resilience::Chaos;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+mod chaos;
+mod connect;
+mod dead_letter;
+mod dedup;
+mod health;
+mod queue;
+mod quota;
+mod reply;
+mod request;
+mod shutdown;
+mod trace;
+mod workers;
+
+pub use chaos::DurableMessages;
+pub use connect::{ConnectError, connect};
+pub use dead_letter::{dead_letter_undecodable, publish_dead_letter};
+pub use dedup::{ClaimGuard, RecentMessages, insert_message_id};
+pub use health::{check_nats, serve_health};
+pub use queue::{queue_group_from_env, subscribe_shared};
+pub use quota::{QUOTA_USAGE_BUCKET, QuotaConfig, QuotaLimits, Quotas, UrlAdmission};
+pub use reply::publish_reply;
+pub use request::request_guarded;
+pub use shutdown::{InFlightGuard, Shutdown};
+pub use trace::{inject_trace_context, receive_span, traced_headers};
+pub use workers::{OVERFLOW_REDELIVERY_DELAY, OverflowPolicy, Rejected, WorkerPool, WorkerSlot};
+
+const DEFAULT_ACK_WAIT: Duration = Duration::from_secs(60);
+const DEFAULT_MAX_DELIVER: i64 = 5;
+const DEFAULT_MAX_ACK_PENDING: i64 = 64;
+/// Longer than the ack wait of every consumer, so a redelivered message's publishes are
+/// still recognised as repeats.
+const DEFAULT_DUPLICATE_WINDOW: Duration = Duration::from_secs(600);
+const DAY: Duration = Duration::from_secs(24 * 3600);
+const GIB: i64 = 1024 * 1024 * 1024;
+/// Keeps a week of pipeline messages: longer than any consumer stays down, and enough for
+/// captures sourcing the streams to catch up.
+const PIPELINE_LIMITS: StreamLimits = StreamLimits {
+    max_age: Duration::from_secs(7 * DAY.as_secs()),
+    max_bytes: 10 * GIB,
+    max_messages: -1,
+};
+/// Dead letters wait for someone to inspect and replay them, so they are kept longer.
+const DEAD_LETTER_LIMITS: StreamLimits = StreamLimits {
+    max_age: Duration::from_secs(30 * DAY.as_secs()),
+    max_bytes: GIB,
+    max_messages: -1,
+};
+
+/// A stream and the subjects it captures. Its name can be overridden with
+/// `NATS_<NAME>_STREAM`, which must then be set the same for publishers and consumers.
+#[derive(Debug, Clone, Copy)]
+pub struct StreamSpec {
+    pub name: &'static str,
+    pub subjects: &'static [&'static str],
+    pub limits: StreamLimits,
+}
+
+/// How much a stream keeps before discarding its oldest messages; `-1` is unlimited.
+/// Streams keep messages by these limits rather than until they are acked: dead letters
+/// are replayed and captures source the pipeline streams from what is stored, and a
+/// stream keeping messages only for its consumers would drop those published before
+/// the first consumer exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamLimits {
+    pub max_age: Duration,
+    pub max_bytes: i64,
+    pub max_messages: i64,
+}
+
+impl StreamLimits {
+    /// Applies `NATS_<STREAM>_MAX_AGE_SECS`, `_MAX_BYTES` and `_MAX_MESSAGES`, with 0 for
+    /// unlimited, looked up by `lookup`.
+    pub fn apply_overrides(
+        &mut self,
+        stream: &StreamSpec,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) {
+        let key = |suffix: &str| format!("NATS_{}_{}", stream.name, suffix);
+        if let Some(raw) = lookup(&key("MAX_AGE_SECS")) {
+            let secs = parse_or(&key("MAX_AGE_SECS"), &raw, self.max_age.as_secs());
+            self.max_age = Duration::from_secs(secs);
+        }
+        let unlimited_at_zero = |limit: i64| if limit <= 0 { -1 } else { limit };
+        if let Some(raw) = lookup(&key("MAX_BYTES")) {
+            self.max_bytes = unlimited_at_zero(parse_or(&key("MAX_BYTES"), &raw, self.max_bytes));
+        }
+        if let Some(raw) = lookup(&key("MAX_MESSAGES")) {
+            self.max_messages =
+                unlimited_at_zero(parse_or(&key("MAX_MESSAGES"), &raw, self.max_messages));
+        }
+    }
+}
+
+/// `PerceiveUrlTask`s from api_service to perception_service.
+pub const PERCEIVE_TASKS_STREAM: StreamSpec = StreamSpec {
+    name: "PERCEIVE_TASKS",
+    subjects: &["tasks.perceive.url"],
+    limits: PIPELINE_LIMITS,
+};
+/// Scraped documents from perception_service to preprocessing_service.
+pub const RAW_TEXT_STREAM: StreamSpec = StreamSpec {
+    name: "RAW_TEXT",
+    subjects: &["data.raw_text.discovered"],
+    limits: PIPELINE_LIMITS,
+};
+/// Re-embedding tasks from vector_memory_service to preprocessing_service.
+pub const REEMBED_TASKS_STREAM: StreamSpec = StreamSpec {
+    name: "REEMBED_TASKS",
+    subjects: &["tasks.embedding.reembed"],
+    limits: PIPELINE_LIMITS,
+};
+/// Embedded sentences from preprocessing_service to vector_memory_service.
+pub const EMBEDDINGS_STREAM: StreamSpec = StreamSpec {
+    name: "EMBEDDINGS",
+    subjects: &["data.text.with_embeddings"],
+    limits: PIPELINE_LIMITS,
+};
+/// Tokenized documents for knowledge_graph_service.
+pub const TOKENIZED_TEXT_STREAM: StreamSpec = StreamSpec {
+    name: "TOKENIZED_TEXT",
+    subjects: &["data.processed_text.tokenized"],
+    limits: PIPELINE_LIMITS,
+};
+/// Messages any service gave up on, under `dlq.<service>.<original subject>`.
+pub const DEAD_LETTERS_STREAM: StreamSpec = StreamSpec {
+    name: "DEAD_LETTERS",
+    subjects: &["dlq.>"],
+    limits: DEAD_LETTER_LIMITS,
+};
+
+impl StreamSpec {
+    pub fn stream_name(&self) -> String {
+        env_var(&format!("NATS_{}_STREAM", self.name))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .unwrap_or_else(|| self.name.to_string())
+    }
+
+    /// [`StreamSpec::limits`] with the overrides of [`StreamLimits::apply_overrides`].
+    pub fn limits_from_env(&self) -> StreamLimits {
+        let mut limits = self.limits;
+        limits.apply_overrides(self, env_var);
+        limits
+    }
+
+    /// Gets the stream, creating it when this is the first service to need it. A stream
+    /// created here drops repeats of a `Nats-Msg-Id` within `NATS_DUPLICATE_WINDOW_SECS`
+    /// (default 600); an existing stream keeps its window. Both are held to
+    /// [`StreamSpec::limits_from_env`], so limits changed since a stream was created apply
+    /// to it too.
+    pub async fn ensure(
+        &self,
+        jetstream: &jetstream::Context,
+    ) -> Result<stream::Stream, JetStreamError> {
+        let name = self.stream_name();
+        let limits = self.limits_from_env();
+        let stream_error = |source| JetStreamError::Stream {
+            stream: name.clone(),
+            source,
+        };
+        let stream = jetstream
+            .get_or_create_stream(stream::Config {
+                name: name.clone(),
+                subjects: self.subjects.iter().map(|s| s.to_string()).collect(),
+                // Lets stored messages be read by sequence or subject, see `stored_messages`.
+                allow_direct: true,
+                duplicate_window: Duration::from_secs(env_parse_or(
+                    "NATS_DUPLICATE_WINDOW_SECS",
+                    DEFAULT_DUPLICATE_WINDOW.as_secs(),
+                )),
+                max_age: limits.max_age,
+                max_bytes: limits.max_bytes,
+                max_messages: limits.max_messages,
+                ..Default::default()
+            })
+            .await
+            .map_err(stream_error)?;
+
+        let config = &stream.cached_info().config;
+        let current = StreamLimits {
+            max_age: config.max_age,
+            max_bytes: config.max_bytes,
+            max_messages: config.max_messages,
+        };
+        if current != limits {
+            info!(
+                "[JETSTREAM] Changing the limits of stream {} from {:?} to {:?}.",
+                name, current, limits
+            );
+            let config = stream::Config {
+                max_age: limits.max_age,
+                max_bytes: limits.max_bytes,
+                max_messages: limits.max_messages,
+                ..config.clone()
+            };
+            jetstream
+                .update_stream(&config)
+                .await
+                .map_err(stream_error)?;
+        }
+        Ok(stream)
+    }
+}
+
+#[derive(Debug)]
+pub enum JetStreamError {
+    Stream {
+        stream: String,
+        source: context::CreateStreamError,
+    },
+    Consumer {
+        durable_name: String,
+        source: stream::ConsumerError,
+    },
+    Messages {
+        durable_name: String,
+        source: consumer::StreamError,
+    },
+    Publish {
+        subject: String,
+        source: context::PublishError,
+    },
+    Read {
+        stream: String,
+        source: stream::DirectGetError,
+    },
+    Delete {
+        stream: String,
+        sequence: u64,
+        source: stream::DeleteMessageError,
+    },
+    KeyValue {
+        bucket: String,
+        source: context::CreateKeyValueError,
+    },
+}
+
+impl fmt::Display for JetStreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JetStreamError::Stream { stream, source } => {
+                write!(f, "failed to get or create stream {}: {}", stream, source)
+            }
+            JetStreamError::Consumer {
+                durable_name,
+                source,
+            } => write!(
+                f,
+                "failed to get or create consumer {}: {}",
+                durable_name, source
+            ),
+            JetStreamError::Messages {
+                durable_name,
+                source,
+            } => write!(f, "failed to start consuming {}: {}", durable_name, source),
+            JetStreamError::Publish { subject, source } => {
+                write!(f, "failed to publish to {}: {}", subject, source)
+            }
+            JetStreamError::Read { stream, source } => {
+                write!(f, "failed to read from stream {}: {}", stream, source)
+            }
+            JetStreamError::Delete {
+                stream,
+                sequence,
+                source,
+            } => write!(
+                f,
+                "failed to delete message {} from stream {}: {}",
+                sequence, stream, source
+            ),
+            JetStreamError::KeyValue { bucket, source } => write!(
+                f,
+                "failed to get or create key-value bucket {}: {}",
+                bucket, source
+            ),
+        }
+    }
+}
+
+impl std::error::Error for JetStreamError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            JetStreamError::Stream { source, .. } => Some(source),
+            JetStreamError::Consumer { source, .. } => Some(source),
+            JetStreamError::Messages { source, .. } => Some(source),
+            JetStreamError::Publish { source, .. } => Some(source),
+            JetStreamError::Read { source, .. } => Some(source),
+            JetStreamError::Delete { source, .. } => Some(source),
+            JetStreamError::KeyValue { source, .. } => Some(source),
+        }
+    }
+}
+
+/// A durable pull consumer with explicit acks on one subject of a stream.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConsumerConfig {
+    pub durable_name: String,
+    pub filter_subject: String,
+    /// How long the server waits for an ack before redelivering a message.
+    pub ack_wait: Duration,
+    /// Deliveries of one message before the server stops redelivering it.
+    pub max_deliver: i64,
+    /// Unacked messages in flight at once; bounds concurrent handlers.
+    pub max_ack_pending: i64,
+}
+
+impl ConsumerConfig {
+    pub fn new(durable_name: impl Into<String>, filter_subject: impl Into<String>) -> Self {
+        ConsumerConfig {
+            durable_name: durable_name.into(),
+            filter_subject: filter_subject.into(),
+            ack_wait: DEFAULT_ACK_WAIT,
+            max_deliver: DEFAULT_MAX_DELIVER,
+            max_ack_pending: DEFAULT_MAX_ACK_PENDING,
+        }
+    }
+
+    pub fn with_ack_wait(mut self, ack_wait: Duration) -> Self {
+        self.ack_wait = ack_wait;
+        self
+    }
+
+    pub fn with_max_ack_pending(mut self, max_ack_pending: i64) -> Self {
+        self.max_ack_pending = max_ack_pending;
+        self
+    }
+
+    /// Applies `NATS_<STREAM>_DURABLE`, `_ACK_WAIT_SECS`, `_MAX_DELIVER` and
+    /// `_MAX_ACK_PENDING` for the consumer on `stream`.
+    pub fn with_env_overrides(mut self, stream: &StreamSpec) -> Self {
+        self.apply_overrides(stream, env_var);
+        info!(
+            "[CONFIG] JetStream consumer config for stream {}: {:?}",
+            stream.name, self
+        );
+        self
+    }
+
+    /// [`ConsumerConfig::with_env_overrides`] with variables looked up by `lookup`.
+    pub fn apply_overrides(
+        &mut self,
+        stream: &StreamSpec,
+        lookup: impl Fn(&str) -> Option<String>,
+    ) {
+        let key = |suffix: &str| format!("NATS_{}_{}", stream.name, suffix);
+        if let Some(durable_name) = lookup(&key("DURABLE"))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+        {
+            self.durable_name = durable_name;
+        }
+        if let Some(raw) = lookup(&key("ACK_WAIT_SECS")) {
+            let secs = parse_or(&key("ACK_WAIT_SECS"), &raw, self.ack_wait.as_secs());
+            self.ack_wait = Duration::from_secs(secs.max(1));
+        }
+        if let Some(raw) = lookup(&key("MAX_DELIVER")) {
+            self.max_deliver = parse_or(&key("MAX_DELIVER"), &raw, self.max_deliver);
+        }
+        if let Some(raw) = lookup(&key("MAX_ACK_PENDING")) {
+            self.max_ack_pending = parse_or(&key("MAX_ACK_PENDING"), &raw, self.max_ack_pending);
+        }
+    }
+}
+
+fn parse_or<T: FromStr>(key: &str, raw: &str, default: T) -> T {
+    raw.trim().parse().unwrap_or_else(|_| {
+        warn!(
+            "[CONFIG] Invalid value '{}' for {}, using default",
+            raw, key
+        );
+        default
+    })
+}
+
+/// Ensures `stream` and the durable consumer described by `config`, and starts pulling its
+/// messages. Each message must be acked once handled, or it is redelivered after
+/// `ack_wait`, up to `max_deliver` times. With `CHAOS_ENABLED` set, faults are injected at
+/// the rates of the stream's lowercased name, e.g. `CHAOS_RAW_TEXT_ERROR_RATE`.
+pub async fn durable_messages(
+    jetstream: &jetstream::Context,
+    stream: &StreamSpec,
+    config: &ConsumerConfig,
+) -> Result<DurableMessages, JetStreamError> {
+    let consumer = stream
+        .ensure(jetstream)
+        .await?
+        .get_or_create_consumer(
+            &config.durable_name,
+            consumer::pull::Config {
+                durable_name: Some(config.durable_name.clone()),
+                filter_subject: config.filter_subject.clone(),
+                ack_policy: consumer::AckPolicy::Explicit,
+                ack_wait: config.ack_wait,
+                max_deliver: config.max_deliver,
+                max_ack_pending: config.max_ack_pending,
+                ..Default::default()
+            },
+        )
+        .await
+        .map_err(|source| JetStreamError::Consumer {
+            durable_name: config.durable_name.clone(),
+            source,
+        })?;
+    let messages = consumer
+        .messages()
+        .await
+        .map_err(|source| JetStreamError::Messages {
+            durable_name: config.durable_name.clone(),
+            source,
+        })?;
+    info!(
+        "[NATS_SUB_SUCCESS] Consuming subject {} via JetStream stream {} (durable consumer {})",
+        config.filter_subject,
+        stream.stream_name(),
+        config.durable_name
+    );
+    Ok(chaos::inject_faults(
+        messages,
+        Chaos::from_env(&stream.name.to_lowercase()),
+    ))
+}
+
+/// Publishes to a subject captured by a stream and waits for the stream to store it, so
+/// a returned `Ok` means the message will reach the stream's consumers. The current span's
+/// trace context is added to `headers`.
+pub async fn publish_durable(
+    jetstream: &jetstream::Context,
+    subject: &str,
+    mut headers: HeaderMap,
+    payload: Vec<u8>,
+) -> Result<(), JetStreamError> {
+    inject_trace_context(&mut headers);
+    let publish_error = |source| JetStreamError::Publish {
+        subject: subject.to_string(),
+        source,
+    };
+    jetstream
+        .publish_with_headers(subject.to_string(), headers, payload.into())
+        .await
+        .map_err(publish_error)?
+        .await
+        .map_err(publish_error)?;
+    Ok(())
+}
+
+/// A message read back from a stream.
+#[derive(Debug, Clone)]
+pub struct StoredMessage {
+    pub sequence: u64,
+    pub subject: String,
+    pub headers: HeaderMap,
+    pub payload: Vec<u8>,
+}
+
+impl StoredMessage {
+    fn from_direct_get(message: jetstream::Message) -> Option<Self> {
+        let headers = message.headers.clone()?;
+        let sequence = headers.get(header::NATS_SEQUENCE)?.as_str().parse().ok()?;
+        let subject = headers.get(header::NATS_SUBJECT)?.as_str().to_string();
+        Some(StoredMessage {
+            sequence,
+            subject,
+            headers,
+            payload: message.payload.to_vec(),
+        })
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.as_str())
+    }
+}
+
+/// Reads up to `limit` messages of `stream` whose subject matches `subject_filter`, which
+/// may contain wildcards, oldest first and starting at sequence `from_sequence`.
+pub async fn stored_messages(
+    jetstream: &jetstream::Context,
+    stream: &StreamSpec,
+    subject_filter: &str,
+    from_sequence: u64,
+    limit: usize,
+) -> Result<Vec<StoredMessage>, JetStreamError> {
+    let name = stream.stream_name();
+    let handle = stream.ensure(jetstream).await?;
+    let mut messages = Vec::new();
+    let mut next_sequence = from_sequence.max(1);
+    while messages.len() < limit {
+        let message = match handle
+            .direct_get_next_for_subject(subject_filter, Some(next_sequence))
+            .await
+        {
+            Ok(message) => message,
+            Err(e) if e.kind() == stream::DirectGetErrorKind::NotFound => break,
+            Err(source) => {
+                return Err(JetStreamError::Read {
+                    stream: name,
+                    source,
+                });
+            }
+        };
+        let Some(stored) = StoredMessage::from_direct_get(message) else {
+            warn!(
+                "[NATS_READ] Stream {} returned a message without sequence headers",
+                name
+            );
+            break;
+        };
+        next_sequence = stored.sequence + 1;
+        messages.push(stored);
+    }
+    Ok(messages)
+}
+
+/// The message stored at `sequence` in `stream`, if it is still there.
+pub async fn stored_message(
+    jetstream: &jetstream::Context,
+    stream: &StreamSpec,
+    sequence: u64,
+) -> Result<Option<StoredMessage>, JetStreamError> {
+    let handle = stream.ensure(jetstream).await?;
+    match handle.direct_get(sequence).await {
+        Ok(message) => Ok(StoredMessage::from_direct_get(message)),
+        Err(e) if e.kind() == stream::DirectGetErrorKind::NotFound => Ok(None),
+        Err(source) => Err(JetStreamError::Read {
+            stream: stream.stream_name(),
+            source,
+        }),
+    }
+}
+
+/// Removes the message at `sequence` from `stream`; `false` if it was already gone.
+pub async fn delete_stored_message(
+    jetstream: &jetstream::Context,
+    stream: &StreamSpec,
+    sequence: u64,
+) -> Result<bool, JetStreamError> {
+    let handle = stream.ensure(jetstream).await?;
+    handle
+        .delete_message(sequence)
+        .await
+        .map_err(|source| JetStreamError::Delete {
+            stream: stream.stream_name(),
+            sequence,
+            source,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_consumer_overrides_are_keyed_by_stream() {
+        let mut config = ConsumerConfig::new("vector_memory_service", "data.text.with_embeddings")
+            .with_ack_wait(Duration::from_secs(120));
+        let vars = [
+            ("NATS_EMBEDDINGS_DURABLE", " storage "),
+            ("NATS_EMBEDDINGS_ACK_WAIT_SECS", "0"),
+            ("NATS_EMBEDDINGS_MAX_DELIVER", "not-a-number"),
+            ("NATS_RAW_TEXT_MAX_ACK_PENDING", "1"),
+        ];
+        config.apply_overrides(&EMBEDDINGS_STREAM, |key| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        });
+        assert_eq!(config.durable_name, "storage");
+        assert_eq!(config.ack_wait, Duration::from_secs(1));
+        assert_eq!(config.max_deliver, DEFAULT_MAX_DELIVER);
+        assert_eq!(config.max_ack_pending, DEFAULT_MAX_ACK_PENDING);
+        assert_eq!(config.filter_subject, "data.text.with_embeddings");
+    }
+
+    #[test]
+    fn test_stream_limits_overrides_are_keyed_by_stream() {
+        let vars = [
+            ("NATS_RAW_TEXT_MAX_AGE_SECS", " 3600 "),
+            ("NATS_RAW_TEXT_MAX_BYTES", "0"),
+            ("NATS_RAW_TEXT_MAX_MESSAGES", "many"),
+            ("NATS_EMBEDDINGS_MAX_MESSAGES", "5"),
+        ];
+        let lookup = |key: &str| {
+            vars.iter()
+                .find(|(name, _)| *name == key)
+                .map(|(_, value)| value.to_string())
+        };
+        let mut limits = RAW_TEXT_STREAM.limits;
+        limits.apply_overrides(&RAW_TEXT_STREAM, lookup);
+        assert_eq!(
+            limits,
+            StreamLimits {
+                max_age: Duration::from_secs(3600),
+                max_bytes: -1,
+                max_messages: PIPELINE_LIMITS.max_messages,
+            }
+        );
+
+        let mut limits = DEAD_LETTERS_STREAM.limits;
+        limits.apply_overrides(&DEAD_LETTERS_STREAM, lookup);
+        assert_eq!(limits, DEAD_LETTER_LIMITS);
+        assert!(DEAD_LETTER_LIMITS.max_age > PIPELINE_LIMITS.max_age);
+    }
+
+    #[test]
+    fn test_streams_do_not_overlap() {
+        let streams = [
+            PERCEIVE_TASKS_STREAM,
+            RAW_TEXT_STREAM,
+            REEMBED_TASKS_STREAM,
+            EMBEDDINGS_STREAM,
+            TOKENIZED_TEXT_STREAM,
+        ];
+        let mut subjects: Vec<&str> = streams.iter().flat_map(|s| s.subjects.to_vec()).collect();
+        assert!(subjects.iter().all(|s| !s.starts_with("dlq.")));
+        let total = subjects.len();
+        subjects.sort_unstable();
+        subjects.dedup();
+        assert_eq!(subjects.len(), total);
+        assert!(
+            subjects
+                .iter()
+                .all(|s| !s.contains('*') && !s.contains('>'))
+        );
+    }
+}
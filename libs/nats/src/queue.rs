@@ -0,0 +1,55 @@
+//! Queue groups for core NATS subscriptions, so replicas of a service split the requests
+//! they serve instead of each handling every one. The JetStream pipeline subjects are
+//! already shared through their durable consumers.
+
+use async_nats::{Client, SubscribeError, Subscriber};
+use shared_config::env_var;
+
+/// The queue group named by `variable`, `default` when it is unset; `off` or an empty value
+/// gives `None`, so every instance receives every message.
+pub fn queue_group_from_env(variable: &str, default: &str) -> Option<String> {
+    parse_queue_group(env_var(variable).as_deref(), default)
+}
+
+fn parse_queue_group(value: Option<&str>, default: &str) -> Option<String> {
+    let group = value.unwrap_or(default).trim();
+    (!group.is_empty() && !group.eq_ignore_ascii_case("off")).then(|| group.to_string())
+}
+
+/// Subscribes to `subject` in `queue_group`, or on its own when there is none.
+pub async fn subscribe_shared(
+    client: &Client,
+    subject: &str,
+    queue_group: Option<&str>,
+) -> Result<Subscriber, SubscribeError> {
+    match queue_group {
+        Some(queue_group) => {
+            client
+                .queue_subscribe(subject.to_string(), queue_group.to_string())
+                .await
+        }
+        None => client.subscribe(subject.to_string()).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_queue_group() {
+        assert_eq!(
+            parse_queue_group(None, "preprocessing_service").as_deref(),
+            Some("preprocessing_service")
+        );
+        assert_eq!(
+            parse_queue_group(Some(" embedders "), "preprocessing_service").as_deref(),
+            Some("embedders")
+        );
+        assert_eq!(
+            parse_queue_group(Some("OFF"), "preprocessing_service"),
+            None
+        );
+        assert_eq!(parse_queue_group(Some(""), "preprocessing_service"), None);
+    }
+}
@@ -0,0 +1,465 @@
+//! Ingestion quotas per tenant, see [`shared_models::QuotaExceeded`]. api_service counts each
+//! submitted URL against its tenant's hourly quota, shared by every replica through the
+//! [`QUOTA_USAGE_BUCKET`] key-value bucket, and refuses it once the quota or the tenant's
+//! stored sentences are used up, giving the URL back when it then cannot be queued;
+//! perception_service checks the stored sentences again
+//! before scraping, since a backlog of tasks can outgrow them. Stored sentences are counted
+//! by vector_memory_service. A quota that cannot be checked, e.g. while vector_memory_service
+//! is down, lets the submission through rather than stalling ingestion.
+
+use crate::JetStreamError;
+use async_nats::Client;
+use async_nats::jetstream::{self, kv};
+use log::{debug, info, warn};
+use prometheus::{IntCounterVec, Opts};
+use shared_config::{env_parse_or, env_var};
+use shared_models::{
+    Envelope, QUOTA_EXCEEDED_SUBJECT, QuotaExceeded, QuotaKind, RequestId, VectorCountResult,
+    VectorCountTask, current_timestamp_ms, validate_tenant_id,
+};
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Key-value bucket holding the URLs each tenant submitted per hour.
+pub const QUOTA_USAGE_BUCKET: &str = "QUOTA_USAGE";
+const VECTOR_COUNT_SUBJECT: &str = "tasks.vector.count";
+const HOUR_MS: u64 = 3_600_000;
+/// Hourly counts are only read within their hour; the bucket forgets them after two.
+const USAGE_MAX_AGE: Duration = Duration::from_secs(2 * 3600);
+/// Attempts at incrementing an hourly count while other replicas keep changing it.
+const MAX_INCREMENT_ATTEMPTS: usize = 5;
+/// How long a tenant's count of stored sentences is reused before it is asked for again.
+const STORED_SENTENCES_TTL: Duration = Duration::from_secs(30);
+const STORED_SENTENCES_TIMEOUT: Duration = Duration::from_secs(5);
+
+static CHECKS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let checks = IntCounterVec::new(
+        Opts::new(
+            "symbiont_quota_checks_total",
+            "Quota checks of submissions, by quota and outcome.",
+        ),
+        &["quota", "outcome"],
+    )
+    .expect("valid metric");
+    if let Err(e) = shared_telemetry::registry().register(Box::new(checks.clone())) {
+        warn!("[QUOTA] Failed to register quota metrics: {}", e);
+    }
+    checks
+});
+
+fn count_check(quota: QuotaKind, outcome: &str) {
+    CHECKS.with_label_values(&[quota.as_str(), outcome]).inc();
+}
+
+/// The quotas of one tenant; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    pub urls_per_hour: Option<u64>,
+    pub stored_sentences: Option<u64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuotaConfig {
+    /// Limits of tenants without their own, and of submissions without a tenant.
+    pub default: QuotaLimits,
+    pub tenants: HashMap<String, QuotaLimits>,
+}
+
+impl QuotaConfig {
+    /// `QUOTA_URLS_PER_HOUR` and `QUOTA_STORED_SENTENCES`, 0 or unset for unlimited, and
+    /// the overrides of `QUOTA_TENANTS`, e.g.
+    /// `acme:urls_per_hour=500,stored_sentences=1000000;trial:urls_per_hour=10`. A tenant
+    /// keeps the defaults it does not override.
+    pub fn from_env() -> Self {
+        let limit = |name: &str| Some(env_parse_or(name, 0_u64)).filter(|&limit| limit > 0);
+        let default = QuotaLimits {
+            urls_per_hour: limit("QUOTA_URLS_PER_HOUR"),
+            stored_sentences: limit("QUOTA_STORED_SENTENCES"),
+        };
+
+        let mut tenants = HashMap::new();
+        let raw = env_var("QUOTA_TENANTS").unwrap_or_default();
+        for entry in raw.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            match parse_tenant_limits(entry, default) {
+                Ok((tenant_id, limits)) => {
+                    tenants.insert(tenant_id, limits);
+                }
+                Err(e) => warn!("[QUOTA] Ignoring QUOTA_TENANTS entry '{}': {}", entry, e),
+            }
+        }
+
+        let config = QuotaConfig { default, tenants };
+        info!("[QUOTA] Quotas: {:?}", config);
+        config
+    }
+
+    pub fn limits(&self, tenant_id: Option<&str>) -> QuotaLimits {
+        tenant_id
+            .and_then(|tenant_id| self.tenants.get(tenant_id))
+            .copied()
+            .unwrap_or(self.default)
+    }
+
+    fn limits_urls(&self) -> bool {
+        self.default.urls_per_hour.is_some()
+            || self.tenants.values().any(|l| l.urls_per_hour.is_some())
+    }
+}
+
+fn parse_tenant_limits(entry: &str, default: QuotaLimits) -> Result<(String, QuotaLimits), String> {
+    let (tenant_id, options) = entry.split_once(':').unwrap_or((entry, ""));
+    let tenant_id = tenant_id.trim();
+    validate_tenant_id(tenant_id).map_err(|e| e.to_string())?;
+
+    let mut limits = default;
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        let (name, value) = option
+            .split_once('=')
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .ok_or_else(|| format!("'{}' is not quota=limit", option))?;
+        let limit = value
+            .parse::<u64>()
+            .map_err(|_| format!("{} must be a whole number, got '{}'", name, value))?;
+        let limit = Some(limit).filter(|&limit| limit > 0);
+        match name {
+            "urls_per_hour" => limits.urls_per_hour = limit,
+            "stored_sentences" => limits.stored_sentences = limit,
+            _ => return Err(format!("unknown quota '{}'", name)),
+        }
+    }
+    Ok((tenant_id.to_string(), limits))
+}
+
+/// Key of a tenant's URL count in the hour of `timestamp_ms`. Tenant ids may contain '.',
+/// which separates key tokens, so it is written as '=', which they may not contain.
+fn usage_key(tenant_id: Option<&str>, timestamp_ms: u64) -> String {
+    let hour = timestamp_ms / HOUR_MS;
+    match tenant_id {
+        Some(tenant_id) => format!("urls.{}.{}", hour, tenant_id.replace('.', "=")),
+        None => format!("urls.{}", hour),
+    }
+}
+
+/// A URL counted against its tenant's hourly quota by [`Quotas::admit_url`], to be given
+/// back with [`Quotas::release_url`] when the submission fails after all.
+#[derive(Debug)]
+#[must_use]
+pub struct UrlAdmission {
+    /// `None` when the URL was not counted.
+    key: Option<String>,
+}
+
+/// Checks submissions against the quotas of their tenant and announces the ones refused.
+pub struct Quotas {
+    config: QuotaConfig,
+    client: Client,
+    service: String,
+    /// `None` while no tenant has an hourly URL quota.
+    usage: Option<kv::Store>,
+    stored_sentences: Mutex<HashMap<Option<String>, (Instant, u64)>>,
+}
+
+impl Quotas {
+    /// Quotas of [`QuotaConfig::from_env`], checked and announced as `service`. Gets the
+    /// [`QUOTA_USAGE_BUCKET`], creating it when this is the first service to need it.
+    pub async fn from_env(client: Client, service: &str) -> Result<Self, JetStreamError> {
+        let config = QuotaConfig::from_env();
+        let usage = if config.limits_urls() {
+            let jetstream = jetstream::new(client.clone());
+            let store = match jetstream.get_key_value(QUOTA_USAGE_BUCKET).await {
+                Ok(store) => store,
+                Err(_) => jetstream
+                    .create_key_value(kv::Config {
+                        bucket: QUOTA_USAGE_BUCKET.to_string(),
+                        history: 1,
+                        max_age: USAGE_MAX_AGE,
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|source| JetStreamError::KeyValue {
+                        bucket: QUOTA_USAGE_BUCKET.to_string(),
+                        source,
+                    })?,
+            };
+            Some(store)
+        } else {
+            None
+        };
+        Ok(Quotas::new(config, client, service, usage))
+    }
+
+    /// Only the stored sentence quotas of [`QuotaConfig::from_env`], for services that check
+    /// them without counting URLs; never touches the [`QUOTA_USAGE_BUCKET`].
+    pub fn stored_sentences_from_env(client: Client, service: &str) -> Self {
+        Quotas::new(QuotaConfig::from_env(), client, service, None)
+    }
+
+    fn new(config: QuotaConfig, client: Client, service: &str, usage: Option<kv::Store>) -> Self {
+        Quotas {
+            config,
+            client,
+            service: service.to_string(),
+            usage,
+            stored_sentences: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a URL submitted by `tenant_id` against its hourly quota, unless that or its
+    /// stored sentences are used up. A refused URL is not counted.
+    pub async fn admit_url(&self, tenant_id: Option<&str>) -> Result<UrlAdmission, QuotaExceeded> {
+        let uncounted = UrlAdmission { key: None };
+        self.check_stored_sentences(tenant_id).await?;
+        let (Some(limit), Some(usage)) = (self.config.limits(tenant_id).urls_per_hour, &self.usage)
+        else {
+            return Ok(uncounted);
+        };
+        let key = usage_key(tenant_id, current_timestamp_ms());
+        match increment_below(usage, &key, limit).await {
+            Ok(Ok(used)) => {
+                debug!("[QUOTA] {} used {} of {} URLs this hour.", key, used, limit);
+                count_check(QuotaKind::UrlsPerHour, "admitted");
+                Ok(UrlAdmission { key: Some(key) })
+            }
+            Ok(Err(used)) => {
+                count_check(QuotaKind::UrlsPerHour, "exceeded");
+                Err(QuotaExceeded::new(
+                    QuotaKind::UrlsPerHour,
+                    tenant_id,
+                    limit,
+                    used,
+                ))
+            }
+            Err(e) => {
+                warn!(
+                    "[QUOTA] Admitting a URL unchecked, failed to count {}: {}",
+                    key, e
+                );
+                count_check(QuotaKind::UrlsPerHour, "unchecked");
+                Ok(uncounted)
+            }
+        }
+    }
+
+    /// Gives back a URL [`Quotas::admit_url`] counted, for a submission that failed after
+    /// being admitted. A count that cannot be given back stays used until the hour is over.
+    pub async fn release_url(&self, admission: UrlAdmission) {
+        let (Some(key), Some(usage)) = (admission.key, &self.usage) else {
+            return;
+        };
+        match decrement(usage, &key).await {
+            Ok(used) => debug!("[QUOTA] {} gave back a URL, {} used this hour.", key, used),
+            Err(e) => warn!(
+                "[QUOTA] Failed to give back a URL counted in {}: {}",
+                key, e
+            ),
+        }
+    }
+
+    /// Refuses submissions of a tenant whose stored sentences reached its quota.
+    pub async fn check_stored_sentences(
+        &self,
+        tenant_id: Option<&str>,
+    ) -> Result<(), QuotaExceeded> {
+        let Some(limit) = self.config.limits(tenant_id).stored_sentences else {
+            return Ok(());
+        };
+        match self.stored_sentences(tenant_id).await {
+            Ok(used) if used >= limit => {
+                count_check(QuotaKind::StoredSentences, "exceeded");
+                Err(QuotaExceeded::new(
+                    QuotaKind::StoredSentences,
+                    tenant_id,
+                    limit,
+                    used,
+                ))
+            }
+            Ok(_) => {
+                count_check(QuotaKind::StoredSentences, "admitted");
+                Ok(())
+            }
+            Err(e) => {
+                warn!(
+                    "[QUOTA] Admitting a submission of {:?} unchecked, failed to count its stored sentences: {}",
+                    tenant_id, e
+                );
+                count_check(QuotaKind::StoredSentences, "unchecked");
+                Ok(())
+            }
+        }
+    }
+
+    /// Publishes `exceeded` to [`QUOTA_EXCEEDED_SUBJECT`], following the refused submission.
+    pub async fn publish_exceeded<C>(&self, cause: &Envelope<C>, exceeded: &QuotaExceeded) {
+        match cause.follow_up(&self.service, exceeded).to_vec() {
+            Ok(payload) => {
+                if let Err(e) = self
+                    .client
+                    .publish(QUOTA_EXCEEDED_SUBJECT, payload.into())
+                    .await
+                {
+                    warn!(
+                        "[QUOTA] Failed to publish to {}: {}",
+                        QUOTA_EXCEEDED_SUBJECT, e
+                    );
+                }
+            }
+            Err(e) => warn!("[QUOTA] Failed to serialize QuotaExceeded: {}", e),
+        }
+    }
+
+    /// Sentences stored for `tenant_id`, approximately, reused for a while so checks do not
+    /// ask vector_memory_service for every submission.
+    async fn stored_sentences(&self, tenant_id: Option<&str>) -> Result<u64, async_nats::Error> {
+        let cache_key = tenant_id.map(str::to_string);
+        let cached = self
+            .stored_sentences
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get(&cache_key)
+            .filter(|(counted_at, _)| counted_at.elapsed() < STORED_SENTENCES_TTL)
+            .map(|(_, count)| *count);
+        if let Some(count) = cached {
+            return Ok(count);
+        }
+
+        let task = VectorCountTask {
+            request_id: RequestId::generate(),
+            model_name: None,
+            original_document_id: None,
+            source_url: None,
+            tenant_id: cache_key.clone(),
+            exact: false,
+            group_by: None,
+            group_limit: None,
+        };
+        let payload = Envelope::new(&self.service, &task)
+            .with_tenant_id(cache_key.clone())
+            .to_vec()?;
+        let reply = tokio::time::timeout(
+            STORED_SENTENCES_TIMEOUT,
+            crate::request_guarded(&self.client, VECTOR_COUNT_SUBJECT, payload),
+        )
+        .await
+        .map_err(|_| format!("no reply from {} in time", VECTOR_COUNT_SUBJECT))??;
+        let result = Envelope::<VectorCountResult>::from_slice(&reply.payload)?.payload;
+        if let Some(error_message) = result.error_message {
+            return Err(error_message.into());
+        }
+
+        self.stored_sentences
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(cache_key, (Instant::now(), result.count));
+        Ok(result.count)
+    }
+}
+
+/// Increments the count under `key` unless it reached `limit`: `Ok(count)` after counting,
+/// `Err(count)` when the limit was reached. Retried while other replicas change the count.
+async fn increment_below(
+    usage: &kv::Store,
+    key: &str,
+    limit: u64,
+) -> Result<Result<u64, u64>, async_nats::Error> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let (used, revision) = match usage.entry(key).await? {
+            Some(entry) => (
+                std::str::from_utf8(&entry.value)?.trim().parse::<u64>()?,
+                entry.revision,
+            ),
+            None => (0, 0),
+        };
+        if used >= limit {
+            return Ok(Err(used));
+        }
+        // Revision 0 only stores the first count of the hour.
+        match usage
+            .update(key, (used + 1).to_string().into(), revision)
+            .await
+        {
+            Ok(_) => return Ok(Ok(used + 1)),
+            Err(e) if attempt >= MAX_INCREMENT_ATTEMPTS => return Err(e.into()),
+            Err(e) => debug!("[QUOTA] Counting {} again after: {}", key, e),
+        }
+    }
+}
+
+/// Decrements the count under `key`, not below 0, and returns what is left. Retried while
+/// other replicas change the count.
+async fn decrement(usage: &kv::Store, key: &str) -> Result<u64, async_nats::Error> {
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        let Some(entry) = usage.entry(key).await? else {
+            return Ok(0);
+        };
+        let used = std::str::from_utf8(&entry.value)?.trim().parse::<u64>()?;
+        let left = used.saturating_sub(1);
+        match usage
+            .update(key, left.to_string().into(), entry.revision)
+            .await
+        {
+            Ok(_) => return Ok(left),
+            Err(e) if attempt >= MAX_INCREMENT_ATTEMPTS => return Err(e.into()),
+            Err(e) => debug!("[QUOTA] Giving back {} again after: {}", key, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_limits_override_the_defaults() {
+        let default = QuotaLimits {
+            urls_per_hour: Some(100),
+            stored_sentences: Some(1_000),
+        };
+        assert_eq!(
+            parse_tenant_limits("acme:urls_per_hour=500,stored_sentences=0", default),
+            Ok((
+                "acme".to_string(),
+                QuotaLimits {
+                    urls_per_hour: Some(500),
+                    stored_sentences: None,
+                }
+            ))
+        );
+        assert_eq!(
+            parse_tenant_limits(" trial ", default),
+            Ok(("trial".to_string(), default))
+        );
+        assert!(parse_tenant_limits("acme:urls=5", default).is_err());
+        assert!(parse_tenant_limits("acme:urls_per_hour=many", default).is_err());
+        assert!(parse_tenant_limits("a b:urls_per_hour=5", default).is_err());
+
+        let config = QuotaConfig {
+            default,
+            tenants: HashMap::from([(
+                "acme".to_string(),
+                QuotaLimits {
+                    urls_per_hour: None,
+                    stored_sentences: None,
+                },
+            )]),
+        };
+        assert_eq!(config.limits(Some("acme")), QuotaLimits::default());
+        assert_eq!(config.limits(Some("other")), default);
+        assert_eq!(config.limits(None), default);
+        assert!(config.limits_urls());
+        assert!(!QuotaConfig::default().limits_urls());
+    }
+
+    #[test]
+    fn test_usage_keys_are_per_hour_and_tenant() {
+        let timestamp_ms = 5 * HOUR_MS + 42;
+        assert_eq!(usage_key(None, timestamp_ms), "urls.5");
+        assert_eq!(usage_key(Some("acme"), timestamp_ms), "urls.5.acme");
+        assert_eq!(usage_key(Some(".eu."), timestamp_ms), "urls.5.=eu=");
+        assert_eq!(usage_key(Some("acme"), 6 * HOUR_MS), "urls.6.acme");
+    }
+}
@@ -0,0 +1,40 @@
+//! Replies of the services answering request/reply subjects.
+
+use async_nats::{Client, Subject};
+use log::{error, warn};
+use serde::Serialize;
+use shared_models::Envelope;
+
+/// Serializes `value` and publishes it to the request's reply subject, if there is one.
+/// The reply continues the request's correlation; `cause` is `None` only when the request
+/// itself could not be decoded. Failures are logged under `log_tag`.
+pub async fn publish_reply<T: Serialize>(
+    client: &Client,
+    reply_to: Option<Subject>,
+    cause: Option<&Envelope<()>>,
+    produced_by: &str,
+    value: &T,
+    log_tag: &str,
+) {
+    let Some(reply_to) = reply_to else {
+        warn!("[{}] No reply subject provided. Result not sent.", log_tag);
+        return;
+    };
+
+    match Envelope::following(cause, produced_by, value).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = client.publish(reply_to, payload_json.into()).await {
+                error!(
+                    "[{}_NATS_REPLY_FAIL] Failed to publish reply: {}",
+                    log_tag, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[{}_SERIALIZE_FAIL] Failed to serialize reply: {}",
+                log_tag, e
+            );
+        }
+    }
+}
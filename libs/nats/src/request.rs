@@ -0,0 +1,45 @@
+//! Requests to the services answering request/reply subjects, through a circuit breaker per
+//! subject. While a subject's responders time out or are gone, further requests to it fail
+//! at once instead of each waiting out its timeout. A request nobody was subscribed to,
+//! e.g. while the responding service restarts, is retried shortly after.
+
+use async_nats::{Client, Message, RequestError, RequestErrorKind};
+use shared_resilience::{BreakerError, BreakerGroup, RetryPolicy, retry_with_backoff};
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Breakers of the request subjects, configured by the `BREAKER_NATS_REQUESTS_*` variables.
+static REQUEST_BREAKERS: LazyLock<BreakerGroup> =
+    LazyLock::new(|| BreakerGroup::from_env("nats_requests"));
+
+/// Retries of a request without responders, configured by the `NATS_REQUEST_*` retry
+/// variables.
+static REQUEST_RETRY: LazyLock<RetryPolicy> = LazyLock::new(|| {
+    RetryPolicy::from_env(
+        "NATS_REQUEST",
+        RetryPolicy::new(2, Duration::from_millis(200), Duration::from_secs(1)),
+    )
+});
+
+/// Sends `payload` to `subject` and waits for the reply, unless the subject's breaker is
+/// open; a request without responders, or failed by fault injection, is sent again. A
+/// request dropped before its reply, e.g. by a caller's timeout, counts as failed.
+pub async fn request_guarded(
+    client: &Client,
+    subject: &str,
+    payload: Vec<u8>,
+) -> Result<Message, BreakerError<RequestError>> {
+    let breaker = REQUEST_BREAKERS.get(subject);
+    let (result, _) = retry_with_backoff(
+        &REQUEST_RETRY,
+        &format!("Request to {}", subject),
+        || breaker.call(client.request(subject.to_string(), payload.clone().into())),
+        |e| match e {
+            BreakerError::Failed(e) => e.kind() == RequestErrorKind::NoResponders,
+            BreakerError::Injected(_) => true,
+            BreakerError::Open(_) => false,
+        },
+    )
+    .await;
+    result
+}
@@ -0,0 +1,171 @@
+//! Graceful shutdown of the worker services. On SIGTERM or SIGINT a service stops taking
+//! new messages, by ending its consumer streams with [`Shutdown::signalled`], then
+//! [`Shutdown::drain`]s: it waits up to a timeout for the handlers it already started and
+//! flushes what they published. Messages whose handlers did not finish are unacked and
+//! redelivered after their ack wait.
+
+use async_nats::Client;
+use futures::future::BoxFuture;
+use log::{info, warn};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, watch};
+
+/// Tells the message loops of a service that it is shutting down and counts the handlers
+/// still running. Clones share the same state.
+#[derive(Clone)]
+pub struct Shutdown {
+    signalled: watch::Receiver<bool>,
+    in_flight: Arc<InFlight>,
+}
+
+#[derive(Default)]
+struct InFlight {
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+/// Keeps [`Shutdown::drain`] waiting until it is dropped.
+#[must_use = "the handler stops being waited for when the guard is dropped"]
+pub struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if self.in_flight.count.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
+
+impl Shutdown {
+    /// Starts listening for SIGTERM and SIGINT. Must be called from within the Tokio runtime.
+    pub fn listen() -> Self {
+        let (sender, receiver) = watch::channel(false);
+        tokio::spawn(async move {
+            wait_for_signal().await;
+            info!("[SHUTDOWN] Shutdown requested; no longer taking new messages.");
+            let _ = sender.send(true);
+            // Keeps the channel open, so a failed signal handler never reads as a shutdown.
+            sender.closed().await;
+        });
+        Shutdown::new(receiver)
+    }
+
+    fn new(signalled: watch::Receiver<bool>) -> Self {
+        Shutdown {
+            signalled,
+            in_flight: Arc::default(),
+        }
+    }
+
+    /// Resolves once shutdown has been requested, e.g. to end a message stream with
+    /// `StreamExt::take_until`.
+    pub fn signalled(&self) -> BoxFuture<'static, ()> {
+        let mut signalled = self.signalled.clone();
+        Box::pin(async move {
+            if signalled.wait_for(|signalled| *signalled).await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        })
+    }
+
+    /// Counts a handler as in flight until the guard is dropped; hold it for the whole
+    /// handling of a message, including its ack.
+    pub fn track(&self) -> InFlightGuard {
+        self.in_flight.count.fetch_add(1, Ordering::AcqRel);
+        InFlightGuard {
+            in_flight: Arc::clone(&self.in_flight),
+        }
+    }
+
+    /// Waits for the handlers still in flight, at most `timeout`, then flushes the
+    /// publishes `client` has buffered.
+    pub async fn drain(&self, client: &Client, timeout: Duration) {
+        info!(
+            "[SHUTDOWN] Waiting up to {:?} for {} in-flight handler(s)...",
+            timeout,
+            self.in_flight.count.load(Ordering::Acquire)
+        );
+        if !self.wait_idle(timeout).await {
+            warn!(
+                "[SHUTDOWN] {} handler(s) still running after {:?}; their messages will be redelivered.",
+                self.in_flight.count.load(Ordering::Acquire),
+                timeout
+            );
+        }
+        if let Err(e) = client.flush().await {
+            warn!("[SHUTDOWN] Failed to flush pending publishes: {}", e);
+        }
+        info!("[SHUTDOWN] Drained.");
+    }
+
+    /// Whether every tracked handler finished within `timeout`.
+    async fn wait_idle(&self, timeout: Duration) -> bool {
+        let idle = async {
+            loop {
+                let finished = self.in_flight.idle.notified();
+                if self.in_flight.count.load(Ordering::Acquire) == 0 {
+                    return;
+                }
+                finished.await;
+            }
+        };
+        tokio::time::timeout(timeout, idle).await.is_ok()
+    }
+}
+
+async fn wait_for_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                tokio::select! {
+                    _ = terminate.recv() => {}
+                    _ = tokio::signal::ctrl_c() => {}
+                }
+                return;
+            }
+            Err(e) => {
+                warn!(
+                    "[SHUTDOWN] Failed to listen for SIGTERM: {}. Only SIGINT shuts down gracefully.",
+                    e
+                );
+            }
+        }
+    }
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!(
+            "[SHUTDOWN] Failed to listen for SIGINT: {}. Graceful shutdown is disabled.",
+            e
+        );
+        std::future::pending::<()>().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain_waits_for_tracked_handlers() {
+        let (sender, receiver) = watch::channel(false);
+        let shutdown = Shutdown::new(receiver);
+        assert!(shutdown.wait_idle(Duration::ZERO).await);
+
+        let guard = shutdown.track();
+        assert!(!shutdown.wait_idle(Duration::from_millis(10)).await);
+        tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            drop(guard);
+        });
+        assert!(shutdown.wait_idle(Duration::from_secs(5)).await);
+
+        let signalled = tokio::spawn(shutdown.signalled());
+        sender.send(true).unwrap();
+        signalled.await.unwrap();
+    }
+}
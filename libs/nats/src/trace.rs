@@ -0,0 +1,105 @@
+//! W3C trace context carried in NATS message headers, so the spans every service records
+//! for one document join a single trace. [`publish_durable`](crate::publish_durable) adds
+//! the current span's `traceparent` to each message; core NATS publishes add it with
+//! [`traced_headers`]. A consumer handles a message inside its [`receive_span`], which
+//! continues the publisher's trace.
+//!
+//! Propagation goes through the global OpenTelemetry propagator, which `shared_telemetry`
+//! installs. Without an OTLP endpoint spans carry no trace context and nothing is added.
+
+use async_nats::HeaderMap;
+use opentelemetry::Context;
+use opentelemetry::propagation::{Extractor, Injector};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+struct HeaderInjector<'a>(&'a mut HeaderMap);
+
+impl Injector for HeaderInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.insert(key, value.as_str());
+    }
+}
+
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl Extractor for HeaderExtractor<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).map(|value| value.as_str())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|(name, _)| name.as_ref()).collect()
+    }
+}
+
+fn inject_context(cx: &Context, headers: &mut HeaderMap) {
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(cx, &mut HeaderInjector(headers))
+    });
+}
+
+fn extract_context(headers: &HeaderMap) -> Context {
+    opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(headers))
+    })
+}
+
+/// Adds the trace context of the current span to the headers of a message about to be
+/// published.
+pub fn inject_trace_context(headers: &mut HeaderMap) {
+    inject_context(&Span::current().context(), headers);
+}
+
+/// Headers carrying only the current span's trace context.
+pub fn traced_headers() -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    inject_trace_context(&mut headers);
+    headers
+}
+
+/// The span to handle a message received on `subject` in, a child of the span that
+/// published it when its headers carry a trace context.
+pub fn receive_span(subject: &str, headers: Option<&HeaderMap>) -> Span {
+    let span = tracing::info_span!(
+        "nats.process",
+        otel.name = %format_args!("{} process", subject),
+        otel.kind = "consumer",
+        messaging.system = "nats",
+        messaging.destination.name = %subject,
+    );
+    if let Some(headers) = headers {
+        // Only fails when no OpenTelemetry layer is installed, i.e. spans are not exported.
+        let _ = span.set_parent(extract_context(headers));
+    }
+    span
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use opentelemetry::trace::TraceContextExt;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+
+    #[test]
+    fn test_trace_context_round_trips_through_headers() {
+        opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let mut received = HeaderMap::new();
+        received.insert("traceparent", traceparent);
+
+        let cx = extract_context(&received);
+        assert_eq!(
+            cx.span().span_context().trace_id().to_string(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+
+        let mut forwarded = HeaderMap::new();
+        inject_context(&cx, &mut forwarded);
+        assert_eq!(
+            forwarded.get("traceparent").map(|value| value.as_str()),
+            Some(traceparent)
+        );
+        assert!(!extract_context(&HeaderMap::new()).has_active_span());
+    }
+}
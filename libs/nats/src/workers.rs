@@ -0,0 +1,322 @@
+//! Bounded concurrency for message handlers. A [`WorkerPool`] runs at most a set number of
+//! handlers at once and queues a bounded number more. Once the queue is full, a message
+//! loop either waits for room, so it stops taking messages until a handler finishes, or is
+//! refused ([`OverflowPolicy`]). Every pool exports its queue depth, running handlers,
+//! queue waits and refusals as `symbiont_worker_*` metrics, labelled with its name.
+
+use async_nats::jetstream::{self, AckKind};
+use log::{info, warn};
+use prometheus::{Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge};
+use prometheus::{IntGaugeVec, Opts};
+use shared_config::env_parse_or;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore, TryAcquireError};
+
+/// How long a JetStream message refused by a full pool waits before it is redelivered.
+pub const OVERFLOW_REDELIVERY_DELAY: Duration = Duration::from_secs(5);
+
+/// What a full pool does with one more handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Waits for room in the queue, holding up the loop and with it the messages behind.
+    Wait,
+    /// Refuses the handler at once, e.g. so its message is redelivered later.
+    Reject,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "wait" => Ok(OverflowPolicy::Wait),
+            "reject" => Ok(OverflowPolicy::Reject),
+            other => Err(format!("unknown overflow policy '{}'", other)),
+        }
+    }
+}
+
+struct Metrics {
+    queue_depth: IntGaugeVec,
+    running: IntGaugeVec,
+    rejections: IntCounterVec,
+    queue_wait: HistogramVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        queue_depth: IntGaugeVec::new(
+            Opts::new(
+                "symbiont_worker_queue_depth",
+                "Handlers spawned and waiting for a free worker.",
+            ),
+            &["pool"],
+        )
+        .expect("valid metric"),
+        running: IntGaugeVec::new(
+            Opts::new("symbiont_workers_running", "Handlers running."),
+            &["pool"],
+        )
+        .expect("valid metric"),
+        rejections: IntCounterVec::new(
+            Opts::new(
+                "symbiont_worker_rejections_total",
+                "Handlers refused because the pool was full.",
+            ),
+            &["pool"],
+        )
+        .expect("valid metric"),
+        queue_wait: HistogramVec::new(
+            HistogramOpts::new(
+                "symbiont_worker_queue_wait_seconds",
+                "Time handlers spent queued before a worker was free.",
+            ),
+            &["pool"],
+        )
+        .expect("valid metric"),
+    };
+    let registry = shared_telemetry::registry();
+    let registered = registry
+        .register(Box::new(metrics.queue_depth.clone()))
+        .and_then(|()| registry.register(Box::new(metrics.running.clone())))
+        .and_then(|()| registry.register(Box::new(metrics.rejections.clone())))
+        .and_then(|()| registry.register(Box::new(metrics.queue_wait.clone())));
+    if let Err(e) = registered {
+        warn!("[WORKERS] Failed to register worker pool metrics: {}", e);
+    }
+    metrics
+});
+
+#[derive(Clone)]
+struct PoolMetrics {
+    queue_depth: IntGauge,
+    running: IntGauge,
+    rejections: IntCounter,
+    queue_wait: Histogram,
+}
+
+/// Spawns message handlers with at most `concurrency` running and `queue` more waiting.
+/// Clones share the same limits.
+#[derive(Clone)]
+pub struct WorkerPool {
+    name: Arc<str>,
+    overflow: OverflowPolicy,
+    /// One permit per handler allowed to run.
+    running: Arc<Semaphore>,
+    /// One permit per handler allowed to run or wait.
+    admitted: Arc<Semaphore>,
+    metrics: PoolMetrics,
+}
+
+/// A handler refused by a full pool, handed back to the caller.
+pub struct Rejected<F>(pub F);
+
+impl WorkerPool {
+    /// A pool named `name` in logs and metrics. `concurrency` is at least 1.
+    pub fn new(name: &str, concurrency: usize, queue: usize, overflow: OverflowPolicy) -> Self {
+        let concurrency = concurrency.max(1);
+        info!(
+            "[WORKERS] Pool {}: {} concurrent handler(s), {} queued, {:?} when full",
+            name, concurrency, queue, overflow
+        );
+        let metrics = PoolMetrics {
+            queue_depth: METRICS.queue_depth.with_label_values(&[name]),
+            running: METRICS.running.with_label_values(&[name]),
+            rejections: METRICS.rejections.with_label_values(&[name]),
+            queue_wait: METRICS.queue_wait.with_label_values(&[name]),
+        };
+        WorkerPool {
+            name: Arc::from(name),
+            overflow,
+            running: Arc::new(Semaphore::new(concurrency)),
+            admitted: Arc::new(Semaphore::new(concurrency.saturating_add(queue))),
+            metrics,
+        }
+    }
+
+    /// [`WorkerPool::new`] with `WORKERS_<NAME>_CONCURRENCY`, `WORKERS_<NAME>_QUEUE` and
+    /// `WORKERS_<NAME>_OVERFLOW` (`wait` or `reject`) applied over the given defaults. The
+    /// queue defaults to the concurrency.
+    pub fn from_env(name: &str, concurrency: usize, overflow: OverflowPolicy) -> Self {
+        let key = |suffix: &str| format!("WORKERS_{}_{}", name.to_uppercase(), suffix);
+        let concurrency = env_parse_or(&key("CONCURRENCY"), concurrency);
+        let queue = env_parse_or(&key("QUEUE"), concurrency);
+        let overflow = env_parse_or(&key("OVERFLOW"), overflow);
+        WorkerPool::new(name, concurrency, queue, overflow)
+    }
+
+    /// Takes a place in the pool for one handler. When the pool is full this waits for a
+    /// place or, with [`OverflowPolicy::Reject`], gives `None`.
+    pub async fn reserve(&self) -> Option<WorkerSlot> {
+        let admission = match Arc::clone(&self.admitted).try_acquire_owned() {
+            Ok(admission) => admission,
+            Err(TryAcquireError::NoPermits) if self.overflow == OverflowPolicy::Wait => {
+                Arc::clone(&self.admitted).acquire_owned().await.ok()?
+            }
+            Err(_) => {
+                self.metrics.rejections.inc();
+                return None;
+            }
+        };
+        Some(WorkerSlot {
+            pool: self.clone(),
+            admission,
+        })
+    }
+
+    /// [`WorkerPool::reserve`] for the handler of a JetStream message. A refused message is
+    /// nacked, to be redelivered after [`OVERFLOW_REDELIVERY_DELAY`].
+    pub async fn reserve_for(&self, message: &jetstream::Message) -> Option<WorkerSlot> {
+        let slot = self.reserve().await;
+        if slot.is_none() {
+            warn!(
+                "[WORKERS_FULL] Pool {} is full; message on {} will be redelivered in {:?}.",
+                self.name, message.subject, OVERFLOW_REDELIVERY_DELAY
+            );
+            if let Err(e) = message
+                .ack_with(AckKind::Nak(Some(OVERFLOW_REDELIVERY_DELAY)))
+                .await
+            {
+                warn!("[WORKERS_FULL] Failed to nack refused message: {}", e);
+            }
+        }
+        slot
+    }
+
+    /// Spawns `task` once the pool has a place for it, see [`WorkerPool::reserve`]. A
+    /// refused task is handed back.
+    pub async fn spawn_limited<F>(&self, task: F) -> Result<(), Rejected<F>>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        match self.reserve().await {
+            Some(slot) => {
+                slot.spawn(task);
+                Ok(())
+            }
+            None => {
+                warn!(
+                    "[WORKERS_FULL] Pool {} is full; refusing the handler.",
+                    self.name
+                );
+                Err(Rejected(task))
+            }
+        }
+    }
+}
+
+/// A place in a [`WorkerPool`], kept until the handler spawned in it finishes.
+pub struct WorkerSlot {
+    pool: WorkerPool,
+    admission: OwnedSemaphorePermit,
+}
+
+impl WorkerSlot {
+    /// Spawns `task`; it is queued until fewer than the pool's concurrency are running.
+    pub fn spawn<F>(self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let WorkerSlot { pool, admission } = self;
+        let queued = GaugeGuard::inc(&pool.metrics.queue_depth);
+        let queued_at = Instant::now();
+        tokio::spawn(async move {
+            let _admission = admission;
+            let Ok(_running) = pool.running.acquire().await else {
+                return;
+            };
+            drop(queued);
+            pool.metrics
+                .queue_wait
+                .observe(queued_at.elapsed().as_secs_f64());
+            let _running_gauge = GaugeGuard::inc(&pool.metrics.running);
+            task.await;
+        });
+    }
+}
+
+/// Counts one in a gauge until dropped, also when a handler panics.
+struct GaugeGuard(IntGauge);
+
+impl GaugeGuard {
+    fn inc(gauge: &IntGauge) -> Self {
+        gauge.inc();
+        GaugeGuard(gauge.clone())
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::oneshot;
+
+    #[tokio::test]
+    async fn test_full_pool_rejects_until_a_handler_finishes() {
+        let pool = WorkerPool::new("test_reject", 1, 1, OverflowPolicy::Reject);
+        let (release_first, first_released) = oneshot::channel::<()>();
+        let (first_done, first_finished) = oneshot::channel();
+        pool.spawn_limited(async move {
+            let _ = first_released.await;
+            let _ = first_done.send(());
+        })
+        .await
+        .ok()
+        .expect("a worker is free");
+        pool.spawn_limited(async {})
+            .await
+            .ok()
+            .expect("the queue has room");
+        assert!(pool.spawn_limited(async {}).await.is_err());
+        assert_eq!(pool.metrics.rejections.get(), 1);
+
+        release_first.send(()).unwrap();
+        first_finished.await.unwrap();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.admitted.available_permits() < 2 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .expect("both handlers finish");
+        assert!(pool.reserve().await.is_some());
+        assert_eq!(pool.metrics.queue_depth.get(), 0);
+        assert_eq!(pool.metrics.running.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_full_pool_waits_with_wait_policy() {
+        let pool = WorkerPool::new("test_wait", 1, 0, OverflowPolicy::Wait);
+        let (release, released) = oneshot::channel::<()>();
+        let slot = pool.reserve().await.expect("a worker is free");
+        slot.spawn(async move {
+            let _ = released.await;
+        });
+        assert!(
+            tokio::time::timeout(Duration::from_millis(20), pool.reserve())
+                .await
+                .is_err()
+        );
+
+        release.send(()).unwrap();
+        let slot = tokio::time::timeout(Duration::from_secs(5), pool.reserve())
+            .await
+            .expect("the handler finishes");
+        assert!(slot.is_some());
+    }
+
+    #[test]
+    fn test_overflow_policy_parses() {
+        assert_eq!(" Wait ".parse(), Ok(OverflowPolicy::Wait));
+        assert_eq!("reject".parse(), Ok(OverflowPolicy::Reject));
+        assert!("drop".parse::<OverflowPolicy>().is_err());
+    }
+}
@@ -0,0 +1,681 @@
+//! Circuit breakers. A breaker is closed while calls go through, open while it refuses them
+//! and half-open while a few trial calls decide whether it closes again. It opens once the
+//! share of failed calls in its window reaches the threshold, and reopens on any failed
+//! trial. Every breaker exports whether it is open, how often it opened and the calls it
+//! refused as `symbiont_circuit_breaker*` metrics, labelled with its name. Faults injected
+//! into calls through a breaker (see [`Chaos`]) count as failures like real ones.
+
+use crate::chaos::{Chaos, Fault, InjectedFault};
+use log::{info, warn};
+use prometheus::{IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts};
+use shared_config::env_parse_or;
+use std::collections::{HashMap, VecDeque};
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Breakers a [`BreakerGroup`] keeps before it forgets the closed ones nobody uses.
+const MAX_GROUP_BREAKERS: usize = 1024;
+
+/// When a [`CircuitBreaker`] opens and for how long.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerConfig {
+    /// Share of failed calls in the window, from 0 to 1, that opens the breaker.
+    pub failure_rate_threshold: f64,
+    /// Calls the window must hold before the failure rate counts.
+    pub minimum_calls: usize,
+    /// Most recent calls the failure rate is taken over.
+    pub window_size: usize,
+    /// How long the breaker stays open before letting trial calls through.
+    pub open_duration: Duration,
+    /// Trial calls that must succeed, while half-open, to close the breaker.
+    pub half_open_calls: usize,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_rate_threshold: 0.5,
+            minimum_calls: 10,
+            window_size: 20,
+            open_duration: Duration::from_secs(30),
+            half_open_calls: 3,
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    /// The defaults with `BREAKER_<NAME>_FAILURE_RATE`, `BREAKER_<NAME>_MIN_CALLS`,
+    /// `BREAKER_<NAME>_WINDOW`, `BREAKER_<NAME>_OPEN_SECS` and
+    /// `BREAKER_<NAME>_HALF_OPEN_CALLS` applied.
+    pub fn from_env(name: &str) -> Self {
+        let key = |suffix: &str| format!("BREAKER_{}_{}", name.to_uppercase(), suffix);
+        let defaults = CircuitBreakerConfig::default();
+        CircuitBreakerConfig {
+            failure_rate_threshold: env_parse_or(
+                &key("FAILURE_RATE"),
+                defaults.failure_rate_threshold,
+            ),
+            minimum_calls: env_parse_or(&key("MIN_CALLS"), defaults.minimum_calls),
+            window_size: env_parse_or(&key("WINDOW"), defaults.window_size),
+            open_duration: Duration::from_secs(env_parse_or(
+                &key("OPEN_SECS"),
+                defaults.open_duration.as_secs(),
+            )),
+            half_open_calls: env_parse_or(&key("HALF_OPEN_CALLS"), defaults.half_open_calls),
+        }
+    }
+}
+
+/// A call refused by an open breaker.
+#[derive(Debug, Clone)]
+pub struct CircuitOpen {
+    pub name: String,
+}
+
+impl fmt::Display for CircuitOpen {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "circuit breaker {} is open", self.name)
+    }
+}
+
+impl Error for CircuitOpen {}
+
+/// The error of a call made through a breaker.
+#[derive(Debug)]
+pub enum BreakerError<E> {
+    /// The breaker refused the call.
+    Open(CircuitOpen),
+    /// The call was made and failed.
+    Failed(E),
+    /// Fault injection failed the call, or dropped its response.
+    Injected(InjectedFault),
+}
+
+impl<E> BreakerError<E> {
+    /// The error of the call, or the refusal or injected fault converted into one.
+    pub fn into_error(self) -> E
+    where
+        E: From<CircuitOpen> + From<InjectedFault>,
+    {
+        match self {
+            BreakerError::Open(open) => E::from(open),
+            BreakerError::Failed(e) => e,
+            BreakerError::Injected(fault) => E::from(fault),
+        }
+    }
+}
+
+impl<E> From<CircuitOpen> for BreakerError<E> {
+    fn from(open: CircuitOpen) -> Self {
+        BreakerError::Open(open)
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for BreakerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BreakerError::Open(open) => open.fmt(f),
+            BreakerError::Failed(e) => e.fmt(f),
+            BreakerError::Injected(fault) => fault.fmt(f),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for BreakerError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            BreakerError::Open(_) | BreakerError::Injected(_) => None,
+            BreakerError::Failed(e) => Some(e),
+        }
+    }
+}
+
+struct Metrics {
+    open: IntGaugeVec,
+    opened: IntCounterVec,
+    rejections: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        open: IntGaugeVec::new(
+            Opts::new(
+                "symbiont_circuit_breakers_open",
+                "Breakers open or half-open.",
+            ),
+            &["breaker"],
+        )
+        .expect("valid metric"),
+        opened: IntCounterVec::new(
+            Opts::new(
+                "symbiont_circuit_breaker_opened_total",
+                "Times a breaker opened.",
+            ),
+            &["breaker"],
+        )
+        .expect("valid metric"),
+        rejections: IntCounterVec::new(
+            Opts::new(
+                "symbiont_circuit_breaker_rejections_total",
+                "Calls refused by an open breaker.",
+            ),
+            &["breaker"],
+        )
+        .expect("valid metric"),
+    };
+    let registry = shared_telemetry::registry();
+    let registered = registry
+        .register(Box::new(metrics.open.clone()))
+        .and_then(|()| registry.register(Box::new(metrics.opened.clone())))
+        .and_then(|()| registry.register(Box::new(metrics.rejections.clone())));
+    if let Err(e) = registered {
+        warn!(
+            "[CIRCUIT] Failed to register circuit breaker metrics: {}",
+            e
+        );
+    }
+    metrics
+});
+
+struct BreakerMetrics {
+    open: IntGauge,
+    opened: IntCounter,
+    rejections: IntCounter,
+}
+
+impl BreakerMetrics {
+    fn new(label: &str) -> Self {
+        BreakerMetrics {
+            open: METRICS.open.with_label_values(&[label]),
+            opened: METRICS.opened.with_label_values(&[label]),
+            rejections: METRICS.rejections.with_label_values(&[label]),
+        }
+    }
+}
+
+enum Phase {
+    /// Outcomes of the last calls, `true` for a failure.
+    Closed {
+        outcomes: VecDeque<bool>,
+    },
+    Open {
+        until: Instant,
+    },
+    HalfOpen {
+        in_flight: usize,
+        successes: usize,
+    },
+}
+
+struct State {
+    phase: Phase,
+    /// Counts the phase changes, so the outcome of a call started in an earlier phase is
+    /// not counted in the current one.
+    generation: u64,
+}
+
+/// Fails calls to a dependency fast while the dependency is down.
+pub struct CircuitBreaker {
+    name: String,
+    config: CircuitBreakerConfig,
+    state: Mutex<State>,
+    metrics: BreakerMetrics,
+    chaos: Option<Arc<Chaos>>,
+}
+
+impl CircuitBreaker {
+    /// A breaker named `name` in logs, errors and metrics.
+    pub fn new(name: &str, config: CircuitBreakerConfig) -> Self {
+        info!(
+            "[CIRCUIT] Breaker {}: opens at a {:.0}% failure rate over the last {} call(s) (at least {}), for {:?}",
+            name,
+            config.failure_rate_threshold * 100.0,
+            config.window_size,
+            config.minimum_calls,
+            config.open_duration
+        );
+        CircuitBreaker::with_label(name, name, config, Chaos::from_env(name).map(Arc::new))
+    }
+
+    /// [`CircuitBreaker::new`] with [`CircuitBreakerConfig::from_env`].
+    pub fn from_env(name: &str) -> Self {
+        CircuitBreaker::new(name, CircuitBreakerConfig::from_env(name))
+    }
+
+    fn with_label(
+        name: &str,
+        label: &str,
+        config: CircuitBreakerConfig,
+        chaos: Option<Arc<Chaos>>,
+    ) -> Self {
+        CircuitBreaker {
+            name: name.to_string(),
+            config,
+            state: Mutex::new(State {
+                phase: Phase::Closed {
+                    outcomes: VecDeque::new(),
+                },
+                generation: 0,
+            }),
+            metrics: BreakerMetrics::new(label),
+            chaos,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Whether calls go through without restriction.
+    pub fn is_closed(&self) -> bool {
+        matches!(self.lock().phase, Phase::Closed { .. })
+    }
+
+    /// Lets one call through unless the breaker is open, or half-open with enough trial
+    /// calls under way. Report the call's outcome on the permit.
+    pub fn try_acquire(&self) -> Result<BreakerPermit<'_>, CircuitOpen> {
+        let mut state = self.lock();
+        if let Phase::Open { until } = state.phase
+            && Instant::now() >= until
+        {
+            info!(
+                "[CIRCUIT_HALF_OPEN] Breaker {} lets {} trial call(s) through.",
+                self.name, self.config.half_open_calls
+            );
+            self.change_phase(
+                &mut state,
+                Phase::HalfOpen {
+                    in_flight: 0,
+                    successes: 0,
+                },
+            );
+        }
+        let admitted = match &mut state.phase {
+            Phase::Closed { .. } => true,
+            Phase::Open { .. } => false,
+            Phase::HalfOpen {
+                in_flight,
+                successes,
+            } => {
+                let admitted = *in_flight + *successes < self.config.half_open_calls.max(1);
+                if admitted {
+                    *in_flight += 1;
+                }
+                admitted
+            }
+        };
+        if !admitted {
+            self.metrics.rejections.inc();
+            return Err(CircuitOpen {
+                name: self.name.clone(),
+            });
+        }
+        Ok(BreakerPermit {
+            breaker: self,
+            generation: state.generation,
+            recorded: false,
+        })
+    }
+
+    /// Runs `call` unless the breaker is open. Every error counts as a failure.
+    pub async fn call<T, E>(
+        &self,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, BreakerError<E>> {
+        self.call_with(|_| true, call).await
+    }
+
+    /// Runs `call` unless the breaker is open. Only errors `is_failure` accepts count as a
+    /// failure; others, e.g. a rejected request, show the dependency is up.
+    pub async fn call_with<T, E>(
+        &self,
+        is_failure: impl Fn(&E) -> bool,
+        call: impl Future<Output = Result<T, E>>,
+    ) -> Result<T, BreakerError<E>> {
+        let permit = self.try_acquire()?;
+        if let Some(chaos) = &self.chaos
+            && let Some(fault) = chaos.next_fault()
+        {
+            match fault {
+                Fault::Delay(delay) => tokio::time::sleep(delay).await,
+                Fault::Drop => {
+                    // The call is made; only its response is lost.
+                    let _ = call.await;
+                    permit.failed();
+                    return Err(BreakerError::Injected(chaos.injected(fault)));
+                }
+                Fault::Error => {
+                    permit.failed();
+                    return Err(BreakerError::Injected(chaos.injected(fault)));
+                }
+            }
+        }
+        match call.await {
+            Ok(value) => {
+                permit.succeeded();
+                Ok(value)
+            }
+            Err(e) => {
+                if is_failure(&e) {
+                    permit.failed();
+                } else {
+                    permit.succeeded();
+                }
+                Err(BreakerError::Failed(e))
+            }
+        }
+    }
+
+    fn record(&self, generation: u64, failed: bool) {
+        let mut state = self.lock();
+        if state.generation != generation {
+            return;
+        }
+        let next = match &mut state.phase {
+            Phase::Closed { outcomes } => {
+                outcomes.push_back(failed);
+                while outcomes.len() > self.config.window_size.max(1) {
+                    outcomes.pop_front();
+                }
+                let failures = outcomes.iter().filter(|failed| **failed).count();
+                let failure_rate = failures as f64 / outcomes.len() as f64;
+                let opens = failed
+                    && outcomes.len() >= self.config.minimum_calls
+                    && failure_rate >= self.config.failure_rate_threshold;
+                if opens {
+                    warn!(
+                        "[CIRCUIT_OPEN] Breaker {} opened: {} of the last {} call(s) failed; refusing calls for {:?}.",
+                        self.name,
+                        failures,
+                        outcomes.len(),
+                        self.config.open_duration
+                    );
+                    self.metrics.open.inc();
+                }
+                opens.then(|| self.open_phase())
+            }
+            Phase::HalfOpen {
+                in_flight,
+                successes,
+            } => {
+                *in_flight = in_flight.saturating_sub(1);
+                if failed {
+                    warn!(
+                        "[CIRCUIT_OPEN] Breaker {} reopened: a trial call failed; refusing calls for {:?}.",
+                        self.name, self.config.open_duration
+                    );
+                    Some(self.open_phase())
+                } else {
+                    *successes += 1;
+                    let closes = *successes >= self.config.half_open_calls;
+                    if closes {
+                        info!(
+                            "[CIRCUIT_CLOSED] Breaker {} closed after {} successful trial call(s).",
+                            self.name, successes
+                        );
+                        self.metrics.open.dec();
+                    }
+                    closes.then(|| Phase::Closed {
+                        outcomes: VecDeque::new(),
+                    })
+                }
+            }
+            Phase::Open { .. } => None,
+        };
+        if let Some(phase) = next {
+            self.change_phase(&mut state, phase);
+        }
+    }
+
+    fn open_phase(&self) -> Phase {
+        self.metrics.opened.inc();
+        Phase::Open {
+            until: Instant::now() + self.config.open_duration,
+        }
+    }
+
+    fn change_phase(&self, state: &mut State, phase: Phase) {
+        state.phase = phase;
+        state.generation += 1;
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// A call let through by a [`CircuitBreaker`]. Dropped without an outcome, e.g. when the
+/// call timed out or was cancelled, it counts as a failure.
+#[must_use = "the call counts as failed as soon as the permit is dropped"]
+pub struct BreakerPermit<'a> {
+    breaker: &'a CircuitBreaker,
+    generation: u64,
+    recorded: bool,
+}
+
+impl BreakerPermit<'_> {
+    pub fn succeeded(mut self) {
+        self.record(false);
+    }
+
+    pub fn failed(mut self) {
+        self.record(true);
+    }
+
+    fn record(&mut self, failed: bool) {
+        self.recorded = true;
+        self.breaker.record(self.generation, failed);
+    }
+}
+
+impl Drop for BreakerPermit<'_> {
+    fn drop(&mut self) {
+        if !self.recorded {
+            self.record(true);
+        }
+    }
+}
+
+/// One [`CircuitBreaker`] per key, e.g. per host, so one dead dependency does not cut off
+/// the others. They share the group's configuration and metric label.
+pub struct BreakerGroup {
+    name: String,
+    config: CircuitBreakerConfig,
+    breakers: Mutex<HashMap<String, Arc<CircuitBreaker>>>,
+    chaos: Option<Arc<Chaos>>,
+}
+
+impl BreakerGroup {
+    /// A group named `name` in metrics; its breakers are named `<name>/<key>`.
+    pub fn new(name: &str, config: CircuitBreakerConfig) -> Self {
+        info!(
+            "[CIRCUIT] Breaker group {}: opens at a {:.0}% failure rate over the last {} call(s) (at least {}), for {:?}",
+            name,
+            config.failure_rate_threshold * 100.0,
+            config.window_size,
+            config.minimum_calls,
+            config.open_duration
+        );
+        BreakerGroup {
+            name: name.to_string(),
+            config,
+            breakers: Mutex::new(HashMap::new()),
+            chaos: Chaos::from_env(name).map(Arc::new),
+        }
+    }
+
+    /// [`BreakerGroup::new`] with [`CircuitBreakerConfig::from_env`].
+    pub fn from_env(name: &str) -> Self {
+        BreakerGroup::new(name, CircuitBreakerConfig::from_env(name))
+    }
+
+    /// The breaker for `key`.
+    pub fn get(&self, key: &str) -> Arc<CircuitBreaker> {
+        let mut breakers = self.breakers.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(breaker) = breakers.get(key) {
+            return Arc::clone(breaker);
+        }
+        if breakers.len() >= MAX_GROUP_BREAKERS {
+            breakers.retain(|_, breaker| Arc::strong_count(breaker) > 1 || !breaker.is_closed());
+        }
+        let breaker = Arc::new(CircuitBreaker::with_label(
+            &format!("{}/{}", self.name, key),
+            &self.name,
+            self.config.clone(),
+            self.chaos.clone(),
+        ));
+        breakers.insert(key.to_string(), Arc::clone(&breaker));
+        breaker
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chaos::ChaosConfig;
+
+    fn config(open_duration: Duration) -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            failure_rate_threshold: 0.5,
+            minimum_calls: 4,
+            window_size: 4,
+            open_duration,
+            half_open_calls: 2,
+        }
+    }
+
+    async fn fail(breaker: &CircuitBreaker) -> Result<(), BreakerError<&'static str>> {
+        breaker.call(async { Err::<(), _>("down") }).await
+    }
+
+    async fn succeed(breaker: &CircuitBreaker) -> Result<(), BreakerError<&'static str>> {
+        breaker.call(async { Ok::<_, &str>(()) }).await
+    }
+
+    #[tokio::test]
+    async fn test_opens_once_the_failure_rate_reaches_the_threshold() {
+        let breaker = CircuitBreaker::new("test_opens", config(Duration::from_secs(60)));
+        assert!(succeed(&breaker).await.is_ok());
+        assert!(succeed(&breaker).await.is_ok());
+        assert!(matches!(fail(&breaker).await, Err(BreakerError::Failed(_))));
+        assert!(breaker.is_closed(), "too few calls to judge");
+        assert!(matches!(fail(&breaker).await, Err(BreakerError::Failed(_))));
+        assert!(!breaker.is_closed());
+
+        assert!(matches!(
+            succeed(&breaker).await,
+            Err(BreakerError::Open(_))
+        ));
+        assert_eq!(breaker.metrics.opened.get(), 1);
+        assert_eq!(breaker.metrics.rejections.get(), 1);
+        assert_eq!(breaker.metrics.open.get(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_half_open_closes_after_successful_trials_and_reopens_on_failure() {
+        let breaker = CircuitBreaker::new("test_half_open", config(Duration::ZERO));
+        for _ in 0..4 {
+            let _ = fail(&breaker).await;
+        }
+        assert!(!breaker.is_closed());
+
+        // The open duration has passed: one trial fails and the breaker opens again.
+        assert!(matches!(fail(&breaker).await, Err(BreakerError::Failed(_))));
+        assert_eq!(breaker.metrics.opened.get(), 2);
+
+        let first = breaker.try_acquire().expect("first trial");
+        let second = breaker.try_acquire().expect("second trial");
+        assert!(breaker.try_acquire().is_err(), "only two trials at once");
+        first.succeeded();
+        second.succeeded();
+        assert!(breaker.is_closed());
+        assert_eq!(breaker.metrics.open.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_with_only_counts_failures() {
+        let breaker = CircuitBreaker::new("test_call_with", config(Duration::from_secs(60)));
+        for _ in 0..8 {
+            let result = breaker
+                .call_with(|e: &&str| *e == "down", async { Err::<(), _>("rejected") })
+                .await;
+            assert!(matches!(result, Err(BreakerError::Failed("rejected"))));
+        }
+        assert!(breaker.is_closed());
+    }
+
+    #[test]
+    fn test_dropped_permit_counts_as_failure() {
+        let breaker = CircuitBreaker::new("test_dropped", config(Duration::from_secs(60)));
+        for _ in 0..4 {
+            drop(breaker.try_acquire().expect("closed"));
+        }
+        assert!(!breaker.is_closed());
+        assert!(breaker.try_acquire().is_err());
+    }
+
+    #[test]
+    fn test_late_outcomes_do_not_count_in_a_later_phase() {
+        let breaker = CircuitBreaker::new("test_late", config(Duration::ZERO));
+        let late = breaker.try_acquire().expect("closed");
+        for _ in 0..4 {
+            breaker.try_acquire().expect("closed").failed();
+        }
+        let trial = breaker.try_acquire().expect("half-open");
+        late.failed();
+        trial.succeeded();
+        breaker.try_acquire().expect("half-open").succeeded();
+        assert!(breaker.is_closed());
+    }
+
+    #[test]
+    fn test_group_keeps_one_breaker_per_key() {
+        let group = BreakerGroup::new("test_group", config(Duration::from_secs(60)));
+        let down = group.get("down.example");
+        for _ in 0..4 {
+            down.try_acquire().expect("closed").failed();
+        }
+        assert!(group.get("down.example").try_acquire().is_err());
+        assert!(group.get("up.example").try_acquire().is_ok());
+        assert_eq!(down.name(), "test_group/down.example");
+    }
+
+    #[tokio::test]
+    async fn test_injected_faults_count_as_failures() {
+        let breaker = CircuitBreaker::with_label(
+            "test_chaos",
+            "test_chaos",
+            config(Duration::from_secs(60)),
+            Some(Arc::new(Chaos::new(
+                "test_chaos",
+                ChaosConfig {
+                    delay_rate: 0.0,
+                    max_delay: Duration::ZERO,
+                    drop_rate: 0.0,
+                    error_rate: 1.0,
+                },
+            ))),
+        );
+        for _ in 0..4 {
+            assert!(matches!(
+                succeed(&breaker).await,
+                Err(BreakerError::Injected(_))
+            ));
+        }
+        assert!(!breaker.is_closed());
+    }
+
+    #[test]
+    fn test_breaker_error_converts_a_refusal() {
+        let error: BreakerError<Box<dyn Error + Send + Sync>> = CircuitOpen {
+            name: "qdrant".to_string(),
+        }
+        .into();
+        let error = error.into_error();
+        assert!(error.is::<CircuitOpen>());
+        assert_eq!(error.to_string(), "circuit breaker qdrant is open");
+    }
+}
@@ -0,0 +1,234 @@
+//! Fault injection, so retries, dead letters and circuit breakers can be exercised against a
+//! running pipeline. Off unless `CHAOS_ENABLED` is set; then every target, a breaker such
+//! as `qdrant` or the consumer of a stream such as `raw_text`, delays, drops or fails the
+//! calls or messages going through it at the rates configured for it. Each injected fault
+//! is logged and counted in `symbiont_chaos_faults_total`, labelled with its target.
+
+use log::{debug, warn};
+use prometheus::{IntCounterVec, Opts};
+use rand::Rng;
+use shared_config::{env_flag_or, env_parse_or};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Longest injected delay unless `CHAOS_DELAY_MS` says otherwise.
+const DEFAULT_MAX_DELAY: Duration = Duration::from_secs(1);
+
+static FAULTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    let faults = IntCounterVec::new(
+        Opts::new("symbiont_chaos_faults_total", "Faults injected, by kind."),
+        &["target", "fault"],
+    )
+    .expect("valid metric");
+    if let Err(e) = shared_telemetry::registry().register(Box::new(faults.clone())) {
+        warn!("[CHAOS] Failed to register fault injection metrics: {}", e);
+    }
+    faults
+});
+
+/// How often a target's calls or messages are delayed, dropped or failed. The rates are
+/// shares from 0 to 1 of everything going through the target.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChaosConfig {
+    pub delay_rate: f64,
+    /// Delays are drawn evenly up to this.
+    pub max_delay: Duration,
+    pub drop_rate: f64,
+    pub error_rate: f64,
+}
+
+impl ChaosConfig {
+    /// `CHAOS_<TARGET>_DELAY_RATE`, `CHAOS_<TARGET>_DELAY_MS`, `CHAOS_<TARGET>_DROP_RATE`
+    /// and `CHAOS_<TARGET>_ERROR_RATE`, each falling back to the same variable without the
+    /// target, e.g. `CHAOS_ERROR_RATE`. `None` unless `CHAOS_ENABLED` is set and a rate is
+    /// above zero.
+    pub fn from_env(target: &str) -> Option<Self> {
+        if !env_flag_or("CHAOS_ENABLED", false) {
+            return None;
+        }
+        let rate = |suffix: &str| chaos_var(target, suffix, 0.0_f64).clamp(0.0, 1.0);
+        let config = ChaosConfig {
+            delay_rate: rate("DELAY_RATE"),
+            max_delay: Duration::from_millis(chaos_var(
+                target,
+                "DELAY_MS",
+                DEFAULT_MAX_DELAY.as_millis() as u64,
+            )),
+            drop_rate: rate("DROP_RATE"),
+            error_rate: rate("ERROR_RATE"),
+        };
+        (config.delay_rate + config.drop_rate + config.error_rate > 0.0).then_some(config)
+    }
+}
+
+/// `CHAOS_<TARGET>_<SUFFIX>`, else `CHAOS_<SUFFIX>`, else `default`.
+fn chaos_var<T: FromStr>(target: &str, suffix: &str, default: T) -> T {
+    let fallback = env_parse_or(&format!("CHAOS_{}", suffix), default);
+    env_parse_or(
+        &format!("CHAOS_{}_{}", target.to_uppercase(), suffix),
+        fallback,
+    )
+}
+
+/// What happens to one call or message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// It goes through this much later.
+    Delay(Duration),
+    /// It is lost: a message never reaches its handler, a call's response never reaches
+    /// the caller.
+    Drop,
+    /// It fails without being handled or made.
+    Error,
+}
+
+impl Fault {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Fault::Delay(_) => "delay",
+            Fault::Drop => "drop",
+            Fault::Error => "error",
+        }
+    }
+}
+
+/// The error of a call dropped or failed by fault injection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InjectedFault {
+    pub target: String,
+    pub fault: Fault,
+}
+
+impl fmt::Display for InjectedFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "injected {} fault in {}",
+            self.fault.as_str(),
+            self.target
+        )
+    }
+}
+
+impl Error for InjectedFault {}
+
+/// Draws the faults of one target.
+#[derive(Debug, Clone)]
+pub struct Chaos {
+    target: String,
+    config: ChaosConfig,
+}
+
+impl Chaos {
+    pub fn new(target: &str, config: ChaosConfig) -> Self {
+        warn!(
+            "[CHAOS] Injecting faults into {}: {:.0}% delayed by up to {:?}, {:.0}% dropped, {:.0}% failed",
+            target,
+            config.delay_rate * 100.0,
+            config.max_delay,
+            config.drop_rate * 100.0,
+            config.error_rate * 100.0
+        );
+        Chaos {
+            target: target.to_string(),
+            config,
+        }
+    }
+
+    /// [`Chaos::new`] with [`ChaosConfig::from_env`]; `None` while fault injection is off
+    /// for `target`.
+    pub fn from_env(target: &str) -> Option<Self> {
+        ChaosConfig::from_env(target).map(|config| Chaos::new(target, config))
+    }
+
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// The fault to inject into the next call or message, if any.
+    pub fn next_fault(&self) -> Option<Fault> {
+        let mut rng = rand::thread_rng();
+        let fault = self.pick(rng.gen_range(0.0..1.0), rng.gen_range(0.0..=1.0))?;
+        debug!("[CHAOS] Injecting {:?} into {}.", fault, self.target);
+        FAULTS
+            .with_label_values(&[self.target.as_str(), fault.as_str()])
+            .inc();
+        Some(fault)
+    }
+
+    /// [`InjectedFault`] for a `fault` drawn by this target.
+    pub fn injected(&self, fault: Fault) -> InjectedFault {
+        InjectedFault {
+            target: self.target.clone(),
+            fault,
+        }
+    }
+
+    /// The fault `roll`, from 0 to 1, lands on: errors first, then drops, then delays of
+    /// `delay_share` of the longest delay.
+    fn pick(&self, roll: f64, delay_share: f64) -> Option<Fault> {
+        let ChaosConfig {
+            delay_rate,
+            max_delay,
+            drop_rate,
+            error_rate,
+        } = self.config;
+        if roll < error_rate {
+            Some(Fault::Error)
+        } else if roll < error_rate + drop_rate {
+            Some(Fault::Drop)
+        } else if roll < error_rate + drop_rate + delay_rate {
+            Some(Fault::Delay(max_delay.mul_f64(delay_share.clamp(0.0, 1.0))))
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rolls_land_on_errors_then_drops_then_delays() {
+        let chaos = Chaos::new(
+            "test_pick",
+            ChaosConfig {
+                delay_rate: 0.3,
+                max_delay: Duration::from_millis(200),
+                drop_rate: 0.2,
+                error_rate: 0.1,
+            },
+        );
+        assert_eq!(chaos.pick(0.05, 0.5), Some(Fault::Error));
+        assert_eq!(chaos.pick(0.1, 0.5), Some(Fault::Drop));
+        assert_eq!(chaos.pick(0.29, 0.5), Some(Fault::Drop));
+        assert_eq!(
+            chaos.pick(0.35, 0.5),
+            Some(Fault::Delay(Duration::from_millis(100)))
+        );
+        assert_eq!(chaos.pick(0.7, 0.5), None);
+        assert_eq!(chaos.pick(0.99, 0.5), None);
+    }
+
+    #[test]
+    fn test_injected_fault_names_its_target() {
+        let chaos = Chaos::new(
+            "qdrant",
+            ChaosConfig {
+                delay_rate: 0.0,
+                max_delay: Duration::ZERO,
+                drop_rate: 0.0,
+                error_rate: 1.0,
+            },
+        );
+        assert_eq!(chaos.next_fault(), Some(Fault::Error));
+        assert_eq!(
+            chaos.injected(Fault::Error).to_string(),
+            "injected error fault in qdrant"
+        );
+    }
+}
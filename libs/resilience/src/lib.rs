@@ -0,0 +1,19 @@
+//! Protection for calls to the pipeline's dependencies: Qdrant, Neo4j, the pages the
+//! perception service fetches and the services answering NATS requests. A
+//! [`CircuitBreaker`] watches the outcome of recent calls to one dependency and, once too
+//! many of them fail, refuses further calls at once for a while instead of letting them
+//! pile up behind timeouts. It then lets a few trial calls through and closes again when
+//! they succeed. A [`BreakerGroup`] keeps one breaker per key, e.g. per host. Calls that
+//! fail for a passing reason are retried with [`retry_with_backoff`]. With fault injection
+//! turned on ([`Chaos`]), calls through a breaker are delayed, dropped or failed at random to
+//! test all of this.
+
+mod breaker;
+mod chaos;
+mod retry;
+
+pub use breaker::{
+    BreakerError, BreakerGroup, BreakerPermit, CircuitBreaker, CircuitBreakerConfig, CircuitOpen,
+};
+pub use chaos::{Chaos, ChaosConfig, Fault, InjectedFault};
+pub use retry::{DEFAULT_JITTER, RetryPolicy, retry_with_backoff};
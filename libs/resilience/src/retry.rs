@@ -0,0 +1,186 @@
+//! Retrying failed calls with bounded exponential backoff. The backoff doubles after every
+//! failed attempt up to a cap, and a random share of it is taken off so callers that failed
+//! together do not retry together. The caller decides which errors are worth retrying.
+
+use log::warn;
+use rand::Rng;
+use shared_config::env_parse_or;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+/// Share of every backoff taken off at random unless a policy says otherwise.
+pub const DEFAULT_JITTER: f64 = 0.2;
+
+/// Bounded exponential backoff: `initial_backoff`, doubled after every failed attempt, capped
+/// at `max_backoff`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    /// Share of each backoff, from 0 to 1, taken off at random.
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// A policy with [`DEFAULT_JITTER`].
+    pub const fn new(max_retries: u32, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        RetryPolicy {
+            max_retries,
+            initial_backoff,
+            max_backoff,
+            jitter: DEFAULT_JITTER,
+        }
+    }
+
+    /// `defaults` with `<PREFIX>_MAX_RETRIES`, `<PREFIX>_RETRY_BACKOFF_MS`,
+    /// `<PREFIX>_RETRY_MAX_BACKOFF_MS` and `<PREFIX>_RETRY_JITTER` applied, e.g. with prefix
+    /// `NEO4J_WRITE`.
+    pub fn from_env(prefix: &str, defaults: RetryPolicy) -> Self {
+        let key = |suffix: &str| format!("{}_{}", prefix, suffix);
+        let millis = |suffix: &str, default: Duration| {
+            Duration::from_millis(env_parse_or(&key(suffix), default.as_millis() as u64))
+        };
+        RetryPolicy {
+            max_retries: env_parse_or(&key("MAX_RETRIES"), defaults.max_retries),
+            initial_backoff: millis("RETRY_BACKOFF_MS", defaults.initial_backoff),
+            max_backoff: millis("RETRY_MAX_BACKOFF_MS", defaults.max_backoff),
+            jitter: env_parse_or(&key("RETRY_JITTER"), defaults.jitter).clamp(0.0, 1.0),
+        }
+    }
+
+    /// Attempts made before giving up.
+    pub fn max_attempts(&self) -> u32 {
+        self.max_retries.saturating_add(1)
+    }
+
+    /// The backoff before retry number `retry + 1`, without jitter.
+    pub fn backoff_for_retry(&self, retry: u32) -> Duration {
+        self.initial_backoff
+            .saturating_mul(2u32.saturating_pow(retry))
+            .min(self.max_backoff)
+    }
+
+    /// [`RetryPolicy::backoff_for_retry`] with a random share of up to `jitter` taken off.
+    pub fn jittered_backoff(&self, retry: u32) -> Duration {
+        let backoff = self.backoff_for_retry(retry);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return backoff;
+        }
+        backoff.mul_f64(1.0 - rand::thread_rng().gen_range(0.0..=jitter))
+    }
+}
+
+/// Runs `operation` until it succeeds, fails with an error `is_retryable` rejects, or
+/// `policy.max_retries` retries are used up. Returns the last result together with the
+/// number of attempts made.
+pub async fn retry_with_backoff<T, E, F, Fut, R>(
+    policy: &RetryPolicy,
+    description: &str,
+    mut operation: F,
+    is_retryable: R,
+) -> (Result<T, E>, u32)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    R: Fn(&E) -> bool,
+    E: Display,
+{
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match operation().await {
+            Ok(value) => return (Ok(value), attempt),
+            Err(e) if attempt > policy.max_retries || !is_retryable(&e) => {
+                return (Err(e), attempt);
+            }
+            Err(e) => {
+                let delay = policy.jittered_backoff(attempt - 1);
+                warn!(
+                    "[RETRY] {} failed (attempt {}/{}): {}. Retrying in {:?}...",
+                    description,
+                    attempt,
+                    policy.max_attempts(),
+                    e,
+                    delay
+                );
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn test_backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::new(5, Duration::from_millis(100), Duration::from_millis(350));
+        assert_eq!(policy.backoff_for_retry(0), Duration::from_millis(100));
+        assert_eq!(policy.backoff_for_retry(1), Duration::from_millis(200));
+        assert_eq!(policy.backoff_for_retry(2), Duration::from_millis(350));
+        assert_eq!(policy.backoff_for_retry(40), Duration::from_millis(350));
+        assert_eq!(policy.max_attempts(), 6);
+    }
+
+    #[test]
+    fn test_jitter_only_shortens_the_backoff() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::new(3, Duration::from_secs(1), Duration::from_secs(1))
+        };
+        for _ in 0..100 {
+            let backoff = policy.jittered_backoff(0);
+            assert!(backoff >= Duration::from_millis(500) && backoff <= Duration::from_secs(1));
+        }
+        let exact = RetryPolicy {
+            jitter: 0.0,
+            ..policy
+        };
+        assert_eq!(exact.jittered_backoff(0), Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn test_retries_until_success_or_exhaustion() {
+        let policy = RetryPolicy::new(2, Duration::ZERO, Duration::ZERO);
+        let calls = Cell::new(0);
+        let (result, attempts) = retry_with_backoff(
+            &policy,
+            "flaky call",
+            || {
+                calls.set(calls.get() + 1);
+                let succeeds = calls.get() == 2;
+                async move { if succeeds { Ok(()) } else { Err("down") } }
+            },
+            |_| true,
+        )
+        .await;
+        assert_eq!((result, attempts), (Ok(()), 2));
+
+        let (result, attempts) = retry_with_backoff(
+            &policy,
+            "dead call",
+            || async { Err::<(), _>("down") },
+            |_| true,
+        )
+        .await;
+        assert_eq!((result, attempts), (Err("down"), 3));
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_an_error_not_worth_retrying() {
+        let policy = RetryPolicy::new(5, Duration::ZERO, Duration::ZERO);
+        let (result, attempts) = retry_with_backoff(
+            &policy,
+            "rejected call",
+            || async { Err::<(), _>("invalid") },
+            |e| *e != "invalid",
+        )
+        .await;
+        assert_eq!((result, attempts), (Err("invalid"), 1));
+    }
+}
@@ -0,0 +1,448 @@
+//! Protobuf encoding of the embedding-heavy messages, where JSON floats dominate both the
+//! size and the parse time. The message body's encoding is named in the
+//! [`CONTENT_TYPE_HEADER`] NATS header; messages without it are JSON.
+
+use crate::{
+    DocumentMetadata, Envelope, QueryEmbeddingResult, SentenceEmbedding, SparseVector,
+    TextWithEmbeddingsMessage,
+};
+use prost::Message;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::str::FromStr;
+
+/// NATS header naming how a message body is encoded.
+pub const CONTENT_TYPE_HEADER: &str = "Content-Type";
+/// NATS request header naming the encoding the requester wants its reply in.
+pub const ACCEPT_HEADER: &str = "Accept";
+
+const JSON_CONTENT_TYPE: &str = "application/json";
+const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PayloadFormat {
+    #[default]
+    Json,
+    Protobuf,
+}
+
+impl PayloadFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            PayloadFormat::Json => JSON_CONTENT_TYPE,
+            PayloadFormat::Protobuf => PROTOBUF_CONTENT_TYPE,
+        }
+    }
+
+    /// Format named by a content-type header value, ignoring parameters. A missing header
+    /// means JSON; an unknown type gives `None`.
+    pub fn from_content_type(content_type: Option<&str>) -> Option<Self> {
+        let Some(content_type) = content_type else {
+            return Some(PayloadFormat::Json);
+        };
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if media_type.eq_ignore_ascii_case(JSON_CONTENT_TYPE) {
+            Some(PayloadFormat::Json)
+        } else if media_type.eq_ignore_ascii_case(PROTOBUF_CONTENT_TYPE) {
+            Some(PayloadFormat::Protobuf)
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for PayloadFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "json" => Ok(PayloadFormat::Json),
+            "protobuf" => Ok(PayloadFormat::Protobuf),
+            other => Err(format!(
+                "unknown payload format '{}' (expected json or protobuf)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PayloadError {
+    Json(serde_json::Error),
+    Protobuf(prost::DecodeError),
+    InvalidId(uuid::Error),
+    UnknownContentType(String),
+}
+
+impl fmt::Display for PayloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PayloadError::Json(e) => write!(f, "invalid JSON payload: {}", e),
+            PayloadError::Protobuf(e) => write!(f, "invalid protobuf payload: {}", e),
+            PayloadError::InvalidId(e) => write!(f, "invalid id in payload: {}", e),
+            PayloadError::UnknownContentType(content_type) => {
+                write!(f, "unsupported content type '{}'", content_type)
+            }
+        }
+    }
+}
+
+impl std::error::Error for PayloadError {}
+
+impl From<serde_json::Error> for PayloadError {
+    fn from(e: serde_json::Error) -> Self {
+        PayloadError::Json(e)
+    }
+}
+
+impl From<prost::DecodeError> for PayloadError {
+    fn from(e: prost::DecodeError) -> Self {
+        PayloadError::Protobuf(e)
+    }
+}
+
+impl From<uuid::Error> for PayloadError {
+    fn from(e: uuid::Error) -> Self {
+        PayloadError::InvalidId(e)
+    }
+}
+
+/// A payload with a protobuf representation.
+pub trait EncodeProtobuf {
+    type Proto: Message;
+
+    fn to_proto(&self) -> Self::Proto;
+}
+
+pub trait DecodeProtobuf: Sized {
+    type Proto: Message + Default;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError>;
+}
+
+impl<T: EncodeProtobuf + ?Sized> EncodeProtobuf for &T {
+    type Proto = T::Proto;
+
+    fn to_proto(&self) -> Self::Proto {
+        (**self).to_proto()
+    }
+}
+
+impl<T: Serialize + EncodeProtobuf> Envelope<T> {
+    pub fn encode(&self, format: PayloadFormat) -> Result<Vec<u8>, PayloadError> {
+        match format {
+            PayloadFormat::Json => Ok(self.to_vec()?),
+            PayloadFormat::Protobuf => Ok(EnvelopeProto {
+                schema_version: self.schema_version,
+                message_id: self.message_id.clone(),
+                correlation_id: self.correlation_id.clone(),
+                causation_id: self.causation_id.clone(),
+                produced_by: self.produced_by.clone(),
+                timestamp_ms: self.timestamp_ms,
+                payload: self.payload.to_proto().encode_to_vec(),
+                tenant_id: self.tenant_id.clone(),
+            }
+            .encode_to_vec()),
+        }
+    }
+}
+
+impl<T: DeserializeOwned + DecodeProtobuf> Envelope<T> {
+    pub fn decode(bytes: &[u8], format: PayloadFormat) -> Result<Self, PayloadError> {
+        match format {
+            PayloadFormat::Json => Ok(Envelope::from_slice(bytes)?),
+            PayloadFormat::Protobuf => {
+                let envelope = EnvelopeProto::decode(bytes)?;
+                let payload = T::Proto::decode(envelope.payload.as_slice())?;
+                Ok(Envelope {
+                    schema_version: envelope.schema_version,
+                    message_id: envelope.message_id,
+                    correlation_id: envelope.correlation_id,
+                    causation_id: envelope.causation_id,
+                    produced_by: envelope.produced_by,
+                    timestamp_ms: envelope.timestamp_ms,
+                    tenant_id: envelope.tenant_id,
+                    payload: T::from_proto(payload)?,
+                })
+            }
+        }
+    }
+
+    /// [`Envelope::decode`] in the format named by the message's content-type header.
+    pub fn decode_with_content_type(
+        bytes: &[u8],
+        content_type: Option<&str>,
+    ) -> Result<Self, PayloadError> {
+        let format = PayloadFormat::from_content_type(content_type).ok_or_else(|| {
+            PayloadError::UnknownContentType(content_type.unwrap_or_default().to_string())
+        })?;
+        Envelope::decode(bytes, format)
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct EnvelopeProto {
+    #[prost(uint32, tag = "1")]
+    schema_version: u32,
+    #[prost(string, tag = "2")]
+    message_id: String,
+    #[prost(string, tag = "3")]
+    correlation_id: String,
+    #[prost(string, optional, tag = "4")]
+    causation_id: Option<String>,
+    #[prost(string, tag = "5")]
+    produced_by: String,
+    #[prost(uint64, tag = "6")]
+    timestamp_ms: u64,
+    /// The encoded payload message.
+    #[prost(bytes = "vec", tag = "7")]
+    payload: Vec<u8>,
+    #[prost(string, optional, tag = "8")]
+    tenant_id: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SparseVectorProto {
+    #[prost(uint32, repeated, tag = "1")]
+    indices: Vec<u32>,
+    #[prost(float, repeated, tag = "2")]
+    values: Vec<f32>,
+}
+
+impl EncodeProtobuf for SparseVector {
+    type Proto = SparseVectorProto;
+
+    fn to_proto(&self) -> Self::Proto {
+        SparseVectorProto {
+            indices: self.indices.clone(),
+            values: self.values.clone(),
+        }
+    }
+}
+
+impl DecodeProtobuf for SparseVector {
+    type Proto = SparseVectorProto;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError> {
+        Ok(SparseVector {
+            indices: proto.indices,
+            values: proto.values,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct SentenceEmbeddingProto {
+    #[prost(string, tag = "1")]
+    sentence_text: String,
+    #[prost(float, repeated, tag = "2")]
+    embedding: Vec<f32>,
+    #[prost(message, optional, tag = "3")]
+    sparse_embedding: Option<SparseVectorProto>,
+    #[prost(uint32, optional, tag = "4")]
+    sentence_order: Option<u32>,
+}
+
+impl EncodeProtobuf for SentenceEmbedding {
+    type Proto = SentenceEmbeddingProto;
+
+    fn to_proto(&self) -> Self::Proto {
+        SentenceEmbeddingProto {
+            sentence_text: self.sentence_text.clone(),
+            embedding: self.embedding.clone(),
+            sparse_embedding: self.sparse_embedding.as_ref().map(EncodeProtobuf::to_proto),
+            sentence_order: self.sentence_order,
+        }
+    }
+}
+
+impl DecodeProtobuf for SentenceEmbedding {
+    type Proto = SentenceEmbeddingProto;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError> {
+        Ok(SentenceEmbedding {
+            sentence_text: proto.sentence_text,
+            embedding: proto.embedding,
+            sparse_embedding: proto
+                .sparse_embedding
+                .map(SparseVector::from_proto)
+                .transpose()?,
+            sentence_order: proto.sentence_order,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct DocumentMetadataProto {
+    #[prost(string, optional, tag = "1")]
+    title: Option<String>,
+    #[prost(string, optional, tag = "2")]
+    language: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    author: Option<String>,
+    #[prost(string, optional, tag = "4")]
+    published_at: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    content_type: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    canonical_url: Option<String>,
+}
+
+impl EncodeProtobuf for DocumentMetadata {
+    type Proto = DocumentMetadataProto;
+
+    fn to_proto(&self) -> Self::Proto {
+        DocumentMetadataProto {
+            title: self.title.clone(),
+            language: self.language.clone(),
+            author: self.author.clone(),
+            published_at: self.published_at.clone(),
+            content_type: self.content_type.clone(),
+            canonical_url: self.canonical_url.clone(),
+        }
+    }
+}
+
+impl DecodeProtobuf for DocumentMetadata {
+    type Proto = DocumentMetadataProto;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError> {
+        Ok(DocumentMetadata {
+            title: proto.title,
+            language: proto.language,
+            author: proto.author,
+            published_at: proto.published_at,
+            content_type: proto.content_type,
+            canonical_url: proto.canonical_url,
+        })
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TextWithEmbeddingsMessageProto {
+    #[prost(string, tag = "1")]
+    original_id: String,
+    #[prost(string, tag = "2")]
+    source_url: String,
+    #[prost(message, repeated, tag = "3")]
+    embeddings_data: Vec<SentenceEmbeddingProto>,
+    #[prost(string, tag = "4")]
+    model_name: String,
+    #[prost(uint64, tag = "5")]
+    timestamp_ms: u64,
+    #[prost(string, optional, tag = "6")]
+    tenant_id: Option<String>,
+    #[prost(message, optional, tag = "7")]
+    metadata: Option<DocumentMetadataProto>,
+    #[prost(uint32, optional, tag = "8")]
+    chunk_index: Option<u32>,
+    #[prost(uint32, optional, tag = "9")]
+    total_chunks: Option<u32>,
+    #[prost(string, optional, tag = "10")]
+    parent_document_id: Option<String>,
+}
+
+impl EncodeProtobuf for TextWithEmbeddingsMessage {
+    type Proto = TextWithEmbeddingsMessageProto;
+
+    fn to_proto(&self) -> Self::Proto {
+        TextWithEmbeddingsMessageProto {
+            original_id: self.original_id.to_string(),
+            source_url: self.source_url.clone(),
+            embeddings_data: self
+                .embeddings_data
+                .iter()
+                .map(EncodeProtobuf::to_proto)
+                .collect(),
+            model_name: self.model_name.clone(),
+            timestamp_ms: self.timestamp_ms,
+            tenant_id: self.tenant_id.clone(),
+            metadata: (!self.metadata.is_empty()).then(|| self.metadata.to_proto()),
+            chunk_index: self.chunk_index,
+            total_chunks: self.total_chunks,
+            parent_document_id: self.parent_document_id.map(|id| id.to_string()),
+        }
+    }
+}
+
+impl DecodeProtobuf for TextWithEmbeddingsMessage {
+    type Proto = TextWithEmbeddingsMessageProto;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError> {
+        Ok(TextWithEmbeddingsMessage {
+            original_id: proto.original_id.parse()?,
+            source_url: proto.source_url,
+            embeddings_data: proto
+                .embeddings_data
+                .into_iter()
+                .map(SentenceEmbedding::from_proto)
+                .collect::<Result<_, _>>()?,
+            model_name: proto.model_name,
+            timestamp_ms: proto.timestamp_ms,
+            tenant_id: proto.tenant_id,
+            metadata: proto
+                .metadata
+                .map(DocumentMetadata::from_proto)
+                .transpose()?
+                .unwrap_or_default(),
+            chunk_index: proto.chunk_index,
+            total_chunks: proto.total_chunks,
+            parent_document_id: proto.parent_document_id.map(|id| id.parse()).transpose()?,
+        })
+    }
+}
+
+/// Wraps the dense vector so that "no embedding" stays distinct from an empty one.
+#[derive(Clone, PartialEq, Message)]
+pub struct DenseVectorProto {
+    #[prost(float, repeated, tag = "1")]
+    values: Vec<f32>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct QueryEmbeddingResultProto {
+    #[prost(string, tag = "1")]
+    request_id: String,
+    #[prost(message, optional, tag = "2")]
+    embedding: Option<DenseVectorProto>,
+    #[prost(message, optional, tag = "3")]
+    sparse_embedding: Option<SparseVectorProto>,
+    #[prost(string, optional, tag = "4")]
+    model_name: Option<String>,
+    #[prost(string, optional, tag = "5")]
+    error_message: Option<String>,
+}
+
+impl EncodeProtobuf for QueryEmbeddingResult {
+    type Proto = QueryEmbeddingResultProto;
+
+    fn to_proto(&self) -> Self::Proto {
+        QueryEmbeddingResultProto {
+            request_id: self.request_id.to_string(),
+            embedding: self.embedding.as_ref().map(|values| DenseVectorProto {
+                values: values.clone(),
+            }),
+            sparse_embedding: self.sparse_embedding.as_ref().map(EncodeProtobuf::to_proto),
+            model_name: self.model_name.clone(),
+            error_message: self.error_message.clone(),
+        }
+    }
+}
+
+impl DecodeProtobuf for QueryEmbeddingResult {
+    type Proto = QueryEmbeddingResultProto;
+
+    fn from_proto(proto: Self::Proto) -> Result<Self, PayloadError> {
+        Ok(QueryEmbeddingResult {
+            request_id: proto.request_id.parse()?,
+            embedding: proto.embedding.map(|vector| vector.values),
+            sparse_embedding: proto
+                .sparse_embedding
+                .map(SparseVector::from_proto)
+                .transpose()?,
+            model_name: proto.model_name,
+            error_message: proto.error_message,
+        })
+    }
+}
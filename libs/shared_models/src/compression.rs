@@ -0,0 +1,140 @@
+//! zstd compression of large message bodies, e.g. the [`crate::RawTextMessage`] of a big
+//! page or a [`crate::TextWithEmbeddingsMessage`] with thousands of sentences. A compressed
+//! body is marked by the [`CONTENT_ENCODING_HEADER`] NATS header; bodies without it are
+//! sent as-is. Compression applies to the encoded envelope, whatever its content type.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{self, Read};
+
+/// NATS header naming how a message body is compressed.
+pub const CONTENT_ENCODING_HEADER: &str = "Content-Encoding";
+
+/// Largest body [`decompress`] inflates; anything bigger is rejected rather than buffered.
+pub const MAX_DECOMPRESSED_SIZE: usize = 64 * 1024 * 1024;
+
+const ZSTD_CONTENT_ENCODING: &str = "zstd";
+const IDENTITY_CONTENT_ENCODING: &str = "identity";
+const COMPRESSION_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ContentEncoding {
+    #[default]
+    Identity,
+    Zstd,
+}
+
+impl ContentEncoding {
+    /// Value of the content-encoding header, or `None` when the header should be omitted.
+    pub fn header_value(self) -> Option<&'static str> {
+        match self {
+            ContentEncoding::Identity => None,
+            ContentEncoding::Zstd => Some(ZSTD_CONTENT_ENCODING),
+        }
+    }
+
+    /// Encoding named by a content-encoding header value. A missing header means the body
+    /// is uncompressed; an unknown encoding gives `None`.
+    pub fn from_header(content_encoding: Option<&str>) -> Option<Self> {
+        let Some(content_encoding) = content_encoding else {
+            return Some(ContentEncoding::Identity);
+        };
+        let content_encoding = content_encoding.trim();
+        if content_encoding.eq_ignore_ascii_case(ZSTD_CONTENT_ENCODING) {
+            Some(ContentEncoding::Zstd)
+        } else if content_encoding.is_empty()
+            || content_encoding.eq_ignore_ascii_case(IDENTITY_CONTENT_ENCODING)
+        {
+            Some(ContentEncoding::Identity)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CompressionError {
+    Io(io::Error),
+    TooLarge,
+    UnknownContentEncoding(String),
+}
+
+impl fmt::Display for CompressionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompressionError::Io(e) => write!(f, "invalid zstd body: {}", e),
+            CompressionError::TooLarge => write!(
+                f,
+                "decompressed body exceeds {} bytes",
+                MAX_DECOMPRESSED_SIZE
+            ),
+            CompressionError::UnknownContentEncoding(content_encoding) => {
+                write!(f, "unsupported content encoding '{}'", content_encoding)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompressionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompressionError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CompressionError {
+    fn from(e: io::Error) -> Self {
+        CompressionError::Io(e)
+    }
+}
+
+pub fn compress(body: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    Ok(zstd::stream::encode_all(body, COMPRESSION_LEVEL)?)
+}
+
+/// Inflates a zstd body of at most [`MAX_DECOMPRESSED_SIZE`] bytes.
+pub fn decompress(body: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    let decoder = zstd::stream::Decoder::new(body)?;
+    let mut decompressed = Vec::new();
+    decoder
+        .take(MAX_DECOMPRESSED_SIZE as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    if decompressed.len() > MAX_DECOMPRESSED_SIZE {
+        return Err(CompressionError::TooLarge);
+    }
+    Ok(decompressed)
+}
+
+/// Compresses `body` when it is at least `threshold` bytes and compression makes it
+/// smaller; a `threshold` of 0 disables compression. Returns the body to publish and the
+/// encoding to name in its [`CONTENT_ENCODING_HEADER`].
+pub fn compress_above(
+    body: Vec<u8>,
+    threshold: usize,
+) -> Result<(Vec<u8>, ContentEncoding), CompressionError> {
+    if threshold == 0 || body.len() < threshold {
+        return Ok((body, ContentEncoding::Identity));
+    }
+    let compressed = compress(&body)?;
+    if compressed.len() < body.len() {
+        Ok((compressed, ContentEncoding::Zstd))
+    } else {
+        Ok((body, ContentEncoding::Identity))
+    }
+}
+
+/// The body of a received message, decompressed according to its content-encoding header.
+pub fn decode_body<'a>(
+    body: &'a [u8],
+    content_encoding: Option<&str>,
+) -> Result<Cow<'a, [u8]>, CompressionError> {
+    match ContentEncoding::from_header(content_encoding) {
+        Some(ContentEncoding::Identity) => Ok(Cow::Borrowed(body)),
+        Some(ContentEncoding::Zstd) => Ok(Cow::Owned(decompress(body)?)),
+        None => Err(CompressionError::UnknownContentEncoding(
+            content_encoding.unwrap_or_default().to_string(),
+        )),
+    }
+}
@@ -0,0 +1,130 @@
+//! The dead-letter queue convention. A service that gives up on a message publishes it to
+//! `dlq.<service>.<original subject>` (see [`dead_letter_subject`]) as a
+//! [`DeadLetterMessage`] with the error and the number of attempts. A message that could
+//! not even be decoded is kept byte for byte as an [`UndecodedPayload`]. Either kind turns
+//! back into the message to republish on its original subject with
+//! [`ReplayMessage::from_dead_letter`].
+
+use crate::{DeadLetterMessage, Envelope};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// First token of every dead-letter subject.
+pub const DEAD_LETTER_SUBJECT_PREFIX: &str = "dlq";
+
+/// Subject a service dead-letters messages received on `original_subject` to, e.g.
+/// `dlq.vector_memory_service.data.text.with_embeddings`. An `original_subject` of `>`
+/// gives the wildcard matching all of the service's dead letters.
+pub fn dead_letter_subject(service: &str, original_subject: &str) -> String {
+    format!(
+        "{}.{}.{}",
+        DEAD_LETTER_SUBJECT_PREFIX, service, original_subject
+    )
+}
+
+/// The service and original subject named by a dead-letter subject.
+pub fn parse_dead_letter_subject(subject: &str) -> Option<(&str, &str)> {
+    let rest = subject
+        .strip_prefix(DEAD_LETTER_SUBJECT_PREFIX)?
+        .strip_prefix('.')?;
+    let (service, original_subject) = rest.split_once('.')?;
+    (!service.is_empty() && !original_subject.is_empty()).then_some((service, original_subject))
+}
+
+/// A received message body that could not be decoded, with the headers needed to
+/// republish it unchanged, such as its content type and encoding. The body is base64 in
+/// JSON.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct UndecodedPayload {
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(with = "base64_body")]
+    pub body_base64: Vec<u8>,
+}
+
+impl UndecodedPayload {
+    pub fn new(body: &[u8]) -> Self {
+        UndecodedPayload {
+            headers: BTreeMap::new(),
+            body_base64: body.to_vec(),
+        }
+    }
+
+    /// Keeps header `name` when the message had it.
+    pub fn with_header(mut self, name: &str, value: Option<&str>) -> Self {
+        if let Some(value) = value {
+            self.headers.insert(name.to_string(), value.to_string());
+        }
+        self
+    }
+}
+
+impl fmt::Debug for UndecodedPayload {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UndecodedPayload")
+            .field("headers", &self.headers)
+            .field(
+                "body_base64",
+                &format_args!("[{} bytes]", self.body_base64.len()),
+            )
+            .finish()
+    }
+}
+
+mod base64_body {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| serde::de::Error::custom(format!("invalid base64 body: {}", e)))
+    }
+}
+
+/// A dead letter read without knowing its payload type, e.g. for inspection or replay.
+pub type AnyDeadLetter = DeadLetterMessage<serde_json::Value>;
+
+/// The message a dead letter is republished as on its original subject.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayMessage {
+    pub subject: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl ReplayMessage {
+    /// An [`UndecodedPayload`] is replayed byte for byte with its original headers. Any
+    /// other payload is sent as JSON in an envelope following the dead letter's, so the
+    /// replayed message keeps its correlation id.
+    pub fn from_dead_letter(
+        envelope: &Envelope<AnyDeadLetter>,
+        produced_by: &str,
+    ) -> serde_json::Result<Self> {
+        let dead_letter = &envelope.payload;
+        if let Ok(undecoded) =
+            serde_json::from_value::<UndecodedPayload>(dead_letter.payload.clone())
+        {
+            return Ok(ReplayMessage {
+                subject: dead_letter.original_subject.clone(),
+                headers: undecoded.headers,
+                body: undecoded.body_base64,
+            });
+        }
+        Ok(ReplayMessage {
+            subject: dead_letter.original_subject.clone(),
+            headers: BTreeMap::new(),
+            body: envelope
+                .follow_up(produced_by, &dead_letter.payload)
+                .to_vec()?,
+        })
+    }
+}
@@ -0,0 +1,129 @@
+//! The health check convention. Every service answers requests on its [`health_subject`]
+//! with a [`ServiceHealthResult`] listing one [`DependencyCheck`] per dependency it needs
+//! to do its work, such as the NATS connection, Qdrant, Neo4j or a loaded model. The
+//! service's status is the worst status among its checks.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// First token of every health check subject.
+pub const HEALTH_SUBJECT_PREFIX: &str = "health";
+
+/// Checks that pass but take longer than this report [`HealthStatus::Degraded`].
+pub const HEALTH_DEGRADED_LATENCY: Duration = Duration::from_millis(1000);
+
+/// Subject `service` answers health checks on: its name without the `_service` suffix,
+/// e.g. `health.vector_memory` for vector_memory_service. `health.*` reaches every service.
+pub fn health_subject(service: &str) -> String {
+    format!(
+        "{}.{}",
+        HEALTH_SUBJECT_PREFIX,
+        service.strip_suffix("_service").unwrap_or(service)
+    )
+}
+
+/// Ordered from best to worst.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// Reachable, but slower than [`HEALTH_DEGRADED_LATENCY`].
+    Degraded,
+    Unavailable,
+}
+
+/// The outcome of checking one dependency, e.g. `nats`, `qdrant`, `neo4j` or `model`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DependencyCheck {
+    pub name: String,
+    pub status: HealthStatus,
+    /// Round-trip time of the check, in milliseconds; 0 for checks without one.
+    pub latency_ms: u64,
+    /// Why the check failed, or what it found, e.g. the name of the loaded model.
+    #[serde(default)]
+    pub detail: Option<String>,
+}
+
+impl DependencyCheck {
+    /// A round trip that took `latency` and succeeded or failed with an error message.
+    pub fn timed(name: &str, latency: Duration, result: Result<(), String>) -> Self {
+        let (status, detail) = match result {
+            Ok(()) if latency > HEALTH_DEGRADED_LATENCY => (HealthStatus::Degraded, None),
+            Ok(()) => (HealthStatus::Ok, None),
+            Err(e) => (HealthStatus::Unavailable, Some(e)),
+        };
+        DependencyCheck {
+            name: name.to_string(),
+            status,
+            latency_ms: u64::try_from(latency.as_millis()).unwrap_or(u64::MAX),
+            detail,
+        }
+    }
+
+    /// A check answered without a round trip, e.g. whether a model is loaded.
+    pub fn passed(name: &str, detail: Option<String>) -> Self {
+        DependencyCheck {
+            name: name.to_string(),
+            status: HealthStatus::Ok,
+            latency_ms: 0,
+            detail,
+        }
+    }
+
+    pub fn failed(name: &str, error: impl Into<String>) -> Self {
+        DependencyCheck {
+            name: name.to_string(),
+            status: HealthStatus::Unavailable,
+            latency_ms: 0,
+            detail: Some(error.into()),
+        }
+    }
+}
+
+/// Reply to a `health.<service>` request.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServiceHealthResult {
+    pub service: String,
+    pub status: HealthStatus,
+    /// Time taken by all dependency checks, in milliseconds.
+    pub latency_ms: u64,
+    /// The failed checks, e.g. `qdrant: connection refused`.
+    #[serde(default)]
+    pub error_message: Option<String>,
+    pub timestamp_ms: u64,
+    #[serde(default)]
+    pub checks: Vec<DependencyCheck>,
+}
+
+impl ServiceHealthResult {
+    /// The health of `service` given its dependency checks, which took `elapsed` in total.
+    /// A service without checks is [`HealthStatus::Ok`].
+    pub fn from_checks(service: &str, checks: Vec<DependencyCheck>, elapsed: Duration) -> Self {
+        let status = checks
+            .iter()
+            .map(|check| check.status)
+            .max()
+            .unwrap_or(HealthStatus::Ok);
+        let failures: Vec<String> = checks
+            .iter()
+            .filter(|check| check.status == HealthStatus::Unavailable)
+            .map(|check| match &check.detail {
+                Some(detail) => format!("{}: {}", check.name, detail),
+                None => check.name.clone(),
+            })
+            .collect();
+        ServiceHealthResult {
+            service: service.to_string(),
+            status,
+            latency_ms: u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX),
+            error_message: (!failures.is_empty()).then(|| failures.join("; ")),
+            timestamp_ms: crate::current_timestamp_ms(),
+            checks,
+        }
+    }
+
+    /// Whether the service can do its work, if slowly.
+    pub fn is_available(&self) -> bool {
+        self.status != HealthStatus::Unavailable
+    }
+}
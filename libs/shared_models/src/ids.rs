@@ -0,0 +1,77 @@
+//! Typed identifiers, so a document id cannot be passed where a request id is expected. They
+//! serialize as hyphenated UUID strings, the format the string ids always had. The default,
+//! nil id stands in for one that could not be read, e.g. in the reply to an undecodable
+//! request.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+macro_rules! uuid_id {
+    ($(#[$attr:meta])* $name:ident) => {
+        $(#[$attr])*
+        #[derive(
+            Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default,
+        )]
+        #[serde(transparent)]
+        pub struct $name(Uuid);
+
+        impl $name {
+            /// A new random id.
+            pub fn generate() -> Self {
+                $name(Uuid::new_v4())
+            }
+
+            pub const fn from_uuid(uuid: Uuid) -> Self {
+                $name(uuid)
+            }
+
+            pub const fn as_uuid(&self) -> &Uuid {
+                &self.0
+            }
+        }
+
+        impl From<Uuid> for $name {
+            fn from(uuid: Uuid) -> Self {
+                $name(uuid)
+            }
+        }
+
+        impl From<$name> for Uuid {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0.hyphenated(), f)
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = uuid::Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                Uuid::parse_str(s.trim()).map($name)
+            }
+        }
+    };
+}
+
+uuid_id! {
+    /// A scraped document, from its `RawTextMessage` through the vector memory and the
+    /// knowledge graph.
+    DocumentId
+}
+
+uuid_id! {
+    /// A unit of work submitted to the pipeline, e.g. a text generation task.
+    TaskId
+}
+
+uuid_id! {
+    /// A request/reply exchange, echoed in the reply so the requester can match it.
+    RequestId
+}
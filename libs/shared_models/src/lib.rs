@@ -1,112 +1,1901 @@
+//! Messages exchanged between the services over NATS. During a rolling upgrade a message
+//! may be read by a service built from an older or newer version of this crate, so fields
+//! added to an existing message are `#[serde(default)]` and unknown fields are ignored.
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+#[cfg(feature = "binary")]
+mod binary;
+#[cfg(feature = "compression")]
+mod compression;
+mod dead_letter;
+mod health;
+mod ids;
+mod lifecycle;
+mod log_safe;
+mod quota;
+mod tenant;
+#[cfg(feature = "chrono")]
+mod timestamp;
+
+#[cfg(feature = "binary")]
+pub use binary::{
+    ACCEPT_HEADER, CONTENT_TYPE_HEADER, DecodeProtobuf, EncodeProtobuf, PayloadError, PayloadFormat,
+};
+#[cfg(feature = "compression")]
+pub use compression::{
+    CONTENT_ENCODING_HEADER, CompressionError, ContentEncoding, MAX_DECOMPRESSED_SIZE, compress,
+    compress_above, decode_body, decompress,
+};
+pub use dead_letter::{
+    AnyDeadLetter, DEAD_LETTER_SUBJECT_PREFIX, ReplayMessage, UndecodedPayload,
+    dead_letter_subject, parse_dead_letter_subject,
+};
+pub use health::{
+    DependencyCheck, HEALTH_DEGRADED_LATENCY, HEALTH_SUBJECT_PREFIX, HealthStatus,
+    ServiceHealthResult, health_subject,
+};
+pub use ids::{DocumentId, RequestId, TaskId};
+pub use lifecycle::{
+    DocumentLifecycle, DocumentState, DocumentStatusResult, DocumentStatusTask, DocumentStuckAlert,
+    StageProgress,
+};
+pub use log_safe::{Elided, LOG_TEXT_CHARS, Truncated};
+pub use quota::{QUOTA_EXCEEDED_SUBJECT, QuotaExceeded, QuotaKind};
+pub use tenant::{MAX_TENANT_ID_LEN, TenantMismatch, validate_tenant_id};
+#[cfg(feature = "chrono")]
+pub use timestamp::{TimeRange, Timestamp};
+
+/// How urgently a task should be handled relative to others waiting at the same consumer,
+/// e.g. an interactive request ahead of a bulk crawl. Ordered from least to most urgent.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskPriority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PerceiveUrlTask {
     pub url: String,
+    /// [`TaskPriority::Normal`] when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<TaskPriority>,
+    /// Unix time in milliseconds after which the task is no longer worth starting.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl PerceiveUrlTask {
+    pub fn new(url: impl Into<String>) -> Self {
+        PerceiveUrlTask {
+            url: url.into(),
+            priority: None,
+            deadline_ms: None,
+        }
+    }
+
+    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn with_deadline_ms(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    pub fn priority(&self) -> TaskPriority {
+        self.priority.unwrap_or_default()
+    }
+
+    /// Whether the deadline has passed at `now_ms`; tasks without one never expire.
+    pub fn is_expired_at(&self, now_ms: u64) -> bool {
+        self.deadline_ms
+            .is_some_and(|deadline_ms| now_ms > deadline_ms)
+    }
+}
+
+/// What a page declares about itself besides its text. Pages rarely declare everything, so
+/// every field is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocumentMetadata {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// Language tag as declared by the page, e.g. "en" or "de-AT".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    /// Publication date as declared by the page, usually ISO 8601.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub published_at: Option<String>,
+    /// Media type of the fetched resource, without parameters.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub canonical_url: Option<String>,
+}
+
+impl DocumentMetadata {
+    pub fn is_empty(&self) -> bool {
+        *self == DocumentMetadata::default()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct RawTextMessage {
-    pub id: String,
+    pub id: DocumentId,
     pub source_url: String,
     pub raw_text: String,
     pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "DocumentMetadata::is_empty")]
+    pub metadata: DocumentMetadata,
+    /// Position of this chunk, from 0, when the document was split into `total_chunks`
+    /// messages; unset for a document sent whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_chunks: Option<u32>,
+    /// The document the chunk was split from. Every chunk still has an id of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_document_id: Option<DocumentId>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+impl RawTextMessage {
+    /// A freshly scraped document, under a new id.
+    pub fn new(source_url: impl Into<String>, raw_text: impl Into<String>) -> Self {
+        RawTextMessage {
+            id: DocumentId::generate(),
+            source_url: source_url.into(),
+            raw_text: raw_text.into(),
+            timestamp_ms: current_timestamp_ms(),
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: DocumentMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Marks the message as chunk `chunk_index` of `total_chunks` split from
+    /// `parent_document_id`.
+    pub fn with_chunk(
+        mut self,
+        parent_document_id: DocumentId,
+        chunk_index: u32,
+        total_chunks: u32,
+    ) -> Self {
+        self.parent_document_id = Some(parent_document_id);
+        self.chunk_index = Some(chunk_index);
+        self.total_chunks = Some(total_chunks);
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TokenizedTextMessage {
-    pub original_id: String,
+    pub original_id: DocumentId,
     pub source_url: String,
     pub tokens: Vec<String>,
     pub sentences: Vec<String>,
     pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "DocumentMetadata::is_empty")]
+    pub metadata: DocumentMetadata,
+}
+
+impl TokenizedTextMessage {
+    pub fn new(
+        original_id: DocumentId,
+        source_url: impl Into<String>,
+        tokens: Vec<String>,
+        sentences: Vec<String>,
+    ) -> Self {
+        TokenizedTextMessage {
+            original_id,
+            source_url: source_url.into(),
+            tokens,
+            sentences,
+            timestamp_ms: current_timestamp_ms(),
+            metadata: DocumentMetadata::default(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: DocumentMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct GenerateTextTask {
-    pub task_id: String,
+    pub task_id: TaskId,
+    #[serde(default)]
     pub prompt: Option<String>,
+    /// Most tokens to generate: words for the Markov backend, tokenizer tokens for the
+    /// neural one.
     pub max_length: u32,
+    #[serde(default)]
+    pub corpus: GenerationCorpus,
+    /// Sampling temperature; 1.0 follows the observed word frequencies, lower values are
+    /// more predictable and higher values more random.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    /// Only the `top_k` most likely continuations are sampled at each step.
+    #[serde(default)]
+    pub top_k: Option<u32>,
+    /// Generation stops before the first occurrence of any of these.
+    #[serde(default)]
+    pub stop_sequences: Vec<String>,
+    /// Seeds the sampler, so the same task against the same model yields the same text.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Generation ends at a sentence boundary after at most `max_sentences` sentences. A
+    /// sentence cut off by `max_length` is dropped unless fewer than `min_sentences`
+    /// (default 1) complete sentences precede it.
+    #[serde(default)]
+    pub min_sentences: Option<u32>,
+    #[serde(default)]
+    pub max_sentences: Option<u32>,
+    /// Generator to use; the service's configured default when unset. `corpus` only
+    /// applies to the Markov backend.
+    #[serde(default)]
+    pub backend: Option<GenerationBackend>,
+    /// Named Markov model to generate from, e.g. "news"; the "default" model when unset.
+    /// `control.generator.models` lists the available names.
+    #[serde(default)]
+    pub model_name: Option<String>,
+    /// Template mode: the text is this template with its slots filled from the knowledge
+    /// graph instead of sampled from a model. `{entity}` and `{keyword}` take the next
+    /// most distinctive capitalized term or term; `{sentence_about:X}` the next sentence
+    /// matching X. A `Document` corpus scopes all slots to that document.
+    #[serde(default)]
+    pub template: Option<String>,
+    /// [`TaskPriority::Normal`] when unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<TaskPriority>,
+    /// Unix time in milliseconds after which the text is no longer wanted; the task then
+    /// fails with [`GenerationFailureReason::DeadlineExceeded`] instead of being generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+}
+
+impl GenerateTextTask {
+    /// A task under a new id with the service's defaults for everything but the length;
+    /// chain the `with_*` methods or use struct update syntax to set the rest.
+    pub fn new(max_length: u32) -> Self {
+        GenerateTextTask {
+            task_id: TaskId::generate(),
+            prompt: None,
+            max_length,
+            corpus: GenerationCorpus::default(),
+            temperature: None,
+            top_k: None,
+            stop_sequences: Vec::new(),
+            seed: None,
+            min_sentences: None,
+            max_sentences: None,
+            backend: None,
+            model_name: None,
+            template: None,
+            priority: None,
+            deadline_ms: None,
+        }
+    }
+
+    pub fn with_prompt(mut self, prompt: impl Into<String>) -> Self {
+        self.prompt = Some(prompt.into());
+        self
+    }
+
+    pub fn with_corpus(mut self, corpus: GenerationCorpus) -> Self {
+        self.corpus = corpus;
+        self
+    }
+
+    pub fn with_backend(mut self, backend: GenerationBackend) -> Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn with_model_name(mut self, model_name: impl Into<String>) -> Self {
+        self.model_name = Some(model_name.into());
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: TaskPriority) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn with_deadline_ms(mut self, deadline_ms: u64) -> Self {
+        self.deadline_ms = Some(deadline_ms);
+        self
+    }
+
+    pub fn priority(&self) -> TaskPriority {
+        self.priority.unwrap_or_default()
+    }
+
+    /// Whether the deadline has passed at `now_ms`; tasks without one never expire.
+    pub fn is_expired_at(&self, now_ms: u64) -> bool {
+        self.deadline_ms
+            .is_some_and(|deadline_ms| now_ms > deadline_ms)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationBackend {
+    Markov,
+    Neural,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorStatsTask {
+    pub request_id: RequestId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct MarkovModelStats {
+    /// Distinct contexts (a word, or up to the chain order words) with a recorded successor.
+    pub states: u64,
+    /// Distinct (context, successor) pairs.
+    pub transitions: u64,
+    pub starters: u64,
+    pub trained_documents: u64,
+    #[serde(default)]
+    pub last_trained_ms: Option<u64>,
+    /// Rough in-memory size, from word lengths and per-entry overhead.
+    pub estimated_memory_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorStatsResult {
+    pub request_id: RequestId,
+    pub global: MarkovModelStats,
+    pub domain_models: u64,
+    pub document_models: u64,
+    /// Estimated size of all Markov models, sub-models included.
+    pub total_estimated_memory_bytes: u64,
+    /// Hugging Face id of the loaded neural model, if any.
+    #[serde(default)]
+    pub neural_model: Option<String>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorModelsTask {
+    pub request_id: RequestId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorModelInfo {
+    pub name: String,
+    /// Subject the model trains from.
+    pub subject: String,
+    /// Hosts a document must come from to be trained on; empty means any.
+    pub hosts: Vec<String>,
+    /// Tenants the model trains for and serves; empty for a model shared by all.
+    #[serde(default)]
+    pub tenants: Vec<String>,
+    pub global: MarkovModelStats,
+    pub domain_models: u64,
+    pub document_models: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorModelsResult {
+    pub request_id: RequestId,
+    pub models: Vec<GeneratorModelInfo>,
+    /// Model used when a task does not name one.
+    pub default_model: String,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Scores `text` under a generator: how likely the model finds it, not a generation.
+/// `model_name` and `corpus` select the Markov model as in `GenerateTextTask`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorEvaluateTask {
+    pub request_id: RequestId,
+    pub text: String,
+    #[serde(default)]
+    pub backend: Option<GenerationBackend>,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub corpus: GenerationCorpus,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TextEvaluation {
+    /// Scored tokens: words and sentence ends for the Markov backend, tokenizer tokens
+    /// after the first for the neural one.
+    pub tokens: u64,
+    /// Natural-log probability of the whole text.
+    pub log_likelihood: f64,
+    /// Mean negative log-likelihood per token (the cross-entropy loss).
+    pub loss: f64,
+    /// `exp(loss)`; lower means the text is more typical of the model.
+    pub perplexity: f64,
+    /// Markov only: tokens the model never saw in that position, scored by smoothing.
+    #[serde(default)]
+    pub unseen_tokens: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorEvaluateResult {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub backend: Option<GenerationBackend>,
+    /// The Markov model name or the neural model id that scored the text.
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub evaluation: Option<TextEvaluation>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Rebuilds the Markov models from the sentences stored in the vector memory, for one
+/// named model or all of them. The reply is sent once the rebuild is done.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorRetrainTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GeneratorRetrainResult {
+    pub request_id: RequestId,
+    /// Names of the models that were replaced.
+    pub models: Vec<String>,
+    /// Stored documents and sentences that were read.
+    pub documents: u64,
+    pub sentences: u64,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Which part of the harvested corpus a generation imitates, e.g.
+/// `{"scope": "domain", "key": "example.com"}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(tag = "scope", content = "key", rename_all = "snake_case")]
+pub enum GenerationCorpus {
+    #[default]
+    Global,
+    /// Documents published on one host, lowercased and without a leading `www.`.
+    Domain(String),
+    /// A single document, by `original_id`.
+    Document(DocumentId),
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct GeneratedTextMessage {
-    pub original_task_id: String,
+    pub original_task_id: TaskId,
     pub generated_text: String,
     pub timestamp_ms: u64,
+    /// Seed the text was sampled with; pass it back in `GenerateTextTask.seed` to reproduce it.
+    #[serde(default)]
+    pub seed: Option<u64>,
+    /// Why generation ended; unset for template output and older generators.
+    #[serde(default)]
+    pub stop_reason: Option<GenerationStopReason>,
 }
 
+impl GeneratedTextMessage {
+    pub fn new(original_task_id: TaskId, generated_text: impl Into<String>) -> Self {
+        GeneratedTextMessage {
+            original_task_id,
+            generated_text: generated_text.into(),
+            timestamp_ms: current_timestamp_ms(),
+            seed: None,
+            stop_reason: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationStopReason {
+    /// `max_length` tokens were generated.
+    Length,
+    /// A stop sequence was reached; the text ends before it.
+    StopSequence,
+    /// The Markov model could not continue: no context backed off to had a successor and
+    /// no new sentence could be started.
+    DeadEnd,
+    /// `max_sentences` sentences were generated.
+    MaxSentences,
+    /// The neural model emitted its end-of-sequence token.
+    EndOfText,
+}
+
+/// Published instead of a `GeneratedTextMessage` when a task produced no usable text.
 #[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GenerationFailedEvent {
+    pub task_id: TaskId,
+    pub reason: GenerationFailureReason,
+    /// Human-readable explanation, e.g. which model or corpus was missing.
+    pub detail: String,
+    pub timestamp_ms: u64,
+}
+
+impl GenerationFailedEvent {
+    pub fn new(
+        task_id: TaskId,
+        reason: GenerationFailureReason,
+        detail: impl Into<String>,
+    ) -> Self {
+        GenerationFailedEvent {
+            task_id,
+            reason,
+            detail: detail.into(),
+            timestamp_ms: current_timestamp_ms(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum GenerationFailureReason {
+    /// The task named a model the service does not have.
+    UnknownModel,
+    /// The selected model or corpus has not been trained on anything yet.
+    ModelNotTrained,
+    /// The output had fewer words than the service's minimum.
+    TooFewWords,
+    /// A template slot could not be filled from the knowledge graph.
+    TemplateSlotUnfilled,
+    /// The task failed [`Validate::validate`], e.g. a `max_length` out of range.
+    InvalidTask,
+    /// The task's `deadline_ms` passed before generation started.
+    DeadlineExceeded,
+}
+
+/// Sparse term-weight vector (parallel `indices`/`values`), used for lexical matching in hybrid search.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct SparseVector {
+    pub indices: Vec<u32>,
+    pub values: Vec<f32>,
+}
+
+impl SparseVector {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct SentenceEmbedding {
     pub sentence_text: String,
     pub embedding: Vec<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sparse_embedding: Option<SparseVector>,
+    /// Position of the sentence in the source document. When absent, the index in
+    /// `embeddings_data` is used; set explicitly when only a subset of sentences is sent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sentence_order: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TextWithEmbeddingsMessage {
-    pub original_id: String,
+    pub original_id: DocumentId,
     pub source_url: String,
     pub embeddings_data: Vec<SentenceEmbedding>,
     pub model_name: String,
     pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(default, skip_serializing_if = "DocumentMetadata::is_empty")]
+    pub metadata: DocumentMetadata,
+    /// Position of this chunk, from 0, when the document was split into `total_chunks`
+    /// messages; unset for a document sent whole.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_chunks: Option<u32>,
+    /// The document the chunk was split from. Every chunk still has an id of its own.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_document_id: Option<DocumentId>,
+}
+
+impl TextWithEmbeddingsMessage {
+    pub fn new(
+        original_id: DocumentId,
+        source_url: impl Into<String>,
+        model_name: impl Into<String>,
+        embeddings_data: Vec<SentenceEmbedding>,
+    ) -> Self {
+        TextWithEmbeddingsMessage {
+            original_id,
+            source_url: source_url.into(),
+            embeddings_data,
+            model_name: model_name.into(),
+            timestamp_ms: current_timestamp_ms(),
+            tenant_id: None,
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
+        }
+    }
+
+    pub fn with_tenant_id(mut self, tenant_id: Option<String>) -> Self {
+        self.tenant_id = tenant_id;
+        self
+    }
+
+    pub fn with_metadata(mut self, metadata: DocumentMetadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// Marks the message as chunk `chunk_index` of `total_chunks` split from
+    /// `parent_document_id`.
+    pub fn with_chunk(
+        mut self,
+        parent_document_id: DocumentId,
+        chunk_index: u32,
+        total_chunks: u32,
+    ) -> Self {
+        self.parent_document_id = Some(parent_document_id);
+        self.chunk_index = Some(chunk_index);
+        self.total_chunks = Some(total_chunks);
+        self
+    }
+}
+
+/// Restricts a search to the points matching every set field.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    /// Exact `source_url` of the sentence's document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_url: Option<String>,
+    /// Language tag from the document's metadata, e.g. "en".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// Inclusive lower bound on the processing time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_after_ms: Option<u64>,
+    /// Exclusive upper bound on the processing time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub processed_before_ms: Option<u64>,
+    /// Required when the vector service runs with multi-tenancy enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+}
+
+impl SearchFilters {
+    pub fn is_empty(&self) -> bool {
+        *self == SearchFilters::default()
+    }
+
+    /// The processing-time bounds as a range.
+    #[cfg(feature = "chrono")]
+    pub fn processed_range(&self) -> TimeRange {
+        TimeRange {
+            start: self.processed_after_ms.map(Timestamp::from_millis),
+            end: self.processed_before_ms.map(Timestamp::from_millis),
+        }
+    }
+}
+
+/// How the hits of a search are paged, cut off and arranged.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    /// Hits to skip, for paging through a flat ranking.
+    #[serde(default)]
+    pub offset: u32,
+    /// Hits scoring below this are left out. Scores are comparable only within one
+    /// collection, and hybrid searches score by fused rank instead.
+    #[serde(default)]
+    pub score_threshold: Option<f32>,
+    /// Return up to `top_k` documents with at most `hits_per_document` sentences each
+    /// instead of `top_k` individual sentences.
+    #[serde(default)]
+    pub group_by_document: bool,
+    #[serde(default)]
+    pub hits_per_document: Option<u32>,
+    /// Return at most one sentence per document, as a flat ranking.
+    #[serde(default)]
+    pub diversify: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchApiRequest {
+    pub query_text: String,
+    pub top_k: u32,
+    #[serde(flatten)]
+    pub filters: SearchFilters,
+    #[serde(flatten)]
+    pub options: SearchOptions,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QueryForEmbeddingTask {
+    pub request_id: RequestId,
+    pub text_to_embed: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueryEmbeddingResult {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub embedding: Option<Vec<f32>>,
+    #[serde(default)]
+    pub sparse_embedding: Option<SparseVector>,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReembedSentence {
+    pub sentence_text: String,
+    pub sentence_order: u32,
+}
+
+/// Asks the preprocessing_service instance running `model_name` to re-embed already stored
+/// sentences and publish them as a regular [`TextWithEmbeddingsMessage`]. Instances running
+/// another model ignore the task.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReembedTextTask {
+    pub reindex_id: RequestId,
+    pub original_id: DocumentId,
+    pub source_url: String,
+    pub model_name: String,
+    pub sentences: Vec<ReembedSentence>,
+    /// Kept from the stored points so retention still counts from the original ingest.
+    pub processed_at_ms: u64,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default, skip_serializing_if = "DocumentMetadata::is_empty")]
+    pub metadata: DocumentMetadata,
+    /// Chunk fields of the stored points, see [`TextWithEmbeddingsMessage::chunk_index`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_chunks: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_document_id: Option<DocumentId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct QdrantPointPayload {
+    pub original_document_id: DocumentId,
+    pub source_url: String,
+    pub sentence_text: String,
+    pub sentence_order: u32,
+    pub model_name: String,
+    pub processed_at_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    /// Metadata of the sentence's document, so results can show a title rather than the URL.
+    #[serde(default, skip_serializing_if = "DocumentMetadata::is_empty")]
+    pub metadata: DocumentMetadata,
+    /// Set when the document was ingested in chunks: `original_document_id` is then the
+    /// chunk's id and `parent_document_id` the id of the whole document.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk_index: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_chunks: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent_document_id: Option<DocumentId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchNatsTask {
+    pub request_id: RequestId,
+    pub query_embedding: Vec<f32>,
+    pub top_k: u32,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    /// Flattened, so `tenant_id` and `group_by_document` stay where earlier versions put them.
+    #[serde(flatten)]
+    pub filters: SearchFilters,
+    #[serde(flatten)]
+    pub options: SearchOptions,
+    /// When present, the vector service runs a hybrid dense + sparse query fused with RRF.
+    #[serde(default)]
+    pub sparse_query: Option<SparseVector>,
+    /// Overrides the vector service's configured read consistency for this request.
+    #[serde(default)]
+    pub read_consistency: Option<ReadConsistency>,
+    /// Overrides the configured Qdrant search timeout, in seconds.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+    /// Overrides the configured HNSW `ef` (higher is slower but more accurate).
+    #[serde(default)]
+    pub hnsw_ef: Option<u64>,
+}
+
+/// How many replicas must agree on a read: a named level (`"all"`, `"majority"`,
+/// `"quorum"`) or a number of replicas.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum ReadConsistency {
+    Factor(u64),
+    Level(ReadConsistencyLevel),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReadConsistencyLevel {
+    All,
+    Majority,
+    Quorum,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchBatchQuery {
+    pub query_embedding: Vec<f32>,
+    pub top_k: u32,
+}
+
+/// Several dense queries against the same model collection, executed in one Qdrant round trip.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchNatsBatchTask {
+    pub request_id: RequestId,
+    pub queries: Vec<SemanticSearchBatchQuery>,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+/// `results[i]` holds the hits for `queries[i]` of the batch task.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchNatsBatchResult {
+    pub request_id: RequestId,
+    pub results: Vec<Vec<SemanticSearchResultItem>>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// "More like this": recommends sentences similar to already stored points instead of
+/// a query embedding. Positive examples are `positive_point_ids` and/or the sentences of
+/// `positive_document_id`; the example document itself is excluded from the results.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecommendNatsTask {
+    pub request_id: RequestId,
+    pub top_k: u32,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub positive_point_ids: Vec<String>,
+    #[serde(default)]
+    pub positive_document_id: Option<DocumentId>,
+    #[serde(default)]
+    pub negative_point_ids: Vec<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RecommendApiRequest {
+    pub top_k: u32,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub positive_point_ids: Vec<String>,
+    #[serde(default)]
+    pub positive_document_id: Option<DocumentId>,
+    #[serde(default)]
+    pub negative_point_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchResultItem {
+    pub qdrant_point_id: String,
+    pub score: f32,
+    pub payload: QdrantPointPayload,
+}
+
+/// Sentences of one document matched by a grouped search, best hit first.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchResultGroup {
+    pub original_document_id: DocumentId,
+    pub hits: Vec<SemanticSearchResultItem>,
+}
+
+/// For grouped searches `groups` holds the per-document results and `results` the same hits flattened.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchNatsResult {
+    pub request_id: RequestId,
+    pub results: Vec<SemanticSearchResultItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<SemanticSearchResultGroup>>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SemanticSearchApiResponse {
+    pub search_request_id: RequestId,
+    pub results: Vec<SemanticSearchResultItem>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<SemanticSearchResultGroup>>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Pages through stored points. With `original_document_id`, the document's sentences come
+/// in `sentence_order` across pages; otherwise in point id order.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorScrollTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub original_document_id: Option<DocumentId>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    pub limit: u32,
+    /// Pagination token returned as `next_offset` by the previous page.
+    #[serde(default)]
+    pub offset: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredPointItem {
+    pub qdrant_point_id: String,
+    pub payload: QdrantPointPayload,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorScrollResult {
+    pub request_id: RequestId,
+    pub points: Vec<StoredPointItem>,
+    #[serde(default)]
+    pub next_offset: Option<String>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Counts stored points (one per sentence) matching the optional filters. With `group_by`
+/// (`source_url` or `original_document_id`) the result also breaks the count down per value,
+/// for the `group_limit` most frequent values. Counts are approximate unless `exact` is set.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorCountTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub original_document_id: Option<DocumentId>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub exact: bool,
+    #[serde(default)]
+    pub group_by: Option<String>,
+    #[serde(default)]
+    pub group_limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorCountGroup {
+    pub value: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorCountResult {
+    pub request_id: RequestId,
+    pub count: u64,
+    pub exact: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub groups: Option<Vec<VectorCountGroup>>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Sets `payload` fields on every stored point of the selected document(s) without
+/// re-embedding. At least one of `original_document_id` / `source_url` is required;
+/// fields not listed in `payload` are left unchanged.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorPayloadUpdateTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+    #[serde(default)]
+    pub original_document_id: Option<DocumentId>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorPayloadUpdateResult {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Requests a Qdrant snapshot of the collection for `model_name`, or of every
+/// collection managed by the vector service when it is unset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorSnapshotTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorSnapshotInfo {
+    pub collection_name: String,
+    pub snapshot_name: String,
+    pub size_bytes: u64,
+    #[serde(default)]
+    pub created_at_ms: Option<u64>,
+    #[serde(default)]
+    pub checksum: Option<String>,
+    /// Download path on Qdrant's REST API, e.g. `/collections/<collection>/snapshots/<name>`.
+    pub location: String,
+}
+
+/// Snapshots that succeeded; `error_message` lists the collections that failed, if any.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorSnapshotResult {
+    pub request_id: RequestId,
+    pub snapshots: Vec<VectorSnapshotInfo>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Requests collection statistics for `model_name`, or for every collection
+/// managed by the vector service when it is unset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStatsTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub model_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PayloadIndexStats {
+    pub field_name: String,
+    pub data_type: String,
+    pub indexed_points: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorCollectionStats {
+    pub collection_name: String,
+    /// Qdrant collection status: `green`, `yellow`, `red` or `grey`.
+    pub status: String,
+    pub optimizer_ok: bool,
+    #[serde(default)]
+    pub optimizer_error: Option<String>,
+    pub points_count: u64,
+    pub indexed_vectors_count: u64,
+    pub segments_count: u64,
+    #[serde(default)]
+    pub vector_size: Option<u64>,
+    #[serde(default)]
+    pub vectors_on_disk: Option<bool>,
+    #[serde(default)]
+    pub payload_on_disk: Option<bool>,
+    /// `points_count * vector_size * 4`; Qdrant's collection info does not report actual disk usage.
+    #[serde(default)]
+    pub estimated_vector_bytes: Option<u64>,
+    pub payload_indexes: Vec<PayloadIndexStats>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorStatsResult {
+    pub request_id: RequestId,
+    pub collections: Vec<VectorCollectionStats>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Migrates the stored sentences of `source_model_name` (the active collection when unset)
+/// into a new collection for `target_model_name`, then switches unqualified reads over to it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorReindexTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub source_model_name: Option<String>,
+    pub target_model_name: String,
+    pub target_vector_dim: u64,
+}
+
+/// Reply to a reindex request (`status` = `started`) and the final event on
+/// `events.vector.reindex` (`completed` or `failed`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VectorReindexResult {
+    pub request_id: RequestId,
+    pub status: String,
+    pub source_collection: String,
+    pub target_collection: String,
+    pub documents_requested: u64,
+    pub source_points: u64,
+    pub target_points: u64,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Wraps a message that could not be processed after retries, or an [`UndecodedPayload`]
+/// that could not be decoded at all. Published to [`dead_letter_subject`] so it can be
+/// inspected and replayed later.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetterMessage<T> {
+    pub original_subject: String,
+    pub payload: T,
+    pub error_message: String,
+    pub attempts: u32,
+    pub dead_lettered_at_ms: u64,
+}
+
+impl<T> DeadLetterMessage<T> {
+    pub fn new(
+        original_subject: impl Into<String>,
+        payload: T,
+        error_message: impl Into<String>,
+        attempts: u32,
+    ) -> Self {
+        DeadLetterMessage {
+            original_subject: original_subject.into(),
+            payload,
+            error_message: error_message.into(),
+            attempts,
+            dead_lettered_at_ms: current_timestamp_ms(),
+        }
+    }
+}
+
+/// Prefix of the subjects [`PipelineErrorMessage`]s are published to, one per stage:
+/// `errors.<stage>`, e.g. `errors.scraping`. Subscribe to `errors.>` for all of them.
+pub const PIPELINE_ERROR_SUBJECT_PREFIX: &str = "errors";
+
+/// Where in the pipeline a document or task is being worked on.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineStage {
+    /// perception_service fetching and extracting a page.
+    Scraping,
+    /// preprocessing_service splitting and embedding text.
+    Preprocessing,
+    /// vector_memory_service storing embeddings.
+    Storage,
+    /// knowledge_graph_service storing terms and sentences.
+    KnowledgeGraph,
+    /// text_generator_service generating text.
+    Generation,
+}
+
+impl PipelineStage {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            PipelineStage::Scraping => "scraping",
+            PipelineStage::Preprocessing => "preprocessing",
+            PipelineStage::Storage => "storage",
+            PipelineStage::KnowledgeGraph => "knowledge_graph",
+            PipelineStage::Generation => "generation",
+        }
+    }
+
+    /// Subject this stage's [`PipelineErrorMessage`]s are published to.
+    pub fn error_subject(self) -> String {
+        format!("{}.{}", PIPELINE_ERROR_SUBJECT_PREFIX, self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PipelineErrorKind {
+    /// The incoming message could not be decoded or failed validation.
+    InvalidMessage,
+    /// An external resource, e.g. the page to scrape, could not be fetched.
+    Fetch,
+    /// The stage's own work failed, e.g. embedding or generation.
+    Processing,
+    /// Writing to a store failed, after any retries.
+    Storage,
+    /// A dependency did not answer in time.
+    Timeout,
+    /// The task's deadline passed before the stage got to it, so it was dropped.
+    Expired,
+    /// The submission's tenant used up an ingestion quota, so it was dropped.
+    QuotaExceeded,
+}
+
+/// Published by every service to [`PipelineStage::error_subject`] when it gives up on a
+/// document or task, so failures can be followed in one place.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PipelineErrorMessage {
+    pub stage: PipelineStage,
+    /// Document the failure concerns, when known.
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    /// Task the failure concerns, e.g. a generation task.
+    #[serde(default)]
+    pub task_id: Option<TaskId>,
+    pub error_kind: PipelineErrorKind,
+    pub message: String,
+    /// Attempts made before giving up.
+    pub attempts: u32,
+    pub timestamp_ms: u64,
+}
+
+impl PipelineErrorMessage {
+    /// A failure after a single attempt, concerning neither a document nor a task yet.
+    pub fn new(
+        stage: PipelineStage,
+        error_kind: PipelineErrorKind,
+        message: impl Into<String>,
+    ) -> Self {
+        PipelineErrorMessage {
+            stage,
+            original_id: None,
+            task_id: None,
+            error_kind,
+            message: message.into(),
+            attempts: 1,
+            timestamp_ms: current_timestamp_ms(),
+        }
+    }
+
+    pub fn with_original_id(mut self, original_id: DocumentId) -> Self {
+        self.original_id = Some(original_id);
+        self
+    }
+
+    pub fn with_task_id(mut self, task_id: TaskId) -> Self {
+        self.task_id = Some(task_id);
+        self
+    }
+
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Started,
+    Completed,
+    Failed,
+}
+
+/// Subject [`TaskStatusChangedMessage`]s are published on.
+pub const TASK_STATUS_EVENT_SUBJECT: &str = "events.task.status";
+
+/// Published by each service as a submitted document enters and leaves its
+/// [`PipelineStage`]. `task_id` is the correlation id of the submission's [`Envelope`], which
+/// api_service returns from `POST /api/submit-url`, so all stages of one submission share it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TaskStatusChangedMessage {
+    pub task_id: TaskId,
+    /// Document id, once perception_service has assigned one.
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    pub stage: PipelineStage,
+    pub status: TaskStatus,
+    /// Why the stage failed, or other context for the change.
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+impl TaskStatusChangedMessage {
+    /// A status change of the submission `cause` belongs to.
+    pub fn new<C>(cause: &Envelope<C>, stage: PipelineStage, status: TaskStatus) -> Self {
+        TaskStatusChangedMessage {
+            task_id: cause.task_id(),
+            original_id: None,
+            stage,
+            status,
+            detail: None,
+            timestamp_ms: current_timestamp_ms(),
+        }
+    }
+
+    pub fn with_original_id(mut self, original_id: DocumentId) -> Self {
+        self.original_id = Some(original_id);
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbeddingDimensionMismatch {
+    pub sentence_index: u32,
+    pub dimension: u64,
+}
+
+/// Published by vector_memory_service when embeddings are rejected on ingest
+/// because their length does not match the target collection's vector size.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EmbeddingsRejectedEvent {
+    pub original_id: DocumentId,
+    pub source_url: String,
+    pub model_name: String,
+    pub collection_name: String,
+    pub expected_dimension: u64,
+    pub mismatches: Vec<EmbeddingDimensionMismatch>,
+    /// Number of sentences of the message that were still stored.
+    pub stored_count: u32,
+    pub error_message: String,
+    pub timestamp_ms: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphExportFormat {
+    #[default]
+    Json,
+    Graphml,
+}
+
+/// Exports the subgraph of the document `original_id` (its sentences, tokens and sentence
+/// order), or the whole graph when it is unset. Relationships are paginated in a stable order;
+/// pass the previous page's `next_cursor` as `cursor` to continue. Whole-graph exports only
+/// include nodes that have at least one relationship.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphExportTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    #[serde(default)]
+    pub format: GraphExportFormat,
+    #[serde(default)]
+    pub cursor: Option<u64>,
+    #[serde(default)]
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphExportNode {
+    pub id: String,
+    pub labels: Vec<String>,
+    pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphExportEdge {
+    pub id: String,
+    pub source: String,
+    pub target: String,
+    pub rel_type: String,
+    pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
+/// One page of a graph export. `nodes` holds the endpoints of this page's `edges`, so a node
+/// can appear on several pages. With the `graphml` format the page is rendered into `graphml`
+/// and `nodes`/`edges` are left empty. `next_cursor` is unset on the last page.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphExportResult {
+    pub request_id: RequestId,
+    pub format: GraphExportFormat,
+    #[serde(default)]
+    pub nodes: Vec<GraphExportNode>,
+    #[serde(default)]
+    pub edges: Vec<GraphExportEdge>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub graphml: Option<String>,
+    #[serde(default)]
+    pub next_cursor: Option<u64>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Removes a document from the knowledge graph: the Document node, its relationships and
+/// the Sentence nodes no other document shares.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphDeleteDocumentTask {
+    pub request_id: RequestId,
+    pub original_id: DocumentId,
+}
+
+/// `deleted` is false when no document with `original_id` existed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphDeleteDocumentResult {
+    pub request_id: RequestId,
+    pub original_id: DocumentId,
+    pub deleted: bool,
+    pub sentences_deleted: u64,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Lexical search over sentence text through the knowledge graph's full-text index.
+/// `query_text` is matched as plain words; Lucene operators in it are escaped.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeywordSearchTask {
+    pub request_id: RequestId,
+    pub query_text: String,
+    pub top_k: u32,
+    /// Restricts the search to the sentences of one document.
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+}
+
+/// A sentence shared by several documents is returned once per document.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeywordSearchResultItem {
+    pub original_id: DocumentId,
+    pub source_url: String,
+    pub sentence_text: String,
+    pub sentence_order: u32,
+    /// Lucene relevance score; only comparable within one result list.
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct KeywordSearchResult {
+    pub request_id: RequestId,
+    pub results: Vec<KeywordSearchResultItem>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Documents related to `original_id` through the knowledge graph: other documents that
+/// contain its most distinctive tokens, or other forms of the same lemmas.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelatedDocumentsTask {
+    pub request_id: RequestId,
+    pub original_id: DocumentId,
+    pub top_k: u32,
+    /// Fewest shared tokens/lemmas a document needs to be returned; the service default
+    /// applies when unset.
+    #[serde(default)]
+    pub min_shared_terms: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelatedDocument {
+    pub original_id: DocumentId,
+    pub source_url: String,
+    /// Sum of tf-idf products over the shared terms; only comparable within one result list.
+    pub score: f64,
+    /// Tokens both documents contain.
+    pub shared_tokens: u32,
+    /// Terms matched only through a shared lemma (e.g. "graphs" and "graph").
+    pub shared_lemmas: u32,
+    /// Both documents were published on the same domain.
+    pub same_domain: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RelatedDocumentsResult {
+    pub request_id: RequestId,
+    pub original_id: DocumentId,
+    pub documents: Vec<RelatedDocument>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Runs the knowledge graph's PageRank and community detection job now. The reply is sent
+/// when the run has finished, which can take a while on large graphs.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphAnalysisTask {
+    pub request_id: RequestId,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphAnalysisResult {
+    pub request_id: RequestId,
+    /// Document and Token nodes that received `pagerank` and `community` properties.
+    pub nodes_ranked: u64,
+    pub pagerank_iterations: u64,
+    pub community_count: u64,
+    pub modularity: f64,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Read-only Cypher for admin callers. The query must not write (no `CREATE`, `MERGE`,
+/// `SET`, `DELETE`, ..., and only read-only procedures); pass values through `params`
+/// instead of splicing them into the text. Requests are rejected unless `admin_token`
+/// matches the service's configured token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphCypherTask {
+    pub request_id: RequestId,
+    pub query: String,
+    #[serde(default)]
+    pub params: serde_json::Map<String, serde_json::Value>,
+    /// Capped by the service's limit, which also applies when unset.
+    #[serde(default)]
+    pub max_rows: Option<u32>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub admin_token: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphCypherResult {
+    pub request_id: RequestId,
+    /// One object per row, keyed by the returned column names.
+    pub rows: Vec<serde_json::Map<String, serde_json::Value>>,
+    /// More rows were available than `max_rows`.
+    pub truncated: bool,
+    pub duration_ms: u64,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Requests the most distinctive terms (tokens ranked by TF-IDF) of one document, or of the
+/// most recently processed documents when `original_id` is unset.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphTermsTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    #[serde(default)]
+    pub kind: GraphTermKind,
+    pub top_k: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum GraphTermKind {
+    #[default]
+    Keyword,
+    /// Terms written with a capital letter, as a stand-in for named entities.
+    Entity,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphTerm {
+    /// The term as it was last written in a document.
+    pub text: String,
+    pub score: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphTermsResult {
+    pub request_id: RequestId,
+    pub terms: Vec<GraphTerm>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Requests knowledge graph statistics. `max_domains` limits the per-domain document counts
+/// to the largest domains (service default when unset).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphStatsTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub max_domains: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphLabelCount {
+    pub label: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphRelationshipTypeCount {
+    pub rel_type: String,
+    pub count: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DomainDocumentCount {
+    pub host: String,
+    pub documents: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GraphStatsResult {
+    pub request_id: RequestId,
+    pub node_count: u64,
+    pub relationship_count: u64,
+    pub nodes_by_label: Vec<GraphLabelCount>,
+    pub relationships_by_type: Vec<GraphRelationshipTypeCount>,
+    /// Largest domains first.
+    pub documents_by_domain: Vec<DomainDocumentCount>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+/// Upper bounds of [`GenerateTextTask::max_length`] and [`SemanticSearchApiRequest::top_k`].
+pub const MAX_GENERATION_LENGTH: u32 = 1000;
+pub const MAX_SEARCH_TOP_K: u32 = 100;
+
+/// A field of a task or request that is out of bounds. The message names the field and is
+/// meant to be shown to the caller as it is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationError {
+    fn new(field: &'static str, message: impl Into<String>) -> Self {
+        ValidationError {
+            field,
+            message: message.into(),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+/// Bounds checks shared by the API handlers accepting a message and the services consuming
+/// it, so both reject the same input.
+pub trait Validate {
+    fn validate(&self) -> Result<(), ValidationError>;
+}
+
+impl Validate for PerceiveUrlTask {
+    /// Only absolute http(s) URLs with a host can be scraped.
+    fn validate(&self) -> Result<(), ValidationError> {
+        let url = self.url.trim();
+        if url.is_empty() {
+            return Err(ValidationError::new("url", "URL cannot be empty"));
+        }
+        let parsed = url::Url::parse(url)
+            .map_err(|e| ValidationError::new("url", format!("URL is invalid: {}", e)))?;
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return Err(ValidationError::new(
+                "url",
+                format!(
+                    "URL scheme must be http or https, not '{}'",
+                    parsed.scheme()
+                ),
+            ));
+        }
+        if parsed.host_str().is_none_or(str::is_empty) {
+            return Err(ValidationError::new("url", "URL must have a host"));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SemanticSearchApiRequest {
-    pub query_text: String,
-    pub top_k: u32,
+impl Validate for GenerateTextTask {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if !(1..=MAX_GENERATION_LENGTH).contains(&self.max_length) {
+            return Err(ValidationError::new(
+                "max_length",
+                format!("max_length must be between 1 and {}", MAX_GENERATION_LENGTH),
+            ));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct QueryForEmbeddingTask {
-    pub request_id: String,
-    pub text_to_embed: String,
+impl Validate for SemanticSearchApiRequest {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.query_text.trim().is_empty() {
+            return Err(ValidationError::new(
+                "query_text",
+                "query_text cannot be empty",
+            ));
+        }
+        if !(1..=MAX_SEARCH_TOP_K).contains(&self.top_k) {
+            return Err(ValidationError::new(
+                "top_k",
+                format!("top_k must be between 1 and {}", MAX_SEARCH_TOP_K),
+            ));
+        }
+        self.filters.validate()?;
+        self.options.validate()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct QueryEmbeddingResult {
-    pub request_id: String,
-    pub embedding: Option<Vec<f32>>,
-    pub model_name: Option<String>,
-    pub error_message: Option<String>,
+impl Validate for SearchFilters {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if let (Some(after), Some(before)) = (self.processed_after_ms, self.processed_before_ms)
+            && after >= before
+        {
+            return Err(ValidationError::new(
+                "processed_before_ms",
+                "processed_before_ms must be later than processed_after_ms",
+            ));
+        }
+        Ok(())
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct QdrantPointPayload {
-    pub original_document_id: String,
-    pub source_url: String,
-    pub sentence_text: String,
-    pub sentence_order: u32,
-    pub model_name: String,
-    pub processed_at_ms: u64,
+impl Validate for SearchOptions {
+    fn validate(&self) -> Result<(), ValidationError> {
+        if self.hits_per_document == Some(0) {
+            return Err(ValidationError::new(
+                "hits_per_document",
+                "hits_per_document must be at least 1",
+            ));
+        }
+        if self
+            .score_threshold
+            .is_some_and(|threshold| !threshold.is_finite())
+        {
+            return Err(ValidationError::new(
+                "score_threshold",
+                "score_threshold must be a finite number",
+            ));
+        }
+        if self.group_by_document && self.diversify {
+            return Err(ValidationError::new(
+                "diversify",
+                "diversify cannot be combined with group_by_document",
+            ));
+        }
+        if self.offset > 0 && (self.group_by_document || self.diversify) {
+            return Err(ValidationError::new(
+                "offset",
+                "offset is only supported for ungrouped searches",
+            ));
+        }
+        Ok(())
+    }
 }
 
+/// Version of the [`Envelope`] fields themselves. Bare payloads from producers that
+/// predate envelopes are decoded as version 0.
+pub const ENVELOPE_SCHEMA_VERSION: u32 = 1;
+const UNKNOWN_PRODUCER: &str = "unknown";
+
+/// What every NATS message is published in. `correlation_id` is shared by all messages
+/// that descend from the same request or document; `causation_id` is the `message_id` of
+/// the message this one was produced in response to. `tenant_id` is inherited the same
+/// way, see [`Envelope::scoped_tenant`].
 #[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SemanticSearchNatsTask {
-    pub request_id: String,
-    pub query_embedding: Vec<f32>,
-    pub top_k: u32,
+pub struct Envelope<T> {
+    pub schema_version: u32,
+    pub message_id: String,
+    pub correlation_id: String,
+    #[serde(default)]
+    pub causation_id: Option<String>,
+    /// Name of the producing service.
+    pub produced_by: String,
+    pub timestamp_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub payload: T,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SemanticSearchResultItem {
-    pub qdrant_point_id: String,
-    pub score: f32,
-    pub payload: QdrantPointPayload,
+impl<T> Envelope<T> {
+    /// Wraps a message that starts a new correlation chain.
+    pub fn new(produced_by: &str, payload: T) -> Self {
+        let message_id = generate_uuid();
+        Envelope {
+            schema_version: ENVELOPE_SCHEMA_VERSION,
+            correlation_id: message_id.clone(),
+            message_id,
+            causation_id: None,
+            produced_by: produced_by.to_string(),
+            timestamp_ms: current_timestamp_ms(),
+            tenant_id: None,
+            payload,
+        }
+    }
+
+    /// The submission this message belongs to, from its correlation id; see
+    /// [`TaskStatusChangedMessage::task_id`].
+    pub fn task_id(&self) -> TaskId {
+        // Correlation ids are UUIDs unless a foreign producer set one; those still map to a
+        // stable task id.
+        self.correlation_id.parse().unwrap_or_else(|_| {
+            TaskId::from_uuid(uuid::Uuid::new_v5(
+                &uuid::Uuid::NAMESPACE_OID,
+                self.correlation_id.as_bytes(),
+            ))
+        })
+    }
+
+    /// Wraps a message produced in response to this one, e.g. a reply or the next
+    /// pipeline stage's output.
+    pub fn follow_up<U>(&self, produced_by: &str, payload: U) -> Envelope<U> {
+        Envelope {
+            causation_id: Some(self.message_id.clone()),
+            correlation_id: self.correlation_id.clone(),
+            tenant_id: self.tenant_id.clone(),
+            ..Envelope::new(produced_by, payload)
+        }
+    }
+
+    /// [`Envelope::follow_up`] of `cause`, or a new chain when the cause is unknown, e.g.
+    /// when replying to a request that could not be decoded.
+    pub fn following<C>(cause: Option<&Envelope<C>>, produced_by: &str, payload: T) -> Self {
+        match cause {
+            Some(cause) => cause.follow_up(produced_by, payload),
+            None => Envelope::new(produced_by, payload),
+        }
+    }
+
+    /// Separates the payload, keeping the metadata to reply with or log.
+    pub fn split(self) -> (Envelope<()>, T) {
+        let Envelope {
+            schema_version,
+            message_id,
+            correlation_id,
+            causation_id,
+            produced_by,
+            timestamp_ms,
+            tenant_id,
+            payload,
+        } = self;
+        let metadata = Envelope {
+            schema_version,
+            message_id,
+            correlation_id,
+            causation_id,
+            produced_by,
+            timestamp_ms,
+            tenant_id,
+            payload: (),
+        };
+        (metadata, payload)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SemanticSearchNatsResult {
-    pub request_id: String,
-    pub results: Vec<SemanticSearchResultItem>,
-    pub error_message: Option<String>,
+impl<T: Serialize> Envelope<T> {
+    pub fn to_vec(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct SemanticSearchApiResponse {
-    pub search_request_id: String,
-    pub results: Vec<SemanticSearchResultItem>,
-    pub error_message: Option<String>,
+impl<T: DeserializeOwned> Envelope<T> {
+    /// Decodes an envelope, or a bare payload from a producer that predates envelopes,
+    /// which then gets version 0 and a fresh correlation id.
+    pub fn from_slice(bytes: &[u8]) -> serde_json::Result<Self> {
+        let envelope_error = match serde_json::from_slice::<Envelope<T>>(bytes) {
+            Ok(envelope) => return Ok(envelope),
+            Err(e) => e,
+        };
+        match serde_json::from_slice::<T>(bytes) {
+            Ok(payload) => Ok(Envelope {
+                schema_version: 0,
+                ..Envelope::new(UNKNOWN_PRODUCER, payload)
+            }),
+            // Report the error of the shape the sender evidently meant.
+            Err(_) if looks_like_envelope(bytes) => Err(envelope_error),
+            Err(payload_error) => Err(payload_error),
+        }
+    }
+}
+
+fn looks_like_envelope(bytes: &[u8]) -> bool {
+    serde_json::from_slice::<serde_json::Map<String, serde_json::Value>>(bytes)
+        .is_ok_and(|object| object.contains_key("schema_version"))
 }
 
 pub fn current_timestamp_ms() -> u64 {
@@ -120,42 +1909,588 @@ pub fn generate_uuid() -> String {
     uuid::Uuid::new_v4().to_string()
 }
 
+/// Namespace of [`sentence_point_id`]. Changing it changes every derived point id.
+const SENTENCE_POINT_ID_NAMESPACE: uuid::Uuid =
+    uuid::Uuid::from_u128(0x6f3c_2a9e_51d4_4b8a_9c27_e1f0_83b5_d46a);
+
+/// Qdrant point id of a document's sentence: a UUIDv5 of `original_id` and `sentence_order`.
+/// vector_memory_service stores points under it and knowledge_graph_service records it on
+/// HAS_SENTENCE, so a hit in one store can be looked up in the other.
+pub fn sentence_point_id(original_id: DocumentId, sentence_order: u32) -> String {
+    uuid::Uuid::new_v5(
+        &SENTENCE_POINT_ID_NAMESPACE,
+        format!("{}/{}", original_id, sentence_order).as_bytes(),
+    )
+    .to_string()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
-    fn test_perceive_url_task_serialization() {
-        let task = PerceiveUrlTask {
-            url: "http://example.com".to_string(),
+    fn test_envelope_serialization() {
+        let task = PerceiveUrlTask::new("http://example.com");
+        let envelope = Envelope::new("api_service", task);
+        assert_eq!(envelope.correlation_id, envelope.message_id);
+        let reply = envelope.follow_up("perception_service", "done".to_string());
+        let unprompted = Envelope::following(None::<&Envelope<()>>, "perception_service", ());
+        assert_eq!(unprompted.causation_id, None);
+        assert_eq!(reply.correlation_id, envelope.correlation_id);
+        assert_eq!(
+            reply.causation_id.as_deref(),
+            Some(envelope.message_id.as_str())
+        );
+
+        let bytes = envelope.to_vec().unwrap();
+        let decoded: Envelope<PerceiveUrlTask> = Envelope::from_slice(&bytes).unwrap();
+        assert_eq!(decoded.schema_version, ENVELOPE_SCHEMA_VERSION);
+        assert_eq!(decoded.message_id, envelope.message_id);
+        assert_eq!(decoded.produced_by, "api_service");
+        let (metadata, payload) = decoded.split();
+        assert_eq!(metadata.correlation_id, envelope.correlation_id);
+        assert_eq!(payload.url, "http://example.com");
+    }
+
+    #[test]
+    fn test_log_safe_formatting_elides_text_and_vectors() {
+        let mut message = RawTextMessage::new("http://example.com", "é".repeat(10_000));
+        message.id = DocumentId::from_uuid(uuid::Uuid::nil());
+        let debug = format!("{:?}", message);
+        assert!(debug.contains(&format!(
+            "\"{}\"… (+9880 chars)",
+            "é".repeat(LOG_TEXT_CHARS)
+        )));
+        assert!(debug.len() < 1_000);
+        assert_eq!(
+            message.to_string(),
+            format!(
+                "RawTextMessage 00000000-0000-0000-0000-000000000000 from http://example.com (10000 chars): \"{}\"… (+9880 chars)",
+                "é".repeat(LOG_TEXT_CHARS)
+            )
+        );
+
+        let embeddings = TextWithEmbeddingsMessage::new(
+            DocumentId::generate(),
+            "http://example.com",
+            "test-model",
+            vec![SentenceEmbedding {
+                sentence_text: "Short.".to_string(),
+                embedding: vec![0.5; 768],
+                sparse_embedding: None,
+                sentence_order: Some(0),
+            }],
+        );
+        assert!(format!("{:?}", embeddings).contains("embeddings_data: [1 sentences]"));
+        assert_eq!(
+            format!("{:?}", embeddings.embeddings_data[0]),
+            r#"SentenceEmbedding { sentence_text: "Short.", embedding: [768 floats], sparse_embedding: None, sentence_order: Some(0) }"#
+        );
+        assert_eq!(Truncated::new("short", 10).to_string(), "short");
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_timestamp_round_trips_as_millis() {
+        let timestamp: Timestamp = "2024-05-01T14:00:00.250+02:00".parse().unwrap();
+        assert_eq!(timestamp.as_millis(), 1_714_564_800_250);
+        assert_eq!(timestamp.to_string(), "2024-05-01T12:00:00.250Z");
+        assert_eq!("1714564800250".parse::<Timestamp>(), Ok(timestamp));
+        assert!("yesterday".parse::<Timestamp>().is_err());
+
+        assert_eq!(serde_json::to_string(&timestamp).unwrap(), "1714564800250");
+        let decoded: Timestamp = serde_json::from_str("1714564800250").unwrap();
+        assert_eq!(decoded, timestamp);
+        assert_eq!(Timestamp::from_datetime(timestamp.to_datetime()), timestamp);
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn test_timestamp_arithmetic_and_ranges() {
+        use std::time::Duration;
+
+        let now = Timestamp::from_millis(10_000);
+        let hour = Duration::from_secs(3600);
+        assert_eq!(now.saturating_sub(hour), Timestamp::UNIX_EPOCH);
+        assert_eq!(
+            now.saturating_sub(Duration::from_secs(4)).as_millis(),
+            6_000
+        );
+        assert_eq!(
+            now.duration_since(Timestamp::from_millis(12_000)),
+            Duration::ZERO
+        );
+        assert!(Timestamp::from_millis(5_999).is_older_than(Duration::from_secs(4), now));
+        assert!(!Timestamp::from_millis(6_000).is_older_than(Duration::from_secs(4), now));
+
+        let range = TimeRange::last(Duration::from_secs(4), now);
+        assert!(range.contains(Timestamp::from_millis(6_000)));
+        assert!(!range.contains(now));
+        assert!(!range.is_empty());
+        let filters = SearchFilters {
+            processed_after_ms: Some(10),
+            processed_before_ms: Some(10),
+            ..Default::default()
+        };
+        assert!(filters.processed_range().is_empty());
+        assert!(SearchFilters::default().processed_range().is_unbounded());
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_large_raw_text_is_compressed() {
+        let message = RawTextMessage::new("http://example.com", "A big page. ".repeat(10_000));
+        let body = Envelope::new("perception_service", &message)
+            .to_vec()
+            .unwrap();
+
+        let (small, encoding) = compress_above(body.clone(), body.len() + 1).unwrap();
+        assert_eq!(encoding, ContentEncoding::Identity);
+        assert_eq!(small, body);
+        let (disabled, encoding) = compress_above(body.clone(), 0).unwrap();
+        assert_eq!(encoding, ContentEncoding::Identity);
+        assert_eq!(disabled, body);
+
+        let (compressed, encoding) = compress_above(body.clone(), 1024).unwrap();
+        assert_eq!(encoding, ContentEncoding::Zstd);
+        assert!(compressed.len() < body.len() / 10);
+        let decoded = decode_body(&compressed, encoding.header_value()).unwrap();
+        let envelope = Envelope::<RawTextMessage>::from_slice(&decoded).unwrap();
+        assert_eq!(envelope.payload.id, message.id);
+        assert_eq!(envelope.payload.raw_text, message.raw_text);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_content_encoding_header() {
+        assert_eq!(
+            ContentEncoding::from_header(None),
+            Some(ContentEncoding::Identity)
+        );
+        assert_eq!(
+            ContentEncoding::from_header(Some(" ZSTD ")),
+            Some(ContentEncoding::Zstd)
+        );
+        assert_eq!(ContentEncoding::from_header(Some("gzip")), None);
+        assert!(matches!(
+            decode_body(b"{}", Some("gzip")),
+            Err(CompressionError::UnknownContentEncoding(_))
+        ));
+        assert!(matches!(
+            decode_body(b"not zstd", Some("zstd")),
+            Err(CompressionError::Io(_))
+        ));
+        assert_eq!(&*decode_body(b"{}", None).unwrap(), b"{}");
+    }
+
+    #[cfg(feature = "binary")]
+    #[test]
+    fn test_envelope_protobuf_round_trip() {
+        let message = TextWithEmbeddingsMessage {
+            original_id: DocumentId::generate(),
+            source_url: "http://example.com".to_string(),
+            embeddings_data: vec![SentenceEmbedding {
+                sentence_text: "Binary framing.".to_string(),
+                embedding: vec![0.25, -1.5, 3.0],
+                sparse_embedding: Some(SparseVector {
+                    indices: vec![3],
+                    values: vec![0.5],
+                }),
+                sentence_order: None,
+            }],
+            model_name: "test-model".to_string(),
+            timestamp_ms: 42,
+            tenant_id: Some("tenant-a".to_string()),
+            metadata: DocumentMetadata {
+                title: Some("Binary framing".to_string()),
+                language: Some("en".to_string()),
+                ..Default::default()
+            },
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
+        }
+        .with_chunk(DocumentId::generate(), 1, 3);
+        let envelope = Envelope::new("preprocessing_service", &message)
+            .with_tenant_id(message.tenant_id.clone());
+        let bytes = envelope.encode(PayloadFormat::Protobuf).unwrap();
+        assert!(bytes.len() < envelope.to_vec().unwrap().len());
+
+        let decoded = Envelope::<TextWithEmbeddingsMessage>::decode_with_content_type(
+            &bytes,
+            Some(PayloadFormat::Protobuf.content_type()),
+        )
+        .unwrap();
+        assert_eq!(decoded.message_id, envelope.message_id);
+        assert_eq!(decoded.causation_id, None);
+        assert_eq!(decoded.tenant_id, message.tenant_id);
+        let sentence = &decoded.payload.embeddings_data[0];
+        assert_eq!(sentence.embedding, message.embeddings_data[0].embedding);
+        assert_eq!(
+            sentence.sparse_embedding,
+            message.embeddings_data[0].sparse_embedding
+        );
+        assert_eq!(sentence.sentence_order, None);
+        assert_eq!(decoded.payload.tenant_id, message.tenant_id);
+        assert_eq!(decoded.payload.metadata, message.metadata);
+        assert_eq!(decoded.payload.chunk_index, Some(1));
+        assert_eq!(decoded.payload.total_chunks, Some(3));
+        assert_eq!(
+            decoded.payload.parent_document_id,
+            message.parent_document_id
+        );
+
+        let result = QueryEmbeddingResult {
+            request_id: RequestId::generate(),
+            embedding: Some(vec![]),
+            sparse_embedding: None,
+            model_name: None,
+            error_message: None,
+        };
+        let bytes = Envelope::new("preprocessing_service", &result)
+            .encode(PayloadFormat::Protobuf)
+            .unwrap();
+        let decoded =
+            Envelope::<QueryEmbeddingResult>::decode(&bytes, PayloadFormat::Protobuf).unwrap();
+        assert_eq!(decoded.payload.embedding, Some(vec![]));
+
+        assert_eq!(
+            PayloadFormat::from_content_type(None),
+            Some(PayloadFormat::Json)
+        );
+        assert_eq!(
+            PayloadFormat::from_content_type(Some("Application/JSON; charset=utf-8")),
+            Some(PayloadFormat::Json)
+        );
+        assert_eq!(PayloadFormat::from_content_type(Some("text/plain")), None);
+    }
+
+    #[test]
+    fn test_ids() {
+        let id = DocumentId::generate();
+        assert_eq!(id.to_string().parse::<DocumentId>().unwrap(), id);
+        assert_eq!(serde_json::to_string(&id).unwrap(), format!("\"{}\"", id));
+        assert_ne!(id, DocumentId::generate());
+
+        let task_id: TaskId =
+            serde_json::from_str(r#""3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70""#).unwrap();
+        assert_eq!(task_id.to_string(), "3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70");
+        assert!(serde_json::from_str::<RequestId>(r#""req-1""#).is_err());
+        assert!("".parse::<RequestId>().is_err());
+    }
+
+    #[test]
+    fn test_constructors() {
+        let raw = RawTextMessage::new("http://example.com", "Some text.");
+        assert!(!raw.id.as_uuid().is_nil());
+        assert!(raw.timestamp_ms > 0);
+        assert_ne!(
+            raw.id,
+            RawTextMessage::new("http://example.com", "Some text.").id
+        );
+
+        let task = GenerateTextTask::new(50)
+            .with_prompt("Once")
+            .with_backend(GenerationBackend::Markov)
+            .with_seed(7);
+        assert!(task.validate().is_ok());
+        let deserialized: GenerateTextTask =
+            serde_json::from_str(&serde_json::to_string(&task).unwrap()).unwrap();
+        assert_eq!(deserialized.task_id, task.task_id);
+        assert_eq!(deserialized.prompt.as_deref(), Some("Once"));
+        assert_eq!(deserialized.seed, Some(7));
+
+        let dead_letter = DeadLetterMessage::new("data.raw_text.discovered", raw, "failed", 3);
+        assert_eq!(dead_letter.attempts, 3);
+        assert!(dead_letter.dead_lettered_at_ms > 0);
+    }
+
+    #[test]
+    fn test_pipeline_error_message_serialization() {
+        let original_id = DocumentId::generate();
+        let error = PipelineErrorMessage::new(
+            PipelineStage::KnowledgeGraph,
+            PipelineErrorKind::Storage,
+            "Neo4j unavailable",
+        )
+        .with_original_id(original_id)
+        .with_attempts(3);
+        assert_eq!(error.stage.error_subject(), "errors.knowledge_graph");
+
+        let serialized = serde_json::to_string(&error).unwrap();
+        assert!(serialized.contains(r#""stage":"knowledge_graph""#));
+        assert!(serialized.contains(r#""error_kind":"storage""#));
+        let deserialized: PipelineErrorMessage = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.original_id, Some(original_id));
+        assert_eq!(deserialized.task_id, None);
+        assert_eq!(deserialized.attempts, 3);
+        assert_eq!(deserialized.error_kind, PipelineErrorKind::Storage);
+    }
+
+    #[test]
+    fn test_task_status_changed_message_serialization() {
+        let submission = Envelope::new("api_service", PerceiveUrlTask::new("http://example.com"));
+        let scraped = submission.follow_up("perception_service", ());
+        let status =
+            TaskStatusChangedMessage::new(&scraped, PipelineStage::Scraping, TaskStatus::Failed)
+                .with_detail("timed out");
+        assert_eq!(status.task_id.to_string(), submission.correlation_id);
+
+        let serialized = serde_json::to_string(&status).unwrap();
+        assert!(serialized.contains(r#""status":"failed""#));
+        let deserialized: TaskStatusChangedMessage = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.stage, PipelineStage::Scraping);
+        assert_eq!(deserialized.status, TaskStatus::Failed);
+        assert_eq!(deserialized.detail.as_deref(), Some("timed out"));
+        assert_eq!(deserialized.original_id, None);
+    }
+
+    #[test]
+    fn test_validation() {
+        let task = PerceiveUrlTask::new;
+        assert!(task(" https://example.com/page ").validate().is_ok());
+        assert_eq!(task("").validate().unwrap_err().field, "url");
+        assert!(task("example.com").validate().is_err());
+        assert!(task("ftp://example.com").validate().is_err());
+        assert!(task("file:///etc/passwd").validate().is_err());
+
+        let generate: GenerateTextTask = serde_json::from_str(
+            r#"{"task_id":"3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70","prompt":null,"max_length":1000}"#,
+        )
+        .unwrap();
+        assert!(generate.validate().is_ok());
+        let too_long = GenerateTextTask {
+            max_length: MAX_GENERATION_LENGTH + 1,
+            ..generate.clone()
+        };
+        assert_eq!(too_long.validate().unwrap_err().field, "max_length");
+        let empty = GenerateTextTask {
+            max_length: 0,
+            ..generate
+        };
+        assert_eq!(
+            empty.validate().unwrap_err().to_string(),
+            "max_length must be between 1 and 1000"
+        );
+
+        let search = SemanticSearchApiRequest {
+            query_text: "rust".to_string(),
+            top_k: 10,
+            filters: SearchFilters::default(),
+            options: SearchOptions::default(),
+        };
+        assert!(search.validate().is_ok());
+        let blank = SemanticSearchApiRequest {
+            query_text: "  ".to_string(),
+            ..search.clone()
+        };
+        assert_eq!(blank.validate().unwrap_err().field, "query_text");
+        let unbounded = SemanticSearchApiRequest {
+            top_k: MAX_SEARCH_TOP_K + 1,
+            ..search.clone()
+        };
+        assert_eq!(unbounded.validate().unwrap_err().field, "top_k");
+        let empty_range = SemanticSearchApiRequest {
+            filters: SearchFilters {
+                processed_after_ms: Some(10),
+                processed_before_ms: Some(10),
+                ..Default::default()
+            },
+            ..search.clone()
+        };
+        assert_eq!(
+            empty_range.validate().unwrap_err().field,
+            "processed_before_ms"
+        );
+        let paged_groups = SemanticSearchApiRequest {
+            options: SearchOptions {
+                offset: 10,
+                group_by_document: true,
+                ..Default::default()
+            },
+            ..search
         };
+        assert_eq!(paged_groups.validate().unwrap_err().field, "offset");
+    }
+
+    #[test]
+    fn test_envelope_accepts_bare_payloads() {
+        let decoded: Envelope<PerceiveUrlTask> =
+            Envelope::from_slice(br#"{"url":"http://example.com"}"#).unwrap();
+        assert_eq!(decoded.schema_version, 0);
+        assert_eq!(decoded.produced_by, "unknown");
+        assert_eq!(decoded.payload.url, "http://example.com");
+
+        // A broken envelope reports the envelope's error, not the payload's.
+        let error = Envelope::<PerceiveUrlTask>::from_slice(
+            br#"{"schema_version":1,"message_id":"m","correlation_id":"c","produced_by":"p","timestamp_ms":1,"payload":{}}"#,
+        )
+        .unwrap_err();
+        assert!(error.to_string().contains("url"));
+    }
+
+    /// Payloads as the first release published them, before any field was added.
+    #[test]
+    fn test_old_format_payloads() {
+        let task: GenerateTextTask = serde_json::from_str(
+            r#"{"task_id":"3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70","prompt":null,"max_length":50}"#,
+        )
+        .unwrap();
+        assert_eq!(task.corpus, GenerationCorpus::Global);
+        assert!(task.stop_sequences.is_empty());
+        assert_eq!(task.backend, None);
+        assert_eq!(task.template, None);
+        assert!(task.validate().is_ok());
+
+        let generated: GeneratedTextMessage = serde_json::from_str(
+            r#"{"original_task_id":"3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70","generated_text":"Hello.","timestamp_ms":1}"#,
+        )
+        .unwrap();
+        assert_eq!(generated.seed, None);
+        assert_eq!(generated.stop_reason, None);
+
+        let message: TextWithEmbeddingsMessage = serde_json::from_str(
+            r#"{"original_id":"1b9d6bcd-bbfd-4b2d-9b5d-ab8dfbbd4bed","source_url":"http://example.com","embeddings_data":[{"sentence_text":"Hello.","embedding":[0.1,0.2]}],"model_name":"m","timestamp_ms":1}"#,
+        )
+        .unwrap();
+        assert_eq!(message.tenant_id, None);
+        assert!(message.embeddings_data[0].sparse_embedding.is_none());
+        assert_eq!(message.embeddings_data[0].sentence_order, None);
+
+        let request: SemanticSearchApiRequest =
+            serde_json::from_str(r#"{"query_text":"rust","top_k":5}"#).unwrap();
+        assert!(request.filters.is_empty());
+        assert_eq!(request.options, SearchOptions::default());
+
+        let result: QueryEmbeddingResult = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","embedding":[0.1],"model_name":"m","error_message":null}"#,
+        )
+        .unwrap();
+        assert!(result.sparse_embedding.is_none());
+
+        let search_task: SemanticSearchNatsTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","query_embedding":[0.1],"top_k":5}"#)
+                .unwrap();
+        assert_eq!(search_task.model_name, None);
+        assert!(search_task.sparse_query.is_none());
+        assert!(search_task.filters.is_empty());
+        assert!(!search_task.options.group_by_document);
+        assert!(search_task.read_consistency.is_none());
+        assert_eq!(search_task.hnsw_ef, None);
+
+        let search_result: SemanticSearchNatsResult = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","results":[{"qdrant_point_id":"p1","score":0.9,"payload":{"original_document_id":"1b9d6bcd-bbfd-4b2d-9b5d-ab8dfbbd4bed","source_url":"http://example.com","sentence_text":"Hello.","sentence_order":0,"model_name":"m","processed_at_ms":1}}],"error_message":null}"#,
+        )
+        .unwrap();
+        assert!(search_result.groups.is_none());
+        assert_eq!(search_result.results[0].payload.tenant_id, None);
+    }
+
+    /// Payloads from a newer release may carry fields this one does not know.
+    #[test]
+    fn test_payloads_with_unknown_fields() {
+        let task: PerceiveUrlTask =
+            serde_json::from_str(r#"{"url":"http://example.com","crawl_depth":2}"#).unwrap();
+        assert_eq!(task.url, "http://example.com");
+
+        let result: GeneratorStatsResult = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","global":{"states":1,"transitions":2,"starters":1,"trained_documents":1,"estimated_memory_bytes":64,"bytes_on_disk":10},"domain_models":0,"document_models":0,"total_estimated_memory_bytes":64,"gpu":true}"#,
+        )
+        .unwrap();
+        assert_eq!(result.global.last_trained_ms, None);
+        assert_eq!(result.neural_model, None);
+        assert_eq!(result.error_message, None);
+
+        let envelope: Envelope<RawTextMessage> = Envelope::from_slice(
+            br#"{"schema_version":2,"message_id":"m","correlation_id":"c","produced_by":"p","timestamp_ms":1,"tenant_id":"t","payload":{"id":"1b9d6bcd-bbfd-4b2d-9b5d-ab8dfbbd4bed","source_url":"http://example.com","raw_text":"Hello.","timestamp_ms":1,"language":"en"}}"#,
+        )
+        .unwrap();
+        assert_eq!(envelope.schema_version, 2);
+        assert_eq!(envelope.causation_id, None);
+        assert_eq!(envelope.tenant_id.as_deref(), Some("t"));
+        assert_eq!(envelope.payload.raw_text, "Hello.");
+    }
+
+    #[test]
+    fn test_perceive_url_task_serialization() {
+        let task = PerceiveUrlTask::new("http://example.com");
         let serialized = serde_json::to_string(&task).unwrap();
+        assert_eq!(serialized, r#"{"url":"http://example.com"}"#);
         let deserialized: PerceiveUrlTask = serde_json::from_str(&serialized).unwrap();
         assert_eq!(task.url, deserialized.url);
+        assert_eq!(deserialized.priority(), TaskPriority::Normal);
+        assert!(!deserialized.is_expired_at(u64::MAX));
+
+        let urgent = PerceiveUrlTask::new("http://example.com")
+            .with_priority(TaskPriority::High)
+            .with_deadline_ms(1_000);
+        let serialized = serde_json::to_string(&urgent).unwrap();
+        assert!(serialized.contains(r#""priority":"high","deadline_ms":1000"#));
+        let deserialized: PerceiveUrlTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.priority(), TaskPriority::High);
+        assert!(!deserialized.is_expired_at(1_000));
+        assert!(deserialized.is_expired_at(1_001));
+    }
+
+    #[test]
+    fn test_task_priority_order() {
+        assert!(TaskPriority::Low < TaskPriority::Normal);
+        assert!(TaskPriority::Normal < TaskPriority::High);
+        assert_eq!(TaskPriority::default(), TaskPriority::Normal);
     }
 
     #[test]
     fn test_raw_text_message_serialization() {
         let msg = RawTextMessage {
-            id: "test-id".to_string(),
+            id: DocumentId::generate(),
             source_url: "http://example.com".to_string(),
             raw_text: "Hello world".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
         };
         let serialized = serde_json::to_string(&msg).unwrap();
+        assert!(!serialized.contains("metadata"));
         let deserialized: RawTextMessage = serde_json::from_str(&serialized).unwrap();
         assert_eq!(msg.id, deserialized.id);
         assert_eq!(msg.raw_text, deserialized.raw_text);
+
+        let msg = msg.with_metadata(DocumentMetadata {
+            title: Some("Hello".to_string()),
+            canonical_url: Some("https://example.com/".to_string()),
+            ..Default::default()
+        });
+        let serialized = serde_json::to_string(&msg).unwrap();
+        assert!(serialized.contains(r#""metadata":{"title":"Hello","canonical_url""#));
+        assert!(!serialized.contains("chunk"));
+        let deserialized: RawTextMessage = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.metadata, msg.metadata);
+        assert_eq!(deserialized.chunk_index, None);
+
+        let parent_document_id = DocumentId::generate();
+        let chunk = RawTextMessage::new("http://example.com", "Second half").with_chunk(
+            parent_document_id,
+            1,
+            2,
+        );
+        let serialized = serde_json::to_string(&chunk).unwrap();
+        assert!(serialized.contains(r#""chunk_index":1,"total_chunks":2"#));
+        let deserialized: RawTextMessage = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.parent_document_id, Some(parent_document_id));
+        assert_ne!(deserialized.id, parent_document_id);
     }
 
     #[test]
     fn test_tokenized_text_message_serialization() {
         let msg = TokenizedTextMessage {
-            original_id: "test-id".to_string(),
+            original_id: DocumentId::generate(),
             source_url: "http://example.com".to_string(),
             tokens: vec!["Hello".to_string(), "world".to_string()],
             sentences: vec!["Hello world.".to_string()],
             timestamp_ms: current_timestamp_ms(),
+            metadata: DocumentMetadata::default(),
         };
         let serialized = serde_json::to_string(&msg).unwrap();
         let deserialized: TokenizedTextMessage = serde_json::from_str(&serialized).unwrap();
@@ -166,27 +2501,196 @@ mod tests {
     #[test]
     fn test_generate_text_task_serialization() {
         let task = GenerateTextTask {
-            task_id: generate_uuid(),
+            task_id: TaskId::generate(),
             prompt: Some("Hello".to_string()),
             max_length: 50,
+            corpus: GenerationCorpus::Domain("example.com".to_string()),
+            temperature: Some(0.7),
+            top_k: Some(5),
+            stop_sequences: vec!["\n".to_string()],
+            seed: Some(42),
+            min_sentences: Some(2),
+            max_sentences: None,
+            backend: Some(GenerationBackend::Neural),
+            model_name: Some("news".to_string()),
+            template: Some("About {entity}: {sentence_about:climate}".to_string()),
+            priority: Some(TaskPriority::Low),
+            deadline_ms: Some(1_700_000_000_000),
         };
         let serialized = serde_json::to_string(&task).unwrap();
+        assert!(serialized.contains(r#""corpus":{"scope":"domain","key":"example.com"}"#));
         let deserialized: GenerateTextTask = serde_json::from_str(&serialized).unwrap();
         assert_eq!(task.task_id, deserialized.task_id);
         assert_eq!(task.prompt, deserialized.prompt);
+        assert_eq!(task.corpus, deserialized.corpus);
+        assert_eq!(task.temperature, deserialized.temperature);
+        assert_eq!(task.top_k, deserialized.top_k);
+        assert_eq!(task.stop_sequences, deserialized.stop_sequences);
+        assert_eq!(task.seed, deserialized.seed);
+        assert_eq!(task.min_sentences, deserialized.min_sentences);
+        assert_eq!(task.max_sentences, deserialized.max_sentences);
+        assert_eq!(deserialized.backend, Some(GenerationBackend::Neural));
+        assert_eq!(deserialized.model_name.as_deref(), Some("news"));
+        assert_eq!(deserialized.template, task.template);
+        assert_eq!(deserialized.priority(), TaskPriority::Low);
+        assert_eq!(deserialized.deadline_ms, task.deadline_ms);
+
+        let legacy: GenerateTextTask = serde_json::from_str(
+            r#"{"task_id":"3a7f5e21-9c4b-4d8e-a1f2-0b3c4d5e6f70","prompt":null,"max_length":10}"#,
+        )
+        .unwrap();
+        assert_eq!(legacy.corpus, GenerationCorpus::Global);
+        assert_eq!(legacy.temperature, None);
+        assert_eq!(legacy.backend, None);
+        assert_eq!(legacy.model_name, None);
+        assert_eq!(legacy.template, None);
+        assert!(legacy.stop_sequences.is_empty());
+    }
+
+    #[test]
+    fn test_generator_stats_serialization() {
+        let result = GeneratorStatsResult {
+            request_id: RequestId::generate(),
+            global: MarkovModelStats {
+                states: 120,
+                transitions: 340,
+                starters: 15,
+                trained_documents: 4,
+                last_trained_ms: Some(1_700_000_000_000),
+                estimated_memory_bytes: 65_536,
+            },
+            domain_models: 2,
+            document_models: 0,
+            total_estimated_memory_bytes: 131_072,
+            neural_model: None,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GeneratorStatsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.global.transitions, 340);
+        assert_eq!(deserialized.global.last_trained_ms, Some(1_700_000_000_000));
+        assert_eq!(deserialized.domain_models, 2);
+        assert_eq!(deserialized.neural_model, None);
+    }
+
+    #[test]
+    fn test_generator_models_serialization() {
+        let result = GeneratorModelsResult {
+            request_id: RequestId::generate(),
+            models: vec![GeneratorModelInfo {
+                name: "news".to_string(),
+                subject: "data.processed_text.tokenized".to_string(),
+                hosts: vec!["example.com".to_string()],
+                tenants: Vec::new(),
+                global: MarkovModelStats {
+                    states: 10,
+                    trained_documents: 1,
+                    ..MarkovModelStats::default()
+                },
+                domain_models: 1,
+                document_models: 0,
+            }],
+            default_model: "default".to_string(),
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GeneratorModelsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.models.len(), 1);
+        assert_eq!(deserialized.models[0].name, "news");
+        assert_eq!(
+            deserialized.models[0].hosts,
+            vec!["example.com".to_string()]
+        );
+        assert_eq!(deserialized.models[0].global.states, 10);
+        assert_eq!(deserialized.default_model, "default");
+    }
+
+    #[test]
+    fn test_generator_evaluate_serialization() {
+        let task: GeneratorEvaluateTask = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","text":"The cat sat."}"#,
+        )
+        .unwrap();
+        assert_eq!(task.backend, None);
+        assert_eq!(task.corpus, GenerationCorpus::Global);
+
+        let result = GeneratorEvaluateResult {
+            request_id: RequestId::generate(),
+            backend: Some(GenerationBackend::Markov),
+            model: Some("default".to_string()),
+            evaluation: Some(TextEvaluation {
+                tokens: 4,
+                log_likelihood: -6.0,
+                loss: 1.5,
+                perplexity: 1.5f64.exp(),
+                unseen_tokens: 1,
+            }),
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GeneratorEvaluateResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.backend, Some(GenerationBackend::Markov));
+        assert_eq!(deserialized.evaluation, result.evaluation);
+    }
+
+    #[test]
+    fn test_generator_retrain_serialization() {
+        let task: GeneratorRetrainTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"}"#)
+                .unwrap();
+        assert_eq!(task.model_name, None);
+
+        let result = GeneratorRetrainResult {
+            request_id: RequestId::generate(),
+            models: vec!["default".to_string(), "news".to_string()],
+            documents: 12,
+            sentences: 480,
+            duration_ms: 1500,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GeneratorRetrainResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.models, result.models);
+        assert_eq!(deserialized.sentences, 480);
     }
 
     #[test]
     fn test_generated_text_message_serialization() {
         let msg = GeneratedTextMessage {
-            original_task_id: "test-id".to_string(),
+            original_task_id: TaskId::generate(),
             generated_text: "Hello world".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            seed: Some(42),
+            stop_reason: Some(GenerationStopReason::StopSequence),
         };
         let serialized = serde_json::to_string(&msg).unwrap();
+        assert!(serialized.contains(r#""stop_reason":"stop_sequence""#));
         let deserialized: GeneratedTextMessage = serde_json::from_str(&serialized).unwrap();
         assert_eq!(msg.original_task_id, deserialized.original_task_id);
         assert_eq!(msg.generated_text, deserialized.generated_text);
+        assert_eq!(
+            deserialized.stop_reason,
+            Some(GenerationStopReason::StopSequence)
+        );
+    }
+
+    #[test]
+    fn test_generation_failed_event_serialization() {
+        let event = GenerationFailedEvent {
+            task_id: TaskId::generate(),
+            reason: GenerationFailureReason::ModelNotTrained,
+            detail: "corpus Global has no trained model".to_string(),
+            timestamp_ms: current_timestamp_ms(),
+        };
+        let serialized = serde_json::to_string(&event).unwrap();
+        assert!(serialized.contains(r#""reason":"model_not_trained""#));
+        let deserialized: GenerationFailedEvent = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.task_id, event.task_id);
+        assert_eq!(
+            deserialized.reason,
+            GenerationFailureReason::ModelNotTrained
+        );
+        assert_eq!(deserialized.detail, event.detail);
     }
 
     #[test]
@@ -194,30 +2698,57 @@ mod tests {
         let se = SentenceEmbedding {
             sentence_text: "This is a test sentence.".to_string(),
             embedding: vec![0.1, 0.2, 0.3],
+            sparse_embedding: Some(SparseVector {
+                indices: vec![7, 42],
+                values: vec![1.0, 2.0],
+            }),
+            sentence_order: Some(4),
         };
         let serialized = serde_json::to_string(&se).unwrap();
         let deserialized: SentenceEmbedding = serde_json::from_str(&serialized).unwrap();
         assert_eq!(se.sentence_text, deserialized.sentence_text);
         assert_eq!(se.embedding, deserialized.embedding);
+        assert_eq!(se.sparse_embedding, deserialized.sparse_embedding);
+        assert_eq!(se.sentence_order, deserialized.sentence_order);
+    }
+
+    #[test]
+    fn test_sentence_embedding_without_sparse_embedding() {
+        let json = r#"{"sentence_text":"Dense only.","embedding":[0.1,0.2]}"#;
+        let deserialized: SentenceEmbedding = serde_json::from_str(json).unwrap();
+        assert!(deserialized.sparse_embedding.is_none());
+        assert!(deserialized.sentence_order.is_none());
+
+        let serialized = serde_json::to_string(&deserialized).unwrap();
+        assert!(!serialized.contains("sparse_embedding"));
     }
 
     #[test]
     fn test_text_with_embeddings_message_serialization() {
         let msg = TextWithEmbeddingsMessage {
-            original_id: "doc-123".to_string(),
+            original_id: DocumentId::generate(),
             source_url: "http://example.com".to_string(),
             embeddings_data: vec![
                 SentenceEmbedding {
                     sentence_text: "Sentence one.".to_string(),
                     embedding: vec![0.1, 0.2],
+                    sparse_embedding: None,
+                    sentence_order: None,
                 },
                 SentenceEmbedding {
                     sentence_text: "Sentence two.".to_string(),
                     embedding: vec![0.3, 0.4],
+                    sparse_embedding: None,
+                    sentence_order: None,
                 },
             ],
             model_name: "test-model-v1".to_string(),
             timestamp_ms: current_timestamp_ms(),
+            tenant_id: Some("tenant-a".to_string()),
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
         };
         let serialized = serde_json::to_string(&msg).unwrap();
         let deserialized: TextWithEmbeddingsMessage = serde_json::from_str(&serialized).unwrap();
@@ -232,17 +2763,41 @@ mod tests {
         let req = SemanticSearchApiRequest {
             query_text: "Hello world".to_string(),
             top_k: 10,
+            filters: SearchFilters {
+                language: Some("en".to_string()),
+                processed_after_ms: Some(1_000),
+                ..Default::default()
+            },
+            options: SearchOptions {
+                score_threshold: Some(0.5),
+                group_by_document: true,
+                hits_per_document: Some(2),
+                ..Default::default()
+            },
         };
         let serialized = serde_json::to_string(&req).unwrap();
+        // Flattened: the filters and options are top-level fields of the request.
+        assert!(serialized.contains(r#""language":"en","processed_after_ms":1000"#));
+        assert!(serialized.contains(r#""group_by_document":true"#));
         let deserialized: SemanticSearchApiRequest = serde_json::from_str(&serialized).unwrap();
         assert_eq!(req.query_text, deserialized.query_text);
         assert_eq!(req.top_k, deserialized.top_k);
+        assert_eq!(req.filters, deserialized.filters);
+        assert_eq!(req.options, deserialized.options);
+
+        let legacy: SemanticSearchApiRequest = serde_json::from_str(
+            r#"{"query_text":"Hello","top_k":5,"group_by_document":true,"hits_per_document":2}"#,
+        )
+        .unwrap();
+        assert!(legacy.options.group_by_document);
+        assert_eq!(legacy.options.hits_per_document, Some(2));
+        assert!(legacy.filters.is_empty());
     }
 
     #[test]
     fn test_query_for_embedding_task_serialization() {
         let task = QueryForEmbeddingTask {
-            request_id: generate_uuid(),
+            request_id: RequestId::generate(),
             text_to_embed: "Hello world".to_string(),
         };
         let serialized = serde_json::to_string(&task).unwrap();
@@ -254,8 +2809,9 @@ mod tests {
     #[test]
     fn test_query_embedding_result_serialization() {
         let result = QueryEmbeddingResult {
-            request_id: generate_uuid(),
+            request_id: RequestId::generate(),
             embedding: Some(vec![0.1, 0.2, 0.3]),
+            sparse_embedding: None,
             model_name: Some("test-model-v1".to_string()),
             error_message: None,
         };
@@ -269,12 +2825,17 @@ mod tests {
     #[test]
     fn test_qdrant_point_payload_serialization() {
         let payload = QdrantPointPayload {
-            original_document_id: "doc-123".to_string(),
+            original_document_id: DocumentId::generate(),
             source_url: "http://example.com".to_string(),
             sentence_text: "This is a test sentence.".to_string(),
             sentence_order: 1,
             model_name: "test-model-v1".to_string(),
             processed_at_ms: current_timestamp_ms(),
+            tenant_id: Some("tenant-a".to_string()),
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
         };
         let serialized = serde_json::to_string(&payload).unwrap();
         let deserialized: QdrantPointPayload = serde_json::from_str(&serialized).unwrap();
@@ -287,20 +2848,121 @@ mod tests {
         assert_eq!(payload.sentence_order, deserialized.sentence_order);
         assert_eq!(payload.model_name, deserialized.model_name);
         assert_eq!(payload.processed_at_ms, deserialized.processed_at_ms);
+        assert_eq!(payload.tenant_id, deserialized.tenant_id);
+
+        let untenanted = QdrantPointPayload {
+            tenant_id: None,
+            ..payload
+        };
+        let serialized = serde_json::to_string(&untenanted).unwrap();
+        assert!(!serialized.contains("tenant_id"));
     }
 
     #[test]
     fn test_semantic_search_nats_task_serialization() {
         let task = SemanticSearchNatsTask {
-            request_id: generate_uuid(),
+            request_id: RequestId::generate(),
             query_embedding: vec![0.1, 0.2, 0.3],
             top_k: 10,
+            model_name: Some("test-model-v1".to_string()),
+            filters: SearchFilters {
+                source_url: Some("http://example.com".to_string()),
+                tenant_id: Some("tenant-a".to_string()),
+                ..Default::default()
+            },
+            options: SearchOptions {
+                group_by_document: true,
+                hits_per_document: Some(3),
+                ..Default::default()
+            },
+            sparse_query: Some(SparseVector {
+                indices: vec![1, 2],
+                values: vec![1.0, 1.0],
+            }),
+            read_consistency: Some(ReadConsistency::Level(ReadConsistencyLevel::Majority)),
+            timeout_secs: Some(5),
+            hnsw_ef: Some(256),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        assert!(serialized.contains(r#""read_consistency":"majority""#));
+        assert!(serialized.contains(r#""tenant_id":"tenant-a""#));
+        let deserialized: SemanticSearchNatsTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.query_embedding, deserialized.query_embedding);
+        assert_eq!(task.top_k, deserialized.top_k);
+        assert_eq!(task.model_name, deserialized.model_name);
+        assert_eq!(task.filters, deserialized.filters);
+        assert_eq!(task.sparse_query, deserialized.sparse_query);
+        assert_eq!(task.options, deserialized.options);
+        assert_eq!(task.read_consistency, deserialized.read_consistency);
+        assert_eq!(task.timeout_secs, deserialized.timeout_secs);
+        assert_eq!(task.hnsw_ef, deserialized.hnsw_ef);
+    }
+
+    #[test]
+    fn test_read_consistency_serialization() {
+        let factor: ReadConsistency = serde_json::from_str("2").unwrap();
+        assert_eq!(factor, ReadConsistency::Factor(2));
+        let level: ReadConsistency = serde_json::from_str(r#""quorum""#).unwrap();
+        assert_eq!(level, ReadConsistency::Level(ReadConsistencyLevel::Quorum));
+        assert_eq!(
+            serde_json::to_string(&ReadConsistency::Level(ReadConsistencyLevel::All)).unwrap(),
+            r#""all""#
+        );
+        assert!(serde_json::from_str::<ReadConsistency>(r#""eventual""#).is_err());
+    }
+
+    #[test]
+    fn test_semantic_search_nats_batch_task_serialization() {
+        let task = SemanticSearchNatsBatchTask {
+            request_id: RequestId::generate(),
+            queries: vec![
+                SemanticSearchBatchQuery {
+                    query_embedding: vec![0.1, 0.2],
+                    top_k: 5,
+                },
+                SemanticSearchBatchQuery {
+                    query_embedding: vec![0.3, 0.4],
+                    top_k: 2,
+                },
+            ],
+            model_name: None,
+            tenant_id: Some("tenant-a".to_string()),
         };
         let serialized = serde_json::to_string(&task).unwrap();
-        let deserialized: SemanticSearchNatsTask = serde_json::from_str(&serialized).unwrap();
+        let deserialized: SemanticSearchNatsBatchTask = serde_json::from_str(&serialized).unwrap();
         assert_eq!(task.request_id, deserialized.request_id);
-        assert_eq!(task.query_embedding, deserialized.query_embedding);
-        assert_eq!(task.top_k, deserialized.top_k);
+        assert_eq!(deserialized.queries.len(), 2);
+        assert_eq!(deserialized.queries[1].query_embedding, vec![0.3, 0.4]);
+        assert_eq!(deserialized.queries[1].top_k, 2);
+        assert_eq!(task.tenant_id, deserialized.tenant_id);
+
+        let result = SemanticSearchNatsBatchResult {
+            request_id: task.request_id,
+            results: vec![vec![], vec![]],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: SemanticSearchNatsBatchResult =
+            serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.results.len(), 2);
+    }
+
+    #[test]
+    fn test_semantic_search_nats_task_without_model_name() {
+        let json = r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","query_embedding":[0.1,0.2],"top_k":5}"#;
+        let deserialized: SemanticSearchNatsTask = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            deserialized.request_id.to_string(),
+            "6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"
+        );
+        assert!(deserialized.model_name.is_none());
+        assert!(deserialized.sparse_query.is_none());
+        assert!(deserialized.filters.is_empty());
+        assert_eq!(deserialized.options, SearchOptions::default());
+        assert!(deserialized.read_consistency.is_none());
+        assert!(deserialized.timeout_secs.is_none());
+        assert!(deserialized.hnsw_ef.is_none());
     }
 
     #[test]
@@ -309,12 +2971,17 @@ mod tests {
             qdrant_point_id: "point-123".to_string(),
             score: 0.5,
             payload: QdrantPointPayload {
-                original_document_id: "doc-123".to_string(),
+                original_document_id: DocumentId::generate(),
                 source_url: "http://example.com".to_string(),
                 sentence_text: "This is a test sentence.".to_string(),
                 sentence_order: 1,
                 model_name: "test-model-v1".to_string(),
                 processed_at_ms: current_timestamp_ms(),
+                tenant_id: None,
+                metadata: DocumentMetadata::default(),
+                chunk_index: None,
+                total_chunks: None,
+                parent_document_id: None,
             },
         };
         let serialized = serde_json::to_string(&item).unwrap();
@@ -344,33 +3011,44 @@ mod tests {
     #[test]
     fn test_semantic_search_nats_result_serialization() {
         let result = SemanticSearchNatsResult {
-            request_id: generate_uuid(),
+            request_id: RequestId::generate(),
             results: vec![
                 SemanticSearchResultItem {
                     qdrant_point_id: "point-123".to_string(),
                     score: 0.5,
                     payload: QdrantPointPayload {
-                        original_document_id: "doc-123".to_string(),
+                        original_document_id: DocumentId::generate(),
                         source_url: "http://example.com".to_string(),
                         sentence_text: "This is a test sentence.".to_string(),
                         sentence_order: 1,
                         model_name: "test-model-v1".to_string(),
                         processed_at_ms: current_timestamp_ms(),
+                        tenant_id: None,
+                        metadata: DocumentMetadata::default(),
+                        chunk_index: None,
+                        total_chunks: None,
+                        parent_document_id: None,
                     },
                 },
                 SemanticSearchResultItem {
                     qdrant_point_id: "point-456".to_string(),
                     score: 0.4,
                     payload: QdrantPointPayload {
-                        original_document_id: "doc-456".to_string(),
+                        original_document_id: DocumentId::generate(),
                         source_url: "http://example.com".to_string(),
                         sentence_text: "This is another test sentence.".to_string(),
                         sentence_order: 2,
                         model_name: "test-model-v1".to_string(),
                         processed_at_ms: current_timestamp_ms(),
+                        tenant_id: None,
+                        metadata: DocumentMetadata::default(),
+                        chunk_index: None,
+                        total_chunks: None,
+                        parent_document_id: None,
                     },
                 },
             ],
+            groups: None,
             error_message: None,
         };
 
@@ -438,36 +3116,94 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_semantic_search_nats_result_with_groups() {
+        let hit = SemanticSearchResultItem {
+            qdrant_point_id: "point-123".to_string(),
+            score: 0.9,
+            payload: QdrantPointPayload {
+                original_document_id: DocumentId::generate(),
+                source_url: "http://example.com".to_string(),
+                sentence_text: "This is a test sentence.".to_string(),
+                sentence_order: 0,
+                model_name: "test-model-v1".to_string(),
+                processed_at_ms: current_timestamp_ms(),
+                tenant_id: None,
+                metadata: DocumentMetadata::default(),
+                chunk_index: None,
+                total_chunks: None,
+                parent_document_id: None,
+            },
+        };
+        let result = SemanticSearchNatsResult {
+            request_id: RequestId::generate(),
+            results: vec![hit.clone()],
+            groups: Some(vec![SemanticSearchResultGroup {
+                original_document_id: DocumentId::generate(),
+                hits: vec![hit],
+            }]),
+            error_message: None,
+        };
+
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: SemanticSearchNatsResult = serde_json::from_str(&serialized).unwrap();
+        let groups = deserialized.groups.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(
+            groups[0].original_document_id,
+            result.groups.as_ref().unwrap()[0].original_document_id
+        );
+        assert_eq!(groups[0].hits[0].qdrant_point_id, "point-123");
+
+        let ungrouped = SemanticSearchNatsResult {
+            groups: None,
+            ..result
+        };
+        let serialized = serde_json::to_string(&ungrouped).unwrap();
+        assert!(!serialized.contains("groups"));
+    }
+
     #[test]
     fn test_semantic_search_api_response_serialization() {
         let response = SemanticSearchApiResponse {
-            search_request_id: generate_uuid(),
+            search_request_id: RequestId::generate(),
             results: vec![
                 SemanticSearchResultItem {
                     qdrant_point_id: "point-123".to_string(),
                     score: 0.5,
                     payload: QdrantPointPayload {
-                        original_document_id: "doc-123".to_string(),
+                        original_document_id: DocumentId::generate(),
                         source_url: "http://example.com".to_string(),
                         sentence_text: "This is a test sentence.".to_string(),
                         sentence_order: 1,
                         model_name: "test-model-v1".to_string(),
                         processed_at_ms: current_timestamp_ms(),
+                        tenant_id: None,
+                        metadata: DocumentMetadata::default(),
+                        chunk_index: None,
+                        total_chunks: None,
+                        parent_document_id: None,
                     },
                 },
                 SemanticSearchResultItem {
                     qdrant_point_id: "point-456".to_string(),
                     score: 0.4,
                     payload: QdrantPointPayload {
-                        original_document_id: "doc-456".to_string(),
+                        original_document_id: DocumentId::generate(),
                         source_url: "http://example.com".to_string(),
                         sentence_text: "This is another test sentence.".to_string(),
                         sentence_order: 2,
                         model_name: "test-model-v1".to_string(),
                         processed_at_ms: current_timestamp_ms(),
+                        tenant_id: None,
+                        metadata: DocumentMetadata::default(),
+                        chunk_index: None,
+                        total_chunks: None,
+                        parent_document_id: None,
                     },
                 },
             ],
+            groups: None,
             error_message: None,
         };
 
@@ -534,4 +3270,759 @@ mod tests {
             deserialized.results[1].payload.processed_at_ms
         );
     }
+
+    #[test]
+    fn test_vector_scroll_task_serialization() {
+        let task = VectorScrollTask {
+            request_id: RequestId::generate(),
+            model_name: None,
+            original_document_id: Some(DocumentId::generate()),
+            source_url: None,
+            limit: 100,
+            offset: Some("point-123".to_string()),
+            tenant_id: Some("tenant-a".to_string()),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: VectorScrollTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.original_document_id, deserialized.original_document_id);
+        assert_eq!(task.limit, deserialized.limit);
+        assert_eq!(task.offset, deserialized.offset);
+    }
+
+    #[test]
+    fn test_vector_payload_update_task_serialization() {
+        let mut payload = serde_json::Map::new();
+        payload.insert(
+            "source_url".to_string(),
+            serde_json::Value::from("https://example.com/corrected"),
+        );
+        payload.insert("language".to_string(), serde_json::Value::from("en"));
+        let task = VectorPayloadUpdateTask {
+            request_id: RequestId::generate(),
+            model_name: None,
+            original_document_id: Some(DocumentId::generate()),
+            source_url: None,
+            tenant_id: None,
+            payload,
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: VectorPayloadUpdateTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.original_document_id, deserialized.original_document_id);
+        assert_eq!(task.payload, deserialized.payload);
+
+        let minimal: VectorPayloadUpdateTask = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","source_url":"http://example.com","payload":{"title":"Example"}}"#,
+        )
+        .unwrap();
+        assert!(minimal.original_document_id.is_none());
+        assert_eq!(minimal.payload["title"], "Example");
+    }
+
+    #[test]
+    fn test_vector_scroll_result_serialization() {
+        let result = VectorScrollResult {
+            request_id: RequestId::generate(),
+            points: vec![StoredPointItem {
+                qdrant_point_id: "point-123".to_string(),
+                payload: QdrantPointPayload {
+                    original_document_id: DocumentId::generate(),
+                    source_url: "http://example.com".to_string(),
+                    sentence_text: "This is a test sentence.".to_string(),
+                    sentence_order: 0,
+                    model_name: "test-model-v1".to_string(),
+                    processed_at_ms: current_timestamp_ms(),
+                    tenant_id: None,
+                    metadata: DocumentMetadata::default(),
+                    chunk_index: None,
+                    total_chunks: None,
+                    parent_document_id: None,
+                },
+            }],
+            next_offset: Some("point-456".to_string()),
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: VectorScrollResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert_eq!(deserialized.points.len(), 1);
+        assert_eq!(
+            result.points[0].qdrant_point_id,
+            deserialized.points[0].qdrant_point_id
+        );
+        assert_eq!(
+            result.points[0].payload.sentence_text,
+            deserialized.points[0].payload.sentence_text
+        );
+        assert_eq!(result.next_offset, deserialized.next_offset);
+    }
+
+    #[test]
+    fn test_vector_count_task_serialization() {
+        let task = VectorCountTask {
+            request_id: RequestId::generate(),
+            model_name: None,
+            original_document_id: None,
+            source_url: Some("http://example.com".to_string()),
+            tenant_id: Some("tenant-a".to_string()),
+            exact: true,
+            group_by: Some("original_document_id".to_string()),
+            group_limit: Some(10),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: VectorCountTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.source_url, deserialized.source_url);
+        assert!(deserialized.exact);
+        assert_eq!(task.group_by, deserialized.group_by);
+        assert_eq!(task.group_limit, deserialized.group_limit);
+
+        let minimal: VectorCountTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"}"#)
+                .unwrap();
+        assert!(!minimal.exact);
+        assert!(minimal.group_by.is_none());
+    }
+
+    #[test]
+    fn test_vector_count_result_serialization() {
+        let result = VectorCountResult {
+            request_id: RequestId::generate(),
+            count: 42,
+            exact: false,
+            groups: Some(vec![VectorCountGroup {
+                value: "http://example.com".to_string(),
+                count: 40,
+            }]),
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: VectorCountResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert_eq!(deserialized.count, 42);
+        let groups = deserialized.groups.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].value, "http://example.com");
+        assert_eq!(groups[0].count, 40);
+
+        let ungrouped = VectorCountResult {
+            groups: None,
+            ..result
+        };
+        let serialized = serde_json::to_string(&ungrouped).unwrap();
+        assert!(!serialized.contains("groups"));
+    }
+
+    #[test]
+    fn test_dead_letter_message_serialization() {
+        let dead_letter = DeadLetterMessage {
+            original_subject: "data.text.with_embeddings".to_string(),
+            payload: TextWithEmbeddingsMessage {
+                original_id: DocumentId::generate(),
+                source_url: "http://example.com".to_string(),
+                embeddings_data: vec![SentenceEmbedding {
+                    sentence_text: "Sentence three.".to_string(),
+                    embedding: vec![0.5, 0.6],
+                    sparse_embedding: None,
+                    sentence_order: Some(2),
+                }],
+                model_name: "test-model-v1".to_string(),
+                timestamp_ms: current_timestamp_ms(),
+                tenant_id: None,
+                metadata: DocumentMetadata::default(),
+                chunk_index: None,
+                total_chunks: None,
+                parent_document_id: None,
+            },
+            error_message: "Qdrant unavailable".to_string(),
+            attempts: 4,
+            dead_lettered_at_ms: current_timestamp_ms(),
+        };
+        let serialized = serde_json::to_string(&dead_letter).unwrap();
+        let deserialized: DeadLetterMessage<TextWithEmbeddingsMessage> =
+            serde_json::from_str(&serialized).unwrap();
+        assert_eq!(dead_letter.original_subject, deserialized.original_subject);
+        assert_eq!(
+            dead_letter.payload.original_id,
+            deserialized.payload.original_id
+        );
+        assert_eq!(
+            deserialized.payload.embeddings_data[0].sentence_order,
+            Some(2)
+        );
+        assert_eq!(dead_letter.error_message, deserialized.error_message);
+        assert_eq!(dead_letter.attempts, deserialized.attempts);
+    }
+
+    #[test]
+    fn test_dead_letter_subject_round_trip() {
+        let subject = dead_letter_subject("vector_memory_service", "data.text.with_embeddings");
+        assert_eq!(
+            subject,
+            "dlq.vector_memory_service.data.text.with_embeddings"
+        );
+        assert_eq!(
+            parse_dead_letter_subject(&subject),
+            Some(("vector_memory_service", "data.text.with_embeddings"))
+        );
+        assert_eq!(parse_dead_letter_subject("dlq.perception_service"), None);
+        assert_eq!(parse_dead_letter_subject("dlqx.a.b"), None);
+    }
+
+    #[test]
+    fn test_replay_message_from_dead_letters() {
+        let raw = RawTextMessage::new("http://example.com".to_string(), "text".to_string());
+        let cause = Envelope::new("perception_service", ());
+        let typed = cause.follow_up(
+            "preprocessing_service",
+            DeadLetterMessage::new("data.raw_text.discovered", &raw, "failed", 1),
+        );
+        let typed: Envelope<AnyDeadLetter> =
+            Envelope::from_slice(&typed.to_vec().unwrap()).unwrap();
+        let replay = ReplayMessage::from_dead_letter(&typed, "api_service").unwrap();
+        assert_eq!(replay.subject, "data.raw_text.discovered");
+        assert!(replay.headers.is_empty());
+        let replayed = Envelope::<RawTextMessage>::from_slice(&replay.body).unwrap();
+        assert_eq!(replayed.correlation_id, cause.correlation_id);
+        assert_eq!(replayed.payload.id, raw.id);
+
+        let body = [0u8, 159, 146, 150];
+        let undecoded = UndecodedPayload::new(&body)
+            .with_header("Content-Encoding", Some("zstd"))
+            .with_header("Content-Type", None);
+        let poison = Envelope::new(
+            "vector_memory_service",
+            DeadLetterMessage::new("data.text.with_embeddings", undecoded, "bad body", 1),
+        );
+        let poison: Envelope<AnyDeadLetter> =
+            Envelope::from_slice(&poison.to_vec().unwrap()).unwrap();
+        let replay = ReplayMessage::from_dead_letter(&poison, "api_service").unwrap();
+        assert_eq!(replay.subject, "data.text.with_embeddings");
+        assert_eq!(replay.headers.len(), 1);
+        assert_eq!(replay.headers["Content-Encoding"], "zstd");
+        assert_eq!(replay.body, body);
+    }
+
+    #[test]
+    fn test_embeddings_rejected_event_serialization() {
+        let event = EmbeddingsRejectedEvent {
+            original_id: DocumentId::generate(),
+            source_url: "http://example.com".to_string(),
+            model_name: "test-model-v1".to_string(),
+            collection_name: "symbiont_document_embeddings__test_model_v1".to_string(),
+            expected_dimension: 768,
+            mismatches: vec![EmbeddingDimensionMismatch {
+                sentence_index: 1,
+                dimension: 384,
+            }],
+            stored_count: 2,
+            error_message: "1 embedding(s) do not match the collection dimension 768".to_string(),
+            timestamp_ms: current_timestamp_ms(),
+        };
+        let serialized = serde_json::to_string(&event).unwrap();
+        let deserialized: EmbeddingsRejectedEvent = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(event.original_id, deserialized.original_id);
+        assert_eq!(event.expected_dimension, deserialized.expected_dimension);
+        assert_eq!(deserialized.mismatches.len(), 1);
+        assert_eq!(deserialized.mismatches[0].sentence_index, 1);
+        assert_eq!(deserialized.mismatches[0].dimension, 384);
+        assert_eq!(event.stored_count, deserialized.stored_count);
+    }
+
+    #[test]
+    fn test_service_health_result_serialization() {
+        let result = ServiceHealthResult {
+            service: "vector_memory_service".to_string(),
+            status: HealthStatus::Ok,
+            latency_ms: 3,
+            error_message: None,
+            timestamp_ms: current_timestamp_ms(),
+            checks: Vec::new(),
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: ServiceHealthResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.service, deserialized.service);
+        assert_eq!(result.status, deserialized.status);
+        assert_eq!(result.latency_ms, deserialized.latency_ms);
+        assert!(deserialized.error_message.is_none());
+        assert!(serialized.contains(r#""status":"ok""#));
+    }
+
+    #[test]
+    fn test_service_health_is_its_worst_check() {
+        use std::time::Duration;
+
+        assert_eq!(
+            health_subject("vector_memory_service"),
+            "health.vector_memory"
+        );
+        assert_eq!(health_subject("api"), "health.api");
+
+        let checks = vec![
+            DependencyCheck::timed("nats", Duration::from_millis(2), Ok(())),
+            DependencyCheck::timed("qdrant", Duration::from_secs(2), Ok(())),
+            DependencyCheck::passed("model", Some("all-MiniLM-L6-v2".to_string())),
+        ];
+        let result =
+            ServiceHealthResult::from_checks("vector_memory_service", checks, Duration::ZERO);
+        assert_eq!(result.status, HealthStatus::Degraded);
+        assert!(result.is_available());
+        assert!(result.error_message.is_none());
+
+        let checks = vec![
+            DependencyCheck::timed("nats", Duration::from_millis(2), Ok(())),
+            DependencyCheck::failed("neo4j", "connection refused"),
+        ];
+        let result = ServiceHealthResult::from_checks(
+            "knowledge_graph_service",
+            checks,
+            Duration::from_millis(5),
+        );
+        assert_eq!(result.status, HealthStatus::Unavailable);
+        assert!(!result.is_available());
+        assert_eq!(
+            result.error_message.as_deref(),
+            Some("neo4j: connection refused")
+        );
+        assert_eq!(result.latency_ms, 5);
+
+        let legacy: ServiceHealthResult = serde_json::from_str(
+            r#"{"service":"vector_memory_service","status":"degraded","latency_ms":1200,"timestamp_ms":0}"#,
+        )
+        .unwrap();
+        assert_eq!(legacy.status, HealthStatus::Degraded);
+        assert!(legacy.checks.is_empty());
+    }
+
+    #[test]
+    fn test_reembed_text_task_serialization() {
+        let task = ReembedTextTask {
+            reindex_id: RequestId::generate(),
+            original_id: DocumentId::generate(),
+            source_url: "http://example.com".to_string(),
+            model_name: "test-model-v2".to_string(),
+            sentences: vec![
+                ReembedSentence {
+                    sentence_text: "First.".to_string(),
+                    sentence_order: 0,
+                },
+                ReembedSentence {
+                    sentence_text: "Third.".to_string(),
+                    sentence_order: 2,
+                },
+            ],
+            processed_at_ms: 1_700_000_000_000,
+            tenant_id: None,
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: ReembedTextTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.reindex_id, deserialized.reindex_id);
+        assert_eq!(task.model_name, deserialized.model_name);
+        assert_eq!(deserialized.sentences.len(), 2);
+        assert_eq!(deserialized.sentences[1].sentence_order, 2);
+        assert_eq!(task.processed_at_ms, deserialized.processed_at_ms);
+    }
+
+    #[test]
+    fn test_vector_reindex_task_serialization() {
+        let json = r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","target_model_name":"test-model-v2","target_vector_dim":384}"#;
+        let task: VectorReindexTask = serde_json::from_str(json).unwrap();
+        assert!(task.source_model_name.is_none());
+        assert_eq!(task.target_model_name, "test-model-v2");
+        assert_eq!(task.target_vector_dim, 384);
+
+        let result = VectorReindexResult {
+            request_id: task.request_id,
+            status: "completed".to_string(),
+            source_collection: "symbiont_document_embeddings__test_model_v1".to_string(),
+            target_collection: "symbiont_document_embeddings__test_model_v2".to_string(),
+            documents_requested: 10,
+            source_points: 120,
+            target_points: 120,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: VectorReindexResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.status, deserialized.status);
+        assert_eq!(result.target_collection, deserialized.target_collection);
+        assert_eq!(result.target_points, deserialized.target_points);
+    }
+
+    #[test]
+    fn test_vector_snapshot_task_serialization() {
+        let task = VectorSnapshotTask {
+            request_id: RequestId::generate(),
+            model_name: Some("test-model-v1".to_string()),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: VectorSnapshotTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.model_name, deserialized.model_name);
+
+        let all_collections: VectorSnapshotTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"}"#)
+                .unwrap();
+        assert!(all_collections.model_name.is_none());
+    }
+
+    #[test]
+    fn test_vector_snapshot_result_serialization() {
+        let result = VectorSnapshotResult {
+            request_id: RequestId::generate(),
+            snapshots: vec![VectorSnapshotInfo {
+                collection_name: "symbiont_document_embeddings__test_model".to_string(),
+                snapshot_name: "snapshot-2024-01-01.snapshot".to_string(),
+                size_bytes: 1024,
+                created_at_ms: Some(current_timestamp_ms()),
+                checksum: Some("abc123".to_string()),
+                location: "/collections/symbiont_document_embeddings__test_model/snapshots/snapshot-2024-01-01.snapshot".to_string(),
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: VectorSnapshotResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert_eq!(deserialized.snapshots.len(), 1);
+        assert_eq!(
+            result.snapshots[0].snapshot_name,
+            deserialized.snapshots[0].snapshot_name
+        );
+        assert_eq!(
+            result.snapshots[0].size_bytes,
+            deserialized.snapshots[0].size_bytes
+        );
+        assert_eq!(
+            result.snapshots[0].location,
+            deserialized.snapshots[0].location
+        );
+    }
+
+    #[test]
+    fn test_vector_stats_result_serialization() {
+        let result = VectorStatsResult {
+            request_id: RequestId::generate(),
+            collections: vec![VectorCollectionStats {
+                collection_name: "symbiont_document_embeddings__test_model".to_string(),
+                status: "green".to_string(),
+                optimizer_ok: true,
+                optimizer_error: None,
+                points_count: 42,
+                indexed_vectors_count: 40,
+                segments_count: 2,
+                vector_size: Some(768),
+                vectors_on_disk: Some(true),
+                payload_on_disk: Some(true),
+                estimated_vector_bytes: Some(42 * 768 * 4),
+                payload_indexes: vec![PayloadIndexStats {
+                    field_name: "original_document_id".to_string(),
+                    data_type: "Keyword".to_string(),
+                    indexed_points: 42,
+                }],
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: VectorStatsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert_eq!(deserialized.collections.len(), 1);
+        let stats = &deserialized.collections[0];
+        assert_eq!(stats.status, "green");
+        assert_eq!(stats.points_count, 42);
+        assert_eq!(stats.vector_size, Some(768));
+        assert_eq!(stats.payload_indexes[0].field_name, "original_document_id");
+    }
+
+    #[test]
+    fn test_recommend_nats_task_serialization() {
+        let task = RecommendNatsTask {
+            request_id: RequestId::generate(),
+            top_k: 5,
+            model_name: None,
+            positive_point_ids: vec!["point-123".to_string()],
+            positive_document_id: Some(DocumentId::generate()),
+            negative_point_ids: vec!["point-456".to_string()],
+            tenant_id: None,
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: RecommendNatsTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.request_id, deserialized.request_id);
+        assert_eq!(task.top_k, deserialized.top_k);
+        assert_eq!(task.positive_point_ids, deserialized.positive_point_ids);
+        assert_eq!(task.positive_document_id, deserialized.positive_document_id);
+        assert_eq!(task.negative_point_ids, deserialized.negative_point_ids);
+    }
+
+    #[test]
+    fn test_recommend_api_request_by_document_only() {
+        let json = r#"{"top_k":3,"positive_document_id":"2c8e7ade-3f4a-4b5c-8d6e-7f8091a2b3c4"}"#;
+        let deserialized: RecommendApiRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(deserialized.top_k, 3);
+        assert_eq!(
+            deserialized.positive_document_id.map(|id| id.to_string()),
+            Some("2c8e7ade-3f4a-4b5c-8d6e-7f8091a2b3c4".to_string())
+        );
+        assert!(deserialized.positive_point_ids.is_empty());
+        assert!(deserialized.negative_point_ids.is_empty());
+        assert!(deserialized.model_name.is_none());
+    }
+
+    #[test]
+    fn test_graph_export_task_serialization() {
+        let task = GraphExportTask {
+            request_id: RequestId::generate(),
+            original_id: Some(DocumentId::generate()),
+            format: GraphExportFormat::Graphml,
+            cursor: Some(500),
+            limit: Some(500),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        assert!(serialized.contains(r#""format":"graphml""#));
+        let deserialized: GraphExportTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.original_id, deserialized.original_id);
+        assert_eq!(deserialized.format, GraphExportFormat::Graphml);
+        assert_eq!(deserialized.cursor, Some(500));
+
+        let minimal: GraphExportTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"}"#)
+                .unwrap();
+        assert!(minimal.original_id.is_none());
+        assert_eq!(minimal.format, GraphExportFormat::Json);
+        assert!(minimal.cursor.is_none());
+    }
+
+    #[test]
+    fn test_graph_export_result_serialization() {
+        let mut properties = serde_json::Map::new();
+        properties.insert("original_id".to_string(), serde_json::Value::from("doc-1"));
+        let result = GraphExportResult {
+            request_id: RequestId::generate(),
+            format: GraphExportFormat::Json,
+            nodes: vec![
+                GraphExportNode {
+                    id: "1".to_string(),
+                    labels: vec!["Document".to_string()],
+                    properties,
+                },
+                GraphExportNode {
+                    id: "2".to_string(),
+                    labels: vec!["Sentence".to_string()],
+                    properties: serde_json::Map::new(),
+                },
+            ],
+            edges: vec![GraphExportEdge {
+                id: "10".to_string(),
+                source: "1".to_string(),
+                target: "2".to_string(),
+                rel_type: "HAS_SENTENCE".to_string(),
+                properties: serde_json::Map::new(),
+            }],
+            graphml: None,
+            next_cursor: Some(1),
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        assert!(!serialized.contains("graphml"));
+        let deserialized: GraphExportResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.nodes.len(), 2);
+        assert_eq!(deserialized.nodes[0].properties["original_id"], "doc-1");
+        assert_eq!(deserialized.edges[0].rel_type, "HAS_SENTENCE");
+        assert_eq!(deserialized.next_cursor, Some(1));
+    }
+
+    #[test]
+    fn test_graph_delete_document_serialization() {
+        let task = GraphDeleteDocumentTask {
+            request_id: RequestId::generate(),
+            original_id: DocumentId::generate(),
+        };
+        let serialized = serde_json::to_string(&task).unwrap();
+        let deserialized: GraphDeleteDocumentTask = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(task.original_id, deserialized.original_id);
+
+        let result = GraphDeleteDocumentResult {
+            request_id: task.request_id,
+            original_id: task.original_id,
+            deleted: true,
+            sentences_deleted: 12,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GraphDeleteDocumentResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert!(deserialized.deleted);
+        assert_eq!(deserialized.sentences_deleted, 12);
+    }
+
+    #[test]
+    fn test_keyword_search_serialization() {
+        let task: KeywordSearchTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","query_text":"neural graph","top_k":5}"#)
+                .unwrap();
+        assert_eq!(task.query_text, "neural graph");
+        assert!(task.original_id.is_none());
+
+        let result = KeywordSearchResult {
+            request_id: task.request_id,
+            results: vec![KeywordSearchResultItem {
+                original_id: DocumentId::generate(),
+                source_url: "http://example.com".to_string(),
+                sentence_text: "A neural graph.".to_string(),
+                sentence_order: 3,
+                score: 1.5,
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: KeywordSearchResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.results.len(), 1);
+        assert_eq!(deserialized.results[0].sentence_order, 3);
+        assert_eq!(deserialized.results[0].score, 1.5);
+    }
+
+    #[test]
+    fn test_related_documents_serialization() {
+        let task: RelatedDocumentsTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","original_id":"1b9d6bcd-bbfd-4b2d-9b5d-ab8dfbbd4bed","top_k":5}"#)
+                .unwrap();
+        assert_eq!(
+            task.original_id.to_string(),
+            "1b9d6bcd-bbfd-4b2d-9b5d-ab8dfbbd4bed"
+        );
+        assert!(task.min_shared_terms.is_none());
+
+        let result = RelatedDocumentsResult {
+            request_id: task.request_id,
+            original_id: task.original_id,
+            documents: vec![RelatedDocument {
+                original_id: DocumentId::generate(),
+                source_url: "http://example.com/2".to_string(),
+                score: 0.42,
+                shared_tokens: 7,
+                shared_lemmas: 2,
+                same_domain: true,
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: RelatedDocumentsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.documents.len(), 1);
+        assert_eq!(deserialized.documents[0].shared_tokens, 7);
+        assert!(deserialized.documents[0].same_domain);
+    }
+
+    #[test]
+    fn test_graph_cypher_serialization() {
+        let task: GraphCypherTask = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","query":"MATCH (d:Document {original_id: $id}) RETURN d.source_url AS url","params":{"id":"doc-1"}}"#,
+        )
+        .unwrap();
+        assert_eq!(task.params["id"], "doc-1");
+        assert!(task.max_rows.is_none());
+        assert!(task.admin_token.is_none());
+
+        let mut row = serde_json::Map::new();
+        row.insert("url".to_string(), serde_json::json!("http://example.com"));
+        let result = GraphCypherResult {
+            request_id: task.request_id,
+            rows: vec![row],
+            truncated: false,
+            duration_ms: 12,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GraphCypherResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.rows.len(), 1);
+        assert_eq!(deserialized.rows[0]["url"], "http://example.com");
+        assert!(!deserialized.truncated);
+    }
+
+    #[test]
+    fn test_graph_terms_serialization() {
+        let task: GraphTermsTask = serde_json::from_str(
+            r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b","top_k":5}"#,
+        )
+        .unwrap();
+        assert_eq!(task.kind, GraphTermKind::Keyword);
+        assert_eq!(task.original_id, None);
+
+        let result = GraphTermsResult {
+            request_id: RequestId::generate(),
+            terms: vec![GraphTerm {
+                text: "Neo4j".to_string(),
+                score: 0.42,
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GraphTermsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.terms.len(), 1);
+        assert_eq!(deserialized.terms[0].text, "Neo4j");
+    }
+
+    #[test]
+    fn test_graph_stats_serialization() {
+        let task: GraphStatsTask =
+            serde_json::from_str(r#"{"request_id":"6f1c2b3a-4d5e-4f60-8a7b-9c0d1e2f3a4b"}"#)
+                .unwrap();
+        assert!(task.max_domains.is_none());
+
+        let result = GraphStatsResult {
+            request_id: task.request_id,
+            node_count: 120,
+            relationship_count: 340,
+            nodes_by_label: vec![GraphLabelCount {
+                label: "Document".to_string(),
+                count: 4,
+            }],
+            relationships_by_type: vec![GraphRelationshipTypeCount {
+                rel_type: "HAS_SENTENCE".to_string(),
+                count: 57,
+            }],
+            documents_by_domain: vec![DomainDocumentCount {
+                host: "example.com".to_string(),
+                documents: 3,
+            }],
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GraphStatsResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.node_count, 120);
+        assert_eq!(deserialized.nodes_by_label[0].label, "Document");
+        assert_eq!(deserialized.relationships_by_type[0].count, 57);
+        assert_eq!(deserialized.documents_by_domain[0].documents, 3);
+    }
+
+    #[test]
+    fn test_sentence_point_id_is_deterministic() {
+        let document_id = DocumentId::generate();
+        let id = sentence_point_id(document_id, 3);
+        assert_eq!(id, sentence_point_id(document_id, 3));
+        assert_ne!(id, sentence_point_id(document_id, 4));
+        assert_ne!(id, sentence_point_id(DocumentId::generate(), 3));
+        assert_eq!(uuid::Uuid::parse_str(&id).unwrap().get_version_num(), 5);
+    }
+
+    #[test]
+    fn test_graph_analysis_result_serialization() {
+        let result = GraphAnalysisResult {
+            request_id: RequestId::generate(),
+            nodes_ranked: 1200,
+            pagerank_iterations: 20,
+            community_count: 14,
+            modularity: 0.61,
+            duration_ms: 5300,
+            error_message: None,
+        };
+        let serialized = serde_json::to_string(&result).unwrap();
+        let deserialized: GraphAnalysisResult = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(result.request_id, deserialized.request_id);
+        assert_eq!(deserialized.nodes_ranked, 1200);
+        assert_eq!(deserialized.community_count, 14);
+        assert_eq!(deserialized.modularity, 0.61);
+    }
 }
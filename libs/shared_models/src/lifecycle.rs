@@ -0,0 +1,408 @@
+//! A submitted document's way through the pipeline, as orchestrator_service follows it from
+//! the stages' [`TaskStatusChangedMessage`]s and [`PipelineErrorMessage`]s. The document's
+//! [`DocumentState`] is derived from the latest status of every stage, so events arriving
+//! out of order, or a stage redone after a replay, still leave the right state.
+
+use crate::{
+    DocumentId, PipelineErrorMessage, PipelineStage, RequestId, TaskId, TaskStatus,
+    TaskStatusChangedMessage,
+};
+use serde::{Deserialize, Serialize};
+
+/// How far a document has come, in pipeline order.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentState {
+    /// Submitted, but not scraped yet.
+    Received,
+    /// Fetched and extracted by perception_service.
+    Scraped,
+    /// Split and embedded by preprocessing_service.
+    Embedded,
+    /// Stored by vector_memory_service.
+    Indexed,
+    /// Stored by both vector_memory_service and knowledge_graph_service; the document is
+    /// done.
+    Graphed,
+    /// A stage gave up on the document.
+    Failed,
+}
+
+impl DocumentState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DocumentState::Received => "received",
+            DocumentState::Scraped => "scraped",
+            DocumentState::Embedded => "embedded",
+            DocumentState::Indexed => "indexed",
+            DocumentState::Graphed => "graphed",
+            DocumentState::Failed => "failed",
+        }
+    }
+
+    /// Whether no further stage is expected to work on the document.
+    pub fn is_final(self) -> bool {
+        matches!(self, DocumentState::Graphed | DocumentState::Failed)
+    }
+}
+
+/// The latest status one stage reported for a document.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StageProgress {
+    pub stage: PipelineStage,
+    pub status: TaskStatus,
+    /// Why the stage failed, when it did.
+    #[serde(default)]
+    pub detail: Option<String>,
+    pub updated_at_ms: u64,
+}
+
+/// Everything known about one submission, keyed by the task id api_service returned for it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct DocumentLifecycle {
+    pub task_id: TaskId,
+    /// Document id, once perception_service has assigned one.
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    /// URL the document was submitted with, when the submission itself was seen.
+    #[serde(default)]
+    pub source_url: Option<String>,
+    /// Tenant the document was submitted for, if any.
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    pub state: DocumentState,
+    /// One entry per stage that reported on the document, in pipeline order.
+    pub stages: Vec<StageProgress>,
+    pub received_at_ms: u64,
+    /// When a stage last reported on the document.
+    pub updated_at_ms: u64,
+    /// When the document was reported stuck, until a stage reports on it again.
+    #[serde(default)]
+    pub stuck_since_ms: Option<u64>,
+}
+
+impl DocumentLifecycle {
+    /// A submission first seen at `at_ms`.
+    pub fn received(task_id: TaskId, source_url: Option<String>, at_ms: u64) -> Self {
+        DocumentLifecycle {
+            task_id,
+            original_id: None,
+            source_url,
+            tenant_id: None,
+            state: DocumentState::Received,
+            stages: Vec::new(),
+            received_at_ms: at_ms,
+            updated_at_ms: at_ms,
+            stuck_since_ms: None,
+        }
+    }
+
+    /// The latest status `stage` reported, if any.
+    pub fn stage(&self, stage: PipelineStage) -> Option<&StageProgress> {
+        self.stages.iter().find(|progress| progress.stage == stage)
+    }
+
+    /// Applies a stage's status change. A change older than the one already recorded for
+    /// the stage is ignored; the document id is taken either way.
+    pub fn record_status(&mut self, status: &TaskStatusChangedMessage) {
+        if self.original_id.is_none() {
+            self.original_id = status.original_id;
+        }
+        self.record(StageProgress {
+            stage: status.stage,
+            status: status.status,
+            detail: status.detail.clone(),
+            updated_at_ms: status.timestamp_ms,
+        });
+    }
+
+    /// Applies a stage giving up on the document, like a `failed` status change.
+    pub fn record_error(&mut self, error: &PipelineErrorMessage) {
+        if self.original_id.is_none() {
+            self.original_id = error.original_id;
+        }
+        self.record(StageProgress {
+            stage: error.stage,
+            status: TaskStatus::Failed,
+            detail: Some(error.message.clone()),
+            updated_at_ms: error.timestamp_ms,
+        });
+    }
+
+    fn record(&mut self, progress: StageProgress) {
+        match self
+            .stages
+            .iter_mut()
+            .find(|recorded| recorded.stage == progress.stage)
+        {
+            Some(recorded) if recorded.updated_at_ms > progress.updated_at_ms => return,
+            // A failure reported by both a status change and an error message keeps the
+            // first detail.
+            Some(recorded)
+                if recorded.status == TaskStatus::Failed
+                    && progress.status == TaskStatus::Failed =>
+            {
+                recorded.updated_at_ms = progress.updated_at_ms;
+            }
+            Some(recorded) => *recorded = progress,
+            None => {
+                self.stages.push(progress);
+                self.stages
+                    .sort_by_key(|recorded| stage_order(recorded.stage));
+            }
+        }
+        self.updated_at_ms = self
+            .stages
+            .iter()
+            .map(|recorded| recorded.updated_at_ms)
+            .max()
+            .unwrap_or(self.received_at_ms)
+            .max(self.updated_at_ms);
+        self.stuck_since_ms = None;
+        self.state = self.derive_state();
+    }
+
+    fn derive_state(&self) -> DocumentState {
+        let completed = |stage| {
+            self.stage(stage)
+                .is_some_and(|progress| progress.status == TaskStatus::Completed)
+        };
+        if self
+            .stages
+            .iter()
+            .any(|progress| progress.status == TaskStatus::Failed)
+        {
+            DocumentState::Failed
+        } else if completed(PipelineStage::Storage) && completed(PipelineStage::KnowledgeGraph) {
+            DocumentState::Graphed
+        } else if completed(PipelineStage::Storage) {
+            DocumentState::Indexed
+        } else if completed(PipelineStage::Preprocessing) {
+            DocumentState::Embedded
+        } else if completed(PipelineStage::Scraping) {
+            DocumentState::Scraped
+        } else {
+            DocumentState::Received
+        }
+    }
+
+    /// Marks the document stuck when it is not done and no stage has reported on it for
+    /// longer than `timeout_ms`. Returns `true` only the first time, so it is reported once
+    /// per stall.
+    pub fn mark_if_stuck(&mut self, now_ms: u64, timeout_ms: u64) -> bool {
+        if self.state.is_final()
+            || self.stuck_since_ms.is_some()
+            || now_ms.saturating_sub(self.updated_at_ms) <= timeout_ms
+        {
+            return false;
+        }
+        self.stuck_since_ms = Some(now_ms);
+        true
+    }
+}
+
+fn stage_order(stage: PipelineStage) -> u8 {
+    match stage {
+        PipelineStage::Scraping => 0,
+        PipelineStage::Preprocessing => 1,
+        PipelineStage::Storage => 2,
+        PipelineStage::KnowledgeGraph => 3,
+        PipelineStage::Generation => 4,
+    }
+}
+
+/// Published by orchestrator_service when a document has not moved for longer than its
+/// stage timeout.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocumentStuckAlert {
+    pub task_id: TaskId,
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    #[serde(default)]
+    pub source_url: Option<String>,
+    pub state: DocumentState,
+    /// Time since a stage last reported on the document.
+    pub stalled_for_ms: u64,
+    pub timestamp_ms: u64,
+}
+
+/// Asks orchestrator_service where a submission is, by task id or by document id.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocumentStatusTask {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub task_id: Option<TaskId>,
+    #[serde(default)]
+    pub original_id: Option<DocumentId>,
+    /// Also return every status change and error recorded for the submission.
+    #[serde(default)]
+    pub include_history: bool,
+}
+
+/// `document` is `None` when the orchestrator knows nothing of the submission, e.g. because
+/// it finished longer ago than the orchestrator keeps documents.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocumentStatusResult {
+    pub request_id: RequestId,
+    #[serde(default)]
+    pub document: Option<DocumentLifecycle>,
+    /// Every status change and error of the submission, oldest first, when asked for and
+    /// the orchestrator keeps a task store.
+    #[serde(default)]
+    pub history: Vec<StageProgress>,
+    #[serde(default)]
+    pub error_message: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Envelope, PipelineErrorKind};
+
+    fn status_at(
+        cause: &Envelope<()>,
+        stage: PipelineStage,
+        status: TaskStatus,
+        at_ms: u64,
+    ) -> TaskStatusChangedMessage {
+        TaskStatusChangedMessage {
+            timestamp_ms: at_ms,
+            ..TaskStatusChangedMessage::new(cause, stage, status)
+        }
+    }
+
+    #[test]
+    fn test_state_follows_completed_stages() {
+        let cause = Envelope::new("test", ());
+        let mut document = DocumentLifecycle::received(cause.task_id(), None, 0);
+        let original_id = DocumentId::generate();
+
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Scraping,
+            TaskStatus::Started,
+            1,
+        ));
+        assert_eq!(document.state, DocumentState::Received);
+        document.record_status(
+            &status_at(&cause, PipelineStage::Scraping, TaskStatus::Completed, 2)
+                .with_original_id(original_id),
+        );
+        assert_eq!(document.state, DocumentState::Scraped);
+        assert_eq!(document.original_id, Some(original_id));
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Preprocessing,
+            TaskStatus::Completed,
+            3,
+        ));
+        assert_eq!(document.state, DocumentState::Embedded);
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::KnowledgeGraph,
+            TaskStatus::Completed,
+            4,
+        ));
+        assert_eq!(document.state, DocumentState::Embedded);
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Storage,
+            TaskStatus::Completed,
+            5,
+        ));
+        assert_eq!(document.state, DocumentState::Graphed);
+        assert_eq!(document.updated_at_ms, 5);
+
+        let order: Vec<_> = document
+            .stages
+            .iter()
+            .map(|progress| progress.stage)
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                PipelineStage::Scraping,
+                PipelineStage::Preprocessing,
+                PipelineStage::Storage,
+                PipelineStage::KnowledgeGraph
+            ]
+        );
+    }
+
+    #[test]
+    fn test_late_events_do_not_roll_a_stage_back() {
+        let cause = Envelope::new("test", ());
+        let mut document = DocumentLifecycle::received(cause.task_id(), None, 0);
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Scraping,
+            TaskStatus::Completed,
+            5,
+        ));
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Scraping,
+            TaskStatus::Started,
+            4,
+        ));
+        assert_eq!(
+            document.stage(PipelineStage::Scraping).unwrap().status,
+            TaskStatus::Completed
+        );
+        assert_eq!(document.state, DocumentState::Scraped);
+    }
+
+    #[test]
+    fn test_errors_fail_the_document_until_the_stage_is_redone() {
+        let cause = Envelope::new("test", ());
+        let mut document = DocumentLifecycle::received(cause.task_id(), None, 0);
+        document.record_status(
+            &status_at(&cause, PipelineStage::Storage, TaskStatus::Failed, 2)
+                .with_detail("collection missing"),
+        );
+        let error = PipelineErrorMessage {
+            timestamp_ms: 3,
+            ..PipelineErrorMessage::new(
+                PipelineStage::Storage,
+                PipelineErrorKind::Storage,
+                "upsert failed",
+            )
+        };
+        document.record_error(&error);
+        assert_eq!(document.state, DocumentState::Failed);
+        let storage = document.stage(PipelineStage::Storage).unwrap();
+        assert_eq!(storage.detail.as_deref(), Some("collection missing"));
+        assert_eq!(storage.updated_at_ms, 3);
+
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Storage,
+            TaskStatus::Completed,
+            9,
+        ));
+        assert_eq!(document.state, DocumentState::Indexed);
+    }
+
+    #[test]
+    fn test_stuck_documents_are_reported_once_per_stall() {
+        let cause = Envelope::new("test", ());
+        let mut document = DocumentLifecycle::received(cause.task_id(), None, 1_000);
+        assert!(!document.mark_if_stuck(1_500, 1_000));
+        assert!(document.mark_if_stuck(2_500, 1_000));
+        assert!(!document.mark_if_stuck(9_000, 1_000));
+        assert_eq!(document.stuck_since_ms, Some(2_500));
+
+        document.record_status(&status_at(
+            &cause,
+            PipelineStage::Scraping,
+            TaskStatus::Started,
+            9_000,
+        ));
+        assert_eq!(document.stuck_since_ms, None);
+        assert!(document.mark_if_stuck(10_500, 1_000));
+
+        document.state = DocumentState::Failed;
+        document.stuck_since_ms = None;
+        assert!(!document.mark_if_stuck(99_000, 1_000));
+    }
+}
@@ -0,0 +1,262 @@
+//! `Debug` and `Display` for the messages that carry whole documents or embedding vectors,
+//! short enough to log at debug level: text is cut to [`LOG_TEXT_CHARS`] characters and
+//! vectors are shown by length only, e.g. `[768 floats]`.
+
+use crate::{
+    GeneratedTextMessage, QueryEmbeddingResult, RawTextMessage, SentenceEmbedding, SparseVector,
+    TextWithEmbeddingsMessage, TokenizedTextMessage,
+};
+use std::fmt;
+
+/// Characters of a text field kept by the log-safe formatting.
+pub const LOG_TEXT_CHARS: usize = 120;
+
+/// Formats the first `max_chars` characters of a text, noting how many were cut.
+#[derive(Clone, Copy)]
+pub struct Truncated<'a> {
+    text: &'a str,
+    max_chars: usize,
+}
+
+impl<'a> Truncated<'a> {
+    pub fn new(text: &'a str, max_chars: usize) -> Self {
+        Truncated { text, max_chars }
+    }
+
+    fn split(&self) -> (&'a str, usize) {
+        match self.text.char_indices().nth(self.max_chars) {
+            Some((end, _)) => (&self.text[..end], self.text[end..].chars().count()),
+            None => (self.text, 0),
+        }
+    }
+}
+
+impl fmt::Display for Truncated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.split() {
+            (kept, 0) => f.write_str(kept),
+            (kept, cut) => write!(f, "{}… (+{} chars)", kept, cut),
+        }
+    }
+}
+
+impl fmt::Debug for Truncated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.split() {
+            (kept, 0) => write!(f, "{:?}", kept),
+            (kept, cut) => write!(f, "{:?}… (+{} chars)", kept, cut),
+        }
+    }
+}
+
+/// Formats a collection by its length alone, e.g. `[768 floats]`.
+#[derive(Clone, Copy)]
+pub struct Elided {
+    len: usize,
+    unit: &'static str,
+}
+
+impl Elided {
+    pub fn floats(values: &[f32]) -> Self {
+        Elided {
+            len: values.len(),
+            unit: "floats",
+        }
+    }
+
+    fn new(len: usize, unit: &'static str) -> Self {
+        Elided { len, unit }
+    }
+}
+
+impl fmt::Display for Elided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} {}]", self.len, self.unit)
+    }
+}
+
+impl fmt::Debug for Elided {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+fn truncated(text: &str) -> Truncated<'_> {
+    Truncated::new(text, LOG_TEXT_CHARS)
+}
+
+impl fmt::Debug for RawTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawTextMessage")
+            .field("id", &self.id)
+            .field("source_url", &self.source_url)
+            .field("raw_text", &truncated(&self.raw_text))
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("metadata", &self.metadata)
+            .field("chunk_index", &self.chunk_index)
+            .field("total_chunks", &self.total_chunks)
+            .field("parent_document_id", &self.parent_document_id)
+            .finish()
+    }
+}
+
+impl fmt::Display for RawTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "RawTextMessage {} from {} ({} chars): {:?}",
+            self.id,
+            self.source_url,
+            self.raw_text.chars().count(),
+            truncated(&self.raw_text)
+        )
+    }
+}
+
+impl fmt::Debug for TokenizedTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenizedTextMessage")
+            .field("original_id", &self.original_id)
+            .field("source_url", &self.source_url)
+            .field("tokens", &Elided::new(self.tokens.len(), "tokens"))
+            .field("sentences", &Elided::new(self.sentences.len(), "sentences"))
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("metadata", &self.metadata)
+            .finish()
+    }
+}
+
+impl fmt::Display for TokenizedTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TokenizedTextMessage {} from {} ({} tokens, {} sentences)",
+            self.original_id,
+            self.source_url,
+            self.tokens.len(),
+            self.sentences.len()
+        )
+    }
+}
+
+impl fmt::Debug for GeneratedTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeneratedTextMessage")
+            .field("original_task_id", &self.original_task_id)
+            .field("generated_text", &truncated(&self.generated_text))
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("seed", &self.seed)
+            .field("stop_reason", &self.stop_reason)
+            .finish()
+    }
+}
+
+impl fmt::Display for GeneratedTextMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "GeneratedTextMessage for task {} ({} chars): {:?}",
+            self.original_task_id,
+            self.generated_text.chars().count(),
+            truncated(&self.generated_text)
+        )
+    }
+}
+
+impl fmt::Debug for SparseVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl fmt::Display for SparseVector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{} sparse terms]", self.indices.len())
+    }
+}
+
+impl fmt::Debug for SentenceEmbedding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SentenceEmbedding")
+            .field("sentence_text", &truncated(&self.sentence_text))
+            .field("embedding", &Elided::floats(&self.embedding))
+            .field("sparse_embedding", &self.sparse_embedding)
+            .field("sentence_order", &self.sentence_order)
+            .finish()
+    }
+}
+
+impl fmt::Display for SentenceEmbedding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} {}",
+            truncated(&self.sentence_text),
+            Elided::floats(&self.embedding)
+        )
+    }
+}
+
+impl fmt::Debug for TextWithEmbeddingsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextWithEmbeddingsMessage")
+            .field("original_id", &self.original_id)
+            .field("source_url", &self.source_url)
+            .field(
+                "embeddings_data",
+                &Elided::new(self.embeddings_data.len(), "sentences"),
+            )
+            .field("model_name", &self.model_name)
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("tenant_id", &self.tenant_id)
+            .field("metadata", &self.metadata)
+            .field("chunk_index", &self.chunk_index)
+            .field("total_chunks", &self.total_chunks)
+            .field("parent_document_id", &self.parent_document_id)
+            .finish()
+    }
+}
+
+impl fmt::Display for TextWithEmbeddingsMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "TextWithEmbeddingsMessage {} from {} ({} sentences, model '{}')",
+            self.original_id,
+            self.source_url,
+            self.embeddings_data.len(),
+            self.model_name
+        )
+    }
+}
+
+impl fmt::Debug for QueryEmbeddingResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueryEmbeddingResult")
+            .field("request_id", &self.request_id)
+            .field("embedding", &self.embedding.as_deref().map(Elided::floats))
+            .field("sparse_embedding", &self.sparse_embedding)
+            .field("model_name", &self.model_name)
+            .field("error_message", &self.error_message)
+            .finish()
+    }
+}
+
+impl fmt::Display for QueryEmbeddingResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.embedding, &self.error_message) {
+            (_, Some(error)) => write!(
+                f,
+                "QueryEmbeddingResult {} failed: {}",
+                self.request_id, error
+            ),
+            (Some(embedding), None) => write!(
+                f,
+                "QueryEmbeddingResult {} {}",
+                self.request_id,
+                Elided::floats(embedding)
+            ),
+            (None, None) => write!(f, "QueryEmbeddingResult {} (empty)", self.request_id),
+        }
+    }
+}
@@ -0,0 +1,108 @@
+//! Ingestion quotas. Each tenant may submit a limited number of URLs per hour and store a
+//! limited number of sentences, so one caller cannot take up the scraping and embedding
+//! capacity of everyone else. A submission refused for a quota is announced as a
+//! [`QuotaExceeded`] event on [`QUOTA_EXCEEDED_SUBJECT`].
+
+use crate::current_timestamp_ms;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Subject [`QuotaExceeded`] events are published on, in an envelope following the refused
+/// submission's.
+pub const QUOTA_EXCEEDED_SUBJECT: &str = "events.quota.exceeded";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum QuotaKind {
+    /// URLs submitted within the current clock hour.
+    UrlsPerHour,
+    /// Sentences stored in the vector memory.
+    StoredSentences,
+}
+
+impl QuotaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            QuotaKind::UrlsPerHour => "urls_per_hour",
+            QuotaKind::StoredSentences => "stored_sentences",
+        }
+    }
+}
+
+/// A submission refused because its tenant used up a quota. Submissions without a tenant
+/// share one quota, reported with no `tenant_id`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct QuotaExceeded {
+    pub quota: QuotaKind,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    pub limit: u64,
+    /// Usage when the submission was refused.
+    pub used: u64,
+    /// The URL refused, when known.
+    #[serde(default)]
+    pub url: Option<String>,
+    pub timestamp_ms: u64,
+}
+
+impl QuotaExceeded {
+    pub fn new(quota: QuotaKind, tenant_id: Option<&str>, limit: u64, used: u64) -> Self {
+        QuotaExceeded {
+            quota,
+            tenant_id: tenant_id.map(str::to_string),
+            limit,
+            used,
+            url: None,
+            timestamp_ms: current_timestamp_ms(),
+        }
+    }
+
+    pub fn with_url(mut self, url: impl Into<String>) -> Self {
+        self.url = Some(url.into());
+        self
+    }
+}
+
+impl fmt::Display for QuotaExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.tenant_id {
+            Some(tenant_id) => write!(f, "tenant '{}'", tenant_id)?,
+            None => write!(f, "submissions without a tenant")?,
+        }
+        write!(
+            f,
+            " used {} of {} {}",
+            self.used,
+            self.limit,
+            self.quota.as_str()
+        )
+    }
+}
+
+impl std::error::Error for QuotaExceeded {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_exceeded_round_trip() {
+        let exceeded = QuotaExceeded::new(QuotaKind::UrlsPerHour, Some("acme"), 100, 100)
+            .with_url("https://example.com");
+        assert_eq!(
+            exceeded.to_string(),
+            "tenant 'acme' used 100 of 100 urls_per_hour"
+        );
+
+        let json = serde_json::to_value(&exceeded).unwrap();
+        assert_eq!(json["quota"], "urls_per_hour");
+        let decoded: QuotaExceeded = serde_json::from_value(json).unwrap();
+        assert_eq!(decoded, exceeded);
+
+        let untenanted = QuotaExceeded::new(QuotaKind::StoredSentences, None, 10, 12);
+        assert_eq!(
+            untenanted.to_string(),
+            "submissions without a tenant used 12 of 10 stored_sentences"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Tenants partition the corpus: every document, point and generator model belongs to at
+//! most one, and every read is scoped to the caller's. The tenant of a request is set on
+//! its [`Envelope`] by api_service and carried by every message that descends from it, so
+//! the stores can scope writes and reads without each payload having to name it.
+
+use crate::{Envelope, ValidationError};
+use std::fmt;
+
+/// Longest accepted tenant id, in bytes.
+pub const MAX_TENANT_ID_LEN: usize = 64;
+
+/// Tenant ids end up in Qdrant payloads, Neo4j properties and log lines, so only letters,
+/// digits, '-', '_' and '.' are accepted.
+pub fn validate_tenant_id(tenant_id: &str) -> Result<(), ValidationError> {
+    if tenant_id.is_empty() || tenant_id.len() > MAX_TENANT_ID_LEN {
+        return Err(ValidationError::new(
+            "tenant_id",
+            format!(
+                "tenant_id must be between 1 and {} characters long",
+                MAX_TENANT_ID_LEN
+            ),
+        ));
+    }
+    if !tenant_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+    {
+        return Err(ValidationError::new(
+            "tenant_id",
+            "tenant_id may only contain letters, digits, '-', '_' and '.'",
+        ));
+    }
+    Ok(())
+}
+
+/// A payload naming another tenant than the envelope it came in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TenantMismatch {
+    pub envelope: String,
+    pub payload: String,
+}
+
+impl fmt::Display for TenantMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "payload tenant '{}' does not match the request's tenant '{}'",
+            self.payload, self.envelope
+        )
+    }
+}
+
+impl std::error::Error for TenantMismatch {}
+
+impl<T> Envelope<T> {
+    pub fn with_tenant_id(mut self, tenant_id: Option<String>) -> Self {
+        self.tenant_id = tenant_id;
+        self
+    }
+
+    /// The tenant the message is scoped to: the envelope's, or the one its payload names
+    /// when the envelope has none, e.g. a task built by a service from a stored point. A
+    /// payload cannot name another tenant than its envelope, so a caller cannot widen a
+    /// request to someone else's data.
+    pub fn scoped_tenant<'a>(
+        &'a self,
+        payload_tenant: Option<&'a str>,
+    ) -> Result<Option<&'a str>, TenantMismatch> {
+        match (self.tenant_id.as_deref(), payload_tenant) {
+            (Some(envelope), Some(payload)) if envelope != payload => Err(TenantMismatch {
+                envelope: envelope.to_string(),
+                payload: payload.to_string(),
+            }),
+            (Some(envelope), _) => Ok(Some(envelope)),
+            (None, payload) => Ok(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tenant_ids_are_validated() {
+        assert!(validate_tenant_id("tenant-a").is_ok());
+        assert!(validate_tenant_id("acme.eu_1").is_ok());
+        assert!(validate_tenant_id("").is_err());
+        assert!(validate_tenant_id("a b").is_err());
+        assert!(validate_tenant_id("a'}) DETACH DELETE (n").is_err());
+        assert!(validate_tenant_id(&"a".repeat(MAX_TENANT_ID_LEN)).is_ok());
+        assert!(validate_tenant_id(&"a".repeat(MAX_TENANT_ID_LEN + 1)).is_err());
+    }
+
+    #[test]
+    fn test_envelope_tenant_scopes_the_payload() {
+        let untenanted = Envelope::new("test", ());
+        assert_eq!(untenanted.scoped_tenant(None), Ok(None));
+        assert_eq!(
+            untenanted.scoped_tenant(Some("tenant-a")),
+            Ok(Some("tenant-a"))
+        );
+
+        let tenanted = Envelope::new("test", ()).with_tenant_id(Some("tenant-a".to_string()));
+        assert_eq!(tenanted.scoped_tenant(None), Ok(Some("tenant-a")));
+        assert_eq!(
+            tenanted.scoped_tenant(Some("tenant-a")),
+            Ok(Some("tenant-a"))
+        );
+        assert_eq!(
+            tenanted.scoped_tenant(Some("tenant-b")),
+            Err(TenantMismatch {
+                envelope: "tenant-a".to_string(),
+                payload: "tenant-b".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn test_follow_ups_keep_the_tenant() {
+        let cause = Envelope::new("api_service", "task").with_tenant_id(Some("tenant-a".into()));
+        let reply = cause.follow_up("vector_memory_service", 1);
+        assert_eq!(reply.tenant_id.as_deref(), Some("tenant-a"));
+        let (metadata, _) = reply.split();
+        assert_eq!(metadata.tenant_id.as_deref(), Some("tenant-a"));
+        assert_eq!(
+            Envelope::<u32>::from_slice(&metadata.follow_up("p", 2).to_vec().unwrap())
+                .unwrap()
+                .tenant_id
+                .as_deref(),
+            Some("tenant-a")
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Typed points in time for the `*_ms` fields. A [`Timestamp`] is serialized as the same
+//! u64 milliseconds since the Unix epoch those fields carry, so it can replace one without
+//! changing the wire format, and converts to [`chrono`] types for parsing and formatting.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Milliseconds since the Unix epoch, in UTC.
+#[derive(
+    Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(transparent)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp(0);
+
+    pub fn now() -> Self {
+        Timestamp(crate::current_timestamp_ms())
+    }
+
+    pub const fn from_millis(millis: u64) -> Self {
+        Timestamp(millis)
+    }
+
+    pub const fn as_millis(self) -> u64 {
+        self.0
+    }
+
+    /// Truncates to whole milliseconds; instants before the epoch clamp to it.
+    pub fn from_datetime(datetime: DateTime<Utc>) -> Self {
+        Timestamp(u64::try_from(datetime.timestamp_millis()).unwrap_or(0))
+    }
+
+    pub fn to_datetime(self) -> DateTime<Utc> {
+        i64::try_from(self.0)
+            .ok()
+            .and_then(DateTime::from_timestamp_millis)
+            .unwrap_or(DateTime::<Utc>::MAX_UTC)
+    }
+
+    /// `self` moved back by `duration`, clamped to the epoch.
+    pub fn saturating_sub(self, duration: Duration) -> Self {
+        Timestamp(self.0.saturating_sub(duration_millis(duration)))
+    }
+
+    pub fn saturating_add(self, duration: Duration) -> Self {
+        Timestamp(self.0.saturating_add(duration_millis(duration)))
+    }
+
+    /// Time elapsed from `earlier` to `self`; zero when `earlier` is later.
+    pub fn duration_since(self, earlier: Timestamp) -> Duration {
+        Duration::from_millis(self.0.saturating_sub(earlier.0))
+    }
+
+    /// Whether `self` lies more than `max_age` before `now`.
+    pub fn is_older_than(self, max_age: Duration, now: Timestamp) -> bool {
+        self < now.saturating_sub(max_age)
+    }
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+impl From<u64> for Timestamp {
+    fn from(millis: u64) -> Self {
+        Timestamp(millis)
+    }
+}
+
+impl From<Timestamp> for u64 {
+    fn from(timestamp: Timestamp) -> Self {
+        timestamp.0
+    }
+}
+
+impl From<DateTime<Utc>> for Timestamp {
+    fn from(datetime: DateTime<Utc>) -> Self {
+        Timestamp::from_datetime(datetime)
+    }
+}
+
+/// RFC 3339 in UTC with millisecond precision, e.g. `2024-05-01T12:00:00.000Z`.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(
+            &self
+                .to_datetime()
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+        )
+    }
+}
+
+/// Accepts an RFC 3339 date-time in any offset, or a plain count of epoch milliseconds.
+impl FromStr for Timestamp {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Ok(millis) = s.parse::<u64>() {
+            return Ok(Timestamp(millis));
+        }
+        DateTime::parse_from_rfc3339(s)
+            .map(|datetime| Timestamp::from_datetime(datetime.with_timezone(&Utc)))
+            .map_err(|e| {
+                format!(
+                    "invalid timestamp '{}' (expected RFC 3339 or epoch milliseconds): {}",
+                    s, e
+                )
+            })
+    }
+}
+
+/// A half-open span `[start, end)`; a missing bound leaves that side unbounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TimeRange {
+    pub start: Option<Timestamp>,
+    pub end: Option<Timestamp>,
+}
+
+impl TimeRange {
+    /// The last `duration` up to `now`.
+    pub fn last(duration: Duration, now: Timestamp) -> Self {
+        TimeRange {
+            start: Some(now.saturating_sub(duration)),
+            end: Some(now),
+        }
+    }
+
+    pub fn is_unbounded(&self) -> bool {
+        self.start.is_none() && self.end.is_none()
+    }
+
+    /// A range whose end is not after its start contains nothing.
+    pub fn is_empty(&self) -> bool {
+        matches!((self.start, self.end), (Some(start), Some(end)) if end <= start)
+    }
+
+    pub fn contains(&self, timestamp: Timestamp) -> bool {
+        self.start.is_none_or(|start| timestamp >= start)
+            && self.end.is_none_or(|end| timestamp < end)
+    }
+}
@@ -0,0 +1,199 @@
+//! The JSON log format: one object per record with the service name, level, target, the
+//! record's fields and the span it was recorded in. The `[TAG]` most messages start with
+//! becomes a `tag` field, so log collectors can filter on it instead of matching text.
+
+use serde_json::{Map, Value};
+use std::fmt;
+use tracing::field::{Field, Visit};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::{FormatTime, SystemTime};
+use tracing_subscriber::fmt::{FmtContext, FormatEvent, FormatFields, FormattedFields};
+use tracing_subscriber::registry::LookupSpan;
+
+/// Formats events as JSON lines; expects span fields formatted by `JsonFields`.
+pub(crate) struct JsonFormat {
+    pub(crate) service: &'static str,
+}
+
+impl<S, N> FormatEvent<S, N> for JsonFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let mut visitor = FieldMap::default();
+        event.record(&mut visitor);
+        let FieldMap {
+            mut fields,
+            log_target,
+        } = visitor;
+        let metadata = event.metadata();
+
+        let mut timestamp = String::new();
+        SystemTime.format_time(&mut Writer::new(&mut timestamp))?;
+        let mut record = Map::new();
+        record.insert("timestamp".into(), timestamp.into());
+        record.insert("level".into(), metadata.level().to_string().into());
+        record.insert("service".into(), self.service.into());
+        record.insert(
+            "target".into(),
+            log_target
+                .unwrap_or_else(|| metadata.target().to_string())
+                .into(),
+        );
+        if let Some(Value::String(message)) = fields.remove("message") {
+            let (tag, message) = split_tag(&message);
+            if let Some(tag) = tag {
+                record.insert("tag".into(), tag.into());
+            }
+            record.insert("message".into(), message.into());
+        }
+        if let Some(span) = ctx.lookup_current() {
+            let mut span_fields = span
+                .extensions()
+                .get::<FormattedFields<N>>()
+                .and_then(|formatted| serde_json::from_str::<Map<_, _>>(&formatted.fields).ok())
+                .unwrap_or_default();
+            span_fields.insert("name".into(), span.name().into());
+            record.insert("span".into(), span_fields.into());
+        }
+        record.extend(fields);
+
+        let line = serde_json::to_string(&record).map_err(|_| fmt::Error)?;
+        writeln!(writer, "{}", line)
+    }
+}
+
+/// Splits `[TAG] rest` into the tag and the rest; other messages have no tag.
+fn split_tag(message: &str) -> (Option<&str>, &str) {
+    if let Some(rest) = message.strip_prefix('[')
+        && let Some((tag, rest)) = rest.split_once(']')
+        && !tag.is_empty()
+        && tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return (Some(tag), rest.trim_start());
+    }
+    (None, message)
+}
+
+/// The fields of an event. Records from the `log` macros carry their origin in `log.*`
+/// fields; only the target is kept, as the record's target.
+#[derive(Default)]
+struct FieldMap {
+    fields: Map<String, Value>,
+    log_target: Option<String>,
+}
+
+impl FieldMap {
+    fn insert(&mut self, field: &Field, value: Value) {
+        match field.name() {
+            "log.target" => self.log_target = value.as_str().map(str::to_string),
+            name if name.starts_with("log.") => {}
+            name => {
+                self.fields.insert(name.to_string(), value);
+            }
+        }
+    }
+}
+
+impl Visit for FieldMap {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use tracing_subscriber::fmt::format::JsonFields;
+
+    struct BufferWriter(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for BufferWriter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_split_tag() {
+        assert_eq!(
+            split_tag("[NATS_CONNECT] Connected"),
+            (Some("NATS_CONNECT"), "Connected")
+        );
+        assert_eq!(
+            split_tag("[api_service] Starting"),
+            (Some("api_service"), "Starting")
+        );
+        assert_eq!(split_tag("Starting ..."), (None, "Starting ..."));
+        assert_eq!(split_tag("[not a tag] text"), (None, "[not a tag] text"));
+        assert_eq!(split_tag("[] text"), (None, "[] text"));
+    }
+
+    #[test]
+    fn test_records_carry_service_tag_and_span() {
+        let buffer = Arc::new(Mutex::new(Vec::new()));
+        let output = Arc::clone(&buffer);
+        let subscriber = tracing_subscriber::fmt()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat {
+                service: "test_service",
+            })
+            .with_writer(move || BufferWriter(Arc::clone(&output)))
+            .finish();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!(
+                "nats.process",
+                messaging.destination.name = "tasks.perceive.url"
+            );
+            let _entered = span.enter();
+            tracing::info!(attempt = 2, "[NATS_URL] Received message");
+        });
+
+        let output = String::from_utf8(buffer.lock().unwrap().clone()).unwrap();
+        let record: Value = serde_json::from_str(output.trim()).unwrap();
+        assert_eq!(record["service"], "test_service");
+        assert_eq!(record["level"], "INFO");
+        assert_eq!(record["tag"], "NATS_URL");
+        assert_eq!(record["message"], "Received message");
+        assert_eq!(record["attempt"], 2);
+        assert_eq!(record["span"]["name"], "nats.process");
+        assert_eq!(
+            record["span"]["messaging.destination.name"],
+            "tasks.perceive.url"
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Logging, tracing and metrics setup shared by every service, done by one [`init`] call at
+//! the top of `main`.
+//!
+//! Records from the `log` macros the services use are routed through a `tracing`
+//! subscriber, formatted as text or as JSON with the service name and the message's
+//! `[TAG]` as fields ([`LogFormat`]). When an OTLP endpoint is
+//! configured, spans are also exported there; the W3C trace context propagator lets
+//! `shared_nats` continue a trace across services. Standard process metrics are kept in a
+//! Prometheus [`registry`] and served on the metrics address together with the service's
+//! own metrics, next to a `/healthz` endpoint answering with the [`set_health_check`] report.
+
+use log::{error, info, warn};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::Resource;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use prometheus::{Encoder, IntGaugeVec, Opts, Registry, TextEncoder};
+use shared_config::{LogFormat, Settings};
+use std::fmt;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{LazyLock, OnceLock};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tracing_subscriber::fmt::format::JsonFields;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+mod json;
+
+use json::JsonFormat;
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::new);
+static HEALTH_CHECK: OnceLock<HealthCheck> = OnceLock::new();
+
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+const TEXT_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+type HealthCheck =
+    Box<dyn Fn() -> Pin<Box<dyn Future<Output = HealthReport> + Send>> + Send + Sync>;
+
+#[derive(Debug)]
+pub enum TelemetryError {
+    /// A global subscriber or logger was already installed.
+    Subscriber(tracing_subscriber::util::TryInitError),
+    Exporter(opentelemetry_otlp::ExporterBuildError),
+    Metrics(prometheus::Error),
+}
+
+impl fmt::Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::Subscriber(e) => write!(f, "failed to install the subscriber: {}", e),
+            TelemetryError::Exporter(e) => write!(f, "failed to build the OTLP exporter: {}", e),
+            TelemetryError::Metrics(e) => write!(f, "failed to register metrics: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TelemetryError::Subscriber(e) => Some(e),
+            TelemetryError::Exporter(e) => Some(e),
+            TelemetryError::Metrics(e) => Some(e),
+        }
+    }
+}
+
+/// Keeps the span exporter alive; dropping it at the end of `main` flushes pending spans.
+#[must_use = "dropping Telemetry immediately stops exporting spans"]
+pub struct Telemetry {
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for Telemetry {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            eprintln!("[TELEMETRY] Failed to flush spans: {}", e);
+        }
+    }
+}
+
+/// Installs the global subscriber for `service_name`, filtered by `settings.logging.filter`
+/// or else `default_filter`, registers the standard metrics and, unless disabled, spawns the
+/// metrics endpoint serving them after `service_metrics()`. Must be called once, from
+/// within the Tokio runtime.
+pub fn init(
+    service_name: &'static str,
+    default_filter: &str,
+    settings: &Settings,
+    service_metrics: fn() -> String,
+) -> Result<Telemetry, TelemetryError> {
+    let filter = match &settings.logging.filter {
+        Some(directives) => EnvFilter::new(directives),
+        None => EnvFilter::new(default_filter),
+    };
+    let fmt_layer = match settings.logging.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .fmt_fields(JsonFields::new())
+            .event_format(JsonFormat {
+                service: service_name,
+            })
+            .boxed(),
+    };
+    opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new());
+    let tracer_provider = match &settings.otel.endpoint {
+        Some(endpoint) => Some(tracer_provider(service_name, endpoint)?),
+        None => None,
+    };
+    let otel_layer = tracer_provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer(service_name)));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .try_init()
+        .map_err(TelemetryError::Subscriber)?;
+
+    info!("[CONFIG] Settings: {:?}", settings);
+    if let Some(endpoint) = &settings.otel.endpoint {
+        info!("[TELEMETRY] Exporting spans to {}", endpoint);
+    }
+
+    register_standard_metrics(service_name).map_err(TelemetryError::Metrics)?;
+    if let Some(addr) = settings.metrics.socket_addr() {
+        tokio::spawn(serve_metrics(addr, service_metrics));
+    }
+
+    Ok(Telemetry { tracer_provider })
+}
+
+fn tracer_provider(
+    service_name: &'static str,
+    endpoint: &str,
+) -> Result<SdkTracerProvider, TelemetryError> {
+    let exporter = SpanExporter::builder()
+        .with_tonic()
+        .with_endpoint(endpoint)
+        .build()
+        .map_err(TelemetryError::Exporter)?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(Resource::builder().with_service_name(service_name).build())
+        .build();
+    opentelemetry::global::set_tracer_provider(provider.clone());
+    Ok(provider)
+}
+
+/// What `GET /healthz` answers: `body` with status 200 when the service is available, or
+/// 503 when it is not.
+pub struct HealthReport {
+    pub available: bool,
+    pub body: String,
+}
+
+/// Makes `GET /healthz` on the metrics endpoint answer with the report of `check`. Until
+/// then, e.g. while the service is still connecting to its dependencies, it answers 503.
+/// Only the first call has an effect.
+pub fn set_health_check<F, Fut>(check: F)
+where
+    F: Fn() -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = HealthReport> + Send + 'static,
+{
+    let _ = HEALTH_CHECK.set(Box::new(move || Box::pin(check())));
+}
+
+/// The registry behind the metrics endpoint; services may register their own collectors.
+pub fn registry() -> &'static Registry {
+    &REGISTRY
+}
+
+fn register_standard_metrics(service_name: &str) -> prometheus::Result<()> {
+    let service_info = IntGaugeVec::new(
+        Opts::new(
+            "symbiont_service_info",
+            "Always 1; labels name the running service.",
+        ),
+        &["service", "version"],
+    )?;
+    service_info
+        .with_label_values(&[service_name, env!("CARGO_PKG_VERSION")])
+        .set(1);
+    REGISTRY.register(Box::new(service_info))?;
+
+    #[cfg(target_os = "linux")]
+    REGISTRY.register(Box::new(
+        prometheus::process_collector::ProcessCollector::for_self(),
+    ))?;
+    Ok(())
+}
+
+/// The [`registry`] in the Prometheus text format.
+pub fn render_metrics() -> String {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&REGISTRY.gather(), &mut buffer) {
+        warn!("[METRICS] Failed to encode metrics: {}", e);
+    }
+    String::from_utf8(buffer).unwrap_or_default()
+}
+
+/// Serves `GET /metrics` and `GET /healthz` over plain HTTP/1.1; every other request gets a
+/// 404.
+async fn serve_metrics(addr: SocketAddr, service_metrics: fn() -> String) {
+    let listener = match TcpListener::bind(addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!(
+                "[METRICS_FAIL] Failed to bind metrics endpoint on {}: {}",
+                addr, e
+            );
+            return;
+        }
+    };
+    info!(
+        "[METRICS] Serving Prometheus metrics on http://{}/metrics",
+        addr
+    );
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = respond(stream, service_metrics).await {
+                        warn!("[METRICS] Failed to answer metrics request: {}", e);
+                    }
+                });
+            }
+            Err(e) => {
+                warn!("[METRICS] Failed to accept metrics connection: {}", e);
+            }
+        }
+    }
+}
+
+async fn respond(mut stream: TcpStream, service_metrics: fn() -> String) -> std::io::Result<()> {
+    let mut buffer = [0u8; 1024];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let mut request_line = request
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace();
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line
+        .next()
+        .and_then(|target| target.split('?').next())
+        .unwrap_or_default();
+
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/metrics") => (
+            "200 OK",
+            METRICS_CONTENT_TYPE,
+            service_metrics() + &render_metrics(),
+        ),
+        ("GET", "/healthz") => match HEALTH_CHECK.get() {
+            Some(check) => {
+                let report = check().await;
+                let status = if report.available {
+                    "200 OK"
+                } else {
+                    "503 Service Unavailable"
+                };
+                (status, "application/json", report.body)
+            }
+            None => (
+                "503 Service Unavailable",
+                TEXT_CONTENT_TYPE,
+                "Starting\n".to_string(),
+            ),
+        },
+        _ => (
+            "404 Not Found",
+            TEXT_CONTENT_TYPE,
+            "Not Found\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_standard_metrics_are_rendered() {
+        register_standard_metrics("test_service").unwrap();
+        let rendered = render_metrics();
+        assert!(rendered.contains("# TYPE symbiont_service_info gauge"));
+        assert!(rendered.contains(r#"symbiont_service_info{service="test_service",version=""#));
+        assert!(register_standard_metrics("test_service").is_err());
+    }
+}
@@ -1,27 +1,61 @@
 use actix_cors::Cors;
-use actix_web::{App, Error as ActixError, HttpResponse, HttpServer, Responder, http::header, web};
+use actix_web::{
+    App, Either, Error as ActixError, HttpRequest, HttpResponse, HttpServer, Responder,
+    http::header, web,
+};
 use actix_web_lab::sse::{Data as SseData, Event as SseEvent, Sse};
 use async_nats::Client as NatsClient;
+use async_nats::jetstream;
 use futures::StreamExt;
 use log::{debug, error, info, warn};
 use serde::{Deserialize, Serialize};
+use shared_config::Settings;
 use shared_models::{
-    GenerateTextTask, GeneratedTextMessage, PerceiveUrlTask, QueryEmbeddingResult,
-    QueryForEmbeddingTask, SemanticSearchApiRequest, SemanticSearchApiResponse,
-    SemanticSearchNatsResult, SemanticSearchNatsTask,
+    AnyDeadLetter, DocumentId, DocumentLifecycle, DocumentStatusResult, DocumentStatusTask,
+    Envelope, GenerateTextTask, GeneratedTextMessage, GeneratorStatsResult, GeneratorStatsTask,
+    GraphCypherResult, GraphCypherTask, GraphStatsResult, GraphStatsTask, MarkovModelStats,
+    PerceiveUrlTask, PipelineErrorMessage, PipelineStage, QueryEmbeddingResult,
+    QueryForEmbeddingTask, RecommendApiRequest, RecommendNatsTask, RelatedDocument,
+    RelatedDocumentsResult, RelatedDocumentsTask, ReplayMessage, RequestId,
+    SemanticSearchApiRequest, SemanticSearchApiResponse, SemanticSearchNatsResult,
+    SemanticSearchNatsTask, StageProgress, StoredPointItem, TASK_STATUS_EVENT_SUBJECT, TaskId,
+    TaskPriority, TaskStatusChangedMessage, Validate, VectorCollectionStats, VectorScrollResult,
+    VectorScrollTask, VectorStatsResult, VectorStatsTask, dead_letter_subject, validate_tenant_id,
+};
+use shared_nats::{
+    DEAD_LETTERS_STREAM, PERCEIVE_TASKS_STREAM, Quotas, delete_stored_message, publish_durable,
+    receive_span, request_guarded, serve_health, stored_message, stored_messages, traced_headers,
 };
-use std::env;
-use std::sync::Arc;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::broadcast;
 use tokio_stream::wrappers::{BroadcastStream, errors::BroadcastStreamRecvError};
-use uuid::Uuid;
+use tracing::Instrument;
 
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
 const PERCEPTION_URL_TASK_SUBJECT: &str = "tasks.perceive.url";
 const GENERATE_TEXT_TASK_SUBJECT: &str = "tasks.generation.text";
 const TEXT_GENERATED_EVENT_SUBJECT: &str = "events.text.generated";
+/// SSE event name of forwarded task status changes; generated text is sent unnamed.
+const TASK_STATUS_SSE_EVENT: &str = "task_status";
 const EMBEDDING_FOR_QUERY_NATS_SUBJECT: &str = "tasks.embedding.for_query";
 const SEMANTIC_SEARCH_NATS_SUBJECT: &str = "tasks.search.semantic.request";
+const VECTOR_SCROLL_NATS_SUBJECT: &str = "tasks.vector.scroll";
+const RECOMMEND_NATS_SUBJECT: &str = "tasks.search.recommend.request";
+const VECTOR_STATS_NATS_SUBJECT: &str = "tasks.vector.stats";
+const RELATED_DOCUMENTS_NATS_SUBJECT: &str = "tasks.graph.related_documents";
+const GRAPH_CYPHER_NATS_SUBJECT: &str = "tasks.graph.cypher";
+const GRAPH_STATS_NATS_SUBJECT: &str = "tasks.graph.stats";
+const GENERATOR_STATS_NATS_SUBJECT: &str = "control.generator.stats";
+const DOCUMENT_STATUS_NATS_SUBJECT: &str = "tasks.orchestrator.status";
+const ADMIN_TOKEN_HEADER: &str = "X-Admin-Token";
+/// Names the caller's tenant; the submissions and reads of a request are scoped to it.
+const TENANT_HEADER: &str = "X-Tenant-Id";
+const PIPELINE_ERRORS_WILDCARD_SUBJECT: &str = "errors.>";
+/// Pipeline errors kept in memory for `GET /api/errors`, oldest dropped first.
+const RECENT_PIPELINE_ERRORS_CAPACITY: usize = 200;
+const MAX_DEAD_LETTERS_PER_PAGE: usize = 500;
 
 #[derive(Serialize, Clone)]
 struct ApiResponse {
@@ -32,85 +66,287 @@ struct ApiResponse {
 #[derive(Deserialize, Debug)]
 struct SubmitUrlApiPayload {
     url: String,
+    #[serde(default)]
+    priority: Option<TaskPriority>,
+    #[serde(default)]
+    deadline_ms: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct TaskStatusApiResponse {
+    task_id: String,
+    document: Option<DocumentLifecycle>,
+    /// Every status change and error of the task, oldest first; only filled by the history
+    /// endpoint.
+    history: Vec<StageProgress>,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DocumentSentencesQuery {
+    limit: Option<u32>,
+    offset: Option<String>,
+    model_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DocumentSentencesApiResponse {
+    document_id: String,
+    sentences: Vec<StoredPointItem>,
+    next_offset: Option<String>,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct RelatedDocumentsQuery {
+    top_k: Option<u32>,
+    min_shared_terms: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct RelatedDocumentsApiResponse {
+    document_id: String,
+    documents: Vec<RelatedDocument>,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GraphCypherApiRequest {
+    query: String,
+    #[serde(default)]
+    params: serde_json::Map<String, serde_json::Value>,
+    max_rows: Option<u32>,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+struct AdminStatsQuery {
+    model_name: Option<String>,
+}
+
+#[derive(Serialize)]
+struct AdminStatsApiResponse {
+    vector_memory: Vec<VectorCollectionStats>,
+    knowledge_graph: Option<GraphStatsResult>,
+    text_generator: Option<GeneratorStatsResult>,
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PipelineErrorsQuery {
+    stage: Option<PipelineStage>,
+    original_id: Option<DocumentId>,
+    task_id: Option<TaskId>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct PipelineErrorsApiResponse {
+    errors: Vec<PipelineErrorMessage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct DeadLettersQuery {
+    service: Option<String>,
+    from_sequence: Option<u64>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct DeadLetterItem {
+    sequence: u64,
+    subject: String,
+    dead_letter: Option<Envelope<AnyDeadLetter>>,
+    error_message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeadLettersApiResponse {
+    dead_letters: Vec<DeadLetterItem>,
+    /// Pass as `from_sequence` to read the next page.
+    next_sequence: Option<u64>,
+    error_message: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DeadLetterReplayApiResponse {
+    sequence: u64,
+    replayed_to: Option<String>,
+    error_message: Option<String>,
+}
+
+/// JSON data for SSE clients, with the event name it is sent under, if any, and the
+/// tenant of the message it was read from; only clients of that tenant receive it.
+#[derive(Clone)]
+struct SseMessage {
+    event: Option<&'static str>,
+    data: String,
+    tenant_id: Option<String>,
+}
+
+/// A [`PipelineErrorMessage`] with the tenant of the message it was read from.
+struct TenantPipelineError {
+    tenant_id: Option<String>,
+    error: PipelineErrorMessage,
 }
 
 struct AppState {
     nats_client: Arc<NatsClient>,
-    sse_tx: broadcast::Sender<String>,
+    jetstream: jetstream::Context,
+    sse_tx: broadcast::Sender<SseMessage>,
+    recent_errors: Arc<Mutex<VecDeque<TenantPipelineError>>>,
+    quotas: Arc<Quotas>,
+}
+
+/// The tenant of a request: its `X-Tenant-Id` header, else the tenant its body names.
+/// The envelopes of the request carry it to every service, which scope their writes and
+/// reads to it. A header and body naming different tenants are rejected.
+fn request_tenant(req: &HttpRequest, body_tenant: Option<&str>) -> Result<Option<String>, String> {
+    let header_tenant = match req.headers().get(TENANT_HEADER) {
+        Some(value) => Some(
+            value
+                .to_str()
+                .map_err(|_| format!("{} must be ASCII", TENANT_HEADER))?
+                .trim(),
+        ),
+        None => None,
+    };
+    let tenant_id = match (header_tenant, body_tenant) {
+        (Some(header), Some(body)) if header != body => {
+            return Err(format!(
+                "{} '{}' does not match tenant_id '{}'",
+                TENANT_HEADER, header, body
+            ));
+        }
+        (Some(tenant_id), _) | (None, Some(tenant_id)) => tenant_id,
+        (None, None) => return Ok(None),
+    };
+    validate_tenant_id(tenant_id).map_err(|e| e.to_string())?;
+    Ok(Some(tenant_id.to_string()))
 }
 
 async fn submit_url_handler(
+    req: HttpRequest,
     payload: web::Json<SubmitUrlApiPayload>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
     let url_to_scrape = payload.url.trim();
+    let perceiver_task = PerceiveUrlTask {
+        url: url_to_scrape.to_string(),
+        priority: payload.priority,
+        deadline_ms: payload.deadline_ms,
+    };
 
-    if url_to_scrape.is_empty() {
-        warn!("[API_SUBMIT_URL] Received empty URL");
+    if let Err(e) = perceiver_task.validate() {
+        warn!("[API_SUBMIT_URL] Rejected URL '{}': {}", url_to_scrape, e);
         return HttpResponse::BadRequest().json(ApiResponse {
-            message: "URL cannot be empty".to_string(),
+            message: e.to_string(),
             task_id: None,
         });
     }
 
-    // TODO: Валидация URL
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            warn!("[API_SUBMIT_URL] Rejected URL '{}': {}", url_to_scrape, e);
+            return HttpResponse::BadRequest().json(ApiResponse {
+                message: e,
+                task_id: None,
+            });
+        }
+    };
 
     info!(
-        "[API_SUBMIT_URL] Received request to scrape URL: {}",
-        url_to_scrape
+        "[API_SUBMIT_URL] Received request to scrape URL: {} (tenant: {:?})",
+        url_to_scrape, tenant_id
     );
 
-    let perceiver_task = PerceiveUrlTask {
-        url: url_to_scrape.to_string(),
-    };
-
-    match serde_json::to_vec(&perceiver_task) {
-        Ok(task_payload_json) => {
-            info!(
-                "[API_SUBMIT_URL] Publishing PerceiveUrlTask to NATS subject: {}",
-                PERCEPTION_URL_TASK_SUBJECT
-            );
-            if let Err(e) = app_state
-                .nats_client
-                .publish(PERCEPTION_URL_TASK_SUBJECT, task_payload_json.into())
-                .await
-            {
-                error!(
-                    "[API_SUBMIT_URL] Failed to publish PerceiveUrlTask to NATS: {}",
-                    e
-                );
-                HttpResponse::InternalServerError().json(ApiResponse {
-                    message: "Failed to publish task to processing queue".to_string(),
-                    task_id: None,
-                })
-            } else {
-                info!(
-                    "[API_SUBMIT_URL] Successfully published PerceiveUrlTask for URL: {}",
-                    url_to_scrape
-                );
-                HttpResponse::Ok().json(ApiResponse {
-                    message: format!(
-                        "Task to scrape URL '{}' submitted successfully.",
-                        url_to_scrape
-                    ),
-                    task_id: None,
-                })
-            }
-        }
+    // Status changes of the submission are published under the envelope's correlation id;
+    // the document and everything derived from it belong to the envelope's tenant.
+    let envelope = Envelope::new(SERVICE_NAME, &perceiver_task).with_tenant_id(tenant_id);
+    let task_payload_json = match envelope.to_vec() {
+        Ok(task_payload_json) => task_payload_json,
         Err(e) => {
             error!(
                 "[API_SUBMIT_URL] Failed to serialize PerceiveUrlTask: {}",
                 e
             );
-            HttpResponse::InternalServerError().json(ApiResponse {
+            return HttpResponse::InternalServerError().json(ApiResponse {
                 message: "Internal error: Failed to prepare task".to_string(),
                 task_id: None,
-            })
+            });
+        }
+    };
+    let admission = match app_state
+        .quotas
+        .admit_url(envelope.tenant_id.as_deref())
+        .await
+    {
+        Ok(admission) => admission,
+        Err(exceeded) => {
+            let exceeded = exceeded.with_url(url_to_scrape);
+            warn!(
+                "[API_SUBMIT_URL] Refused URL '{}': {}",
+                url_to_scrape, exceeded
+            );
+            app_state
+                .quotas
+                .publish_exceeded(&envelope, &exceeded)
+                .await;
+            return HttpResponse::TooManyRequests().json(ApiResponse {
+                message: format!("Quota exceeded: {}", exceeded),
+                task_id: None,
+            });
         }
+    };
+
+    info!(
+        "[API_SUBMIT_URL] Publishing PerceiveUrlTask to NATS subject: {}",
+        PERCEPTION_URL_TASK_SUBJECT
+    );
+    // Stored by the stream, so a task submitted while perception_service is down is
+    // scraped once it is back. The span is the root of the submission's trace.
+    let span = tracing::info_span!(
+        "submit_url",
+        otel.kind = "producer",
+        task_id = %envelope.correlation_id,
+    );
+    if let Err(e) = publish_durable(
+        &app_state.jetstream,
+        PERCEPTION_URL_TASK_SUBJECT,
+        async_nats::HeaderMap::new(),
+        task_payload_json,
+    )
+    .instrument(span)
+    .await
+    {
+        error!(
+            "[API_SUBMIT_URL] Failed to publish PerceiveUrlTask to NATS: {}",
+            e
+        );
+        // The URL was never queued, so it does not count against the tenant's quota.
+        app_state.quotas.release_url(admission).await;
+        HttpResponse::InternalServerError().json(ApiResponse {
+            message: "Failed to publish task to processing queue".to_string(),
+            task_id: None,
+        })
+    } else {
+        info!(
+            "[API_SUBMIT_URL] Successfully published PerceiveUrlTask for URL: {}",
+            url_to_scrape
+        );
+        HttpResponse::Ok().json(ApiResponse {
+            message: format!(
+                "Task to scrape URL '{}' submitted successfully.",
+                url_to_scrape
+            ),
+            task_id: Some(envelope.correlation_id.clone()),
+        })
     }
 }
 
 async fn generate_text_handler(
+    req: HttpRequest,
     task_payload_from_http: web::Json<GenerateTextTask>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
@@ -122,35 +358,51 @@ async fn generate_text_handler(
     );
     debug!("[API_GENERATE_TEXT] Task details: {:?}", task);
 
-    if task.task_id.trim().is_empty() {
-        warn!("[API_GENERATE_TEXT] Received task with empty task_id");
-        return HttpResponse::BadRequest().json(ApiResponse {
-            message: "task_id cannot be empty".to_string(),
-            task_id: None,
-        });
-    }
-
-    if task.max_length == 0 || task.max_length > 1000 {
-        warn!(
-            "[API_GENERATE_TEXT] Received task with invalid max_length: {}",
-            task.max_length
-        );
-        return HttpResponse::BadRequest().json(ApiResponse {
-            message: "max_length must be between 1 and 1000".to_string(),
-            task_id: Some(task.task_id),
-        });
-    }
+    let validation = task
+        .validate()
+        .map_err(|e| e.to_string())
+        .and_then(|()| request_tenant(&req, None));
+    let tenant_id = match validation {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            warn!(
+                "[API_GENERATE_TEXT] Rejected task (id: {}): {}",
+                task.task_id, e
+            );
+            return HttpResponse::BadRequest().json(ApiResponse {
+                message: e,
+                task_id: Some(task.task_id.to_string()),
+            });
+        }
+    };
 
-    match serde_json::to_vec(&task) {
+    // The generator picks a model of the envelope's tenant.
+    match Envelope::new(SERVICE_NAME, &task)
+        .with_tenant_id(tenant_id)
+        .to_vec()
+    {
         Ok(nats_payload_json) => {
             info!(
                 "[API_GENERATE_TEXT] Publishing GenerateTextTask (id: {}) to NATS subject: {}",
                 task.task_id, GENERATE_TEXT_TASK_SUBJECT
             );
-            if let Err(e) = app_state
-                .nats_client
-                .publish(GENERATE_TEXT_TASK_SUBJECT, nats_payload_json.into())
-                .await
+            let span = tracing::info_span!(
+                "generate_text",
+                otel.kind = "producer",
+                task_id = %task.task_id,
+            );
+            if let Err(e) = async {
+                app_state
+                    .nats_client
+                    .publish_with_headers(
+                        GENERATE_TEXT_TASK_SUBJECT,
+                        traced_headers(),
+                        nats_payload_json.into(),
+                    )
+                    .await
+            }
+            .instrument(span)
+            .await
             {
                 error!(
                     "[API_GENERATE_TEXT] Failed to publish GenerateTextTask (id: {}) to NATS: {}",
@@ -158,7 +410,7 @@ async fn generate_text_handler(
                 );
                 HttpResponse::InternalServerError().json(ApiResponse {
                     message: "Failed to publish generation task to queue".to_string(),
-                    task_id: Some(task.task_id.clone()),
+                    task_id: Some(task.task_id.to_string()),
                 })
             } else {
                 info!(
@@ -170,7 +422,7 @@ async fn generate_text_handler(
                         "Text generation task (id: {}) submitted successfully.",
                         task.task_id
                     ),
-                    task_id: Some(task.task_id.clone()),
+                    task_id: Some(task.task_id.to_string()),
                 })
             }
         }
@@ -181,38 +433,64 @@ async fn generate_text_handler(
             );
             HttpResponse::InternalServerError().json(ApiResponse {
                 message: "Internal error: Failed to prepare generation task".to_string(),
-                task_id: Some(task.task_id.clone()),
+                task_id: Some(task.task_id.to_string()),
             })
         }
     }
 }
 
+/// Streams generated texts and task status changes of the request's tenant; a request
+/// without a tenant only receives those of documents stored without one.
 async fn sse_events_handler(
+    req: HttpRequest,
     app_state: web::Data<AppState>,
-) -> Sse<impl futures::Stream<Item = Result<SseEvent, ActixError>>> {
-    info!("[API_SSE] New SSE client connected to /api/events");
+) -> Either<Sse<impl futures::Stream<Item = Result<SseEvent, ActixError>>>, HttpResponse> {
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            warn!("[API_SSE] Rejected SSE client: {}", e);
+            return Either::Right(HttpResponse::BadRequest().json(ApiResponse {
+                message: e,
+                task_id: None,
+            }));
+        }
+    };
+    info!(
+        "[API_SSE] New SSE client connected to /api/events (tenant: {:?})",
+        tenant_id
+    );
 
     let rx = app_state.sse_tx.subscribe();
 
     let event_stream = BroadcastStream::new(rx).filter_map(
-        |result: Result<String, BroadcastStreamRecvError>| async move {
-            match result {
-                Ok(json_payload) => Some(Ok(SseEvent::Data(SseData::new(json_payload)))),
-                Err(BroadcastStreamRecvError::Lagged(num_skipped)) => {
-                    warn!(
-                        "[SSE_STREAM] SSE receiver lagged, skipped {} messages.",
-                        num_skipped
-                    );
-                    None
+        move |result: Result<SseMessage, BroadcastStreamRecvError>| {
+            let tenant_id = tenant_id.clone();
+            async move {
+                match result {
+                    Ok(message) if message.tenant_id != tenant_id => None,
+                    Ok(SseMessage { event, data, .. }) => {
+                        let data = SseData::new(data);
+                        Some(Ok(SseEvent::Data(match event {
+                            Some(event) => data.event(event),
+                            None => data,
+                        })))
+                    }
+                    Err(BroadcastStreamRecvError::Lagged(num_skipped)) => {
+                        warn!(
+                            "[SSE_STREAM] SSE receiver lagged, skipped {} messages.",
+                            num_skipped
+                        );
+                        None
+                    }
                 }
             }
         },
     );
 
-    Sse::from_stream(event_stream).with_keep_alive(Duration::from_secs(15))
+    Either::Left(Sse::from_stream(event_stream).with_keep_alive(Duration::from_secs(15)))
 }
 
-async fn nats_to_sse_listener(nats_client: Arc<NatsClient>, sse_tx: broadcast::Sender<String>) {
+async fn nats_to_sse_listener(nats_client: Arc<NatsClient>, sse_tx: broadcast::Sender<SseMessage>) {
     info!(
         "[NATS_SSE_Bridge] Subscribing to NATS subject: {}",
         TEXT_GENERATED_EVENT_SUBJECT
@@ -228,10 +506,17 @@ async fn nats_to_sse_listener(nats_client: Arc<NatsClient>, sse_tx: broadcast::S
                     "[NATS_SSE_Bridge] Received NATS message for SSE: {:?}",
                     message.payload
                 );
-                match serde_json::from_slice::<GeneratedTextMessage>(&message.payload) {
-                    Ok(gen_text_msg) => match serde_json::to_string(&gen_text_msg) {
+                let _span = receive_span(&message.subject, message.headers.as_ref()).entered();
+                match Envelope::<GeneratedTextMessage>::from_slice(&message.payload)
+                    .map(|envelope| (envelope.tenant_id, envelope.payload))
+                {
+                    Ok((tenant_id, gen_text_msg)) => match serde_json::to_string(&gen_text_msg) {
                         Ok(json_payload_for_sse) => {
-                            if let Err(e) = sse_tx.send(json_payload_for_sse) {
+                            if let Err(e) = sse_tx.send(SseMessage {
+                                event: None,
+                                data: json_payload_for_sse,
+                                tenant_id,
+                            }) {
                                 warn!(
                                     "[NATS_SSE_Bridge] Failed to send message to broadcast channel (no active SSE receivers?): {}",
                                     e
@@ -269,24 +554,365 @@ async fn nats_to_sse_listener(nats_client: Arc<NatsClient>, sse_tx: broadcast::S
     }
 }
 
+/// Forwards every pipeline stage's [`TaskStatusChangedMessage`] to the SSE clients of its
+/// tenant as a [`TASK_STATUS_SSE_EVENT`] event, which ends the trace of the submission it
+/// belongs to.
+async fn task_status_to_sse_listener(
+    nats_client: Arc<NatsClient>,
+    sse_tx: broadcast::Sender<SseMessage>,
+) {
+    let mut subscriber = match nats_client.subscribe(TASK_STATUS_EVENT_SUBJECT).await {
+        Ok(subscriber) => subscriber,
+        Err(e) => {
+            error!(
+                "[NATS_SSE_Bridge] Failed to subscribe to {} for SSE: {}",
+                TASK_STATUS_EVENT_SUBJECT, e
+            );
+            return;
+        }
+    };
+    info!(
+        "[NATS_SSE_Bridge] Successfully subscribed to {}",
+        TASK_STATUS_EVENT_SUBJECT
+    );
+    while let Some(message) = subscriber.next().await {
+        let _span = receive_span(&message.subject, message.headers.as_ref()).entered();
+        let (tenant_id, status) = match Envelope::<TaskStatusChangedMessage>::from_slice(
+            &message.payload,
+        ) {
+            Ok(envelope) => (envelope.tenant_id, envelope.payload),
+            Err(e) => {
+                warn!(
+                    "[NATS_SSE_Bridge] Failed to deserialize TaskStatusChangedMessage from NATS: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        match serde_json::to_string(&status) {
+            // No receivers just means no SSE client is connected.
+            Ok(data) => {
+                let _ = sse_tx.send(SseMessage {
+                    event: Some(TASK_STATUS_SSE_EVENT),
+                    data,
+                    tenant_id,
+                });
+            }
+            Err(e) => {
+                error!(
+                    "[NATS_SSE_Bridge] Failed to re-serialize TaskStatusChangedMessage for SSE: {}",
+                    e
+                );
+            }
+        }
+    }
+    info!("[NATS_SSE_Bridge] NATS subscription for task status ended.");
+}
+
+/// Keeps the most recent [`PipelineErrorMessage`]s published by any service, with their
+/// tenants.
+async fn pipeline_errors_listener(
+    nats_client: Arc<NatsClient>,
+    recent_errors: Arc<Mutex<VecDeque<TenantPipelineError>>>,
+) {
+    let mut subscriber = match nats_client
+        .subscribe(PIPELINE_ERRORS_WILDCARD_SUBJECT)
+        .await
+    {
+        Ok(subscriber) => subscriber,
+        Err(e) => {
+            error!(
+                "[PIPELINE_ERRORS] Failed to subscribe to {}: {}",
+                PIPELINE_ERRORS_WILDCARD_SUBJECT, e
+            );
+            return;
+        }
+    };
+    info!(
+        "[PIPELINE_ERRORS] Subscribed to {}",
+        PIPELINE_ERRORS_WILDCARD_SUBJECT
+    );
+    while let Some(message) = subscriber.next().await {
+        match Envelope::<PipelineErrorMessage>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let tenant_id = envelope.tenant_id;
+                let pipeline_error = envelope.payload;
+                debug!(
+                    "[PIPELINE_ERRORS] {:?} error at stage {:?} (original_id: {:?}, task_id: {:?}): {}",
+                    pipeline_error.error_kind,
+                    pipeline_error.stage,
+                    pipeline_error.original_id,
+                    pipeline_error.task_id,
+                    pipeline_error.message
+                );
+                let mut recent_errors = recent_errors.lock().unwrap_or_else(|e| e.into_inner());
+                if recent_errors.len() == RECENT_PIPELINE_ERRORS_CAPACITY {
+                    recent_errors.pop_front();
+                }
+                recent_errors.push_back(TenantPipelineError {
+                    tenant_id,
+                    error: pipeline_error,
+                });
+            }
+            Err(e) => {
+                warn!(
+                    "[PIPELINE_ERRORS] Failed to deserialize PipelineErrorMessage from {}: {}",
+                    message.subject, e
+                );
+            }
+        }
+    }
+    info!("[PIPELINE_ERRORS] NATS subscription for pipeline errors ended.");
+}
+
+/// Lists the request tenant's recent pipeline errors, newest first, optionally for one
+/// stage, document or task.
+async fn pipeline_errors_handler(
+    req: HttpRequest,
+    query: web::Query<PipelineErrorsQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(ApiResponse {
+                message: e,
+                task_id: None,
+            });
+        }
+    };
+    let query = query.into_inner();
+    let limit = query
+        .limit
+        .unwrap_or(50)
+        .min(RECENT_PIPELINE_ERRORS_CAPACITY);
+    let recent_errors = app_state
+        .recent_errors
+        .lock()
+        .unwrap_or_else(|e| e.into_inner());
+    let errors = recent_errors
+        .iter()
+        .rev()
+        .filter(|entry| entry.tenant_id == tenant_id)
+        .map(|entry| &entry.error)
+        .filter(|error| query.stage.is_none_or(|stage| error.stage == stage))
+        .filter(|error| query.original_id.is_none() || error.original_id == query.original_id)
+        .filter(|error| query.task_id.is_none() || error.task_id == query.task_id)
+        .take(limit)
+        .cloned()
+        .collect();
+    HttpResponse::Ok().json(PipelineErrorsApiResponse { errors })
+}
+
+/// Lists the request tenant's dead letters oldest first, optionally of one service, a page
+/// at a time. A page can hold fewer than `limit` of them; `next_sequence` says where the
+/// next one starts.
+async fn dead_letters_handler(
+    req: HttpRequest,
+    query: web::Query<DeadLettersQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            return HttpResponse::BadRequest().json(DeadLettersApiResponse {
+                dead_letters: vec![],
+                next_sequence: None,
+                error_message: Some(e),
+            });
+        }
+    };
+    let query = query.into_inner();
+    let subject_filter = match &query.service {
+        Some(service) => dead_letter_subject(service, ">"),
+        None => dead_letter_subject("*", ">"),
+    };
+    let limit = query
+        .limit
+        .unwrap_or(50)
+        .clamp(1, MAX_DEAD_LETTERS_PER_PAGE);
+    let stored = match stored_messages(
+        &app_state.jetstream,
+        &DEAD_LETTERS_STREAM,
+        &subject_filter,
+        query.from_sequence.unwrap_or(1),
+        limit,
+    )
+    .await
+    {
+        Ok(stored) => stored,
+        Err(e) => {
+            error!("[API_DEAD_LETTERS] Failed to read dead letters: {}", e);
+            return HttpResponse::InternalServerError().json(DeadLettersApiResponse {
+                dead_letters: vec![],
+                next_sequence: None,
+                error_message: Some(format!("Failed to read dead letters: {}", e)),
+            });
+        }
+    };
+    let next_sequence = (stored.len() == limit)
+        .then(|| stored.last().map(|message| message.sequence + 1))
+        .flatten();
+    // Unreadable dead letters have no known tenant and are only listed without one.
+    let dead_letters = stored
+        .into_iter()
+        .filter_map(|message| {
+            let (dead_letter, error_message) =
+                match Envelope::<AnyDeadLetter>::from_slice(&message.payload) {
+                    Ok(envelope) if envelope.tenant_id != tenant_id => return None,
+                    Ok(envelope) => (Some(envelope), None),
+                    Err(_) if tenant_id.is_some() => return None,
+                    Err(e) => (None, Some(format!("Unreadable dead letter: {}", e))),
+                };
+            Some(DeadLetterItem {
+                sequence: message.sequence,
+                subject: message.subject,
+                dead_letter,
+                error_message,
+            })
+        })
+        .collect();
+    HttpResponse::Ok().json(DeadLettersApiResponse {
+        dead_letters,
+        next_sequence,
+        error_message: None,
+    })
+}
+
+/// Republishes a dead letter of the request's tenant on its original subject and removes
+/// it from the dead-letter stream, so it is replayed at most once.
+async fn replay_dead_letter_handler(
+    req: HttpRequest,
+    path: web::Path<u64>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let sequence = path.into_inner();
+    let failure = |message: String| DeadLetterReplayApiResponse {
+        sequence,
+        replayed_to: None,
+        error_message: Some(message),
+    };
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return HttpResponse::BadRequest().json(failure(e)),
+    };
+
+    let stored = match stored_message(&app_state.jetstream, &DEAD_LETTERS_STREAM, sequence).await {
+        Ok(Some(stored)) => stored,
+        Ok(None) => {
+            return HttpResponse::NotFound().json(failure(format!(
+                "No dead letter with sequence {}",
+                sequence
+            )));
+        }
+        Err(e) => {
+            error!(
+                "[API_DEAD_LETTER_REPLAY] Failed to read dead letter {}: {}",
+                sequence, e
+            );
+            return HttpResponse::InternalServerError()
+                .json(failure(format!("Failed to read dead letter: {}", e)));
+        }
+    };
+    let dead_letter = Envelope::<AnyDeadLetter>::from_slice(&stored.payload);
+    // Like listing, unreadable dead letters belong to requests without a tenant.
+    let owned = match &dead_letter {
+        Ok(envelope) => envelope.tenant_id == tenant_id,
+        Err(_) => tenant_id.is_none(),
+    };
+    if !owned {
+        return HttpResponse::NotFound().json(failure(format!(
+            "No dead letter with sequence {}",
+            sequence
+        )));
+    }
+    let replay = match dead_letter
+        .and_then(|envelope| ReplayMessage::from_dead_letter(&envelope, SERVICE_NAME))
+    {
+        Ok(replay) => replay,
+        Err(e) => {
+            warn!(
+                "[API_DEAD_LETTER_REPLAY] Dead letter {} on {} cannot be replayed: {}",
+                sequence, stored.subject, e
+            );
+            return HttpResponse::UnprocessableEntity()
+                .json(failure(format!("Unreadable dead letter: {}", e)));
+        }
+    };
+
+    let mut headers = async_nats::HeaderMap::new();
+    for (name, value) in &replay.headers {
+        headers.insert(name.as_str(), value.as_str());
+    }
+    if let Err(e) =
+        publish_durable(&app_state.jetstream, &replay.subject, headers, replay.body).await
+    {
+        error!(
+            "[API_DEAD_LETTER_REPLAY] Failed to replay dead letter {} to {}: {}",
+            sequence, replay.subject, e
+        );
+        return HttpResponse::InternalServerError()
+            .json(failure(format!("Failed to replay dead letter: {}", e)));
+    }
+    info!(
+        "[API_DEAD_LETTER_REPLAY] Replayed dead letter {} to {}",
+        sequence, replay.subject
+    );
+    if let Err(e) =
+        delete_stored_message(&app_state.jetstream, &DEAD_LETTERS_STREAM, sequence).await
+    {
+        warn!(
+            "[API_DEAD_LETTER_REPLAY] Replayed dead letter {} could not be removed: {}",
+            sequence, e
+        );
+    }
+    HttpResponse::Ok().json(DeadLetterReplayApiResponse {
+        sequence,
+        replayed_to: Some(replay.subject),
+        error_message: None,
+    })
+}
+
 async fn semantic_search_handler(
+    req: HttpRequest,
     http_payload: web::Json<SemanticSearchApiRequest>,
     app_state: web::Data<AppState>,
 ) -> impl Responder {
-    let search_api_req = http_payload.into_inner();
-    let client_request_id = Uuid::new_v4().to_string();
+    let mut search_api_req = http_payload.into_inner();
+    let client_request_id = RequestId::generate();
 
     info!(
         "[API_SEARCH_HANDLER] Received semantic search request (client_req_id: {}): query='{}', top_k={}",
         client_request_id, search_api_req.query_text, search_api_req.top_k
     );
 
+    let validation = search_api_req
+        .validate()
+        .map_err(|e| e.to_string())
+        .and_then(|()| request_tenant(&req, search_api_req.filters.tenant_id.as_deref()));
+    let tenant_id = match validation {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => {
+            warn!(
+                "[API_SEARCH_HANDLER] Rejected search request (client_req_id: {}): {}",
+                client_request_id, e
+            );
+            return HttpResponse::BadRequest().json(SemanticSearchApiResponse {
+                search_request_id: client_request_id,
+                results: vec![],
+                groups: None,
+                error_message: Some(e),
+            });
+        }
+    };
+    search_api_req.filters.tenant_id = tenant_id.clone();
+
     let embedding_task = QueryForEmbeddingTask {
-        request_id: client_request_id.clone(),
+        request_id: client_request_id,
         text_to_embed: search_api_req.query_text.clone(),
     };
 
-    let embedding_task_payload_json = match serde_json::to_vec(&embedding_task) {
+    let embedding_task_payload_json = match Envelope::new(SERVICE_NAME, &embedding_task).to_vec() {
         Ok(json) => json,
         Err(e) => {
             error!(
@@ -296,6 +922,7 @@ async fn semantic_search_handler(
             return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some("Internal error: Failed to prepare embedding task".to_string()),
             });
         }
@@ -308,9 +935,10 @@ async fn semantic_search_handler(
 
     let embedding_response_msg = match tokio::time::timeout(
         Duration::from_secs(15),
-        app_state.nats_client.request(
-            EMBEDDING_FOR_QUERY_NATS_SUBJECT.to_string(),
-            embedding_task_payload_json.into(),
+        request_guarded(
+            &app_state.nats_client,
+            EMBEDDING_FOR_QUERY_NATS_SUBJECT,
+            embedding_task_payload_json,
         ),
     )
     .await
@@ -325,6 +953,7 @@ async fn semantic_search_handler(
                 return HttpResponse::ServiceUnavailable().json(SemanticSearchApiResponse {
                     search_request_id: client_request_id,
                     results: vec![],
+                    groups: None,
                     error_message: Some(format!(
                         "Failed to get embedding from preprocessing service: {}",
                         e
@@ -340,6 +969,7 @@ async fn semantic_search_handler(
             return HttpResponse::ServiceUnavailable().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some(
                     "Timeout: Failed to get embedding from preprocessing service within 15 seconds"
                         .to_string(),
@@ -348,9 +978,11 @@ async fn semantic_search_handler(
         }
     };
 
-    let embedding_result: QueryEmbeddingResult = match serde_json::from_slice(
+    let embedding_result: QueryEmbeddingResult = match Envelope::from_slice(
         &embedding_response_msg.payload,
-    ) {
+    )
+    .map(|envelope| envelope.payload)
+    {
         Ok(res) => res,
         Err(e) => {
             error!(
@@ -360,6 +992,7 @@ async fn semantic_search_handler(
             return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some(
                     "Internal error: Failed to parse embedding service response".to_string(),
                 ),
@@ -375,6 +1008,7 @@ async fn semantic_search_handler(
         return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
             search_request_id: client_request_id,
             results: vec![],
+            groups: None,
             error_message: Some(format!("Error from preprocessing service: {}", err_msg)),
         });
     }
@@ -389,6 +1023,7 @@ async fn semantic_search_handler(
             return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some(
                     "Preprocessing service did not return an embedding.".to_string(),
                 ),
@@ -401,12 +1036,22 @@ async fn semantic_search_handler(
     );
 
     let search_nats_task = SemanticSearchNatsTask {
-        request_id: client_request_id.clone(),
+        request_id: client_request_id,
         query_embedding,
         top_k: search_api_req.top_k,
+        model_name: embedding_result.model_name.clone(),
+        sparse_query: embedding_result.sparse_embedding.clone(),
+        filters: search_api_req.filters.clone(),
+        options: search_api_req.options.clone(),
+        read_consistency: None,
+        timeout_secs: None,
+        hnsw_ef: None,
     };
 
-    let search_nats_task_payload_json = match serde_json::to_vec(&search_nats_task) {
+    let search_nats_task_payload_json = match Envelope::new(SERVICE_NAME, &search_nats_task)
+        .with_tenant_id(tenant_id)
+        .to_vec()
+    {
         Ok(json) => json,
         Err(e) => {
             error!(
@@ -416,6 +1061,7 @@ async fn semantic_search_handler(
             return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some("Internal error: Failed to prepare search task".to_string()),
             });
         }
@@ -428,9 +1074,10 @@ async fn semantic_search_handler(
 
     let search_response_msg = match tokio::time::timeout(
         Duration::from_secs(20),
-        app_state.nats_client.request(
-            SEMANTIC_SEARCH_NATS_SUBJECT.to_string(),
-            search_nats_task_payload_json.into(),
+        request_guarded(
+            &app_state.nats_client,
+            SEMANTIC_SEARCH_NATS_SUBJECT,
+            search_nats_task_payload_json,
         ),
     )
     .await
@@ -445,6 +1092,7 @@ async fn semantic_search_handler(
                 return HttpResponse::ServiceUnavailable().json(SemanticSearchApiResponse {
                     search_request_id: client_request_id,
                     results: vec![],
+                    groups: None,
                     error_message: Some(format!(
                         "Failed to get search results from vector memory service: {}",
                         e
@@ -460,6 +1108,7 @@ async fn semantic_search_handler(
             return HttpResponse::ServiceUnavailable().json(SemanticSearchApiResponse {
             search_request_id: client_request_id,
             results: vec![],
+            groups: None,
             error_message: Some(
                 "Timeout: Failed to get search results from vector memory service within 20 seconds".to_string()
             ),
@@ -467,9 +1116,11 @@ async fn semantic_search_handler(
         }
     };
 
-    let search_nats_result: SemanticSearchNatsResult = match serde_json::from_slice(
+    let search_nats_result: SemanticSearchNatsResult = match Envelope::from_slice(
         &search_response_msg.payload,
-    ) {
+    )
+    .map(|envelope| envelope.payload)
+    {
         Ok(res) => res,
         Err(e) => {
             error!(
@@ -479,6 +1130,7 @@ async fn semantic_search_handler(
             return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
                 search_request_id: client_request_id,
                 results: vec![],
+                groups: None,
                 error_message: Some(
                     "Internal error: Failed to parse search service response".to_string(),
                 ),
@@ -494,6 +1146,7 @@ async fn semantic_search_handler(
         return HttpResponse::InternalServerError().json(SemanticSearchApiResponse {
             search_request_id: client_request_id,
             results: vec![],
+            groups: None,
             error_message: Some(format!("Error from vector memory service: {}", err_msg)),
         });
     }
@@ -507,23 +1160,902 @@ async fn semantic_search_handler(
     HttpResponse::Ok().json(SemanticSearchApiResponse {
         search_request_id: client_request_id,
         results: search_nats_result.results,
+        groups: search_nats_result.groups,
         error_message: None,
     })
 }
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    info!("[api_service] Starting Actix Web server...");
+async fn recommend_handler(
+    req: HttpRequest,
+    http_payload: web::Json<RecommendApiRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let recommend_api_req = http_payload.into_inner();
+    let client_request_id = RequestId::generate();
 
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!(
-            "[NATS_CONFIG] NATS_URL (for API service) not set, defaulting to nats://cs-nats:4222"
-        );
-        "nats://cs-nats:4222".to_string()
-    });
-    let nats_client = Arc::new(async_nats::connect(&nats_url).await.map_err(|e| {
-        error!(
+    info!(
+        "[API_RECOMMEND_HANDLER] Received recommendation request (client_req_id: {}): positive points: {:?}, positive document: {:?}, top_k={}",
+        client_request_id,
+        recommend_api_req.positive_point_ids,
+        recommend_api_req.positive_document_id,
+        recommend_api_req.top_k
+    );
+
+    let error_response = |message: String| SemanticSearchApiResponse {
+        search_request_id: client_request_id,
+        results: vec![],
+        groups: None,
+        error_message: Some(message),
+    };
+
+    if recommend_api_req.positive_point_ids.is_empty()
+        && recommend_api_req.positive_document_id.is_none()
+    {
+        return HttpResponse::BadRequest().json(error_response(
+            "Either positive_point_ids or positive_document_id is required".to_string(),
+        ));
+    }
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return HttpResponse::BadRequest().json(error_response(e)),
+    };
+
+    let recommend_task = RecommendNatsTask {
+        request_id: client_request_id,
+        top_k: recommend_api_req.top_k,
+        model_name: recommend_api_req.model_name,
+        positive_point_ids: recommend_api_req.positive_point_ids,
+        positive_document_id: recommend_api_req.positive_document_id,
+        negative_point_ids: recommend_api_req.negative_point_ids,
+        tenant_id: tenant_id.clone(),
+    };
+
+    let recommend_task_payload_json = match Envelope::new(SERVICE_NAME, &recommend_task)
+        .with_tenant_id(tenant_id)
+        .to_vec()
+    {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_RECOMMEND_HANDLER] Failed to serialize RecommendNatsTask (client_req_id: {}): {}",
+                client_request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to prepare recommendation task".to_string(),
+            ));
+        }
+    };
+
+    let recommend_response_msg = match tokio::time::timeout(
+        Duration::from_secs(20),
+        request_guarded(
+            &app_state.nats_client,
+            RECOMMEND_NATS_SUBJECT,
+            recommend_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_RECOMMEND_HANDLER] NATS request for recommendation failed (client_req_id: {}): {}",
+                client_request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(format!(
+                "Failed to get recommendations from vector memory service: {}",
+                e
+            )));
+        }
+        Err(_) => {
+            error!(
+                "[API_RECOMMEND_HANDLER] NATS request for recommendation timed out after 20 seconds (client_req_id: {})",
+                client_request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(
+                "Timeout: Failed to get recommendations from vector memory service within 20 seconds"
+                    .to_string(),
+            ));
+        }
+    };
+
+    let recommend_result: SemanticSearchNatsResult = match Envelope::from_slice(
+        &recommend_response_msg.payload,
+    )
+    .map(|envelope| envelope.payload)
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!(
+                "[API_RECOMMEND_HANDLER] Failed to deserialize recommendation result (client_req_id: {}): {}",
+                client_request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to parse vector memory service response".to_string(),
+            ));
+        }
+    };
+
+    if let Some(err_msg) = recommend_result.error_message {
+        error!(
+            "[API_RECOMMEND_HANDLER] Vector memory service returned error for recommendation (client_req_id: {}): {}",
+            client_request_id, err_msg
+        );
+        return HttpResponse::InternalServerError().json(error_response(format!(
+            "Error from vector memory service: {}",
+            err_msg
+        )));
+    }
+
+    HttpResponse::Ok().json(SemanticSearchApiResponse {
+        search_request_id: client_request_id,
+        results: recommend_result.results,
+        groups: None,
+        error_message: None,
+    })
+}
+
+/// Where a submission is in the pipeline, as orchestrator_service has followed it.
+async fn task_status_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    lookup_task(&req, path.into_inner(), false, &app_state).await
+}
+
+/// Where a submission is in the pipeline, with every status change and error recorded for
+/// it in orchestrator_service's task store.
+async fn task_history_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    lookup_task(&req, path.into_inner(), true, &app_state).await
+}
+
+/// Looks up a task of the request's tenant; tasks of other tenants are reported unknown.
+async fn lookup_task(
+    req: &HttpRequest,
+    task_id_raw: String,
+    include_history: bool,
+    app_state: &AppState,
+) -> HttpResponse {
+    let request_id = RequestId::generate();
+
+    info!(
+        "[API_TASK_STATUS] Looking up task {} (req_id: {})",
+        task_id_raw, request_id
+    );
+
+    let error_response = |message: String| TaskStatusApiResponse {
+        task_id: task_id_raw.clone(),
+        document: None,
+        history: Vec::new(),
+        error_message: Some(message),
+    };
+
+    let task_id: TaskId = match task_id_raw.parse() {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(
+                "[API_TASK_STATUS] Rejected task id '{}' (req_id: {}): {}",
+                task_id_raw, request_id, e
+            );
+            return HttpResponse::BadRequest()
+                .json(error_response(format!("Invalid task id: {}", e)));
+        }
+    };
+    let tenant_id = match request_tenant(req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return HttpResponse::BadRequest().json(error_response(e)),
+    };
+
+    let status_task = DocumentStatusTask {
+        request_id,
+        task_id: Some(task_id),
+        original_id: None,
+        include_history,
+    };
+
+    let status_task_payload_json = match Envelope::new(SERVICE_NAME, &status_task).to_vec() {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_TASK_STATUS] Failed to serialize DocumentStatusTask (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to prepare status task".to_string(),
+            ));
+        }
+    };
+
+    let status_response_msg = match tokio::time::timeout(
+        Duration::from_secs(5),
+        request_guarded(
+            &app_state.nats_client,
+            DOCUMENT_STATUS_NATS_SUBJECT,
+            status_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_TASK_STATUS] NATS request for task status failed (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(format!(
+                "Failed to get task status from orchestrator service: {}",
+                e
+            )));
+        }
+        Err(_) => {
+            error!(
+                "[API_TASK_STATUS] NATS request for task status timed out after 5 seconds (req_id: {})",
+                request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(
+                "Timeout: Failed to get task status from orchestrator service within 5 seconds"
+                    .to_string(),
+            ));
+        }
+    };
+
+    let status_result: DocumentStatusResult =
+        match Envelope::from_slice(&status_response_msg.payload).map(|envelope| envelope.payload) {
+            Ok(res) => res,
+            Err(e) => {
+                error!(
+                    "[API_TASK_STATUS] Failed to deserialize DocumentStatusResult (req_id: {}): {}",
+                    request_id, e
+                );
+                return HttpResponse::InternalServerError().json(error_response(
+                    "Internal error: Failed to parse orchestrator service response".to_string(),
+                ));
+            }
+        };
+
+    if let Some(err_msg) = status_result.error_message {
+        error!(
+            "[API_TASK_STATUS] Orchestrator service returned error (req_id: {}): {}",
+            request_id, err_msg
+        );
+        return HttpResponse::InternalServerError().json(error_response(format!(
+            "Error from orchestrator service: {}",
+            err_msg
+        )));
+    }
+
+    match status_result
+        .document
+        .filter(|document| document.tenant_id == tenant_id)
+    {
+        Some(document) => HttpResponse::Ok().json(TaskStatusApiResponse {
+            task_id: task_id_raw,
+            document: Some(document),
+            history: status_result.history,
+            error_message: None,
+        }),
+        None => HttpResponse::NotFound().json(error_response(format!(
+            "Task {} is unknown or finished too long ago",
+            task_id
+        ))),
+    }
+}
+
+async fn document_sentences_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<DocumentSentencesQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let document_id = path.into_inner();
+    let query = query.into_inner();
+    let request_id = RequestId::generate();
+
+    info!(
+        "[API_DOCUMENT_SENTENCES] Listing sentences for document {} (req_id: {}, limit: {:?}, offset: {:?})",
+        document_id, request_id, query.limit, query.offset
+    );
+
+    let error_response = |message: String| DocumentSentencesApiResponse {
+        document_id: document_id.clone(),
+        sentences: vec![],
+        next_offset: None,
+        error_message: Some(message),
+    };
+
+    let original_document_id: DocumentId = match document_id.parse() {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(
+                "[API_DOCUMENT_SENTENCES] Rejected document id '{}' (req_id: {}): {}",
+                document_id, request_id, e
+            );
+            return HttpResponse::BadRequest()
+                .json(error_response(format!("Invalid document id: {}", e)));
+        }
+    };
+
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(tenant_id) => tenant_id,
+        Err(e) => return HttpResponse::BadRequest().json(error_response(e)),
+    };
+
+    let scroll_task = VectorScrollTask {
+        request_id,
+        model_name: query.model_name,
+        original_document_id: Some(original_document_id),
+        source_url: None,
+        limit: query.limit.unwrap_or(100),
+        offset: query.offset,
+        tenant_id: tenant_id.clone(),
+    };
+
+    let scroll_task_payload_json = match Envelope::new(SERVICE_NAME, &scroll_task)
+        .with_tenant_id(tenant_id)
+        .to_vec()
+    {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_DOCUMENT_SENTENCES] Failed to serialize VectorScrollTask (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to prepare scroll task".to_string(),
+            ));
+        }
+    };
+
+    let scroll_response_msg = match tokio::time::timeout(
+        Duration::from_secs(10),
+        request_guarded(
+            &app_state.nats_client,
+            VECTOR_SCROLL_NATS_SUBJECT,
+            scroll_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_DOCUMENT_SENTENCES] NATS request for scroll failed (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(format!(
+                "Failed to list sentences from vector memory service: {}",
+                e
+            )));
+        }
+        Err(_) => {
+            error!(
+                "[API_DOCUMENT_SENTENCES] NATS request for scroll timed out after 10 seconds (req_id: {})",
+                request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(
+                "Timeout: Failed to list sentences from vector memory service within 10 seconds"
+                    .to_string(),
+            ));
+        }
+    };
+
+    let scroll_result: VectorScrollResult = match Envelope::from_slice(&scroll_response_msg.payload)
+        .map(|envelope| envelope.payload)
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!(
+                "[API_DOCUMENT_SENTENCES] Failed to deserialize VectorScrollResult (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to parse vector memory service response".to_string(),
+            ));
+        }
+    };
+
+    if let Some(err_msg) = scroll_result.error_message {
+        error!(
+            "[API_DOCUMENT_SENTENCES] Vector memory service returned error for scroll (req_id: {}): {}",
+            request_id, err_msg
+        );
+        return HttpResponse::InternalServerError().json(error_response(format!(
+            "Error from vector memory service: {}",
+            err_msg
+        )));
+    }
+
+    HttpResponse::Ok().json(DocumentSentencesApiResponse {
+        document_id,
+        sentences: scroll_result.points,
+        next_offset: scroll_result.next_offset,
+        error_message: None,
+    })
+}
+
+async fn related_documents_handler(
+    req: HttpRequest,
+    path: web::Path<String>,
+    query: web::Query<RelatedDocumentsQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let document_id = path.into_inner();
+    let query = query.into_inner();
+    let request_id = RequestId::generate();
+
+    info!(
+        "[API_RELATED_DOCUMENTS] Finding documents related to {} (req_id: {}, top_k: {:?})",
+        document_id, request_id, query.top_k
+    );
+
+    let error_response = |message: String| RelatedDocumentsApiResponse {
+        document_id: document_id.clone(),
+        documents: vec![],
+        error_message: Some(message),
+    };
+
+    let original_id: DocumentId = match document_id.parse() {
+        Ok(id) => id,
+        Err(e) => {
+            warn!(
+                "[API_RELATED_DOCUMENTS] Rejected document id '{}' (req_id: {}): {}",
+                document_id, request_id, e
+            );
+            return HttpResponse::BadRequest()
+                .json(error_response(format!("Invalid document id: {}", e)));
+        }
+    };
+
+    // knowledge_graph_service only relates documents of a tenant.
+    let tenant_id = match request_tenant(&req, None) {
+        Ok(Some(tenant_id)) => tenant_id,
+        Ok(None) => {
+            return HttpResponse::BadRequest()
+                .json(error_response(format!("{} is required", TENANT_HEADER)));
+        }
+        Err(e) => return HttpResponse::BadRequest().json(error_response(e)),
+    };
+
+    let related_task = RelatedDocumentsTask {
+        request_id,
+        original_id,
+        top_k: query.top_k.unwrap_or(10),
+        min_shared_terms: query.min_shared_terms,
+    };
+
+    let related_task_payload_json = match Envelope::new(SERVICE_NAME, &related_task)
+        .with_tenant_id(Some(tenant_id))
+        .to_vec()
+    {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_RELATED_DOCUMENTS] Failed to serialize RelatedDocumentsTask (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to prepare related documents task".to_string(),
+            ));
+        }
+    };
+
+    let related_response_msg = match tokio::time::timeout(
+        Duration::from_secs(10),
+        request_guarded(
+            &app_state.nats_client,
+            RELATED_DOCUMENTS_NATS_SUBJECT,
+            related_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_RELATED_DOCUMENTS] NATS request for related documents failed (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(format!(
+                "Failed to get related documents from knowledge graph service: {}",
+                e
+            )));
+        }
+        Err(_) => {
+            error!(
+                "[API_RELATED_DOCUMENTS] NATS request for related documents timed out after 10 seconds (req_id: {})",
+                request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(
+                "Timeout: Failed to get related documents from knowledge graph service within 10 seconds"
+                    .to_string(),
+            ));
+        }
+    };
+
+    let related_result: RelatedDocumentsResult = match Envelope::from_slice(
+        &related_response_msg.payload,
+    )
+    .map(|envelope| envelope.payload)
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!(
+                "[API_RELATED_DOCUMENTS] Failed to deserialize RelatedDocumentsResult (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to parse knowledge graph service response".to_string(),
+            ));
+        }
+    };
+
+    if let Some(err_msg) = related_result.error_message {
+        error!(
+            "[API_RELATED_DOCUMENTS] Knowledge graph service returned error (req_id: {}): {}",
+            request_id, err_msg
+        );
+        return HttpResponse::InternalServerError().json(error_response(format!(
+            "Error from knowledge graph service: {}",
+            err_msg
+        )));
+    }
+
+    HttpResponse::Ok().json(RelatedDocumentsApiResponse {
+        document_id,
+        documents: related_result.documents,
+        error_message: None,
+    })
+}
+
+/// Forwards a read-only Cypher query to the knowledge graph service together with the
+/// caller's `X-Admin-Token`, which that service checks.
+async fn admin_cypher_handler(
+    req: HttpRequest,
+    body: web::Json<GraphCypherApiRequest>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = RequestId::generate();
+    let body = body.into_inner();
+
+    info!(
+        "[API_ADMIN_CYPHER] Forwarding Cypher query (req_id: {}, max_rows: {:?})",
+        request_id, body.max_rows
+    );
+
+    let error_response = |message: String| GraphCypherResult {
+        request_id,
+        rows: vec![],
+        truncated: false,
+        duration_ms: 0,
+        error_message: Some(message),
+    };
+
+    let cypher_task = GraphCypherTask {
+        request_id,
+        query: body.query,
+        params: body.params,
+        max_rows: body.max_rows,
+        timeout_ms: body.timeout_ms,
+        admin_token: req
+            .headers()
+            .get(ADMIN_TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+    };
+
+    let cypher_task_payload_json = match Envelope::new(SERVICE_NAME, &cypher_task).to_vec() {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_ADMIN_CYPHER] Failed to serialize GraphCypherTask (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(error_response(
+                "Internal error: Failed to prepare Cypher task".to_string(),
+            ));
+        }
+    };
+
+    let cypher_response_msg = match tokio::time::timeout(
+        Duration::from_secs(30),
+        request_guarded(
+            &app_state.nats_client,
+            GRAPH_CYPHER_NATS_SUBJECT,
+            cypher_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_ADMIN_CYPHER] NATS request for Cypher query failed (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(format!(
+                "Failed to run query on knowledge graph service: {}",
+                e
+            )));
+        }
+        Err(_) => {
+            error!(
+                "[API_ADMIN_CYPHER] NATS request for Cypher query timed out after 30 seconds (req_id: {})",
+                request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(error_response(
+                "Timeout: Knowledge graph service did not answer within 30 seconds".to_string(),
+            ));
+        }
+    };
+
+    let cypher_result: GraphCypherResult =
+        match Envelope::from_slice(&cypher_response_msg.payload).map(|envelope| envelope.payload) {
+            Ok(res) => res,
+            Err(e) => {
+                error!(
+                    "[API_ADMIN_CYPHER] Failed to deserialize GraphCypherResult (req_id: {}): {}",
+                    request_id, e
+                );
+                return HttpResponse::InternalServerError().json(error_response(
+                    "Internal error: Failed to parse knowledge graph service response".to_string(),
+                ));
+            }
+        };
+
+    if let Some(err_msg) = &cypher_result.error_message {
+        warn!(
+            "[API_ADMIN_CYPHER] Knowledge graph service rejected or failed the query (req_id: {}): {}",
+            request_id, err_msg
+        );
+        return HttpResponse::BadRequest().json(cypher_result);
+    }
+
+    HttpResponse::Ok().json(cypher_result)
+}
+
+async fn admin_stats_handler(
+    query: web::Query<AdminStatsQuery>,
+    app_state: web::Data<AppState>,
+) -> impl Responder {
+    let request_id = RequestId::generate();
+    let query = query.into_inner();
+
+    info!(
+        "[API_ADMIN_STATS] Collecting stats (req_id: {}, model: {:?})",
+        request_id, query.model_name
+    );
+
+    // Collected alongside the vector stats; a slow or failing service only affects its own
+    // section of the response.
+    let graph_stats = tokio::spawn(knowledge_graph_stats(
+        Arc::clone(&app_state.nats_client),
+        request_id,
+    ));
+    let generator_stats = tokio::spawn(text_generator_stats(
+        Arc::clone(&app_state.nats_client),
+        request_id,
+    ));
+
+    let stats_task = VectorStatsTask {
+        request_id,
+        model_name: query.model_name,
+    };
+
+    let stats_task_payload_json = match Envelope::new(SERVICE_NAME, &stats_task).to_vec() {
+        Ok(json) => json,
+        Err(e) => {
+            error!(
+                "[API_ADMIN_STATS] Failed to serialize VectorStatsTask (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(AdminStatsApiResponse {
+                vector_memory: vec![],
+                knowledge_graph: None,
+                text_generator: None,
+                error_message: Some("Internal error: Failed to prepare stats task".to_string()),
+            });
+        }
+    };
+
+    let stats_response_msg = match tokio::time::timeout(
+        Duration::from_secs(10),
+        request_guarded(
+            &app_state.nats_client,
+            VECTOR_STATS_NATS_SUBJECT,
+            stats_task_payload_json,
+        ),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_ADMIN_STATS] NATS request for vector stats failed (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::ServiceUnavailable().json(AdminStatsApiResponse {
+                vector_memory: vec![],
+                knowledge_graph: None,
+                text_generator: None,
+                error_message: Some(format!(
+                    "Failed to get stats from vector memory service: {}",
+                    e
+                )),
+            });
+        }
+        Err(_) => {
+            error!(
+                "[API_ADMIN_STATS] NATS request for vector stats timed out after 10 seconds (req_id: {})",
+                request_id
+            );
+            return HttpResponse::ServiceUnavailable().json(AdminStatsApiResponse {
+                vector_memory: vec![],
+                knowledge_graph: None,
+                text_generator: None,
+                error_message: Some(
+                    "Timeout: Failed to get stats from vector memory service within 10 seconds"
+                        .to_string(),
+                ),
+            });
+        }
+    };
+
+    let stats_result: VectorStatsResult = match Envelope::from_slice(&stats_response_msg.payload)
+        .map(|envelope| envelope.payload)
+    {
+        Ok(res) => res,
+        Err(e) => {
+            error!(
+                "[API_ADMIN_STATS] Failed to deserialize VectorStatsResult (req_id: {}): {}",
+                request_id, e
+            );
+            return HttpResponse::InternalServerError().json(AdminStatsApiResponse {
+                vector_memory: vec![],
+                knowledge_graph: None,
+                text_generator: None,
+                error_message: Some(
+                    "Internal error: Failed to parse vector memory service response".to_string(),
+                ),
+            });
+        }
+    };
+
+    // Partial results are still useful on an admin dashboard, so a per-collection
+    // error is passed through alongside the stats that were collected.
+    HttpResponse::Ok().json(AdminStatsApiResponse {
+        vector_memory: stats_result.collections,
+        knowledge_graph: graph_stats.await.ok(),
+        text_generator: generator_stats.await.ok(),
+        error_message: stats_result.error_message,
+    })
+}
+
+/// Sends a stats request to one service, with the same 10 second timeout as the vector stats.
+/// Errors are logged and returned as the message to show in that service's section.
+async fn request_service_stats<T: Serialize, R: serde::de::DeserializeOwned>(
+    nats_client: &NatsClient,
+    subject: &str,
+    task: &T,
+    service_name: &str,
+    request_id: RequestId,
+) -> Result<R, String> {
+    let task_payload_json = Envelope::new(SERVICE_NAME, task).to_vec().map_err(|e| {
+        error!(
+            "[API_ADMIN_STATS] Failed to serialize {} stats task (req_id: {}): {}",
+            service_name, request_id, e
+        );
+        format!(
+            "Internal error: Failed to prepare {} stats task",
+            service_name
+        )
+    })?;
+
+    let response_msg = match tokio::time::timeout(
+        Duration::from_secs(10),
+        request_guarded(nats_client, subject, task_payload_json),
+    )
+    .await
+    {
+        Ok(Ok(msg)) => msg,
+        Ok(Err(e)) => {
+            error!(
+                "[API_ADMIN_STATS] NATS request for {} stats failed (req_id: {}): {}",
+                service_name, request_id, e
+            );
+            return Err(format!("Failed to get stats from {}: {}", service_name, e));
+        }
+        Err(_) => {
+            error!(
+                "[API_ADMIN_STATS] NATS request for {} stats timed out after 10 seconds (req_id: {})",
+                service_name, request_id
+            );
+            return Err(format!(
+                "Timeout: Failed to get stats from {} within 10 seconds",
+                service_name
+            ));
+        }
+    };
+
+    Envelope::<R>::from_slice(&response_msg.payload)
+        .map(|envelope| envelope.payload)
+        .map_err(|e| {
+            error!(
+                "[API_ADMIN_STATS] Failed to deserialize {} stats (req_id: {}): {}",
+                service_name, request_id, e
+            );
+            format!("Internal error: Failed to parse {} response", service_name)
+        })
+}
+
+/// Knowledge graph counts for the admin dashboard. Failures are reported in the result's
+/// `error_message` rather than failing the whole stats response.
+async fn knowledge_graph_stats(
+    nats_client: Arc<NatsClient>,
+    request_id: RequestId,
+) -> GraphStatsResult {
+    let stats_task = GraphStatsTask {
+        request_id,
+        max_domains: None,
+    };
+    request_service_stats(
+        &nats_client,
+        GRAPH_STATS_NATS_SUBJECT,
+        &stats_task,
+        "knowledge graph service",
+        request_id,
+    )
+    .await
+    .unwrap_or_else(|message| GraphStatsResult {
+        request_id,
+        node_count: 0,
+        relationship_count: 0,
+        nodes_by_label: vec![],
+        relationships_by_type: vec![],
+        documents_by_domain: vec![],
+        error_message: Some(message),
+    })
+}
+
+/// Markov model size and training state for the admin dashboard, reported like
+/// [`knowledge_graph_stats`].
+async fn text_generator_stats(
+    nats_client: Arc<NatsClient>,
+    request_id: RequestId,
+) -> GeneratorStatsResult {
+    let stats_task = GeneratorStatsTask { request_id };
+    request_service_stats(
+        &nats_client,
+        GENERATOR_STATS_NATS_SUBJECT,
+        &stats_task,
+        "text generator service",
+        request_id,
+    )
+    .await
+    .unwrap_or_else(|message| GeneratorStatsResult {
+        request_id,
+        global: MarkovModelStats::default(),
+        domain_models: 0,
+        document_models: 0,
+        total_estimated_memory_bytes: 0,
+        neural_model: None,
+        error_message: Some(message),
+    })
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    let settings = Settings::load(SERVICE_NAME).map_err(std::io::Error::other)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info", &settings, String::new)
+        .map_err(std::io::Error::other)?;
+    info!("[api_service] Starting Actix Web server...");
+
+    let nats_client = Arc::new(shared_nats::connect(&settings.nats).await.map_err(|e| {
+        error!(
             "[NATS_CONNECT_FAIL] Failed to connect to NATS for API service: {}",
             e
         );
@@ -534,17 +2066,45 @@ async fn main() -> std::io::Result<()> {
     })?);
     info!("[NATS_CONNECT_SUCCESS] API Service connected to NATS.");
 
-    let (sse_tx, _) = broadcast::channel::<String>(32);
+    let jetstream = jetstream::new((*nats_client).clone());
+    PERCEIVE_TASKS_STREAM
+        .ensure(&jetstream)
+        .await
+        .map_err(std::io::Error::other)?;
+    serve_health((*nats_client).clone(), SERVICE_NAME, || async {
+        Vec::new()
+    })
+    .await
+    .map_err(std::io::Error::other)?;
+
+    let quotas = Arc::new(
+        Quotas::from_env((*nats_client).clone(), SERVICE_NAME)
+            .await
+            .map_err(std::io::Error::other)?,
+    );
+
+    let (sse_tx, _) = broadcast::channel::<SseMessage>(32);
 
     let nats_client_for_listener = Arc::clone(&nats_client);
     let sse_tx_for_listener = sse_tx.clone();
     tokio::spawn(async move {
         nats_to_sse_listener(nats_client_for_listener, sse_tx_for_listener).await;
     });
+    tokio::spawn(task_status_to_sse_listener(
+        Arc::clone(&nats_client),
+        sse_tx.clone(),
+    ));
 
-    let server_host = env::var("API_SERVER_HOST").unwrap_or_else(|_| "0.0.0.0".to_string());
-    let server_port_str = env::var("API_SERVER_PORT").unwrap_or_else(|_| "8080".to_string());
-    let server_port = server_port_str.parse::<u16>().unwrap_or(8080);
+    let recent_errors = Arc::new(Mutex::new(VecDeque::with_capacity(
+        RECENT_PIPELINE_ERRORS_CAPACITY,
+    )));
+    tokio::spawn(pipeline_errors_listener(
+        Arc::clone(&nats_client),
+        Arc::clone(&recent_errors),
+    ));
+
+    let server_host = settings.api.host;
+    let server_port = settings.api.port;
 
     info!(
         "[HTTP_SERVER] Starting API HTTP server at http://{}:{}",
@@ -570,14 +2130,42 @@ async fn main() -> std::io::Result<()> {
             .wrap(cors)
             .app_data(web::Data::new(AppState {
                 nats_client: Arc::clone(&nats_client),
+                jetstream: jetstream.clone(),
                 sse_tx: sse_tx.clone(),
+                recent_errors: Arc::clone(&recent_errors),
+                quotas: Arc::clone(&quotas),
             }))
             .service(
                 web::scope("/api")
                     .route("/submit-url", web::post().to(submit_url_handler))
                     .route("/generate-text", web::post().to(generate_text_handler))
+                    .route(
+                        "/tasks/{task_id}/status",
+                        web::get().to(task_status_handler),
+                    )
+                    .route(
+                        "/tasks/{task_id}/history",
+                        web::get().to(task_history_handler),
+                    )
                     .route("/events", web::get().to(sse_events_handler))
-                    .route("/search/semantic", web::post().to(semantic_search_handler)),
+                    .route("/errors", web::get().to(pipeline_errors_handler))
+                    .route("/search/semantic", web::post().to(semantic_search_handler))
+                    .route("/search/recommend", web::post().to(recommend_handler))
+                    .route(
+                        "/documents/{document_id}/sentences",
+                        web::get().to(document_sentences_handler),
+                    )
+                    .route(
+                        "/documents/{document_id}/related",
+                        web::get().to(related_documents_handler),
+                    )
+                    .route("/admin/stats", web::get().to(admin_stats_handler))
+                    .route("/admin/cypher", web::post().to(admin_cypher_handler))
+                    .route("/admin/dead-letters", web::get().to(dead_letters_handler))
+                    .route(
+                        "/admin/dead-letters/{sequence}/replay",
+                        web::post().to(replay_dead_letter_handler),
+                    ),
             )
     })
     .bind((server_host, server_port))?
@@ -0,0 +1,125 @@
+use log::{info, warn};
+use neo4rs::{Error as Neo4jError, Graph, Query, Row};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Instant;
+
+/// Name of the in-memory GDS projection; it only exists while a run is in progress.
+const PROJECTION_NAME: &str = "symbiont_token_graph";
+
+/// Set while an analysis is running; only one run may use the projection at a time.
+static ANALYSIS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Default)]
+pub struct AnalysisSummary {
+    pub nodes_ranked: u64,
+    pub pagerank_iterations: u64,
+    pub community_count: u64,
+    pub modularity: f64,
+    pub duration_ms: u64,
+}
+
+#[derive(Debug)]
+pub enum AnalysisError {
+    AlreadyRunning,
+    Neo4j(Neo4jError),
+}
+
+impl std::fmt::Display for AnalysisError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AnalysisError::AlreadyRunning => write!(f, "a graph analysis is already running"),
+            AnalysisError::Neo4j(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<Neo4jError> for AnalysisError {
+    fn from(e: Neo4jError) -> Self {
+        AnalysisError::Neo4j(e)
+    }
+}
+
+/// Writes `pagerank` and `community` onto Document and Token nodes with the Graph Data Science
+/// library. Both run on the undirected CONTAINS_TOKEN graph weighted by `tf_idf`: central
+/// tokens are the ones shared by many otherwise unrelated documents, and Louvain communities
+/// group documents with the tokens that characterize them.
+pub async fn run_analysis(graph: &Graph) -> Result<AnalysisSummary, AnalysisError> {
+    if ANALYSIS_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(AnalysisError::AlreadyRunning);
+    }
+    let started = Instant::now();
+    let result = run_with_projection(graph).await;
+    if let Err(e) = drop_projection(graph).await {
+        warn!(
+            "[GRAPH_ANALYSIS] Failed to drop GDS projection {}: {}",
+            PROJECTION_NAME, e
+        );
+    }
+    ANALYSIS_RUNNING.store(false, Ordering::SeqCst);
+
+    let mut summary = result?;
+    summary.duration_ms = started.elapsed().as_millis() as u64;
+    info!("[GRAPH_ANALYSIS] Analysis finished: {:?}", summary);
+    Ok(summary)
+}
+
+async fn run_with_projection(graph: &Graph) -> Result<AnalysisSummary, Neo4jError> {
+    // A projection left behind by a crashed run would make the projection below fail.
+    drop_projection(graph).await?;
+
+    graph
+        .run(Query::new(format!(
+            "CALL gds.graph.project('{}', ['Document', 'Token'], \
+             {{CONTAINS_TOKEN: {{orientation: 'UNDIRECTED', properties: {{tf_idf: {{defaultValue: 0.0}}}}}}}})",
+            PROJECTION_NAME
+        )))
+        .await?;
+
+    let mut summary = AnalysisSummary::default();
+
+    let pagerank_row = single_row(
+        graph,
+        format!(
+            "CALL gds.pageRank.write('{}', {{writeProperty: 'pagerank', relationshipWeightProperty: 'tf_idf'}}) \
+             YIELD nodePropertiesWritten, ranIterations \
+             RETURN nodePropertiesWritten, ranIterations",
+            PROJECTION_NAME
+        ),
+    )
+    .await?;
+    if let Some(row) = pagerank_row {
+        summary.nodes_ranked = row.get::<i64>("nodePropertiesWritten").unwrap_or(0).max(0) as u64;
+        summary.pagerank_iterations = row.get::<i64>("ranIterations").unwrap_or(0).max(0) as u64;
+    }
+
+    let louvain_row = single_row(
+        graph,
+        format!(
+            "CALL gds.louvain.write('{}', {{writeProperty: 'community', relationshipWeightProperty: 'tf_idf'}}) \
+             YIELD communityCount, modularity \
+             RETURN communityCount, modularity",
+            PROJECTION_NAME
+        ),
+    )
+    .await?;
+    if let Some(row) = louvain_row {
+        summary.community_count = row.get::<i64>("communityCount").unwrap_or(0).max(0) as u64;
+        summary.modularity = row.get::<f64>("modularity").unwrap_or(0.0);
+    }
+
+    Ok(summary)
+}
+
+async fn drop_projection(graph: &Graph) -> Result<(), Neo4jError> {
+    graph
+        .run(Query::new(format!(
+            "CALL gds.graph.drop('{}', false) YIELD graphName RETURN graphName",
+            PROJECTION_NAME
+        )))
+        .await
+}
+
+async fn single_row(graph: &Graph, query_str: String) -> Result<Option<Row>, Neo4jError> {
+    let mut stream = graph.execute(Query::new(query_str)).await?;
+    stream.next().await
+}
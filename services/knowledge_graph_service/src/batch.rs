@@ -0,0 +1,66 @@
+use crate::metrics;
+use log::info;
+use neo4rs::{Graph, Txn};
+use shared_models::DocumentId;
+use std::time::Instant;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Splits the writes of one document over several transactions: after `batch_size`
+/// checkpoints the current transaction is committed and a new one is started. Documents
+/// smaller than one batch are still written in a single transaction. Create it right before
+/// the first transaction is started so that transaction's latency is measured from its start.
+pub struct WriteBatches<'a> {
+    graph: &'a Graph,
+    original_id: DocumentId,
+    batch_size: usize,
+    pending: usize,
+    committed: u32,
+    transaction_started: Instant,
+}
+
+impl<'a> WriteBatches<'a> {
+    pub fn new(graph: &'a Graph, original_id: DocumentId, batch_size: usize) -> Self {
+        WriteBatches {
+            graph,
+            original_id,
+            batch_size: batch_size.max(1),
+            pending: 0,
+            committed: 0,
+            transaction_started: Instant::now(),
+        }
+    }
+
+    /// Counts one written item (`done` of `total` in `phase`) and hands back the transaction
+    /// to keep writing to, which is a fresh one when the previous batch was just committed.
+    pub async fn checkpoint(
+        &mut self,
+        tx: Txn,
+        phase: &str,
+        done: usize,
+        total: usize,
+    ) -> Result<Txn, BoxError> {
+        self.pending += 1;
+        if self.pending < self.batch_size {
+            return Ok(tx);
+        }
+
+        tx.commit().await?;
+        metrics::observe_transaction(self.transaction_started.elapsed());
+        self.pending = 0;
+        self.committed += 1;
+        info!(
+            "[NEO4J_SAVE] Committed batch {} for original_id: {} ({} {}/{})",
+            self.committed, self.original_id, phase, done, total
+        );
+        self.transaction_started = Instant::now();
+        Ok(self.graph.start_txn().await?)
+    }
+
+    /// Commits the last transaction and returns how many transactions the document took.
+    pub async fn finish(self, tx: Txn) -> Result<u32, BoxError> {
+        tx.commit().await?;
+        metrics::observe_transaction(self.transaction_started.elapsed());
+        Ok(self.committed + 1)
+    }
+}
@@ -0,0 +1,158 @@
+use crate::sentences::SentenceDedupScope;
+use log::info;
+pub use shared_config::{env_parse_or, env_var};
+use shared_resilience::RetryPolicy;
+use std::time::Duration;
+
+const DEFAULT_WRITE_RETRY: RetryPolicy =
+    RetryPolicy::new(3, Duration::from_millis(500), Duration::from_secs(10));
+const DEFAULT_CONNECT_RETRY: RetryPolicy =
+    RetryPolicy::new(10, Duration::from_secs(1), Duration::from_secs(30));
+const DEFAULT_HEALTH_CHECK_INTERVAL_SECS: u64 = 15;
+const DEFAULT_HEALTH_CHECK_TIMEOUT_SECS: u64 = 5;
+const DEFAULT_WRITE_MAX_CONCURRENCY: usize = 8;
+const DEFAULT_WRITE_BATCH_SIZE: usize = 1000;
+const DEFAULT_CYPHER_MAX_ROWS: u32 = 1000;
+const DEFAULT_CYPHER_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_SIMILARITY_TOP_TOKENS: u32 = 50;
+const DEFAULT_SIMILARITY_MIN_SHARED_TOKENS: u32 = 3;
+const DEFAULT_SIMILARITY_MIN_SCORE: f64 = 0.1;
+const DEFAULT_SIMILARITY_MAX_LINKS: u32 = 10;
+
+/// How the Neo4j connection is established at startup and watched afterwards.
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectConfig {
+    /// Startup connection attempts; after a lost connection, reconnecting uses the same
+    /// backoff but keeps trying until it succeeds.
+    pub retry: RetryPolicy,
+    /// How often the connection is pinged; `None` disables the health monitor.
+    pub health_check_interval: Option<Duration>,
+    pub health_check_timeout: Duration,
+}
+
+impl ConnectConfig {
+    pub fn from_env() -> Self {
+        let health_check_interval_secs = env_parse_or(
+            "NEO4J_HEALTH_CHECK_INTERVAL_SECS",
+            DEFAULT_HEALTH_CHECK_INTERVAL_SECS,
+        );
+        let config = ConnectConfig {
+            retry: RetryPolicy::from_env("NEO4J_CONNECT", DEFAULT_CONNECT_RETRY),
+            health_check_interval: (health_check_interval_secs > 0)
+                .then(|| Duration::from_secs(health_check_interval_secs)),
+            health_check_timeout: Duration::from_secs(
+                env_parse_or(
+                    "NEO4J_HEALTH_CHECK_TIMEOUT_SECS",
+                    DEFAULT_HEALTH_CHECK_TIMEOUT_SECS,
+                )
+                .max(1),
+            ),
+        };
+
+        info!("[CONFIG] Neo4j connection config: {:?}", config);
+        config
+    }
+}
+
+/// How documents are written to Neo4j and how transient write failures are retried before
+/// the message is dead-lettered.
+#[derive(Debug, Clone, Copy)]
+pub struct WriteConfig {
+    pub retry: RetryPolicy,
+    /// Most documents saved at the same time; further messages wait for a free slot.
+    pub max_concurrency: usize,
+    /// Sentences and tokens written per transaction before it is committed and a new one
+    /// is started, so a very large document is saved in several transactions.
+    pub batch_size: usize,
+    pub sentence_dedup: SentenceDedupScope,
+}
+
+impl WriteConfig {
+    pub fn from_env() -> Self {
+        let config = WriteConfig {
+            retry: RetryPolicy::from_env("NEO4J_WRITE", DEFAULT_WRITE_RETRY),
+            max_concurrency: env_parse_or(
+                "NEO4J_WRITE_MAX_CONCURRENCY",
+                DEFAULT_WRITE_MAX_CONCURRENCY,
+            )
+            .max(1),
+            batch_size: env_parse_or("NEO4J_WRITE_BATCH_SIZE", DEFAULT_WRITE_BATCH_SIZE).max(1),
+            sentence_dedup: env_parse_or("KG_SENTENCE_DEDUP_SCOPE", SentenceDedupScope::Global),
+        };
+
+        info!("[CONFIG] Neo4j write config: {:?}", config);
+        config
+    }
+}
+
+/// How SIMILAR_TO edges are computed when a document is saved.
+#[derive(Debug, Clone, Copy)]
+pub struct SimilarityConfig {
+    /// Only the document's highest tf-idf tokens are matched against other documents.
+    pub top_tokens: u32,
+    pub min_shared_tokens: u32,
+    /// Minimum cosine similarity of the two documents' tf-idf vectors.
+    pub min_score: f64,
+    /// Most SIMILAR_TO edges kept per document; `0` disables linking.
+    pub max_links: u32,
+}
+
+impl SimilarityConfig {
+    pub fn from_env() -> Self {
+        let config = SimilarityConfig {
+            top_tokens: env_parse_or("KG_SIMILARITY_TOP_TOKENS", DEFAULT_SIMILARITY_TOP_TOKENS)
+                .max(1),
+            min_shared_tokens: env_parse_or(
+                "KG_SIMILARITY_MIN_SHARED_TOKENS",
+                DEFAULT_SIMILARITY_MIN_SHARED_TOKENS,
+            )
+            .max(1),
+            min_score: env_parse_or("KG_SIMILARITY_MIN_SCORE", DEFAULT_SIMILARITY_MIN_SCORE),
+            max_links: env_parse_or("KG_SIMILARITY_MAX_LINKS", DEFAULT_SIMILARITY_MAX_LINKS),
+        };
+
+        info!("[CONFIG] Document similarity config: {:?}", config);
+        config
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.max_links > 0
+    }
+}
+
+/// Limits of the `tasks.graph.cypher` passthrough. Without `KG_CYPHER_ADMIN_TOKEN` the
+/// handler rejects every request.
+#[derive(Clone)]
+pub struct CypherConfig {
+    pub admin_token: Option<String>,
+    /// Most rows returned; also the default when a request sets none.
+    pub max_rows: u32,
+    /// Longest a query may run; also the default when a request sets none.
+    pub timeout: Duration,
+}
+
+impl CypherConfig {
+    pub fn from_env() -> Self {
+        let config = CypherConfig {
+            admin_token: env_var("KG_CYPHER_ADMIN_TOKEN").filter(|token| !token.trim().is_empty()),
+            max_rows: env_parse_or("KG_CYPHER_MAX_ROWS", DEFAULT_CYPHER_MAX_ROWS).max(1),
+            timeout: Duration::from_millis(
+                env_parse_or("KG_CYPHER_TIMEOUT_MS", DEFAULT_CYPHER_TIMEOUT_MS).max(1),
+            ),
+        };
+
+        info!("[CONFIG] Cypher passthrough config: {:?}", config);
+        config
+    }
+}
+
+// Keeps the admin token out of the logs.
+impl std::fmt::Debug for CypherConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CypherConfig")
+            .field("enabled", &self.admin_token.is_some())
+            .field("max_rows", &self.max_rows)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
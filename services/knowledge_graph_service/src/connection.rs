@@ -0,0 +1,183 @@
+use crate::config::ConnectConfig;
+use crate::metrics;
+use log::{error, info, warn};
+use neo4rs::{ConfigBuilder, Graph, Query};
+use shared_resilience::retry_with_backoff;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::sync::Notify;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub struct Neo4jSettings {
+    pub uri: String,
+    pub user: String,
+    pub password: String,
+}
+
+/// The Neo4j connection pool shared by all handlers. Handlers take the current [`Graph`] for
+/// every message, so the health monitor can replace a pool whose server went away.
+pub struct Neo4jConnection {
+    settings: Neo4jSettings,
+    config: ConnectConfig,
+    graph: RwLock<Arc<Graph>>,
+    healthy: AtomicBool,
+    recovered: Notify,
+}
+
+impl Neo4jConnection {
+    /// Connects and pings Neo4j, retrying with backoff while it is not reachable yet.
+    /// Client errors (bad credentials, unknown database) fail immediately.
+    pub async fn establish(
+        settings: Neo4jSettings,
+        config: ConnectConfig,
+    ) -> Result<Arc<Self>, BoxError> {
+        info!(
+            "[NEO4J_CONNECT] Attempting to connect to Neo4j at URI: {}, User: {}",
+            settings.uri, settings.user
+        );
+        let (result, attempts) = retry_with_backoff(
+            &config.retry,
+            "Neo4j connection",
+            || connect_and_ping(&settings, &config),
+            |e| metrics::neo4j_error_class(e.as_ref()) != "client",
+        )
+        .await;
+        let graph = result.inspect_err(|e| {
+            error!(
+                "[NEO4J_CONNECT_FATAL] Failed to connect to Neo4j after {} attempt(s): {}",
+                attempts, e
+            );
+        })?;
+        info!("[NEO4J_CONNECT_SUCCESS] Connected to Neo4j.");
+
+        Ok(Arc::new(Neo4jConnection {
+            settings,
+            config,
+            graph: RwLock::new(Arc::new(graph)),
+            healthy: AtomicBool::new(true),
+            recovered: Notify::new(),
+        }))
+    }
+
+    pub fn graph(&self) -> Arc<Graph> {
+        match self.graph.read() {
+            Ok(graph) => Arc::clone(&graph),
+            Err(poisoned) => Arc::clone(&poisoned.into_inner()),
+        }
+    }
+
+    /// One round trip to Neo4j on the current pool, bounded by the health check timeout.
+    pub async fn ping(&self) -> Result<(), BoxError> {
+        ping(&self.graph(), &self.config).await
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+
+    /// Returns once the health monitor considers Neo4j reachable.
+    pub async fn wait_until_healthy(&self) {
+        loop {
+            let recovered = self.recovered.notified();
+            if self.is_healthy() {
+                return;
+            }
+            recovered.await;
+        }
+    }
+
+    /// Pings Neo4j every `health_check_interval`. A failed or timed-out ping marks the
+    /// connection unhealthy and reconnects with backoff until a new pool answers.
+    pub async fn run_health_monitor(self: Arc<Self>) {
+        let Some(interval) = self.config.health_check_interval else {
+            info!("[NEO4J_HEALTH] Health monitor disabled.");
+            return;
+        };
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            match ping(&self.graph(), &self.config).await {
+                Ok(()) => continue,
+                Err(e) => {
+                    metrics::neo4j_error(metrics::neo4j_error_class(e.as_ref()));
+                    self.healthy.store(false, Ordering::Release);
+                    error!(
+                        "[NEO4J_CONNECTION_LOST] Neo4j health check failed: {}. Pausing writes and reconnecting...",
+                        e
+                    );
+                }
+            }
+
+            self.reconnect().await;
+            self.healthy.store(true, Ordering::Release);
+            self.recovered.notify_waiters();
+            ticker.reset();
+        }
+    }
+
+    async fn reconnect(&self) {
+        let mut attempt = 0;
+        loop {
+            match connect_and_ping(&self.settings, &self.config).await {
+                Ok(graph) => {
+                    match self.graph.write() {
+                        Ok(mut current) => *current = Arc::new(graph),
+                        Err(poisoned) => *poisoned.into_inner() = Arc::new(graph),
+                    }
+                    info!(
+                        "[NEO4J_RECONNECTED] Reconnected to Neo4j after {} attempt(s).",
+                        attempt + 1
+                    );
+                    return;
+                }
+                Err(e) => {
+                    let delay = self.config.retry.jittered_backoff(attempt);
+                    warn!(
+                        "[NEO4J_RECONNECT_FAIL] Reconnect attempt {} failed: {}. Retrying in {:?}...",
+                        attempt + 1,
+                        e,
+                        delay
+                    );
+                    attempt = attempt.saturating_add(1);
+                    tokio::time::sleep(delay).await;
+                }
+            }
+        }
+    }
+}
+
+async fn connect_and_ping(
+    settings: &Neo4jSettings,
+    config: &ConnectConfig,
+) -> Result<Graph, BoxError> {
+    let neo4j_config = ConfigBuilder::default()
+        .uri(&settings.uri)
+        .user(&settings.user)
+        .password(&settings.password)
+        .db("neo4j")
+        .fetch_size(500)
+        .max_connections(10)
+        .build()?;
+    let graph = Graph::connect(neo4j_config).await?;
+    // The pool connects lazily, so only a round trip proves the server is reachable.
+    ping(&graph, config).await?;
+    Ok(graph)
+}
+
+async fn ping(graph: &Graph, config: &ConnectConfig) -> Result<(), BoxError> {
+    let round_trip = async {
+        let mut stream = graph.execute(Query::new("RETURN 1".to_string())).await?;
+        while stream.next().await?.is_some() {}
+        Ok::<(), neo4rs::Error>(())
+    };
+    match tokio::time::timeout(config.health_check_timeout, round_trip).await {
+        Ok(result) => Ok(result?),
+        Err(_) => Err(format!(
+            "no answer from Neo4j within {:?}",
+            config.health_check_timeout
+        )
+        .into()),
+    }
+}
@@ -0,0 +1,342 @@
+use log::warn;
+use neo4rs::{BoltType, Graph, Query};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Clauses and commands that write data, change the schema or administer the server.
+const WRITE_KEYWORDS: &[&str] = &[
+    "CREATE",
+    "MERGE",
+    "SET",
+    "DELETE",
+    "DETACH",
+    "REMOVE",
+    "DROP",
+    "FOREACH",
+    "LOAD",
+    "ALTER",
+    "RENAME",
+    "GRANT",
+    "DENY",
+    "REVOKE",
+    "START",
+    "STOP",
+    "TERMINATE",
+    "USE",
+];
+
+/// Procedures that only read. Every other procedure is rejected, since e.g. GDS and APOC
+/// write procedures commit in transactions of their own.
+const READ_ONLY_PROCEDURES: &[&str] = &[
+    "db.labels",
+    "db.relationshiptypes",
+    "db.propertykeys",
+    "db.schema.nodetypeproperties",
+    "db.schema.reltypeproperties",
+    "db.schema.visualization",
+    "db.index.fulltext.querynodes",
+    "db.index.fulltext.queryrelationships",
+];
+
+pub struct CypherRows {
+    pub rows: Vec<Map<String, Value>>,
+    pub truncated: bool,
+}
+
+enum Token {
+    Word(String),
+    Symbol(char),
+}
+
+/// Splits a query into words and symbols, dropping string literals, backtick-quoted names,
+/// comments, and labels/parameters (`:Set`, `$create`) so they cannot trip the keyword check.
+fn tokenize(query: &str) -> Vec<Token> {
+    let chars: Vec<char> = query.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '\'' | '"' | '`' => {
+                i += 1;
+                while i < chars.len() && chars[i] != c {
+                    if chars[i] == '\\' && c != '`' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                i += 1;
+            }
+            '/' if chars.get(i + 1) == Some(&'/') => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if chars.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i < chars.len() && !(chars[i] == '*' && chars.get(i + 1) == Some(&'/')) {
+                    i += 1;
+                }
+                i += 2;
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len()
+                    && (chars[i].is_alphanumeric() || chars[i] == '_' || chars[i] == '.')
+                {
+                    i += 1;
+                }
+                let preceded_by = chars[..start]
+                    .iter()
+                    .rev()
+                    .find(|c| !c.is_whitespace())
+                    .copied();
+                if !matches!(preceded_by, Some(':') | Some('$')) {
+                    tokens.push(Token::Word(chars[start..i].iter().collect()));
+                }
+            }
+            c if c.is_whitespace() => i += 1,
+            c => {
+                tokens.push(Token::Symbol(c));
+                i += 1;
+            }
+        }
+    }
+    tokens
+}
+
+/// Rejects queries that could write: any write clause or admin command, and any `CALL` of a
+/// procedure outside [`READ_ONLY_PROCEDURES`] (`CALL { ... }` subqueries are allowed).
+pub fn check_read_only(query: &str) -> Result<(), String> {
+    let tokens = tokenize(query);
+    if tokens.is_empty() {
+        return Err("query must not be empty".to_string());
+    }
+    for (index, token) in tokens.iter().enumerate() {
+        let Token::Word(word) = token else {
+            continue;
+        };
+        let keyword = word.to_ascii_uppercase();
+        if WRITE_KEYWORDS.contains(&keyword.as_str()) {
+            return Err(format!("'{}' is not allowed in read-only queries", keyword));
+        }
+        if keyword == "CALL" {
+            match tokens.get(index + 1) {
+                Some(Token::Symbol('{')) | Some(Token::Symbol('(')) => {}
+                Some(Token::Word(procedure))
+                    if READ_ONLY_PROCEDURES.contains(&procedure.to_ascii_lowercase().as_str()) => {}
+                Some(Token::Word(procedure)) => {
+                    return Err(format!(
+                        "procedure '{}' is not allowed in read-only queries",
+                        procedure
+                    ));
+                }
+                _ => return Err("CALL must be followed by a procedure or subquery".to_string()),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn json_to_bolt(value: Value) -> BoltType {
+    match value {
+        Value::Null => Option::<i64>::None.into(),
+        Value::Bool(b) => b.into(),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => i.into(),
+            None => n.as_f64().unwrap_or_default().into(),
+        },
+        Value::String(s) => s.into(),
+        Value::Array(items) => items
+            .into_iter()
+            .map(json_to_bolt)
+            .collect::<Vec<BoltType>>()
+            .into(),
+        Value::Object(map) => map
+            .into_iter()
+            .map(|(key, value)| (key, json_to_bolt(value)))
+            .collect::<HashMap<String, BoltType>>()
+            .into(),
+    }
+}
+
+/// Runs a query that passed [`check_read_only`] inside a transaction that is always rolled
+/// back, so nothing it might still write is kept. Reads at most `max_rows` rows and gives up
+/// after `timeout`.
+pub async fn run_read_only(
+    graph: &Graph,
+    query: &str,
+    params: Map<String, Value>,
+    max_rows: usize,
+    timeout: Duration,
+) -> Result<CypherRows, BoxError> {
+    let params: HashMap<String, BoltType> = params
+        .into_iter()
+        .map(|(key, value)| (key, json_to_bolt(value)))
+        .collect();
+
+    let mut txn = graph.start_txn().await?;
+    let read = async {
+        let mut stream = txn
+            .execute(Query::new(query.to_string()).params(params))
+            .await?;
+        let mut result = CypherRows {
+            rows: Vec::new(),
+            truncated: false,
+        };
+        while let Some(row) = stream.next(&mut txn).await? {
+            if result.rows.len() == max_rows {
+                result.truncated = true;
+                break;
+            }
+            result.rows.push(row.to::<Map<String, Value>>()?);
+        }
+        Ok::<CypherRows, BoxError>(result)
+    };
+    match tokio::time::timeout(timeout, read).await {
+        Ok(result) => {
+            if let Err(e) = txn.rollback().await {
+                warn!(
+                    "[CYPHER_HANDLER] Failed to roll back read-only transaction: {}",
+                    e
+                );
+            }
+            result
+        }
+        // The transaction is dropped mid-stream; the pool resets its connection before reuse.
+        Err(_) => Err(format!("query did not finish within {:?}", timeout).into()),
+    }
+}
+
+/// Compares the caller's token without stopping at the first differing byte.
+pub fn admin_token_matches(expected: &str, provided: Option<&str>) -> bool {
+    let Some(provided) = provided else {
+        return false;
+    };
+    expected.len() == provided.len()
+        && expected
+            .bytes()
+            .zip(provided.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rejected(query: &str) -> String {
+        check_read_only(query).expect_err(query)
+    }
+
+    #[test]
+    fn test_read_queries_are_allowed() {
+        for query in [
+            "MATCH (d:Document) RETURN d.original_id LIMIT 10",
+            "MATCH (d:Document)-[:HAS_SENTENCE]->(s) WITH d, count(s) AS n RETURN d, n",
+            "CALL db.labels() YIELD label RETURN label",
+            "CALL db.index.fulltext.queryNodes('sentence_text_fulltext', 'rust') YIELD node RETURN node",
+            "MATCH (d:Document) CALL { WITH d MATCH (d)-->(t:Token) RETURN count(t) AS n } RETURN n",
+        ] {
+            assert_eq!(check_read_only(query), Ok(()), "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_write_procedures_are_rejected() {
+        assert!(rejected("CALL apoc.create.node(['Doc'], {})").contains("apoc.create.node"));
+        assert!(rejected("CALL gds.pageRank.write('g', {})").contains("gds.pageRank.write"));
+        assert!(
+            rejected("MATCH (n) CALL db.createLabel('Leak') RETURN n").contains("db.createLabel")
+        );
+        assert_eq!(
+            rejected("MATCH (n) CALL"),
+            "CALL must be followed by a procedure or subquery"
+        );
+    }
+
+    #[test]
+    fn test_load_csv_and_foreach_are_rejected() {
+        assert_eq!(
+            rejected("LOAD CSV FROM 'file:///x.csv' AS row RETURN row"),
+            "'LOAD' is not allowed in read-only queries"
+        );
+        assert_eq!(
+            rejected("MATCH p = ()-->() FOREACH (n IN nodes(p) | SET n.seen = true)"),
+            "'FOREACH' is not allowed in read-only queries"
+        );
+    }
+
+    #[test]
+    fn test_keywords_are_matched_in_any_case() {
+        assert_eq!(
+            rejected("match (n) merge (m:Copy) return m"),
+            "'MERGE' is not allowed in read-only queries"
+        );
+        assert_eq!(
+            rejected("MATCH (n) DeTaCh DeLeTe n"),
+            "'DETACH' is not allowed in read-only queries"
+        );
+        assert!(rejected("call APOC.Create.Node(['Doc'], {})").contains("APOC.Create.Node"));
+    }
+
+    #[test]
+    fn test_chained_statements_are_checked_past_the_first() {
+        assert_eq!(
+            rejected("MATCH (n) RETURN n; MERGE (m:Leak) RETURN m"),
+            "'MERGE' is not allowed in read-only queries"
+        );
+        assert_eq!(
+            rejected("RETURN 1;\nCREATE (n:Leak)"),
+            "'CREATE' is not allowed in read-only queries"
+        );
+    }
+
+    #[test]
+    fn test_keywords_in_comments_strings_and_names_are_ignored() {
+        for query in [
+            "MATCH (n) // MERGE (m) later\nRETURN n",
+            "MATCH (n) /* CREATE (m)\n SET m.x = 1 */ RETURN n",
+            "MATCH (n) WHERE n.text = 'MERGE (m)' RETURN n",
+            "MATCH (n) WHERE n.text = \"it's a DELETE\" RETURN n",
+            "MATCH (n) WHERE n.text = 'don\\'t CREATE' RETURN n",
+            "MATCH (n:Set) RETURN n.`create` AS `MERGE`",
+            "MATCH (n) WHERE n.name = $create RETURN n",
+        ] {
+            assert_eq!(check_read_only(query), Ok(()), "{}", query);
+        }
+    }
+
+    /// The string literal is dropped before the keyword check, so `CREATE` inside it is not
+    /// mistaken for the clause, while the same word outside a literal still is.
+    #[test]
+    fn test_create_in_a_string_literal_is_not_a_false_positive() {
+        assert_eq!(
+            check_read_only("MATCH (d:Document) WHERE d.title CONTAINS 'CREATE' RETURN d"),
+            Ok(())
+        );
+        assert_eq!(
+            rejected("MATCH (d:Document) WHERE d.title CONTAINS 'x' CREATE (n) RETURN d"),
+            "'CREATE' is not allowed in read-only queries"
+        );
+    }
+
+    #[test]
+    fn test_empty_queries_are_rejected() {
+        assert_eq!(rejected(""), "query must not be empty");
+        assert_eq!(rejected("  // only a comment"), "query must not be empty");
+    }
+
+    #[test]
+    fn test_admin_token_must_match_exactly() {
+        assert!(admin_token_matches("s3cret-token", Some("s3cret-token")));
+        assert!(!admin_token_matches("s3cret-token", Some("s3cret-tokeN")));
+        assert!(!admin_token_matches("s3cret-token", Some("s3cret-token ")));
+        assert!(!admin_token_matches("s3cret-token", Some("s3cret")));
+        assert!(!admin_token_matches("s3cret-token", Some("")));
+        assert!(!admin_token_matches("s3cret-token", None));
+    }
+}
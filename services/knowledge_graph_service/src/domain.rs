@@ -0,0 +1,106 @@
+use log::info;
+use neo4rs::{BoltType, Error as Neo4jError, Graph, Query};
+use shared_models::DocumentId;
+use std::collections::HashMap;
+use url::Url;
+
+const BACKFILL_BATCH_SIZE: i64 = 1000;
+
+/// Lowercased host of `source_url` without a leading `www.`, so `https://www.example.com/a`
+/// and `http://example.com/b` group under one `(:Domain {host: "example.com"})`.
+pub fn host_of(source_url: &str) -> Option<String> {
+    let url = Url::parse(source_url.trim()).ok()?;
+    let host = url.host_str()?.trim_end_matches('.').to_lowercase();
+    let host = host.strip_prefix("www.").unwrap_or(&host).to_string();
+    (!host.is_empty()).then_some(host)
+}
+
+/// Points the document's PUBLISHED_ON edge at the Domain of its current `source_url`,
+/// removing the edge left by a previous URL.
+pub fn published_on_query(original_id: DocumentId, source_url: &str) -> Query {
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("original_id".to_string(), original_id.to_string().into());
+    let mut query_str = "MATCH (d:Document {original_id: $original_id}) \
+                         OPTIONAL MATCH (d)-[old:PUBLISHED_ON]->(:Domain) \
+                         DELETE old"
+        .to_string();
+    if let Some(host) = host_of(source_url) {
+        query_str.push_str(
+            " WITH DISTINCT d \
+             MERGE (dom:Domain {host: $host}) \
+             ON CREATE SET dom.created_at_ms = timestamp() \
+             MERGE (d)-[:PUBLISHED_ON]->(dom)",
+        );
+        params.insert("host".to_string(), host.into());
+    }
+    Query::new(query_str).params(params)
+}
+
+/// Links Documents written before Domain nodes existed. Walks documents in `original_id`
+/// order so documents with an unparsable `source_url` are not fetched again.
+pub async fn backfill_domains(graph: &Graph) -> Result<u64, Neo4jError> {
+    let mut after = String::new();
+    let mut linked = 0u64;
+    loop {
+        let mut params: HashMap<String, BoltType> = HashMap::new();
+        params.insert("after".to_string(), after.as_str().into());
+        params.insert("batch_size".to_string(), BACKFILL_BATCH_SIZE.into());
+        let mut stream = graph
+            .execute(
+                Query::new(
+                    "MATCH (d:Document) WHERE d.original_id > $after AND NOT (d)-[:PUBLISHED_ON]->(:Domain) \
+                     RETURN d.original_id AS original_id, coalesce(d.source_url, '') AS source_url \
+                     ORDER BY original_id LIMIT $batch_size"
+                        .to_string(),
+                )
+                .params(params),
+            )
+            .await?;
+
+        let mut batch: Vec<BoltType> = Vec::new();
+        let mut fetched = 0;
+        while let Some(row) = stream.next().await? {
+            fetched += 1;
+            let (Ok(original_id), Ok(source_url)) = (
+                row.get::<String>("original_id"),
+                row.get::<String>("source_url"),
+            ) else {
+                continue;
+            };
+            if let Some(host) = host_of(&source_url) {
+                let mut entry: HashMap<String, BoltType> = HashMap::new();
+                entry.insert("original_id".to_string(), original_id.as_str().into());
+                entry.insert("host".to_string(), host.into());
+                batch.push(entry.into());
+            }
+            after = original_id;
+        }
+
+        if !batch.is_empty() {
+            linked += batch.len() as u64;
+            let mut write_params: HashMap<String, BoltType> = HashMap::new();
+            write_params.insert("rows".to_string(), batch.into());
+            graph
+                .run(
+                    Query::new(
+                        "UNWIND $rows AS row \
+                         MATCH (d:Document {original_id: row.original_id}) \
+                         MERGE (dom:Domain {host: row.host}) \
+                         ON CREATE SET dom.created_at_ms = timestamp() \
+                         MERGE (d)-[:PUBLISHED_ON]->(dom)"
+                            .to_string(),
+                    )
+                    .params(write_params),
+                )
+                .await?;
+        }
+
+        if fetched < BACKFILL_BATCH_SIZE {
+            info!(
+                "[DOMAIN_BACKFILL] Linked {} existing documents to domains.",
+                linked
+            );
+            return Ok(linked);
+        }
+    }
+}
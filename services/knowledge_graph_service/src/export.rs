@@ -0,0 +1,253 @@
+use neo4rs::{BoltType, Graph, Query, Row};
+use serde_json::{Map, Value};
+use shared_models::{DocumentId, GraphExportEdge, GraphExportNode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Relationships of one document: its outgoing HAS_SENTENCE, FIRST_SENTENCE and
+/// CONTAINS_TOKEN edges plus the NEXT edges scoped to it.
+const DOCUMENT_EXPORT_QUERY: &str = "MATCH (d:Document {original_id: $original_id}) \
+                                     WHERE d.tenant_id = $tenant_id OR ($tenant_id IS NULL AND d.tenant_id IS NULL) \
+                                     CALL { \
+                                         WITH d MATCH (d)-[r]->() RETURN r \
+                                         UNION \
+                                         WITH d MATCH ()-[r:NEXT {original_id: d.original_id}]->() RETURN r \
+                                     } \
+                                     WITH r ORDER BY elementId(r) SKIP $skip LIMIT $limit \
+                                     WITH r, startNode(r) AS a, endNode(r) AS b \
+                                     RETURN elementId(r) AS rel_id, type(r) AS rel_type, properties(r) AS rel_props, \
+                                            elementId(a) AS source_id, labels(a) AS source_labels, properties(a) AS source_props, \
+                                            elementId(b) AS target_id, labels(b) AS target_labels, properties(b) AS target_props";
+
+/// The relationships of every document of the tenant, as in [`DOCUMENT_EXPORT_QUERY`].
+/// Relationships between shared nodes, e.g. tokens and their lemmas, belong to no tenant
+/// and are left out.
+const GRAPH_EXPORT_QUERY: &str = "MATCH (d:Document) \
+                                  WHERE d.tenant_id = $tenant_id OR ($tenant_id IS NULL AND d.tenant_id IS NULL) \
+                                  CALL { \
+                                      WITH d MATCH (d)-[r]->() RETURN r \
+                                      UNION \
+                                      WITH d MATCH ()-[r:NEXT {original_id: d.original_id}]->() RETURN r \
+                                  } \
+                                  WITH DISTINCT r ORDER BY elementId(r) SKIP $skip LIMIT $limit \
+                                  WITH r, startNode(r) AS a, endNode(r) AS b \
+                                  RETURN elementId(r) AS rel_id, type(r) AS rel_type, properties(r) AS rel_props, \
+                                         elementId(a) AS source_id, labels(a) AS source_labels, properties(a) AS source_props, \
+                                         elementId(b) AS target_id, labels(b) AS target_labels, properties(b) AS target_props";
+
+const DOCUMENT_NODE_QUERY: &str = "MATCH (d:Document {original_id: $original_id}) \
+                                   WHERE d.tenant_id = $tenant_id OR ($tenant_id IS NULL AND d.tenant_id IS NULL) \
+                                   RETURN elementId(d) AS node_id, labels(d) AS node_labels, properties(d) AS node_props";
+
+#[derive(Debug, Default)]
+pub struct ExportPage {
+    pub nodes: Vec<GraphExportNode>,
+    pub edges: Vec<GraphExportEdge>,
+    pub has_more: bool,
+}
+
+/// Reads up to `limit` relationships of the documents of `tenant_id`, or without a tenant
+/// of the documents stored without one, starting at `skip`, together with their endpoints.
+/// Returns `Ok(None)` when `original_id` names a document that does not exist, or belongs
+/// to another tenant.
+pub async fn fetch_page(
+    graph: &Graph,
+    original_id: Option<DocumentId>,
+    tenant_id: Option<&str>,
+    skip: u64,
+    limit: u32,
+) -> Result<Option<ExportPage>, BoxError> {
+    let tenant_id: BoltType = tenant_id.map(str::to_string).into();
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("tenant_id".to_string(), tenant_id.clone());
+    params.insert("skip".to_string(), (skip as i64).into());
+    // One extra row tells whether another page follows.
+    params.insert("limit".to_string(), (limit as i64 + 1).into());
+    let query_str = match original_id {
+        Some(original_id) => {
+            params.insert("original_id".to_string(), original_id.to_string().into());
+            DOCUMENT_EXPORT_QUERY
+        }
+        None => GRAPH_EXPORT_QUERY,
+    };
+
+    let mut stream = graph
+        .execute(Query::new(query_str.to_string()).params(params))
+        .await?;
+
+    let mut page = ExportPage::default();
+    let mut seen_nodes = HashSet::new();
+    while let Some(row) = stream.next().await? {
+        if page.edges.len() == limit as usize {
+            page.has_more = true;
+            break;
+        }
+
+        let source = node_from_row(&row, "source")?;
+        let target = node_from_row(&row, "target")?;
+        page.edges.push(GraphExportEdge {
+            id: row.get("rel_id")?,
+            source: source.id.clone(),
+            target: target.id.clone(),
+            rel_type: row.get("rel_type")?,
+            properties: row.get("rel_props")?,
+        });
+        for node in [source, target] {
+            if seen_nodes.insert(node.id.clone()) {
+                page.nodes.push(node);
+            }
+        }
+    }
+
+    // A document without any relationships still exports its own node.
+    if let Some(original_id) = original_id
+        && skip == 0
+        && page.edges.is_empty()
+    {
+        let mut node_params: HashMap<String, BoltType> = HashMap::new();
+        node_params.insert("original_id".to_string(), original_id.to_string().into());
+        node_params.insert("tenant_id".to_string(), tenant_id);
+        let mut node_stream = graph
+            .execute(Query::new(DOCUMENT_NODE_QUERY.to_string()).params(node_params))
+            .await?;
+        match node_stream.next().await? {
+            Some(row) => page.nodes.push(node_from_row(&row, "node")?),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(page))
+}
+
+fn node_from_row(row: &Row, prefix: &str) -> Result<GraphExportNode, BoxError> {
+    Ok(GraphExportNode {
+        id: row.get(&format!("{}_id", prefix))?,
+        labels: row.get(&format!("{}_labels", prefix))?,
+        properties: row.get(&format!("{}_props", prefix))?,
+    })
+}
+
+/// Renders nodes and edges as a directed GraphML document (loadable in Gephi, yEd, networkx).
+/// Every property becomes a `<key>`; its `attr.type` is inferred from the values seen.
+pub fn render_graphml(nodes: &[GraphExportNode], edges: &[GraphExportEdge]) -> String {
+    let node_keys = property_types(nodes.iter().map(|node| &node.properties));
+    let edge_keys = property_types(edges.iter().map(|edge| &edge.properties));
+
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n");
+    out.push_str("  <key id=\"labels\" for=\"node\" attr.name=\"labels\" attr.type=\"string\"/>\n");
+    for (name, attr_type) in &node_keys {
+        let _ = writeln!(
+            out,
+            "  <key id=\"n_{0}\" for=\"node\" attr.name=\"{0}\" attr.type=\"{1}\"/>",
+            xml_escape(name),
+            attr_type
+        );
+    }
+    out.push_str("  <key id=\"type\" for=\"edge\" attr.name=\"type\" attr.type=\"string\"/>\n");
+    for (name, attr_type) in &edge_keys {
+        let _ = writeln!(
+            out,
+            "  <key id=\"e_{0}\" for=\"edge\" attr.name=\"{0}\" attr.type=\"{1}\"/>",
+            xml_escape(name),
+            attr_type
+        );
+    }
+
+    out.push_str("  <graph id=\"G\" edgedefault=\"directed\">\n");
+    for node in nodes {
+        let _ = writeln!(out, "    <node id=\"n{}\">", xml_escape(&node.id));
+        let _ = writeln!(
+            out,
+            "      <data key=\"labels\">:{}</data>",
+            xml_escape(&node.labels.join(":"))
+        );
+        render_data(&mut out, "n_", &node.properties);
+        out.push_str("    </node>\n");
+    }
+    for edge in edges {
+        let _ = writeln!(
+            out,
+            "    <edge id=\"e{}\" source=\"n{}\" target=\"n{}\">",
+            xml_escape(&edge.id),
+            xml_escape(&edge.source),
+            xml_escape(&edge.target)
+        );
+        let _ = writeln!(
+            out,
+            "      <data key=\"type\">{}</data>",
+            xml_escape(&edge.rel_type)
+        );
+        render_data(&mut out, "e_", &edge.properties);
+        out.push_str("    </edge>\n");
+    }
+    out.push_str("  </graph>\n</graphml>\n");
+    out
+}
+
+fn render_data(out: &mut String, key_prefix: &str, properties: &Map<String, Value>) {
+    for (name, value) in properties {
+        if value.is_null() {
+            continue;
+        }
+        let text = match value {
+            Value::String(text) => text.clone(),
+            other => other.to_string(),
+        };
+        let _ = writeln!(
+            out,
+            "      <data key=\"{}{}\">{}</data>",
+            key_prefix,
+            xml_escape(name),
+            xml_escape(&text)
+        );
+    }
+}
+
+/// GraphML `attr.type` per property name: `long`, `double` or `boolean` when every
+/// value fits, `string` otherwise (including lists, which are written as JSON).
+fn property_types<'a>(
+    properties: impl Iterator<Item = &'a Map<String, Value>>,
+) -> BTreeMap<&'a str, &'static str> {
+    let mut types: BTreeMap<&'a str, &'static str> = BTreeMap::new();
+    for map in properties {
+        for (name, value) in map {
+            let value_type = match value {
+                Value::Null => continue,
+                Value::Bool(_) => "boolean",
+                Value::Number(number) if number.is_f64() => "double",
+                Value::Number(_) => "long",
+                _ => "string",
+            };
+            types
+                .entry(name.as_str())
+                .and_modify(|current| {
+                    *current = match (*current, value_type) {
+                        (a, b) if a == b => a,
+                        ("long", "double") | ("double", "long") => "double",
+                        _ => "string",
+                    }
+                })
+                .or_insert(value_type);
+        }
+    }
+    types
+}
+
+fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,119 @@
+use log::info;
+use neo4rs::{BoltType, Error as Neo4jError, Graph, Query};
+use rust_stemmers::{Algorithm, Stemmer};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+const BACKFILL_BATCH_SIZE: i64 = 1000;
+
+pub const LANGUAGE_ENGLISH: &str = "en";
+pub const LANGUAGE_RUSSIAN: &str = "ru";
+
+/// Normalized form shared by the inflections of a word, e.g. «собаку» and «собака».
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lemma {
+    pub text: String,
+    pub language: &'static str,
+}
+
+struct Lemmatizer {
+    english: Stemmer,
+    russian: Stemmer,
+}
+
+static LEMMATIZER: LazyLock<Lemmatizer> = LazyLock::new(|| Lemmatizer {
+    english: Stemmer::create(Algorithm::English),
+    russian: Stemmer::create(Algorithm::Russian),
+});
+
+/// Snowball stem of a lowercased token. Cyrillic words use the Russian stemmer and Latin words
+/// the English one; numbers, punctuation and mixed-script tokens have no lemma.
+pub fn lemma_of(token_lc: &str) -> Option<Lemma> {
+    if token_lc.chars().all(is_cyrillic_letter) {
+        let normalized = token_lc.replace('ё', "е");
+        Some(Lemma {
+            text: LEMMATIZER.russian.stem(&normalized).into_owned(),
+            language: LANGUAGE_RUSSIAN,
+        })
+    } else if token_lc
+        .chars()
+        .all(|c| c.is_ascii_alphabetic() || c == '\'')
+        && token_lc.chars().any(|c| c.is_ascii_alphabetic())
+    {
+        Some(Lemma {
+            text: LEMMATIZER.english.stem(token_lc).into_owned(),
+            language: LANGUAGE_ENGLISH,
+        })
+    } else {
+        None
+    }
+}
+
+fn is_cyrillic_letter(c: char) -> bool {
+    matches!(c, 'а'..='я' | 'ё' | 'А'..='Я' | 'Ё')
+}
+
+/// Links Token nodes written before lemmatization existed to their Lemma. Walks tokens in
+/// `text_lc` order so tokens without a lemma are not fetched again.
+pub async fn backfill_lemmas(graph: &Graph) -> Result<u64, Neo4jError> {
+    let mut after = String::new();
+    let mut linked = 0u64;
+    loop {
+        let mut params: HashMap<String, BoltType> = HashMap::new();
+        params.insert("after".to_string(), after.as_str().into());
+        params.insert("batch_size".to_string(), BACKFILL_BATCH_SIZE.into());
+        let mut stream = graph
+            .execute(
+                Query::new(
+                    "MATCH (t:Token) WHERE t.text_lc > $after AND NOT (t)-[:HAS_LEMMA]->(:Lemma) \
+                     RETURN t.text_lc AS text_lc ORDER BY text_lc LIMIT $batch_size"
+                        .to_string(),
+                )
+                .params(params),
+            )
+            .await?;
+
+        let mut batch: Vec<BoltType> = Vec::new();
+        let mut fetched = 0;
+        while let Some(row) = stream.next().await? {
+            fetched += 1;
+            let Ok(text_lc) = row.get::<String>("text_lc") else {
+                continue;
+            };
+            if let Some(lemma) = lemma_of(&text_lc) {
+                let mut entry: HashMap<String, BoltType> = HashMap::new();
+                entry.insert("text_lc".to_string(), text_lc.as_str().into());
+                entry.insert("lemma".to_string(), lemma.text.into());
+                entry.insert("language".to_string(), lemma.language.into());
+                batch.push(entry.into());
+            }
+            after = text_lc;
+        }
+
+        if !batch.is_empty() {
+            linked += batch.len() as u64;
+            let mut write_params: HashMap<String, BoltType> = HashMap::new();
+            write_params.insert("rows".to_string(), batch.into());
+            graph
+                .run(
+                    Query::new(
+                        "UNWIND $rows AS row \
+                         MATCH (t:Token {text_lc: row.text_lc}) \
+                         MERGE (l:Lemma {text: row.lemma, language: row.language}) \
+                         MERGE (t)-[:HAS_LEMMA]->(l)"
+                            .to_string(),
+                    )
+                    .params(write_params),
+                )
+                .await?;
+        }
+
+        if fetched < BACKFILL_BATCH_SIZE {
+            info!(
+                "[LEMMA_BACKFILL] Linked {} existing tokens to lemmas.",
+                linked
+            );
+            return Ok(linked);
+        }
+    }
+}
@@ -1,12 +1,82 @@
+mod analysis;
+mod batch;
+mod config;
+mod connection;
+mod cypher;
+mod domain;
+mod export;
+mod lemma;
+mod metrics;
+mod related;
+mod search;
+mod sentences;
+mod similarity;
+mod stats;
+mod terms;
+mod versions;
+
+use async_nats::jetstream;
 use futures::StreamExt;
-use std::{collections::HashMap, env, sync::Arc, time::Duration};
+use std::{
+    collections::{BTreeMap, HashMap},
+    future::Future,
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
 
+use config::{ConnectConfig, CypherConfig, SimilarityConfig, WriteConfig, env_parse_or};
+use connection::{Neo4jConnection, Neo4jSettings};
 use log::{debug, error, info, warn};
+use sentences::SentenceDedupScope;
 
-use neo4rs::{BoltType, ConfigBuilder, Error as Neo4jError, Graph, Query};
-use shared_models::TokenizedTextMessage;
+use neo4rs::{BoltType, Error as Neo4jError, Graph, Query};
+use shared_config::Settings;
+use shared_models::{
+    DeadLetterMessage, DependencyCheck, DocumentId, Envelope, GraphAnalysisResult,
+    GraphAnalysisTask, GraphCypherResult, GraphCypherTask, GraphDeleteDocumentResult,
+    GraphDeleteDocumentTask, GraphExportFormat, GraphExportResult, GraphExportTask,
+    GraphStatsResult, GraphStatsTask, GraphTermsResult, GraphTermsTask, KeywordSearchResult,
+    KeywordSearchTask, LOG_TEXT_CHARS, PipelineErrorKind, PipelineErrorMessage, PipelineStage,
+    RelatedDocumentsResult, RelatedDocumentsTask, RequestId, TASK_STATUS_EVENT_SUBJECT, TaskStatus,
+    TaskStatusChangedMessage, TokenizedTextMessage, Truncated, UndecodedPayload, sentence_point_id,
+};
+use shared_nats::{
+    ConsumerConfig, DEAD_LETTERS_STREAM, OverflowPolicy, RecentMessages, Shutdown,
+    TOKENIZED_TEXT_STREAM, WorkerPool, dead_letter_undecodable, durable_messages,
+    publish_dead_letter, publish_reply, queue_group_from_env, receive_span, serve_health,
+    subscribe_shared, traced_headers,
+};
+use shared_resilience::{
+    BreakerError, CircuitBreaker, CircuitOpen, InjectedFault, RetryPolicy, retry_with_backoff,
+};
+use tracing::Instrument;
 
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
 const PROCESSED_TEXT_TOKENIZED_SUBJECT: &str = "data.processed_text.tokenized";
+const GRAPH_DELETE_DOCUMENT_TASK_SUBJECT: &str = "tasks.graph.delete_document";
+const KEYWORD_SEARCH_TASK_SUBJECT: &str = "tasks.graph.search.keyword";
+const MAX_KEYWORD_SEARCH_TOP_K: u32 = 100;
+const RELATED_DOCUMENTS_TASK_SUBJECT: &str = "tasks.graph.related_documents";
+const MAX_RELATED_DOCUMENTS_TOP_K: u32 = 100;
+const GRAPH_CYPHER_TASK_SUBJECT: &str = "tasks.graph.cypher";
+const GRAPH_STATS_TASK_SUBJECT: &str = "tasks.graph.stats";
+const MAX_STATS_DOMAINS: u32 = 1000;
+const GRAPH_TERMS_TASK_SUBJECT: &str = "tasks.graph.terms";
+const MAX_GRAPH_TERMS_TOP_K: u32 = 100;
+/// Keyword searches, related documents and term rankings only read one tenant's documents.
+const TENANT_REQUIRED: &str = "A tenant_id is required";
+const GRAPH_ANALYSIS_CONTROL_SUBJECT: &str = "control.graph.analyze";
+const GRAPH_EXPORT_CONTROL_SUBJECT: &str = "control.graph.export";
+const DEFAULT_EXPORT_PAGE_SIZE: u32 = 1000;
+const MAX_EXPORT_PAGE_SIZE: u32 = 10_000;
+const DEFAULT_TFIDF_REFRESH_INTERVAL_SECS: u64 = 3600;
+const DEFAULT_ANALYSIS_INTERVAL_SECS: u64 = 0;
+/// Search, stats and admin requests handled at once unless
+/// `WORKERS_GRAPH_REQUESTS_CONCURRENCY` says otherwise.
+const DEFAULT_REQUEST_WORKERS: usize = 64;
+/// Schema setup at startup: five attempts, three seconds apart.
+const SCHEMA_RETRY: RetryPolicy =
+    RetryPolicy::new(4, Duration::from_secs(3), Duration::from_secs(3));
 
 fn new_boxed_error(message: &str) -> Box<dyn std::error::Error + Send + Sync> {
     #[derive(Debug)]
@@ -20,32 +90,61 @@ fn new_boxed_error(message: &str) -> Box<dyn std::error::Error + Send + Sync> {
     Box::new(StringError(message.to_string()))
 }
 
+/// Writes the document, its sentences and tokens. Large documents are committed in batches
+/// of `write_config.batch_size` sentences/tokens; every step is idempotent, so a retry after
+/// a partially committed save completes it. The document belongs to `tenant_id`, the
+/// tenant of the envelope it came in.
 async fn save_to_neo4j(
     msg: &TokenizedTextMessage,
+    tenant_id: Option<&str>,
     graph: Arc<Graph>,
+    write_config: &WriteConfig,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!(
         "[NEO4J_SAVE] Attempting to save data for original_id: {}",
         msg.original_id
     );
 
+    let mut batches = batch::WriteBatches::new(&graph, msg.original_id, write_config.batch_size);
     let mut tx = graph
         .start_txn()
         .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
 
     let doc_query_str = "MERGE (d:Document {original_id: $original_id}) \
-                         ON CREATE SET d.source_url = $source_url, d.processed_at_ms = $processed_at, d.created_at_ms = timestamp() \
-                         ON MATCH SET d.source_url = $source_url, d.processed_at_ms = $processed_at \
-                         RETURN id(d) AS doc_node_id";
+                         ON CREATE SET d.source_url = $source_url, d.processed_at_ms = $processed_at_ms, d.created_at_ms = timestamp() \
+                         ON MATCH SET d.source_url = $source_url, d.processed_at_ms = $processed_at_ms \
+                         SET d.title = $title, d.language = $language, d.author = $author, \
+                             d.published_at = $published_at, d.content_type = $content_type, \
+                             d.canonical_url = $canonical_url, d.tenant_id = $tenant_id \
+                         RETURN elementId(d) AS doc_element_id";
 
     let mut doc_params: HashMap<String, BoltType> = HashMap::new();
-    doc_params.insert("original_id".to_string(), msg.original_id.clone().into());
+    doc_params.insert(
+        "original_id".to_string(),
+        msg.original_id.to_string().into(),
+    );
     doc_params.insert("source_url".to_string(), msg.source_url.clone().into());
     doc_params.insert(
-        "processed_at".to_string(),
-        msg.timestamp_ms.to_string().into(),
+        "processed_at_ms".to_string(),
+        (msg.timestamp_ms as i64).into(),
+    );
+    doc_params.insert(
+        "tenant_id".to_string(),
+        tenant_id.map(str::to_string).into(),
     );
+    // Undeclared fields are null, which removes what an earlier version of the page declared.
+    let metadata = &msg.metadata;
+    for (key, value) in [
+        ("title", &metadata.title),
+        ("language", &metadata.language),
+        ("author", &metadata.author),
+        ("published_at", &metadata.published_at),
+        ("content_type", &metadata.content_type),
+        ("canonical_url", &metadata.canonical_url),
+    ] {
+        doc_params.insert(key.to_string(), value.clone().into());
+    }
 
     let mut doc_stream = tx
         .execute(Query::new(doc_query_str.to_string()).params(doc_params))
@@ -58,15 +157,51 @@ async fn save_to_neo4j(
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?
         .ok_or_else(|| new_boxed_error("Document node not created/found after MERGE"))?;
 
-    let doc_node_id: i64 = doc_row
-        .get("doc_node_id")
+    let doc_element_id: String = doc_row
+        .get("doc_element_id")
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
 
     info!(
-        "[NEO4J_SAVE] Document node (Neo4j ID: {}) processed for original_id: {}",
-        doc_node_id, msg.original_id
+        "[NEO4J_SAVE] Document node (element ID: {}) processed for original_id: {}",
+        doc_element_id, msg.original_id
+    );
+
+    tx.run(domain::published_on_query(msg.original_id, &msg.source_url))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+    let version_element_id = versions::record_version(&mut tx, msg).await?;
+    if version_element_id.is_some() {
+        info!(
+            "[NEO4J_SAVE] Content changed, linking sentences to DocumentVersion for original_id: {}",
+            msg.original_id
+        );
+    }
+
+    // Sentence nodes can be shared between documents, so NEXT edges are scoped to a document by
+    // `original_id`. The document's sentences and their order always reflect the latest content;
+    // earlier content stays reachable through its DocumentVersion.
+    let clear_order_query_str = "MATCH (d:Document {original_id: $original_id}) \
+                                 OPTIONAL MATCH (d)-[f:FIRST_SENTENCE]->() \
+                                 DELETE f \
+                                 WITH DISTINCT d \
+                                 OPTIONAL MATCH (d)-[h:HAS_SENTENCE]->() \
+                                 DELETE h \
+                                 WITH DISTINCT d \
+                                 OPTIONAL MATCH ()-[n:NEXT {original_id: $original_id}]->() \
+                                 DELETE n";
+
+    let mut clear_order_params: HashMap<String, BoltType> = HashMap::new();
+    clear_order_params.insert(
+        "original_id".to_string(),
+        msg.original_id.to_string().into(),
     );
 
+    tx.run(Query::new(clear_order_query_str.to_string()).params(clear_order_params))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+    let mut previous_sentence: Option<(String, i64)> = None;
     for (sentence_order, sentence_text) in msg.sentences.iter().enumerate() {
         if sentence_text.trim().is_empty() {
             warn!(
@@ -76,20 +211,103 @@ async fn save_to_neo4j(
             continue;
         }
 
-        let sentence_query_str = "MATCH (d:Document) WHERE id(d) = $doc_node_id \
-                                  MERGE (s:Sentence {text: $text}) \
-                                  ON CREATE SET s.created_at_ms = timestamp() \
-                                  MERGE (d)-[r:HAS_SENTENCE {order: $order}]->(s) \
-                                  RETURN id(s) AS sentence_node_id";
+        let mut sentence_query_str = format!(
+            "MATCH (d:Document {{original_id: $original_id}}) \
+             {}\
+             MERGE (d)-[r:HAS_SENTENCE {{order: $order}}]->(s) \
+             SET r.qdrant_point_id = $qdrant_point_id ",
+            write_config.sentence_dedup.merge_clause()
+        );
 
         let mut sentence_params: HashMap<String, BoltType> = HashMap::new();
-        sentence_params.insert("doc_node_id".to_string(), doc_node_id.into());
-        sentence_params.insert("text".to_string(), sentence_text.as_str().into());
+        write_config.sentence_dedup.insert_params(
+            &mut sentence_params,
+            msg.original_id,
+            sentence_text,
+        );
+        sentence_params.insert(
+            "original_id".to_string(),
+            msg.original_id.to_string().into(),
+        );
         sentence_params.insert("order".to_string(), (sentence_order as i64).into());
+        // Sentence nodes can be shared between documents, so the point id lives on the edge.
+        sentence_params.insert(
+            "qdrant_point_id".to_string(),
+            sentence_point_id(msg.original_id, sentence_order as u32).into(),
+        );
+        if let Some(version_element_id) = &version_element_id {
+            sentence_query_str.push_str(
+                "WITH s \
+                 MATCH (v:DocumentVersion) WHERE elementId(v) = $version_element_id \
+                 MERGE (v)-[:HAS_SENTENCE {order: $order}]->(s) ",
+            );
+            sentence_params.insert(
+                "version_element_id".to_string(),
+                version_element_id.as_str().into(),
+            );
+        }
+        sentence_query_str.push_str("RETURN elementId(s) AS sentence_element_id");
+
+        let mut sentence_stream = tx
+            .execute(Query::new(sentence_query_str).params(sentence_params))
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+        let sentence_row = sentence_stream
+            .next(&mut tx)
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?
+            .ok_or_else(|| new_boxed_error("Sentence node not created/found after MERGE"))?;
+
+        let sentence_element_id: String = sentence_row
+            .get("sentence_element_id")
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+        let order_query = match previous_sentence.take() {
+            Some((previous_element_id, previous_order)) => {
+                let next_query_str = "MATCH (prev:Sentence) WHERE elementId(prev) = $prev_element_id \
+                                      MATCH (s:Sentence) WHERE elementId(s) = $sentence_element_id \
+                                      MERGE (prev)-[:NEXT {original_id: $original_id, order: $prev_order}]->(s)";
+
+                let mut next_params: HashMap<String, BoltType> = HashMap::new();
+                next_params.insert("prev_element_id".to_string(), previous_element_id.into());
+                next_params.insert(
+                    "sentence_element_id".to_string(),
+                    sentence_element_id.as_str().into(),
+                );
+                next_params.insert(
+                    "original_id".to_string(),
+                    msg.original_id.to_string().into(),
+                );
+                next_params.insert("prev_order".to_string(), previous_order.into());
+                Query::new(next_query_str.to_string()).params(next_params)
+            }
+            None => {
+                let first_query_str = "MATCH (d:Document {original_id: $original_id}) \
+                                       MATCH (s:Sentence) WHERE elementId(s) = $sentence_element_id \
+                                       MERGE (d)-[:FIRST_SENTENCE]->(s)";
+
+                let mut first_params: HashMap<String, BoltType> = HashMap::new();
+                first_params.insert(
+                    "original_id".to_string(),
+                    msg.original_id.to_string().into(),
+                );
+                first_params.insert(
+                    "sentence_element_id".to_string(),
+                    sentence_element_id.as_str().into(),
+                );
+                Query::new(first_query_str.to_string()).params(first_params)
+            }
+        };
 
-        tx.run(Query::new(sentence_query_str.to_string()).params(sentence_params))
+        tx.run(order_query)
             .await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+        previous_sentence = Some((sentence_element_id, sentence_order as i64));
+        tx = batches
+            .checkpoint(tx, "sentences", sentence_order + 1, msg.sentences.len())
+            .await?;
     }
     info!(
         "[NEO4J_SAVE] All {} sentences processed for document original_id: {}",
@@ -97,6 +315,9 @@ async fn save_to_neo4j(
         msg.original_id
     );
 
+    // Occurrences per lowercased token, keeping the casing of the last occurrence. A BTreeMap
+    // also makes concurrent documents lock shared Token nodes in the same order.
+    let mut token_counts: BTreeMap<String, (&str, i64)> = BTreeMap::new();
     for token_text_original in msg.tokens.iter() {
         let token_text = token_text_original.trim();
         if token_text.is_empty() {
@@ -106,214 +327,1979 @@ async fn save_to_neo4j(
             );
             continue;
         }
-        let token_text_lc = token_text.to_lowercase();
+        let entry = token_counts
+            .entry(token_text.to_lowercase())
+            .or_insert((token_text, 0));
+        *entry = (token_text, entry.1 + 1);
+    }
+    let token_total: i64 = token_counts.values().map(|(_, count)| count).sum();
+
+    // Tokens dropped by a new version of the document lose their edge and one document
+    // of frequency; the remaining edges are updated in place below.
+    let clear_tokens_query_str = "MATCH (d:Document {original_id: $original_id}) \
+                                  SET d.token_count = $token_count \
+                                  WITH d \
+                                  MATCH (d)-[r_ct:CONTAINS_TOKEN]->(t:Token) \
+                                  WHERE NOT t.text_lc IN $token_texts_lc \
+                                  SET t.document_frequency = coalesce(t.document_frequency, 1) - 1 \
+                                  DELETE r_ct";
+
+    let mut clear_tokens_params: HashMap<String, BoltType> = HashMap::new();
+    clear_tokens_params.insert(
+        "original_id".to_string(),
+        msg.original_id.to_string().into(),
+    );
+    clear_tokens_params.insert("token_count".to_string(), token_total.into());
+    clear_tokens_params.insert(
+        "token_texts_lc".to_string(),
+        token_counts.keys().cloned().collect::<Vec<String>>().into(),
+    );
 
-        let token_query_str = "MATCH (d:Document) WHERE id(d) = $doc_node_id \
+    tx.run(Query::new(clear_tokens_query_str.to_string()).params(clear_tokens_params))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+    let mut doc_count_stream = tx
+        .execute(Query::new(
+            "MATCH (d:Document) RETURN count(d) AS doc_count".to_string(),
+        ))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    let doc_count: i64 = match doc_count_stream
+        .next(&mut tx)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?
+    {
+        Some(row) => row
+            .get("doc_count")
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?,
+        None => 1,
+    };
+
+    for (token_index, (token_text_lc, (token_text, token_count))) in token_counts.iter().enumerate()
+    {
+        let mut token_query_str = "MATCH (d:Document {original_id: $original_id}) \
                                MERGE (t:Token {text_lc: $token_text_lc}) \
                                ON CREATE SET t.text_original_case = $token_text_original, t.created_at_ms = timestamp() \
                                ON MATCH SET t.text_original_case = $token_text_original \
-                               MERGE (d)-[r_ct:CONTAINS_TOKEN]->(t)";
+                               MERGE (d)-[r_ct:CONTAINS_TOKEN]->(t) \
+                               ON CREATE SET t.document_frequency = coalesce(t.document_frequency, 0) + 1 \
+                               SET r_ct.count = $count, r_ct.tf = $tf, \
+                                   r_ct.tf_idf = $tf * (log(toFloat($doc_count + 1) / (t.document_frequency + 1)) + 1)"
+            .to_string();
 
         let mut token_params: HashMap<String, BoltType> = HashMap::new();
-        token_params.insert("doc_node_id".to_string(), doc_node_id.into());
+        token_params.insert(
+            "original_id".to_string(),
+            msg.original_id.to_string().into(),
+        );
         token_params.insert("token_text_lc".to_string(), token_text_lc.as_str().into());
-        token_params.insert("token_text_original".to_string(), token_text.into());
+        token_params.insert("token_text_original".to_string(), (*token_text).into());
+        token_params.insert("count".to_string(), (*token_count).into());
+        token_params.insert(
+            "tf".to_string(),
+            (*token_count as f64 / token_total as f64).into(),
+        );
+        token_params.insert("doc_count".to_string(), doc_count.into());
+        if let Some(lemma) = lemma::lemma_of(token_text_lc) {
+            token_query_str.push_str(
+                " MERGE (l:Lemma {text: $lemma, language: $lemma_language}) \
+                 MERGE (t)-[:HAS_LEMMA]->(l)",
+            );
+            token_params.insert("lemma".to_string(), lemma.text.into());
+            token_params.insert("lemma_language".to_string(), lemma.language.into());
+        }
 
-        tx.run(Query::new(token_query_str.to_string()).params(token_params))
+        tx.run(Query::new(token_query_str).params(token_params))
             .await
             .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+        tx = batches
+            .checkpoint(tx, "tokens", token_index + 1, token_counts.len())
+            .await?;
     }
     info!(
-        "[NEO4J_SAVE] All {} tokens processed for document original_id: {}",
+        "[NEO4J_SAVE] All {} tokens ({} distinct) processed for document original_id: {}",
         msg.tokens.len(),
+        token_counts.len(),
         msg.original_id
     );
 
-    tx.commit()
-        .await
-        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    if let Some(version_element_id) = &version_element_id {
+        versions::mark_complete(&mut tx, version_element_id).await?;
+    }
+
+    let transactions = batches.finish(tx).await?;
+    metrics::document_written(
+        msg.sentences
+            .iter()
+            .filter(|sentence| !sentence.trim().is_empty())
+            .count(),
+        token_counts.len(),
+    );
     info!(
-        "[NEO4J_SAVE] Successfully committed transaction for original_id: {}",
-        msg.original_id
+        "[NEO4J_SAVE] Successfully committed {} transaction(s) for original_id: {}",
+        transactions, msg.original_id
     );
     Ok(())
 }
 
-async fn handle_tokenized_text_message(msg: TokenizedTextMessage, graph: Arc<Graph>) {
+/// Connection drops, Neo4j `TransientError`s (deadlocks, lock timeouts, leader switches),
+/// an open [`NEO4J_BREAKER`] and injected faults are worth retrying; anything else would
+/// fail the same way again.
+fn is_transient_neo4j_error(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    if e.is::<CircuitOpen>() || e.is::<InjectedFault>() {
+        return true;
+    }
+    match e.downcast_ref::<Neo4jError>() {
+        Some(Neo4jError::IOError { .. }) | Some(Neo4jError::ConnectionError) => true,
+        Some(neo4j_error) => neo4j_error.to_string().contains("Neo.TransientError"),
+        None => false,
+    }
+}
+
+/// Refuses Neo4j calls for a while once too many fail, configured by the `BREAKER_NEO4J_*`
+/// variables. Schema setup, health checks and scheduled jobs bypass it.
+static NEO4J_BREAKER: LazyLock<CircuitBreaker> =
+    LazyLock::new(|| CircuitBreaker::from_env("neo4j"));
+
+/// Runs a Neo4j call through [`NEO4J_BREAKER`]; a refusal comes back as a [`CircuitOpen`],
+/// an injected fault as an [`InjectedFault`].
+async fn neo4j_call<T>(
+    call: impl Future<Output = Result<T, Box<dyn std::error::Error + Send + Sync>>>,
+) -> Result<T, Box<dyn std::error::Error + Send + Sync>> {
+    NEO4J_BREAKER
+        .call_with(|e| is_neo4j_outage(e.as_ref()), call)
+        .await
+        .map_err(BreakerError::into_error)
+}
+
+/// Whether `e` shows Neo4j unreachable or failing, rather than refusing a query.
+fn is_neo4j_outage(e: &(dyn std::error::Error + Send + Sync + 'static)) -> bool {
+    matches!(metrics::neo4j_error_class(e), "connection" | "database")
+}
+
+/// Reports a message this service gave up on to [`PipelineStage::KnowledgeGraph`]'s error
+/// subject.
+async fn publish_pipeline_error(
+    nats_client: &async_nats::Client,
+    cause: Option<&Envelope<()>>,
+    pipeline_error: PipelineErrorMessage,
+) {
+    let subject = pipeline_error.stage.error_subject();
+    match Envelope::following(cause, SERVICE_NAME, &pipeline_error).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish(subject.clone(), payload_json.into())
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish PipelineErrorMessage to {}: {}",
+                    subject, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize PipelineErrorMessage: {}",
+                e
+            );
+        }
+    }
+}
+
+async fn publish_task_status(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    status: TaskStatusChangedMessage,
+) {
+    match cause.follow_up(SERVICE_NAME, &status).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish_with_headers(
+                    TASK_STATUS_EVENT_SUBJECT,
+                    traced_headers(),
+                    payload_json.into(),
+                )
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish {:?} status of task {}: {}",
+                    status.status, status.task_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize TaskStatusChangedMessage: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Reports a message that could not be saved after retries and dead-letters it for later
+/// replay.
+async fn dead_letter_tokenized(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    msg: TokenizedTextMessage,
+    error_message: String,
+    attempts: u32,
+) {
+    let original_id = msg.original_id;
+    publish_pipeline_error(
+        nats_client,
+        Some(cause),
+        PipelineErrorMessage::new(
+            PipelineStage::KnowledgeGraph,
+            PipelineErrorKind::Storage,
+            error_message.clone(),
+        )
+        .with_original_id(original_id)
+        .with_attempts(attempts),
+    )
+    .await;
+    let dead_letter = DeadLetterMessage::new(
+        PROCESSED_TEXT_TOKENIZED_SUBJECT,
+        msg,
+        error_message,
+        attempts,
+    );
+    publish_dead_letter(
+        &jetstream::new(nats_client.clone()),
+        SERVICE_NAME,
+        Some(cause),
+        &dead_letter,
+    )
+    .await;
+}
+
+async fn handle_tokenized_text_message(
+    msg: TokenizedTextMessage,
+    cause: Envelope<()>,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+    write_config: WriteConfig,
+    similarity_config: SimilarityConfig,
+) {
     info!(
         "[KG_HANDLER] Received TokenizedTextMessage (original_id: {}), {} tokens, {} sentences.",
         msg.original_id,
         msg.tokens.len(),
         msg.sentences.len()
     );
+    let original_id = msg.original_id;
+    let status = |status| {
+        TaskStatusChangedMessage::new(&cause, PipelineStage::KnowledgeGraph, status)
+            .with_original_id(original_id)
+    };
+    publish_task_status(&nats_client, &cause, status(TaskStatus::Started)).await;
+
+    let description = format!("Neo4j save for original_id {}", msg.original_id);
+    let (save_result, attempts) = retry_with_backoff(
+        &write_config.retry,
+        &description,
+        || async {
+            let result = neo4j_call(save_to_neo4j(
+                &msg,
+                cause.tenant_id.as_deref(),
+                Arc::clone(&graph),
+                &write_config,
+            ))
+            .await;
+            if let Err(e) = &result
+                && !e.is::<CircuitOpen>()
+                && !e.is::<InjectedFault>()
+            {
+                metrics::neo4j_error(metrics::neo4j_error_class(e.as_ref()));
+            }
+            result
+        },
+        |e| is_transient_neo4j_error(e.as_ref()),
+    )
+    .await;
+    metrics::add_write_retries(attempts.saturating_sub(1));
 
-    if let Err(e) = save_to_neo4j(&msg, graph).await {
+    if let Err(e) = save_result {
         error!(
-            "[KG_HANDLER_ERROR] Failed to save data to Neo4j for original_id {}: {}",
-            msg.original_id, e
+            "[KG_HANDLER_ERROR] Failed to save data to Neo4j for original_id {} after {} attempt(s): {}",
+            msg.original_id, attempts, e
         );
+        metrics::document_dead_lettered();
+        let failed = status(TaskStatus::Failed).with_detail(e.to_string());
+        publish_task_status(&nats_client, &cause, failed).await;
+        dead_letter_tokenized(&nats_client, &cause, msg, e.to_string(), attempts).await;
+        return;
+    }
+    publish_task_status(&nats_client, &cause, status(TaskStatus::Completed)).await;
+
+    // The document itself is stored at this point; a failed similarity pass is only logged
+    // and is redone the next time the document is saved.
+    if similarity_config.enabled() {
+        match similarity::link_similar_documents(&graph, msg.original_id, &similarity_config).await
+        {
+            Ok(linked) => info!(
+                "[KG_SIMILARITY] Linked original_id {} to {} similar documents.",
+                msg.original_id, linked
+            ),
+            Err(e) => error!(
+                "[KG_SIMILARITY_FAIL] Failed to link similar documents for original_id {}: {}",
+                msg.original_id, e
+            ),
+        }
     }
 }
 
-async fn ensure_schema_internal(graph_client: Arc<Graph>) -> Result<(), Neo4jError> {
-    graph_client
-        .run(Query::new(
-            "CREATE CONSTRAINT IF NOT EXISTS FOR (d:Document) REQUIRE d.original_id IS UNIQUE"
-                .to_string(),
-        ))
-        .await?;
-    graph_client
+/// Recomputes `tf_idf` on every CONTAINS_TOKEN edge against the current document count and
+/// document frequencies. Edges are only updated when their own document is saved, so older
+/// documents drift as the corpus grows without this.
+async fn refresh_tf_idf(graph: &Graph) -> Result<(), Neo4jError> {
+    graph
         .run(Query::new(
-            "CREATE INDEX token_text_lc_index IF NOT EXISTS FOR (t:Token) ON (t.text_lc)"
+            "MATCH (d:Document) WITH count(d) AS doc_count \
+             MATCH ()-[r_ct:CONTAINS_TOKEN]->(t:Token) \
+             CALL { \
+                 WITH r_ct, t, doc_count \
+                 SET r_ct.tf_idf = r_ct.tf * (log(toFloat(doc_count + 1) / (t.document_frequency + 1)) + 1) \
+             } IN TRANSACTIONS OF 10000 ROWS"
                 .to_string(),
         ))
-        .await?;
-    info!("[NEO4J_SCHEMA] Database schema ensured.");
-    Ok(())
+        .await
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    info!("Starting knowledge graph service...");
+async fn run_scheduled_tf_idf_refresh(neo4j: Arc<Neo4jConnection>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        info!("[TFIDF_REFRESH] Recomputing tf_idf on CONTAINS_TOKEN edges...");
+        match refresh_tf_idf(&neo4j.graph()).await {
+            Ok(()) => info!("[TFIDF_REFRESH] tf_idf refreshed."),
+            Err(e) => error!("[TFIDF_REFRESH_FAIL] Failed to refresh tf_idf: {}", e),
+        }
+    }
+}
 
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!("[NATS_CONFIG] NATS_URL not set, defaulting to nats://localhost:4222");
-        "nats://localhost:4222".to_string()
-    });
+async fn run_scheduled_analysis(neo4j: Arc<Neo4jConnection>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        info!("[GRAPH_ANALYSIS] Starting scheduled PageRank/community analysis...");
+        if let Err(e) = analysis::run_analysis(&neo4j.graph()).await {
+            error!("[GRAPH_ANALYSIS_FAIL] Scheduled analysis failed: {}", e);
+        }
+    }
+}
+
+async fn handle_graph_export_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphExportTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphExportTask: {}", e);
+            error!("[EXPORT_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphExportResult {
+                request_id: RequestId::default(),
+                format: GraphExportFormat::default(),
+                nodes: vec![],
+                edges: vec![],
+                graphml: None,
+                next_cursor: None,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "EXPORT_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    let skip = task.cursor.unwrap_or(0);
+    let limit = task
+        .limit
+        .unwrap_or(DEFAULT_EXPORT_PAGE_SIZE)
+        .clamp(1, MAX_EXPORT_PAGE_SIZE);
     info!(
-        "[NATS_CONNECT] Attempting to connect to NATS server at {}...",
-        nats_url
+        "[EXPORT_HANDLER] Processing GraphExportTask (request_id: {}, original_id: {:?}, format: {:?}, cursor: {}, limit: {})",
+        task.request_id, task.original_id, task.format, skip, limit
     );
 
-    let nats_client = Arc::new(match async_nats::connect(&nats_url).await {
-        Ok(client) => {
-            info!("[NATS_CONNECT_SUCCESS] Successfully connected to NATS!");
-            client
-        }
-        Err(err) => {
-            error!("[NATS_CONNECT_FAIL] Failed to connect to NATS: {}", err);
-            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
-        }
-    });
+    let mut result = GraphExportResult {
+        request_id: task.request_id,
+        format: task.format,
+        nodes: vec![],
+        edges: vec![],
+        graphml: None,
+        next_cursor: None,
+        error_message: None,
+    };
 
-    let mut subscriber = match nats_client
-        .subscribe(PROCESSED_TEXT_TOKENIZED_SUBJECT)
-        .await
+    match neo4j_call(export::fetch_page(
+        &graph,
+        task.original_id,
+        cause.tenant_id.as_deref(),
+        skip,
+        limit,
+    ))
+    .await
     {
-        Ok(sub) => {
+        Ok(Some(page)) => {
             info!(
-                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
-                PROCESSED_TEXT_TOKENIZED_SUBJECT
+                "[EXPORT_HANDLER] Exported {} nodes and {} edges for request_id: {}",
+                page.nodes.len(),
+                page.edges.len(),
+                task.request_id
+            );
+            result.next_cursor = page.has_more.then(|| skip + page.edges.len() as u64);
+            match task.format {
+                GraphExportFormat::Json => {
+                    result.nodes = page.nodes;
+                    result.edges = page.edges;
+                }
+                GraphExportFormat::Graphml => {
+                    result.graphml = Some(export::render_graphml(&page.nodes, &page.edges));
+                }
+            }
+        }
+        Ok(None) => {
+            warn!(
+                "[EXPORT_HANDLER] Document not found for original_id: {:?}",
+                task.original_id
             );
-            sub
+            result.error_message = Some(format!(
+                "Document not found: {}",
+                task.original_id
+                    .map(|id| id.to_string())
+                    .unwrap_or_default()
+            ));
         }
-        Err(err) => {
+        Err(e) => {
             error!(
-                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
-                PROCESSED_TEXT_TOKENIZED_SUBJECT, err
+                "[EXPORT_HANDLER_NEO4J_FAIL] Graph export failed for request_id {}: {}",
+                task.request_id, e
             );
-            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+            result.error_message = Some(format!("Graph export failed: {}", e));
         }
-    };
+    }
 
-    let neo4j_uri = env::var("NEO4J_URI").unwrap_or_else(|_| {
-        warn!("[NEO4J_CONFIG] NEO4J_URI not set, defaulting to bolt://localhost:7687");
-        "bolt://localhost:7687".to_string()
-    });
-    let neo4j_user = env::var("NEO4J_USER").unwrap_or_else(|_| {
-        warn!("[NEO4J_CONFIG] NEO4J_USER not set, defaulting to 'neo4j'");
-        "neo4j".to_string()
-    });
-    let neo4j_pass = env::var("NEO4J_PASSWORD").unwrap_or_else(|_| {
-        warn!("[NEO4J_CONFIG] NEO4J_PASSWORD not set. Ensure Neo4j auth is 'none' or provide password.");
-        "".to_string()
-    });
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "EXPORT_HANDLER",
+    )
+    .await;
+    Ok(())
+}
 
-    info!(
-        "[NEO4J_CONNECT] Attempting to connect to Neo4j at URI: {}, User: {}",
-        neo4j_uri, neo4j_user
+/// Detach-deletes the document, its versions and its sentence-order edges, decrements the document frequency
+/// of its tokens and removes the sentences no other document has. Returns the number of deleted
+/// sentences, or `None` when the document does not exist or belongs to another tenant than
+/// `tenant_id`; a request without a tenant only deletes documents stored without one.
+async fn delete_document_from_neo4j(
+    original_id: DocumentId,
+    tenant_id: Option<&str>,
+    graph: &Graph,
+) -> Result<Option<i64>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut tx = graph
+        .start_txn()
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+
+    let mut exists_params: HashMap<String, BoltType> = HashMap::new();
+    exists_params.insert("original_id".to_string(), original_id.to_string().into());
+    exists_params.insert(
+        "tenant_id".to_string(),
+        tenant_id.map(str::to_string).into(),
     );
+    let mut exists_stream = tx
+        .execute(
+            Query::new(
+                "MATCH (d:Document {original_id: $original_id}) \
+                 WHERE d.tenant_id = $tenant_id OR ($tenant_id IS NULL AND d.tenant_id IS NULL) \
+                 RETURN count(d) AS doc_count"
+                    .to_string(),
+            )
+            .params(exists_params),
+        )
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    let doc_count: i64 = match exists_stream
+        .next(&mut tx)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?
+    {
+        Some(row) => row
+            .get("doc_count")
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?,
+        None => 0,
+    };
+    if doc_count == 0 {
+        tx.rollback()
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+        return Ok(None);
+    }
+
+    let cleanup_queries = [
+        "MATCH ()-[n:NEXT {original_id: $original_id}]->() DELETE n",
+        "MATCH (:Document {original_id: $original_id})-[:CONTAINS_TOKEN]->(t:Token) \
+         SET t.document_frequency = coalesce(t.document_frequency, 1) - 1",
+    ];
+    for query_str in cleanup_queries {
+        let mut params: HashMap<String, BoltType> = HashMap::new();
+        params.insert("original_id".to_string(), original_id.to_string().into());
+        tx.run(Query::new(query_str.to_string()).params(params))
+            .await
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    }
+
+    let delete_query_str = "MATCH (d:Document {original_id: $original_id}) \
+                            OPTIONAL MATCH (d)-[:HAS_VERSION]->(v:DocumentVersion) \
+                            WITH d, collect(v) AS versions \
+                            UNWIND [d] + versions AS owner \
+                            OPTIONAL MATCH (owner)-[:HAS_SENTENCE]->(s:Sentence) \
+                            WITH d, versions, collect(DISTINCT s) AS sentences \
+                            FOREACH (v IN versions | DETACH DELETE v) \
+                            DETACH DELETE d \
+                            WITH sentences \
+                            UNWIND sentences AS s \
+                            WITH s WHERE NOT (s)<-[:HAS_SENTENCE]-() \
+                            DETACH DELETE s \
+                            RETURN count(s) AS sentences_deleted";
+
+    let mut delete_params: HashMap<String, BoltType> = HashMap::new();
+    delete_params.insert("original_id".to_string(), original_id.to_string().into());
+    let mut delete_stream = tx
+        .execute(Query::new(delete_query_str.to_string()).params(delete_params))
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    let sentences_deleted: i64 = match delete_stream
+        .next(&mut tx)
+        .await
+        .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?
+    {
+        Some(row) => row
+            .get("sentences_deleted")
+            .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?,
+        None => 0,
+    };
 
-    let config = ConfigBuilder::default()
-        .uri(&neo4j_uri)
-        .user(&neo4j_user)
-        .password(&neo4j_pass)
-        .db("neo4j")
-        .fetch_size(500)
-        .max_connections(10)
-        .build()
+    tx.commit()
+        .await
         .map_err(|e| Box::new(e) as Box<dyn std::error::Error + Send + Sync>)?;
+    Ok(Some(sentences_deleted))
+}
 
-    let graph = Arc::new(Graph::connect(config).await.map_err(|e| {
-        error!("[NEO4J_CONNECT_FAIL] Failed to connect to Neo4j: {:?}", e);
-        Box::new(e) as Box<dyn std::error::Error + Send + Sync>
-    })?);
+async fn handle_graph_delete_document_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphDeleteDocumentTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphDeleteDocumentTask: {}", e);
+            error!("[DELETE_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphDeleteDocumentResult {
+                request_id: RequestId::default(),
+                original_id: DocumentId::default(),
+                deleted: false,
+                sentences_deleted: 0,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "DELETE_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
 
-    const MAX_SCHEMA_RETRIES: u32 = 5;
-    const SCHEMA_RETRY_DELAY_MS: u64 = 3000;
+    info!(
+        "[DELETE_HANDLER] Processing GraphDeleteDocumentTask (request_id: {}, original_id: {})",
+        task.request_id, task.original_id
+    );
 
-    let graph_arc_for_schema = Arc::clone(&graph);
-    tokio::spawn(async move {
-        for attempt in 1..=MAX_SCHEMA_RETRIES {
+    let mut result = GraphDeleteDocumentResult {
+        request_id: task.request_id,
+        original_id: task.original_id,
+        deleted: false,
+        sentences_deleted: 0,
+        error_message: None,
+    };
+
+    match neo4j_call(delete_document_from_neo4j(
+        task.original_id,
+        cause.tenant_id.as_deref(),
+        &graph,
+    ))
+    .await
+    {
+        Ok(Some(sentences_deleted)) => {
+            info!(
+                "[DELETE_HANDLER] Deleted original_id {} and {} orphaned sentences.",
+                task.original_id, sentences_deleted
+            );
+            result.deleted = true;
+            result.sentences_deleted = sentences_deleted.max(0) as u64;
+        }
+        Ok(None) => {
             info!(
-                "[NEO4J_SCHEMA_ATTEMPT] Attempt {} to ensure Neo4j schema...",
-                attempt
+                "[DELETE_HANDLER] Nothing to delete for original_id: {}",
+                task.original_id
+            );
+        }
+        Err(e) => {
+            error!(
+                "[DELETE_HANDLER_NEO4J_FAIL] Failed to delete original_id {}: {}",
+                task.original_id, e
             );
+            result.error_message = Some(format!("Failed to delete document: {}", e));
+        }
+    }
 
-            match ensure_schema_internal(Arc::clone(&graph_arc_for_schema)).await {
-                Ok(_) => {
-                    info!("[NEO4J_SCHEMA_SUCCESS] Neo4j schema ensured successfully.");
-                    return;
-                }
-                Err(e) => {
-                    error!(
-                        "[NEO4J_SCHEMA_FAIL] Failed to ensure Neo4j schema (attempt {}/{}): {:?}. Retrying in {}ms...",
-                        attempt, MAX_SCHEMA_RETRIES, e, SCHEMA_RETRY_DELAY_MS
-                    );
-                    if attempt == MAX_SCHEMA_RETRIES {
-                        error!(
-                            "[NEO4J_SCHEMA_FATAL] Max retries reached for ensuring schema. Service might not work correctly."
-                        );
-                        return;
-                    }
-                    tokio::time::sleep(Duration::from_millis(SCHEMA_RETRY_DELAY_MS)).await;
-                }
-            }
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "DELETE_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn handle_keyword_search_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<KeywordSearchTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize KeywordSearchTask: {}", e);
+            error!("[KEYWORD_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = KeywordSearchResult {
+                request_id: RequestId::default(),
+                results: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "KEYWORD_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
         }
-    });
+    };
 
-    info!("[NATS_LOOP] Waiting for tokenized text messages...");
+    info!(
+        "[KEYWORD_HANDLER] Processing KeywordSearchTask (request_id: {}, top_k: {}, original_id: {:?})",
+        task.request_id, task.top_k, task.original_id
+    );
 
-    while let Some(message) = subscriber.next().await {
-        info!(
-            "[NATS_MSG_RECV] Received message on subject: {}",
-            message.subject
-        );
-        debug!("[NATS_MSG_PAYLOAD] Payload (raw): {:?}", message.payload);
+    let mut result = KeywordSearchResult {
+        request_id: task.request_id,
+        results: vec![],
+        error_message: None,
+    };
 
-        match serde_json::from_slice::<TokenizedTextMessage>(&message.payload) {
-            Ok(tokenized_msg) => {
+    if task.query_text.trim().is_empty() {
+        result.error_message = Some("query_text must not be empty".to_string());
+    } else if let Some(tenant_id) = cause.tenant_id.as_deref() {
+        let top_k = task.top_k.clamp(1, MAX_KEYWORD_SEARCH_TOP_K);
+        match neo4j_call(search::keyword_search(
+            &graph,
+            &task.query_text,
+            top_k,
+            task.original_id,
+            tenant_id,
+        ))
+        .await
+        {
+            Ok(results) => {
                 info!(
-                    "[TASK_DESERIALIZED] Deserialized TokenizedTextMessage (original_id: {})",
-                    tokenized_msg.original_id
+                    "[KEYWORD_HANDLER] Found {} sentences for request_id: {}",
+                    results.len(),
+                    task.request_id
+                );
+                result.results = results;
+            }
+            Err(e) => {
+                error!(
+                    "[KEYWORD_HANDLER_NEO4J_FAIL] Keyword search failed for request_id {}: {}",
+                    task.request_id, e
                 );
+                result.error_message = Some(format!("Keyword search failed: {}", e));
+            }
+        }
+    } else {
+        result.error_message = Some(TENANT_REQUIRED.to_string());
+    }
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "KEYWORD_HANDLER",
+    )
+    .await;
+    Ok(())
+}
 
-                let graph_clone = Arc::clone(&graph);
-                tokio::spawn(async move {
-                    handle_tokenized_text_message(tokenized_msg, graph_clone).await;
-                });
+async fn handle_related_documents_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<RelatedDocumentsTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize RelatedDocumentsTask: {}", e);
+            error!("[RELATED_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = RelatedDocumentsResult {
+                request_id: RequestId::default(),
+                original_id: DocumentId::default(),
+                documents: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "RELATED_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    info!(
+        "[RELATED_HANDLER] Processing RelatedDocumentsTask (request_id: {}, original_id: {}, top_k: {})",
+        task.request_id, task.original_id, task.top_k
+    );
+
+    let mut result = RelatedDocumentsResult {
+        request_id: task.request_id,
+        original_id: task.original_id,
+        documents: vec![],
+        error_message: None,
+    };
+    let Some(tenant_id) = cause.tenant_id.as_deref() else {
+        warn!(
+            "[RELATED_HANDLER] Rejected request_id {}: {}",
+            task.request_id, TENANT_REQUIRED
+        );
+        result.error_message = Some(TENANT_REQUIRED.to_string());
+        publish_reply(
+            &nats_client,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &result,
+            "RELATED_HANDLER",
+        )
+        .await;
+        return Ok(());
+    };
+
+    let top_k = task.top_k.clamp(1, MAX_RELATED_DOCUMENTS_TOP_K);
+    let min_shared_terms = task
+        .min_shared_terms
+        .unwrap_or(related::DEFAULT_MIN_SHARED_TERMS)
+        .max(1);
+    match neo4j_call(related::related_documents(
+        &graph,
+        task.original_id,
+        top_k,
+        min_shared_terms,
+        tenant_id,
+    ))
+    .await
+    {
+        Ok(Some(documents)) => {
+            info!(
+                "[RELATED_HANDLER] Found {} related documents for original_id {} (request_id: {})",
+                documents.len(),
+                task.original_id,
+                task.request_id
+            );
+            result.documents = documents;
+        }
+        Ok(None) => {
+            warn!(
+                "[RELATED_HANDLER] Document {} not found (request_id: {})",
+                task.original_id, task.request_id
+            );
+            result.error_message = Some(format!("Document {} not found", task.original_id));
+        }
+        Err(e) => {
+            error!(
+                "[RELATED_HANDLER_NEO4J_FAIL] Related documents query failed for request_id {}: {}",
+                task.request_id, e
+            );
+            result.error_message = Some(format!("Related documents query failed: {}", e));
+        }
+    }
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "RELATED_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn handle_graph_cypher_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+    cypher_config: Arc<CypherConfig>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphCypherTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphCypherTask: {}", e);
+            error!("[CYPHER_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphCypherResult {
+                request_id: RequestId::default(),
+                rows: vec![],
+                truncated: false,
+                duration_ms: 0,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "CYPHER_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    info!(
+        "[CYPHER_HANDLER] Processing GraphCypherTask (request_id: {}, max_rows: {:?}, timeout_ms: {:?})",
+        task.request_id, task.max_rows, task.timeout_ms
+    );
+    debug!(
+        "[CYPHER_HANDLER] Query for request_id {}: {}",
+        task.request_id, task.query
+    );
+
+    let mut result = GraphCypherResult {
+        request_id: task.request_id,
+        rows: vec![],
+        truncated: false,
+        duration_ms: 0,
+        error_message: None,
+    };
+
+    let rejection = match &cypher_config.admin_token {
+        None => Some("Cypher passthrough is disabled".to_string()),
+        Some(expected) if !cypher::admin_token_matches(expected, task.admin_token.as_deref()) => {
+            warn!(
+                "[CYPHER_HANDLER_UNAUTHORIZED] Rejected request_id {}: missing or wrong admin token",
+                task.request_id
+            );
+            Some("Unauthorized".to_string())
+        }
+        Some(_) => cypher::check_read_only(&task.query)
+            .err()
+            .map(|reason| format!("Query rejected: {}", reason)),
+    };
+
+    if let Some(rejection) = rejection {
+        result.error_message = Some(rejection);
+    } else {
+        let max_rows = task
+            .max_rows
+            .unwrap_or(cypher_config.max_rows)
+            .clamp(1, cypher_config.max_rows);
+        let timeout = task
+            .timeout_ms
+            .map(Duration::from_millis)
+            .unwrap_or(cypher_config.timeout)
+            .min(cypher_config.timeout);
+        let started = Instant::now();
+        match neo4j_call(cypher::run_read_only(
+            &graph,
+            &task.query,
+            task.params,
+            max_rows as usize,
+            timeout,
+        ))
+        .await
+        {
+            Ok(rows) => {
+                info!(
+                    "[CYPHER_HANDLER] Returned {} rows (truncated: {}) for request_id: {}",
+                    rows.rows.len(),
+                    rows.truncated,
+                    task.request_id
+                );
+                result.rows = rows.rows;
+                result.truncated = rows.truncated;
+            }
+            Err(e) => {
+                error!(
+                    "[CYPHER_HANDLER_NEO4J_FAIL] Query failed for request_id {}: {}",
+                    task.request_id, e
+                );
+                result.error_message = Some(format!("Query failed: {}", e));
+            }
+        }
+        result.duration_ms = started.elapsed().as_millis() as u64;
+    }
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "CYPHER_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn handle_graph_terms_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphTermsTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphTermsTask: {}", e);
+            error!("[TERMS_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphTermsResult {
+                request_id: RequestId::default(),
+                terms: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "TERMS_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    info!(
+        "[TERMS_HANDLER] Processing GraphTermsTask (request_id: {}, kind: {:?}, top_k: {}, original_id: {:?})",
+        task.request_id, task.kind, task.top_k, task.original_id
+    );
+
+    let mut result = GraphTermsResult {
+        request_id: task.request_id,
+        terms: vec![],
+        error_message: None,
+    };
+    let Some(tenant_id) = cause.tenant_id.as_deref() else {
+        warn!(
+            "[TERMS_HANDLER] Rejected request_id {}: {}",
+            task.request_id, TENANT_REQUIRED
+        );
+        result.error_message = Some(TENANT_REQUIRED.to_string());
+        publish_reply(
+            &nats_client,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &result,
+            "TERMS_HANDLER",
+        )
+        .await;
+        return Ok(());
+    };
+    let top_k = task.top_k.clamp(1, MAX_GRAPH_TERMS_TOP_K);
+    match neo4j_call(terms::top_terms(
+        &graph,
+        task.original_id,
+        task.kind,
+        top_k,
+        tenant_id,
+    ))
+    .await
+    {
+        Ok(terms) => {
+            info!(
+                "[TERMS_HANDLER] Found {} terms for request_id: {}",
+                terms.len(),
+                task.request_id
+            );
+            result.terms = terms;
+        }
+        Err(e) => {
+            error!(
+                "[TERMS_HANDLER_NEO4J_FAIL] Term ranking failed for request_id {}: {}",
+                task.request_id, e
+            );
+            result.error_message = Some(format!("Term ranking failed: {}", e));
+        }
+    }
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "TERMS_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn handle_graph_stats_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphStatsTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphStatsTask: {}", e);
+            error!("[STATS_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphStatsResult {
+                request_id: RequestId::default(),
+                node_count: 0,
+                relationship_count: 0,
+                nodes_by_label: vec![],
+                relationships_by_type: vec![],
+                documents_by_domain: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "STATS_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    info!(
+        "[STATS_HANDLER] Processing GraphStatsTask (request_id: {})",
+        task.request_id
+    );
+
+    let max_domains = task
+        .max_domains
+        .unwrap_or(stats::DEFAULT_MAX_DOMAINS)
+        .clamp(1, MAX_STATS_DOMAINS);
+    let result = match neo4j_call(stats::collect(&graph, max_domains)).await {
+        Ok(graph_stats) => {
+            info!(
+                "[STATS_HANDLER] Graph has {} nodes and {} relationships (request_id: {})",
+                graph_stats.node_count, graph_stats.relationship_count, task.request_id
+            );
+            GraphStatsResult {
+                request_id: task.request_id,
+                node_count: graph_stats.node_count,
+                relationship_count: graph_stats.relationship_count,
+                nodes_by_label: graph_stats.nodes_by_label,
+                relationships_by_type: graph_stats.relationships_by_type,
+                documents_by_domain: graph_stats.documents_by_domain,
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            error!(
+                "[STATS_HANDLER_NEO4J_FAIL] Failed to collect graph stats for request_id {}: {}",
+                task.request_id, e
+            );
+            GraphStatsResult {
+                request_id: task.request_id,
+                node_count: 0,
+                relationship_count: 0,
+                nodes_by_label: vec![],
+                relationships_by_type: vec![],
+                documents_by_domain: vec![],
+                error_message: Some(format!("Failed to collect graph stats: {}", e)),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "STATS_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn handle_graph_analysis_task(
+    nats_msg: async_nats::Message,
+    graph: Arc<Graph>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let (cause, task) = match Envelope::<GraphAnalysisTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize GraphAnalysisTask: {}", e);
+            error!("[ANALYSIS_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = GraphAnalysisResult {
+                request_id: RequestId::default(),
+                nodes_ranked: 0,
+                pagerank_iterations: 0,
+                community_count: 0,
+                modularity: 0.0,
+                duration_ms: 0,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "ANALYSIS_HANDLER",
+            )
+            .await;
+            return Err(new_boxed_error(&err_msg));
+        }
+    };
+
+    info!(
+        "[ANALYSIS_HANDLER] Processing GraphAnalysisTask (request_id: {})",
+        task.request_id
+    );
+
+    let result = match analysis::run_analysis(&graph).await {
+        Ok(summary) => GraphAnalysisResult {
+            request_id: task.request_id,
+            nodes_ranked: summary.nodes_ranked,
+            pagerank_iterations: summary.pagerank_iterations,
+            community_count: summary.community_count,
+            modularity: summary.modularity,
+            duration_ms: summary.duration_ms,
+            error_message: None,
+        },
+        Err(e) => {
+            error!(
+                "[ANALYSIS_HANDLER_FAIL] Analysis failed for request_id {}: {}",
+                task.request_id, e
+            );
+            GraphAnalysisResult {
+                request_id: task.request_id,
+                nodes_ranked: 0,
+                pagerank_iterations: 0,
+                community_count: 0,
+                modularity: 0.0,
+                duration_ms: 0,
+                error_message: Some(format!("Graph analysis failed: {}", e)),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "ANALYSIS_HANDLER",
+    )
+    .await;
+    Ok(())
+}
+
+async fn ensure_schema_internal(
+    graph_client: Arc<Graph>,
+    sentence_dedup: SentenceDedupScope,
+) -> Result<(), Neo4jError> {
+    graph_client
+        .run(Query::new(
+            "CREATE CONSTRAINT IF NOT EXISTS FOR (d:Document) REQUIRE d.original_id IS UNIQUE"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE INDEX token_text_lc_index IF NOT EXISTS FOR (t:Token) ON (t.text_lc)"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE INDEX next_original_id_index IF NOT EXISTS FOR ()-[n:NEXT]-() ON (n.original_id)"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(sentence_dedup.schema_query().to_string()))
+        .await?;
+    graph_client
+        .run(Query::new(format!(
+            "CREATE FULLTEXT INDEX {} IF NOT EXISTS FOR (s:Sentence) ON EACH [s.text]",
+            search::SENTENCE_FULLTEXT_INDEX
+        )))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE CONSTRAINT IF NOT EXISTS FOR (dom:Domain) REQUIRE dom.host IS UNIQUE"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE CONSTRAINT IF NOT EXISTS FOR (l:Lemma) REQUIRE (l.text, l.language) IS UNIQUE"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE INDEX has_sentence_point_id_index IF NOT EXISTS FOR ()-[h:HAS_SENTENCE]-() ON (h.qdrant_point_id)"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE INDEX document_version_index IF NOT EXISTS FOR (v:DocumentVersion) ON (v.original_id, v.version)"
+                .to_string(),
+        ))
+        .await?;
+    graph_client
+        .run(Query::new(
+            "CREATE INDEX document_tenant_index IF NOT EXISTS FOR (d:Document) ON (d.tenant_id)"
+                .to_string(),
+        ))
+        .await?;
+    for label in ["Document", "Token"] {
+        graph_client
+            .run(Query::new(format!(
+                "CREATE INDEX {}_community_index IF NOT EXISTS FOR (n:{}) ON (n.community)",
+                label.to_lowercase(),
+                label
+            )))
+            .await?;
+    }
+    // Documents written when processed_at_ms was stored as a string.
+    graph_client
+        .run(Query::new(
+            "MATCH (d:Document) WHERE d.processed_at_ms IS :: STRING \
+             SET d.processed_at_ms = toInteger(d.processed_at_ms)"
+                .to_string(),
+        ))
+        .await?;
+    // Tokens written before document frequencies were tracked.
+    graph_client
+        .run(Query::new(
+            "MATCH (t:Token) WHERE t.document_frequency IS NULL \
+             SET t.document_frequency = COUNT { (:Document)-[:CONTAINS_TOKEN]->(t) }"
+                .to_string(),
+        ))
+        .await?;
+    info!("[NEO4J_SCHEMA] Database schema ensured.");
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info", &settings, metrics::render)?;
+    info!("Starting knowledge graph service...");
+    let drain_timeout = settings.shutdown.drain_timeout();
+    let shutdown = Shutdown::listen();
+
+    let nats_client = Arc::new(match shared_nats::connect(&settings.nats).await {
+        Ok(client) => {
+            info!("[NATS_CONNECT_SUCCESS] Successfully connected to NATS!");
+            client
+        }
+        Err(err) => {
+            error!("[NATS_CONNECT_FAIL] Failed to connect to NATS: {}", err);
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    });
+
+    // Long enough to cover a full round of Neo4j write retries before the server redelivers.
+    let consumer_config = ConsumerConfig::new(SERVICE_NAME, PROCESSED_TEXT_TOKENIZED_SUBJECT)
+        .with_ack_wait(Duration::from_secs(120))
+        .with_env_overrides(&TOKENIZED_TEXT_STREAM);
+    let jetstream = jetstream::new((*nats_client).clone());
+    let mut tokenized_messages =
+        durable_messages(&jetstream, &TOKENIZED_TEXT_STREAM, &consumer_config)
+            .await?
+            .take_until(shutdown.signalled());
+    DEAD_LETTERS_STREAM.ensure(&jetstream).await?;
+
+    if settings.neo4j.password.is_empty() {
+        warn!(
+            "[NEO4J_CONFIG] NEO4J_PASSWORD not set. Ensure Neo4j auth is 'none' or provide password."
+        );
+    }
+
+    let neo4j = Neo4jConnection::establish(
+        Neo4jSettings {
+            uri: settings.neo4j.uri.clone(),
+            user: settings.neo4j.user.clone(),
+            password: settings.neo4j.password.clone(),
+        },
+        ConnectConfig::from_env(),
+    )
+    .await?;
+    tokio::spawn(Arc::clone(&neo4j).run_health_monitor());
+
+    let write_config = WriteConfig::from_env();
+    let similarity_config = SimilarityConfig::from_env();
+
+    let analysis_interval_secs =
+        env_parse_or("KG_ANALYSIS_INTERVAL_SECS", DEFAULT_ANALYSIS_INTERVAL_SECS);
+    if analysis_interval_secs > 0 {
+        info!(
+            "[CONFIG] Running graph analysis every {} seconds.",
+            analysis_interval_secs
+        );
+        tokio::spawn(run_scheduled_analysis(
+            Arc::clone(&neo4j),
+            Duration::from_secs(analysis_interval_secs),
+        ));
+    }
+
+    let tf_idf_refresh_secs = env_parse_or(
+        "KG_TFIDF_REFRESH_INTERVAL_SECS",
+        DEFAULT_TFIDF_REFRESH_INTERVAL_SECS,
+    );
+    if tf_idf_refresh_secs > 0 {
+        info!(
+            "[CONFIG] Refreshing tf_idf every {} seconds.",
+            tf_idf_refresh_secs
+        );
+        tokio::spawn(run_scheduled_tf_idf_refresh(
+            Arc::clone(&neo4j),
+            Duration::from_secs(tf_idf_refresh_secs),
+        ));
+    }
+
+    let neo4j_for_schema = Arc::clone(&neo4j);
+    tokio::spawn(async move {
+        let (schema_result, _) = retry_with_backoff(
+            &SCHEMA_RETRY,
+            "Ensuring Neo4j schema",
+            || ensure_schema_internal(neo4j_for_schema.graph(), write_config.sentence_dedup),
+            |_| true,
+        )
+        .await;
+        if let Err(e) = schema_result {
+            error!(
+                "[NEO4J_SCHEMA_FATAL] Failed to ensure Neo4j schema: {:?}. Service might not work correctly.",
+                e
+            );
+            return;
+        }
+        info!("[NEO4J_SCHEMA_SUCCESS] Neo4j schema ensured successfully.");
+        let graph_for_schema = neo4j_for_schema.graph();
+        if let Err(e) = lemma::backfill_lemmas(&graph_for_schema).await {
+            error!(
+                "[LEMMA_BACKFILL_FAIL] Failed to link existing tokens to lemmas: {}",
+                e
+            );
+        }
+        if let Err(e) = domain::backfill_domains(&graph_for_schema).await {
+            error!(
+                "[DOMAIN_BACKFILL_FAIL] Failed to link existing documents to domains: {}",
+                e
+            );
+        }
+    });
+
+    // Shared by every request handler. A refused request is left to time out on the
+    // requester's side.
+    let request_workers = WorkerPool::from_env(
+        "graph_requests",
+        DEFAULT_REQUEST_WORKERS,
+        OverflowPolicy::Reject,
+    );
+
+    // Replicas split requests, so each delete or analysis runs once and is answered once.
+    let request_queue_group = queue_group_from_env("KNOWLEDGE_GRAPH_QUEUE_GROUP", SERVICE_NAME);
+    info!("[NATS_SUB] Request queue group: {:?}", request_queue_group);
+
+    let mut delete_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_DELETE_DOCUMENT_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_DELETE_DOCUMENT_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_DELETE_DOCUMENT_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_delete_task = Arc::clone(&neo4j);
+    let nats_client_for_delete_task = Arc::clone(&nats_client);
+    let shutdown_for_delete_task = shutdown.clone();
+    let workers_for_delete_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = delete_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_delete_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_delete_task);
+            let in_flight = shutdown_for_delete_task.track();
+            let _ = workers_for_delete_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_graph_delete_document_task(message, graph_clone, nats_client_clone)
+                            .await
+                    {
+                        error!("[DELETE_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Document delete subscription ended.");
+    });
+
+    let mut keyword_subscriber = match subscribe_shared(
+        &nats_client,
+        KEYWORD_SEARCH_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                KEYWORD_SEARCH_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                KEYWORD_SEARCH_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_keyword_task = Arc::clone(&neo4j);
+    let nats_client_for_keyword_task = Arc::clone(&nats_client);
+    let shutdown_for_keyword_task = shutdown.clone();
+    let workers_for_keyword_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = keyword_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_keyword_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_keyword_task);
+            let in_flight = shutdown_for_keyword_task.track();
+            let _ = workers_for_keyword_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_keyword_search_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[KEYWORD_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Keyword search subscription ended.");
+    });
+
+    let mut related_subscriber = match subscribe_shared(
+        &nats_client,
+        RELATED_DOCUMENTS_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                RELATED_DOCUMENTS_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                RELATED_DOCUMENTS_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_related_task = Arc::clone(&neo4j);
+    let nats_client_for_related_task = Arc::clone(&nats_client);
+    let shutdown_for_related_task = shutdown.clone();
+    let workers_for_related_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = related_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_related_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_related_task);
+            let in_flight = shutdown_for_related_task.track();
+            let _ = workers_for_related_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_related_documents_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[RELATED_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Related documents subscription ended.");
+    });
+
+    let mut cypher_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_CYPHER_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_CYPHER_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_CYPHER_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let cypher_config = Arc::new(CypherConfig::from_env());
+    let neo4j_for_cypher_task = Arc::clone(&neo4j);
+    let nats_client_for_cypher_task = Arc::clone(&nats_client);
+    let shutdown_for_cypher_task = shutdown.clone();
+    let workers_for_cypher_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = cypher_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_cypher_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_cypher_task);
+            let cypher_config_clone = Arc::clone(&cypher_config);
+            let in_flight = shutdown_for_cypher_task.track();
+            let _ = workers_for_cypher_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_graph_cypher_task(
+                        message,
+                        graph_clone,
+                        nats_client_clone,
+                        cypher_config_clone,
+                    )
+                    .await
+                    {
+                        error!("[CYPHER_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Cypher passthrough subscription ended.");
+    });
+
+    let mut stats_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_STATS_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_STATS_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_STATS_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_stats_task = Arc::clone(&neo4j);
+    let nats_client_for_stats_task = Arc::clone(&nats_client);
+    let shutdown_for_stats_task = shutdown.clone();
+    let workers_for_stats_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = stats_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_stats_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_stats_task);
+            let in_flight = shutdown_for_stats_task.track();
+            let _ = workers_for_stats_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_graph_stats_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[STATS_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Graph stats subscription ended.");
+    });
+
+    let mut terms_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_TERMS_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_TERMS_TASK_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_TERMS_TASK_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_terms_task = Arc::clone(&neo4j);
+    let nats_client_for_terms_task = Arc::clone(&nats_client);
+    let shutdown_for_terms_task = shutdown.clone();
+    let workers_for_terms_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = terms_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_terms_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_terms_task);
+            let in_flight = shutdown_for_terms_task.track();
+            let _ = workers_for_terms_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_graph_terms_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[TERMS_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Graph terms subscription ended.");
+    });
+
+    let mut analysis_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_ANALYSIS_CONTROL_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_ANALYSIS_CONTROL_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_ANALYSIS_CONTROL_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_analysis_task = Arc::clone(&neo4j);
+    let nats_client_for_analysis_task = Arc::clone(&nats_client);
+    let shutdown_for_analysis_task = shutdown.clone();
+    let workers_for_analysis_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = analysis_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_analysis_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_analysis_task);
+            let in_flight = shutdown_for_analysis_task.track();
+            let _ = workers_for_analysis_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_graph_analysis_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[ANALYSIS_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Graph analysis subscription ended.");
+    });
+
+    let mut export_subscriber = match subscribe_shared(
+        &nats_client,
+        GRAPH_EXPORT_CONTROL_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
+                GRAPH_EXPORT_CONTROL_SUBJECT
+            );
+            sub.take_until(shutdown.signalled())
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GRAPH_EXPORT_CONTROL_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error + Send + Sync>);
+        }
+    };
+
+    let neo4j_for_export_task = Arc::clone(&neo4j);
+    let nats_client_for_export_task = Arc::clone(&nats_client);
+    let shutdown_for_export_task = shutdown.clone();
+    let workers_for_export_task = request_workers.clone();
+    tokio::spawn(async move {
+        while let Some(message) = export_subscriber.next().await {
+            info!(
+                "[NATS_MSG_RECV] Received message on subject: {}",
+                message.subject
+            );
+            let graph_clone = neo4j_for_export_task.graph();
+            let nats_client_clone = Arc::clone(&nats_client_for_export_task);
+            let in_flight = shutdown_for_export_task.track();
+            let _ = workers_for_export_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) =
+                        handle_graph_export_task(message, graph_clone, nats_client_clone).await
+                    {
+                        error!("[EXPORT_HANDLER_ERROR] {}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_END] Graph export subscription ended.");
+    });
+
+    // Waiting for a free slot before taking the next message keeps a burst of documents
+    // from opening more Neo4j transactions than the connection pool can serve, so nothing
+    // is queued beyond the running writes.
+    let write_workers = WorkerPool::new(
+        "tokenized_text",
+        write_config.max_concurrency,
+        0,
+        OverflowPolicy::Wait,
+    );
+
+    let neo4j_for_health = Arc::clone(&neo4j);
+    serve_health((*nats_client).clone(), SERVICE_NAME, move || {
+        let neo4j = Arc::clone(&neo4j_for_health);
+        async move {
+            let started = Instant::now();
+            let result = neo4j.ping().await.map_err(|e| e.to_string());
+            vec![DependencyCheck::timed("neo4j", started.elapsed(), result)]
+        }
+    })
+    .await?;
+
+    let recent_tokenized = RecentMessages::from_env();
+    info!("[NATS_LOOP] Waiting for tokenized text messages...");
+
+    while let Some(next) = tokenized_messages.next().await {
+        let message = match next {
+            Ok(message) => message,
+            Err(e) => {
+                error!(
+                    "[NATS_MSG_RECV_FAIL] Failed to receive message from JetStream consumer: {}",
+                    e
+                );
+                continue;
+            }
+        };
+        info!(
+            "[NATS_MSG_RECV] Received message on subject: {}",
+            message.subject
+        );
+        debug!(
+            "[NATS_MSG_PAYLOAD] Payload (raw): {:?}",
+            Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
+        );
+
+        match Envelope::<TokenizedTextMessage>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let (cause, tokenized_msg) = envelope.split();
+                info!(
+                    "[TASK_DESERIALIZED] Deserialized TokenizedTextMessage (original_id: {})",
+                    tokenized_msg.original_id
+                );
+                let Some(claim) = recent_tokenized.claim_delivery(&message, &cause).await else {
+                    continue;
+                };
+
+                if !neo4j.is_healthy() {
+                    warn!(
+                        "[NEO4J_UNAVAILABLE] Neo4j is unreachable; holding original_id {} until it reconnects.",
+                        tokenized_msg.original_id
+                    );
+                }
+                let wait_started = Instant::now();
+                neo4j.wait_until_healthy().await;
+                let Some(worker) = write_workers.reserve_for(&message).await else {
+                    continue;
+                };
+                metrics::observe_write_permit_wait(wait_started.elapsed());
+                let graph_clone = neo4j.graph();
+                let nats_client_clone = Arc::clone(&nats_client);
+                let span = receive_span(&message.subject, message.headers.as_ref());
+                let handling = shutdown.track();
+                worker.spawn(
+                    async move {
+                        let _handling = handling;
+                        let _in_flight = metrics::track_write_in_flight();
+                        handle_tokenized_text_message(
+                            tokenized_msg,
+                            cause,
+                            graph_clone,
+                            nats_client_clone,
+                            write_config,
+                            similarity_config,
+                        )
+                        .await;
+                        // Failed writes are dead-lettered by the handler, so the message is acked
+                        // either way; only a crash before this point gets it redelivered.
+                        if let Err(e) = message.ack().await {
+                            error!(
+                                "[NATS_ACK_FAIL] Failed to ack tokenized text message: {}",
+                                e
+                            );
+                        }
+                        claim.handled();
+                    }
+                    .instrument(span),
+                );
             }
             Err(e) => {
                 error!(
                     "[TASK_DESERIALIZE_FAIL] Failed to deserialize TokenizedTextMessage: {}. Payload: {}",
                     e,
-                    String::from_utf8_lossy(&message.payload)
+                    Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
                 );
+                publish_pipeline_error(
+                    &nats_client,
+                    None,
+                    PipelineErrorMessage::new(
+                        PipelineStage::KnowledgeGraph,
+                        PipelineErrorKind::InvalidMessage,
+                        format!("Invalid TokenizedTextMessage: {}", e),
+                    ),
+                )
+                .await;
+                dead_letter_undecodable(
+                    &jetstream,
+                    SERVICE_NAME,
+                    &message,
+                    UndecodedPayload::new(&message.payload),
+                    e.to_string(),
+                )
+                .await;
             }
         }
     }
 
     info!("[NATS_LOOP_END] Subscription ended or NATS connection lost. Shutting down.");
+    shutdown.drain(&nats_client, drain_timeout).await;
     Ok(())
 }
@@ -0,0 +1,233 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Cumulative: `buckets[i]` counts observations `<= LATENCY_BUCKETS[i]`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (count, upper_bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, upper_bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let braced = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, braced, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braced, self.count);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    transaction_duration: Mutex<Histogram>,
+    write_permit_wait: Mutex<Histogram>,
+    documents_written: AtomicU64,
+    documents_dead_lettered: AtomicU64,
+    sentences_written: AtomicU64,
+    tokens_written: AtomicU64,
+    write_retries: AtomicU64,
+    neo4j_errors: Mutex<BTreeMap<&'static str, u64>>,
+    writes_in_flight: AtomicI64,
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+/// Time from starting a Neo4j write transaction to its commit.
+pub fn observe_transaction(elapsed: Duration) {
+    if let Ok(mut histogram) = REGISTRY.transaction_duration.lock() {
+        histogram.observe(elapsed);
+    }
+}
+
+/// Time a tokenized message waited for a free write slot before its save started.
+pub fn observe_write_permit_wait(elapsed: Duration) {
+    if let Ok(mut histogram) = REGISTRY.write_permit_wait.lock() {
+        histogram.observe(elapsed);
+    }
+}
+
+/// Counts one fully saved document with its sentence and token writes.
+pub fn document_written(sentences: usize, tokens: usize) {
+    REGISTRY.documents_written.fetch_add(1, Ordering::Relaxed);
+    REGISTRY
+        .sentences_written
+        .fetch_add(sentences as u64, Ordering::Relaxed);
+    REGISTRY
+        .tokens_written
+        .fetch_add(tokens as u64, Ordering::Relaxed);
+}
+
+pub fn document_dead_lettered() {
+    REGISTRY
+        .documents_dead_lettered
+        .fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn add_write_retries(count: u32) {
+    REGISTRY
+        .write_retries
+        .fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Counts a failed Neo4j request by error class (see [`neo4j_error_class`]).
+pub fn neo4j_error(class: &'static str) {
+    if let Ok(mut errors) = REGISTRY.neo4j_errors.lock() {
+        *errors.entry(class).or_default() += 1;
+    }
+}
+
+/// Groups an error by the class of its Neo4j status code (`Neo.<Class>.<Category>.<Title>`):
+/// `client`, `transient` or `database`, plus `connection` for dropped connections and
+/// `other` for everything else.
+pub fn neo4j_error_class(e: &(dyn std::error::Error + Send + Sync + 'static)) -> &'static str {
+    match e.downcast_ref::<neo4rs::Error>() {
+        Some(neo4rs::Error::IOError { .. }) | Some(neo4rs::Error::ConnectionError) => "connection",
+        Some(neo4j_error) => {
+            let message = neo4j_error.to_string();
+            if message.contains("Neo.ClientError") {
+                "client"
+            } else if message.contains("Neo.TransientError") {
+                "transient"
+            } else if message.contains("Neo.DatabaseError") {
+                "database"
+            } else {
+                "other"
+            }
+        }
+        None => "other",
+    }
+}
+
+/// Counts a document save as in flight until the returned guard is dropped.
+pub fn track_write_in_flight() -> InFlightGuard {
+    REGISTRY.writes_in_flight.fetch_add(1, Ordering::Relaxed);
+    InFlightGuard
+}
+
+pub struct InFlightGuard;
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        REGISTRY.writes_in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+fn render_counter(out: &mut String, name: &str, help: &str, value: &AtomicU64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value.load(Ordering::Relaxed));
+}
+
+/// Current values in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    out.push_str(
+        "# HELP knowledge_graph_transaction_duration_seconds Latency of Neo4j write transactions, from start to commit.\n",
+    );
+    out.push_str("# TYPE knowledge_graph_transaction_duration_seconds histogram\n");
+    if let Ok(histogram) = REGISTRY.transaction_duration.lock() {
+        histogram.render(&mut out, "knowledge_graph_transaction_duration_seconds", "");
+    }
+
+    out.push_str(
+        "# HELP knowledge_graph_write_permit_wait_seconds Time tokenized messages waited for a free write slot.\n",
+    );
+    out.push_str("# TYPE knowledge_graph_write_permit_wait_seconds histogram\n");
+    if let Ok(histogram) = REGISTRY.write_permit_wait.lock() {
+        histogram.render(&mut out, "knowledge_graph_write_permit_wait_seconds", "");
+    }
+
+    render_counter(
+        &mut out,
+        "knowledge_graph_documents_written_total",
+        "Documents saved to Neo4j.",
+        &REGISTRY.documents_written,
+    );
+    render_counter(
+        &mut out,
+        "knowledge_graph_documents_dead_lettered_total",
+        "Documents dead-lettered after failed saves.",
+        &REGISTRY.documents_dead_lettered,
+    );
+    render_counter(
+        &mut out,
+        "knowledge_graph_sentences_written_total",
+        "Sentences written to Neo4j.",
+        &REGISTRY.sentences_written,
+    );
+    render_counter(
+        &mut out,
+        "knowledge_graph_tokens_written_total",
+        "Distinct document tokens written to Neo4j.",
+        &REGISTRY.tokens_written,
+    );
+    render_counter(
+        &mut out,
+        "knowledge_graph_write_retries_total",
+        "Retried document saves.",
+        &REGISTRY.write_retries,
+    );
+
+    out.push_str(
+        "# HELP knowledge_graph_neo4j_errors_total Failed Neo4j requests by error class.\n",
+    );
+    out.push_str("# TYPE knowledge_graph_neo4j_errors_total counter\n");
+    if let Ok(errors) = REGISTRY.neo4j_errors.lock() {
+        for (class, count) in errors.iter() {
+            let _ = writeln!(
+                out,
+                "knowledge_graph_neo4j_errors_total{{class=\"{}\"}} {}",
+                class, count
+            );
+        }
+    }
+
+    out.push_str(
+        "# HELP knowledge_graph_writes_in_flight Documents currently being saved to Neo4j.\n",
+    );
+    out.push_str("# TYPE knowledge_graph_writes_in_flight gauge\n");
+    let _ = writeln!(
+        out,
+        "knowledge_graph_writes_in_flight {}",
+        REGISTRY.writes_in_flight.load(Ordering::Relaxed)
+    );
+
+    out
+}
@@ -0,0 +1,95 @@
+use neo4rs::{BoltType, Graph, Query};
+use shared_models::{DocumentId, RelatedDocument};
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Only the document's highest tf-idf tokens are followed to other documents.
+const TOP_TOKENS: i64 = 50;
+/// A match through another form of the same lemma counts this much of an exact token match.
+const LEMMA_MATCH_WEIGHT: f64 = 0.5;
+pub const DEFAULT_MIN_SHARED_TERMS: u32 = 2;
+
+/// Each of the document's top tokens is expanded to the tokens sharing its lemma; a term
+/// matched both exactly and through its lemma counts once, as the better of the two.
+/// Only documents of the request's tenant are related.
+const RELATED_DOCUMENTS_QUERY: &str = "MATCH (d:Document {original_id: $original_id})-[r1:CONTAINS_TOKEN]->(t:Token) \
+                                       WHERE d.tenant_id = $tenant_id \
+                                       WITH d, r1, t ORDER BY r1.tf_idf DESC LIMIT $top_tokens \
+                                       OPTIONAL MATCH (t)-[:HAS_LEMMA]->(:Lemma)<-[:HAS_LEMMA]-(variant:Token) \
+                                       WITH d, r1, t, collect(DISTINCT variant) + [t] AS forms \
+                                       UNWIND forms AS form \
+                                       WITH DISTINCT d, r1, t, form \
+                                       MATCH (form)<-[r2:CONTAINS_TOKEN]-(other:Document) \
+                                       WHERE other <> d AND other.tenant_id = $tenant_id \
+                                       WITH d, other, t, \
+                                            max(r1.tf_idf * r2.tf_idf * CASE WHEN form = t THEN 1.0 ELSE $lemma_weight END) AS term_score, \
+                                            max(CASE WHEN form = t THEN 1 ELSE 0 END) AS exact \
+                                       WITH d, other, count(t) AS shared_terms, sum(exact) AS shared_tokens, sum(term_score) AS score \
+                                       WHERE shared_terms >= $min_shared_terms \
+                                       WITH d, other, shared_terms, shared_tokens, score \
+                                       ORDER BY score DESC, other.original_id LIMIT $top_k \
+                                       OPTIONAL MATCH (d)-[:PUBLISHED_ON]->(dom:Domain)<-[:PUBLISHED_ON]-(other) \
+                                       RETURN other.original_id AS original_id, coalesce(other.source_url, '') AS source_url, \
+                                              score, shared_tokens, shared_terms - shared_tokens AS shared_lemmas, \
+                                              dom IS NOT NULL AS same_domain \
+                                       ORDER BY score DESC, original_id";
+
+const DOCUMENT_EXISTS_QUERY: &str = "MATCH (d:Document {original_id: $original_id}) \
+                                     WHERE d.tenant_id = $tenant_id \
+                                     RETURN count(d) > 0 AS exists";
+
+/// Ranks the documents sharing the most distinctive terms with `original_id`.
+/// Returns `Ok(None)` when the document does not exist, or belongs to another tenant than
+/// `tenant_id`.
+pub async fn related_documents(
+    graph: &Graph,
+    original_id: DocumentId,
+    top_k: u32,
+    min_shared_terms: u32,
+    tenant_id: &str,
+) -> Result<Option<Vec<RelatedDocument>>, BoxError> {
+    let tenant_id: BoltType = tenant_id.into();
+    let mut exists_params: HashMap<String, BoltType> = HashMap::new();
+    exists_params.insert("original_id".to_string(), original_id.to_string().into());
+    exists_params.insert("tenant_id".to_string(), tenant_id.clone());
+    let mut exists_stream = graph
+        .execute(Query::new(DOCUMENT_EXISTS_QUERY.to_string()).params(exists_params))
+        .await?;
+    let exists: bool = match exists_stream.next().await? {
+        Some(row) => row.get("exists")?,
+        None => false,
+    };
+    if !exists {
+        return Ok(None);
+    }
+
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("original_id".to_string(), original_id.to_string().into());
+    params.insert("tenant_id".to_string(), tenant_id);
+    params.insert("top_tokens".to_string(), TOP_TOKENS.into());
+    params.insert("lemma_weight".to_string(), LEMMA_MATCH_WEIGHT.into());
+    params.insert(
+        "min_shared_terms".to_string(),
+        (min_shared_terms as i64).into(),
+    );
+    params.insert("top_k".to_string(), (top_k as i64).into());
+
+    let mut stream = graph
+        .execute(Query::new(RELATED_DOCUMENTS_QUERY.to_string()).params(params))
+        .await?;
+    let mut documents = Vec::new();
+    while let Some(row) = stream.next().await? {
+        let shared_tokens: i64 = row.get("shared_tokens")?;
+        let shared_lemmas: i64 = row.get("shared_lemmas")?;
+        documents.push(RelatedDocument {
+            original_id: row.get("original_id")?,
+            source_url: row.get("source_url")?,
+            score: row.get("score")?,
+            shared_tokens: shared_tokens.max(0) as u32,
+            shared_lemmas: shared_lemmas.max(0) as u32,
+            same_domain: row.get("same_domain")?,
+        });
+    }
+    Ok(Some(documents))
+}
@@ -0,0 +1,67 @@
+use neo4rs::{BoltType, Graph, Query};
+use shared_models::{DocumentId, KeywordSearchResultItem};
+use std::collections::HashMap;
+
+pub const SENTENCE_FULLTEXT_INDEX: &str = "sentence_text_fulltext";
+const LUCENE_SPECIAL_CHARS: &str = "+-&|!(){}[]^\"~*?:\\/";
+
+/// Runs `query_text` against the Sentence full-text index and returns the best
+/// `top_k` (document, sentence) pairs, among the documents of `tenant_id`.
+pub async fn keyword_search(
+    graph: &Graph,
+    query_text: &str,
+    top_k: u32,
+    original_id: Option<DocumentId>,
+    tenant_id: &str,
+) -> Result<Vec<KeywordSearchResultItem>, Box<dyn std::error::Error + Send + Sync>> {
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("index_name".to_string(), SENTENCE_FULLTEXT_INDEX.into());
+    params.insert("query".to_string(), escape_lucene(query_text).into());
+    params.insert("top_k".to_string(), (top_k as i64).into());
+    params.insert("tenant_id".to_string(), tenant_id.into());
+    let document_filter = match original_id {
+        Some(original_id) => {
+            params.insert("original_id".to_string(), original_id.to_string().into());
+            "AND d.original_id = $original_id "
+        }
+        None => "",
+    };
+
+    let query_str = format!(
+        "CALL db.index.fulltext.queryNodes($index_name, $query) YIELD node, score \
+         MATCH (d:Document)-[h:HAS_SENTENCE]->(node) \
+         WHERE d.tenant_id = $tenant_id {}\
+         RETURN d.original_id AS original_id, coalesce(d.source_url, '') AS source_url, \
+                node.text AS sentence_text, h.order AS sentence_order, score \
+         ORDER BY score DESC, original_id, sentence_order \
+         LIMIT $top_k",
+        document_filter
+    );
+
+    let mut stream = graph.execute(Query::new(query_str).params(params)).await?;
+    let mut results = Vec::new();
+    while let Some(row) = stream.next().await? {
+        let sentence_order: i64 = row.get("sentence_order")?;
+        let score: f64 = row.get("score")?;
+        results.push(KeywordSearchResultItem {
+            original_id: row.get("original_id")?,
+            source_url: row.get("source_url")?,
+            sentence_text: row.get("sentence_text")?,
+            sentence_order: sentence_order.max(0) as u32,
+            score: score as f32,
+        });
+    }
+    Ok(results)
+}
+
+/// Escapes Lucene query syntax so the text is searched for as plain terms.
+fn escape_lucene(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if LUCENE_SPECIAL_CHARS.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
@@ -0,0 +1,88 @@
+use neo4rs::BoltType;
+use sha2::{Digest, Sha256};
+use shared_models::DocumentId;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Which identical sentences share one `(:Sentence)` node. Switching the scope does not
+/// migrate existing nodes; documents move to the new scheme as they are re-saved.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SentenceDedupScope {
+    /// One node per distinct text across all documents, keyed by the text itself.
+    Global,
+    /// One node per (document, text), so documents never share sentence nodes.
+    Document,
+    /// Like `Global`, but keyed by a SHA-256 of the text; avoids indexing long texts.
+    Hash,
+}
+
+impl SentenceDedupScope {
+    /// `MERGE` clause binding `s`; expects the parameters set by [`Self::insert_params`].
+    pub fn merge_clause(self) -> &'static str {
+        match self {
+            SentenceDedupScope::Global => {
+                "MERGE (s:Sentence {text: $text}) \
+                 ON CREATE SET s.created_at_ms = timestamp() "
+            }
+            SentenceDedupScope::Document => {
+                "MERGE (s:Sentence {original_id: $original_id, text: $text}) \
+                 ON CREATE SET s.created_at_ms = timestamp() "
+            }
+            SentenceDedupScope::Hash => {
+                "MERGE (s:Sentence {text_hash: $text_hash}) \
+                 ON CREATE SET s.text = $text, s.created_at_ms = timestamp() "
+            }
+        }
+    }
+
+    pub fn insert_params(
+        self,
+        params: &mut HashMap<String, BoltType>,
+        original_id: DocumentId,
+        text: &str,
+    ) {
+        params.insert("text".to_string(), text.into());
+        match self {
+            SentenceDedupScope::Global => {}
+            SentenceDedupScope::Document => {
+                params.insert("original_id".to_string(), original_id.to_string().into());
+            }
+            SentenceDedupScope::Hash => {
+                params.insert("text_hash".to_string(), text_hash(text).into());
+            }
+        }
+    }
+
+    /// Index or constraint backing the `MERGE` lookup. Full texts go into a text index,
+    /// which unlike a range index has no key size limit.
+    pub fn schema_query(self) -> &'static str {
+        match self {
+            SentenceDedupScope::Global => {
+                "CREATE TEXT INDEX sentence_text_index IF NOT EXISTS FOR (s:Sentence) ON (s.text)"
+            }
+            SentenceDedupScope::Document => {
+                "CREATE INDEX sentence_original_id_index IF NOT EXISTS FOR (s:Sentence) ON (s.original_id)"
+            }
+            SentenceDedupScope::Hash => {
+                "CREATE CONSTRAINT sentence_text_hash_unique IF NOT EXISTS FOR (s:Sentence) REQUIRE s.text_hash IS UNIQUE"
+            }
+        }
+    }
+}
+
+impl FromStr for SentenceDedupScope {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "global" => Ok(SentenceDedupScope::Global),
+            "document" => Ok(SentenceDedupScope::Document),
+            "hash" => Ok(SentenceDedupScope::Hash),
+            other => Err(format!("unknown sentence dedup scope '{}'", other)),
+        }
+    }
+}
+
+fn text_hash(text: &str) -> String {
+    format!("{:x}", Sha256::digest(text.as_bytes()))
+}
@@ -0,0 +1,71 @@
+use crate::config::SimilarityConfig;
+use neo4rs::{BoltType, Error as Neo4jError, Graph, Query};
+use shared_models::DocumentId;
+use std::collections::HashMap;
+
+/// Candidates are pre-ranked by the unnormalized dot product; only this many times
+/// `max_links` of them have their norms computed for the cosine score.
+const CANDIDATE_FACTOR: i64 = 4;
+
+/// Replaces the outgoing SIMILAR_TO edges of `original_id` with links to the documents whose
+/// tf-idf vectors are closest to it. The edges are directed from the document that computed
+/// them; query them undirected (`(a)-[:SIMILAR_TO]-(b)`) for related documents. Only
+/// documents of the same tenant, or both without one, are linked.
+/// Returns the number of edges written.
+pub async fn link_similar_documents(
+    graph: &Graph,
+    original_id: DocumentId,
+    config: &SimilarityConfig,
+) -> Result<i64, Neo4jError> {
+    let query_str = "MATCH (d:Document {original_id: $original_id}) \
+                     OPTIONAL MATCH (d)-[old:SIMILAR_TO]->(:Document) \
+                     DELETE old \
+                     WITH DISTINCT d \
+                     MATCH (d)-[r_all:CONTAINS_TOKEN]->(:Token) \
+                     WITH d, sqrt(sum(r_all.tf_idf ^ 2)) AS d_norm \
+                     WHERE d_norm > 0 \
+                     MATCH (d)-[r1:CONTAINS_TOKEN]->(t:Token) \
+                     WITH d, d_norm, r1, t ORDER BY r1.tf_idf DESC LIMIT $top_tokens \
+                     MATCH (t)<-[r2:CONTAINS_TOKEN]-(other:Document) \
+                     WHERE other <> d \
+                       AND (other.tenant_id = d.tenant_id OR (other.tenant_id IS NULL AND d.tenant_id IS NULL)) \
+                     WITH d, d_norm, other, count(t) AS shared_tokens, sum(r1.tf_idf * r2.tf_idf) AS dot \
+                     WHERE shared_tokens >= $min_shared_tokens \
+                     WITH d, d_norm, other, shared_tokens, dot ORDER BY dot DESC LIMIT $candidate_limit \
+                     CALL { \
+                         WITH other \
+                         MATCH (other)-[r_other:CONTAINS_TOKEN]->(:Token) \
+                         RETURN sqrt(sum(r_other.tf_idf ^ 2)) AS other_norm \
+                     } \
+                     WITH d, other, shared_tokens, dot / (d_norm * other_norm) AS score \
+                     WHERE score >= $min_score \
+                     WITH d, other, shared_tokens, score ORDER BY score DESC LIMIT $max_links \
+                     MERGE (d)-[s:SIMILAR_TO]->(other) \
+                     SET s.score = score, s.shared_tokens = shared_tokens, s.updated_at_ms = timestamp() \
+                     RETURN count(s) AS linked";
+
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("original_id".to_string(), original_id.to_string().into());
+    params.insert("top_tokens".to_string(), (config.top_tokens as i64).into());
+    params.insert(
+        "min_shared_tokens".to_string(),
+        (config.min_shared_tokens as i64).into(),
+    );
+    params.insert(
+        "candidate_limit".to_string(),
+        (config.max_links as i64 * CANDIDATE_FACTOR).into(),
+    );
+    params.insert("min_score".to_string(), config.min_score.into());
+    params.insert("max_links".to_string(), (config.max_links as i64).into());
+
+    let mut txn = graph.start_txn().await?;
+    let mut stream = txn
+        .execute(Query::new(query_str.to_string()).params(params))
+        .await?;
+    let linked = match stream.next(&mut txn).await? {
+        Some(row) => row.get("linked").unwrap_or_default(),
+        None => 0,
+    };
+    txn.commit().await?;
+    Ok(linked)
+}
@@ -0,0 +1,107 @@
+use neo4rs::{BoltType, Graph, Query};
+use shared_models::{DomainDocumentCount, GraphLabelCount, GraphRelationshipTypeCount};
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DEFAULT_MAX_DOMAINS: u32 = 50;
+
+#[derive(Debug, Default)]
+pub struct GraphStats {
+    pub node_count: u64,
+    pub relationship_count: u64,
+    pub nodes_by_label: Vec<GraphLabelCount>,
+    pub relationships_by_type: Vec<GraphRelationshipTypeCount>,
+    pub documents_by_domain: Vec<DomainDocumentCount>,
+}
+
+/// Collects graph-wide counts. Counting one label or relationship type at a time lets Neo4j
+/// answer from its count store instead of scanning the graph.
+pub async fn collect(graph: &Graph, max_domains: u32) -> Result<GraphStats, BoxError> {
+    let mut stats = GraphStats {
+        node_count: single_count(graph, "MATCH (n) RETURN count(n) AS count".to_string()).await?,
+        relationship_count: single_count(
+            graph,
+            "MATCH ()-[r]->() RETURN count(r) AS count".to_string(),
+        )
+        .await?,
+        ..GraphStats::default()
+    };
+
+    for label in names(graph, "CALL db.labels() YIELD label RETURN label AS name").await? {
+        let count = single_count(
+            graph,
+            format!("MATCH (n:{}) RETURN count(n) AS count", quote_name(&label)),
+        )
+        .await?;
+        stats.nodes_by_label.push(GraphLabelCount { label, count });
+    }
+
+    for rel_type in names(
+        graph,
+        "CALL db.relationshipTypes() YIELD relationshipType RETURN relationshipType AS name",
+    )
+    .await?
+    {
+        let count = single_count(
+            graph,
+            format!(
+                "MATCH ()-[r:{}]->() RETURN count(r) AS count",
+                quote_name(&rel_type)
+            ),
+        )
+        .await?;
+        stats
+            .relationships_by_type
+            .push(GraphRelationshipTypeCount { rel_type, count });
+    }
+
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("max_domains".to_string(), (max_domains as i64).into());
+    let mut stream = graph
+        .execute(
+            Query::new(
+                "MATCH (dom:Domain) \
+                 WITH dom, COUNT { (dom)<-[:PUBLISHED_ON]-(:Document) } AS documents \
+                 RETURN dom.host AS host, documents \
+                 ORDER BY documents DESC, host \
+                 LIMIT $max_domains"
+                    .to_string(),
+            )
+            .params(params),
+        )
+        .await?;
+    while let Some(row) = stream.next().await? {
+        let documents: i64 = row.get("documents")?;
+        stats.documents_by_domain.push(DomainDocumentCount {
+            host: row.get("host")?,
+            documents: documents.max(0) as u64,
+        });
+    }
+
+    Ok(stats)
+}
+
+async fn names(graph: &Graph, query: &str) -> Result<Vec<String>, BoxError> {
+    let mut stream = graph.execute(Query::new(query.to_string())).await?;
+    let mut names = Vec::new();
+    while let Some(row) = stream.next().await? {
+        names.push(row.get("name")?);
+    }
+    names.sort();
+    Ok(names)
+}
+
+async fn single_count(graph: &Graph, query: String) -> Result<u64, BoxError> {
+    let mut stream = graph.execute(Query::new(query)).await?;
+    let count: i64 = match stream.next().await? {
+        Some(row) => row.get("count")?,
+        None => 0,
+    };
+    Ok(count.max(0) as u64)
+}
+
+/// Labels and relationship types cannot be parameters; backticks keep any name literal.
+fn quote_name(name: &str) -> String {
+    format!("`{}`", name.replace('`', "``"))
+}
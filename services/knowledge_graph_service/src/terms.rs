@@ -0,0 +1,64 @@
+use neo4rs::{BoltType, Graph, Query};
+use shared_models::{DocumentId, GraphTerm, GraphTermKind};
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How many of the newest documents are ranked when a request is not scoped to one.
+const RECENT_DOCUMENTS: i64 = 200;
+/// Shorter tokens are mostly function words, numbers and punctuation.
+const MIN_TERM_LENGTH: i64 = 3;
+
+/// Ranks the tokens of one document by their TF-IDF in it, or those of the most recently
+/// processed documents by their summed TF-IDF. Entities are approximated by tokens whose
+/// last seen spelling starts with an uppercase letter. Only documents of `tenant_id` are
+/// ranked.
+pub async fn top_terms(
+    graph: &Graph,
+    original_id: Option<DocumentId>,
+    kind: GraphTermKind,
+    top_k: u32,
+    tenant_id: &str,
+) -> Result<Vec<GraphTerm>, BoxError> {
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("top_k".to_string(), (top_k as i64).into());
+    params.insert("min_length".to_string(), MIN_TERM_LENGTH.into());
+    params.insert("tenant_id".to_string(), tenant_id.into());
+    let documents = match original_id {
+        Some(original_id) => {
+            params.insert("original_id".to_string(), original_id.to_string().into());
+            "MATCH (d:Document {original_id: $original_id}) \
+             WHERE d.tenant_id = $tenant_id "
+        }
+        None => {
+            params.insert("recent_documents".to_string(), RECENT_DOCUMENTS.into());
+            "MATCH (d:Document) WHERE d.tenant_id = $tenant_id \
+             WITH d ORDER BY d.processed_at_ms DESC LIMIT $recent_documents "
+        }
+    };
+    let kind_filter = match kind {
+        GraphTermKind::Keyword => "",
+        GraphTermKind::Entity => "AND t.text_original_case =~ '\\\\p{Lu}.*' ",
+    };
+
+    let query_str = format!(
+        "{}MATCH (d)-[r:CONTAINS_TOKEN]->(t:Token) \
+         WHERE size(t.text_lc) >= $min_length {}\
+         WITH t, sum(coalesce(r.tf_idf, 0.0)) AS score \
+         RETURN coalesce(t.text_original_case, t.text_lc) AS text, score \
+         ORDER BY score DESC, text \
+         LIMIT $top_k",
+        documents, kind_filter
+    );
+
+    let mut stream = graph.execute(Query::new(query_str).params(params)).await?;
+    let mut terms = Vec::new();
+    while let Some(row) = stream.next().await? {
+        let score: f64 = row.get("score")?;
+        terms.push(GraphTerm {
+            text: row.get("text")?,
+            score: score as f32,
+        });
+    }
+    Ok(terms)
+}
@@ -0,0 +1,145 @@
+use neo4rs::{BoltType, Query, Txn};
+use sha2::{Digest, Sha256};
+use shared_models::TokenizedTextMessage;
+use std::collections::HashMap;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// SHA-256 over the document's sentences, so re-ingesting unchanged content is recognized.
+pub fn content_hash(sentences: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for sentence in sentences {
+        hasher.update(sentence.as_bytes());
+        // Unit separator: keeps ["ab", "c"] and ["a", "bc"] apart.
+        hasher.update([0x1f]);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Appends a `(:DocumentVersion)` to the document when its content differs from the latest
+/// version and moves `LATEST_VERSION` to it; unchanged content only bumps the latest version's
+/// `last_seen_at_ms`. Returns the element id of the version whose sentences still have to be
+/// linked: the new one, or the latest one when an earlier save of it did not finish (see
+/// [`mark_complete`]). `None` when the content is unchanged.
+pub async fn record_version(
+    tx: &mut Txn,
+    msg: &TokenizedTextMessage,
+) -> Result<Option<String>, BoxError> {
+    let content_hash = content_hash(&msg.sentences);
+
+    let mut latest_params: HashMap<String, BoltType> = HashMap::new();
+    latest_params.insert(
+        "original_id".to_string(),
+        msg.original_id.to_string().into(),
+    );
+    let mut latest_stream = tx
+        .execute(
+            Query::new(
+                "MATCH (d:Document {original_id: $original_id}) \
+                 OPTIONAL MATCH (d)-[:LATEST_VERSION]->(latest:DocumentVersion) \
+                 RETURN latest.content_hash AS latest_hash, coalesce(latest.version, 0) AS latest_version, \
+                        elementId(latest) AS latest_element_id, coalesce(latest.complete, true) AS latest_complete"
+                    .to_string(),
+            )
+            .params(latest_params),
+        )
+        .await?;
+    let (latest_hash, latest_version, latest_element_id, latest_complete): (
+        Option<String>,
+        i64,
+        Option<String>,
+        bool,
+    ) = match latest_stream.next(&mut *tx).await? {
+        Some(row) => (
+            row.get("latest_hash")?,
+            row.get("latest_version")?,
+            row.get("latest_element_id")?,
+            row.get("latest_complete")?,
+        ),
+        None => (None, 0, None, true),
+    };
+
+    if latest_hash.as_deref() == Some(content_hash.as_str()) {
+        let mut seen_params: HashMap<String, BoltType> = HashMap::new();
+        seen_params.insert(
+            "original_id".to_string(),
+            msg.original_id.to_string().into(),
+        );
+        seen_params.insert(
+            "processed_at_ms".to_string(),
+            (msg.timestamp_ms as i64).into(),
+        );
+        tx.run(
+            Query::new(
+                "MATCH (:Document {original_id: $original_id})-[:LATEST_VERSION]->(v:DocumentVersion) \
+                 SET v.last_seen_at_ms = $processed_at_ms"
+                    .to_string(),
+            )
+            .params(seen_params),
+        )
+        .await?;
+        return Ok(if latest_complete {
+            None
+        } else {
+            latest_element_id
+        });
+    }
+
+    let create_query_str = "MATCH (d:Document {original_id: $original_id}) \
+                            OPTIONAL MATCH (d)-[old:LATEST_VERSION]->(previous:DocumentVersion) \
+                            DELETE old \
+                            CREATE (v:DocumentVersion {original_id: $original_id, version: $version, \
+                                content_hash: $content_hash, source_url: $source_url, \
+                                sentence_count: $sentence_count, processed_at_ms: $processed_at_ms, \
+                                last_seen_at_ms: $processed_at_ms, complete: false, created_at_ms: timestamp()}) \
+                            CREATE (d)-[:HAS_VERSION]->(v) \
+                            CREATE (d)-[:LATEST_VERSION]->(v) \
+                            SET d.version = $version \
+                            FOREACH (p IN CASE WHEN previous IS NULL THEN [] ELSE [previous] END | \
+                                CREATE (v)-[:PREVIOUS_VERSION]->(p)) \
+                            RETURN elementId(v) AS version_element_id";
+
+    let mut create_params: HashMap<String, BoltType> = HashMap::new();
+    create_params.insert(
+        "original_id".to_string(),
+        msg.original_id.to_string().into(),
+    );
+    create_params.insert("version".to_string(), (latest_version + 1).into());
+    create_params.insert("content_hash".to_string(), content_hash.into());
+    create_params.insert("source_url".to_string(), msg.source_url.clone().into());
+    create_params.insert(
+        "sentence_count".to_string(),
+        (msg.sentences.len() as i64).into(),
+    );
+    create_params.insert(
+        "processed_at_ms".to_string(),
+        (msg.timestamp_ms as i64).into(),
+    );
+
+    let mut create_stream = tx
+        .execute(Query::new(create_query_str.to_string()).params(create_params))
+        .await?;
+    let version_element_id: String = create_stream
+        .next(&mut *tx)
+        .await?
+        .ok_or("DocumentVersion not created")?
+        .get("version_element_id")?;
+    Ok(Some(version_element_id))
+}
+
+/// Flags a version as fully linked to its sentences. A save split over several transactions
+/// that fails midway leaves the flag unset, and the retry links the remaining sentences.
+pub async fn mark_complete(tx: &mut Txn, version_element_id: &str) -> Result<(), BoxError> {
+    let mut params: HashMap<String, BoltType> = HashMap::new();
+    params.insert("version_element_id".to_string(), version_element_id.into());
+    tx.run(
+        Query::new(
+            "MATCH (v:DocumentVersion) WHERE elementId(v) = $version_element_id \
+             SET v.complete = true"
+                .to_string(),
+        )
+        .params(params),
+    )
+    .await?;
+    Ok(())
+}
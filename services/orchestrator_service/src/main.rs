@@ -0,0 +1,506 @@
+mod metrics;
+mod store;
+mod tracker;
+
+use async_nats::{Client as NatsClient, Subscriber};
+use futures::StreamExt;
+use futures::stream::TakeUntil;
+use log::{debug, error, info, warn};
+use shared_config::{Settings, env_parse_or};
+use shared_models::{
+    DocumentLifecycle, DocumentStatusResult, DocumentStatusTask, DocumentStuckAlert, Envelope,
+    LOG_TEXT_CHARS, PIPELINE_ERROR_SUBJECT_PREFIX, PerceiveUrlTask, PipelineErrorMessage,
+    PipelineStage, RequestId, StageProgress, TASK_STATUS_EVENT_SUBJECT, TaskStatus,
+    TaskStatusChangedMessage, Truncated, current_timestamp_ms,
+};
+use shared_nats::{
+    OverflowPolicy, Shutdown, WorkerPool, publish_reply, serve_health, traced_headers,
+};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store::{TaskRecord, TaskStore, TaskStoreWriter};
+use tracker::Tracker;
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+const PERCEPTION_URL_TASK_SUBJECT: &str = "tasks.perceive.url";
+const DOCUMENT_STATUS_TASK_SUBJECT: &str = "tasks.orchestrator.status";
+const DOCUMENT_STUCK_EVENT_SUBJECT: &str = "events.pipeline.stuck";
+const DEFAULT_STAGE_TIMEOUT_SECS: u64 = 600;
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 30;
+const DEFAULT_RETENTION_SECS: u64 = 3600;
+const DEFAULT_MAX_DOCUMENTS: usize = 100_000;
+const DEFAULT_STORE_RETENTION_DAYS: u64 = 30;
+const STORE_PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+/// Status requests answered at once unless `WORKERS_STATUS_REQUESTS_CONCURRENCY` says
+/// otherwise.
+const DEFAULT_STATUS_WORKERS: usize = 16;
+type Messages = TakeUntil<Subscriber, futures::future::BoxFuture<'static, ()>>;
+
+/// How long documents may sit in one state and how many are remembered.
+#[derive(Debug, Clone, Copy)]
+struct OrchestratorConfig {
+    /// A document that no stage has reported on for this long is reported stuck.
+    stage_timeout: Duration,
+    sweep_interval: Duration,
+    /// How long graphed and failed documents can still be looked up.
+    retention: Duration,
+    max_documents: usize,
+    /// How long the task store keeps documents; zero keeps them for good.
+    store_retention: Duration,
+}
+
+impl OrchestratorConfig {
+    fn from_env() -> Self {
+        let secs = |key: &str, default: u64| Duration::from_secs(env_parse_or(key, default));
+        OrchestratorConfig {
+            stage_timeout: secs(
+                "ORCHESTRATOR_STAGE_TIMEOUT_SECS",
+                DEFAULT_STAGE_TIMEOUT_SECS,
+            ),
+            sweep_interval: secs(
+                "ORCHESTRATOR_SWEEP_INTERVAL_SECS",
+                DEFAULT_SWEEP_INTERVAL_SECS,
+            )
+            .max(Duration::from_secs(1)),
+            retention: secs("ORCHESTRATOR_RETENTION_SECS", DEFAULT_RETENTION_SECS),
+            max_documents: env_parse_or("ORCHESTRATOR_MAX_DOCUMENTS", DEFAULT_MAX_DOCUMENTS),
+            store_retention: Duration::from_secs(
+                env_parse_or("TASK_STORE_RETENTION_DAYS", DEFAULT_STORE_RETENTION_DAYS)
+                    .saturating_mul(24 * 3600),
+            ),
+        }
+    }
+}
+
+/// The followed documents, and the task store they are saved to when one is configured.
+struct Orchestrator {
+    tracker: Mutex<Tracker>,
+    store: Option<TaskStore>,
+    writer: Option<TaskStoreWriter>,
+}
+
+impl Orchestrator {
+    /// Applies `update` to the tracker and queues the document it returns, with `event`,
+    /// for the task store.
+    fn update(
+        &self,
+        event: Option<StageProgress>,
+        update: impl FnOnce(&mut Tracker) -> Option<DocumentLifecycle>,
+    ) {
+        let Some(document) = self
+            .tracker
+            .lock()
+            .ok()
+            .and_then(|mut tracker| update(&mut tracker))
+        else {
+            return;
+        };
+        if let Some(writer) = &self.writer {
+            writer.record(TaskRecord { document, event });
+        }
+    }
+
+    /// The followed document, or else the one in the task store.
+    async fn find(&self, task: &DocumentStatusTask) -> Result<Option<DocumentLifecycle>, String> {
+        let followed = self.tracker.lock().ok().and_then(|tracker| {
+            task.task_id
+                .and_then(|task_id| tracker.by_task(task_id))
+                .or_else(|| task.original_id.and_then(|id| tracker.by_document(id)))
+                .cloned()
+        });
+        match (followed, &self.store) {
+            (Some(document), _) => Ok(Some(document)),
+            (None, Some(store)) => store
+                .find(task.task_id, task.original_id)
+                .await
+                .map_err(|e| format!("Failed to read the task store: {}", e)),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+async fn subscribe(
+    client: &NatsClient,
+    subject: &str,
+    shutdown: &Shutdown,
+) -> Result<Messages, async_nats::SubscribeError> {
+    match client.subscribe(subject.to_string()).await {
+        Ok(subscriber) => {
+            info!("[NATS_SUB_SUCCESS] Subscribed to subject: {}", subject);
+            Ok(subscriber.take_until(shutdown.signalled()))
+        }
+        Err(e) => {
+            error!("[NATS_SUB_FAIL] Failed to subscribe to {}: {}", subject, e);
+            Err(e)
+        }
+    }
+}
+
+/// Starts following every submitted URL. The submissions are read from the subject the
+/// perception tasks are published to; the orchestrator does not consume them.
+async fn follow_submissions(mut submissions: Messages, orchestrator: Arc<Orchestrator>) {
+    while let Some(message) = submissions.next().await {
+        match Envelope::<PerceiveUrlTask>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let task_id = envelope.task_id();
+                debug!(
+                    "[SUBMISSION] Following task {} for URL: {}",
+                    task_id, envelope.payload.url
+                );
+                orchestrator.update(None, |tracker| {
+                    Some(tracker.received(
+                        task_id,
+                        envelope.payload.url,
+                        envelope.tenant_id,
+                        envelope.timestamp_ms,
+                    ))
+                });
+            }
+            Err(e) => warn!(
+                "[SUBMISSION] Failed to deserialize PerceiveUrlTask: {}. Payload: {:?}",
+                e,
+                Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
+            ),
+        }
+    }
+    info!("[SUBMISSION] Submission subscription ended.");
+}
+
+/// Applies the stages' status changes. Generation tasks are not documents and are skipped.
+async fn follow_status_changes(mut statuses: Messages, orchestrator: Arc<Orchestrator>) {
+    while let Some(message) = statuses.next().await {
+        match Envelope::<TaskStatusChangedMessage>::from_slice(&message.payload) {
+            Ok(envelope) if envelope.payload.stage == PipelineStage::Generation => {}
+            Ok(envelope) => {
+                let tenant_id = envelope.tenant_id;
+                let status = envelope.payload;
+                debug!(
+                    "[TASK_STATUS] Task {}: {} {:?}",
+                    status.task_id,
+                    status.stage.as_str(),
+                    status.status
+                );
+                let event = StageProgress {
+                    stage: status.stage,
+                    status: status.status,
+                    detail: status.detail.clone(),
+                    updated_at_ms: status.timestamp_ms,
+                };
+                orchestrator.update(Some(event), |tracker| {
+                    Some(tracker.record_status(&status, tenant_id))
+                });
+            }
+            Err(e) => warn!(
+                "[TASK_STATUS] Failed to deserialize TaskStatusChangedMessage: {}. Payload: {:?}",
+                e,
+                Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
+            ),
+        }
+    }
+    info!("[TASK_STATUS] Task status subscription ended.");
+}
+
+/// Applies the stages' errors to the submissions they belong to.
+async fn follow_pipeline_errors(mut errors: Messages, orchestrator: Arc<Orchestrator>) {
+    while let Some(message) = errors.next().await {
+        match Envelope::<PipelineErrorMessage>::from_slice(&message.payload) {
+            Ok(envelope) if envelope.payload.stage == PipelineStage::Generation => {}
+            Ok(envelope) => {
+                let task_id = envelope
+                    .payload
+                    .task_id
+                    .unwrap_or_else(|| envelope.task_id());
+                let error = envelope.payload;
+                let event = StageProgress {
+                    stage: error.stage,
+                    status: TaskStatus::Failed,
+                    detail: Some(error.message.clone()),
+                    updated_at_ms: error.timestamp_ms,
+                };
+                orchestrator.update(Some(event), |tracker| tracker.record_error(task_id, &error));
+            }
+            Err(e) => warn!(
+                "[PIPELINE_ERROR] Failed to deserialize PipelineErrorMessage: {}. Payload: {:?}",
+                e,
+                Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
+            ),
+        }
+    }
+    info!("[PIPELINE_ERROR] Pipeline error subscription ended.");
+}
+
+/// Every `sweep_interval`, publishes an alert for each document that became stuck and
+/// forgets documents past the retention.
+async fn run_sweeps(
+    client: Arc<NatsClient>,
+    orchestrator: Arc<Orchestrator>,
+    config: OrchestratorConfig,
+) {
+    let mut interval = tokio::time::interval(config.sweep_interval);
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        interval.tick().await;
+        let alerts = match orchestrator.tracker.lock() {
+            Ok(mut tracker) => tracker.sweep(current_timestamp_ms(), config.stage_timeout),
+            Err(_) => return,
+        };
+        for alert in alerts {
+            // Saved, so a restart does not report the same stall again.
+            orchestrator.update(None, |tracker| tracker.by_task(alert.task_id).cloned());
+            publish_stuck_alert(&client, &alert).await;
+        }
+    }
+}
+
+/// Deletes documents older than `retention` from the task store every hour.
+async fn run_store_pruning(store: TaskStore, retention: Duration) {
+    let mut interval = tokio::time::interval(STORE_PRUNE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let before_ms = current_timestamp_ms()
+            .saturating_sub(u64::try_from(retention.as_millis()).unwrap_or(u64::MAX));
+        match store.prune(before_ms).await {
+            Ok(0) => {}
+            Ok(pruned) => info!(
+                "[TASK_STORE_PRUNE] Deleted {} task(s) older than {:?}.",
+                pruned, retention
+            ),
+            Err(e) => error!(
+                "[TASK_STORE_PRUNE_FAIL] Failed to prune the task store: {}",
+                e
+            ),
+        }
+    }
+}
+
+async fn publish_stuck_alert(client: &NatsClient, alert: &DocumentStuckAlert) {
+    warn!(
+        "[DOCUMENT_STUCK] Task {} (original_id: {:?}, URL: {:?}) has been {} for {}s.",
+        alert.task_id,
+        alert.original_id,
+        alert.source_url,
+        alert.state.as_str(),
+        alert.stalled_for_ms / 1000
+    );
+    metrics::stuck_alert();
+    // The alert joins the correlation of the submission it concerns.
+    let mut envelope = Envelope::new(SERVICE_NAME, alert);
+    envelope.correlation_id = alert.task_id.to_string();
+    match envelope.to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = client
+                .publish_with_headers(
+                    DOCUMENT_STUCK_EVENT_SUBJECT,
+                    traced_headers(),
+                    payload_json.into(),
+                )
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish stuck alert for task {}: {}",
+                    alert.task_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize DocumentStuckAlert: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Answers a [`DocumentStatusTask`] from the tracker or else the task store, by task id when
+/// given, else by document id.
+async fn handle_document_status_task(
+    message: async_nats::Message,
+    client: &NatsClient,
+    orchestrator: &Orchestrator,
+) {
+    let (cause, task) = match Envelope::<DocumentStatusTask>::from_slice(&message.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize DocumentStatusTask: {}", e);
+            error!("[STATUS_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = DocumentStatusResult {
+                request_id: RequestId::default(),
+                document: None,
+                history: Vec::new(),
+                error_message: Some(err_msg),
+            };
+            publish_reply(
+                client,
+                message.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "STATUS_HANDLER",
+            )
+            .await;
+            return;
+        }
+    };
+
+    let error_result = |message: String| DocumentStatusResult {
+        request_id: task.request_id,
+        document: None,
+        history: Vec::new(),
+        error_message: Some(message),
+    };
+    let result = if task.task_id.is_none() && task.original_id.is_none() {
+        error_result("Either task_id or original_id is required".to_string())
+    } else {
+        match orchestrator.find(&task).await {
+            Ok(document) => {
+                debug!(
+                    "[STATUS_HANDLER] Status of task {:?} / document {:?}: {:?}",
+                    task.task_id,
+                    task.original_id,
+                    document.as_ref().map(|document| document.state)
+                );
+                let history = match (&document, &orchestrator.store) {
+                    (Some(document), Some(store)) if task.include_history => {
+                        store.history(document.task_id).await.map_err(|e| {
+                            format!(
+                                "Failed to read the history of task {}: {}",
+                                document.task_id, e
+                            )
+                        })
+                    }
+                    _ => Ok(Vec::new()),
+                };
+                match history {
+                    Ok(history) => DocumentStatusResult {
+                        request_id: task.request_id,
+                        document,
+                        history,
+                        error_message: None,
+                    },
+                    Err(e) => error_result(e),
+                }
+            }
+            Err(e) => error_result(e),
+        }
+    };
+    if let Some(e) = &result.error_message {
+        error!("[STATUS_HANDLER] {}", e);
+    }
+    publish_reply(
+        client,
+        message.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "STATUS_HANDLER",
+    )
+    .await;
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info", &settings, String::new)?;
+    info!("Starting ...");
+
+    let drain_timeout = settings.shutdown.drain_timeout();
+    let shutdown = Shutdown::listen();
+    let config = OrchestratorConfig::from_env();
+    info!(
+        "[ORCHESTRATOR] Stage timeout {:?}, sweep every {:?}, finished documents kept for {:?}, at most {} documents.",
+        config.stage_timeout, config.sweep_interval, config.retention, config.max_documents
+    );
+
+    let client = Arc::new(match shared_nats::connect(&settings.nats).await {
+        Ok(client) => {
+            info!("[NATS_URL] Successfully connected to NATS!");
+            client
+        }
+        Err(err) => {
+            error!("[NATS_URL] Failed to connect to NATS: {}", err);
+            return Err(Box::new(err) as Box<dyn std::error::Error>);
+        }
+    });
+
+    // The tracker lives in this process, so every instance subscribes to all events and
+    // answers status requests from its own view; run a single instance.
+    let mut tracker = Tracker::new(config.retention, config.max_documents);
+    let store = TaskStore::connect(&settings.postgres).await?;
+    match &store {
+        Some(store) => {
+            let documents = store
+                .load_recent(
+                    current_timestamp_ms(),
+                    config.retention,
+                    config.max_documents,
+                )
+                .await?;
+            info!(
+                "[TASK_STORE] Following {} document(s) loaded from the task store.",
+                documents.len()
+            );
+            for document in documents {
+                tracker.restore(document);
+            }
+            if !config.store_retention.is_zero() {
+                tokio::spawn(run_store_pruning(store.clone(), config.store_retention));
+            }
+        }
+        None => warn!("[TASK_STORE] POSTGRES_URL is not set; task history is kept in memory only."),
+    }
+    let orchestrator = Arc::new(Orchestrator {
+        tracker: Mutex::new(tracker),
+        writer: store.clone().map(TaskStore::spawn_writer),
+        store,
+    });
+    // A refused status request is left to time out on the requester's side.
+    let status_workers = WorkerPool::from_env(
+        "status_requests",
+        DEFAULT_STATUS_WORKERS,
+        OverflowPolicy::Reject,
+    );
+    let submissions = subscribe(&client, PERCEPTION_URL_TASK_SUBJECT, &shutdown).await?;
+    let statuses = subscribe(&client, TASK_STATUS_EVENT_SUBJECT, &shutdown).await?;
+    let errors_subject = format!("{}.>", PIPELINE_ERROR_SUBJECT_PREFIX);
+    let errors = subscribe(&client, &errors_subject, &shutdown).await?;
+    let mut status_requests = subscribe(&client, DOCUMENT_STATUS_TASK_SUBJECT, &shutdown).await?;
+
+    tokio::spawn(follow_submissions(submissions, Arc::clone(&orchestrator)));
+    tokio::spawn(follow_status_changes(statuses, Arc::clone(&orchestrator)));
+    tokio::spawn(follow_pipeline_errors(errors, Arc::clone(&orchestrator)));
+    tokio::spawn(run_sweeps(
+        Arc::clone(&client),
+        Arc::clone(&orchestrator),
+        config,
+    ));
+
+    let store_for_health = orchestrator.store.clone();
+    serve_health((*client).clone(), SERVICE_NAME, move || {
+        let store = store_for_health.clone();
+        async move {
+            match store {
+                Some(store) => vec![store.check().await],
+                None => Vec::new(),
+            }
+        }
+    })
+    .await?;
+
+    info!("[NATS_URL] Waiting for status requests...");
+
+    while let Some(message) = status_requests.next().await {
+        let client = Arc::clone(&client);
+        let orchestrator = Arc::clone(&orchestrator);
+        let in_flight = shutdown.track();
+        let _ = status_workers
+            .spawn_limited(async move {
+                let _in_flight = in_flight;
+                handle_document_status_task(message, &client, &orchestrator).await;
+            })
+            .await;
+    }
+
+    info!("[NATS_URL] Status request subscription ended or NATS connection lost.");
+    shutdown.drain(&client, drain_timeout).await;
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use log::warn;
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts};
+use shared_models::DocumentState;
+use std::sync::LazyLock;
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the pipeline duration buckets.
+const PIPELINE_DURATION_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 15.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+struct Metrics {
+    documents: IntGaugeVec,
+    stuck_alerts: IntCounter,
+    pipeline_duration: HistogramVec,
+    store_writes_lost: IntCounterVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| {
+    let metrics = Metrics {
+        documents: IntGaugeVec::new(
+            Opts::new(
+                "symbiont_orchestrator_documents",
+                "Documents followed by the orchestrator, by state.",
+            ),
+            &["state"],
+        )
+        .expect("valid metric"),
+        stuck_alerts: IntCounter::new(
+            "symbiont_orchestrator_stuck_alerts_total",
+            "Documents reported stuck.",
+        )
+        .expect("valid metric"),
+        pipeline_duration: HistogramVec::new(
+            HistogramOpts::new(
+                "symbiont_orchestrator_pipeline_duration_seconds",
+                "Time from submission until a document was graphed or failed.",
+            )
+            .buckets(PIPELINE_DURATION_BUCKETS.to_vec()),
+            &["state"],
+        )
+        .expect("valid metric"),
+        store_writes_lost: IntCounterVec::new(
+            Opts::new(
+                "symbiont_orchestrator_store_writes_lost_total",
+                "Task store writes given up on, because they failed or the queue was full.",
+            ),
+            &["reason"],
+        )
+        .expect("valid metric"),
+    };
+    let registry = shared_telemetry::registry();
+    let registered = registry
+        .register(Box::new(metrics.documents.clone()))
+        .and_then(|()| registry.register(Box::new(metrics.stuck_alerts.clone())))
+        .and_then(|()| registry.register(Box::new(metrics.pipeline_duration.clone())))
+        .and_then(|()| registry.register(Box::new(metrics.store_writes_lost.clone())));
+    if let Err(e) = registered {
+        warn!("[METRICS] Failed to register orchestrator metrics: {}", e);
+    }
+    metrics
+});
+
+/// A document moved from `from`, or started being followed, to `to`.
+pub fn document_moved(from: Option<DocumentState>, to: DocumentState) {
+    if from == Some(to) {
+        return;
+    }
+    if let Some(from) = from {
+        METRICS.documents.with_label_values(&[from.as_str()]).dec();
+    }
+    METRICS.documents.with_label_values(&[to.as_str()]).inc();
+}
+
+/// A document in `state` is no longer followed.
+pub fn document_forgotten(state: DocumentState) {
+    METRICS.documents.with_label_values(&[state.as_str()]).dec();
+}
+
+/// Time from submission until a document reached the final `state`.
+pub fn observe_pipeline(state: DocumentState, elapsed: Duration) {
+    METRICS
+        .pipeline_duration
+        .with_label_values(&[state.as_str()])
+        .observe(elapsed.as_secs_f64());
+}
+
+pub fn stuck_alert() {
+    METRICS.stuck_alerts.inc();
+}
+
+/// A task store write was given up on, for `reason`: `failed` or `queue_full`.
+pub fn store_write_lost(reason: &str) {
+    METRICS.store_writes_lost.with_label_values(&[reason]).inc();
+}
@@ -0,0 +1,338 @@
+//! The task store: every followed document and every status change or error recorded for
+//! it, kept in Postgres so task history survives restarts and outlives the in-memory
+//! retention. Writes go through one background writer, in the order the events arrived;
+//! each write upserts the document's whole row, so a write lost to a full queue or a
+//! Postgres outage is made good by the document's next one.
+
+use crate::metrics;
+use log::{error, info, warn};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use shared_config::PostgresSettings;
+use shared_models::{
+    DependencyCheck, DocumentId, DocumentLifecycle, StageProgress, TaskId, TaskStatus,
+};
+use shared_resilience::{RetryPolicy, retry_with_backoff};
+use sqlx::postgres::{PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
+use sqlx::{Row, Transaction};
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+/// Writes waiting for the writer before further ones are dropped.
+const WRITE_QUEUE_CAPACITY: usize = 10_000;
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Connecting at startup: five attempts, five seconds apart, unless the `POSTGRES_CONNECT_*`
+/// retry variables say otherwise.
+const DEFAULT_CONNECT_RETRY: RetryPolicy =
+    RetryPolicy::new(4, Duration::from_secs(5), Duration::from_secs(5));
+/// Writes that fail for a passing reason are retried three times unless the
+/// `POSTGRES_WRITE_*` retry variables say otherwise.
+const DEFAULT_WRITE_RETRY: RetryPolicy =
+    RetryPolicy::new(3, Duration::from_millis(200), Duration::from_secs(5));
+
+static WRITE_RETRY: LazyLock<RetryPolicy> =
+    LazyLock::new(|| RetryPolicy::from_env("POSTGRES_WRITE", DEFAULT_WRITE_RETRY));
+
+const SCHEMA: [&str; 7] = [
+    "CREATE TABLE IF NOT EXISTS tasks (
+        task_id UUID PRIMARY KEY,
+        original_id UUID,
+        source_url TEXT,
+        state TEXT NOT NULL,
+        stages JSONB NOT NULL DEFAULT '[]',
+        received_at_ms BIGINT NOT NULL,
+        updated_at_ms BIGINT NOT NULL,
+        stuck_since_ms BIGINT
+    )",
+    "ALTER TABLE tasks ADD COLUMN IF NOT EXISTS tenant_id TEXT",
+    "CREATE INDEX IF NOT EXISTS tasks_original_id_idx ON tasks (original_id)",
+    "CREATE INDEX IF NOT EXISTS tasks_updated_at_idx ON tasks (updated_at_ms)",
+    "CREATE TABLE IF NOT EXISTS task_events (
+        event_id BIGSERIAL PRIMARY KEY,
+        task_id UUID NOT NULL REFERENCES tasks (task_id) ON DELETE CASCADE,
+        stage TEXT NOT NULL,
+        status TEXT NOT NULL,
+        detail TEXT,
+        recorded_at_ms BIGINT NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS task_events_task_idx ON task_events (task_id, event_id)",
+    "CREATE INDEX IF NOT EXISTS tasks_open_idx ON tasks (updated_at_ms)
+        WHERE state NOT IN ('graphed', 'failed')",
+];
+
+const TASK_COLUMNS: &str = "task_id, original_id, source_url, state, stages, received_at_ms, \
+    updated_at_ms, stuck_since_ms, tenant_id";
+
+/// A document's latest state and, when it changed because of one, the status change or
+/// error that changed it.
+pub struct TaskRecord {
+    pub document: DocumentLifecycle,
+    pub event: Option<StageProgress>,
+}
+
+#[derive(Clone)]
+pub struct TaskStore {
+    pool: PgPool,
+}
+
+impl TaskStore {
+    /// Connects to `settings.url`, retrying while Postgres is starting, and creates the
+    /// tables. `None` when no URL is configured.
+    pub async fn connect(settings: &PostgresSettings) -> Result<Option<Self>, sqlx::Error> {
+        let Some(url) = settings.url.as_deref() else {
+            return Ok(None);
+        };
+        let policy = RetryPolicy::from_env("POSTGRES_CONNECT", DEFAULT_CONNECT_RETRY);
+        let (pool, _) = retry_with_backoff(
+            &policy,
+            "Connecting to Postgres",
+            || {
+                PgPoolOptions::new()
+                    .max_connections(settings.max_connections.max(1))
+                    .acquire_timeout(ACQUIRE_TIMEOUT)
+                    .connect(url)
+            },
+            is_transient,
+        )
+        .await;
+        let store = TaskStore { pool: pool? };
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&store.pool).await?;
+        }
+        info!("[TASK_STORE] Connected to Postgres; task tables are ready.");
+        Ok(Some(store))
+    }
+
+    /// Documents still in the pipeline and those done within `retention`, most recently
+    /// updated first, at most `limit` of them.
+    pub async fn load_recent(
+        &self,
+        now_ms: u64,
+        retention: Duration,
+        limit: usize,
+    ) -> Result<Vec<DocumentLifecycle>, sqlx::Error> {
+        let since_ms = now_ms.saturating_sub(u64::try_from(retention.as_millis()).unwrap_or(0));
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM tasks
+            WHERE state NOT IN ('graphed', 'failed') OR updated_at_ms >= $1
+            ORDER BY updated_at_ms DESC
+            LIMIT $2",
+            TASK_COLUMNS
+        ))
+        .bind(to_db_ms(since_ms))
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter().map(document_from_row).collect()
+    }
+
+    /// The document of submission `task_id`, or else the latest submission of document
+    /// `original_id`.
+    pub async fn find(
+        &self,
+        task_id: Option<TaskId>,
+        original_id: Option<DocumentId>,
+    ) -> Result<Option<DocumentLifecycle>, sqlx::Error> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM tasks
+            WHERE task_id = $1 OR ($1 IS NULL AND original_id = $2)
+            ORDER BY updated_at_ms DESC
+            LIMIT 1",
+            TASK_COLUMNS
+        ))
+        .bind(task_id.map(Uuid::from))
+        .bind(original_id.map(Uuid::from))
+        .fetch_optional(&self.pool)
+        .await?;
+        row.as_ref().map(document_from_row).transpose()
+    }
+
+    /// Every status change and error recorded for `task_id`, oldest first.
+    pub async fn history(&self, task_id: TaskId) -> Result<Vec<StageProgress>, sqlx::Error> {
+        let rows = sqlx::query(
+            "SELECT stage, status, detail, recorded_at_ms FROM task_events
+            WHERE task_id = $1
+            ORDER BY event_id",
+        )
+        .bind(Uuid::from(task_id))
+        .fetch_all(&self.pool)
+        .await?;
+        rows.iter()
+            .map(|row| {
+                Ok(StageProgress {
+                    stage: from_text(row.try_get("stage")?)?,
+                    status: from_text::<TaskStatus>(row.try_get("status")?)?,
+                    detail: row.try_get("detail")?,
+                    updated_at_ms: from_db_ms(row.try_get("recorded_at_ms")?),
+                })
+            })
+            .collect()
+    }
+
+    /// Deletes documents, with their history, last updated before `before_ms`.
+    pub async fn prune(&self, before_ms: u64) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM tasks WHERE updated_at_ms < $1")
+            .bind(to_db_ms(before_ms))
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    pub async fn check(&self) -> DependencyCheck {
+        let started = Instant::now();
+        let result = sqlx::query("SELECT 1")
+            .execute(&self.pool)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string());
+        DependencyCheck::timed("postgres", started.elapsed(), result)
+    }
+
+    async fn save(&self, record: &TaskRecord) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        upsert_document(&mut tx, &record.document).await?;
+        if let Some(event) = &record.event {
+            sqlx::query(
+                "INSERT INTO task_events (task_id, stage, status, detail, recorded_at_ms)
+                VALUES ($1, $2, $3, $4, $5)",
+            )
+            .bind(Uuid::from(record.document.task_id))
+            .bind(to_text(&event.stage))
+            .bind(to_text(&event.status))
+            .bind(event.detail.as_deref())
+            .bind(to_db_ms(event.updated_at_ms))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await
+    }
+
+    /// Starts the background writer; records are queued through the returned handle.
+    pub fn spawn_writer(self) -> TaskStoreWriter {
+        let (sender, mut receiver) = mpsc::channel::<TaskRecord>(WRITE_QUEUE_CAPACITY);
+        tokio::spawn(async move {
+            while let Some(record) = receiver.recv().await {
+                let description = format!("Saving task {}", record.document.task_id);
+                let (result, _) = retry_with_backoff(
+                    &WRITE_RETRY,
+                    &description,
+                    || self.save(&record),
+                    is_transient,
+                )
+                .await;
+                if let Err(e) = result {
+                    metrics::store_write_lost("failed");
+                    error!(
+                        "[TASK_STORE_WRITE_FAIL] Failed to save task {}: {}",
+                        record.document.task_id, e
+                    );
+                }
+            }
+            info!("[TASK_STORE] Writer stopped.");
+        });
+        TaskStoreWriter { sender }
+    }
+}
+
+/// Queues records for the task store's writer.
+#[derive(Clone)]
+pub struct TaskStoreWriter {
+    sender: mpsc::Sender<TaskRecord>,
+}
+
+impl TaskStoreWriter {
+    /// Queues `record` without waiting; when the queue is full it is dropped.
+    pub fn record(&self, record: TaskRecord) {
+        if let Err(e) = self.sender.try_send(record) {
+            metrics::store_write_lost("queue_full");
+            let task_id = match &e {
+                mpsc::error::TrySendError::Full(record)
+                | mpsc::error::TrySendError::Closed(record) => record.document.task_id,
+            };
+            warn!(
+                "[TASK_STORE_QUEUE_FULL] Dropped the update of task {}; the store catches up with its next one.",
+                task_id
+            );
+        }
+    }
+}
+
+async fn upsert_document(
+    tx: &mut Transaction<'_, sqlx::Postgres>,
+    document: &DocumentLifecycle,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!(
+        "INSERT INTO tasks ({})
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        ON CONFLICT (task_id) DO UPDATE SET
+            original_id = EXCLUDED.original_id,
+            source_url = EXCLUDED.source_url,
+            state = EXCLUDED.state,
+            stages = EXCLUDED.stages,
+            received_at_ms = EXCLUDED.received_at_ms,
+            updated_at_ms = EXCLUDED.updated_at_ms,
+            stuck_since_ms = EXCLUDED.stuck_since_ms,
+            tenant_id = EXCLUDED.tenant_id",
+        TASK_COLUMNS
+    ))
+    .bind(Uuid::from(document.task_id))
+    .bind(document.original_id.map(Uuid::from))
+    .bind(document.source_url.as_deref())
+    .bind(document.state.as_str())
+    .bind(Json(&document.stages))
+    .bind(to_db_ms(document.received_at_ms))
+    .bind(to_db_ms(document.updated_at_ms))
+    .bind(document.stuck_since_ms.map(to_db_ms))
+    .bind(document.tenant_id.as_deref())
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+fn document_from_row(row: &PgRow) -> Result<DocumentLifecycle, sqlx::Error> {
+    let Json(stages): Json<Vec<StageProgress>> = row.try_get("stages")?;
+    Ok(DocumentLifecycle {
+        task_id: TaskId::from_uuid(row.try_get("task_id")?),
+        original_id: row
+            .try_get::<Option<Uuid>, _>("original_id")?
+            .map(DocumentId::from_uuid),
+        source_url: row.try_get("source_url")?,
+        tenant_id: row.try_get("tenant_id")?,
+        state: from_text(row.try_get("state")?)?,
+        stages,
+        received_at_ms: from_db_ms(row.try_get("received_at_ms")?),
+        updated_at_ms: from_db_ms(row.try_get("updated_at_ms")?),
+        stuck_since_ms: row
+            .try_get::<Option<i64>, _>("stuck_since_ms")?
+            .map(from_db_ms),
+    })
+}
+
+/// Connection trouble that may pass, as opposed to a rejected statement.
+fn is_transient(e: &sqlx::Error) -> bool {
+    matches!(e, sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut)
+}
+
+/// The serde name of a unit variant, e.g. `knowledge_graph`, as stored in text columns.
+fn to_text<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(text)) => text,
+        _ => String::new(),
+    }
+}
+
+fn from_text<T: DeserializeOwned>(text: String) -> Result<T, sqlx::Error> {
+    serde_json::from_value(serde_json::Value::String(text))
+        .map_err(|e| sqlx::Error::Decode(Box::new(e)))
+}
+
+fn to_db_ms(ms: u64) -> i64 {
+    i64::try_from(ms).unwrap_or(i64::MAX)
+}
+
+fn from_db_ms(ms: i64) -> u64 {
+    u64::try_from(ms).unwrap_or(0)
+}
@@ -0,0 +1,190 @@
+//! The documents the orchestrator follows, by task id and, once perception_service has
+//! assigned one, by document id. Documents stay here until they have been done for longer
+//! than the retention; past the capacity the longest-idle ones are forgotten first.
+
+use crate::metrics;
+use shared_models::{
+    DocumentId, DocumentLifecycle, DocumentState, DocumentStuckAlert, PipelineErrorMessage, TaskId,
+    TaskStatusChangedMessage,
+};
+use std::collections::HashMap;
+use std::time::Duration;
+
+pub struct Tracker {
+    documents: HashMap<TaskId, DocumentLifecycle>,
+    by_document: HashMap<DocumentId, TaskId>,
+    retention_ms: u64,
+    capacity: usize,
+}
+
+impl Tracker {
+    pub fn new(retention: Duration, capacity: usize) -> Self {
+        Tracker {
+            documents: HashMap::new(),
+            by_document: HashMap::new(),
+            retention_ms: u64::try_from(retention.as_millis()).unwrap_or(u64::MAX),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Follows a document loaded from the task store, unless it is followed already.
+    pub fn restore(&mut self, document: DocumentLifecycle) {
+        if self.documents.contains_key(&document.task_id) {
+            return;
+        }
+        metrics::document_moved(None, document.state);
+        if let Some(original_id) = document.original_id {
+            self.by_document.insert(original_id, document.task_id);
+        }
+        self.documents.insert(document.task_id, document);
+    }
+
+    /// A submission of `source_url` for `tenant_id` was seen. Returns the document as it is
+    /// now.
+    pub fn received(
+        &mut self,
+        task_id: TaskId,
+        source_url: String,
+        tenant_id: Option<String>,
+        at_ms: u64,
+    ) -> DocumentLifecycle {
+        let document = self.document(task_id, at_ms);
+        if document.source_url.is_none() {
+            document.source_url = Some(source_url);
+        }
+        if document.tenant_id.is_none() {
+            document.tenant_id = tenant_id;
+        }
+        document.clone()
+    }
+
+    /// Applies a stage's status change, published for `tenant_id`, following the document
+    /// from now on if it was not followed yet, e.g. because it was submitted before the
+    /// orchestrator started. Returns the document as it is now.
+    pub fn record_status(
+        &mut self,
+        status: &TaskStatusChangedMessage,
+        tenant_id: Option<String>,
+    ) -> DocumentLifecycle {
+        let document = self.document(status.task_id, status.timestamp_ms);
+        if document.tenant_id.is_none() {
+            document.tenant_id = tenant_id;
+        }
+        let before = document.state;
+        document.record_status(status);
+        let document = document.clone();
+        self.settle(&document, before);
+        document
+    }
+
+    /// Applies a stage giving up on the submission `task_id`. Errors of submissions that are
+    /// not followed are ignored: they may concern generation tasks or undecodable messages.
+    /// Returns the document as it is now, if followed.
+    pub fn record_error(
+        &mut self,
+        task_id: TaskId,
+        error: &PipelineErrorMessage,
+    ) -> Option<DocumentLifecycle> {
+        let document = self.documents.get_mut(&task_id)?;
+        let before = document.state;
+        document.record_error(error);
+        let document = document.clone();
+        self.settle(&document, before);
+        Some(document)
+    }
+
+    pub fn by_task(&self, task_id: TaskId) -> Option<&DocumentLifecycle> {
+        self.documents.get(&task_id)
+    }
+
+    pub fn by_document(&self, original_id: DocumentId) -> Option<&DocumentLifecycle> {
+        self.by_document
+            .get(&original_id)
+            .and_then(|task_id| self.documents.get(task_id))
+    }
+
+    /// Reports documents that have not moved for longer than `stage_timeout` and forgets
+    /// those done for longer than the retention, then the longest-idle ones past the
+    /// capacity.
+    pub fn sweep(&mut self, now_ms: u64, stage_timeout: Duration) -> Vec<DocumentStuckAlert> {
+        let timeout_ms = u64::try_from(stage_timeout.as_millis()).unwrap_or(u64::MAX);
+        let alerts: Vec<_> = self
+            .documents
+            .values_mut()
+            .filter_map(|document| {
+                document
+                    .mark_if_stuck(now_ms, timeout_ms)
+                    .then(|| DocumentStuckAlert {
+                        task_id: document.task_id,
+                        original_id: document.original_id,
+                        source_url: document.source_url.clone(),
+                        state: document.state,
+                        stalled_for_ms: now_ms.saturating_sub(document.updated_at_ms),
+                        timestamp_ms: now_ms,
+                    })
+            })
+            .collect();
+
+        let expired: Vec<_> = self
+            .documents
+            .values()
+            .filter(|document| {
+                document.state.is_final()
+                    && now_ms.saturating_sub(document.updated_at_ms) > self.retention_ms
+            })
+            .map(|document| document.task_id)
+            .collect();
+        for task_id in expired {
+            self.forget(task_id);
+        }
+        if self.documents.len() > self.capacity {
+            let mut idle: Vec<_> = self
+                .documents
+                .values()
+                .map(|document| (document.updated_at_ms, document.task_id))
+                .collect();
+            idle.sort_unstable();
+            let excess = self.documents.len() - self.capacity;
+            for (_, task_id) in idle.into_iter().take(excess) {
+                self.forget(task_id);
+            }
+        }
+        alerts
+    }
+
+    fn document(&mut self, task_id: TaskId, at_ms: u64) -> &mut DocumentLifecycle {
+        self.documents.entry(task_id).or_insert_with(|| {
+            metrics::document_moved(None, DocumentState::Received);
+            DocumentLifecycle::received(task_id, None, at_ms)
+        })
+    }
+
+    /// Updates the document id index and metrics after the document left `before`.
+    fn settle(&mut self, document: &DocumentLifecycle, before: DocumentState) {
+        if let Some(original_id) = document.original_id {
+            self.by_document.insert(original_id, document.task_id);
+        }
+        metrics::document_moved(Some(before), document.state);
+        if !before.is_final() && document.state.is_final() {
+            metrics::observe_pipeline(
+                document.state,
+                Duration::from_millis(
+                    document
+                        .updated_at_ms
+                        .saturating_sub(document.received_at_ms),
+                ),
+            );
+        }
+    }
+
+    fn forget(&mut self, task_id: TaskId) {
+        if let Some(document) = self.documents.remove(&task_id) {
+            if let Some(original_id) = document.original_id
+                && self.by_document.get(&original_id) == Some(&task_id)
+            {
+                self.by_document.remove(&original_id);
+            }
+            metrics::document_forgotten(document.state);
+        }
+    }
+}
@@ -1,28 +1,124 @@
 use async_nats::Client as NatsClient;
+use async_nats::jetstream;
 use futures::StreamExt;
 use log::{debug, error, info, trace, warn};
 use scraper::{Html, Selector};
-use serde_json;
-use std::sync::Arc;
-use std::{env, time::Duration};
-use uuid::Uuid;
-
-use shared_models::{PerceiveUrlTask, RawTextMessage, current_timestamp_ms};
-
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use shared_config::Settings;
+use shared_models::{
+    CONTENT_ENCODING_HEADER, DeadLetterMessage, DocumentId, DocumentMetadata, Envelope,
+    LOG_TEXT_CHARS, PerceiveUrlTask, PipelineErrorKind, PipelineErrorMessage, PipelineStage,
+    RawTextMessage, TASK_STATUS_EVENT_SUBJECT, TaskStatus, TaskStatusChangedMessage, Truncated,
+    UndecodedPayload, Validate, compress_above, current_timestamp_ms,
+};
+use shared_nats::{
+    ConsumerConfig, DEAD_LETTERS_STREAM, OverflowPolicy, PERCEIVE_TASKS_STREAM, Quotas,
+    RAW_TEXT_STREAM, RecentMessages, Shutdown, WorkerPool, dead_letter_undecodable,
+    durable_messages, insert_message_id, publish_dead_letter, publish_durable, receive_span,
+    serve_health, traced_headers,
+};
+use shared_resilience::{BreakerError, BreakerGroup, RetryPolicy, retry_with_backoff};
+use tracing::Instrument;
+
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
 const PERCEPTION_URL_TASK_SUBJECT: &str = "tasks.perceive.url";
 const RAW_TEXT_DISCOVERED_SUBJECT: &str = "data.raw_text.discovered";
+/// Scrapes running at once unless `WORKERS_PERCEIVE_TASKS_CONCURRENCY` says otherwise.
+const DEFAULT_SCRAPE_WORKERS: usize = 16;
+/// Page fetches that time out or cannot connect are retried twice unless the
+/// `HTTP_FETCH_*` retry variables say otherwise.
+const DEFAULT_FETCH_RETRY: RetryPolicy =
+    RetryPolicy::new(2, Duration::from_millis(500), Duration::from_secs(5));
+
+/// Reports a task this service gave up on to [`PipelineStage::Scraping`]'s error subject.
+async fn publish_pipeline_error(
+    nats_client: &NatsClient,
+    cause: Option<&Envelope<()>>,
+    pipeline_error: PipelineErrorMessage,
+) {
+    let subject = pipeline_error.stage.error_subject();
+    match Envelope::following(cause, SERVICE_NAME, &pipeline_error).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish(subject.clone(), payload_json.into())
+                .await
+            {
+                error!(
+                    "[NATS_PUB_FAIL] Failed to publish PipelineErrorMessage to {}: {}",
+                    subject, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[SERIALIZE_FAIL] Failed to serialize PipelineErrorMessage: {}",
+                e
+            );
+        }
+    }
+}
 
+async fn publish_task_status(
+    nats_client: &NatsClient,
+    cause: &Envelope<()>,
+    status: TaskStatusChangedMessage,
+) {
+    match cause.follow_up(SERVICE_NAME, &status).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish_with_headers(
+                    TASK_STATUS_EVENT_SUBJECT,
+                    traced_headers(),
+                    payload_json.into(),
+                )
+                .await
+            {
+                error!(
+                    "[NATS_PUB_FAIL] Failed to publish {:?} status of task {}: {}",
+                    status.status, status.task_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[SERIALIZE_FAIL] Failed to serialize TaskStatusChangedMessage: {}",
+                e
+            );
+        }
+    }
+}
+
+/// Scrapes the task's URL and publishes the text, returning the new document's id.
 async fn scrape_and_publish(
     task: PerceiveUrlTask,
-    nats_client: Arc<NatsClient>,
-) -> Result<(), Box<dyn std::error::Error>> {
+    cause: &Envelope<()>,
+    nats_client: &NatsClient,
+    jetstream: &jetstream::Context,
+    compression_threshold: usize,
+) -> Result<DocumentId, Box<dyn std::error::Error>> {
     info!("[TASK] Processing task for URL: {}", task.url);
 
-    let scraped_text = match scrape_url_content(&task.url).await {
-        Ok(text) => text,
+    // The error is not Send, so only its message is kept across the publish below.
+    let (scraped_text, metadata) = match scrape_url_content(&task.url)
+        .await
+        .map_err(|e| e.to_string())
+    {
+        Ok(scraped) => scraped,
         Err(e) => {
             error!("[SCRAPE_FAIL] Failed to scrape URL {}: {}", task.url, e);
-            return Err(e);
+            publish_pipeline_error(
+                nats_client,
+                Some(cause),
+                PipelineErrorMessage::new(
+                    PipelineStage::Scraping,
+                    PipelineErrorKind::Fetch,
+                    format!("Failed to scrape {}: {}", task.url, e),
+                ),
+            )
+            .await;
+            return Err(e.into());
         }
     };
 
@@ -31,7 +127,7 @@ async fn scrape_and_publish(
             "[SCRAPE_EMPTY] Scraping URL {} yielded no text. Not publishing.",
             task.url
         );
-        return Ok(());
+        return Err("the page yielded no text".into());
     }
 
     info!(
@@ -44,29 +140,37 @@ async fn scrape_and_publish(
         scraped_text
     );
 
-    let raw_msg = RawTextMessage {
-        id: Uuid::new_v4().to_string(),
-        source_url: task.url.clone(),
-        raw_text: scraped_text,
-        timestamp_ms: current_timestamp_ms(),
-    };
+    debug!("[SCRAPE_METADATA] Metadata of {}: {:?}", task.url, metadata);
+    let raw_msg = RawTextMessage::new(task.url.clone(), scraped_text).with_metadata(metadata);
 
-    let Ok(payload_json) = serde_json::to_vec(&raw_msg) else {
+    let Ok(payload_json) = cause.follow_up(SERVICE_NAME, &raw_msg).to_vec() else {
         error!(
             "[SERIALIZE_FAIL] Failed to serialize RawTextMessage to JSON for id: {}",
             raw_msg.id
         );
         return Err("Failed to serialize RawTextMessage".into());
     };
+    let json_len = payload_json.len();
+    let (payload, content_encoding) = compress_above(payload_json, compression_threshold)?;
+    let mut headers = async_nats::HeaderMap::new();
+    if let Some(encoding) = content_encoding.header_value() {
+        headers.insert(CONTENT_ENCODING_HEADER, encoding);
+        debug!(
+            "[NATS_PUB] Compressed RawTextMessage (id: {}) from {} to {} bytes",
+            raw_msg.id,
+            json_len,
+            payload.len()
+        );
+    }
+    // A redelivered task is scraped again; the stream drops the repeated document.
+    insert_message_id(&mut headers, cause, RAW_TEXT_DISCOVERED_SUBJECT, "");
 
     debug!(
         "[NATS_PUB] Publishing RawTextMessage (id: {}) to subject: {}",
         raw_msg.id, RAW_TEXT_DISCOVERED_SUBJECT
     );
 
-    if let Err(e) = nats_client
-        .publish(RAW_TEXT_DISCOVERED_SUBJECT, payload_json.into())
-        .await
+    if let Err(e) = publish_durable(jetstream, RAW_TEXT_DISCOVERED_SUBJECT, headers, payload).await
     {
         error!(
             "[NATS_PUB_FAIL] Failed to publish RawTextMessage (id: {}) to NATS: {}",
@@ -80,10 +184,87 @@ async fn scrape_and_publish(
         );
     }
 
-    Ok(())
+    Ok(raw_msg.id)
 }
 
-async fn scrape_url_content(url: &str) -> Result<String, Box<dyn std::error::Error>> {
+/// Reads what the page declares about itself from its `<head>`, preferring Open Graph and
+/// article tags over the generic ones.
+fn extract_metadata(document: &Html, content_type: Option<String>) -> DocumentMetadata {
+    let first = |selectors: &[&str], attribute: Option<&str>| {
+        selectors.iter().find_map(|selector_str| {
+            let selector = Selector::parse(selector_str).ok()?;
+            document.select(&selector).find_map(|element| {
+                let value = match attribute {
+                    Some(attribute) => element.value().attr(attribute)?.to_string(),
+                    None => element.text().collect::<String>(),
+                };
+                let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+                (!value.is_empty()).then_some(value)
+            })
+        })
+    };
+
+    DocumentMetadata {
+        title: first(&["meta[property='og:title']"], Some("content"))
+            .or_else(|| first(&["title"], None)),
+        language: first(&["html[lang]"], Some("lang"))
+            .or_else(|| first(&["meta[http-equiv='content-language']"], Some("content"))),
+        author: first(
+            &["meta[name='author']", "meta[property='article:author']"],
+            Some("content"),
+        ),
+        published_at: first(
+            &[
+                "meta[property='article:published_time']",
+                "meta[name='date']",
+                "meta[itemprop='datePublished']",
+            ],
+            Some("content"),
+        )
+        .or_else(|| first(&["time[datetime]"], Some("datetime"))),
+        content_type: content_type
+            .and_then(|value| {
+                value
+                    .split(';')
+                    .next()
+                    .map(|media_type| media_type.trim().to_string())
+            })
+            .filter(|media_type| !media_type.is_empty()),
+        canonical_url: first(&["link[rel='canonical']"], Some("href"))
+            .or_else(|| first(&["meta[property='og:url']"], Some("content"))),
+    }
+}
+
+/// One breaker per host, so the URLs of a site that is down fail at once instead of each
+/// waiting out the timeout; configured by the `BREAKER_HTTP_FETCH_*` variables.
+static HOST_BREAKERS: LazyLock<BreakerGroup> =
+    LazyLock::new(|| BreakerGroup::from_env("http_fetch"));
+
+/// Whether `e` shows the host unreachable or too slow; an error status is still an answer.
+fn is_host_outage(e: &reqwest::Error) -> bool {
+    e.is_connect() || e.is_timeout()
+}
+
+static FETCH_RETRY: LazyLock<RetryPolicy> =
+    LazyLock::new(|| RetryPolicy::from_env("HTTP_FETCH", DEFAULT_FETCH_RETRY));
+
+/// The content type and body of the page at `url`.
+async fn fetch_page(
+    client: &reqwest::Client,
+    url: &str,
+) -> Result<(Option<String>, String), reqwest::Error> {
+    let response = client.get(url).send().await?;
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    Ok((content_type, response.text().await?))
+}
+
+async fn scrape_url_content(
+    url: &str,
+) -> Result<(String, DocumentMetadata), Box<dyn std::error::Error>> {
     info!("[SCRAPE_URL_CONTENT] Scraping URL: {}", url);
 
     let client = reqwest::Client::builder()
@@ -91,9 +272,26 @@ async fn scrape_url_content(url: &str) -> Result<String, Box<dyn std::error::Err
         .user_agent("CodenameSymbiontBot/0.1 (+https://makkenzo.com)")
         .build()?;
 
-    let response_text = client.get(url).send().await?.text().await?;
+    let host = reqwest::Url::parse(url)?
+        .host_str()
+        .unwrap_or_default()
+        .to_string();
+    let breaker = HOST_BREAKERS.get(&host);
+    let (fetched, _) = retry_with_backoff(
+        &FETCH_RETRY,
+        &format!("Fetching {}", url),
+        || breaker.call_with(is_host_outage, fetch_page(&client, url)),
+        |e| match e {
+            BreakerError::Failed(e) => is_host_outage(e),
+            BreakerError::Injected(_) => true,
+            BreakerError::Open(_) => false,
+        },
+    )
+    .await;
+    let (content_type, response_text) = fetched?;
 
     let document = Html::parse_document(&response_text);
+    let metadata = extract_metadata(&document, content_type);
 
     let mut content_parts = Vec::new();
 
@@ -166,25 +364,20 @@ async fn scrape_url_content(url: &str) -> Result<String, Box<dyn std::error::Err
         );
     }
 
-    Ok(extracted_text)
+    Ok((extracted_text, metadata))
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info", &settings, String::new)?;
     info!("Starting ...");
 
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!("[NATS_URL] NATS_URL not set, defaulting to nats://localhost:4222");
-        "nats://localhost:4222".to_string()
-    });
+    let compression_threshold = settings.nats.compression_threshold;
+    let drain_timeout = settings.shutdown.drain_timeout();
+    let shutdown = Shutdown::listen();
 
-    info!(
-        "[NATS_URL] Attempting to connect to NATS server at {}...",
-        nats_url
-    );
-
-    let client = Arc::new(match async_nats::connect(&nats_url).await {
+    let client = Arc::new(match shared_nats::connect(&settings.nats).await {
         Ok(client) => {
             info!("[NATS_URL] Successfully connected to NATS!");
             client
@@ -195,53 +388,199 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    let mut subscriber = match client.subscribe(PERCEPTION_URL_TASK_SUBJECT).await {
-        Ok(sub) => {
-            info!(
-                "[NATS_URL] Subscribed to subject: {}",
-                PERCEPTION_URL_TASK_SUBJECT
-            );
-            sub
-        }
-        Err(err) => {
-            error!(
-                "[NATS_URL] Failed to subscribe to {}: {}",
-                PERCEPTION_URL_TASK_SUBJECT, err
-            );
-            return Err(Box::new(err) as Box<dyn std::error::Error>);
-        }
-    };
+    let jetstream = jetstream::new((*client).clone());
+    RAW_TEXT_STREAM.ensure(&jetstream).await?;
+    DEAD_LETTERS_STREAM.ensure(&jetstream).await?;
+    let consumer_config = ConsumerConfig::new(SERVICE_NAME, PERCEPTION_URL_TASK_SUBJECT)
+        .with_env_overrides(&PERCEIVE_TASKS_STREAM);
+    let mut tasks = durable_messages(&jetstream, &PERCEIVE_TASKS_STREAM, &consumer_config)
+        .await?
+        .take_until(shutdown.signalled());
+    let recent_tasks = RecentMessages::from_env();
+    let quotas = Quotas::stored_sentences_from_env((*client).clone(), SERVICE_NAME);
+    let scrape_workers = WorkerPool::from_env(
+        "perceive_tasks",
+        DEFAULT_SCRAPE_WORKERS,
+        OverflowPolicy::Wait,
+    );
+
+    serve_health((*client).clone(), SERVICE_NAME, || async { Vec::new() }).await?;
 
     info!("[NATS_URL] Waiting for URL tasks...");
 
-    while let Some(message) = subscriber.next().await {
+    while let Some(next) = tasks.next().await {
+        let message = match next {
+            Ok(message) => message,
+            Err(e) => {
+                error!(
+                    "[NATS_URL] Failed to receive task from JetStream consumer: {}",
+                    e
+                );
+                continue;
+            }
+        };
         info!(
             "[NATS_URL] Received message on subject: {}",
             message.subject
         );
 
-        match serde_json::from_slice::<PerceiveUrlTask>(&message.payload) {
-            Ok(task) => {
-                info!("[NATS_URL] Deserialized task for URL: {}", task.url);
+        match Envelope::<PerceiveUrlTask>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let (cause, task) = envelope.split();
+                info!(
+                    "[NATS_URL] Deserialized task for URL: {} (priority: {:?}, deadline_ms: {:?})",
+                    task.url,
+                    task.priority(),
+                    task.deadline_ms
+                );
+                let Some(claim) = recent_tasks.claim_delivery(&message, &cause).await else {
+                    continue;
+                };
+                let rejection = match task.validate() {
+                    Err(e) => Some((
+                        PipelineErrorKind::InvalidMessage,
+                        format!("Invalid URL '{}': {}", task.url, e),
+                        e.to_string(),
+                    )),
+                    Ok(()) if task.is_expired_at(current_timestamp_ms()) => Some((
+                        PipelineErrorKind::Expired,
+                        format!("Deadline passed before scraping '{}'", task.url),
+                        "the task's deadline has passed".to_string(),
+                    )),
+                    // Tasks queued before the tenant's stored sentences reached its quota.
+                    Ok(()) => match quotas
+                        .check_stored_sentences(cause.tenant_id.as_deref())
+                        .await
+                    {
+                        Ok(()) => None,
+                        Err(exceeded) => {
+                            let exceeded = exceeded.with_url(&task.url);
+                            quotas.publish_exceeded(&cause, &exceeded).await;
+                            Some((
+                                PipelineErrorKind::QuotaExceeded,
+                                format!("Not scraping '{}': {}", task.url, exceeded),
+                                format!("quota exceeded: {}", exceeded),
+                            ))
+                        }
+                    },
+                };
+                if let Some((error_kind, reason, detail)) = rejection {
+                    warn!("[NATS_URL] Skipping task: {}", reason);
+                    publish_pipeline_error(
+                        &client,
+                        Some(&cause),
+                        PipelineErrorMessage::new(PipelineStage::Scraping, error_kind, reason),
+                    )
+                    .await;
+                    let rejected = TaskStatusChangedMessage::new(
+                        &cause,
+                        PipelineStage::Scraping,
+                        TaskStatus::Failed,
+                    )
+                    .with_detail(detail);
+                    publish_task_status(&client, &cause, rejected).await;
+                    if let Err(e) = message.ack().await {
+                        error!("[NATS_URL] Failed to ack rejected task: {}", e);
+                    }
+                    continue;
+                }
 
+                let Some(worker) = scrape_workers.reserve_for(&message).await else {
+                    continue;
+                };
                 let nats_client_clone = Arc::clone(&client);
-
-                tokio::spawn(async move {
-                    if let Err(e) = scrape_and_publish(task, nats_client_clone).await {
-                        error!("[NATS_URL] Error during scrape_and_publish: {}", e);
+                let jetstream = jetstream.clone();
+                let span = receive_span(&message.subject, message.headers.as_ref());
+                let in_flight = shutdown.track();
+
+                worker.spawn(
+                    async move {
+                        let _in_flight = in_flight;
+                        let started = TaskStatusChangedMessage::new(
+                            &cause,
+                            PipelineStage::Scraping,
+                            TaskStatus::Started,
+                        );
+                        publish_task_status(&nats_client_clone, &cause, started).await;
+
+                        let result = scrape_and_publish(
+                            task.clone(),
+                            &cause,
+                            &nats_client_clone,
+                            &jetstream,
+                            compression_threshold,
+                        )
+                        .await
+                        .map_err(|e| e.to_string());
+                        let status = match result {
+                            Ok(original_id) => TaskStatusChangedMessage::new(
+                                &cause,
+                                PipelineStage::Scraping,
+                                TaskStatus::Completed,
+                            )
+                            .with_original_id(original_id),
+                            Err(e) => {
+                                error!("[NATS_URL] Error during scrape_and_publish: {}", e);
+                                publish_dead_letter(
+                                    &jetstream,
+                                    SERVICE_NAME,
+                                    Some(&cause),
+                                    &DeadLetterMessage::new(
+                                        PERCEPTION_URL_TASK_SUBJECT,
+                                        &task,
+                                        e.clone(),
+                                        1,
+                                    ),
+                                )
+                                .await;
+                                TaskStatusChangedMessage::new(
+                                    &cause,
+                                    PipelineStage::Scraping,
+                                    TaskStatus::Failed,
+                                )
+                                .with_detail(e)
+                            }
+                        };
+                        publish_task_status(&nats_client_clone, &cause, status).await;
+                        // A failed scrape has already been reported, so the task is acked either
+                        // way; only a crash before this point gets it redelivered.
+                        if let Err(e) = message.ack().await {
+                            error!("[NATS_URL] Failed to ack task: {}", e);
+                        }
+                        claim.handled();
                     }
-                });
+                    .instrument(span),
+                );
             }
             Err(e) => {
                 warn!(
                     "[NATS_URL] Failed to deserialize PerceiveUrlTask: {}. Payload: {:?}",
                     e,
-                    String::from_utf8_lossy(&message.payload)
+                    Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
                 );
+                publish_pipeline_error(
+                    &client,
+                    None,
+                    PipelineErrorMessage::new(
+                        PipelineStage::Scraping,
+                        PipelineErrorKind::InvalidMessage,
+                        format!("Invalid PerceiveUrlTask: {}", e),
+                    ),
+                )
+                .await;
+                dead_letter_undecodable(
+                    &jetstream,
+                    SERVICE_NAME,
+                    &message,
+                    UndecodedPayload::new(&message.payload),
+                    e.to_string(),
+                )
+                .await;
             }
         }
     }
 
-    info!("[NATS_URL] Subscription ended or NATS connection lost.");
+    info!("[NATS_URL] Task consumer ended or NATS connection lost.");
+    shutdown.drain(&client, drain_timeout).await;
     Ok(())
 }
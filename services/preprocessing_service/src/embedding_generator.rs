@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use tokenizers::{EncodeInput, Tokenizer};
 
 pub struct EmbeddingGenerator {
+    model_id: String,
     model: BertModel,
     tokenizer: Tokenizer,
     device: Device,
@@ -124,6 +125,7 @@ impl EmbeddingGenerator {
         let model = BertModel::load(vb, &config)?;
 
         Ok(Self {
+            model_id: model_id.to_string(),
             model,
             tokenizer,
             device,
@@ -131,6 +133,10 @@ impl EmbeddingGenerator {
         })
     }
 
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
     pub fn generate_sentence_embeddings(&self, sentences: &[String]) -> Result<Vec<Vec<f32>>> {
         if sentences.is_empty() {
             return Ok(Vec::new());
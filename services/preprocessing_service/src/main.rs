@@ -1,20 +1,53 @@
 mod embedding_generator;
+mod sparse_encoder;
 use anyhow::{Context, Result};
 use async_nats::Message;
+use async_nats::jetstream;
 use embedding_generator::EmbeddingGenerator;
 use futures::StreamExt;
 use log::{debug, error, info, warn};
-use serde_json;
+use sparse_encoder::SparseEncoder;
+use shared_config::Settings;
+use shared_nats::{
+    ConsumerConfig, DEAD_LETTERS_STREAM, EMBEDDINGS_STREAM, OverflowPolicy, RAW_TEXT_STREAM,
+    REEMBED_TASKS_STREAM, RecentMessages, Shutdown, WorkerPool, dead_letter_undecodable,
+    durable_messages, insert_message_id, publish_dead_letter, publish_durable,
+    queue_group_from_env, receive_span, serve_health, subscribe_shared, traced_headers,
+};
 use shared_models::{
-    QueryEmbeddingResult, QueryForEmbeddingTask, RawTextMessage, SentenceEmbedding,
-    TextWithEmbeddingsMessage, current_timestamp_ms,
+    ACCEPT_HEADER, CONTENT_ENCODING_HEADER, CONTENT_TYPE_HEADER, DeadLetterMessage, DependencyCheck,
+    Envelope, LOG_TEXT_CHARS, PayloadFormat, PipelineErrorKind, PipelineErrorMessage, PipelineStage,
+    QueryEmbeddingResult, QueryForEmbeddingTask, RawTextMessage, ReembedTextTask, RequestId,
+    SentenceEmbedding, TASK_STATUS_EVENT_SUBJECT, TaskStatus, TaskStatusChangedMessage,
+    TextWithEmbeddingsMessage, Truncated, UndecodedPayload, compress_above, decode_body,
 };
-use std::env;
 use std::sync::Arc;
+use std::time::Duration;
+use tracing::Instrument;
 
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
 const RAW_TEXT_DISCOVERED_SUBJECT: &str = "data.raw_text.discovered";
 const TEXT_WITH_EMBEDDINGS_SUBJECT: &str = "data.text.with_embeddings";
 const EMBEDDING_FOR_QUERY_TASK_SUBJECT: &str = "tasks.embedding.for_query";
+const REEMBED_TEXT_TASK_SUBJECT: &str = "tasks.embedding.reembed";
+/// Embedding a large document on the CPU can take minutes.
+const EMBEDDING_ACK_WAIT: Duration = Duration::from_secs(300);
+/// Documents and re-embedding tasks embedded at once, each unless
+/// `WORKERS_RAW_TEXT_CONCURRENCY` or `WORKERS_REEMBED_TASKS_CONCURRENCY` says otherwise.
+const DEFAULT_EMBEDDING_WORKERS: usize = 4;
+/// Query embeddings computed at once unless `WORKERS_QUERY_EMBEDDINGS_CONCURRENCY` says
+/// otherwise.
+const DEFAULT_QUERY_WORKERS: usize = 32;
+
+/// Durable consumer name shared by the instances running `model_id`. Each model gets its
+/// own consumer, so every model sees every document and re-embedding task.
+fn durable_name(model_id: &str) -> String {
+    let model: String = model_id
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    format!("{}_{}", SERVICE_NAME, model)
+}
 
 fn process_text_and_embed(
     raw_msg: &RawTextMessage,
@@ -109,48 +142,136 @@ fn process_text_and_embed(
         .into_iter()
         .zip(embeddings.into_iter())
         .map(|(sentence, embedding)| SentenceEmbedding {
+            sparse_embedding: Some(SparseEncoder::encode(&sentence)),
+            sentence_order: None,
             sentence_text: sentence,
             embedding,
         })
         .collect();
 
     Ok(TextWithEmbeddingsMessage {
-        original_id: raw_msg.id.clone(),
-        source_url: raw_msg.source_url.clone(),
-        embeddings_data,
-        model_name: "sentence-transformers/paraphrase-multilingual-mpnet-base-v2".to_string(),
-        timestamp_ms: current_timestamp_ms(),
+        chunk_index: raw_msg.chunk_index,
+        total_chunks: raw_msg.total_chunks,
+        parent_document_id: raw_msg.parent_document_id,
+        ..TextWithEmbeddingsMessage::new(
+            raw_msg.id,
+            raw_msg.source_url.clone(),
+            embed_generator.model_id(),
+            embeddings_data,
+        )
+        .with_metadata(raw_msg.metadata.clone())
     })
 }
 
+async fn publish_task_status(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    status: TaskStatusChangedMessage,
+) {
+    match cause.follow_up(SERVICE_NAME, &status).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish_with_headers(TASK_STATUS_EVENT_SUBJECT, traced_headers(), payload_json.into())
+                .await
+            {
+                error!(
+                    "[NATS_PUB_FAIL] Failed to publish {:?} status of task {}: {}",
+                    status.status, status.task_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!("[SERIALIZE_FAIL] Failed to serialize TaskStatusChangedMessage: {}", e);
+        }
+    }
+}
+
+/// Headers naming the encoding of a published message body.
+fn content_type_headers(format: PayloadFormat) -> async_nats::HeaderMap {
+    let mut headers = async_nats::HeaderMap::new();
+    headers.insert(CONTENT_TYPE_HEADER, format.content_type());
+    headers
+}
+
+/// Encodes embeddings for [`TEXT_WITH_EMBEDDINGS_SUBJECT`] in `format`, zstd-compressing
+/// bodies of at least `compression_threshold` bytes, together with the headers naming both.
+fn encode_embeddings(
+    envelope: &Envelope<&TextWithEmbeddingsMessage>,
+    format: PayloadFormat,
+    compression_threshold: usize,
+) -> Result<(async_nats::HeaderMap, Vec<u8>), String> {
+    let payload = envelope.encode(format).map_err(|e| e.to_string())?;
+    let (payload, encoding) =
+        compress_above(payload, compression_threshold).map_err(|e| e.to_string())?;
+    let mut headers = content_type_headers(format);
+    if let Some(encoding) = encoding.header_value() {
+        headers.insert(CONTENT_ENCODING_HEADER, encoding);
+    }
+    Ok((headers, payload))
+}
+
+/// Reports a document this service gave up on to [`PipelineStage::Preprocessing`]'s error
+/// subject.
+async fn publish_pipeline_error(
+    nats_client: &async_nats::Client,
+    cause: Option<&Envelope<()>>,
+    pipeline_error: PipelineErrorMessage,
+) {
+    let subject = pipeline_error.stage.error_subject();
+    match Envelope::following(cause, SERVICE_NAME, &pipeline_error).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client.publish(subject.clone(), payload_json.into()).await {
+                error!(
+                    "[NATS_PUB_FAIL] Failed to publish PipelineErrorMessage to {}: {}",
+                    subject, e
+                );
+            }
+        }
+        Err(e) => {
+            error!("[SERIALIZE_FAIL] Failed to serialize PipelineErrorMessage: {}", e);
+        }
+    }
+}
+
 async fn handle_raw_text_message_and_publish_embeddings(
-    raw_text_msg: RawTextMessage,
+    raw_text_msg: &RawTextMessage,
+    cause: Envelope<()>,
     nats_client: Arc<async_nats::Client>,
+    jetstream: jetstream::Context,
     embed_generator: Arc<EmbeddingGenerator>,
-) {
-    match process_text_and_embed(&raw_text_msg, &embed_generator) {
+    payload_format: PayloadFormat,
+    compression_threshold: usize,
+) -> Result<(), String> {
+    // The points are stored under the tenant the document was submitted by.
+    match process_text_and_embed(raw_text_msg, &embed_generator)
+        .map(|msg| msg.with_tenant_id(cause.tenant_id.clone()))
+    {
         Ok(msg_with_embeddings) => {
             info!(
                 "[NATS_PUB_PREP] Text processed with embeddings for original_id: {}. Publishing...",
                 msg_with_embeddings.original_id
             );
 
-            match serde_json::to_vec(&msg_with_embeddings) {
-                Ok(payload_json) => {
-                    if let Err(e) = nats_client
-                        .publish(TEXT_WITH_EMBEDDINGS_SUBJECT, payload_json.into())
-                        .await
+            let envelope = cause.follow_up(SERVICE_NAME, &msg_with_embeddings);
+            match encode_embeddings(&envelope, payload_format, compression_threshold) {
+                Ok((mut headers, payload)) => {
+                    insert_message_id(&mut headers, &cause, TEXT_WITH_EMBEDDINGS_SUBJECT, "");
+                    if let Err(e) =
+                        publish_durable(&jetstream, TEXT_WITH_EMBEDDINGS_SUBJECT, headers, payload)
+                            .await
                     {
                         error!(
                             "[NATS_PUB_FAIL] Failed to publish TextWithEmbeddingsMessage (original_id: {}): {}",
                             msg_with_embeddings.original_id, e
                         );
+                        Err(format!("Failed to publish embeddings: {}", e))
                     } else {
                         info!(
                             "[NATS_PUB_SUCCESS] Successfully published TextWithEmbeddingsMessage (original_id: {}) with {} embeddings.",
                             msg_with_embeddings.original_id,
                             msg_with_embeddings.embeddings_data.len()
                         );
+                        Ok(())
                     }
                 }
                 Err(e) => {
@@ -158,6 +279,7 @@ async fn handle_raw_text_message_and_publish_embeddings(
                         "[SERIALIZE_FAIL] Failed to serialize TextWithEmbeddingsMessage (original_id: {}): {}",
                         msg_with_embeddings.original_id, e
                     );
+                    Err(format!("Failed to serialize embeddings: {}", e))
                 }
             }
         }
@@ -166,6 +288,140 @@ async fn handle_raw_text_message_and_publish_embeddings(
                 "[PROCESS_TEXT_FAIL] Failed to process text with embeddings for id {}: {}",
                 raw_text_msg.id, e
             );
+            publish_pipeline_error(
+                &nats_client,
+                Some(&cause),
+                PipelineErrorMessage::new(
+                    PipelineStage::Preprocessing,
+                    PipelineErrorKind::Processing,
+                    e.clone(),
+                )
+                .with_original_id(raw_text_msg.id),
+            )
+            .await;
+            Err(e)
+        }
+    }
+}
+
+/// Re-embeds stored sentences for a vector reindex and publishes them like freshly
+/// processed text. Tasks for another model are left to the instance running it.
+async fn handle_reembed_text_task(
+    task: ReembedTextTask,
+    cause: Envelope<()>,
+    nats_client: Arc<async_nats::Client>,
+    jetstream: jetstream::Context,
+    embed_generator: Arc<EmbeddingGenerator>,
+    payload_format: PayloadFormat,
+    compression_threshold: usize,
+) {
+    if task.model_name != embed_generator.model_id() {
+        debug!(
+            "[REEMBED_HANDLER] Skipping re-embedding task for original_id {}: requested model '{}', this instance runs '{}'",
+            task.original_id,
+            task.model_name,
+            embed_generator.model_id()
+        );
+        return;
+    }
+
+    let sentences: Vec<String> = task
+        .sentences
+        .iter()
+        .map(|s| s.sentence_text.clone())
+        .collect();
+
+    let embeddings = match embed_generator.generate_sentence_embeddings(&sentences) {
+        Ok(embs) if embs.len() == sentences.len() => Ok(embs),
+        Ok(embs) => Err(format!(
+            "Mismatch between number of sentences ({}) and embeddings ({})",
+            sentences.len(),
+            embs.len()
+        )),
+        Err(e) => Err(format!("Failed to re-embed {} sentences: {}", sentences.len(), e)),
+    };
+    let embeddings = match embeddings {
+        Ok(embeddings) => embeddings,
+        Err(e) => {
+            error!(
+                "[REEMBED_HANDLER] {} for original_id: {} (reindex: {})",
+                e, task.original_id, task.reindex_id
+            );
+            publish_pipeline_error(
+                &nats_client,
+                Some(&cause),
+                PipelineErrorMessage::new(
+                    PipelineStage::Preprocessing,
+                    PipelineErrorKind::Processing,
+                    e.clone(),
+                )
+                .with_original_id(task.original_id),
+            )
+            .await;
+            publish_dead_letter(
+                &jetstream,
+                SERVICE_NAME,
+                Some(&cause),
+                &DeadLetterMessage::new(REEMBED_TEXT_TASK_SUBJECT, &task, e, 1),
+            )
+            .await;
+            return;
+        }
+    };
+
+    let embeddings_data: Vec<SentenceEmbedding> = task
+        .sentences
+        .into_iter()
+        .zip(embeddings)
+        .map(|(sentence, embedding)| SentenceEmbedding {
+            sparse_embedding: Some(SparseEncoder::encode(&sentence.sentence_text)),
+            sentence_order: Some(sentence.sentence_order),
+            sentence_text: sentence.sentence_text,
+            embedding,
+        })
+        .collect();
+
+    // Keeps the original processing time, so retention and ordering are unaffected.
+    let msg_with_embeddings = TextWithEmbeddingsMessage {
+        timestamp_ms: task.processed_at_ms,
+        chunk_index: task.chunk_index,
+        total_chunks: task.total_chunks,
+        parent_document_id: task.parent_document_id,
+        ..TextWithEmbeddingsMessage::new(
+            task.original_id,
+            task.source_url,
+            task.model_name,
+            embeddings_data,
+        )
+        .with_tenant_id(task.tenant_id)
+        .with_metadata(task.metadata)
+    };
+
+    let envelope = cause.follow_up(SERVICE_NAME, &msg_with_embeddings);
+    match encode_embeddings(&envelope, payload_format, compression_threshold) {
+        Ok((mut headers, payload)) => {
+            insert_message_id(&mut headers, &cause, TEXT_WITH_EMBEDDINGS_SUBJECT, "");
+            if let Err(e) =
+                publish_durable(&jetstream, TEXT_WITH_EMBEDDINGS_SUBJECT, headers, payload).await
+            {
+                error!(
+                    "[NATS_PUB_FAIL] Failed to publish re-embedded TextWithEmbeddingsMessage (original_id: {}, reindex: {}): {}",
+                    msg_with_embeddings.original_id, task.reindex_id, e
+                );
+            } else {
+                info!(
+                    "[REEMBED_HANDLER] Published {} re-embedded sentences for original_id: {} (reindex: {}).",
+                    msg_with_embeddings.embeddings_data.len(),
+                    msg_with_embeddings.original_id,
+                    task.reindex_id
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[SERIALIZE_FAIL] Failed to serialize re-embedded TextWithEmbeddingsMessage (original_id: {}): {}",
+                msg_with_embeddings.original_id, e
+            );
         }
     }
 }
@@ -175,21 +431,35 @@ async fn handle_query_for_embedding_task(
     embed_generator: Arc<EmbeddingGenerator>,
     nats_client_for_reply: Arc<async_nats::Client>,
 ) -> Result<()> {
-    let task: QueryForEmbeddingTask = match serde_json::from_slice(&nats_msg.payload) {
-        Ok(t) => t,
+    // Requesters that can decode protobuf ask for it; everyone else gets JSON.
+    let reply_format = nats_msg
+        .headers
+        .as_ref()
+        .and_then(|headers| headers.get(ACCEPT_HEADER))
+        .and_then(|accept| PayloadFormat::from_content_type(Some(accept.as_str())))
+        .unwrap_or_default();
+    let (cause, task) = match Envelope::<QueryForEmbeddingTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
         Err(e) => {
             let err_msg = format!("Failed to deserialize QueryForEmbeddingTask: {}", e);
             error!("[QUERY_EMBED_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
             if let Some(reply_to) = &nats_msg.reply {
                 let error_result = QueryEmbeddingResult {
-                    request_id: "unknown".to_string(),
+                    request_id: RequestId::default(),
                     embedding: None,
+                    sparse_embedding: None,
                     model_name: None,
                     error_message: Some(err_msg.clone()),
                 };
-                if let Ok(payload_json) = serde_json::to_vec(&error_result) {
+                if let Ok(payload) =
+                    Envelope::new(SERVICE_NAME, &error_result).encode(reply_format)
+                {
                     let _ = nats_client_for_reply
-                        .publish(reply_to.clone(), payload_json.into())
+                        .publish_with_headers(
+                            reply_to.clone(),
+                            content_type_headers(reply_format),
+                            payload.into(),
+                        )
                         .await;
                 }
             }
@@ -205,8 +475,7 @@ async fn handle_query_for_embedding_task(
     let sentences_to_embed = vec![task.text_to_embed.clone()];
     let mut result_embedding: Option<Vec<f32>> = None;
     let mut error_msg_opt: Option<String> = None;
-    let model_name_used =
-        Some("sentence-transformers/paraphrase-multilingual-mpnet-base-v2".to_string());
+    let model_name_used = Some(embed_generator.model_id().to_string());
 
     match embed_generator.generate_sentence_embeddings(&sentences_to_embed) {
         Ok(mut embeddings_vec) => {
@@ -245,22 +514,31 @@ async fn handle_query_for_embedding_task(
         }
     }
 
+    let sparse_embedding = result_embedding
+        .as_ref()
+        .map(|_| SparseEncoder::encode(&task.text_to_embed));
+
     let final_result = QueryEmbeddingResult {
-        request_id: task.request_id.clone(),
+        request_id: task.request_id,
         embedding: result_embedding,
+        sparse_embedding,
         model_name: model_name_used,
         error_message: error_msg_opt,
     };
 
     if let Some(reply_to) = nats_msg.reply {
-        match serde_json::to_vec(&final_result) {
-            Ok(payload_json) => {
+        match cause.follow_up(SERVICE_NAME, &final_result).encode(reply_format) {
+            Ok(payload) => {
                 info!(
                     "[QUERY_EMBED_HANDLER] Sending embedding result for request_id {} to NATS reply subject: {}",
                     task.request_id, reply_to
                 );
                 if let Err(e) = nats_client_for_reply
-                    .publish(reply_to, payload_json.into())
+                    .publish_with_headers(
+                        reply_to,
+                        content_type_headers(reply_format),
+                        payload.into(),
+                    )
                     .await
                 {
                     error!(
@@ -275,12 +553,16 @@ async fn handle_query_for_embedding_task(
                     task.request_id, e
                 );
                 let error_result_on_serialize_fail = QueryEmbeddingResult {
-                    request_id: task.request_id.clone(),
+                    request_id: task.request_id,
                     embedding: None,
+                    sparse_embedding: None,
                     model_name: None,
                     error_message: Some(format!("Failed to serialize result: {}", e)),
                 };
-                if let Ok(err_payload_json) = serde_json::to_vec(&error_result_on_serialize_fail) {
+                if let Ok(err_payload_json) = cause
+                    .follow_up(SERVICE_NAME, &error_result_on_serialize_fail)
+                    .to_vec()
+                {
                     let _ = nats_client_for_reply
                         .publish(reply_to, err_payload_json.into())
                         .await;
@@ -299,12 +581,24 @@ async fn handle_query_for_embedding_task(
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info,preprocessing_service=debug,candle_core=warn,candle_nn=warn,candle_transformers=warn,tokenizers=warn,hf_hub=warn")).init();
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info,preprocessing_service=debug,candle_core=warn,candle_nn=warn,candle_transformers=warn,tokenizers=warn,hf_hub=warn", &settings, String::new)?;
     println!("Starting with embedding generation capabilities...");
 
-    let model_id = "sentence-transformers/paraphrase-multilingual-mpnet-base-v2";
-    let revision = "main".to_string();
-    let force_cpu = env::var("FORCE_CPU").map_or(false, |v| v == "1" || v.to_lowercase() == "true");
+    let model_id = settings.embedding.model_id;
+    let revision = settings.embedding.revision;
+    let force_cpu = settings.force_cpu;
+    let payload_format = match shared_config::env_var("NATS_PAYLOAD_FORMAT") {
+        Some(value) => value.parse::<PayloadFormat>().unwrap_or_else(|e| {
+            warn!("[NATS_CONFIG] {}, defaulting to JSON", e);
+            PayloadFormat::Json
+        }),
+        None => PayloadFormat::Json,
+    };
+    info!("[NATS_CONFIG] Publishing embeddings as {:?}", payload_format);
+    let compression_threshold = settings.nats.compression_threshold;
+    let drain_timeout = settings.shutdown.drain_timeout();
+    let shutdown = Shutdown::listen();
 
     info!(
         "[EMBED_INIT] Initializing EmbeddingGenerator with model: {}, revision: {}, force_cpu: {}",
@@ -312,22 +606,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     );
 
     let embedding_generator = Arc::new(
-        EmbeddingGenerator::new(model_id, Some(revision), force_cpu)
+        EmbeddingGenerator::new(&model_id, Some(revision), force_cpu)
             .context("Failed to create EmbeddingGenerator during service startup")?,
     );
 
     info!("[EMBED_INIT_SUCCESS] EmbeddingGenerator initialized successfully.");
 
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!("[NATS_CONFIG] NATS_URL not set, defaulting to nats://localhost:4222");
-        "nats://localhost:4222".to_string()
-    });
-    info!(
-        "[NATS_CONNECT] Attempting to connect to NATS server at {}...",
-        nats_url
-    );
-
-    let client = match async_nats::connect(&nats_url).await {
+    let client = match shared_nats::connect(&settings.nats).await {
         Ok(client) => {
             info!("Successfully connected to NATS!");
             Arc::new(client)
@@ -338,56 +623,172 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let mut raw_text_subscriber = match client.subscribe(RAW_TEXT_DISCOVERED_SUBJECT).await {
-        Ok(sub) => {
-            info!("Subscribed to subject: {}", RAW_TEXT_DISCOVERED_SUBJECT);
-            sub
-        }
-        Err(err) => {
-            error!(
-                "Failed to subscribe to {}: {}",
-                RAW_TEXT_DISCOVERED_SUBJECT, err
-            );
-            return Err(Box::new(err) as Box<dyn std::error::Error>);
-        }
-    };
+    let jetstream = jetstream::new((*client).clone());
+    EMBEDDINGS_STREAM.ensure(&jetstream).await?;
+    DEAD_LETTERS_STREAM.ensure(&jetstream).await?;
+    let durable_name = durable_name(embedding_generator.model_id());
+
+    let raw_text_consumer_config =
+        ConsumerConfig::new(durable_name.clone(), RAW_TEXT_DISCOVERED_SUBJECT)
+            .with_ack_wait(EMBEDDING_ACK_WAIT)
+            .with_env_overrides(&RAW_TEXT_STREAM);
+    let mut raw_text_messages =
+        durable_messages(&jetstream, &RAW_TEXT_STREAM, &raw_text_consumer_config)
+            .await?
+            .take_until(shutdown.signalled());
 
     let nats_client_for_raw_text_task = Arc::clone(&client);
+    let jetstream_for_raw_text_task = jetstream.clone();
     let embedding_generator_for_raw_text_task = Arc::clone(&embedding_generator);
+    let shutdown_for_raw_text_task = shutdown.clone();
+    let recent_messages = RecentMessages::from_env();
+    let recent_messages_for_raw_text_task = recent_messages.clone();
+    let raw_text_workers =
+        WorkerPool::from_env("raw_text", DEFAULT_EMBEDDING_WORKERS, OverflowPolicy::Wait);
 
     tokio::spawn(async move {
         info!("[NATS_LOOP_RAW_TEXT] Waiting for raw text messages to process and embed...");
-        while let Some(message) = raw_text_subscriber.next().await {
+        while let Some(next) = raw_text_messages.next().await {
+            let message = match next {
+                Ok(message) => message,
+                Err(e) => {
+                    error!(
+                        "[NATS_MSG_RECV_FAIL_RAW_TEXT] Failed to receive message from JetStream consumer: {}",
+                        e
+                    );
+                    continue;
+                }
+            };
             info!(
                 "[NATS_MSG_RECV_RAW_TEXT] Received message on subject: {}",
                 message.subject
             );
 
-            match serde_json::from_slice::<RawTextMessage>(&message.payload) {
-                Ok(raw_text_msg) => {
+            let content_encoding = message
+                .headers
+                .as_ref()
+                .and_then(|headers| headers.get(CONTENT_ENCODING_HEADER))
+                .map(|value| value.as_str());
+            let decoded = decode_body(&message.payload, content_encoding)
+                .map_err(|e| e.to_string())
+                .and_then(|body| {
+                    Envelope::<RawTextMessage>::from_slice(&body).map_err(|e| e.to_string())
+                });
+            match decoded {
+                Ok(envelope) => {
+                    let (cause, raw_text_msg) = envelope.split();
                     info!(
                         "[TASK_DESERIALIZED_RAW_TEXT] Deserialized RawTextMessage (id: {}, url: {})",
                         raw_text_msg.id, raw_text_msg.source_url,
                     );
+                    let Some(claim) =
+                        recent_messages_for_raw_text_task.claim_delivery(&message, &cause).await
+                    else {
+                        continue;
+                    };
+
+                    let Some(worker) = raw_text_workers.reserve_for(&message).await else {
+                        continue;
+                    };
 
                     let nats_client_clone = Arc::clone(&nats_client_for_raw_text_task);
+                    let jetstream_clone = jetstream_for_raw_text_task.clone();
                     let embed_generator_clone = Arc::clone(&embedding_generator_for_raw_text_task);
+                    let span = receive_span(&message.subject, message.headers.as_ref());
+                    let in_flight = shutdown_for_raw_text_task.track();
+
+                    worker.spawn(async move {
+                        let _in_flight = in_flight;
+                        let original_id = raw_text_msg.id;
+                        let started = TaskStatusChangedMessage::new(
+                            &cause,
+                            PipelineStage::Preprocessing,
+                            TaskStatus::Started,
+                        );
+                        publish_task_status(
+                            &nats_client_clone,
+                            &cause,
+                            started.with_original_id(original_id),
+                        )
+                        .await;
 
-                    tokio::spawn(async move {
-                        handle_raw_text_message_and_publish_embeddings(
-                            raw_text_msg,
-                            nats_client_clone,
+                        let result = handle_raw_text_message_and_publish_embeddings(
+                            &raw_text_msg,
+                            cause.clone(),
+                            Arc::clone(&nats_client_clone),
+                            jetstream_clone.clone(),
                             embed_generator_clone,
+                            payload_format,
+                            compression_threshold,
                         )
                         .await;
-                    });
+                        let status = match result {
+                            Ok(()) => TaskStatusChangedMessage::new(
+                                &cause,
+                                PipelineStage::Preprocessing,
+                                TaskStatus::Completed,
+                            ),
+                            Err(e) => {
+                                publish_dead_letter(
+                                    &jetstream_clone,
+                                    SERVICE_NAME,
+                                    Some(&cause),
+                                    &DeadLetterMessage::new(
+                                        RAW_TEXT_DISCOVERED_SUBJECT,
+                                        &raw_text_msg,
+                                        e.clone(),
+                                        1,
+                                    ),
+                                )
+                                .await;
+                                TaskStatusChangedMessage::new(
+                                    &cause,
+                                    PipelineStage::Preprocessing,
+                                    TaskStatus::Failed,
+                                )
+                                .with_detail(e)
+                            }
+                        };
+                        publish_task_status(
+                            &nats_client_clone,
+                            &cause,
+                            status.with_original_id(original_id),
+                        )
+                        .await;
+                        // A failure has already been reported, so the message is acked either
+                        // way; only a crash before this point gets it redelivered.
+                        if let Err(e) = message.ack().await {
+                            error!("[NATS_ACK_FAIL_RAW_TEXT] Failed to ack raw text message: {}", e);
+                        }
+                        claim.handled();
+                    }.instrument(span));
                 }
                 Err(e) => {
                     warn!(
                         "[TASK_DESERIALIZE_FAIL_RAW_TEXT] Failed to deserialize RawTextMessage: {}. Payload: {:?}",
                         e,
-                        String::from_utf8_lossy(&message.payload),
+                        Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS),
                     );
+                    publish_pipeline_error(
+                        &nats_client_for_raw_text_task,
+                        None,
+                        PipelineErrorMessage::new(
+                            PipelineStage::Preprocessing,
+                            PipelineErrorKind::InvalidMessage,
+                            format!("Invalid RawTextMessage: {}", e),
+                        ),
+                    )
+                    .await;
+                    let undecoded = UndecodedPayload::new(&message.payload)
+                        .with_header(CONTENT_ENCODING_HEADER, content_encoding);
+                    dead_letter_undecodable(
+                        &jetstream_for_raw_text_task,
+                        SERVICE_NAME,
+                        &message,
+                        undecoded,
+                        e,
+                    )
+                    .await;
                 }
             }
         }
@@ -395,22 +796,123 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         info!("[NATS_LOOP_RAW_TEXT_END] Raw text processing subscription ended.");
     });
 
-    let mut query_embedding_subscriber = client
-        .subscribe(EMBEDDING_FOR_QUERY_TASK_SUBJECT)
-        .await
-        .with_context(|| {
+    let reembed_consumer_config = ConsumerConfig::new(durable_name, REEMBED_TEXT_TASK_SUBJECT)
+        .with_ack_wait(EMBEDDING_ACK_WAIT)
+        .with_env_overrides(&REEMBED_TASKS_STREAM);
+    let mut reembed_messages =
+        durable_messages(&jetstream, &REEMBED_TASKS_STREAM, &reembed_consumer_config)
+            .await?
+            .take_until(shutdown.signalled());
+
+    let nats_client_for_reembed_task = Arc::clone(&client);
+    let jetstream_for_reembed_task = jetstream.clone();
+    let embedding_generator_for_reembed_task = Arc::clone(&embedding_generator);
+    let shutdown_for_reembed_task = shutdown.clone();
+    let recent_messages_for_reembed_task = recent_messages;
+    let reembed_workers =
+        WorkerPool::from_env("reembed_tasks", DEFAULT_EMBEDDING_WORKERS, OverflowPolicy::Wait);
+
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_REEMBED] Waiting for re-embedding tasks...");
+        while let Some(next) = reembed_messages.next().await {
+            let message = match next {
+                Ok(message) => message,
+                Err(e) => {
+                    error!(
+                        "[NATS_MSG_RECV_FAIL_REEMBED] Failed to receive message from JetStream consumer: {}",
+                        e
+                    );
+                    continue;
+                }
+            };
+            match Envelope::<ReembedTextTask>::from_slice(&message.payload) {
+                Ok(envelope) => {
+                    let (cause, reembed_task) = envelope.split();
+                    let Some(claim) =
+                        recent_messages_for_reembed_task.claim_delivery(&message, &cause).await
+                    else {
+                        continue;
+                    };
+                    let Some(worker) = reembed_workers.reserve_for(&message).await else {
+                        continue;
+                    };
+                    let nats_client_clone = Arc::clone(&nats_client_for_reembed_task);
+                    let jetstream_clone = jetstream_for_reembed_task.clone();
+                    let embed_generator_clone = Arc::clone(&embedding_generator_for_reembed_task);
+                    let span = receive_span(&message.subject, message.headers.as_ref());
+                    let in_flight = shutdown_for_reembed_task.track();
+
+                    worker.spawn(async move {
+                        let _in_flight = in_flight;
+                        handle_reembed_text_task(
+                            reembed_task,
+                            cause,
+                            nats_client_clone,
+                            jetstream_clone,
+                            embed_generator_clone,
+                            payload_format,
+                            compression_threshold,
+                        )
+                        .await;
+                        if let Err(e) = message.ack().await {
+                            error!("[NATS_ACK_FAIL_REEMBED] Failed to ack re-embedding task: {}", e);
+                        }
+                        claim.handled();
+                    }.instrument(span));
+                }
+                Err(e) => {
+                    warn!(
+                        "[TASK_DESERIALIZE_FAIL_REEMBED] Failed to deserialize ReembedTextTask: {}. Payload (first 100 bytes): {:?}",
+                        e,
+                        message.payload.get(..100)
+                    );
+                    dead_letter_undecodable(
+                        &jetstream_for_reembed_task,
+                        SERVICE_NAME,
+                        &message,
+                        UndecodedPayload::new(&message.payload),
+                        e.to_string(),
+                    )
+                    .await;
+                }
+            }
+        }
+
+        info!("[NATS_LOOP_REEMBED_END] Re-embedding subscription ended.");
+    });
+
+    // Replicas share query embeddings like the documents of their durable consumers.
+    let query_queue_group = queue_group_from_env("PREPROCESSING_QUEUE_GROUP", SERVICE_NAME);
+    let mut query_embedding_subscriber = subscribe_shared(
+        &client,
+        EMBEDDING_FOR_QUERY_TASK_SUBJECT,
+        query_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
         format!(
             "Failed to subscribe to NATS subject {}",
             EMBEDDING_FOR_QUERY_TASK_SUBJECT
         )
-    })?;
+    })?
+    .take_until(shutdown.signalled());
     info!(
-        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for query embedding tasks",
-        EMBEDDING_FOR_QUERY_TASK_SUBJECT
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for query embedding tasks (queue group: {:?})",
+        EMBEDDING_FOR_QUERY_TASK_SUBJECT, query_queue_group
     );
 
+    // The model is loaded before the service subscribes to anything, so it is only named.
+    let loaded_model = embedding_generator.model_id().to_string();
+    serve_health((*client).clone(), SERVICE_NAME, move || {
+        let loaded_model = loaded_model.clone();
+        async move { vec![DependencyCheck::passed("model", Some(loaded_model))] }
+    })
+    .await?;
+
     let nats_client_for_query_reply = Arc::clone(&client);
     let embedding_generator_for_query_task = Arc::clone(&embedding_generator);
+    let query_workers =
+        WorkerPool::from_env("query_embeddings", DEFAULT_QUERY_WORKERS, OverflowPolicy::Reject);
 
     info!("[NATS_LOOP_QUERY_EMBED] Waiting for query embedding tasks...");
 
@@ -421,8 +923,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
         let n_client_clone = Arc::clone(&nats_client_for_query_reply);
         let embed_gen_clone = Arc::clone(&embedding_generator_for_query_task);
+        let in_flight = shutdown.track();
 
-        tokio::spawn(async move {
+        // A refused request is left to time out on the requester's side.
+        let _ = query_workers.spawn_limited(async move {
+            let _in_flight = in_flight;
             if let Err(e) =
                 handle_query_for_embedding_task(message, embed_gen_clone, n_client_clone).await
             {
@@ -431,10 +936,11 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     e
                 );
             }
-        });
+        }).await;
     }
 
     info!("[NATS_LOOP_QUERY_EMBED_END] Query embedding subscription ended.");
 
+    shutdown.drain(&client, drain_timeout).await;
     Ok(())
 }
@@ -0,0 +1,34 @@
+use shared_models::SparseVector;
+use std::collections::BTreeMap;
+
+const FNV_OFFSET_BASIS: u32 = 0x811c_9dc5;
+const FNV_PRIME: u32 = 0x0100_0193;
+
+/// Hashed bag-of-words encoder producing term-frequency sparse vectors.
+///
+/// Tokens are lowercased alphanumeric runs hashed with FNV-1a, so the same term always maps to
+/// the same index without a shared vocabulary. IDF weighting is applied on the Qdrant side.
+pub struct SparseEncoder;
+
+impl SparseEncoder {
+    pub fn encode(text: &str) -> SparseVector {
+        let mut term_counts: BTreeMap<u32, f32> = BTreeMap::new();
+
+        for token in text
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|t| !t.is_empty())
+        {
+            let index = fnv1a(&token.to_lowercase());
+            *term_counts.entry(index).or_insert(0.0) += 1.0;
+        }
+
+        let (indices, values) = term_counts.into_iter().unzip();
+        SparseVector { indices, values }
+    }
+}
+
+fn fnv1a(token: &str) -> u32 {
+    token.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+    })
+}
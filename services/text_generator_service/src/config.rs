@@ -0,0 +1,392 @@
+use crate::corpora::{host_of, normalize_host};
+use crate::markov::MAX_MARKOV_ORDER;
+use crate::neural::DEFAULT_NEURAL_MODEL_ID;
+use log::{info, warn};
+pub use shared_config::{env_flag_or, env_parse_or, env_var};
+use shared_models::{GenerationBackend, validate_tenant_id};
+use shared_nats::queue_group_from_env;
+use std::path::PathBuf;
+use std::time::Duration;
+
+pub const DEFAULT_MODEL_NAME: &str = "default";
+const DEFAULT_MODEL_PATH: &str = "data/markov_model.bin";
+const DEFAULT_CHECKPOINT_INTERVAL_SECS: u64 = 300;
+const DEFAULT_MIN_WORDS: usize = 3;
+const DEFAULT_TEMPLATE_TIMEOUT_MS: u64 = 5000;
+const DEFAULT_QUEUE_GROUP: &str = "text_generator_service";
+const DEFAULT_SNAPSHOT_RELOAD_SECS: u64 = 60;
+const DEFAULT_RETRAIN_PAGE_SIZE: u32 = 1000;
+const DEFAULT_RETRAIN_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_MARKOV_ORDER: usize = 2;
+const DEFAULT_DECAY_FACTOR: f32 = 0.98;
+const DEFAULT_MAINTENANCE_INTERVAL_DOCUMENTS: u64 = 100;
+const DEFAULT_PRUNE_BELOW: f32 = 0.5;
+const DEFAULT_MAX_MEMORY_MB: u64 = 1024;
+const DEFAULT_PUBLISH_INTERVAL_MS: u64 = 1000;
+
+/// Which generator serves tasks that do not pick one (`TEXT_GEN_BACKEND`), and the neural
+/// model to load. The neural model is only loaded when it is the default backend or
+/// `TEXT_GEN_NEURAL_MODEL_ID` is set; the Markov model is always available as a fallback.
+#[derive(Debug, Clone)]
+pub struct GeneratorConfig {
+    pub default_backend: GenerationBackend,
+    pub neural: Option<NeuralConfig>,
+    /// Outputs with fewer words (`TEXT_GEN_MIN_WORDS`, capped at the task's `max_length`)
+    /// are reported as failed instead of published.
+    pub min_words: usize,
+    /// How long template mode waits for each knowledge graph reply
+    /// (`TEXT_GEN_TEMPLATE_TIMEOUT_MS`).
+    pub template_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct NeuralConfig {
+    pub model_id: String,
+    pub revision: String,
+    pub force_cpu: bool,
+}
+
+impl GeneratorConfig {
+    pub fn from_env(force_cpu: bool) -> Self {
+        let default_backend = match env_var("TEXT_GEN_BACKEND")
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase()
+            .as_str()
+        {
+            "" | "markov" => GenerationBackend::Markov,
+            "neural" => GenerationBackend::Neural,
+            other => {
+                warn!(
+                    "[CONFIG] Unknown TEXT_GEN_BACKEND '{}', using markov",
+                    other
+                );
+                GenerationBackend::Markov
+            }
+        };
+        let model_id = env_var("TEXT_GEN_NEURAL_MODEL_ID")
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+        let neural = match (default_backend, model_id) {
+            (_, Some(model_id)) => Some(model_id),
+            (GenerationBackend::Neural, None) => Some(DEFAULT_NEURAL_MODEL_ID.to_string()),
+            (GenerationBackend::Markov, None) => None,
+        }
+        .map(|model_id| NeuralConfig {
+            model_id,
+            revision: env_var("TEXT_GEN_NEURAL_MODEL_REVISION")
+                .unwrap_or_else(|| "main".to_string()),
+            force_cpu,
+        });
+
+        let config = GeneratorConfig {
+            default_backend,
+            neural,
+            min_words: env_parse_or("TEXT_GEN_MIN_WORDS", DEFAULT_MIN_WORDS),
+            template_timeout: Duration::from_millis(env_parse_or(
+                "TEXT_GEN_TEMPLATE_TIMEOUT_MS",
+                DEFAULT_TEMPLATE_TIMEOUT_MS,
+            )),
+        };
+        info!("[CONFIG] Generators: {:?}", config);
+        config
+    }
+}
+
+/// One named Markov model and the documents it learns from.
+#[derive(Debug, Clone)]
+pub struct NamedModelConfig {
+    pub name: String,
+    pub subject: String,
+    /// Hosts whose documents (subdomains included) are trained on; empty means all.
+    pub hosts: Vec<String>,
+    /// Tenants whose documents are trained on and whose tasks may use the model. A model
+    /// without tenants is shared: it only trains on documents without a tenant, so no
+    /// tenant's corpus leaks into text generated for another, and serves every task.
+    pub tenants: Vec<String>,
+}
+
+impl NamedModelConfig {
+    pub fn accepts(&self, tenant_id: Option<&str>, source_url: &str) -> bool {
+        let tenant_accepted = match tenant_id {
+            Some(tenant_id) => self.tenants.iter().any(|allowed| allowed == tenant_id),
+            None => self.tenants.is_empty(),
+        };
+        if !tenant_accepted {
+            return false;
+        }
+        if self.hosts.is_empty() {
+            return true;
+        }
+        host_of(source_url).is_some_and(|host| {
+            self.hosts.iter().any(|allowed| {
+                host == *allowed
+                    || host
+                        .strip_suffix(allowed.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            })
+        })
+    }
+
+    /// Whether a task of `tenant_id` may generate from the model.
+    pub fn serves(&self, tenant_id: Option<&str>) -> bool {
+        self.tenants.is_empty() || tenant_id.is_some_and(|t| self.tenants.iter().any(|a| a == t))
+    }
+}
+
+/// The named Markov models tasks can pick with `model_name` (`MARKOV_MODELS`), e.g.
+/// `news:hosts=bbc.co.uk|reuters.com;docs:subject=data.docs.tokenized;acme:tenants=acme`.
+/// A `default` model trained on every tokenized document without a tenant is always
+/// present unless the list redefines it.
+#[derive(Debug, Clone)]
+pub struct NamedModelsConfig {
+    pub models: Vec<NamedModelConfig>,
+}
+
+impl NamedModelsConfig {
+    pub fn from_env(default_subject: &str) -> Self {
+        let mut models = vec![NamedModelConfig {
+            name: DEFAULT_MODEL_NAME.to_string(),
+            subject: default_subject.to_string(),
+            hosts: Vec::new(),
+            tenants: Vec::new(),
+        }];
+        let raw = env_var("MARKOV_MODELS").unwrap_or_default();
+        for entry in raw.split(';').map(str::trim).filter(|e| !e.is_empty()) {
+            match parse_named_model(entry, default_subject) {
+                Ok(model) => {
+                    models.retain(|existing| existing.name != model.name);
+                    models.push(model);
+                }
+                Err(e) => warn!("[CONFIG] Ignoring MARKOV_MODELS entry '{}': {}", entry, e),
+            }
+        }
+
+        let config = NamedModelsConfig { models };
+        info!("[CONFIG] Named models: {:?}", config);
+        config
+    }
+}
+
+fn parse_named_model(entry: &str, default_subject: &str) -> Result<NamedModelConfig, String> {
+    let (name, options) = entry.split_once(':').unwrap_or((entry, ""));
+    let name = name.trim().to_lowercase();
+    // Names end up in snapshot file names.
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err("names may only contain letters, digits, '_' and '-'".to_string());
+    }
+
+    let mut model = NamedModelConfig {
+        name,
+        subject: default_subject.to_string(),
+        hosts: Vec::new(),
+        tenants: Vec::new(),
+    };
+    for option in options.split(',').map(str::trim).filter(|o| !o.is_empty()) {
+        match option
+            .split_once('=')
+            .map(|(key, value)| (key.trim(), value.trim()))
+        {
+            Some(("subject", subject)) if !subject.is_empty() => {
+                model.subject = subject.to_string()
+            }
+            Some(("hosts", hosts)) => {
+                model.hosts = hosts
+                    .split('|')
+                    .map(normalize_host)
+                    .filter(|host| !host.is_empty())
+                    .collect()
+            }
+            Some(("tenants", tenants)) => {
+                model.tenants = tenants
+                    .split('|')
+                    .map(str::trim)
+                    .filter(|tenant| !tenant.is_empty())
+                    .map(|tenant| {
+                        validate_tenant_id(tenant)
+                            .map(|()| tenant.to_string())
+                            .map_err(|e| e.to_string())
+                    })
+                    .collect::<Result<_, _>>()?
+            }
+            _ => return Err(format!("unknown option '{}'", option)),
+        }
+    }
+    Ok(model)
+}
+
+/// Which sub-models are trained next to the global model, and how they are kept current.
+/// Per-document models roughly double the memory used, so they are off unless
+/// `MARKOV_DOCUMENT_MODELS` is set.
+#[derive(Debug, Clone)]
+pub struct CorpusConfig {
+    pub domain_models: bool,
+    pub document_models: bool,
+    /// Longest context, in words, the chains are trained on (`MARKOV_ORDER`); generation
+    /// backs off to shorter ones.
+    pub order: usize,
+    /// Every `maintenance_interval_documents` trained documents, the global and domain
+    /// counts are multiplied by `decay_factor` (1.0 disables decay) and transitions counted
+    /// less than `prune_below` are dropped.
+    pub decay_factor: f32,
+    pub maintenance_interval_documents: u64,
+    pub prune_below: f32,
+    /// Estimated size of all models together above which rarer transitions are pruned
+    /// until they fit again. `None` when `MARKOV_MAX_MEMORY_MB` is 0.
+    pub max_memory_bytes: Option<u64>,
+    /// How long newly trained documents may wait before generation sees them
+    /// (`MARKOV_PUBLISH_INTERVAL_MS`). Each publish copies the models once.
+    pub publish_interval: Duration,
+}
+
+impl CorpusConfig {
+    pub fn from_env() -> Self {
+        let mut decay_factor = env_parse_or("MARKOV_DECAY_FACTOR", DEFAULT_DECAY_FACTOR);
+        if !(decay_factor > 0.0 && decay_factor <= 1.0) {
+            warn!(
+                "[CONFIG] MARKOV_DECAY_FACTOR must be in (0, 1], got {}; using {}",
+                decay_factor, DEFAULT_DECAY_FACTOR
+            );
+            decay_factor = DEFAULT_DECAY_FACTOR;
+        }
+        let max_memory_mb = env_parse_or("MARKOV_MAX_MEMORY_MB", DEFAULT_MAX_MEMORY_MB);
+
+        let config = CorpusConfig {
+            domain_models: env_flag_or("MARKOV_DOMAIN_MODELS", true),
+            document_models: env_flag_or("MARKOV_DOCUMENT_MODELS", false),
+            order: env_parse_or("MARKOV_ORDER", DEFAULT_MARKOV_ORDER).clamp(1, MAX_MARKOV_ORDER),
+            decay_factor,
+            maintenance_interval_documents: env_parse_or(
+                "MARKOV_MAINTENANCE_INTERVAL_DOCUMENTS",
+                DEFAULT_MAINTENANCE_INTERVAL_DOCUMENTS,
+            )
+            .max(1),
+            prune_below: env_parse_or("MARKOV_PRUNE_BELOW", DEFAULT_PRUNE_BELOW).max(0.0),
+            max_memory_bytes: (max_memory_mb > 0).then(|| max_memory_mb * 1024 * 1024),
+            publish_interval: Duration::from_millis(
+                env_parse_or("MARKOV_PUBLISH_INTERVAL_MS", DEFAULT_PUBLISH_INTERVAL_MS).max(1),
+            ),
+        };
+        info!("[CONFIG] Corpus models: {:?}", config);
+        config
+    }
+}
+
+/// How this instance shares work with other replicas. Generation tasks and the requests on
+/// `control.generator.*` are split across the `TEXT_GEN_QUEUE_GROUP` (`off` gives every
+/// instance every task). Only instances with
+/// `MARKOV_TRAINING` enabled train and write snapshots; the others serve the snapshots in
+/// the shared `MARKOV_MODEL_PATH`, reloading them every `MARKOV_SNAPSHOT_RELOAD_SECS`.
+#[derive(Debug, Clone)]
+pub struct ReplicaConfig {
+    pub queue_group: Option<String>,
+    pub training: bool,
+    /// `None` for training instances, or when `MARKOV_SNAPSHOT_RELOAD_SECS` is 0.
+    pub snapshot_reload_interval: Option<Duration>,
+}
+
+impl ReplicaConfig {
+    pub fn from_env() -> Self {
+        let training = env_flag_or("MARKOV_TRAINING", true);
+        let reload_secs = env_parse_or("MARKOV_SNAPSHOT_RELOAD_SECS", DEFAULT_SNAPSHOT_RELOAD_SECS);
+
+        let config = ReplicaConfig {
+            queue_group: queue_group_from_env("TEXT_GEN_QUEUE_GROUP", DEFAULT_QUEUE_GROUP),
+            training,
+            snapshot_reload_interval: (!training && reload_secs > 0)
+                .then(|| Duration::from_secs(reload_secs)),
+        };
+        info!("[CONFIG] Replica: {:?}", config);
+        config
+    }
+}
+
+/// Rebuilding the Markov models from the sentences archived in the vector memory.
+#[derive(Debug, Clone)]
+pub struct RetrainConfig {
+    /// `None` when `MARKOV_RETRAIN_INTERVAL_SECS` is 0 (the default): models are then only
+    /// rebuilt on request or at startup.
+    pub interval: Option<Duration>,
+    /// Rebuild models that start out empty, so a fresh instance does not wait for new
+    /// documents (`MARKOV_RETRAIN_ON_START`).
+    pub on_start: bool,
+    /// Embedding model whose points are read; all points when unset.
+    pub vector_model: Option<String>,
+    pub page_size: u32,
+    pub request_timeout: Duration,
+}
+
+impl RetrainConfig {
+    pub fn from_env() -> Self {
+        let interval_secs = env_parse_or("MARKOV_RETRAIN_INTERVAL_SECS", 0u64);
+        let vector_model = env_var("MARKOV_RETRAIN_VECTOR_MODEL")
+            .map(|model| model.trim().to_string())
+            .filter(|model| !model.is_empty());
+        let page_size = env_parse_or("MARKOV_RETRAIN_PAGE_SIZE", DEFAULT_RETRAIN_PAGE_SIZE);
+
+        let config = RetrainConfig {
+            interval: (interval_secs > 0).then(|| Duration::from_secs(interval_secs)),
+            on_start: env_flag_or("MARKOV_RETRAIN_ON_START", true),
+            vector_model,
+            page_size: page_size.clamp(1, DEFAULT_RETRAIN_PAGE_SIZE),
+            request_timeout: Duration::from_millis(env_parse_or(
+                "MARKOV_RETRAIN_TIMEOUT_MS",
+                DEFAULT_RETRAIN_TIMEOUT_MS,
+            )),
+        };
+        info!("[CONFIG] Retraining: {:?}", config);
+        config
+    }
+}
+
+/// Where the trained model is kept between restarts (`MARKOV_MODEL_PATH`, `off` disables).
+#[derive(Debug, Clone)]
+pub struct PersistenceConfig {
+    pub model_path: Option<PathBuf>,
+    /// `None` when `MARKOV_CHECKPOINT_INTERVAL_SECS` is 0: the model is then only saved
+    /// when the service stops.
+    pub checkpoint_interval: Option<Duration>,
+}
+
+impl PersistenceConfig {
+    /// The `default` model is kept at `model_path`; other models next to it, with their
+    /// name before the extension (`markov_model.news.bin`).
+    pub fn model_path_for(&self, name: &str) -> Option<PathBuf> {
+        let path = self.model_path.as_ref()?;
+        if name == DEFAULT_MODEL_NAME {
+            return Some(path.clone());
+        }
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let file_name = match path.extension() {
+            Some(extension) => format!("{}.{}.{}", stem, name, extension.to_string_lossy()),
+            None => format!("{}.{}", stem, name),
+        };
+        Some(path.with_file_name(file_name))
+    }
+
+    pub fn from_env() -> Self {
+        let raw_path =
+            env_var("MARKOV_MODEL_PATH").unwrap_or_else(|| DEFAULT_MODEL_PATH.to_string());
+        let raw_path = raw_path.trim();
+        let model_path = if raw_path.is_empty() || raw_path.eq_ignore_ascii_case("off") {
+            None
+        } else {
+            Some(PathBuf::from(raw_path))
+        };
+        let interval_secs = env_parse_or(
+            "MARKOV_CHECKPOINT_INTERVAL_SECS",
+            DEFAULT_CHECKPOINT_INTERVAL_SECS,
+        );
+
+        let config = PersistenceConfig {
+            model_path,
+            checkpoint_interval: (interval_secs > 0).then(|| Duration::from_secs(interval_secs)),
+        };
+        info!("[CONFIG] Model persistence: {:?}", config);
+        config
+    }
+}
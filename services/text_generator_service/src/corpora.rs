@@ -0,0 +1,140 @@
+use crate::config::CorpusConfig;
+use crate::markov::MarkovModel;
+use arc_swap::ArcSwap;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use shared_models::{DocumentId, GenerationCorpus};
+use std::collections::HashMap;
+use url::Url;
+
+/// The models generation reads from. Training swaps in a new snapshot instead of mutating
+/// the current one, so readers never wait on a lock.
+pub type SharedCorpusModels = ArcSwap<CorpusModels>;
+
+/// The global model plus the per-domain and per-document sub-models a generation can be
+/// scoped to.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CorpusModels {
+    pub global: MarkovModel,
+    pub by_domain: HashMap<String, MarkovModel>,
+    pub by_document: HashMap<DocumentId, MarkovModel>,
+    /// Restarts at zero after a restart, which only delays the next maintenance pass.
+    #[serde(skip)]
+    documents_since_maintenance: u64,
+}
+
+/// Upper bound on pruning passes when the models exceed the memory limit; each pass doubles
+/// the pruning threshold.
+const MAX_MEMORY_PRUNE_PASSES: usize = 32;
+
+impl CorpusModels {
+    /// Trains the global model and the enabled sub-models on one document. A re-ingested
+    /// document replaces its per-document model instead of being counted twice there.
+    pub fn train_document(
+        &mut self,
+        config: &CorpusConfig,
+        original_id: DocumentId,
+        source_url: &str,
+        sentences: &[String],
+    ) -> usize {
+        let trained = self.global.train(sentences, config.order);
+        if trained == 0 {
+            return 0;
+        }
+
+        if config.domain_models
+            && let Some(host) = host_of(source_url)
+        {
+            self.by_domain
+                .entry(host)
+                .or_default()
+                .train(sentences, config.order);
+        }
+        if config.document_models {
+            let mut document_model = MarkovModel::new();
+            document_model.train(sentences, config.order);
+            self.by_document.insert(original_id, document_model);
+        }
+
+        self.documents_since_maintenance += 1;
+        if self.documents_since_maintenance >= config.maintenance_interval_documents {
+            self.documents_since_maintenance = 0;
+            self.maintain(config);
+        }
+        trained
+    }
+
+    /// Decays and prunes the global and domain models, then prunes every model with a rising
+    /// threshold while they exceed the memory limit. Per-document models are not decayed:
+    /// they are replaced whenever their document is re-ingested.
+    fn maintain(&mut self, config: &CorpusConfig) {
+        let mut removed = 0;
+        for model in std::iter::once(&mut self.global).chain(self.by_domain.values_mut()) {
+            model.decay(config.decay_factor);
+            removed += model.prune(config.prune_below);
+        }
+        self.by_domain.retain(|_, model| !model.is_empty());
+
+        let mut memory = self.estimated_memory_bytes();
+        if let Some(limit) = config.max_memory_bytes {
+            let mut threshold = config.prune_below.max(1.0);
+            let mut passes = 0;
+            while memory > limit && passes < MAX_MEMORY_PRUNE_PASSES {
+                threshold *= 2.0;
+                for model in std::iter::once(&mut self.global)
+                    .chain(self.by_domain.values_mut())
+                    .chain(self.by_document.values_mut())
+                {
+                    removed += model.prune(threshold);
+                }
+                self.by_domain.retain(|_, model| !model.is_empty());
+                self.by_document.retain(|_, model| !model.is_empty());
+                memory = self.estimated_memory_bytes();
+                passes += 1;
+            }
+            if passes > 0 {
+                warn!(
+                    "[MARKOV_MAINTENANCE] Models exceeded the {} byte limit; pruned transitions counted below {} ({} bytes now).",
+                    limit, threshold, memory
+                );
+            }
+        }
+        info!(
+            "[MARKOV_MAINTENANCE] Decayed counts by {} and removed {} transitions; estimated memory {} bytes.",
+            config.decay_factor, removed, memory
+        );
+    }
+
+    pub fn estimated_memory_bytes(&self) -> u64 {
+        self.global.estimated_memory_bytes()
+            + self
+                .sub_models()
+                .map(MarkovModel::estimated_memory_bytes)
+                .sum::<u64>()
+    }
+
+    pub fn sub_models(&self) -> impl Iterator<Item = &MarkovModel> {
+        self.by_domain.values().chain(self.by_document.values())
+    }
+
+    pub fn get(&self, corpus: &GenerationCorpus) -> Option<&MarkovModel> {
+        match corpus {
+            GenerationCorpus::Global => Some(&self.global),
+            GenerationCorpus::Domain(host) => self.by_domain.get(&normalize_host(host)),
+            GenerationCorpus::Document(original_id) => self.by_document.get(original_id),
+        }
+    }
+}
+
+/// Same grouping as the knowledge graph's Domain nodes: lowercased, without a trailing dot
+/// or a leading `www.`.
+pub fn host_of(source_url: &str) -> Option<String> {
+    let url = Url::parse(source_url.trim()).ok()?;
+    let host = normalize_host(url.host_str()?);
+    (!host.is_empty()).then_some(host)
+}
+
+pub fn normalize_host(host: &str) -> String {
+    let host = host.trim().trim_end_matches('.').to_lowercase();
+    host.strip_prefix("www.").unwrap_or(&host).to_string()
+}
@@ -0,0 +1,136 @@
+use log::warn;
+use shared_models::{
+    GenerateTextTask, GenerationFailureReason, GenerationStopReason, TextEvaluation,
+};
+
+/// Successors are drawn in proportion to how often they followed the current word.
+pub const DEFAULT_TEMPERATURE: f32 = 1.0;
+/// Far above this every successor is about equally likely, so higher values add nothing.
+pub const MAX_TEMPERATURE: f32 = 10.0;
+pub const DEFAULT_MIN_SENTENCES: u32 = 1;
+
+/// How one generation samples and how much text it produces, shared by all backends.
+#[derive(Debug, Clone)]
+pub struct GenerationParams {
+    /// Hard limit in tokens: words for the Markov backend, tokenizer tokens for the neural
+    /// one.
+    pub max_tokens: u32,
+    pub temperature: f32,
+    /// Only the `top_k` most likely continuations are considered at each step.
+    pub top_k: Option<usize>,
+    /// Generation ends before the first occurrence of any of these.
+    pub stop_sequences: Vec<String>,
+    /// A sentence cut off at `max_tokens` is kept while fewer complete sentences precede it.
+    pub min_sentences: u32,
+    pub max_sentences: Option<u32>,
+    /// The task's seed, or a random one so any generation can be reproduced.
+    pub seed: u64,
+}
+
+impl GenerationParams {
+    pub fn from_task(task: &GenerateTextTask) -> Self {
+        GenerationParams {
+            max_tokens: task.max_length,
+            temperature: sampling_temperature(task),
+            top_k: task.top_k.filter(|k| *k > 0).map(|k| k as usize),
+            stop_sequences: task
+                .stop_sequences
+                .iter()
+                .filter(|stop| !stop.is_empty())
+                .cloned()
+                .collect(),
+            min_sentences: task.min_sentences.unwrap_or(DEFAULT_MIN_SENTENCES),
+            max_sentences: task.max_sentences.map(|max| max.max(1)),
+            seed: task.seed.unwrap_or_else(rand::random),
+        }
+    }
+
+    /// Byte offset of the earliest stop sequence in `text`, if any.
+    pub fn stop_position(&self, text: &str) -> Option<usize> {
+        self.stop_sequences
+            .iter()
+            .filter_map(|stop| text.find(stop.as_str()))
+            .min()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GeneratedText {
+    pub text: String,
+    /// `None` for template output, which is not sampled.
+    pub stop_reason: Option<GenerationStopReason>,
+}
+
+impl GeneratedText {
+    pub fn new(text: String, stop_reason: GenerationStopReason) -> Self {
+        GeneratedText {
+            text,
+            stop_reason: Some(stop_reason),
+        }
+    }
+}
+
+/// Why a task produced no usable text.
+#[derive(Debug, Clone)]
+pub struct GenerationFailure {
+    pub reason: GenerationFailureReason,
+    pub detail: String,
+}
+
+impl GenerationFailure {
+    pub fn new(reason: GenerationFailureReason, detail: impl Into<String>) -> Self {
+        GenerationFailure {
+            reason,
+            detail: detail.into(),
+        }
+    }
+}
+
+/// Sums the log-probabilities a backend assigns to each token of an evaluated text.
+#[derive(Debug, Default)]
+pub struct LogLikelihood {
+    tokens: u64,
+    log_likelihood: f64,
+    unseen_tokens: u64,
+}
+
+impl LogLikelihood {
+    pub fn add(&mut self, log_probability: f64, seen: bool) {
+        self.tokens += 1;
+        self.log_likelihood += log_probability;
+        if !seen {
+            self.unseen_tokens += 1;
+        }
+    }
+
+    /// `None` when no token was scored.
+    pub fn evaluation(&self) -> Option<TextEvaluation> {
+        if self.tokens == 0 {
+            return None;
+        }
+        let loss = -self.log_likelihood / self.tokens as f64;
+        Some(TextEvaluation {
+            tokens: self.tokens,
+            log_likelihood: self.log_likelihood,
+            loss,
+            perplexity: loss.exp(),
+            unseen_tokens: self.unseen_tokens,
+        })
+    }
+}
+
+/// The task's temperature, capped at [`MAX_TEMPERATURE`]; missing or invalid values fall
+/// back to [`DEFAULT_TEMPERATURE`].
+fn sampling_temperature(task: &GenerateTextTask) -> f32 {
+    match task.temperature {
+        None => DEFAULT_TEMPERATURE,
+        Some(t) if t.is_finite() && t >= 0.0 => t.min(MAX_TEMPERATURE),
+        Some(t) => {
+            warn!(
+                "[TEXT_GEN_HANDLER] Invalid temperature {} (task_id: {}), using {}",
+                t, task.task_id, DEFAULT_TEMPERATURE
+            );
+            DEFAULT_TEMPERATURE
+        }
+    }
+}
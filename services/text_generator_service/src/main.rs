@@ -1,188 +1,1023 @@
+mod config;
+mod corpora;
+mod generation;
+mod markov;
+mod neural;
+mod persistence;
+mod retraining;
+mod template;
+
+use config::{
+    CorpusConfig, DEFAULT_MODEL_NAME, GeneratorConfig, NamedModelConfig, NamedModelsConfig,
+    PersistenceConfig, ReplicaConfig, RetrainConfig,
+};
+use corpora::{CorpusModels, SharedCorpusModels};
 use futures::StreamExt;
+use generation::{GeneratedText, GenerationFailure, GenerationParams};
 use log::{debug, error, info, warn};
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use shared_models::{GenerateTextTask, GeneratedTextMessage, current_timestamp_ms};
-use std::collections::HashMap;
-use std::env;
+use markov::MarkovModel;
+use neural::NeuralGenerator;
+use retraining::Retrainer;
+use serde::Serialize;
+use shared_config::Settings;
+use shared_models::{
+    DependencyCheck, Envelope, GenerateTextTask, GeneratedTextMessage, GenerationBackend,
+    GenerationCorpus, GenerationFailedEvent, GenerationFailureReason, GeneratorEvaluateResult,
+    GeneratorEvaluateTask, GeneratorModelInfo, GeneratorModelsResult, GeneratorModelsTask,
+    GeneratorRetrainResult, GeneratorRetrainTask, GeneratorStatsResult, GeneratorStatsTask,
+    LOG_TEXT_CHARS, MarkovModelStats, PipelineErrorKind, PipelineErrorMessage, PipelineStage,
+    RequestId, TaskId, TokenizedTextMessage, Truncated, Validate, current_timestamp_ms,
+};
+use shared_nats::{
+    OverflowPolicy, Shutdown, WorkerPool, receive_span, serve_health, subscribe_shared,
+    traced_headers,
+};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tracing::Instrument;
 
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
 const GENERATE_TEXT_TASK_SUBJECT: &str = "tasks.generation.text";
 const TEXT_GENERATED_EVENT_SUBJECT: &str = "events.text.generated";
+const GENERATION_FAILED_EVENT_SUBJECT: &str = "events.generation.failed";
+const PROCESSED_TEXT_TOKENIZED_SUBJECT: &str = "data.processed_text.tokenized";
+const GENERATOR_STATS_SUBJECT: &str = "control.generator.stats";
+const GENERATOR_MODELS_SUBJECT: &str = "control.generator.models";
+const GENERATOR_RETRAIN_SUBJECT: &str = "control.generator.retrain";
+const GENERATOR_EVALUATE_SUBJECT: &str = "control.generator.evaluate";
+/// Generation tasks handled at once unless `WORKERS_GENERATION_TASKS_CONCURRENCY` says
+/// otherwise.
+const DEFAULT_GENERATION_WORKERS: usize = 32;
+/// Evaluation and retraining requests handled at once unless
+/// `WORKERS_GENERATOR_CONTROL_CONCURRENCY` says otherwise.
+const DEFAULT_CONTROL_WORKERS: usize = 8;
 
-type MarkovChainModel = HashMap<String, Vec<String>>;
+/// A Markov model trained from its own subject and hosts, selected by `model_name`.
+struct NamedModel {
+    config: NamedModelConfig,
+    corpus_models: Arc<SharedCorpusModels>,
+    model_path: Option<PathBuf>,
+    loaded_documents: u64,
+}
 
-#[derive(Clone, Debug)]
-struct MarkovModel {
-    chain: MarkovChainModel,
-    starters: Vec<String>,
+/// The generators a task can be served by. The Markov models are always there; the
+/// neural model only when it is configured and loaded.
+struct Generators {
+    markov_models: BTreeMap<String, NamedModel>,
+    neural: Option<Arc<NeuralGenerator>>,
+    default_backend: GenerationBackend,
+    min_words: usize,
+    template_timeout: Duration,
 }
 
-impl MarkovModel {
-    fn new() -> Self {
-        MarkovModel {
-            chain: HashMap::new(),
-            starters: Vec::new(),
-        }
+/// Health checks naming the loaded models; without a Markov model nothing can be generated.
+fn model_checks(generators: &Generators) -> Vec<DependencyCheck> {
+    let mut checks = vec![if generators.markov_models.is_empty() {
+        DependencyCheck::failed("markov_models", "no Markov model is loaded")
+    } else {
+        let names: Vec<&str> = generators
+            .markov_models
+            .keys()
+            .map(String::as_str)
+            .collect();
+        DependencyCheck::passed("markov_models", Some(names.join(", ")))
+    }];
+    if let Some(neural) = &generators.neural {
+        checks.push(DependencyCheck::passed(
+            "neural_model",
+            Some(neural.model_id().to_string()),
+        ));
     }
+    checks
+}
 
-    fn train(&mut self, text: &str) {
-        if text.is_empty() {
-            warn!("[MARKOV_TRAIN] Input text for training is empty.");
-            return;
-        }
-        info!("[MARKOV_TRAIN] Training Markov model...");
-
-        let words: Vec<String> = text.split_whitespace().map(String::from).collect();
-
-        if words.len() < 2 {
-            warn!(
-                "[MARKOV_TRAIN] Not enough words in text to train (need at least 2). Text: '{}'",
-                text
-            );
-            if !words.is_empty() {
-                self.starters.push(words[0].clone());
-            }
-            return;
-        }
-
-        self.starters.push(words[0].clone());
-
-        for i in 0..(words.len() - 1) {
-            let current_word = words[i].clone();
-            let next_word = words[i + 1].clone();
-
-            self.chain
-                .entry(current_word)
-                .or_insert_with(Vec::new)
-                .push(next_word);
-        }
-
-        self.starters.sort();
-        self.starters.dedup();
-        info!(
-            "[MARKOV_TRAIN] Training complete. Model has {} states. {} starter words.",
-            self.chain.len(),
-            self.starters.len()
-        );
-        if self.chain.len() < 20 && !self.chain.is_empty() {
-            debug!(
-                "[MARKOV_TRAIN] Model sample: {:?}",
-                self.chain.iter().take(5).collect::<Vec<_>>()
-            );
-        }
-        if self.starters.len() < 20 && !self.starters.is_empty() {
-            debug!(
-                "[MARKOV_TRAIN] Starters sample: {:?}",
-                self.starters.iter().take(5).collect::<Vec<_>>()
-            );
-        }
+/// The model a task of `tenant_id` uses: the one it names, else the first model of its
+/// tenant, else the default model.
+fn markov_model_name(
+    markov_models: &BTreeMap<String, NamedModel>,
+    model_name: Option<&str>,
+    tenant_id: Option<&str>,
+) -> String {
+    if let Some(name) = model_name {
+        return name.trim().to_lowercase();
     }
+    tenant_id
+        .and_then(|tenant_id| {
+            markov_models
+                .values()
+                .find(|named_model| named_model.config.tenants.iter().any(|t| t == tenant_id))
+        })
+        .map_or_else(
+            || DEFAULT_MODEL_NAME.to_string(),
+            |named_model| named_model.config.name.clone(),
+        )
+}
 
-    fn generate(&self, max_length: u32) -> String {
-        if self.chain.is_empty() || self.starters.is_empty() {
-            warn!(
-                "[MARKOV_GENERATE] Model is not trained or has no starters. Cannot generate text."
-            );
-            return String::from("Model not trained.");
-        }
-
-        let mut rng = thread_rng();
-        let mut current_word = self.starters.choose(&mut rng).unwrap().clone();
-        let mut result_text = vec![current_word.clone()];
+/// Runs `use_model` on the current snapshot of the named model's model for `corpus`. Models
+/// of other tenants are reported as unknown, so their names are not given away.
+fn with_markov_model<R>(
+    markov_models: &BTreeMap<String, NamedModel>,
+    model_name: &str,
+    tenant_id: Option<&str>,
+    corpus: &GenerationCorpus,
+    use_model: impl FnOnce(&MarkovModel) -> Result<R, GenerationFailure>,
+) -> Result<R, GenerationFailure> {
+    let Some(named_model) = markov_models
+        .get(model_name)
+        .filter(|named_model| named_model.config.serves(tenant_id))
+    else {
+        return Err(GenerationFailure::new(
+            GenerationFailureReason::UnknownModel,
+            format!("no model named '{}'", model_name),
+        ));
+    };
+    match named_model.corpus_models.load().get(corpus) {
+        Some(model) => use_model(model),
+        None => Err(GenerationFailure::new(
+            GenerationFailureReason::ModelNotTrained,
+            format!(
+                "model '{}' has no trained model for corpus {:?}",
+                model_name, corpus
+            ),
+        )),
+    }
+}
 
-        for _ in 0..(max_length - 1) {
-            if let Some(next_words) = self.chain.get(current_word.as_str()) {
-                if let Some(next_word) = next_words.choose(&mut rng) {
-                    result_text.push(next_word.clone());
-                    current_word = next_word.clone();
-                } else {
-                    break;
-                }
-            } else {
-                break;
-            }
-        }
+fn generate_markov(
+    markov_models: &BTreeMap<String, NamedModel>,
+    task: &GenerateTextTask,
+    tenant_id: Option<&str>,
+    params: &GenerationParams,
+) -> Result<GeneratedText, GenerationFailure> {
+    let model_name = markov_model_name(markov_models, task.model_name.as_deref(), tenant_id);
+    with_markov_model(
+        markov_models,
+        &model_name,
+        tenant_id,
+        &task.corpus,
+        |model| model.generate(params),
+    )
+}
 
-        result_text.join(" ")
+/// Runs the neural model on a blocking thread, since a forward pass can take seconds.
+async fn generate_neural(
+    neural: Arc<NeuralGenerator>,
+    task: &GenerateTextTask,
+    params: GenerationParams,
+) -> Result<GeneratedText, String> {
+    let prompt = task.prompt.clone();
+    match tokio::task::spawn_blocking(move || neural.generate(prompt.as_deref(), &params)).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(e) => Err(format!("generation task failed: {}", e)),
     }
 }
 
 async fn handle_generate_text_task(
     task: GenerateTextTask,
+    cause: Envelope<()>,
     nats_client: Arc<async_nats::Client>,
-    markov_model: Arc<MarkovModel>,
+    generators: Arc<Generators>,
 ) {
+    let backend = task.backend.unwrap_or(generators.default_backend);
     info!(
-        "[TEXT_GEN_HANDLER] Received GenerateTextTask (id: {}), max_length: {}",
-        task.task_id, task.max_length
+        "[TEXT_GEN_HANDLER] Received GenerateTextTask (id: {}), max_length: {}, backend: {:?}, model: {:?}, corpus: {:?}, priority: {:?}",
+        task.task_id,
+        task.max_length,
+        backend,
+        task.model_name,
+        task.corpus,
+        task.priority()
     );
     if let Some(prompt) = &task.prompt {
         info!("[TEXT_GEN_HANDLER] Prompt: {}", prompt);
         // TODO: Использовать prompt
     }
 
-    let generated_output = markov_model.generate(task.max_length);
-    info!("[TEXT_GEN_HANDLER] Generated text: '{}'", generated_output);
-
-    let result_message = GeneratedTextMessage {
-        original_task_id: task.task_id.clone(),
-        generated_text: generated_output,
-        timestamp_ms: current_timestamp_ms(),
+    let params = GenerationParams::from_task(&task);
+    let generated_output = if let Err(e) = task.validate() {
+        Err(GenerationFailure::new(
+            GenerationFailureReason::InvalidTask,
+            e.to_string(),
+        ))
+    } else if task.is_expired_at(current_timestamp_ms()) {
+        Err(GenerationFailure::new(
+            GenerationFailureReason::DeadlineExceeded,
+            format!("deadline {:?} passed before generation", task.deadline_ms),
+        ))
+    } else {
+        match (&task.template, backend, &generators.neural) {
+            (Some(template), _, _) => {
+                let original_id = match &task.corpus {
+                    GenerationCorpus::Document(original_id) => Some(*original_id),
+                    _ => None,
+                };
+                template::render(
+                    &nats_client,
+                    &cause,
+                    template,
+                    original_id,
+                    generators.template_timeout,
+                )
+                .await
+                .map(|text| GeneratedText {
+                    text,
+                    stop_reason: None,
+                })
+            }
+            (None, GenerationBackend::Neural, Some(neural)) => {
+                match generate_neural(Arc::clone(neural), &task, params.clone()).await {
+                    Ok(generated) => Ok(generated),
+                    Err(e) => {
+                        error!(
+                            "[TEXT_GEN_HANDLER] Neural generation with {} failed (task_id: {}): {}. Falling back to Markov.",
+                            neural.model_id(),
+                            task.task_id,
+                            e
+                        );
+                        generate_markov(
+                            &generators.markov_models,
+                            &task,
+                            cause.tenant_id.as_deref(),
+                            &params,
+                        )
+                    }
+                }
+            }
+            (None, GenerationBackend::Neural, None) => {
+                warn!(
+                    "[TEXT_GEN_HANDLER] No neural model loaded (task_id: {}). Falling back to Markov.",
+                    task.task_id
+                );
+                generate_markov(
+                    &generators.markov_models,
+                    &task,
+                    cause.tenant_id.as_deref(),
+                    &params,
+                )
+            }
+            (None, GenerationBackend::Markov, _) => generate_markov(
+                &generators.markov_models,
+                &task,
+                cause.tenant_id.as_deref(),
+                &params,
+            ),
+        }
+    };
+    // A template's length is the caller's choice; only sampled output can degenerate.
+    let min_words = match task.template {
+        Some(_) => 1,
+        None => generators.min_words.min(task.max_length.max(1) as usize),
     };
+    let generated_output = generated_output.and_then(|generated| {
+        let words = generated.text.split_whitespace().count();
+        if words < min_words {
+            Err(GenerationFailure::new(
+                GenerationFailureReason::TooFewWords,
+                format!("generated {} words, at least {} required", words, min_words),
+            ))
+        } else {
+            Ok(generated)
+        }
+    });
+
+    match generated_output {
+        Ok(generated) => {
+            info!(
+                "[TEXT_GEN_HANDLER] Generated text (stop reason: {:?}): '{}'",
+                generated.stop_reason, generated.text
+            );
+            let result_message = GeneratedTextMessage {
+                seed: Some(params.seed),
+                stop_reason: generated.stop_reason,
+                ..GeneratedTextMessage::new(task.task_id, generated.text)
+            };
+            publish_event(
+                &nats_client,
+                &cause,
+                TEXT_GENERATED_EVENT_SUBJECT,
+                "GeneratedTextMessage",
+                task.task_id,
+                &result_message,
+            )
+            .await;
+        }
+        Err(failure) => {
+            warn!(
+                "[TEXT_GEN_HANDLER] Generation failed (task_id: {}): {:?}, {}",
+                task.task_id, failure.reason, failure.detail
+            );
+            let error_kind = match failure.reason {
+                GenerationFailureReason::InvalidTask => PipelineErrorKind::InvalidMessage,
+                GenerationFailureReason::DeadlineExceeded => PipelineErrorKind::Expired,
+                _ => PipelineErrorKind::Processing,
+            };
+            let pipeline_error =
+                PipelineErrorMessage::new(PipelineStage::Generation, error_kind, &failure.detail)
+                    .with_task_id(task.task_id);
+            publish_event(
+                &nats_client,
+                &cause,
+                &pipeline_error.stage.error_subject(),
+                "PipelineErrorMessage",
+                task.task_id,
+                &pipeline_error,
+            )
+            .await;
+            let failed_event =
+                GenerationFailedEvent::new(task.task_id, failure.reason, failure.detail);
+            publish_event(
+                &nats_client,
+                &cause,
+                GENERATION_FAILED_EVENT_SUBJECT,
+                "GenerationFailedEvent",
+                task.task_id,
+                &failed_event,
+            )
+            .await;
+        }
+    }
+}
 
-    match serde_json::to_vec(&result_message) {
+async fn publish_event<T: Serialize>(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    subject: &str,
+    kind: &str,
+    task_id: TaskId,
+    event: &T,
+) {
+    match cause.follow_up(SERVICE_NAME, event).to_vec() {
         Ok(payload_json) => {
             info!(
-                "[NATS_PUB_PREP] Publishing GeneratedTextMessage (task_id: {}) to subject: {}",
-                result_message.original_task_id, TEXT_GENERATED_EVENT_SUBJECT
+                "[NATS_PUB_PREP] Publishing {} (task_id: {}) to subject: {}",
+                kind, task_id, subject
             );
             if let Err(e) = nats_client
-                .publish(TEXT_GENERATED_EVENT_SUBJECT, payload_json.into())
+                .publish_with_headers(subject.to_string(), traced_headers(), payload_json.into())
                 .await
             {
                 error!(
-                    "[NATS_PUB_FAIL] Failed to publish GeneratedTextMessage (task_id: {}): {}",
-                    result_message.original_task_id, e
+                    "[NATS_PUB_FAIL] Failed to publish {} (task_id: {}): {}",
+                    kind, task_id, e
                 );
             } else {
                 info!(
-                    "[NATS_PUB_SUCCESS] Successfully published GeneratedTextMessage (task_id: {})",
-                    result_message.original_task_id
+                    "[NATS_PUB_SUCCESS] Successfully published {} (task_id: {})",
+                    kind, task_id
                 );
             }
         }
         Err(e) => {
             error!(
-                "[SERIALIZE_FAIL] Failed to serialize GeneratedTextMessage (task_id: {}): {}",
-                result_message.original_task_id, e
+                "[SERIALIZE_FAIL] Failed to serialize {} (task_id: {}): {}",
+                kind, task_id, e
             );
         }
     }
 }
 
+async fn collect_generator_stats(
+    generators: &Generators,
+    request_id: RequestId,
+) -> GeneratorStatsResult {
+    let default_models = generators
+        .markov_models
+        .get(DEFAULT_MODEL_NAME)
+        .map(|named_model| named_model.corpus_models.load_full())
+        .unwrap_or_default();
+    GeneratorStatsResult {
+        request_id,
+        global: default_models.global.stats(),
+        domain_models: default_models.by_domain.len() as u64,
+        document_models: default_models.by_document.len() as u64,
+        total_estimated_memory_bytes: generators
+            .markov_models
+            .values()
+            .map(|named_model| named_model.corpus_models.load().estimated_memory_bytes())
+            .sum(),
+        neural_model: generators
+            .neural
+            .as_ref()
+            .map(|neural| neural.model_id().to_string()),
+        error_message: None,
+    }
+}
+
+/// Answers `control.generator.stats` requests with the size and training state of the models.
+async fn run_stats_handler(
+    subscriber: async_nats::Subscriber,
+    nats_client: Arc<async_nats::Client>,
+    generators: Arc<Generators>,
+    shutdown: Shutdown,
+) {
+    let mut subscriber = subscriber.take_until(shutdown.signalled());
+    while let Some(message) = subscriber.next().await {
+        let _in_flight = shutdown.track();
+        let Some(reply_to) = message.reply else {
+            warn!("[STATS_HANDLER] No reply subject provided. Stats not sent.");
+            continue;
+        };
+        let (cause, result) = match Envelope::<GeneratorStatsTask>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let (cause, task) = envelope.split();
+                let result = collect_generator_stats(&generators, task.request_id).await;
+                (Some(cause), result)
+            }
+            Err(e) => {
+                warn!(
+                    "[STATS_HANDLER_DESERIALIZE_FAIL] Failed to deserialize GeneratorStatsTask: {}",
+                    e
+                );
+                (
+                    None,
+                    GeneratorStatsResult {
+                        request_id: RequestId::default(),
+                        global: MarkovModelStats::default(),
+                        domain_models: 0,
+                        document_models: 0,
+                        total_estimated_memory_bytes: 0,
+                        neural_model: None,
+                        error_message: Some(format!("Invalid GeneratorStatsTask: {}", e)),
+                    },
+                )
+            }
+        };
+
+        match Envelope::following(cause.as_ref(), SERVICE_NAME, &result).to_vec() {
+            Ok(payload_json) => {
+                if let Err(e) = nats_client.publish(reply_to, payload_json.into()).await {
+                    error!(
+                        "[STATS_HANDLER_NATS_REPLY_FAIL] Failed to publish reply: {}",
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                error!(
+                    "[STATS_HANDLER_SERIALIZE_FAIL] Failed to serialize reply: {}",
+                    e
+                );
+            }
+        }
+    }
+    info!("[NATS_LOOP_END] Stats subscription ended or NATS connection lost.");
+}
+
+/// The models tasks of `tenant_id` can select.
+fn list_generator_models(
+    generators: &Generators,
+    request_id: RequestId,
+    tenant_id: Option<&str>,
+) -> GeneratorModelsResult {
+    let models = generators
+        .markov_models
+        .values()
+        .filter(|named_model| named_model.config.serves(tenant_id))
+        .map(|named_model| {
+            let corpus_models = named_model.corpus_models.load();
+            GeneratorModelInfo {
+                name: named_model.config.name.clone(),
+                subject: named_model.config.subject.clone(),
+                hosts: named_model.config.hosts.clone(),
+                tenants: named_model.config.tenants.clone(),
+                global: corpus_models.global.stats(),
+                domain_models: corpus_models.by_domain.len() as u64,
+                document_models: corpus_models.by_document.len() as u64,
+            }
+        })
+        .collect();
+    GeneratorModelsResult {
+        request_id,
+        models,
+        default_model: markov_model_name(&generators.markov_models, None, tenant_id),
+        error_message: None,
+    }
+}
+
+/// Answers `control.generator.models` requests with the named models tasks can select.
+async fn run_models_handler(
+    subscriber: async_nats::Subscriber,
+    nats_client: Arc<async_nats::Client>,
+    generators: Arc<Generators>,
+    shutdown: Shutdown,
+) {
+    let mut subscriber = subscriber.take_until(shutdown.signalled());
+    while let Some(message) = subscriber.next().await {
+        let _in_flight = shutdown.track();
+        let Some(reply_to) = message.reply else {
+            warn!("[MODELS_HANDLER] No reply subject provided. Model list not sent.");
+            continue;
+        };
+        let (cause, result) = match Envelope::<GeneratorModelsTask>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let (cause, task) = envelope.split();
+                let models =
+                    list_generator_models(&generators, task.request_id, cause.tenant_id.as_deref());
+                (Some(cause), models)
+            }
+            Err(e) => {
+                warn!(
+                    "[MODELS_HANDLER_DESERIALIZE_FAIL] Failed to deserialize GeneratorModelsTask: {}",
+                    e
+                );
+                (
+                    None,
+                    GeneratorModelsResult {
+                        request_id: RequestId::default(),
+                        models: Vec::new(),
+                        default_model: DEFAULT_MODEL_NAME.to_string(),
+                        error_message: Some(format!("Invalid GeneratorModelsTask: {}", e)),
+                    },
+                )
+            }
+        };
+
+        match Envelope::following(cause.as_ref(), SERVICE_NAME, &result).to_vec() {
+            Ok(payload_json) => {
+                if let Err(e) = nats_client.publish(reply_to, payload_json.into()).await {
+                    error!(
+                        "[MODELS_HANDLER_NATS_REPLY_FAIL] Failed to publish reply: {}",
+                        e
+                    );
+                }
+            }
+            Err(e) => {
+                error!(
+                    "[MODELS_HANDLER_SERIALIZE_FAIL] Failed to serialize reply: {}",
+                    e
+                );
+            }
+        }
+    }
+    info!("[NATS_LOOP_END] Models subscription ended or NATS connection lost.");
+}
+
+async fn handle_evaluate_task(
+    task: GeneratorEvaluateTask,
+    tenant_id: Option<&str>,
+    generators: &Generators,
+) -> GeneratorEvaluateResult {
+    let backend = task.backend.unwrap_or(generators.default_backend);
+    // Unlike generation, evaluation never falls back: the caller compares a specific model.
+    let (model, evaluation) = match (backend, &generators.neural) {
+        (GenerationBackend::Neural, Some(neural)) => {
+            let model_id = neural.model_id().to_string();
+            let neural = Arc::clone(neural);
+            let text = task.text.clone();
+            let evaluation = match tokio::task::spawn_blocking(move || neural.evaluate(&text)).await
+            {
+                Ok(result) => result.map_err(|e| e.to_string()),
+                Err(e) => Err(format!("evaluation task failed: {}", e)),
+            };
+            (Some(model_id), evaluation)
+        }
+        (GenerationBackend::Neural, None) => (None, Err("no neural model is loaded".to_string())),
+        (GenerationBackend::Markov, _) => {
+            let model_name = markov_model_name(
+                &generators.markov_models,
+                task.model_name.as_deref(),
+                tenant_id,
+            );
+            let evaluation = with_markov_model(
+                &generators.markov_models,
+                &model_name,
+                tenant_id,
+                &task.corpus,
+                |model| model.evaluate(&task.text),
+            )
+            .map_err(|failure| failure.detail);
+            (Some(model_name), evaluation)
+        }
+    };
+
+    match evaluation {
+        Ok(evaluation) => {
+            info!(
+                "[EVALUATE_HANDLER] {:?} model {:?} scored {} tokens (request_id: {}): loss {:.3}, perplexity {:.2}.",
+                backend,
+                model,
+                evaluation.tokens,
+                task.request_id,
+                evaluation.loss,
+                evaluation.perplexity
+            );
+            GeneratorEvaluateResult {
+                request_id: task.request_id,
+                backend: Some(backend),
+                model,
+                evaluation: Some(evaluation),
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            warn!(
+                "[EVALUATE_HANDLER] Evaluation (request_id: {}) failed: {}",
+                task.request_id, e
+            );
+            GeneratorEvaluateResult {
+                request_id: task.request_id,
+                backend: Some(backend),
+                model,
+                evaluation: None,
+                error_message: Some(e),
+            }
+        }
+    }
+}
+
+/// Answers `control.generator.evaluate` requests with how likely a model finds a text.
+/// Neural evaluations can take seconds, so each request is served on its own task in
+/// `workers`; a request refused by a full pool is left to time out.
+async fn run_evaluate_handler(
+    subscriber: async_nats::Subscriber,
+    nats_client: Arc<async_nats::Client>,
+    generators: Arc<Generators>,
+    shutdown: Shutdown,
+    workers: WorkerPool,
+) {
+    let mut subscriber = subscriber.take_until(shutdown.signalled());
+    while let Some(message) = subscriber.next().await {
+        let Some(reply_to) = message.reply else {
+            warn!("[EVALUATE_HANDLER] No reply subject provided. Evaluation not sent.");
+            continue;
+        };
+        let nats_client = Arc::clone(&nats_client);
+        let generators = Arc::clone(&generators);
+        let in_flight = shutdown.track();
+        let _ = workers.spawn_limited(async move {
+            let _in_flight = in_flight;
+            let (cause, result) = match Envelope::<GeneratorEvaluateTask>::from_slice(
+                &message.payload,
+            ) {
+                Ok(envelope) => {
+                    let (cause, task) = envelope.split();
+                    let result =
+                        handle_evaluate_task(task, cause.tenant_id.as_deref(), &generators).await;
+                    (Some(cause), result)
+                }
+                Err(e) => {
+                    warn!(
+                        "[EVALUATE_HANDLER_DESERIALIZE_FAIL] Failed to deserialize GeneratorEvaluateTask: {}",
+                        e
+                    );
+                    (
+                        None,
+                        GeneratorEvaluateResult {
+                            request_id: RequestId::default(),
+                            backend: None,
+                            model: None,
+                            evaluation: None,
+                            error_message: Some(format!("Invalid GeneratorEvaluateTask: {}", e)),
+                        },
+                    )
+                }
+            };
+
+            match Envelope::following(cause.as_ref(), SERVICE_NAME, &result).to_vec() {
+                Ok(payload_json) => {
+                    if let Err(e) = nats_client.publish(reply_to, payload_json.into()).await {
+                        error!(
+                            "[EVALUATE_HANDLER_NATS_REPLY_FAIL] Failed to publish reply: {}",
+                            e
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "[EVALUATE_HANDLER_SERIALIZE_FAIL] Failed to serialize reply: {}",
+                        e
+                    );
+                }
+            }
+        })
+        .await;
+    }
+    info!("[NATS_LOOP_END] Evaluate subscription ended or NATS connection lost.");
+}
+
+async fn handle_retrain_task(
+    task: GeneratorRetrainTask,
+    retrainer: &Retrainer,
+) -> GeneratorRetrainResult {
+    let started = std::time::Instant::now();
+    let model_names = task.model_name.map(|name| vec![name.trim().to_lowercase()]);
+    match retrainer.retrain(model_names.as_deref()).await {
+        Ok(summary) => GeneratorRetrainResult {
+            request_id: task.request_id,
+            models: summary.models,
+            documents: summary.documents,
+            sentences: summary.sentences,
+            duration_ms: started.elapsed().as_millis() as u64,
+            error_message: None,
+        },
+        Err(e) => {
+            warn!(
+                "[RETRAIN_HANDLER] Retraining (request_id: {}) failed: {}",
+                task.request_id, e
+            );
+            GeneratorRetrainResult {
+                request_id: task.request_id,
+                models: Vec::new(),
+                documents: 0,
+                sentences: 0,
+                duration_ms: started.elapsed().as_millis() as u64,
+                error_message: Some(e),
+            }
+        }
+    }
+}
+
+/// Answers `control.generator.retrain` requests once the requested models are rebuilt from
+/// the stored corpus. Requests are served concurrently in `workers`; overlapping ones are
+/// refused.
+async fn run_retrain_handler(
+    subscriber: async_nats::Subscriber,
+    nats_client: Arc<async_nats::Client>,
+    retrainer: Arc<Retrainer>,
+    shutdown: Shutdown,
+    workers: WorkerPool,
+) {
+    let mut subscriber = subscriber.take_until(shutdown.signalled());
+    while let Some(message) = subscriber.next().await {
+        let Some(reply_to) = message.reply else {
+            warn!("[RETRAIN_HANDLER] No reply subject provided. Retraining not started.");
+            continue;
+        };
+        let nats_client = Arc::clone(&nats_client);
+        let retrainer = Arc::clone(&retrainer);
+        let in_flight = shutdown.track();
+        let _ = workers.spawn_limited(async move {
+            let _in_flight = in_flight;
+            let (cause, result) = match Envelope::<GeneratorRetrainTask>::from_slice(
+                &message.payload,
+            ) {
+                Ok(envelope) => {
+                    let (cause, task) = envelope.split();
+                    (Some(cause), handle_retrain_task(task, &retrainer).await)
+                }
+                Err(e) => {
+                    warn!(
+                        "[RETRAIN_HANDLER_DESERIALIZE_FAIL] Failed to deserialize GeneratorRetrainTask: {}",
+                        e
+                    );
+                    (
+                        None,
+                        GeneratorRetrainResult {
+                            request_id: RequestId::default(),
+                            models: Vec::new(),
+                            documents: 0,
+                            sentences: 0,
+                            duration_ms: 0,
+                            error_message: Some(format!("Invalid GeneratorRetrainTask: {}", e)),
+                        },
+                    )
+                }
+            };
+
+            match Envelope::following(cause.as_ref(), SERVICE_NAME, &result).to_vec() {
+                Ok(payload_json) => {
+                    if let Err(e) = nats_client.publish(reply_to, payload_json.into()).await {
+                        error!(
+                            "[RETRAIN_HANDLER_NATS_REPLY_FAIL] Failed to publish reply: {}",
+                            e
+                        );
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "[RETRAIN_HANDLER_SERIALIZE_FAIL] Failed to serialize reply: {}",
+                        e
+                    );
+                }
+            }
+        })
+        .await;
+    }
+    info!("[NATS_LOOP_END] Retrain subscription ended or NATS connection lost.");
+}
+
+fn handle_tokenized_text(
+    msg: TokenizedTextMessage,
+    model_name: &str,
+    corpus_models: &mut CorpusModels,
+    corpus_config: &CorpusConfig,
+) {
+    if msg.sentences.is_empty() {
+        warn!(
+            "[MARKOV_TRAIN] TokenizedTextMessage (id: {}) has no sentences. Skipping.",
+            msg.original_id
+        );
+        return;
+    }
+
+    let trained = corpus_models.train_document(
+        corpus_config,
+        msg.original_id,
+        &msg.source_url,
+        &msg.sentences,
+    );
+    info!(
+        "[MARKOV_TRAIN] Model '{}' trained on {}/{} sentences of document (id: {}). Model has {} states, {} starter words.",
+        model_name,
+        trained,
+        msg.sentences.len(),
+        msg.original_id,
+        corpus_models.global.chain.len(),
+        corpus_models.global.starters.len()
+    );
+}
+
+/// Feeds every tokenized document the named model accepts into its models, so generation
+/// reflects the harvested corpus. Training works on a private copy that is published at
+/// most every `publish_interval`, so generation and checkpoints read a consistent snapshot
+/// without waiting for training. Models rebuilt from the stored corpus arrive on
+/// `retrained` and replace the copy.
+async fn run_training_loop(
+    subscriber: async_nats::Subscriber,
+    mut retrained: mpsc::Receiver<CorpusModels>,
+    model_config: NamedModelConfig,
+    corpus_models: Arc<SharedCorpusModels>,
+    corpus_config: CorpusConfig,
+    shutdown: Shutdown,
+) {
+    // Held until the last document is published, so the checkpoint taken on shutdown has it.
+    let _in_flight = shutdown.track();
+    let mut subscriber = subscriber.take_until(shutdown.signalled());
+    let mut working = CorpusModels::clone(&corpus_models.load());
+    let mut unpublished = false;
+    let mut publish_ticker = tokio::time::interval(corpus_config.publish_interval);
+    publish_ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    info!(
+        "[NATS_LOOP] Model '{}' waiting for tokenized text on {}...",
+        model_config.name, model_config.subject
+    );
+    loop {
+        tokio::select! {
+            message = subscriber.next() => {
+                let Some(message) = message else {
+                    break;
+                };
+                match Envelope::<TokenizedTextMessage>::from_slice(&message.payload)
+                    .map(Envelope::split)
+                {
+                    Ok((cause, msg))
+                        if !model_config.accepts(cause.tenant_id.as_deref(), &msg.source_url) =>
+                    {
+                        debug!(
+                            "[MARKOV_TRAIN] Model '{}' skips document (id: {}) from {} (tenant: {:?}).",
+                            model_config.name, msg.original_id, msg.source_url, cause.tenant_id
+                        );
+                    }
+                    Ok((_, msg)) => {
+                        handle_tokenized_text(msg, &model_config.name, &mut working, &corpus_config);
+                        unpublished = true;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "[TRAIN_DESERIALIZE_FAIL] Failed to deserialize TokenizedTextMessage: {}. Payload: {}",
+                            e,
+                            Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
+                        );
+                    }
+                }
+            }
+            Some(rebuilt) = retrained.recv() => {
+                working = rebuilt;
+                corpus_models.store(Arc::new(working.clone()));
+                unpublished = false;
+                info!(
+                    "[MARKOV_RETRAIN] Model '{}' replaced by its rebuild ({} documents, {} states).",
+                    model_config.name,
+                    working.global.trained_documents,
+                    working.global.chain.len()
+                );
+            }
+            _ = publish_ticker.tick(), if unpublished => {
+                corpus_models.store(Arc::new(working.clone()));
+                unpublished = false;
+                debug!(
+                    "[MARKOV_PUBLISH] Published model '{}' trained on {} documents.",
+                    model_config.name, working.global.trained_documents
+                );
+            }
+        }
+    }
+    if unpublished {
+        corpus_models.store(Arc::new(working));
+    }
+    info!(
+        "[NATS_LOOP_END] Training subscription of model '{}' ended or NATS connection lost.",
+        model_config.name
+    );
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(SERVICE_NAME, "info", &settings, String::new)?;
     info!("Starting...");
+    let shutdown = Shutdown::listen();
 
-    let mut model = MarkovModel::new();
-    let training_text = "я пошел гулять в парк и увидел там собаку собака была очень веселая и я решил с ней поиграть";
-
-    model.train(training_text);
-    let markov_model_instance = Arc::new(model);
-    info!("[MAIN] Markov model initialized and trained.");
-
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!("[NATS_CONFIG] NATS_URL not set, defaulting to nats://localhost:4222");
-        "nats://localhost:4222".to_string()
-    });
+    let persistence_config = PersistenceConfig::from_env();
+    let corpus_config = CorpusConfig::from_env();
+    let named_models_config = NamedModelsConfig::from_env(PROCESSED_TEXT_TOKENIZED_SUBJECT);
+    let mut markov_models = BTreeMap::new();
+    for model_config in named_models_config.models {
+        let model_path = persistence_config.model_path_for(&model_config.name);
+        let models = persistence::load_or_default(model_path.as_deref()).await;
+        let named_model = NamedModel {
+            loaded_documents: models.global.trained_documents,
+            corpus_models: Arc::new(SharedCorpusModels::from_pointee(models)),
+            model_path,
+            config: model_config,
+        };
+        markov_models.insert(named_model.config.name.clone(), named_model);
+    }
     info!(
-        "[NATS_CONNECT] Attempting to connect to NATS server at {}...",
-        nats_url
+        "[MAIN] Markov models {:?} initialized; they are trained from the live pipeline and the stored corpus.",
+        markov_models.keys().collect::<Vec<_>>()
     );
 
-    let nats_client = Arc::new(match async_nats::connect(&nats_url).await {
+    let generator_config = GeneratorConfig::from_env(settings.force_cpu);
+    let neural = match generator_config.neural {
+        Some(neural_config) => {
+            info!(
+                "[NEURAL_INIT] Loading neural model {} (revision: {}, force_cpu: {})",
+                neural_config.model_id, neural_config.revision, neural_config.force_cpu
+            );
+            match tokio::task::spawn_blocking(move || {
+                NeuralGenerator::new(
+                    &neural_config.model_id,
+                    Some(neural_config.revision),
+                    neural_config.force_cpu,
+                )
+            })
+            .await
+            {
+                Ok(Ok(generator)) => Some(Arc::new(generator)),
+                Ok(Err(e)) => {
+                    error!(
+                        "[NEURAL_INIT_FAIL] Failed to load neural model: {:#}. Serving Markov only.",
+                        e
+                    );
+                    None
+                }
+                Err(e) => {
+                    error!(
+                        "[NEURAL_INIT_FAIL] Neural model loader panicked: {}. Serving Markov only.",
+                        e
+                    );
+                    None
+                }
+            }
+        }
+        None => None,
+    };
+    let generators = Arc::new(Generators {
+        markov_models,
+        neural,
+        default_backend: generator_config.default_backend,
+        min_words: generator_config.min_words,
+        template_timeout: generator_config.template_timeout,
+    });
+
+    let replica_config = ReplicaConfig::from_env();
+    if !replica_config.training && persistence_config.model_path.is_none() {
+        warn!(
+            "[MAIN] Training is disabled and MARKOV_MODEL_PATH is off; the Markov models stay empty."
+        );
+    }
+    if let Some(interval) = replica_config.snapshot_reload_interval {
+        for named_model in generators.markov_models.values() {
+            if let Some(path) = named_model.model_path.clone() {
+                tokio::spawn(persistence::run_reloads(
+                    Arc::clone(&named_model.corpus_models),
+                    path,
+                    interval,
+                ));
+            }
+        }
+    }
+    if replica_config.training
+        && let Some(interval) = persistence_config.checkpoint_interval
+    {
+        for named_model in generators.markov_models.values() {
+            if let Some(path) = named_model.model_path.clone() {
+                tokio::spawn(persistence::run_checkpoints(
+                    Arc::clone(&named_model.corpus_models),
+                    path,
+                    interval,
+                    named_model.loaded_documents,
+                ));
+            }
+        }
+    }
+
+    let nats_client = Arc::new(match shared_nats::connect(&settings.nats).await {
         Ok(client) => {
             info!("[NATS_CONNECT_SUCCESS] Successfully connected to NATS!");
             client
@@ -193,13 +1028,179 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     });
 
-    let mut subscriber = match nats_client.subscribe(GENERATE_TEXT_TASK_SUBJECT).await {
+    let queue_group = replica_config.queue_group.as_deref();
+    let control_workers = WorkerPool::from_env(
+        "generator_control",
+        DEFAULT_CONTROL_WORKERS,
+        OverflowPolicy::Reject,
+    );
+    // Replicas that do not train serve the trainer's snapshots and never overwrite them.
+    if replica_config.training {
+        let mut retrain_targets = Vec::new();
+        for named_model in generators.markov_models.values() {
+            let subject = named_model.config.subject.clone();
+            // Not in the queue group: a training instance has to see every document.
+            match nats_client.subscribe(subject.clone()).await {
+                Ok(sub) => {
+                    info!(
+                        "[NATS_SUB_SUCCESS] Subscribed to subject: {} (model '{}')",
+                        subject, named_model.config.name
+                    );
+                    let (retrained_sender, retrained) = mpsc::channel(1);
+                    retrain_targets.push((named_model.config.clone(), retrained_sender));
+                    tokio::spawn(run_training_loop(
+                        sub,
+                        retrained,
+                        named_model.config.clone(),
+                        Arc::clone(&named_model.corpus_models),
+                        corpus_config.clone(),
+                        shutdown.clone(),
+                    ));
+                }
+                Err(err) => {
+                    error!(
+                        "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                        subject, err
+                    );
+                    return Err(Box::new(err) as Box<dyn std::error::Error>);
+                }
+            }
+        }
+
+        let retrain_config = RetrainConfig::from_env();
+        let retrainer = Arc::new(Retrainer::new(
+            Arc::clone(&nats_client),
+            retrain_config.clone(),
+            corpus_config.clone(),
+            retrain_targets,
+        ));
+        if let Some(interval) = retrain_config.interval {
+            tokio::spawn(retraining::run_scheduled(Arc::clone(&retrainer), interval));
+        }
+        if retrain_config.on_start {
+            let empty_models: Vec<String> = generators
+                .markov_models
+                .values()
+                .filter(|named_model| named_model.corpus_models.load().global.is_empty())
+                .map(|named_model| named_model.config.name.clone())
+                .collect();
+            if !empty_models.is_empty() {
+                let retrainer = Arc::clone(&retrainer);
+                tokio::spawn(async move {
+                    info!(
+                        "[MARKOV_RETRAIN] Bootstrapping empty models {:?} from the stored corpus.",
+                        empty_models
+                    );
+                    if let Err(e) = retrainer.retrain(Some(&empty_models)).await {
+                        warn!("[MARKOV_RETRAIN_FAIL] Bootstrap retraining failed: {}", e);
+                    }
+                });
+            }
+        }
+
+        match subscribe_shared(&nats_client, GENERATOR_RETRAIN_SUBJECT, queue_group).await {
+            Ok(sub) => {
+                info!(
+                    "[NATS_SUB_SUCCESS] Subscribed to subject: {} (queue group: {:?})",
+                    GENERATOR_RETRAIN_SUBJECT, queue_group
+                );
+                tokio::spawn(run_retrain_handler(
+                    sub,
+                    Arc::clone(&nats_client),
+                    retrainer,
+                    shutdown.clone(),
+                    control_workers.clone(),
+                ));
+            }
+            Err(err) => {
+                error!(
+                    "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                    GENERATOR_RETRAIN_SUBJECT, err
+                );
+                return Err(Box::new(err) as Box<dyn std::error::Error>);
+            }
+        }
+    }
+
+    match subscribe_shared(&nats_client, GENERATOR_STATS_SUBJECT, queue_group).await {
         Ok(sub) => {
             info!(
-                "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
-                GENERATE_TEXT_TASK_SUBJECT
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {} (queue group: {:?})",
+                GENERATOR_STATS_SUBJECT, queue_group
             );
-            sub
+            tokio::spawn(run_stats_handler(
+                sub,
+                Arc::clone(&nats_client),
+                Arc::clone(&generators),
+                shutdown.clone(),
+            ));
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GENERATOR_STATS_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error>);
+        }
+    }
+
+    match subscribe_shared(&nats_client, GENERATOR_MODELS_SUBJECT, queue_group).await {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {} (queue group: {:?})",
+                GENERATOR_MODELS_SUBJECT, queue_group
+            );
+            tokio::spawn(run_models_handler(
+                sub,
+                Arc::clone(&nats_client),
+                Arc::clone(&generators),
+                shutdown.clone(),
+            ));
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GENERATOR_MODELS_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error>);
+        }
+    }
+
+    // Replicas share evaluations like generation tasks, so each is scored once.
+    let evaluate_subscription =
+        subscribe_shared(&nats_client, GENERATOR_EVALUATE_SUBJECT, queue_group).await;
+    match evaluate_subscription {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {} (queue group: {:?})",
+                GENERATOR_EVALUATE_SUBJECT, queue_group
+            );
+            tokio::spawn(run_evaluate_handler(
+                sub,
+                Arc::clone(&nats_client),
+                Arc::clone(&generators),
+                shutdown.clone(),
+                control_workers.clone(),
+            ));
+        }
+        Err(err) => {
+            error!(
+                "[NATS_SUB_FAIL] Failed to subscribe to {}: {}",
+                GENERATOR_EVALUATE_SUBJECT, err
+            );
+            return Err(Box::new(err) as Box<dyn std::error::Error>);
+        }
+    }
+
+    let generate_subscription =
+        subscribe_shared(&nats_client, GENERATE_TEXT_TASK_SUBJECT, queue_group).await;
+    let mut subscriber = match generate_subscription {
+        Ok(sub) => {
+            info!(
+                "[NATS_SUB_SUCCESS] Subscribed to subject: {} (queue group: {:?})",
+                GENERATE_TEXT_TASK_SUBJECT, queue_group
+            );
+            sub.take_until(shutdown.signalled())
         }
         Err(err) => {
             error!(
@@ -209,6 +1210,18 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             return Err(Box::new(err) as Box<dyn std::error::Error>);
         }
     };
+    let generators_for_health = Arc::clone(&generators);
+    serve_health((*nats_client).clone(), SERVICE_NAME, move || {
+        let checks = model_checks(&generators_for_health);
+        async move { checks }
+    })
+    .await?;
+
+    let generation_workers = WorkerPool::from_env(
+        "generation_tasks",
+        DEFAULT_GENERATION_WORKERS,
+        OverflowPolicy::Wait,
+    );
     info!("[NATS_LOOP] Waiting for text generation tasks...");
 
     while let Some(message) = subscriber.next().await {
@@ -218,30 +1231,56 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         );
         debug!("[NATS_MSG_PAYLOAD] Payload (raw): {:?}", message.payload);
 
-        match serde_json::from_slice::<GenerateTextTask>(&message.payload) {
-            Ok(task) => {
+        match Envelope::<GenerateTextTask>::from_slice(&message.payload) {
+            Ok(envelope) => {
+                let (cause, task) = envelope.split();
                 info!(
                     "[TASK_DESERIALIZED] Deserialized GenerateTextTask (id: {})",
                     task.task_id
                 );
 
                 let client_clone = Arc::clone(&nats_client);
-                let model_clone = Arc::clone(&markov_model_instance);
+                let generators_clone = Arc::clone(&generators);
+                let span = receive_span(&message.subject, message.headers.as_ref());
+                let in_flight = shutdown.track();
 
-                tokio::spawn(async move {
-                    handle_generate_text_task(task, client_clone, model_clone).await;
-                });
+                // While the pool is full the subscription buffers further tasks.
+                let _ = generation_workers
+                    .spawn_limited(
+                        async move {
+                            let _in_flight = in_flight;
+                            handle_generate_text_task(task, cause, client_clone, generators_clone)
+                                .await;
+                        }
+                        .instrument(span),
+                    )
+                    .await;
             }
             Err(e) => {
                 warn!(
                     "[TASK_DESERIALIZE_FAIL] Failed to deserialize GenerateTextTask: {}. Payload: {}",
                     e,
-                    String::from_utf8_lossy(&message.payload)
+                    Truncated::new(&String::from_utf8_lossy(&message.payload), LOG_TEXT_CHARS)
                 );
             }
         }
     }
 
     info!("[NATS_LOOP_END] Subscription ended or NATS connection lost.");
+    shutdown
+        .drain(&nats_client, settings.shutdown.drain_timeout())
+        .await;
+    if replica_config.training {
+        for named_model in generators.markov_models.values() {
+            if let Some(path) = &named_model.model_path {
+                persistence::checkpoint(
+                    &named_model.corpus_models,
+                    path,
+                    named_model.loaded_documents,
+                )
+                .await;
+            }
+        }
+    }
     Ok(())
 }
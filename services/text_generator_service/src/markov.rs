@@ -0,0 +1,496 @@
+use crate::generation::{GeneratedText, GenerationFailure, GenerationParams, LogLikelihood};
+use log::{debug, warn};
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use shared_models::{
+    GenerationFailureReason, GenerationStopReason, MarkovModelStats, TextEvaluation,
+    current_timestamp_ms,
+};
+use std::collections::{BTreeMap, HashMap};
+use std::mem::size_of;
+
+/// How often each word was seen, in a stable order so sampling only depends on the RNG.
+/// Fractional once decay has scaled older observations down.
+type WordCounts = BTreeMap<String, f32>;
+/// Successors of every context: one to `order` consecutive words joined by single spaces.
+/// Words never contain whitespace, so contexts of different lengths cannot collide, and
+/// first-order models keep plain words as keys.
+type MarkovChainModel = HashMap<String, WordCounts>;
+
+/// Longest context `MARKOV_ORDER` may ask for; longer contexts mostly repeat the corpus.
+pub const MAX_MARKOV_ORDER: usize = 4;
+
+/// At or below this, generation always takes the most frequent successor.
+const GREEDY_TEMPERATURE: f32 = 0.01;
+/// Successor recorded after the last word of a sentence. Words come from
+/// `split_whitespace`, so no real word can be empty.
+const SENTENCE_END: &str = "";
+/// Added to every count when scoring text, so words and transitions the model never saw
+/// get a small probability instead of zero.
+const EVALUATION_SMOOTHING: f64 = 0.1;
+/// Characters the preprocessing service ends sentences at.
+const SENTENCE_TERMINATORS: &[char] = &['.', '?', '!'];
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MarkovModel {
+    pub chain: MarkovChainModel,
+    pub starters: WordCounts,
+    /// Documents that contributed at least one sentence; checkpoints are skipped while it
+    /// is unchanged.
+    pub trained_documents: u64,
+    pub last_trained_ms: Option<u64>,
+}
+
+impl MarkovModel {
+    pub fn new() -> Self {
+        MarkovModel {
+            chain: HashMap::new(),
+            starters: BTreeMap::new(),
+            trained_documents: 0,
+            last_trained_ms: None,
+        }
+    }
+
+    /// Learns the transitions of one sentence from every context of up to `order` words
+    /// within it, including the step to the sentence end; its first word becomes a starter.
+    /// Sentences of fewer than two words are skipped.
+    fn train_sentence(&mut self, sentence: &str, order: usize) -> bool {
+        let words: Vec<&str> = sentence.split_whitespace().collect();
+        if words.len() < 2 {
+            return false;
+        }
+
+        *self.starters.entry(words[0].to_string()).or_default() += 1.0;
+        let successors = words[1..].iter().copied().chain([SENTENCE_END]);
+        for (end, next_word) in successors.enumerate() {
+            for length in 1..=order.min(end + 1) {
+                *self
+                    .chain
+                    .entry(words[end + 1 - length..=end].join(" "))
+                    .or_default()
+                    .entry(next_word.to_string())
+                    .or_default() += 1.0;
+            }
+        }
+        true
+    }
+
+    /// Adds a document's sentences to the model. Returns how many sentences were used.
+    pub fn train(&mut self, sentences: &[String], order: usize) -> usize {
+        let trained = sentences
+            .iter()
+            .filter(|sentence| self.train_sentence(sentence, order))
+            .count();
+        if trained == 0 {
+            return 0;
+        }
+        self.trained_documents += 1;
+        self.last_trained_ms = Some(current_timestamp_ms());
+
+        if self.chain.len() < 20 && !self.chain.is_empty() {
+            debug!(
+                "[MARKOV_TRAIN] Model sample: {:?}",
+                self.chain.iter().take(5).collect::<Vec<_>>()
+            );
+        }
+        if self.starters.len() < 20 && !self.starters.is_empty() {
+            debug!(
+                "[MARKOV_TRAIN] Starters sample: {:?}",
+                self.starters.iter().take(5).collect::<Vec<_>>()
+            );
+        }
+        trained
+    }
+
+    pub fn stats(&self) -> MarkovModelStats {
+        MarkovModelStats {
+            states: self.chain.len() as u64,
+            transitions: self.chain.values().map(|s| s.len() as u64).sum(),
+            starters: self.starters.len() as u64,
+            trained_documents: self.trained_documents,
+            last_trained_ms: self.last_trained_ms,
+            estimated_memory_bytes: self.estimated_memory_bytes(),
+        }
+    }
+
+    /// Counts every word's bytes plus the `String` and map entry it lives in. Allocator and
+    /// tree node overhead are not included, so the real footprint is somewhat larger.
+    pub fn estimated_memory_bytes(&self) -> u64 {
+        let entry = size_of::<String>() + size_of::<f32>();
+        let counts_size =
+            |counts: &WordCounts| counts.keys().map(|word| word.len() + entry).sum::<usize>();
+        let chain_size: usize = self
+            .chain
+            .iter()
+            .map(|(word, successors)| {
+                word.len() + size_of::<String>() + size_of::<WordCounts>() + counts_size(successors)
+            })
+            .sum();
+        (chain_size + counts_size(&self.starters)) as u64
+    }
+
+    /// Scales every count by `factor`, so newer observations outweigh older ones.
+    pub fn decay(&mut self, factor: f32) {
+        for successors in self.chain.values_mut() {
+            successors.values_mut().for_each(|count| *count *= factor);
+        }
+        self.starters
+            .values_mut()
+            .for_each(|count| *count *= factor);
+    }
+
+    /// Drops transitions and starters counted less than `min_count`, and words left without
+    /// any successor. Returns how many transitions were removed.
+    pub fn prune(&mut self, min_count: f32) -> u64 {
+        let mut removed = 0;
+        self.chain.retain(|_, successors| {
+            let before = successors.len();
+            successors.retain(|_, count| *count >= min_count);
+            removed += (before - successors.len()) as u64;
+            !successors.is_empty()
+        });
+        self.starters.retain(|_, count| *count >= min_count);
+        removed
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chain.is_empty() || self.starters.is_empty()
+    }
+
+    /// Scores `text` as the training pipeline would see it: split into sentences, each
+    /// scored from its starter through its transitions to the sentence end, with additive
+    /// smoothing over the model's vocabulary. Each transition is scored from the longest
+    /// context the model knows, as generation would sample it.
+    pub fn evaluate(&self, text: &str) -> Result<TextEvaluation, GenerationFailure> {
+        if self.is_empty() {
+            return Err(GenerationFailure::new(
+                GenerationFailureReason::ModelNotTrained,
+                "the model has no trained transitions or starters",
+            ));
+        }
+
+        // Every known word has successors, plus the sentence end and one slot for unknown
+        // words. Longer contexts inflate this slightly, which only evens out the smoothing.
+        let vocabulary = (self.chain.len() + 2) as f64;
+        let probability = |counts: Option<&WordCounts>, word: &str| {
+            let count = counts.and_then(|counts| counts.get(word)).copied();
+            let total: f32 = counts.map_or(0.0, |counts| counts.values().sum());
+            let log_probability = ((f64::from(count.unwrap_or(0.0)) + EVALUATION_SMOOTHING)
+                / (f64::from(total) + EVALUATION_SMOOTHING * vocabulary))
+                .ln();
+            (log_probability, count.is_some())
+        };
+
+        let mut likelihood = LogLikelihood::default();
+        for sentence in text.split_inclusive(SENTENCE_TERMINATORS) {
+            let words: Vec<&str> = sentence.split_whitespace().collect();
+            let Some(first) = words.first() else {
+                continue;
+            };
+            let (log_probability, seen) = probability(Some(&self.starters), first);
+            likelihood.add(log_probability, seen);
+            let successors = words[1..].iter().copied().chain([SENTENCE_END]);
+            for (end, next_word) in successors.enumerate() {
+                let successors = self
+                    .longest_context(&words[..=end])
+                    .map(|(_, counts)| counts);
+                let (log_probability, seen) = probability(successors, next_word);
+                likelihood.add(log_probability, seen);
+            }
+        }
+        likelihood.evaluation().ok_or_else(|| {
+            GenerationFailure::new(
+                GenerationFailureReason::TooFewWords,
+                "the text has no words",
+            )
+        })
+    }
+
+    /// Successors of the longest context ending the sentence so far that the chain knows,
+    /// with that context's length.
+    fn longest_context(&self, sentence: &[&str]) -> Option<(usize, &WordCounts)> {
+        (1..=sentence.len().min(MAX_MARKOV_ORDER))
+            .rev()
+            .find_map(|length| {
+                let context = sentence[sentence.len() - length..].join(" ");
+                self.chain.get(&context).map(|counts| (length, counts))
+            })
+    }
+
+    /// Samples the next word from the longest known context of the sentence so far, backing
+    /// off to shorter contexts when a longer one yields nothing.
+    fn next_word<R: Rng>(
+        &self,
+        sentence: &[&str],
+        params: &GenerationParams,
+        rng: &mut R,
+    ) -> Option<&str> {
+        let mut sentence = sentence;
+        while let Some((length, successors)) = self.longest_context(sentence) {
+            if let Some(word) = sample(successors, params, rng) {
+                return Some(word);
+            }
+            if length == 1 {
+                break;
+            }
+            sentence = &sentence[sentence.len() - (length - 1)..];
+        }
+        None
+    }
+
+    /// Generates whole sentences until `max_tokens` words or `max_sentences` are reached.
+    /// Each word is drawn from the longest context the chain knows; when even the last word
+    /// alone has no successor, the sentence ends there and a new one starts from a random
+    /// starter, so the requested length is still reached. `temperature` reshapes the word
+    /// frequencies: 1.0 samples them as observed, lower values favour common continuations
+    /// and higher values flatten the distribution towards uniform.
+    pub fn generate(&self, params: &GenerationParams) -> Result<GeneratedText, GenerationFailure> {
+        if self.is_empty() {
+            warn!(
+                "[MARKOV_GENERATE] Model is not trained or has no starters. Cannot generate text."
+            );
+            return Err(GenerationFailure::new(
+                GenerationFailureReason::ModelNotTrained,
+                "the model has no trained transitions or starters",
+            ));
+        }
+
+        let mut rng = StdRng::seed_from_u64(params.seed);
+        let max_tokens = params.max_tokens as usize;
+        let mut words: Vec<&str> = Vec::new();
+        let mut sentences = 0;
+        let mut complete_words = 0;
+
+        let stop_reason = 'sentences: loop {
+            if params.max_sentences.is_some_and(|max| sentences >= max) {
+                break GenerationStopReason::MaxSentences;
+            }
+            if words.len() >= max_tokens {
+                break GenerationStopReason::Length;
+            }
+
+            let Some(starter) = sample(&self.starters, params, &mut rng) else {
+                break GenerationStopReason::DeadEnd;
+            };
+            let sentence_start = words.len();
+            words.push(starter);
+            if let Some(text) = stopped_text(&words, params) {
+                return Ok(GeneratedText::new(text, GenerationStopReason::StopSequence));
+            }
+            loop {
+                match self.next_word(&words[sentence_start..], params, &mut rng) {
+                    Some(SENTENCE_END) => break,
+                    // Nothing ever followed this word (or it was pruned); what was generated
+                    // so far still ends as a sentence and the next one begins.
+                    None => {
+                        debug!(
+                            "[MARKOV_GENERATE] No successor for {:?}; starting a new sentence.",
+                            words.last()
+                        );
+                        break;
+                    }
+                    Some(_) if words.len() >= max_tokens => {
+                        break 'sentences GenerationStopReason::Length;
+                    }
+                    Some(next_word) => {
+                        words.push(next_word);
+                        if let Some(text) = stopped_text(&words, params) {
+                            return Ok(GeneratedText::new(
+                                text,
+                                GenerationStopReason::StopSequence,
+                            ));
+                        }
+                    }
+                }
+            }
+            sentences += 1;
+            complete_words = words.len();
+        };
+
+        // Drop a sentence cut off by the length limit once enough complete ones precede it.
+        if sentences >= params.min_sentences.max(1) {
+            words.truncate(complete_words);
+        }
+        Ok(GeneratedText::new(words.join(" "), stop_reason))
+    }
+}
+
+/// The text so far, cut before a stop sequence once one appears in it.
+fn stopped_text(words: &[&str], params: &GenerationParams) -> Option<String> {
+    if params.stop_sequences.is_empty() {
+        return None;
+    }
+    let text = words.join(" ");
+    let position = params.stop_position(&text)?;
+    Some(text[..position].trim_end().to_string())
+}
+
+/// Draws one of the `top_k` most frequent words with probability proportional to
+/// `count^(1 / temperature)`. Counts are scaled by the largest one first, so small
+/// temperatures cannot overflow the weights.
+fn sample<'a, R: Rng>(
+    counts: &'a WordCounts,
+    params: &GenerationParams,
+    rng: &mut R,
+) -> Option<&'a str> {
+    let mut candidates: Vec<(&str, f32)> = counts
+        .iter()
+        .map(|(word, count)| (word.as_str(), *count))
+        .collect();
+    if let Some(top_k) = params.top_k.filter(|k| *k < candidates.len()) {
+        // Stable, so equally frequent words keep their alphabetical order.
+        candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        candidates.truncate(top_k);
+    }
+
+    if params.temperature <= GREEDY_TEMPERATURE {
+        // On ties the last candidate wins; any fixed choice would do.
+        return candidates
+            .iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(word, _)| *word);
+    }
+
+    let max_count = f64::from(
+        candidates
+            .iter()
+            .map(|(_, count)| *count)
+            .reduce(f32::max)?,
+    );
+    let exponent = 1.0 / f64::from(params.temperature);
+    let weights = candidates
+        .iter()
+        .map(|(_, count)| (f64::from(*count) / max_count).powf(exponent));
+    let index = WeightedIndex::new(weights).ok()?.sample(rng);
+    Some(candidates[index].0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(seed: u64) -> GenerationParams {
+        GenerationParams {
+            max_tokens: 20,
+            temperature: 1.0,
+            top_k: None,
+            stop_sequences: Vec::new(),
+            min_sentences: 1,
+            max_sentences: None,
+            seed,
+        }
+    }
+
+    fn trained(sentences: &[&str], order: usize) -> MarkovModel {
+        let sentences: Vec<String> = sentences.iter().map(|s| s.to_string()).collect();
+        let mut model = MarkovModel::new();
+        model.train(&sentences, order);
+        model
+    }
+
+    #[test]
+    fn test_sampling_follows_the_observed_counts() {
+        let counts: WordCounts = [("common".to_string(), 3.0), ("rare".to_string(), 1.0)].into();
+        let mut rng = StdRng::seed_from_u64(42);
+        let draws = 4000;
+        let common = (0..draws)
+            .filter(|_| sample(&counts, &params(0), &mut rng) == Some("common"))
+            .count();
+        let share = common as f64 / draws as f64;
+        assert!((0.7..0.8).contains(&share), "common drawn {}", share);
+    }
+
+    #[test]
+    fn test_greedy_and_top_k_sampling_take_the_most_frequent_word() {
+        let counts: WordCounts = [
+            ("a".to_string(), 1.0),
+            ("b".to_string(), 5.0),
+            ("c".to_string(), 2.0),
+        ]
+        .into();
+        let mut rng = StdRng::seed_from_u64(7);
+        let greedy = GenerationParams {
+            temperature: 0.0,
+            ..params(0)
+        };
+        let top_one = GenerationParams {
+            top_k: Some(1),
+            ..params(0)
+        };
+        for _ in 0..50 {
+            assert_eq!(sample(&counts, &greedy, &mut rng), Some("b"));
+            assert_eq!(sample(&counts, &top_one, &mut rng), Some("b"));
+        }
+        assert_eq!(sample(&WordCounts::new(), &params(0), &mut rng), None);
+    }
+
+    #[test]
+    fn test_generation_is_reproducible_from_the_seed() {
+        let model = trained(
+            &[
+                "the cat sat on the mat",
+                "the dog sat on the rug",
+                "a cat ran to the dog",
+            ],
+            2,
+        );
+        let first = model.generate(&params(11)).unwrap();
+        let again = model.generate(&params(11)).unwrap();
+        assert_eq!(first.text, again.text);
+        assert!(!first.text.is_empty());
+        let vocabulary = [
+            "the", "cat", "sat", "on", "mat", "dog", "rug", "a", "ran", "to",
+        ];
+        assert!(first.text.split(' ').all(|word| vocabulary.contains(&word)));
+    }
+
+    #[test]
+    fn test_next_word_backs_off_to_shorter_contexts() {
+        let model = trained(&["the cat sat", "my cat ran"], 2);
+        let mut rng = StdRng::seed_from_u64(3);
+        for _ in 0..20 {
+            assert_eq!(
+                model.next_word(&["the", "cat"], &params(0), &mut rng),
+                Some("sat")
+            );
+            assert_eq!(
+                model.next_word(&["my", "cat"], &params(0), &mut rng),
+                Some("ran")
+            );
+        }
+        // "a cat" was never seen, so the successors of "cat" alone are drawn from.
+        let backed_off: Vec<_> = (0..50)
+            .filter_map(|_| model.next_word(&["a", "cat"], &params(0), &mut rng))
+            .collect();
+        assert!(backed_off.contains(&"sat") && backed_off.contains(&"ran"));
+        assert_eq!(
+            model.longest_context(&["a", "cat"]).map(|(len, _)| len),
+            Some(1)
+        );
+        assert_eq!(model.next_word(&["unknown"], &params(0), &mut rng), None);
+    }
+
+    #[test]
+    fn test_prune_drops_rare_transitions_and_words_left_without_successors() {
+        let mut model = trained(&["go home", "go home", "go away", "stay home"], 1);
+        let removed = model.prune(2.0);
+
+        // "go away", "away" -> end and "stay home"; starters are dropped without counting.
+        assert_eq!(removed, 3);
+        assert_eq!(model.chain["go"].keys().collect::<Vec<_>>(), ["home"]);
+        assert!(!model.chain.contains_key("away"));
+        assert!(!model.chain.contains_key("stay"));
+        assert_eq!(model.starters.keys().collect::<Vec<_>>(), ["go"]);
+        for seed in 0..20 {
+            let generated = model.generate(&params(seed)).unwrap();
+            assert!(
+                generated
+                    .text
+                    .split(' ')
+                    .all(|word| word == "go" || word == "home")
+            );
+        }
+    }
+}
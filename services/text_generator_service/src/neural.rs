@@ -0,0 +1,236 @@
+use crate::generation::{GeneratedText, GenerationParams, LogLikelihood};
+use anyhow::Result;
+use candle_core::{D, DType, Device, Tensor};
+use candle_nn::VarBuilder;
+use candle_transformers::generation::{LogitsProcessor, Sampling};
+use candle_transformers::models::llama::{Cache, Config, Llama, LlamaConfig, LlamaEosToks};
+use hf_hub::{Repo, RepoType, api::sync::Api, api::sync::ApiRepo};
+use log::{info, warn};
+use shared_models::{GenerationStopReason, TextEvaluation};
+use std::path::PathBuf;
+use tokenizers::Tokenizer;
+
+pub const DEFAULT_NEURAL_MODEL_ID: &str = "TinyLlama/TinyLlama-1.1B-Chat-v1.0";
+
+/// Characters after which the generated text may be cut off as a complete sentence.
+const SENTENCE_TERMINATORS: &[char] = &['.', '!', '?', '…'];
+
+/// A small Llama-architecture causal language model (e.g. TinyLlama) loaded from the
+/// Hugging Face Hub.
+pub struct NeuralGenerator {
+    model_id: String,
+    model: Llama,
+    config: Config,
+    tokenizer: Tokenizer,
+    device: Device,
+    dtype: DType,
+    eos_token_ids: Vec<u32>,
+}
+
+impl NeuralGenerator {
+    pub fn new(model_id: &str, revision: Option<String>, force_cpu: bool) -> Result<Self> {
+        let device = if force_cpu {
+            Device::Cpu
+        } else {
+            Device::cuda_if_available(0).unwrap_or(Device::Cpu)
+        };
+        let dtype = if device.is_cuda() {
+            DType::F16
+        } else {
+            DType::F32
+        };
+        info!("[NeuralGenerator] Using device: {:?}", device);
+
+        let api = Api::new()?;
+        let revision = revision.unwrap_or_else(|| "main".to_string());
+        let repo = api.repo(Repo::with_revision(
+            model_id.to_string(),
+            RepoType::Model,
+            revision,
+        ));
+
+        info!("[NeuralGenerator] Fetching model files from Hugging Face Hub...");
+        let tokenizer_filename = repo.get("tokenizer.json")?;
+        let config_filename = repo.get("config.json")?;
+        let model_filenames = weight_files(&repo)?;
+        info!(
+            "[NeuralGenerator] Model weights paths: {:?}",
+            model_filenames
+        );
+
+        let llama_config: LlamaConfig =
+            serde_json::from_str(&std::fs::read_to_string(config_filename)?)?;
+        let config = llama_config.into_config(false);
+        let tokenizer = Tokenizer::from_file(tokenizer_filename).map_err(anyhow::Error::msg)?;
+
+        let eos_token_ids = match &config.eos_token_id {
+            Some(LlamaEosToks::Single(id)) => vec![*id],
+            Some(LlamaEosToks::Multiple(ids)) => ids.clone(),
+            None => tokenizer.token_to_id("</s>").into_iter().collect(),
+        };
+
+        let vb = unsafe { VarBuilder::from_mmaped_safetensors(&model_filenames, dtype, &device)? };
+        let model = Llama::load(vb, &config)?;
+        info!("[NeuralGenerator] Loaded model {}", model_id);
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            model,
+            config,
+            tokenizer,
+            device,
+            dtype,
+            eos_token_ids,
+        })
+    }
+
+    pub fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    /// Continues `prompt` by up to `max_tokens` tokens or until a stop sequence, then drops
+    /// a trailing unfinished sentence if at least one complete sentence was produced. Runs
+    /// on the calling thread; call it from a blocking task.
+    pub fn generate(
+        &self,
+        prompt: Option<&str>,
+        params: &GenerationParams,
+    ) -> Result<GeneratedText> {
+        let mut tokens = match prompt.filter(|p| !p.trim().is_empty()) {
+            Some(prompt) => self
+                .tokenizer
+                .encode(prompt, true)
+                .map_err(anyhow::Error::msg)?
+                .get_ids()
+                .to_vec(),
+            None => self.config.bos_token_id.into_iter().collect(),
+        };
+        if tokens.is_empty() {
+            anyhow::bail!("model {} has no BOS token to start from", self.model_id);
+        }
+        let prompt_len = tokens.len();
+        let max_new_tokens = (params.max_tokens as usize).min(
+            self.config
+                .max_position_embeddings
+                .saturating_sub(prompt_len),
+        );
+
+        let mut cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+        let temperature = f64::from(params.temperature);
+        let sampling = match params.top_k {
+            _ if temperature < 1e-7 => Sampling::ArgMax,
+            Some(k) => Sampling::TopK { k, temperature },
+            None => Sampling::All { temperature },
+        };
+        let mut logits_processor = LogitsProcessor::from_sampling(params.seed, sampling);
+        let mut index_pos = 0;
+        let mut stop_reason = GenerationStopReason::Length;
+        for index in 0..max_new_tokens {
+            // The first pass feeds the whole prompt; later passes only the newest token.
+            let context_size = if index > 0 { 1 } else { tokens.len() };
+            let context = &tokens[tokens.len() - context_size..];
+            let input = Tensor::new(context, &self.device)?.unsqueeze(0)?;
+            let logits = self
+                .model
+                .forward(&input, index_pos, &mut cache)?
+                .squeeze(0)?;
+            index_pos += context.len();
+
+            let next_token = logits_processor.sample(&logits)?;
+            if self.eos_token_ids.contains(&next_token) {
+                stop_reason = GenerationStopReason::EndOfText;
+                break;
+            }
+            tokens.push(next_token);
+
+            if !params.stop_sequences.is_empty() {
+                let text = self.decode(&tokens[prompt_len..])?;
+                if let Some(position) = params.stop_position(&text) {
+                    return Ok(GeneratedText::new(
+                        text[..position].trim_end().to_string(),
+                        GenerationStopReason::StopSequence,
+                    ));
+                }
+            }
+        }
+
+        let text = self.decode(&tokens[prompt_len..])?;
+        Ok(GeneratedText::new(
+            trim_to_sentence(&text).to_string(),
+            stop_reason,
+        ))
+    }
+
+    /// Scores `text` by the log-probability of each token given the ones before it. Tokens
+    /// beyond the context window are not scored. Runs on the calling thread; call it from a
+    /// blocking task.
+    pub fn evaluate(&self, text: &str) -> Result<TextEvaluation> {
+        let mut tokens = self
+            .tokenizer
+            .encode(text, true)
+            .map_err(anyhow::Error::msg)?
+            .get_ids()
+            .to_vec();
+        tokens.truncate(self.config.max_position_embeddings);
+
+        let mut cache = Cache::new(true, self.dtype, &self.config, &self.device)?;
+        let mut likelihood = LogLikelihood::default();
+        // The model only returns the logits after the last input token, so the text is fed
+        // one token at a time.
+        for (index_pos, pair) in tokens.windows(2).enumerate() {
+            let input = Tensor::new(&pair[..1], &self.device)?.unsqueeze(0)?;
+            let logits = self
+                .model
+                .forward(&input, index_pos, &mut cache)?
+                .squeeze(0)?
+                .to_dtype(DType::F32)?;
+            let log_probabilities = candle_nn::ops::log_softmax(&logits, D::Minus1)?;
+            let log_probability = log_probabilities
+                .get(pair[1] as usize)?
+                .to_scalar::<f32>()?;
+            likelihood.add(f64::from(log_probability), true);
+        }
+        likelihood
+            .evaluation()
+            .ok_or_else(|| anyhow::anyhow!("the text has fewer than two tokens"))
+    }
+
+    fn decode(&self, tokens: &[u32]) -> Result<String> {
+        self.tokenizer
+            .decode(tokens, true)
+            .map_err(anyhow::Error::msg)
+    }
+}
+
+fn weight_files(repo: &ApiRepo) -> Result<Vec<PathBuf>> {
+    if let Ok(sf_file) = repo.get("model.safetensors") {
+        return Ok(vec![sf_file]);
+    }
+    let sf_index_file = repo.get("model.safetensors.index.json")?;
+    let index_content: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(&sf_index_file)?)?;
+    let weight_map = index_content
+        .get("weight_map")
+        .and_then(|map| map.as_object())
+        .ok_or_else(|| anyhow::anyhow!("No weight_map in safetensors index"))?;
+    let files_to_download: std::collections::BTreeSet<&str> =
+        weight_map.values().filter_map(|f| f.as_str()).collect();
+    files_to_download
+        .into_iter()
+        .map(|f| repo.get(f).map_err(anyhow::Error::from))
+        .collect()
+}
+
+fn trim_to_sentence(text: &str) -> &str {
+    let text = text.trim();
+    match text.rfind(SENTENCE_TERMINATORS) {
+        Some(end) => {
+            let end = end + text[end..].chars().next().map_or(0, char::len_utf8);
+            &text[..end]
+        }
+        None => {
+            warn!("[NeuralGenerator] Output has no complete sentence; returning it as is.");
+            text
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use crate::corpora::{CorpusModels, SharedCorpusModels};
+use log::{error, info, warn};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Written ahead of the models; a snapshot from an incompatible build is ignored on load
+/// instead of being misread.
+const SNAPSHOT_FORMAT_VERSION: u32 = 6;
+
+/// Loads the models saved at `path`, or starts empty when there is no path or no usable
+/// snapshot.
+pub async fn load_or_default(path: Option<&Path>) -> CorpusModels {
+    let Some(path) = path else {
+        return CorpusModels::default();
+    };
+    match load(path).await {
+        Ok(Some(models)) => {
+            info!(
+                "[MODEL_LOAD] Loaded model from {} ({} documents, {} states, {} domain and {} document sub-models).",
+                path.display(),
+                models.global.trained_documents,
+                models.global.chain.len(),
+                models.by_domain.len(),
+                models.by_document.len()
+            );
+            models
+        }
+        Ok(None) => {
+            info!(
+                "[MODEL_LOAD] No saved model at {}. Starting empty.",
+                path.display()
+            );
+            CorpusModels::default()
+        }
+        Err(e) => {
+            error!(
+                "[MODEL_LOAD_FAIL] Failed to load model from {}: {}. Starting empty.",
+                path.display(),
+                e
+            );
+            CorpusModels::default()
+        }
+    }
+}
+
+/// Loads the models saved at `path`. Returns `Ok(None)` when there is no usable snapshot.
+async fn load(path: &Path) -> Result<Option<CorpusModels>, BoxError> {
+    let bytes = match tokio::fs::read(path).await {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let Some((version, model_bytes)) = bytes.split_first_chunk::<4>() else {
+        return Err(format!("snapshot {} is truncated", path.display()).into());
+    };
+    match u32::from_le_bytes(*version) {
+        SNAPSHOT_FORMAT_VERSION => Ok(Some(bincode::deserialize(model_bytes)?)),
+        version => {
+            warn!(
+                "[MODEL_LOAD] Snapshot {} has format version {}, expected {}. Ignoring it.",
+                path.display(),
+                version,
+                SNAPSHOT_FORMAT_VERSION
+            );
+            Ok(None)
+        }
+    }
+}
+
+fn encode(models: &CorpusModels) -> Result<Vec<u8>, BoxError> {
+    let mut bytes = SNAPSHOT_FORMAT_VERSION.to_le_bytes().to_vec();
+    bincode::serialize_into(&mut bytes, models)?;
+    Ok(bytes)
+}
+
+/// Writes the snapshot next to `path` and renames it into place, so a crash mid-write never
+/// leaves a corrupt snapshot behind.
+async fn write_snapshot(bytes: &[u8], path: &Path) -> Result<(), BoxError> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    let tmp_path = tmp_path(path);
+    tokio::fs::write(&tmp_path, bytes).await?;
+    tokio::fs::rename(&tmp_path, path).await?;
+    Ok(())
+}
+
+/// Saves the models if they learned from new documents since `saved_documents`, returning the
+/// document count the snapshot on disk now reflects. Encodes the currently published
+/// snapshot, so training carries on meanwhile.
+pub async fn checkpoint(models: &SharedCorpusModels, path: &Path, saved_documents: u64) -> u64 {
+    let encoded = {
+        let models = models.load_full();
+        if models.global.trained_documents == saved_documents {
+            return saved_documents;
+        }
+        encode(&models).map(|bytes| {
+            (
+                bytes,
+                models.global.trained_documents,
+                models.global.chain.len(),
+            )
+        })
+    };
+    let result = match encoded {
+        Ok((bytes, documents, states)) => write_snapshot(&bytes, path)
+            .await
+            .map(|()| (documents, states)),
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok((documents, states)) => {
+            info!(
+                "[MODEL_CHECKPOINT] Saved model ({} documents, {} states) to {}",
+                documents,
+                states,
+                path.display()
+            );
+            documents
+        }
+        Err(e) => {
+            error!(
+                "[MODEL_CHECKPOINT_FAIL] Failed to save model to {}: {}",
+                path.display(),
+                e
+            );
+            saved_documents
+        }
+    }
+}
+
+/// Periodically checkpoints the models until the service stops.
+pub async fn run_checkpoints(
+    models: Arc<SharedCorpusModels>,
+    path: PathBuf,
+    interval: Duration,
+    mut saved_documents: u64,
+) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        saved_documents = checkpoint(&models, &path, saved_documents).await;
+    }
+}
+
+/// Swaps in the snapshot at `path` whenever the file changes, for replicas that serve
+/// generation without training themselves.
+pub async fn run_reloads(models: Arc<SharedCorpusModels>, path: PathBuf, interval: Duration) {
+    let mut loaded_version = modified(&path).await;
+    let mut ticker = tokio::time::interval(interval);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        let version = modified(&path).await;
+        if version.is_none() || version == loaded_version {
+            continue;
+        }
+        match load(&path).await {
+            Ok(Some(loaded)) => {
+                info!(
+                    "[MODEL_RELOAD] Reloaded model from {} ({} documents, {} states)",
+                    path.display(),
+                    loaded.global.trained_documents,
+                    loaded.global.chain.len()
+                );
+                models.store(Arc::new(loaded));
+                loaded_version = version;
+            }
+            // An incompatible snapshot was already reported by `load`; wait for the next one.
+            Ok(None) => loaded_version = version,
+            Err(e) => {
+                error!(
+                    "[MODEL_RELOAD_FAIL] Failed to reload model from {}: {}",
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+async fn modified(path: &Path) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".tmp");
+    path.with_file_name(file_name)
+}
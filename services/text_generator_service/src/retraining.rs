@@ -0,0 +1,211 @@
+use crate::config::{CorpusConfig, NamedModelConfig, RetrainConfig};
+use crate::corpora::CorpusModels;
+use crate::template;
+use log::{error, info};
+use shared_models::{DocumentId, RequestId, VectorScrollResult, VectorScrollTask};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::{Mutex, mpsc};
+
+const VECTOR_SCROLL_SUBJECT: &str = "tasks.vector.scroll";
+
+/// A document as it was archived in the vector memory, one entry per stored sentence.
+struct StoredDocument {
+    source_url: String,
+    tenant_id: Option<String>,
+    processed_at_ms: u64,
+    sentences: BTreeMap<u32, String>,
+}
+
+pub struct RetrainSummary {
+    pub models: Vec<String>,
+    pub documents: u64,
+    pub sentences: u64,
+}
+
+/// Rebuilds the Markov models from the sentences archived in the vector memory. Rebuilt
+/// models are handed to their training loops, which replace their working copy with them.
+pub struct Retrainer {
+    nats_client: Arc<async_nats::Client>,
+    config: RetrainConfig,
+    corpus_config: CorpusConfig,
+    models: Vec<(NamedModelConfig, mpsc::Sender<CorpusModels>)>,
+    running: Mutex<()>,
+}
+
+impl Retrainer {
+    pub fn new(
+        nats_client: Arc<async_nats::Client>,
+        config: RetrainConfig,
+        corpus_config: CorpusConfig,
+        models: Vec<(NamedModelConfig, mpsc::Sender<CorpusModels>)>,
+    ) -> Self {
+        Retrainer {
+            nats_client,
+            config,
+            corpus_config,
+            models,
+            running: Mutex::new(()),
+        }
+    }
+
+    /// Rebuilds the named models, or all of them when `model_names` is `None`. The stored
+    /// corpus is read once and replayed oldest document first, so decay favours recent
+    /// documents as it does during live training. Only one rebuild runs at a time.
+    pub async fn retrain(&self, model_names: Option<&[String]>) -> Result<RetrainSummary, String> {
+        if let Some(unknown) = model_names
+            .unwrap_or_default()
+            .iter()
+            .find(|name| !self.models.iter().any(|(config, _)| &config.name == *name))
+        {
+            return Err(format!("no model named '{}'", unknown));
+        }
+        let targets: Vec<_> = self
+            .models
+            .iter()
+            .filter(|(config, _)| model_names.is_none_or(|names| names.contains(&config.name)))
+            .cloned()
+            .collect();
+        let Ok(_running) = self.running.try_lock() else {
+            return Err("a retraining is already in progress".to_string());
+        };
+
+        let started = Instant::now();
+        let documents = Arc::new(self.read_stored_corpus().await?);
+        if documents.is_empty() {
+            // Replacing the models with empty ones would only discard live training.
+            return Err("the vector memory holds no stored sentences".to_string());
+        }
+        let sentences = documents
+            .iter()
+            .map(|document| document.1.sentences.len() as u64)
+            .sum();
+        info!(
+            "[MARKOV_RETRAIN] Read {} documents ({} sentences) from the vector memory in {:?}.",
+            documents.len(),
+            sentences,
+            started.elapsed()
+        );
+
+        let mut models = Vec::with_capacity(targets.len());
+        for (model_config, sender) in targets {
+            let documents = Arc::clone(&documents);
+            let corpus_config = self.corpus_config.clone();
+            let rebuild_config = model_config.clone();
+            let rebuilt = tokio::task::spawn_blocking(move || {
+                rebuild(&documents, &rebuild_config, &corpus_config)
+            })
+            .await
+            .map_err(|e| format!("rebuild of model '{}' panicked: {}", model_config.name, e))?;
+            info!(
+                "[MARKOV_RETRAIN] Rebuilt model '{}' from {} stored documents.",
+                model_config.name, rebuilt.global.trained_documents
+            );
+            sender.send(rebuilt).await.map_err(|_| {
+                format!("training loop of model '{}' has stopped", model_config.name)
+            })?;
+            models.push(model_config.name);
+        }
+
+        info!(
+            "[MARKOV_RETRAIN] Retrained models {:?} in {:?}.",
+            models,
+            started.elapsed()
+        );
+        Ok(RetrainSummary {
+            models,
+            documents: documents.len() as u64,
+            sentences,
+        })
+    }
+
+    /// Pages through every point of the vector memory, ordered by processing time.
+    async fn read_stored_corpus(&self) -> Result<Vec<(DocumentId, StoredDocument)>, String> {
+        let mut documents: HashMap<DocumentId, StoredDocument> = HashMap::new();
+        let mut offset = None;
+        loop {
+            let task = VectorScrollTask {
+                request_id: RequestId::generate(),
+                model_name: self.config.vector_model.clone(),
+                original_document_id: None,
+                source_url: None,
+                limit: self.config.page_size,
+                offset,
+                tenant_id: None,
+            };
+            let page: VectorScrollResult = template::request(
+                &self.nats_client,
+                None,
+                VECTOR_SCROLL_SUBJECT,
+                &task,
+                self.config.request_timeout,
+            )
+            .await?;
+            if let Some(e) = page.error_message {
+                return Err(format!("vector scroll failed: {}", e));
+            }
+
+            for point in page.points {
+                let payload = point.payload;
+                let document = documents
+                    .entry(payload.original_document_id)
+                    .or_insert_with(|| StoredDocument {
+                        source_url: payload.source_url,
+                        tenant_id: payload.tenant_id,
+                        processed_at_ms: payload.processed_at_ms,
+                        sentences: BTreeMap::new(),
+                    });
+                document.processed_at_ms = document.processed_at_ms.max(payload.processed_at_ms);
+                // Points of several embedding models share a sentence order; keep one.
+                document
+                    .sentences
+                    .insert(payload.sentence_order, payload.sentence_text);
+            }
+
+            match page.next_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        let mut documents: Vec<_> = documents.into_iter().collect();
+        documents.sort_by(|a, b| (a.1.processed_at_ms, &a.0).cmp(&(b.1.processed_at_ms, &b.0)));
+        Ok(documents)
+    }
+}
+
+fn rebuild(
+    documents: &[(DocumentId, StoredDocument)],
+    model_config: &NamedModelConfig,
+    corpus_config: &CorpusConfig,
+) -> CorpusModels {
+    let mut models = CorpusModels::default();
+    for (original_id, document) in documents {
+        if !model_config.accepts(document.tenant_id.as_deref(), &document.source_url) {
+            continue;
+        }
+        let sentences: Vec<String> = document.sentences.values().cloned().collect();
+        models.train_document(
+            corpus_config,
+            *original_id,
+            &document.source_url,
+            &sentences,
+        );
+    }
+    models
+}
+
+/// Rebuilds all models every `interval`; the first rebuild happens one interval after
+/// startup.
+pub async fn run_scheduled(retrainer: Arc<Retrainer>, interval: std::time::Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        if let Err(e) = retrainer.retrain(None).await {
+            error!("[MARKOV_RETRAIN_FAIL] Scheduled retraining failed: {}", e);
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::generation::GenerationFailure;
+use log::{debug, info};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use shared_models::{
+    DocumentId, Envelope, GenerationFailureReason, GraphTermKind, GraphTermsResult, GraphTermsTask,
+    KeywordSearchResult, KeywordSearchTask, RequestId,
+};
+use shared_nats::request_guarded;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const GRAPH_TERMS_SUBJECT: &str = "tasks.graph.terms";
+const KEYWORD_SEARCH_SUBJECT: &str = "tasks.graph.search.keyword";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Slot {
+    Entity,
+    Keyword,
+    SentenceAbout(String),
+}
+
+#[derive(Debug)]
+enum Segment {
+    Text(String),
+    Slot(Slot),
+}
+
+/// Splits a template into literal text and slots. Braces that do not form a known slot
+/// are kept as they are.
+fn parse(template: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    let mut text = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        let after_brace = &rest[start + 1..];
+        let Some(end) = after_brace.find('}') else {
+            text.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let inner = after_brace[..end].trim();
+        let slot = match inner {
+            "entity" => Some(Slot::Entity),
+            "keyword" => Some(Slot::Keyword),
+            _ => inner
+                .strip_prefix("sentence_about:")
+                .map(str::trim)
+                .filter(|topic| !topic.is_empty())
+                .map(|topic| Slot::SentenceAbout(topic.to_string())),
+        };
+        match slot {
+            Some(slot) => {
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(Segment::Slot(slot));
+            }
+            None => text.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &after_brace[end + 1..];
+    }
+    text.push_str(rest);
+    if !text.is_empty() {
+        segments.push(Segment::Text(text));
+    }
+    segments
+}
+
+/// Fills the template's slots from the knowledge graph, scoped to `original_id` when set.
+/// Repeated slots take successive results, so `{keyword}, {keyword}` names two different
+/// terms. Fails when the graph has fewer results than a slot kind is used.
+pub async fn render(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    template: &str,
+    original_id: Option<DocumentId>,
+    timeout: Duration,
+) -> Result<String, GenerationFailure> {
+    let segments = parse(template);
+    let mut slot_counts: HashMap<&Slot, usize> = HashMap::new();
+    for segment in &segments {
+        if let Segment::Slot(slot) = segment {
+            *slot_counts.entry(slot).or_default() += 1;
+        }
+    }
+
+    let mut fillings: HashMap<&Slot, std::vec::IntoIter<String>> = HashMap::new();
+    for (slot, count) in slot_counts {
+        let values = fetch(nats_client, cause, slot, count, original_id, timeout)
+            .await
+            .map_err(|e| unfilled(format!("{:?}: {}", slot, e)))?;
+        if values.len() < count {
+            return Err(unfilled(format!(
+                "{:?} is used {} times but the knowledge graph returned {} results",
+                slot,
+                count,
+                values.len()
+            )));
+        }
+        debug!("[TEMPLATE] {:?} filled with {:?}", slot, values);
+        fillings.insert(slot, values.into_iter());
+    }
+
+    let mut output = String::new();
+    for segment in &segments {
+        match segment {
+            Segment::Text(text) => output.push_str(text),
+            Segment::Slot(slot) => {
+                if let Some(value) = fillings.get_mut(slot).and_then(Iterator::next) {
+                    output.push_str(&value);
+                }
+            }
+        }
+    }
+    info!(
+        "[TEMPLATE] Rendered template with {} segments (original_id: {:?}).",
+        segments.len(),
+        original_id
+    );
+    Ok(output)
+}
+
+async fn fetch(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    slot: &Slot,
+    count: usize,
+    original_id: Option<DocumentId>,
+    timeout: Duration,
+) -> Result<Vec<String>, String> {
+    match slot {
+        Slot::Entity | Slot::Keyword => {
+            let kind = if *slot == Slot::Entity {
+                GraphTermKind::Entity
+            } else {
+                GraphTermKind::Keyword
+            };
+            let task = GraphTermsTask {
+                request_id: RequestId::generate(),
+                original_id,
+                kind,
+                top_k: count as u32,
+            };
+            let result: GraphTermsResult = request(
+                nats_client,
+                Some(cause),
+                GRAPH_TERMS_SUBJECT,
+                &task,
+                timeout,
+            )
+            .await?;
+            match result.error_message {
+                Some(e) => Err(e),
+                None => Ok(result.terms.into_iter().map(|term| term.text).collect()),
+            }
+        }
+        Slot::SentenceAbout(topic) => {
+            let task = KeywordSearchTask {
+                request_id: RequestId::generate(),
+                query_text: topic.clone(),
+                top_k: count as u32,
+                original_id,
+            };
+            let result: KeywordSearchResult = request(
+                nats_client,
+                Some(cause),
+                KEYWORD_SEARCH_SUBJECT,
+                &task,
+                timeout,
+            )
+            .await?;
+            match result.error_message {
+                Some(e) => Err(e),
+                None => Ok(result
+                    .results
+                    .into_iter()
+                    .map(|item| item.sentence_text)
+                    .collect()),
+            }
+        }
+    }
+}
+
+/// Sends `task` to a request/reply subject, as a follow-up of `cause` when given.
+pub async fn request<T: Serialize, R: DeserializeOwned>(
+    nats_client: &async_nats::Client,
+    cause: Option<&Envelope<()>>,
+    subject: &str,
+    task: &T,
+    timeout: Duration,
+) -> Result<R, String> {
+    let payload = Envelope::following(cause, crate::SERVICE_NAME, task)
+        .to_vec()
+        .map_err(|e| format!("serialize failed: {}", e))?;
+    let reply = tokio::time::timeout(timeout, request_guarded(nats_client, subject, payload))
+        .await
+        .map_err(|_| format!("{} did not reply within {:?}", subject, timeout))?
+        .map_err(|e| format!("request to {} failed: {}", subject, e))?;
+    Envelope::from_slice(&reply.payload)
+        .map(|envelope| envelope.payload)
+        .map_err(|e| format!("invalid reply from {}: {}", subject, e))
+}
+
+fn unfilled(detail: String) -> GenerationFailure {
+    GenerationFailure::new(GenerationFailureReason::TemplateSlotUnfilled, detail)
+}
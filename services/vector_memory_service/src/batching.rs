@@ -0,0 +1,60 @@
+/// Splits `items` (each paired with its estimated encoded size in bytes) into consecutive
+/// batches holding at most `max_items` items and at most `max_bytes` bytes. An item larger
+/// than `max_bytes` on its own still gets a batch of its own rather than being dropped.
+pub fn split_into_batches<T>(
+    items: Vec<(T, usize)>,
+    max_items: usize,
+    max_bytes: usize,
+) -> Vec<Vec<T>> {
+    let mut batches: Vec<Vec<T>> = Vec::new();
+    let mut current: Vec<T> = Vec::new();
+    let mut current_bytes = 0usize;
+
+    for (item, size) in items {
+        let exceeds_items = current.len() >= max_items;
+        let exceeds_bytes = !current.is_empty() && current_bytes + size > max_bytes;
+        if exceeds_items || exceeds_bytes {
+            batches.push(std::mem::take(&mut current));
+            current_bytes = 0;
+        }
+        current.push(item);
+        current_bytes += size;
+    }
+
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batches_hold_at_most_max_items() {
+        let items = (0..5).map(|i| (i, 1)).collect();
+        assert_eq!(
+            split_into_batches(items, 2, 1_000),
+            vec![vec![0, 1], vec![2, 3], vec![4]]
+        );
+    }
+
+    #[test]
+    fn test_batches_hold_at_most_max_bytes() {
+        let items = vec![("a", 40), ("b", 40), ("c", 30), ("d", 50)];
+        assert_eq!(
+            split_into_batches(items, 10, 100),
+            vec![vec!["a", "b"], vec!["c", "d"]]
+        );
+    }
+
+    #[test]
+    fn test_item_larger_than_max_bytes_gets_its_own_batch() {
+        let items = vec![("small", 10), ("huge", 500), ("after", 10)];
+        assert_eq!(
+            split_into_batches(items, 10, 100),
+            vec![vec!["small"], vec!["huge"], vec!["after"]]
+        );
+    }
+}
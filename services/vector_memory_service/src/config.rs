@@ -0,0 +1,263 @@
+use log::{info, warn};
+use qdrant_client::qdrant::{
+    BinaryQuantizationBuilder, CompressionRatio, Distance, ProductQuantizationBuilder,
+    QuantizationConfig, QuantizationType, ReadConsistencyType, ScalarQuantizationBuilder,
+    SearchParams, SearchParamsBuilder, quantization_config, read_consistency,
+};
+use shared_config::QdrantSettings;
+pub use shared_config::{env_flag_or, env_parse_or, env_var};
+use shared_models::{ReadConsistency, ReadConsistencyLevel};
+use shared_resilience::RetryPolicy;
+use std::time::Duration;
+
+const DEFAULT_VECTOR_DIM: u64 = 768;
+const DEFAULT_SCALAR_QUANTILE: f32 = 0.99;
+const DEFAULT_UPSERT_BATCH_SIZE: usize = 256;
+/// Stays under the 4 MiB default gRPC message limit with room for request framing.
+const DEFAULT_UPSERT_MAX_BATCH_BYTES: usize = 3 * 1024 * 1024;
+const DEFAULT_WRITE_RETRY: RetryPolicy =
+    RetryPolicy::new(3, Duration::from_millis(500), Duration::from_secs(10));
+
+/// Storage settings applied when vector_memory_service creates a Qdrant collection.
+#[derive(Debug, Clone)]
+pub struct CollectionConfig {
+    pub collection_prefix: String,
+    pub default_vector_dim: u64,
+    pub distance: Distance,
+    pub vectors_on_disk: bool,
+    pub payload_on_disk: bool,
+    pub quantization: Option<QuantizationSettings>,
+    /// Every point carries a `tenant_id` and every read is scoped to one tenant
+    /// (`QDRANT_MULTI_TENANCY`). Messages and tasks without a tenant are rejected.
+    pub multi_tenancy: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum QuantizationMode {
+    /// int8 scalar quantization; `quantile` clips outliers before scaling.
+    Scalar {
+        quantile: f32,
+    },
+    Product {
+        compression: CompressionRatio,
+    },
+    Binary,
+}
+
+/// Vector quantization applied to newly created collections (`QDRANT_QUANTIZATION`).
+#[derive(Debug, Clone, Copy)]
+pub struct QuantizationSettings {
+    pub mode: QuantizationMode,
+    /// Keep quantized vectors in RAM even when the original vectors are on disk.
+    pub always_ram: bool,
+}
+
+impl QuantizationSettings {
+    fn from_env() -> Option<Self> {
+        let raw_mode = env_var("QDRANT_QUANTIZATION")?;
+        let mode = match raw_mode.trim().to_lowercase().as_str() {
+            "" | "none" | "off" => return None,
+            "scalar" => QuantizationMode::Scalar {
+                quantile: env_parse_or("QDRANT_SCALAR_QUANTILE", DEFAULT_SCALAR_QUANTILE),
+            },
+            "product" => QuantizationMode::Product {
+                compression: env_var("QDRANT_PRODUCT_COMPRESSION")
+                    .map(|v| {
+                        parse_compression_ratio(&v).unwrap_or_else(|| {
+                            warn!(
+                                "[CONFIG] Unknown QDRANT_PRODUCT_COMPRESSION '{}', falling back to x16",
+                                v
+                            );
+                            CompressionRatio::X16
+                        })
+                    })
+                    .unwrap_or(CompressionRatio::X16),
+            },
+            "binary" => QuantizationMode::Binary,
+            _ => {
+                warn!(
+                    "[CONFIG] Unknown QDRANT_QUANTIZATION '{}', quantization disabled",
+                    raw_mode
+                );
+                return None;
+            }
+        };
+
+        Some(QuantizationSettings {
+            mode,
+            always_ram: env_flag_or("QDRANT_QUANTIZATION_ALWAYS_RAM", true),
+        })
+    }
+
+    pub fn to_qdrant_config(self) -> QuantizationConfig {
+        let quantization = match self.mode {
+            QuantizationMode::Scalar { quantile } => quantization_config::Quantization::from(
+                ScalarQuantizationBuilder::default()
+                    .r#type(QuantizationType::Int8.into())
+                    .quantile(quantile)
+                    .always_ram(self.always_ram),
+            ),
+            QuantizationMode::Product { compression } => quantization_config::Quantization::from(
+                ProductQuantizationBuilder::new(compression.into()).always_ram(self.always_ram),
+            ),
+            QuantizationMode::Binary => quantization_config::Quantization::from(
+                BinaryQuantizationBuilder::new(self.always_ram),
+            ),
+        };
+        QuantizationConfig::from(quantization)
+    }
+}
+
+impl CollectionConfig {
+    pub fn from_env(qdrant: &QdrantSettings) -> Self {
+        let config = CollectionConfig {
+            collection_prefix: qdrant.collection_prefix.clone(),
+            default_vector_dim: env_parse_or("QDRANT_VECTOR_DIM", DEFAULT_VECTOR_DIM),
+            distance: env_var("QDRANT_DISTANCE")
+                .map(|v| {
+                    parse_distance(&v).unwrap_or_else(|| {
+                        warn!(
+                            "[CONFIG] Unknown QDRANT_DISTANCE '{}', falling back to Cosine",
+                            v
+                        );
+                        Distance::Cosine
+                    })
+                })
+                .unwrap_or(Distance::Cosine),
+            vectors_on_disk: env_flag_or("QDRANT_VECTORS_ON_DISK", true),
+            payload_on_disk: env_flag_or("QDRANT_PAYLOAD_ON_DISK", true),
+            quantization: QuantizationSettings::from_env(),
+            multi_tenancy: env_flag_or("QDRANT_MULTI_TENANCY", false),
+        };
+
+        info!("[CONFIG] Qdrant collection config: {:?}", config);
+        config
+    }
+}
+
+/// Limits for splitting a document's points into several upsert requests,
+/// and how failed Qdrant writes are retried before the message is dead-lettered.
+#[derive(Debug, Clone, Copy)]
+pub struct UpsertConfig {
+    pub batch_size: usize,
+    pub max_batch_bytes: usize,
+    pub retry: RetryPolicy,
+}
+
+impl UpsertConfig {
+    pub fn from_env() -> Self {
+        let config = UpsertConfig {
+            batch_size: env_parse_or("QDRANT_UPSERT_BATCH_SIZE", DEFAULT_UPSERT_BATCH_SIZE).max(1),
+            max_batch_bytes: env_parse_or(
+                "QDRANT_UPSERT_MAX_BATCH_BYTES",
+                DEFAULT_UPSERT_MAX_BATCH_BYTES,
+            )
+            .max(1),
+            retry: RetryPolicy::from_env("QDRANT_WRITE", DEFAULT_WRITE_RETRY),
+        };
+
+        info!("[CONFIG] Qdrant upsert config: {:?}", config);
+        config
+    }
+}
+
+/// Latency/recall settings applied to Qdrant searches. Configured per deployment
+/// (`QDRANT_SEARCH_READ_CONSISTENCY`, `QDRANT_SEARCH_TIMEOUT_SECS`, `QDRANT_SEARCH_HNSW_EF`);
+/// unset values keep Qdrant's defaults. Semantic search tasks may override each of them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SearchSettings {
+    pub read_consistency: Option<ReadConsistency>,
+    pub timeout_secs: Option<u64>,
+    pub hnsw_ef: Option<u64>,
+}
+
+impl SearchSettings {
+    pub fn from_env() -> Self {
+        let settings = SearchSettings {
+            read_consistency: env_var("QDRANT_SEARCH_READ_CONSISTENCY")
+                .filter(|v| !v.trim().is_empty())
+                .and_then(|v| {
+                    let parsed = parse_read_consistency(&v);
+                    if parsed.is_none() {
+                        warn!(
+                            "[CONFIG] Unknown QDRANT_SEARCH_READ_CONSISTENCY '{}', using Qdrant's default",
+                            v
+                        );
+                    }
+                    parsed
+                }),
+            timeout_secs: Some(env_parse_or("QDRANT_SEARCH_TIMEOUT_SECS", 0u64)).filter(|v| *v > 0),
+            hnsw_ef: Some(env_parse_or("QDRANT_SEARCH_HNSW_EF", 0u64)).filter(|v| *v > 0),
+        };
+
+        info!("[CONFIG] Qdrant search settings: {:?}", settings);
+        settings
+    }
+
+    /// Per-request values take precedence over the configured ones.
+    pub fn with_overrides(
+        self,
+        read_consistency: Option<ReadConsistency>,
+        timeout_secs: Option<u64>,
+        hnsw_ef: Option<u64>,
+    ) -> Self {
+        SearchSettings {
+            read_consistency: read_consistency.or(self.read_consistency),
+            timeout_secs: timeout_secs.filter(|t| *t > 0).or(self.timeout_secs),
+            hnsw_ef: hnsw_ef.filter(|ef| *ef > 0).or(self.hnsw_ef),
+        }
+    }
+
+    pub fn qdrant_read_consistency(&self) -> Option<read_consistency::Value> {
+        self.read_consistency.map(|consistency| match consistency {
+            ReadConsistency::Factor(factor) => read_consistency::Value::Factor(factor),
+            ReadConsistency::Level(level) => read_consistency::Value::Type(
+                match level {
+                    ReadConsistencyLevel::All => ReadConsistencyType::All,
+                    ReadConsistencyLevel::Majority => ReadConsistencyType::Majority,
+                    ReadConsistencyLevel::Quorum => ReadConsistencyType::Quorum,
+                }
+                .into(),
+            ),
+        })
+    }
+
+    pub fn search_params(&self) -> Option<SearchParams> {
+        self.hnsw_ef
+            .map(|ef| SearchParamsBuilder::default().hnsw_ef(ef).build())
+    }
+}
+
+fn parse_read_consistency(value: &str) -> Option<ReadConsistency> {
+    match value.trim().to_lowercase().as_str() {
+        "all" => Some(ReadConsistency::Level(ReadConsistencyLevel::All)),
+        "majority" => Some(ReadConsistency::Level(ReadConsistencyLevel::Majority)),
+        "quorum" => Some(ReadConsistency::Level(ReadConsistencyLevel::Quorum)),
+        other => other
+            .parse::<u64>()
+            .ok()
+            .filter(|factor| *factor > 0)
+            .map(ReadConsistency::Factor),
+    }
+}
+
+fn parse_distance(value: &str) -> Option<Distance> {
+    match value.trim().to_lowercase().as_str() {
+        "cosine" => Some(Distance::Cosine),
+        "euclid" | "euclidean" => Some(Distance::Euclid),
+        "dot" => Some(Distance::Dot),
+        "manhattan" => Some(Distance::Manhattan),
+        _ => None,
+    }
+}
+
+fn parse_compression_ratio(value: &str) -> Option<CompressionRatio> {
+    match value.trim().to_lowercase().as_str() {
+        "x4" => Some(CompressionRatio::X4),
+        "x8" => Some(CompressionRatio::X8),
+        "x16" => Some(CompressionRatio::X16),
+        "x32" => Some(CompressionRatio::X32),
+        "x64" => Some(CompressionRatio::X64),
+        _ => None,
+    }
+}
@@ -1,45 +1,434 @@
+mod batching;
+mod config;
+mod metrics;
+mod payload;
+mod reembed;
+mod reindex_lock;
+mod retention;
+mod stats;
 use anyhow::{Context, Result};
 use async_nats::Message;
+use async_nats::jetstream;
+use batching::split_into_batches;
+use config::{CollectionConfig, SearchSettings, UpsertConfig, env_parse_or};
 use futures::StreamExt;
 use log::{error, info, warn};
-use qdrant_client::Qdrant;
+use payload::{
+    group_id_to_string, insert_chunk_fields, insert_document_metadata, payload_integer,
+    payload_string, point_id_from_str, point_id_to_string, qdrant_payload_from_map,
+};
 use qdrant_client::qdrant::{
-    CreateCollection, Distance, PointId as QdrantPointId, PointStruct, SearchPoints, UpsertPoints,
-    Value, VectorParams, VectorsConfig, WithPayloadSelector, WithVectorsSelector,
+    Condition, CountPointsBuilder, CreateAliasBuilder, CreateCollection,
+    CreateFieldIndexCollectionBuilder, DeletePointsBuilder, Direction, FacetCountsBuilder,
+    FieldType, Filter, Fusion, KeywordIndexParamsBuilder, Modifier, NamedVectors, OrderByBuilder,
+    PayloadIncludeSelector, PointGroup, PointId, PointStruct, PrefetchQuery, PrefetchQueryBuilder,
+    Query, QueryPointGroupsBuilder, QueryPointsBuilder, Range, RecommendInputBuilder,
+    RecommendStrategy, ScoredPoint, ScrollPointsBuilder, SearchBatchPointsBuilder, SearchParams,
+    SearchPointGroupsBuilder, SearchPoints, SearchPointsBuilder, SetPayloadPointsBuilder,
+    SparseVectorParamsBuilder, SparseVectorsConfigBuilder, UpsertPointsBuilder, Value, Vector,
+    VectorInput, VectorParams, VectorParamsMap, VectorsConfig, WithPayloadSelector,
+    WithVectorsSelector, facet_value, start_from, vectors_config,
 };
+use qdrant_client::{Qdrant, QdrantError};
+use reembed::{reembed_message_key, reembed_tasks};
+use reindex_lock::acquire_reindex_lock;
+use retention::RetentionPolicy;
+use shared_config::Settings;
 use shared_models::{
-    QdrantPointPayload, SemanticSearchNatsResult, SemanticSearchNatsTask, SemanticSearchResultItem,
-    TextWithEmbeddingsMessage,
+    CONTENT_ENCODING_HEADER, CONTENT_TYPE_HEADER, DeadLetterMessage, DependencyCheck, DocumentId,
+    EmbeddingDimensionMismatch, EmbeddingsRejectedEvent, Envelope, PipelineErrorKind,
+    PipelineErrorMessage, PipelineStage, RecommendNatsTask, RequestId, SearchFilters,
+    SearchOptions, SemanticSearchNatsBatchResult, SemanticSearchNatsBatchTask,
+    SemanticSearchNatsResult, SemanticSearchNatsTask, SemanticSearchResultGroup,
+    SemanticSearchResultItem, SparseVector, StoredPointItem, TASK_STATUS_EVENT_SUBJECT, TaskStatus,
+    TaskStatusChangedMessage, TextWithEmbeddingsMessage, Timestamp, UndecodedPayload, Validate,
+    VectorCountGroup, VectorCountResult, VectorCountTask, VectorPayloadUpdateResult,
+    VectorPayloadUpdateTask, VectorReindexResult, VectorReindexTask, VectorScrollResult,
+    VectorScrollTask, VectorSnapshotInfo, VectorSnapshotResult, VectorSnapshotTask,
+    VectorStatsResult, VectorStatsTask, current_timestamp_ms, decode_body, sentence_point_id,
+};
+use shared_nats::{
+    ConsumerConfig, DEAD_LETTERS_STREAM, EMBEDDINGS_STREAM, OverflowPolicy, REEMBED_TASKS_STREAM,
+    RecentMessages, Shutdown, WorkerPool, dead_letter_undecodable, durable_messages,
+    insert_message_id, publish_dead_letter, publish_durable, publish_reply, queue_group_from_env,
+    receive_span, serve_health, subscribe_shared, traced_headers,
 };
+use shared_resilience::{BreakerError, CircuitBreaker, RetryPolicy, retry_with_backoff};
+use stats::collection_stats_from_info;
 use std::collections::HashMap;
-use std::time::Duration;
-use std::{env, sync::Arc};
-use uuid::Uuid;
+use std::future::Future;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::Instrument;
 
 const TEXT_WITH_EMBEDDINGS_SUBJECT: &str = "data.text.with_embeddings";
-const QDRANT_COLLECTION_NAME: &str = "symbiont_document_embeddings";
 const SEMANTIC_SEARCH_TASK_SUBJECT: &str = "tasks.search.semantic.request";
-const QDRANT_VECTOR_DIM: u64 = 768;
+const VECTOR_SCROLL_TASK_SUBJECT: &str = "tasks.vector.scroll";
+const VECTOR_PAYLOAD_UPDATE_TASK_SUBJECT: &str = "tasks.vector.update_payload";
+const VECTOR_COUNT_TASK_SUBJECT: &str = "tasks.vector.count";
+const RECOMMEND_TASK_SUBJECT: &str = "tasks.search.recommend.request";
+const SEMANTIC_SEARCH_BATCH_TASK_SUBJECT: &str = "tasks.search.semantic.batch.request";
+const VECTOR_STATS_TASK_SUBJECT: &str = "tasks.vector.stats";
+const VECTOR_SNAPSHOT_CONTROL_SUBJECT: &str = "control.vector.snapshot";
+const VECTOR_REINDEX_CONTROL_SUBJECT: &str = "control.vector.reindex";
+const VECTOR_REINDEX_EVENT_SUBJECT: &str = "events.vector.reindex";
+const REEMBED_TEXT_TASK_SUBJECT: &str = "tasks.embedding.reembed";
+const EMBEDDINGS_REJECTED_EVENT_SUBJECT: &str = "events.vector.embeddings_rejected";
+const SERVICE_NAME: &str = env!("CARGO_PKG_NAME");
+const DEFAULT_EMBEDDING_MODEL: &str = "sentence-transformers/paraphrase-multilingual-mpnet-base-v2";
+const MAX_SCROLL_LIMIT: u32 = 1000;
+const MAX_BATCH_QUERIES: usize = 64;
+/// Page size used when scanning candidate points for per-source retention rules.
+const RETENTION_SCAN_PAGE_SIZE: u32 = 1000;
+/// Upper bound on the sentences of a document used as positive examples for a recommendation.
+const MAX_RECOMMEND_DOCUMENT_EXAMPLES: u32 = 64;
+/// Named vectors stored on each point: every representation of a sentence lives on the same
+/// point, so further ones (e.g. a document-level vector) are added as more named vectors
+/// rather than parallel collections.
+const DENSE_VECTOR_NAME: &str = "dense";
+/// Sparse (lexical) vector used by hybrid search.
+const SPARSE_VECTOR_NAME: &str = "sparse";
+/// Candidates fetched from each of the dense and sparse branches before RRF fusion, as a multiple of top_k.
+const HYBRID_PREFETCH_MULTIPLIER: u64 = 4;
+const GROUP_BY_FIELD: &str = "original_document_id";
+const DEFAULT_HITS_PER_DOCUMENT: u32 = 3;
+const MAX_HITS_PER_DOCUMENT: u32 = 20;
+/// Payload field partitioning points by tenant; indexed as a Qdrant tenant index
+/// when multi-tenancy is enabled.
+const TENANT_FIELD: &str = "tenant_id";
+/// Payload fields that payload updates may not overwrite: they are tied to the stored
+/// vector (sentence text, order, model) or to access control (tenant).
+const PROTECTED_PAYLOAD_FIELDS: &[&str] = &[
+    "original_document_id",
+    "sentence_text",
+    "sentence_order",
+    "model_name",
+    TENANT_FIELD,
+];
+/// Fields a count may be broken down by. Facet counts need a keyword payload index,
+/// which both get from [`PAYLOAD_INDEXED_FIELDS`].
+const COUNT_GROUP_FIELDS: &[&str] = &["source_url", "original_document_id"];
+const DEFAULT_COUNT_GROUP_LIMIT: u32 = 100;
+const MAX_COUNT_GROUP_LIMIT: u32 = 1000;
+/// Points read per scroll page while requesting re-embedding during a reindex.
+const REINDEX_SCAN_PAGE_SIZE: u32 = 256;
+const REINDEX_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_REINDEX_TIMEOUT_SECS: u64 = 6 * 3600;
+/// Rough per-point protobuf overhead (ids, field tags, payload keys) used for batch sizing.
+const POINT_OVERHEAD_BYTES: usize = 256;
+/// Creating the Qdrant client at startup: five attempts, five seconds apart.
+const DEFAULT_CONNECT_RETRY: RetryPolicy =
+    RetryPolicy::new(4, Duration::from_secs(5), Duration::from_secs(5));
+/// Embeddings messages stored at once unless `WORKERS_EMBEDDINGS_CONCURRENCY` says otherwise.
+const DEFAULT_STORAGE_WORKERS: usize = 16;
+/// Search and admin requests handled at once unless `WORKERS_VECTOR_REQUESTS_CONCURRENCY`
+/// says otherwise.
+const DEFAULT_REQUEST_WORKERS: usize = 64;
+
+/// Payload fields used in filters (per-document lookups, deletes, source/time ranges)
+/// that get a Qdrant payload index on every collection.
+const PAYLOAD_INDEXED_FIELDS: &[(&str, FieldType)] = &[
+    ("original_document_id", FieldType::Keyword),
+    ("source_url", FieldType::Keyword),
+    ("model_name", FieldType::Keyword),
+    ("language", FieldType::Keyword),
+    ("processed_at_ms", FieldType::Integer),
+    // Lets a document's sentences be scrolled in order, see `handle_vector_scroll_task`.
+    ("sentence_order", FieldType::Integer),
+];
+
+/// Builds the Qdrant collection name for an embedding model, e.g.
+/// `sentence-transformers/all-MiniLM-L6-v2` -> `symbiont_document_embeddings__sentence_transformers_all_minilm_l6_v2`.
+fn collection_name_for_model(collection_prefix: &str, model_name: &str) -> String {
+    let sanitized: String = model_name
+        .trim()
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}__{}", collection_prefix, sanitized)
+}
+
+/// Vector configuration of an existing collection that writes and queries depend on.
+#[derive(Debug, Clone, Copy)]
+struct CollectionLayout {
+    /// Name of the dense vector: [`DENSE_VECTOR_NAME`], or `None` for collections created
+    /// before named vectors, which store it as the unnamed default vector.
+    dense_vector_name: Option<&'static str>,
+    /// Size of the dense vector, when Qdrant reported it.
+    vector_dim: Option<u64>,
+    /// Whether the sparse vector is configured (collections created before hybrid
+    /// search was introduced only have the dense vector).
+    sparse_enabled: bool,
+}
+
+/// Keeps track of the per-model collections this instance has already ensured,
+/// so a collection is only checked/created once per model.
+struct CollectionRegistry {
+    client: Arc<Qdrant>,
+    config: CollectionConfig,
+    known_collections: Mutex<HashMap<String, CollectionLayout>>,
+}
+
+impl CollectionRegistry {
+    fn new(client: Arc<Qdrant>, config: CollectionConfig) -> Self {
+        CollectionRegistry {
+            client,
+            config,
+            known_collections: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn collection_name(&self, model_name: &str) -> String {
+        collection_name_for_model(&self.config.collection_prefix, model_name)
+    }
+
+    /// Qdrant alias serving requests that do not name a model. It points at the default
+    /// model's collection until a reindex switches it. Model collection names never contain
+    /// `-`, so the alias cannot clash with one.
+    fn active_alias(&self) -> String {
+        format!("{}-active", self.config.collection_prefix)
+    }
+
+    /// Collection (or the active alias) that reads for an optional model name go to.
+    fn read_collection(&self, model_name: Option<&str>) -> String {
+        match model_name {
+            Some(model_name) => self.collection_name(model_name),
+            None => self.active_alias(),
+        }
+    }
+
+    /// Collection the active alias currently points at, if the alias exists.
+    async fn active_collection(&self) -> Result<Option<String>> {
+        let alias_name = self.active_alias();
+        let aliases = self
+            .client
+            .list_aliases()
+            .await
+            .with_context(|| "Failed to list Qdrant aliases")?;
+
+        Ok(aliases
+            .aliases
+            .into_iter()
+            .find(|alias| alias.alias_name == alias_name)
+            .map(|alias| alias.collection_name))
+    }
+
+    /// Creates the active alias for `collection_name` unless it already exists.
+    async fn ensure_active_alias(&self, collection_name: &str) -> Result<()> {
+        if let Some(current) = self.active_collection().await? {
+            info!(
+                "[QDRANT_ALIAS] Alias '{}' points at collection '{}'.",
+                self.active_alias(),
+                current
+            );
+            return Ok(());
+        }
+        self.switch_active_alias(collection_name).await
+    }
+
+    /// Points the active alias at `collection_name`. Creating an alias that already exists
+    /// re-points it in one operation, so reads never see the alias missing.
+    async fn switch_active_alias(&self, collection_name: &str) -> Result<()> {
+        let alias_name = self.active_alias();
+        self.client
+            .create_alias(CreateAliasBuilder::new(
+                collection_name,
+                alias_name.as_str(),
+            ))
+            .await
+            .with_context(|| {
+                format!(
+                    "Failed to point alias '{}' at collection '{}'",
+                    alias_name, collection_name
+                )
+            })?;
+        self.known_collections.lock().await.remove(&alias_name);
+
+        info!(
+            "[QDRANT_ALIAS] Alias '{}' now points at collection '{}'.",
+            alias_name, collection_name
+        );
+        Ok(())
+    }
+
+    /// Rejects requests without a tenant when multi-tenancy is enabled, so a missing
+    /// tenant can never widen a read to every tenant's points.
+    fn require_tenant(&self, tenant_id: Option<&str>) -> Result<()> {
+        if self.config.multi_tenancy && tenant_id.is_none_or(|t| t.trim().is_empty()) {
+            return Err(anyhow::anyhow!(
+                "tenant_id is required when multi-tenancy is enabled"
+            ));
+        }
+        Ok(())
+    }
+
+    /// Scopes a message or request to the tenant of its envelope, writing it into the
+    /// payload's `tenant_id` so stored payloads and filters use it, then applies
+    /// [`Self::require_tenant`]. A payload naming another tenant is rejected.
+    fn scope_tenant(&self, cause: &Envelope<()>, tenant_id: &mut Option<String>) -> Result<()> {
+        *tenant_id = cause
+            .scoped_tenant(tenant_id.as_deref())?
+            .map(str::to_string);
+        self.require_tenant(tenant_id.as_deref())
+    }
+
+    async fn ensure_for_model(&self, model_name: &str, vector_dim: u64) -> Result<String> {
+        let collection_name = self.collection_name(model_name);
+
+        let mut known = self.known_collections.lock().await;
+        if known.contains_key(&collection_name) {
+            return Ok(collection_name);
+        }
+
+        let layout = ensure_qdrant_collection(
+            Arc::clone(&self.client),
+            &collection_name,
+            vector_dim,
+            &self.config,
+        )
+        .await
+        .with_context(|| {
+            format!(
+                "Failed to ensure collection '{}' for model '{}'",
+                collection_name, model_name
+            )
+        })?;
+        known.insert(collection_name.clone(), layout);
+
+        Ok(collection_name)
+    }
+
+    /// Names of all Qdrant collections created by this service, for any model.
+    async fn managed_collections(&self) -> Result<Vec<String>> {
+        let name_prefix = format!("{}__", self.config.collection_prefix);
+        let collections = self
+            .client
+            .list_collections()
+            .await
+            .with_context(|| "Failed to list Qdrant collections")?;
+
+        Ok(collections
+            .collections
+            .into_iter()
+            .map(|collection| collection.name)
+            .filter(|name| name.starts_with(&name_prefix))
+            .collect())
+    }
+
+    /// Layout of `collection_name`. Looked up in Qdrant (and cached) when the collection
+    /// has not been ensured by this instance yet; unknown when the lookup fails.
+    async fn layout(&self, collection_name: &str) -> CollectionLayout {
+        if let Some(layout) = self.known_collections.lock().await.get(collection_name) {
+            return *layout;
+        }
+
+        match inspect_collection_layout(&self.client, collection_name).await {
+            Ok(layout) => {
+                self.known_collections
+                    .lock()
+                    .await
+                    .insert(collection_name.to_string(), layout);
+                layout
+            }
+            Err(e) => {
+                warn!(
+                    "[QDRANT_SETUP] Could not inspect collection '{}' for its vector layout: {}",
+                    collection_name, e
+                );
+                CollectionLayout {
+                    dense_vector_name: Some(DENSE_VECTOR_NAME),
+                    vector_dim: None,
+                    sparse_enabled: false,
+                }
+            }
+        }
+    }
+}
+
+async fn inspect_collection_layout(
+    client: &Qdrant,
+    collection_name: &str,
+) -> Result<CollectionLayout> {
+    let info = client
+        .collection_info(collection_name)
+        .await
+        .with_context(|| format!("Failed to get info for collection '{}'", collection_name))?;
+
+    let params = info
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.params);
+    let (dense_vector_name, vector_dim) = match params
+        .as_ref()
+        .and_then(|params| params.vectors_config.as_ref())
+        .and_then(|vectors_config| vectors_config.config.as_ref())
+    {
+        Some(vectors_config::Config::Params(vector_params)) => (None, Some(vector_params.size)),
+        Some(vectors_config::Config::ParamsMap(params_map)) => (
+            Some(DENSE_VECTOR_NAME),
+            params_map
+                .map
+                .get(DENSE_VECTOR_NAME)
+                .map(|vector_params| vector_params.size),
+        ),
+        None => (Some(DENSE_VECTOR_NAME), None),
+    };
+    let sparse_enabled = params
+        .and_then(|params| params.sparse_vectors_config)
+        .is_some_and(|sparse| sparse.map.contains_key(SPARSE_VECTOR_NAME));
+
+    Ok(CollectionLayout {
+        dense_vector_name,
+        vector_dim,
+        sparse_enabled,
+    })
+}
 
 async fn create_new_qdrant_collection(
     client: Arc<Qdrant>,
     collection_name: &str,
     vector_dim: u64,
+    config: &CollectionConfig,
 ) -> Result<()> {
     info!(
-        "[QDRANT_CREATE] Attempting to create new collection '{}' with vector size {}...",
-        collection_name, vector_dim
+        "[QDRANT_CREATE] Attempting to create new collection '{}' with vector size {} (distance: {:?}, vectors_on_disk: {}, payload_on_disk: {}, quantization: {:?})...",
+        collection_name,
+        vector_dim,
+        config.distance,
+        config.vectors_on_disk,
+        config.payload_on_disk,
+        config.quantization
     );
 
-    let vectors_config = Some(VectorsConfig::from(VectorParams {
-        size: vector_dim,
-        distance: Distance::Cosine.into(),
-        hnsw_config: None,
-        quantization_config: None,
-        on_disk: Some(true),
-        multivector_config: None,
-        datatype: None,
-    }));
+    let vectors_config = Some(VectorsConfig {
+        config: Some(vectors_config::Config::ParamsMap(VectorParamsMap {
+            map: HashMap::from([(
+                DENSE_VECTOR_NAME.to_string(),
+                VectorParams {
+                    size: vector_dim,
+                    distance: config.distance.into(),
+                    hnsw_config: None,
+                    quantization_config: None,
+                    on_disk: Some(config.vectors_on_disk),
+                    multivector_config: None,
+                    datatype: None,
+                },
+            )]),
+        })),
+    });
+
+    let mut sparse_vectors_config = SparseVectorsConfigBuilder::default();
+    sparse_vectors_config.add_named_vector_params(
+        SPARSE_VECTOR_NAME,
+        SparseVectorParamsBuilder::default().modifier(Modifier::Idf),
+    );
 
     let create_collection_request = CreateCollection {
         collection_name: collection_name.to_string(),
@@ -49,13 +438,15 @@ async fn create_new_qdrant_collection(
         wal_config: None,
         optimizers_config: None,
         shard_number: None,
-        on_disk_payload: Some(true),
+        on_disk_payload: Some(config.payload_on_disk),
         replication_factor: None,
         write_consistency_factor: None,
         init_from_collection: None,
-        quantization_config: None,
+        quantization_config: config
+            .quantization
+            .map(|quantization| quantization.to_qdrant_config()),
         sharding_method: None,
-        sparse_vectors_config: None,
+        sparse_vectors_config: Some(sparse_vectors_config.into()),
 
         strict_mode_config: None,
         timeout: None,
@@ -79,11 +470,60 @@ async fn create_new_qdrant_collection(
     Ok(())
 }
 
+async fn ensure_payload_indexes(client: Arc<Qdrant>, collection_name: &str, multi_tenancy: bool) {
+    for (field_name, field_type) in PAYLOAD_INDEXED_FIELDS {
+        let request =
+            CreateFieldIndexCollectionBuilder::new(collection_name, *field_name, *field_type)
+                .wait(true);
+
+        match client.create_field_index(request).await {
+            Ok(_) => {
+                info!(
+                    "[QDRANT_INDEX] Payload index on '{}' ({:?}) ensured for collection '{}'.",
+                    field_name, field_type, collection_name
+                );
+            }
+            Err(e) => {
+                warn!(
+                    "[QDRANT_INDEX_FAIL] Failed to create payload index on '{}' for collection '{}': {}. Filters on this field will fall back to full scans.",
+                    field_name, collection_name, e
+                );
+            }
+        }
+    }
+
+    let mut tenant_request =
+        CreateFieldIndexCollectionBuilder::new(collection_name, TENANT_FIELD, FieldType::Keyword)
+            .wait(true);
+    if multi_tenancy {
+        // Lets Qdrant co-locate each tenant's points and build per-tenant HNSW subgraphs.
+        tenant_request =
+            tenant_request.field_index_params(KeywordIndexParamsBuilder::default().is_tenant(true));
+    }
+    match client.create_field_index(tenant_request).await {
+        Ok(_) => {
+            info!(
+                "[QDRANT_INDEX] Payload index on '{}' (tenant: {}) ensured for collection '{}'.",
+                TENANT_FIELD, multi_tenancy, collection_name
+            );
+        }
+        Err(e) => {
+            warn!(
+                "[QDRANT_INDEX_FAIL] Failed to create payload index on '{}' for collection '{}': {}. Tenant filters will fall back to full scans.",
+                TENANT_FIELD, collection_name, e
+            );
+        }
+    }
+}
+
+/// Creates the collection if it is missing and ensures its payload indexes.
+/// Returns the collection's vector layout.
 async fn ensure_qdrant_collection(
     client: Arc<Qdrant>,
     collection_name: &str,
     vector_dim: u64,
-) -> Result<()> {
+    config: &CollectionConfig,
+) -> Result<CollectionLayout> {
     info!(
         "[QDRANT_SETUP] Checking if collection '{}' exists...",
         collection_name
@@ -99,29 +539,205 @@ async fn ensure_qdrant_collection(
         .iter()
         .any(|collection| collection.name == collection_name);
 
-    if collection_exists {
+    let layout = if collection_exists {
         info!(
             "[QDRANT_SETUP] Collection '{}' already exists, skipping creation.",
             collection_name
         );
+        let layout = inspect_collection_layout(&client, collection_name).await?;
+        if layout.dense_vector_name.is_none() {
+            warn!(
+                "[QDRANT_SETUP] Collection '{}' stores its dense vector unnamed (created before named vectors); it is read and written in that layout.",
+                collection_name
+            );
+        }
+        if !layout.sparse_enabled {
+            warn!(
+                "[QDRANT_SETUP] Collection '{}' has no '{}' sparse vector; hybrid search is disabled for it.",
+                collection_name, SPARSE_VECTOR_NAME
+            );
+        }
+        layout
     } else {
         info!(
             "[QDRANT_SETUP] Collection '{}' does not exist, creating...",
             collection_name
         );
 
-        create_new_qdrant_collection(client, collection_name, vector_dim)
+        create_new_qdrant_collection(Arc::clone(&client), collection_name, vector_dim, config)
             .await
             .with_context(|| format!("Failed to create collection '{}'", collection_name))?;
+        CollectionLayout {
+            dense_vector_name: Some(DENSE_VECTOR_NAME),
+            vector_dim: Some(vector_dim),
+            sparse_enabled: true,
+        }
+    };
+
+    ensure_payload_indexes(client, collection_name, config.multi_tenancy).await;
+
+    Ok(layout)
+}
+
+/// Reports a message that could not be stored after retries and dead-letters it for later
+/// replay.
+async fn dead_letter_embeddings(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    msg: TextWithEmbeddingsMessage,
+    error_message: String,
+    attempts: u32,
+) {
+    let original_id = msg.original_id;
+    publish_pipeline_error(
+        nats_client,
+        Some(cause),
+        PipelineErrorMessage::new(
+            PipelineStage::Storage,
+            PipelineErrorKind::Storage,
+            error_message.clone(),
+        )
+        .with_original_id(original_id)
+        .with_attempts(attempts),
+    )
+    .await;
+    let dead_letter =
+        DeadLetterMessage::new(TEXT_WITH_EMBEDDINGS_SUBJECT, msg, error_message, attempts);
+    publish_dead_letter(
+        &jetstream::new(nats_client.clone()),
+        SERVICE_NAME,
+        Some(cause),
+        &dead_letter,
+    )
+    .await;
+}
+
+/// Reports a message this service gave up on to [`PipelineStage::Storage`]'s error subject.
+async fn publish_pipeline_error(
+    nats_client: &async_nats::Client,
+    cause: Option<&Envelope<()>>,
+    pipeline_error: PipelineErrorMessage,
+) {
+    let subject = pipeline_error.stage.error_subject();
+    match Envelope::following(cause, SERVICE_NAME, &pipeline_error).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish(subject.clone(), payload_json.into())
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish PipelineErrorMessage to {}: {}",
+                    subject, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize PipelineErrorMessage: {}",
+                e
+            );
+        }
     }
+}
 
-    Ok(())
+async fn publish_task_status(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    status: TaskStatusChangedMessage,
+) {
+    match cause.follow_up(SERVICE_NAME, &status).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish_with_headers(
+                    TASK_STATUS_EVENT_SUBJECT,
+                    traced_headers(),
+                    payload_json.into(),
+                )
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish {:?} status of task {}: {}",
+                    status.status, status.task_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize TaskStatusChangedMessage: {}",
+                e
+            );
+        }
+    }
+}
+
+async fn publish_embeddings_rejected(
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    event: &EmbeddingsRejectedEvent,
+) {
+    match cause.follow_up(SERVICE_NAME, event).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish(EMBEDDINGS_REJECTED_EVENT_SUBJECT, payload_json.into())
+                .await
+            {
+                error!(
+                    "[EVENT_PUBLISH_FAIL] Failed to publish rejection event for original_id {}: {}",
+                    event.original_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[EVENT_SERIALIZE_FAIL] Failed to serialize rejection event for original_id {}: {}",
+                event.original_id, e
+            );
+        }
+    }
+}
+
+/// Refuses Qdrant calls for a while once too many fail, configured by the
+/// `BREAKER_QDRANT_*` variables. Collection setup and health checks bypass it.
+static QDRANT_BREAKER: LazyLock<CircuitBreaker> =
+    LazyLock::new(|| CircuitBreaker::from_env("qdrant"));
+
+/// Runs a Qdrant call through [`QDRANT_BREAKER`].
+async fn qdrant_call<T>(
+    call: impl Future<Output = Result<T, QdrantError>>,
+) -> Result<T, BreakerError<QdrantError>> {
+    QDRANT_BREAKER.call_with(is_qdrant_outage, call).await
+}
+
+/// Whether a Qdrant call is worth retrying: Qdrant was down, the breaker refused it or a
+/// fault was injected into it. A refused request would fail the same way again.
+fn is_retryable_qdrant_error(e: &BreakerError<QdrantError>) -> bool {
+    match e {
+        BreakerError::Open(_) | BreakerError::Injected(_) => true,
+        BreakerError::Failed(e) => is_qdrant_outage(e),
+    }
+}
+
+/// Whether `e` shows Qdrant unreachable or failing, rather than refusing a request.
+fn is_qdrant_outage(e: &QdrantError) -> bool {
+    match e {
+        // Cancelled, Unknown, DeadlineExceeded, Internal and Unavailable.
+        QdrantError::ResponseError { status } => {
+            matches!(i32::from(status.code()), 1 | 2 | 4 | 13 | 14)
+        }
+        QdrantError::ResourceExhaustedError { .. } | QdrantError::Io(_) => true,
+        _ => false,
+    }
 }
 
 async fn handle_text_with_embeddings_message(
-    msg: TextWithEmbeddingsMessage,
+    mut msg: TextWithEmbeddingsMessage,
+    cause: Envelope<()>,
     qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client: Arc<async_nats::Client>,
+    upsert_config: UpsertConfig,
 ) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("storage");
     info!(
         "[QDRANT_HANDLER] Received TextWithEmbeddingsMessage (original_id: {}), {} embeddings from model '{}'.",
         msg.original_id,
@@ -137,13 +753,116 @@ async fn handle_text_with_embeddings_message(
         return Ok(());
     }
 
-    let mut points_to_upsert: Vec<PointStruct> = Vec::with_capacity(msg.embeddings_data.len());
+    if let Err(e) = collections.scope_tenant(&cause, &mut msg.tenant_id) {
+        let err_msg = format!("Rejected original_id {}: {}", msg.original_id, e);
+        error!("[QDRANT_HANDLER_ERROR] {}", err_msg);
+        dead_letter_embeddings(&nats_client, &cause, msg, err_msg.clone(), 0).await;
+        return Err(anyhow::anyhow!(err_msg));
+    }
+
+    let vector_dim = msg.embeddings_data[0].embedding.len() as u64;
+    let (ensure_result, ensure_attempts) = retry_with_backoff(
+        &upsert_config.retry,
+        &format!("Ensuring collection for model '{}'", msg.model_name),
+        || collections.ensure_for_model(&msg.model_name, vector_dim),
+        |_| true,
+    )
+    .await;
+    let collection_name = match ensure_result {
+        Ok(name) => name,
+        Err(e) => {
+            metrics::qdrant_error("ensure_collection");
+            let err_msg = format!("{:#}", e);
+            error!(
+                "[QDRANT_HANDLER_ERROR] Giving up on original_id {} after {} attempts: {}",
+                msg.original_id, ensure_attempts, err_msg
+            );
+            let original_id = msg.original_id;
+            dead_letter_embeddings(&nats_client, &cause, msg, err_msg, ensure_attempts).await;
+            return Err(e.context(format!(
+                "Failed to store embeddings for original_id {}",
+                original_id
+            )));
+        }
+    };
+    let layout = collections.layout(&collection_name).await;
+
+    // Qdrant rejects the whole upsert request when one vector has the wrong size, with an
+    // error that does not say which sentence caused it, so mismatches are filtered out here.
+    let mismatches: Vec<EmbeddingDimensionMismatch> = match layout.vector_dim {
+        Some(expected_dim) => msg
+            .embeddings_data
+            .iter()
+            .enumerate()
+            .filter(|(_, sentence_embedding)| {
+                sentence_embedding.embedding.len() as u64 != expected_dim
+            })
+            .map(|(index, sentence_embedding)| EmbeddingDimensionMismatch {
+                sentence_index: index as u32,
+                dimension: sentence_embedding.embedding.len() as u64,
+            })
+            .collect(),
+        None => Vec::new(),
+    };
+    if let Some(expected_dim) = layout.vector_dim.filter(|_| !mismatches.is_empty()) {
+        let stored_count = (msg.embeddings_data.len() - mismatches.len()) as u32;
+        let err_msg = format!(
+            "{} of {} embedding(s) for original_id {} from model '{}' do not match dimension {} of collection '{}' (got {:?})",
+            mismatches.len(),
+            msg.embeddings_data.len(),
+            msg.original_id,
+            msg.model_name,
+            expected_dim,
+            collection_name,
+            mismatches.iter().map(|m| m.dimension).collect::<Vec<_>>()
+        );
+        error!("[QDRANT_HANDLER_DIM_MISMATCH] {}", err_msg);
+
+        publish_embeddings_rejected(
+            &nats_client,
+            &cause,
+            &EmbeddingsRejectedEvent {
+                original_id: msg.original_id,
+                source_url: msg.source_url.clone(),
+                model_name: msg.model_name.clone(),
+                collection_name: collection_name.clone(),
+                expected_dimension: expected_dim,
+                mismatches: mismatches.clone(),
+                stored_count,
+                error_message: err_msg.clone(),
+                timestamp_ms: current_timestamp_ms(),
+            },
+        )
+        .await;
+
+        // Nothing is left to store, so the whole message is dead-lettered like any other
+        // the handler gives up on; the consumer acks it either way.
+        if stored_count == 0 {
+            dead_letter_embeddings(&nats_client, &cause, msg, err_msg.clone(), 0).await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    }
+
+    // Every point repeats the document's metadata; its JSON size is close enough.
+    let metadata_bytes = serde_json::to_vec(&msg.metadata).map_or(0, |json| json.len());
+    let mut points_to_upsert: Vec<((usize, PointStruct), usize)> =
+        Vec::with_capacity(msg.embeddings_data.len());
 
     for (index, sentence_embedding) in msg.embeddings_data.iter().enumerate() {
+        if mismatches
+            .iter()
+            .any(|m| m.sentence_index as usize == index)
+        {
+            continue;
+        }
+        let sentence_order = sentence_embedding
+            .sentence_order
+            .map_or(index as i64, i64::from);
+
         let mut payload: HashMap<String, Value> = HashMap::new();
         payload.insert(
             "original_document_id".to_string(),
-            Value::from(msg.original_id.clone()),
+            Value::from(msg.original_id.to_string()),
         );
         payload.insert(
             "source_url".to_string(),
@@ -153,7 +872,7 @@ async fn handle_text_with_embeddings_message(
             "sentence_text".to_string(),
             Value::from(sentence_embedding.sentence_text.clone()),
         );
-        payload.insert("sentence_order".to_string(), Value::from(index as i64));
+        payload.insert("sentence_order".to_string(), Value::from(sentence_order));
         payload.insert(
             "model_name".to_string(),
             Value::from(msg.model_name.clone()),
@@ -162,107 +881,215 @@ async fn handle_text_with_embeddings_message(
             "processed_at_ms".to_string(),
             Value::from(msg.timestamp_ms as i64),
         );
+        if let Some(tenant_id) = &msg.tenant_id {
+            payload.insert(TENANT_FIELD.to_string(), Value::from(tenant_id.clone()));
+        }
+        insert_document_metadata(&mut payload, &msg.metadata);
+        insert_chunk_fields(
+            &mut payload,
+            msg.chunk_index,
+            msg.total_chunks,
+            msg.parent_document_id,
+        );
+
+        // Deterministic, so re-ingesting or replaying a document overwrites its points and the
+        // knowledge graph can reference them.
+        let point_id = qdrant_client::qdrant::PointId::from(sentence_point_id(
+            msg.original_id,
+            sentence_embedding.sentence_order.unwrap_or(index as u32),
+        ));
 
-        let point_id = qdrant_client::qdrant::PointId::from(Uuid::new_v4().to_string());
+        let mut estimated_bytes = POINT_OVERHEAD_BYTES
+            + sentence_embedding.embedding.len() * size_of::<f32>()
+            + sentence_embedding.sentence_text.len()
+            + msg.original_id.to_string().len()
+            + msg.source_url.len()
+            + msg.model_name.len()
+            + msg.tenant_id.as_ref().map_or(0, String::len)
+            + metadata_bytes;
+
+        // "" addresses the unnamed dense vector of collections created before named vectors.
+        let mut vectors = NamedVectors::default().add_vector(
+            layout.dense_vector_name.unwrap_or(""),
+            Vector::new_dense(sentence_embedding.embedding.clone()),
+        );
+        if let Some(sparse) = sentence_embedding
+            .sparse_embedding
+            .as_ref()
+            .filter(|sparse| layout.sparse_enabled && !sparse.is_empty())
+        {
+            estimated_bytes += sparse.indices.len() * (size_of::<u32>() + size_of::<f32>());
+            vectors = vectors.add_vector(
+                SPARSE_VECTOR_NAME,
+                Vector::new_sparse(sparse.indices.clone(), sparse.values.clone()),
+            );
+        }
 
         let point = PointStruct {
             id: Some(point_id),
             payload,
-            vectors: Some(qdrant_client::qdrant::Vectors::from(
-                sentence_embedding.embedding.clone(),
-            )),
+            vectors: Some(vectors.into()),
         };
 
-        points_to_upsert.push(point);
+        points_to_upsert.push(((index, point), estimated_bytes));
     }
 
-    if points_to_upsert.is_empty() {
-        warn!(
-            "[QDRANT_HANDLER] No points to upsert for original_id: {}. This shouldn't happen if embeddings_data was not empty.",
-            msg.original_id
-        );
-        return Ok(());
-    }
+    let total_points = points_to_upsert.len();
+    let batches = split_into_batches(
+        points_to_upsert,
+        upsert_config.batch_size,
+        upsert_config.max_batch_bytes,
+    );
+    let total_batches = batches.len();
 
     info!(
-        "[QDRANT_HANDLER] Upserting {} points to Qdrant collection '{}' for original_id: {}...",
-        points_to_upsert.len(),
-        QDRANT_COLLECTION_NAME,
-        msg.original_id
+        "[QDRANT_HANDLER] Upserting {} points in {} batch(es) to Qdrant collection '{}' for original_id: {}...",
+        total_points, total_batches, collection_name, msg.original_id
     );
 
-    let upsert_request = UpsertPoints {
-        collection_name: QDRANT_COLLECTION_NAME.to_string(),
-        wait: Some(true),
-        points: points_to_upsert,
-        ordering: None,
-        shard_key_selector: None,
-    };
+    let mut failed_sentence_indices: Vec<usize> = Vec::new();
+    let mut failed_batch_errors: Vec<String> = Vec::new();
+    let mut max_attempts = 0u32;
 
-    match qdrant_client.upsert_points(upsert_request).await {
-        Ok(response) => {
-            if response.result.map_or(false, |op_info| {
-                op_info.status == qdrant_client::qdrant::UpdateStatus::Completed as i32
-            }) {
-                info!(
-                    "[QDRANT_HANDLER] Successfully upserted points for original_id: {}. Qdrant op time: {}s",
-                    msg.original_id, response.time
-                );
-            } else {
-                warn!(
-                    "[QDRANT_HANDLER] Qdrant upsert operation for original_id: {} completed but status was not 'Completed'. Response: {:?}",
-                    msg.original_id, response
+    for (batch_index, batch) in batches.into_iter().enumerate() {
+        let (sentence_indices, points): (Vec<usize>, Vec<PointStruct>) = batch.into_iter().unzip();
+        let batch_len = points.len();
+
+        let (upsert_result, attempts) = retry_with_backoff(
+            &upsert_config.retry,
+            &format!(
+                "Upsert of batch {}/{} for original_id {}",
+                batch_index + 1,
+                total_batches,
+                msg.original_id
+            ),
+            || async {
+                let started = Instant::now();
+                let result = qdrant_call(qdrant_client.upsert_points(
+                    UpsertPointsBuilder::new(collection_name.clone(), points.clone()).wait(true),
+                ))
+                .await;
+                metrics::observe_upsert(started.elapsed());
+                if result.is_err() {
+                    metrics::qdrant_error("upsert");
+                }
+                result
+            },
+            is_retryable_qdrant_error,
+        )
+        .await;
+        max_attempts = max_attempts.max(attempts);
+
+        match upsert_result {
+            Ok(response) => {
+                metrics::add_points_written(batch_len);
+                if response.result.is_some_and(|op_info| {
+                    op_info.status == qdrant_client::qdrant::UpdateStatus::Completed as i32
+                }) {
+                    info!(
+                        "[QDRANT_HANDLER] Upserted batch {}/{} ({} points) for original_id: {}. Qdrant op time: {}s",
+                        batch_index + 1,
+                        total_batches,
+                        batch_len,
+                        msg.original_id,
+                        response.time
+                    );
+                } else {
+                    warn!(
+                        "[QDRANT_HANDLER] Qdrant upsert of batch {}/{} for original_id: {} completed but status was not 'Completed'. Response: {:?}",
+                        batch_index + 1,
+                        total_batches,
+                        msg.original_id,
+                        response
+                    );
+                }
+            }
+            Err(e) => {
+                error!(
+                    "[QDRANT_HANDLER_ERROR] Failed to upsert batch {}/{} ({} points) to Qdrant for original_id {} after {} attempts: {}",
+                    batch_index + 1,
+                    total_batches,
+                    batch_len,
+                    msg.original_id,
+                    attempts,
+                    e
                 );
+                failed_batch_errors.push(format!("batch {}: {}", batch_index + 1, e));
+                failed_sentence_indices.extend(sentence_indices);
             }
         }
-        Err(e) => {
-            error!(
-                "[QDRANT_HANDLER_ERROR] Failed to upsert points to Qdrant for original_id {}: {}",
-                msg.original_id, e
-            );
-            return Err(e.into());
-        }
     }
 
-    Ok(())
-}
+    if !failed_sentence_indices.is_empty() {
+        error!(
+            "[QDRANT_HANDLER_PARTIAL_FAIL] Stored {}/{} points for original_id {}; {} of {} batch(es) failed.",
+            total_points - failed_sentence_indices.len(),
+            total_points,
+            msg.original_id,
+            failed_batch_errors.len(),
+            total_batches
+        );
 
-async fn handle_semantic_search_task(
-    nats_msg: Message,
-    qdrant_client: Arc<Qdrant>,
-    nats_client_for_reply: Arc<async_nats::Client>,
-) -> Result<()> {
-    let task: SemanticSearchNatsTask = match serde_json::from_slice(&nats_msg.payload) {
-        Ok(t) => t,
-        Err(e) => {
-            let err_msg = format!("Failed to deserialize SemanticSearchNatsTask: {}", e);
-            error!("[SEARCH_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
-            if let Some(reply_to) = &nats_msg.reply {
-                let error_result = SemanticSearchNatsResult {
-                    request_id: "unknown".to_string(),
-                    results: vec![],
-                    error_message: Some(err_msg.clone()),
-                };
-                if let Ok(payload_json) = serde_json::to_vec(&error_result) {
-                    let _ = nats_client_for_reply
-                        .publish(reply_to.clone(), payload_json.into())
-                        .await;
-                }
-            }
-            return Err(anyhow::anyhow!(err_msg));
-        }
-    };
+        let err_msg = format!(
+            "Failed to upsert {} of {} batch(es) for original_id {}: {}",
+            failed_batch_errors.len(),
+            total_batches,
+            msg.original_id,
+            failed_batch_errors.join("; ")
+        );
+
+        // Only the failed sentences are dead-lettered, with their original order pinned,
+        // so replaying the dead letter does not duplicate the batches that were stored.
+        let failed_embeddings = failed_sentence_indices
+            .into_iter()
+            .map(|index| {
+                let mut sentence_embedding = msg.embeddings_data[index].clone();
+                sentence_embedding.sentence_order =
+                    Some(sentence_embedding.sentence_order.unwrap_or(index as u32));
+                sentence_embedding
+            })
+            .collect();
+        let failed_msg = TextWithEmbeddingsMessage {
+            embeddings_data: failed_embeddings,
+            ..msg
+        };
+        dead_letter_embeddings(
+            &nats_client,
+            &cause,
+            failed_msg,
+            err_msg.clone(),
+            max_attempts,
+        )
+        .await;
+
+        return Err(anyhow::anyhow!(err_msg));
+    }
 
     info!(
-        "[SEARCH_HANDLER] Processing SemanticSearchNatsTask (request_id: {}, top_k: {})",
-        task.request_id, task.top_k
+        "[QDRANT_HANDLER] Successfully upserted all {} points for original_id: {}.",
+        total_points, msg.original_id
     );
 
-    let search_request = SearchPoints {
-        collection_name: QDRANT_COLLECTION_NAME.to_string(),
-        vector: task.query_embedding,
-        limit: task.top_k as u64,
-        with_payload: Some(WithPayloadSelector {
+    Ok(())
+}
+
+/// Plain dense nearest-neighbour search.
+#[allow(clippy::too_many_arguments)]
+async fn dense_search(
+    qdrant_client: &Qdrant,
+    collection_name: String,
+    dense_vector: Option<&str>,
+    query_embedding: Vec<f32>,
+    top_k: u32,
+    filter: Option<Filter>,
+    options: &SearchOptions,
+    settings: SearchSettings,
+) -> Result<(Vec<ScoredPoint>, f64)> {
+    let search_request = SearchPoints {
+        collection_name,
+        vector: query_embedding,
+        limit: top_k as u64,
+        with_payload: Some(WithPayloadSelector {
             selector_options: Some(
                 qdrant_client::qdrant::with_payload_selector::SelectorOptions::Enable(true),
             ),
@@ -272,32 +1099,233 @@ async fn handle_semantic_search_task(
                 qdrant_client::qdrant::with_vectors_selector::SelectorOptions::Enable(false),
             ),
         }),
-        offset: Some(0),
-        vector_name: None,
-        read_consistency: None,
-        timeout: None,
+        offset: Some(options.offset as u64),
+        vector_name: dense_vector.map(str::to_string),
+        read_consistency: settings.qdrant_read_consistency().map(Into::into),
+        timeout: settings.timeout_secs,
         shard_key_selector: None,
-        filter: None,
-        score_threshold: None,
-        params: None,
+        filter,
+        score_threshold: options.score_threshold,
+        params: settings.search_params(),
         sparse_indices: None,
     };
 
-    let search_result_qdrant = match qdrant_client.search_points(search_request).await {
-        Ok(res) => res,
+    let response = qdrant_call(qdrant_client.search_points(search_request)).await?;
+    Ok((response.result, response.time))
+}
+
+/// Hybrid search: dense and sparse candidates are prefetched separately and merged with
+/// Reciprocal Rank Fusion, so exact-term matches surface even when their dense score is low.
+#[allow(clippy::too_many_arguments)]
+async fn hybrid_search(
+    qdrant_client: &Qdrant,
+    collection_name: String,
+    dense_vector: Option<&str>,
+    query_embedding: Vec<f32>,
+    sparse_query: SparseVector,
+    top_k: u64,
+    filter: Option<Filter>,
+    options: &SearchOptions,
+    settings: SearchSettings,
+) -> Result<(Vec<ScoredPoint>, f64)> {
+    let prefetch_limit = (top_k.max(1) + options.offset as u64) * HYBRID_PREFETCH_MULTIPLIER;
+
+    let mut query_request = QueryPointsBuilder::new(collection_name)
+        .prefetch(hybrid_prefetches(
+            dense_vector,
+            query_embedding,
+            sparse_query,
+            prefetch_limit,
+            filter,
+            settings.search_params(),
+        ))
+        .query(Query::new_fusion(Fusion::Rrf))
+        .limit(top_k)
+        .offset(options.offset as u64)
+        .with_payload(true);
+    if let Some(score_threshold) = options.score_threshold {
+        query_request = query_request.score_threshold(score_threshold);
+    }
+    if let Some(read_consistency) = settings.qdrant_read_consistency() {
+        query_request = query_request.read_consistency(read_consistency);
+    }
+    if let Some(timeout_secs) = settings.timeout_secs {
+        query_request = query_request.timeout(timeout_secs);
+    }
+
+    let response = qdrant_call(qdrant_client.query(query_request)).await?;
+    Ok((response.result, response.time))
+}
+
+/// The filter is applied to both branches: fusion only ranks prefetched candidates,
+/// so filtering after RRF would return fewer than top_k hits. Search `params` (HNSW ef)
+/// only affect the dense branch; the sparse vector has no HNSW index.
+fn hybrid_prefetches(
+    dense_vector: Option<&str>,
+    query_embedding: Vec<f32>,
+    sparse_query: SparseVector,
+    prefetch_limit: u64,
+    filter: Option<Filter>,
+    params: Option<SearchParams>,
+) -> Vec<PrefetchQuery> {
+    let mut dense_prefetch = PrefetchQueryBuilder::default()
+        .query(Query::new_nearest(query_embedding))
+        .limit(prefetch_limit);
+    let mut sparse_prefetch = PrefetchQueryBuilder::default()
+        .query(Query::new_nearest(VectorInput::new_sparse(
+            sparse_query.indices,
+            sparse_query.values,
+        )))
+        .using(SPARSE_VECTOR_NAME)
+        .limit(prefetch_limit);
+    if let Some(filter) = filter {
+        dense_prefetch = dense_prefetch.filter(filter.clone());
+        sparse_prefetch = sparse_prefetch.filter(filter);
+    }
+    if let Some(dense_vector) = dense_vector {
+        dense_prefetch = dense_prefetch.using(dense_vector);
+    }
+    if let Some(params) = params {
+        dense_prefetch = dense_prefetch.params(params);
+    }
+
+    vec![dense_prefetch.build(), sparse_prefetch.build()]
+}
+
+/// Dense search returning up to `top_k` documents with at most `hits_per_document` sentences each.
+#[allow(clippy::too_many_arguments)]
+async fn dense_search_groups(
+    qdrant_client: &Qdrant,
+    collection_name: String,
+    dense_vector: Option<&str>,
+    query_embedding: Vec<f32>,
+    top_k: u32,
+    hits_per_document: u32,
+    filter: Option<Filter>,
+    score_threshold: Option<f32>,
+    settings: SearchSettings,
+) -> Result<(Vec<PointGroup>, f64)> {
+    let mut request = SearchPointGroupsBuilder::new(
+        collection_name,
+        query_embedding,
+        top_k,
+        GROUP_BY_FIELD,
+        hits_per_document,
+    )
+    .with_payload(true);
+    if let Some(dense_vector) = dense_vector {
+        request = request.vector_name(dense_vector);
+    }
+    if let Some(filter) = filter {
+        request = request.filter(filter);
+    }
+    if let Some(score_threshold) = score_threshold {
+        request = request.score_threshold(score_threshold);
+    }
+    if let Some(params) = settings.search_params() {
+        request = request.params(params);
+    }
+    if let Some(read_consistency) = settings.qdrant_read_consistency() {
+        request = request.read_consistency(read_consistency);
+    }
+    if let Some(timeout_secs) = settings.timeout_secs {
+        request = request.timeout(timeout_secs);
+    }
+
+    let response = qdrant_call(qdrant_client.search_groups(request)).await?;
+    Ok((
+        response.result.map(|r| r.groups).unwrap_or_default(),
+        response.time,
+    ))
+}
+
+/// Grouped variant of [`hybrid_search`].
+#[allow(clippy::too_many_arguments)]
+async fn hybrid_search_groups(
+    qdrant_client: &Qdrant,
+    collection_name: String,
+    dense_vector: Option<&str>,
+    query_embedding: Vec<f32>,
+    sparse_query: SparseVector,
+    top_k: u64,
+    hits_per_document: u64,
+    filter: Option<Filter>,
+    score_threshold: Option<f32>,
+    settings: SearchSettings,
+) -> Result<(Vec<PointGroup>, f64)> {
+    let prefetch_limit = top_k.max(1) * hits_per_document.max(1) * HYBRID_PREFETCH_MULTIPLIER;
+
+    let mut request = QueryPointGroupsBuilder::new(collection_name, GROUP_BY_FIELD)
+        .prefetch(hybrid_prefetches(
+            dense_vector,
+            query_embedding,
+            sparse_query,
+            prefetch_limit,
+            filter,
+            settings.search_params(),
+        ))
+        .query(Query::new_fusion(Fusion::Rrf))
+        .limit(top_k)
+        .group_size(hits_per_document)
+        .with_payload(true);
+    if let Some(score_threshold) = score_threshold {
+        request = request.score_threshold(score_threshold);
+    }
+    if let Some(read_consistency) = settings.qdrant_read_consistency() {
+        request = request.read_consistency(read_consistency);
+    }
+    if let Some(timeout_secs) = settings.timeout_secs {
+        request = request.timeout(timeout_secs);
+    }
+
+    let response = qdrant_call(qdrant_client.query_groups(request)).await?;
+    Ok((
+        response.result.map(|r| r.groups).unwrap_or_default(),
+        response.time,
+    ))
+}
+
+/// Search hits as returned by Qdrant: a flat ranking, or per-document groups.
+enum SearchHits {
+    Points(Vec<ScoredPoint>),
+    Groups(Vec<PointGroup>),
+}
+
+fn scored_point_to_result_item(scored_point: ScoredPoint) -> Option<SemanticSearchResultItem> {
+    let Some(qdrant_point_id_str) = point_id_to_string(scored_point.id) else {
+        warn!("[SEARCH_HANDLER] Found point with missing or unexpected ID format. Skipping.");
+        return None;
+    };
+
+    Some(SemanticSearchResultItem {
+        qdrant_point_id: qdrant_point_id_str,
+        score: scored_point.score,
+        payload: qdrant_payload_from_map(&scored_point.payload),
+    })
+}
+
+async fn handle_semantic_search_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+    search_settings: SearchSettings,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("search");
+    let (cause, mut task) = match Envelope::<SemanticSearchNatsTask>::from_slice(&nats_msg.payload)
+    {
+        Ok(envelope) => envelope.split(),
         Err(e) => {
-            let err_msg = format!(
-                "Qdrant search failed for request_id {}: {}",
-                task.request_id, e
-            );
-            error!("[SEARCH_HANDLER_QDRANT_FAIL] {}", err_msg);
+            let err_msg = format!("Failed to deserialize SemanticSearchNatsTask: {}", e);
+            error!("[SEARCH_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
             if let Some(reply_to) = &nats_msg.reply {
                 let error_result = SemanticSearchNatsResult {
-                    request_id: task.request_id.clone(),
+                    request_id: RequestId::default(),
                     results: vec![],
+                    groups: None,
                     error_message: Some(err_msg.clone()),
                 };
-                if let Ok(payload_json) = serde_json::to_vec(&error_result) {
+                if let Ok(payload_json) = Envelope::new(SERVICE_NAME, &error_result).to_vec() {
                     let _ = nats_client_for_reply
                         .publish(reply_to.clone(), payload_json.into())
                         .await;
@@ -307,112 +1335,210 @@ async fn handle_semantic_search_task(
         }
     };
 
-    info!(
-        "[SEARCH_HANDLER] Qdrant search completed for request_id {}. Found {} points. Took: {}s",
-        task.request_id,
-        search_result_qdrant.result.len(),
-        search_result_qdrant.time
-    );
+    let collection_name = collections.read_collection(task.model_name.as_deref());
 
-    let mut results_for_nats: Vec<SemanticSearchResultItem> = Vec::new();
+    let rejection = match (task.filters.validate(), task.options.validate()) {
+        (Err(e), _) | (_, Err(e)) => Some(e.to_string()),
+        _ => collections
+            .scope_tenant(&cause, &mut task.filters.tenant_id)
+            .err()
+            .map(|e| e.to_string()),
+    };
+    if let Some(e) = rejection {
+        let err_msg = format!("Rejected search request_id {}: {}", task.request_id, e);
+        error!("[SEARCH_HANDLER_REJECTED] {}", err_msg);
+        let error_result = SemanticSearchNatsResult {
+            request_id: task.request_id,
+            results: vec![],
+            groups: None,
+            error_message: Some(err_msg.clone()),
+        };
+        publish_reply(
+            &nats_client_for_reply,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &error_result,
+            "SEARCH_HANDLER",
+        )
+        .await;
+        return Err(anyhow::anyhow!(err_msg));
+    }
+    let search_filter = search_filter(&task.filters);
+    let layout = collections.layout(&collection_name).await;
 
-    for scored_point in search_result_qdrant.result {
-        let qdrant_point_id_str = match scored_point.id {
-            Some(QdrantPointId {
-                point_id_options: Some(qdrant_client::qdrant::point_id::PointIdOptions::Uuid(s)),
-            }) => s,
-            Some(QdrantPointId {
-                point_id_options: Some(qdrant_client::qdrant::point_id::PointIdOptions::Num(n)),
-            }) => n.to_string(),
-            _ => {
+    let hybrid_sparse_query = match task.sparse_query.as_ref() {
+        Some(sparse) if !sparse.is_empty() => {
+            if layout.sparse_enabled {
+                Some(sparse.clone())
+            } else {
                 warn!(
-                    "[SEARCH_HANDLER] Found point with missing or unexpected ID format. Skipping."
+                    "[SEARCH_HANDLER] Sparse query provided for request_id {} but collection '{}' has no sparse vector. Falling back to dense search.",
+                    task.request_id, collection_name
                 );
-                continue;
+                None
             }
-        };
+        }
+        _ => None,
+    };
 
-        let payload_map = scored_point.payload;
+    let search_settings =
+        search_settings.with_overrides(task.read_consistency, task.timeout_secs, task.hnsw_ef);
 
-        let original_document_id = payload_map
-            .get("original_document_id")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::StringValue(s) => Some(s.clone()),
-                    _ => None,
-                })
-            })
-            .unwrap_or_default();
-        let source_url = payload_map
-            .get("source_url")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::StringValue(s) => Some(s.clone()),
-                    _ => None,
-                })
-            })
-            .unwrap_or_default();
-        let sentence_text = payload_map
-            .get("sentence_text")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::StringValue(s) => Some(s.clone()),
-                    _ => None,
-                })
-            })
-            .unwrap_or_default();
-        let sentence_order = payload_map
-            .get("sentence_order")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::IntegerValue(i) => Some(*i as u32),
-                    _ => None,
-                })
-            })
-            .unwrap_or(0);
-        let model_name = payload_map
-            .get("model_name")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::StringValue(s) => Some(s.clone()),
-                    _ => None,
-                })
-            })
-            .unwrap_or_default();
-        let processed_at_ms = payload_map
-            .get("processed_at_ms")
-            .and_then(|v| {
-                v.kind.as_ref().and_then(|k| match k {
-                    qdrant_client::qdrant::value::Kind::IntegerValue(i) => Some(*i as u64),
-                    _ => None,
+    // Diversified searches are grouped searches with one hit per document, flattened.
+    let hits_per_document = if task.options.diversify {
+        Some(1)
+    } else {
+        task.options.group_by_document.then(|| {
+            task.options
+                .hits_per_document
+                .unwrap_or(DEFAULT_HITS_PER_DOCUMENT)
+                .clamp(1, MAX_HITS_PER_DOCUMENT)
+        })
+    };
+
+    info!(
+        "[SEARCH_HANDLER] Processing SemanticSearchNatsTask (request_id: {}, top_k: {}, collection: {}, hybrid: {}, hits_per_document: {:?}, filters: {:?}, options: {:?}, settings: {:?})",
+        task.request_id,
+        task.top_k,
+        collection_name,
+        hybrid_sparse_query.is_some(),
+        hits_per_document,
+        task.filters,
+        task.options,
+        search_settings
+    );
+
+    let search_started = Instant::now();
+    let search_outcome = match (hybrid_sparse_query, hits_per_document) {
+        (Some(sparse_query), None) => hybrid_search(
+            &qdrant_client,
+            collection_name,
+            layout.dense_vector_name,
+            task.query_embedding,
+            sparse_query,
+            task.top_k as u64,
+            search_filter,
+            &task.options,
+            search_settings,
+        )
+        .await
+        .map(|(points, time)| (SearchHits::Points(points), time)),
+        (None, None) => dense_search(
+            &qdrant_client,
+            collection_name,
+            layout.dense_vector_name,
+            task.query_embedding,
+            task.top_k,
+            search_filter,
+            &task.options,
+            search_settings,
+        )
+        .await
+        .map(|(points, time)| (SearchHits::Points(points), time)),
+        (Some(sparse_query), Some(group_size)) => hybrid_search_groups(
+            &qdrant_client,
+            collection_name,
+            layout.dense_vector_name,
+            task.query_embedding,
+            sparse_query,
+            task.top_k as u64,
+            group_size as u64,
+            search_filter,
+            task.options.score_threshold,
+            search_settings,
+        )
+        .await
+        .map(|(groups, time)| (SearchHits::Groups(groups), time)),
+        (None, Some(group_size)) => dense_search_groups(
+            &qdrant_client,
+            collection_name,
+            layout.dense_vector_name,
+            task.query_embedding,
+            task.top_k,
+            group_size,
+            search_filter,
+            task.options.score_threshold,
+            search_settings,
+        )
+        .await
+        .map(|(groups, time)| (SearchHits::Groups(groups), time)),
+    };
+
+    metrics::observe_search("search", search_started.elapsed());
+
+    let (search_hits, search_time) = match search_outcome {
+        Ok(res) => res,
+        Err(e) => {
+            metrics::qdrant_error("search");
+            let err_msg = format!(
+                "Qdrant search failed for request_id {}: {}",
+                task.request_id, e
+            );
+            error!("[SEARCH_HANDLER_QDRANT_FAIL] {}", err_msg);
+            if let Some(reply_to) = &nats_msg.reply {
+                let error_result = SemanticSearchNatsResult {
+                    request_id: task.request_id,
+                    results: vec![],
+                    groups: None,
+                    error_message: Some(err_msg.clone()),
+                };
+                if let Ok(payload_json) = cause.follow_up(SERVICE_NAME, &error_result).to_vec() {
+                    let _ = nats_client_for_reply
+                        .publish(reply_to.clone(), payload_json.into())
+                        .await;
+                }
+            }
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let (results_for_nats, groups_for_nats) = match search_hits {
+        SearchHits::Points(scored_points) => {
+            let results: Vec<SemanticSearchResultItem> = scored_points
+                .into_iter()
+                .filter_map(scored_point_to_result_item)
+                .collect();
+            (results, None)
+        }
+        SearchHits::Groups(point_groups) => {
+            let groups: Vec<SemanticSearchResultGroup> = point_groups
+                .into_iter()
+                .filter_map(|group| {
+                    let original_document_id = group_id_to_string(group.id)?.parse().ok()?;
+                    let hits: Vec<SemanticSearchResultItem> = group
+                        .hits
+                        .into_iter()
+                        .filter_map(scored_point_to_result_item)
+                        .collect();
+                    Some(SemanticSearchResultGroup {
+                        original_document_id,
+                        hits,
+                    })
                 })
-            })
-            .unwrap_or(0);
-
-        let qdrant_payload = QdrantPointPayload {
-            original_document_id,
-            source_url,
-            sentence_text,
-            sentence_order,
-            model_name,
-            processed_at_ms,
-        };
+                .collect();
+            let results = groups.iter().flat_map(|g| g.hits.clone()).collect();
+            (results, (!task.options.diversify).then_some(groups))
+        }
+    };
 
-        results_for_nats.push(SemanticSearchResultItem {
-            qdrant_point_id: qdrant_point_id_str,
-            score: scored_point.score,
-            payload: qdrant_payload,
-        });
-    }
+    info!(
+        "[SEARCH_HANDLER] Qdrant search completed for request_id {}. Found {} points (document groups: {:?}). Took: {}s",
+        task.request_id,
+        results_for_nats.len(),
+        groups_for_nats.as_ref().map(Vec::len),
+        search_time
+    );
 
     let final_result = SemanticSearchNatsResult {
-        request_id: task.request_id.clone(),
+        request_id: task.request_id,
         results: results_for_nats,
+        groups: groups_for_nats,
         error_message: None,
     };
 
     if let Some(reply_to) = nats_msg.reply {
-        match serde_json::to_vec(&final_result) {
+        match cause.follow_up(SERVICE_NAME, &final_result).to_vec() {
             Ok(payload_json) => {
                 info!(
                     "[SEARCH_HANDLER] Sending search results for request_id {} to NATS reply subject: {}",
@@ -434,11 +1560,15 @@ async fn handle_semantic_search_task(
                     task.request_id, e
                 );
                 let error_result_on_serialize_fail = SemanticSearchNatsResult {
-                    request_id: task.request_id.clone(),
+                    request_id: task.request_id,
                     results: vec![],
+                    groups: None,
                     error_message: Some(format!("Failed to serialize result: {}", e)),
                 };
-                if let Ok(err_payload_json) = serde_json::to_vec(&error_result_on_serialize_fail) {
+                if let Ok(err_payload_json) = cause
+                    .follow_up(SERVICE_NAME, &error_result_on_serialize_fail)
+                    .to_vec()
+                {
                     let _ = nats_client_for_reply
                         .publish(reply_to, err_payload_json.into())
                         .await;
@@ -455,175 +1585,2443 @@ async fn handle_semantic_search_task(
     Ok(())
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::Builder::from_env(
-        env_logger::Env::default()
-            .default_filter_or("info,vector_memory_service=debug,qdrant_client=info"),
-    )
-    .init();
+/// Runs all queries of a batch task with a single `search_batch_points` call. Batches are
+/// dense-only; hybrid and grouped searches go through [`handle_semantic_search_task`].
+async fn handle_semantic_search_batch_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+    search_settings: SearchSettings,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("search_batch");
+    let (cause, mut task) =
+        match Envelope::<SemanticSearchNatsBatchTask>::from_slice(&nats_msg.payload) {
+            Ok(envelope) => envelope.split(),
+            Err(e) => {
+                let err_msg = format!("Failed to deserialize SemanticSearchNatsBatchTask: {}", e);
+                error!("[SEARCH_BATCH_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+                let error_result = SemanticSearchNatsBatchResult {
+                    request_id: RequestId::default(),
+                    results: vec![],
+                    error_message: Some(err_msg.clone()),
+                };
+                publish_reply(
+                    &nats_client_for_reply,
+                    nats_msg.reply,
+                    None,
+                    SERVICE_NAME,
+                    &error_result,
+                    "SEARCH_BATCH_HANDLER",
+                )
+                .await;
+                return Err(anyhow::anyhow!(err_msg));
+            }
+        };
 
-    let nats_url = env::var("NATS_URL").unwrap_or_else(|_| {
-        warn!("[NATS_CONFIG] NATS_URL not set, defaulting to nats://localhost:4222");
-        "nats://localhost:4222".to_string()
-    });
-    info!(
-        "[NATS_CONNECT] Attempting to connect to NATS server at {}...",
-        nats_url
-    );
-    let nats_client = Arc::new(
-        async_nats::connect(&nats_url)
-            .await
-            .with_context(|| format!("Failed to connect to NATS at {}", nats_url))?,
-    );
-    info!("[NATS_CONNECT_SUCCESS] Successfully connected to NATS!");
+    let collection_name = collections.read_collection(task.model_name.as_deref());
 
-    let mut embeddings_subscriber = nats_client
-        .subscribe(TEXT_WITH_EMBEDDINGS_SUBJECT)
-        .await
-        .with_context(|| {
-            format!(
-                "Failed to subscribe to NATS subject {}",
-                TEXT_WITH_EMBEDDINGS_SUBJECT
-            )
-        })?;
     info!(
-        "[NATS_SUB_SUCCESS] Subscribed to subject: {}",
-        TEXT_WITH_EMBEDDINGS_SUBJECT
+        "[SEARCH_BATCH_HANDLER] Processing SemanticSearchNatsBatchTask (request_id: {}, queries: {}, collection: {})",
+        task.request_id,
+        task.queries.len(),
+        collection_name
     );
 
-    let qdrant_uri = env::var("QDRANT_URI").unwrap_or_else(|_| {
-        warn!("[QDRANT_CONFIG] QDRANT_URI not set, defaulting to http://localhost:6334");
-        "http://localhost:6334".to_string()
-    });
-
-    info!(
-        "[QDRANT_CONNECT] Attempting to connect to Qdrant at URI: {}",
-        qdrant_uri
-    );
+    let validation = if task.queries.len() > MAX_BATCH_QUERIES {
+        Err(anyhow::anyhow!(
+            "Batch has {} queries, at most {} are allowed",
+            task.queries.len(),
+            MAX_BATCH_QUERIES
+        ))
+    } else {
+        collections.scope_tenant(&cause, &mut task.tenant_id)
+    };
+    if let Err(e) = validation {
+        let err_msg = format!(
+            "Rejected batch search request_id {}: {}",
+            task.request_id, e
+        );
+        error!("[SEARCH_BATCH_HANDLER_INVALID] {}", err_msg);
+        let error_result = SemanticSearchNatsBatchResult {
+            request_id: task.request_id,
+            results: vec![],
+            error_message: Some(err_msg.clone()),
+        };
+        publish_reply(
+            &nats_client_for_reply,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &error_result,
+            "SEARCH_BATCH_HANDLER",
+        )
+        .await;
+        return Err(anyhow::anyhow!(err_msg));
+    }
 
-    let qdrant_client_arc: Arc<Qdrant>;
-    let max_retries = 5;
-    let retry_delay = Duration::from_secs(5);
-    let mut attempt = 0;
+    if task.queries.is_empty() {
+        let empty_result = SemanticSearchNatsBatchResult {
+            request_id: task.request_id,
+            results: vec![],
+            error_message: None,
+        };
+        publish_reply(
+            &nats_client_for_reply,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &empty_result,
+            "SEARCH_BATCH_HANDLER",
+        )
+        .await;
+        return Ok(());
+    }
 
-    loop {
-        attempt += 1;
-        match Qdrant::from_url(&qdrant_uri).build() {
-            Ok(client_instance) => {
-                qdrant_client_arc = Arc::new(client_instance);
-                info!("[QDRANT_CONNECT_SUCCESS] Successfully created Qdrant client.");
-                break;
+    let search_filter = document_filter(task.tenant_id.as_deref(), None, None);
+    let dense_vector = collections.layout(&collection_name).await.dense_vector_name;
+    let searches: Vec<SearchPoints> = task
+        .queries
+        .into_iter()
+        .map(|query| {
+            let mut search = SearchPointsBuilder::new(
+                collection_name.clone(),
+                query.query_embedding,
+                query.top_k as u64,
+            )
+            .with_payload(true);
+            if let Some(dense_vector) = dense_vector {
+                search = search.vector_name(dense_vector);
             }
-            Err(e) => {
-                error!(
-                    "[QDRANT_CONNECT_FAIL] Attempt {} to connect to Qdrant failed: {}. Retrying in {:?}...",
-                    attempt, e, retry_delay
-                );
-                if attempt >= max_retries {
-                    error!(
-                        "[QDRANT_CONNECT_FATAL] Max retries reached. Failed to connect to Qdrant."
-                    );
-                    return Err(e.into());
-                }
-                tokio::time::sleep(retry_delay).await;
+            if let Some(filter) = search_filter.clone() {
+                search = search.filter(filter);
             }
-        }
-    }
+            if let Some(params) = search_settings.search_params() {
+                search = search.params(params);
+            }
+            search.build()
+        })
+        .collect();
 
-    if let Err(e) = ensure_qdrant_collection(
-        Arc::clone(&qdrant_client_arc),
-        QDRANT_COLLECTION_NAME,
-        QDRANT_VECTOR_DIM,
-    )
-    .await
-    {
-        error!(
-            "[QDRANT_SETUP_FATAL] Failed to ensure Qdrant collection: {}. Service will not be able to store vectors.",
-            e
-        );
+    let mut batch_request = SearchBatchPointsBuilder::new(collection_name.clone(), searches);
+    if let Some(read_consistency) = search_settings.qdrant_read_consistency() {
+        batch_request = batch_request.read_consistency(read_consistency);
+    }
+    if let Some(timeout_secs) = search_settings.timeout_secs {
+        batch_request = batch_request.timeout(timeout_secs);
     }
 
-    let qdrant_client_for_storage_task = Arc::clone(&qdrant_client_arc);
-    tokio::spawn(async move {
-        info!("[NATS_LOOP_STORAGE] Waiting for messages with text embeddings...");
+    let search_started = Instant::now();
+    let batch_outcome = qdrant_call(qdrant_client.search_batch_points(batch_request)).await;
+    metrics::observe_search("search_batch", search_started.elapsed());
 
-        while let Some(message) = embeddings_subscriber.next().await {
+    let result = match batch_outcome {
+        Ok(response) => {
+            let results: Vec<Vec<SemanticSearchResultItem>> = response
+                .result
+                .into_iter()
+                .map(|batch| {
+                    batch
+                        .result
+                        .into_iter()
+                        .filter_map(scored_point_to_result_item)
+                        .collect()
+                })
+                .collect();
             info!(
-                "[NATS_MSG_RECV_STORAGE] Received message on subject: {}",
-                message.subject
+                "[SEARCH_BATCH_HANDLER] Batch search for request_id {} completed: {} queries, {} points in total. Took: {}s",
+                task.request_id,
+                results.len(),
+                results.iter().map(Vec::len).sum::<usize>(),
+                response.time
             );
-
-            match serde_json::from_slice::<TextWithEmbeddingsMessage>(&message.payload) {
-                Ok(embeddings_msg) => {
-                    info!(
-                        "[TASK_DESERIALIZED_STORAGE] Deserialized TextWithEmbeddingsMessage (original_id: {})",
-                        embeddings_msg.original_id
-                    );
-                    let qdrant_client_clone = Arc::clone(&qdrant_client_for_storage_task);
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            handle_text_with_embeddings_message(embeddings_msg, qdrant_client_clone)
-                                .await
-                        {
-                            error!(
-                                "[HANDLER_ERROR_STORAGE] Error processing storage message: {:?}",
-                                e
-                            );
-                        }
-                    });
-                }
-                Err(e) => {
-                    warn!(
-                        "[TASK_DESERIALIZE_FAIL_STORAGE] Failed to deserialize TextWithEmbeddingsMessage: {}. Payload (first 100 bytes): {:?}",
-                        e,
-                        message.payload.get(..100)
-                    );
-                }
+            SemanticSearchNatsBatchResult {
+                request_id: task.request_id,
+                results,
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Qdrant batch search failed for request_id {}: {}",
+                task.request_id, e
+            );
+            metrics::qdrant_error("search_batch");
+            error!("[SEARCH_BATCH_HANDLER_QDRANT_FAIL] {}", err_msg);
+            SemanticSearchNatsBatchResult {
+                request_id: task.request_id,
+                results: vec![],
+                error_message: Some(err_msg),
             }
         }
+    };
 
-        info!("[NATS_LOOP_STORAGE_END] Embeddings storage subscription ended.");
-    });
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "SEARCH_BATCH_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+fn document_filter(
+    tenant_id: Option<&str>,
+    original_document_id: Option<DocumentId>,
+    source_url: Option<&str>,
+) -> Option<Filter> {
+    let mut conditions: Vec<Condition> = Vec::new();
+    if let Some(tenant_id) = tenant_id {
+        conditions.push(Condition::matches(TENANT_FIELD, tenant_id.to_string()));
+    }
+    if let Some(document_id) = original_document_id {
+        conditions.push(Condition::matches(
+            "original_document_id",
+            document_id.to_string(),
+        ));
+    }
+    if let Some(url) = source_url {
+        conditions.push(Condition::matches("source_url", url.to_string()));
+    }
+
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(Filter::must(conditions))
+    }
+}
+
+/// Qdrant filter for the [`SearchFilters`] of a search; `None` when none is set.
+fn search_filter(filters: &SearchFilters) -> Option<Filter> {
+    let mut conditions: Vec<Condition> = Vec::new();
+    for (field, value) in [
+        (TENANT_FIELD, &filters.tenant_id),
+        ("source_url", &filters.source_url),
+        ("language", &filters.language),
+    ] {
+        if let Some(value) = value {
+            conditions.push(Condition::matches(field, value.clone()));
+        }
+    }
+    let processed = filters.processed_range();
+    if !processed.is_unbounded() {
+        conditions.push(Condition::range(
+            "processed_at_ms",
+            Range {
+                gte: processed.start.map(|start| start.as_millis() as f64),
+                lt: processed.end.map(|end| end.as_millis() as f64),
+                ..Default::default()
+            },
+        ));
+    }
+
+    if conditions.is_empty() {
+        None
+    } else {
+        Some(Filter::must(conditions))
+    }
+}
 
-    let mut search_task_subscriber = nats_client
-        .subscribe(SEMANTIC_SEARCH_TASK_SUBJECT)
+/// Point IDs of (up to [`MAX_RECOMMEND_DOCUMENT_EXAMPLES`]) sentences of a document.
+async fn document_point_ids(
+    qdrant_client: &Qdrant,
+    collection_name: &str,
+    tenant_id: Option<&str>,
+    original_document_id: DocumentId,
+) -> Result<Vec<PointId>> {
+    let filter = document_filter(tenant_id, Some(original_document_id), None)
+        .context("Document filter must not be empty")?;
+    let response = qdrant_client
+        .scroll(
+            ScrollPointsBuilder::new(collection_name)
+                .filter(filter)
+                .limit(MAX_RECOMMEND_DOCUMENT_EXAMPLES)
+                .with_payload(false)
+                .with_vectors(false),
+        )
         .await
         .with_context(|| {
             format!(
-                "Failed to subscribe to NATS subject {}",
-                SEMANTIC_SEARCH_TASK_SUBJECT
+                "Failed to list points of document '{}' in '{}'",
+                original_document_id, collection_name
             )
         })?;
-    info!(
-        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for semantic search tasks",
-        SEMANTIC_SEARCH_TASK_SUBJECT
-    );
 
-    let qdrant_client_for_search_task = Arc::clone(&qdrant_client_arc);
-    let nats_client_for_search_reply = Arc::clone(&nats_client);
+    Ok(response
+        .result
+        .into_iter()
+        .filter_map(|point| point.id)
+        .collect())
+}
 
-    info!("[NATS_LOOP_SEARCH] Waiting for semantic search tasks...");
-    while let Some(message) = search_task_subscriber.next().await {
-        info!(
-            "[NATS_MSG_RECV_SEARCH] Received search task on subject: {}",
-            message.subject
-        );
-        let q_client_clone = Arc::clone(&qdrant_client_for_search_task);
-        let n_client_clone = Arc::clone(&nats_client_for_search_reply);
+async fn recommend_points(
+    qdrant_client: &Qdrant,
+    collection_name: &str,
+    dense_vector: Option<&str>,
+    task: &RecommendNatsTask,
+    settings: SearchSettings,
+) -> Result<(Vec<ScoredPoint>, f64)> {
+    let mut positive_ids: Vec<PointId> = task
+        .positive_point_ids
+        .iter()
+        .map(|id| point_id_from_str(id))
+        .collect();
+    let negative_ids: Vec<PointId> = task
+        .negative_point_ids
+        .iter()
+        .map(|id| point_id_from_str(id))
+        .collect();
+
+    let mut exclusions: Vec<Condition> = Vec::new();
+    if let Some(document_id) = task.positive_document_id {
+        let document_ids = document_point_ids(
+            qdrant_client,
+            collection_name,
+            task.tenant_id.as_deref(),
+            document_id,
+        )
+        .await?;
+        if document_ids.is_empty() {
+            return Err(anyhow::anyhow!(
+                "Document '{}' has no stored sentences in '{}'",
+                document_id,
+                collection_name
+            ));
+        }
+        positive_ids.extend(document_ids);
+        exclusions.push(Condition::matches(
+            "original_document_id",
+            document_id.to_string(),
+        ));
+    }
+    if positive_ids.is_empty() {
+        return Err(anyhow::anyhow!(
+            "At least one positive point ID or a positive document ID is required"
+        ));
+    }
+    exclusions.push(Condition::has_id(
+        positive_ids.iter().chain(negative_ids.iter()).cloned(),
+    ));
+
+    let mut recommend_input = RecommendInputBuilder::default();
+    for point_id in positive_ids {
+        recommend_input = recommend_input.add_positive(VectorInput::new_id(point_id));
+    }
+    for point_id in negative_ids {
+        recommend_input = recommend_input.add_negative(VectorInput::new_id(point_id));
+    }
+
+    let mut filter = Filter::must_not(exclusions);
+    if let Some(tenant_id) = task.tenant_id.as_deref() {
+        filter
+            .must
+            .push(Condition::matches(TENANT_FIELD, tenant_id.to_string()));
+    }
+
+    let mut query_request = QueryPointsBuilder::new(collection_name)
+        .query(Query::new_recommend(
+            recommend_input.strategy(RecommendStrategy::AverageVector),
+        ))
+        .filter(filter)
+        .limit(task.top_k as u64)
+        .with_payload(true);
+    if let Some(dense_vector) = dense_vector {
+        query_request = query_request.using(dense_vector);
+    }
+    if let Some(params) = settings.search_params() {
+        query_request = query_request.params(params);
+    }
+    if let Some(read_consistency) = settings.qdrant_read_consistency() {
+        query_request = query_request.read_consistency(read_consistency);
+    }
+    if let Some(timeout_secs) = settings.timeout_secs {
+        query_request = query_request.timeout(timeout_secs);
+    }
+
+    let search_started = Instant::now();
+    let response = qdrant_call(qdrant_client.query(query_request))
+        .await
+        .inspect_err(|_| metrics::qdrant_error("recommend"))?;
+    metrics::observe_search("recommend", search_started.elapsed());
+    Ok((response.result, response.time))
+}
+
+async fn handle_recommend_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+    search_settings: SearchSettings,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("recommend");
+    let (cause, mut task) = match Envelope::<RecommendNatsTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize RecommendNatsTask: {}", e);
+            error!("[RECOMMEND_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = SemanticSearchNatsResult {
+                request_id: RequestId::default(),
+                results: vec![],
+                groups: None,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "RECOMMEND_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let collection_name = collections.read_collection(task.model_name.as_deref());
+
+    info!(
+        "[RECOMMEND_HANDLER] Processing RecommendNatsTask (request_id: {}, top_k: {}, collection: {}, positive points: {}, positive document: {:?}, negative points: {})",
+        task.request_id,
+        task.top_k,
+        collection_name,
+        task.positive_point_ids.len(),
+        task.positive_document_id,
+        task.negative_point_ids.len()
+    );
+
+    let recommend_result = match collections.scope_tenant(&cause, &mut task.tenant_id) {
+        Ok(()) => {
+            let dense_vector = collections.layout(&collection_name).await.dense_vector_name;
+            recommend_points(
+                &qdrant_client,
+                &collection_name,
+                dense_vector,
+                &task,
+                search_settings,
+            )
+            .await
+        }
+        Err(e) => Err(e),
+    };
+    let result = match recommend_result {
+        Ok((scored_points, time)) => {
+            let results: Vec<SemanticSearchResultItem> = scored_points
+                .into_iter()
+                .filter_map(scored_point_to_result_item)
+                .collect();
+            info!(
+                "[RECOMMEND_HANDLER] Recommendation for request_id {} returned {} points. Took: {}s",
+                task.request_id,
+                results.len(),
+                time
+            );
+            SemanticSearchNatsResult {
+                request_id: task.request_id,
+                results,
+                groups: None,
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Qdrant recommendation failed for request_id {}: {:#}",
+                task.request_id, e
+            );
+            error!("[RECOMMEND_HANDLER_QDRANT_FAIL] {}", err_msg);
+            SemanticSearchNatsResult {
+                request_id: task.request_id,
+                results: vec![],
+                groups: None,
+                error_message: Some(err_msg),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "RECOMMEND_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn handle_vector_scroll_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("scroll");
+    let (cause, mut task) = match Envelope::<VectorScrollTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorScrollTask: {}", e);
+            error!("[SCROLL_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorScrollResult {
+                request_id: RequestId::default(),
+                points: vec![],
+                next_offset: None,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "SCROLL_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let collection_name = collections.read_collection(task.model_name.as_deref());
+    let limit = task.limit.clamp(1, MAX_SCROLL_LIMIT);
+
+    info!(
+        "[SCROLL_HANDLER] Processing VectorScrollTask (request_id: {}, collection: {}, document: {:?}, source_url: {:?}, limit: {}, offset: {:?})",
+        task.request_id,
+        collection_name,
+        task.original_document_id,
+        task.source_url,
+        limit,
+        task.offset
+    );
+
+    // A document is read in sentence order across pages: its points are ordered by
+    // `sentence_order`, and the next page starts at the sentence after the last one returned.
+    // Other scrolls page by point id.
+    let in_document_order = task.original_document_id.is_some();
+    let validation = collections
+        .scope_tenant(&cause, &mut task.tenant_id)
+        .and_then(|()| match task.offset.as_deref() {
+            Some(offset) if in_document_order => offset.parse::<i64>().map(Some).map_err(|_| {
+                anyhow::anyhow!(
+                    "offset '{}' is not the next_offset of a page of this document",
+                    offset
+                )
+            }),
+            _ => Ok(None),
+        });
+    let next_sentence = match validation {
+        Ok(next_sentence) => next_sentence,
+        Err(e) => {
+            let err_msg = format!("Rejected scroll request_id {}: {}", task.request_id, e);
+            error!("[SCROLL_HANDLER_VALIDATION_FAIL] {}", err_msg);
+            let error_result = VectorScrollResult {
+                request_id: task.request_id,
+                points: vec![],
+                next_offset: None,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                Some(&cause),
+                SERVICE_NAME,
+                &error_result,
+                "SCROLL_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let mut scroll_request = ScrollPointsBuilder::new(collection_name)
+        .limit(limit)
+        .with_payload(true)
+        .with_vectors(false);
+    if let Some(filter) = document_filter(
+        task.tenant_id.as_deref(),
+        task.original_document_id,
+        task.source_url.as_deref(),
+    ) {
+        scroll_request = scroll_request.filter(filter);
+    }
+    if in_document_order {
+        let mut order_by = OrderByBuilder::new("sentence_order").direction(Direction::Asc as i32);
+        if let Some(next_sentence) = next_sentence {
+            order_by = order_by.start_from(start_from::Value::Integer(next_sentence));
+        }
+        scroll_request = scroll_request.order_by(order_by);
+    } else if let Some(offset) = task.offset.as_deref() {
+        scroll_request = scroll_request.offset(point_id_from_str(offset));
+    }
+
+    let result = match qdrant_call(qdrant_client.scroll(scroll_request)).await {
+        Ok(response) => {
+            let page_full = response.result.len() >= limit as usize;
+            let points: Vec<StoredPointItem> = response
+                .result
+                .into_iter()
+                .filter_map(|point| {
+                    Some(StoredPointItem {
+                        qdrant_point_id: point_id_to_string(point.id)?,
+                        payload: qdrant_payload_from_map(&point.payload),
+                    })
+                })
+                .collect();
+            // Ordered scrolls return no offset of their own.
+            let next_offset = if in_document_order {
+                points
+                    .last()
+                    .filter(|_| page_full)
+                    .map(|last| (i64::from(last.payload.sentence_order) + 1).to_string())
+            } else {
+                point_id_to_string(response.next_page_offset)
+            };
+
+            info!(
+                "[SCROLL_HANDLER] Scroll for request_id {} returned {} points. Took: {}s",
+                task.request_id,
+                points.len(),
+                response.time
+            );
+
+            VectorScrollResult {
+                request_id: task.request_id,
+                points,
+                next_offset,
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Qdrant scroll failed for request_id {}: {}",
+                task.request_id, e
+            );
+            metrics::qdrant_error("scroll");
+            error!("[SCROLL_HANDLER_QDRANT_FAIL] {}", err_msg);
+            VectorScrollResult {
+                request_id: task.request_id,
+                points: vec![],
+                next_offset: None,
+                error_message: Some(err_msg),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "SCROLL_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn handle_vector_count_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("count");
+    let (cause, mut task) = match Envelope::<VectorCountTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorCountTask: {}", e);
+            error!("[COUNT_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorCountResult {
+                request_id: RequestId::default(),
+                count: 0,
+                exact: false,
+                groups: None,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "COUNT_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let collection_name = collections.read_collection(task.model_name.as_deref());
+
+    info!(
+        "[COUNT_HANDLER] Processing VectorCountTask (request_id: {}, collection: {}, document: {:?}, source_url: {:?}, exact: {}, group_by: {:?})",
+        task.request_id,
+        collection_name,
+        task.original_document_id,
+        task.source_url,
+        task.exact,
+        task.group_by
+    );
+
+    let validation = collections
+        .scope_tenant(&cause, &mut task.tenant_id)
+        .and_then(|()| match task.group_by.as_deref() {
+            Some(field) if !COUNT_GROUP_FIELDS.contains(&field) => Err(anyhow::anyhow!(
+                "group_by must be one of {:?}, got '{}'",
+                COUNT_GROUP_FIELDS,
+                field
+            )),
+            _ => Ok(()),
+        });
+    if let Err(e) = validation {
+        let err_msg = format!("Rejected count request_id {}: {}", task.request_id, e);
+        error!("[COUNT_HANDLER_VALIDATION_FAIL] {}", err_msg);
+        let error_result = VectorCountResult {
+            request_id: task.request_id,
+            count: 0,
+            exact: task.exact,
+            groups: None,
+            error_message: Some(err_msg.clone()),
+        };
+        publish_reply(
+            &nats_client_for_reply,
+            nats_msg.reply,
+            Some(&cause),
+            SERVICE_NAME,
+            &error_result,
+            "COUNT_HANDLER",
+        )
+        .await;
+        return Err(anyhow::anyhow!(err_msg));
+    }
+
+    let filter = document_filter(
+        task.tenant_id.as_deref(),
+        task.original_document_id,
+        task.source_url.as_deref(),
+    );
+
+    let mut count_request = CountPointsBuilder::new(&collection_name).exact(task.exact);
+    if let Some(filter) = filter.clone() {
+        count_request = count_request.filter(filter);
+    }
+    let count_result = qdrant_call(qdrant_client.count(count_request))
+        .await
+        .map(|response| response.result.map(|r| r.count).unwrap_or(0));
+
+    let groups_result = match task.group_by.as_deref() {
+        Some(field) => {
+            let mut facet_request = FacetCountsBuilder::new(&collection_name, field)
+                .limit(
+                    task.group_limit
+                        .unwrap_or(DEFAULT_COUNT_GROUP_LIMIT)
+                        .clamp(1, MAX_COUNT_GROUP_LIMIT) as u64,
+                )
+                .exact(task.exact);
+            if let Some(filter) = filter {
+                facet_request = facet_request.filter(filter);
+            }
+            qdrant_call(qdrant_client.facet(facet_request))
+                .await
+                .map(|response| {
+                    Some(
+                        response
+                            .hits
+                            .into_iter()
+                            .filter_map(|hit| {
+                                let value = match hit.value?.variant? {
+                                    facet_value::Variant::StringValue(value) => value,
+                                    facet_value::Variant::IntegerValue(value) => value.to_string(),
+                                    facet_value::Variant::BoolValue(value) => value.to_string(),
+                                };
+                                Some(VectorCountGroup {
+                                    value,
+                                    count: hit.count,
+                                })
+                            })
+                            .collect::<Vec<_>>(),
+                    )
+                })
+        }
+        None => Ok(None),
+    };
+
+    let result = match (count_result, groups_result) {
+        (Ok(count), Ok(groups)) => {
+            info!(
+                "[COUNT_HANDLER] Count for request_id {} in '{}': {} points{}",
+                task.request_id,
+                collection_name,
+                count,
+                groups
+                    .as_ref()
+                    .map(|groups| format!(" across {} group(s)", groups.len()))
+                    .unwrap_or_default()
+            );
+            VectorCountResult {
+                request_id: task.request_id,
+                count,
+                exact: task.exact,
+                groups,
+                error_message: None,
+            }
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            let err_msg = format!(
+                "Qdrant count failed for request_id {}: {}",
+                task.request_id, e
+            );
+            metrics::qdrant_error("count");
+            error!("[COUNT_HANDLER_QDRANT_FAIL] {}", err_msg);
+            VectorCountResult {
+                request_id: task.request_id,
+                count: 0,
+                exact: task.exact,
+                groups: None,
+                error_message: Some(err_msg),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "COUNT_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn handle_vector_payload_update_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("payload_update");
+    let (cause, mut task) = match Envelope::<VectorPayloadUpdateTask>::from_slice(&nats_msg.payload)
+    {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorPayloadUpdateTask: {}", e);
+            error!("[PAYLOAD_UPDATE_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorPayloadUpdateResult {
+                request_id: RequestId::default(),
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "PAYLOAD_UPDATE_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    let collection_name = collections.read_collection(task.model_name.as_deref());
+
+    info!(
+        "[PAYLOAD_UPDATE_HANDLER] Processing VectorPayloadUpdateTask (request_id: {}, collection: {}, document: {:?}, source_url: {:?}, fields: {:?})",
+        task.request_id,
+        collection_name,
+        task.original_document_id,
+        task.source_url,
+        task.payload.keys().collect::<Vec<_>>()
+    );
+
+    let protected_fields: Vec<&str> = task
+        .payload
+        .keys()
+        .map(String::as_str)
+        .filter(|field| PROTECTED_PAYLOAD_FIELDS.contains(field))
+        .collect();
+
+    // Without a document selector the filter would match the whole collection
+    // (or the whole tenant), which is never what a correction means.
+    let selection = if task.original_document_id.is_none() && task.source_url.is_none() {
+        Err(anyhow::anyhow!(
+            "original_document_id or source_url is required"
+        ))
+    } else if task.payload.is_empty() {
+        Err(anyhow::anyhow!("payload must contain at least one field"))
+    } else if !protected_fields.is_empty() {
+        Err(anyhow::anyhow!(
+            "payload fields {:?} cannot be updated",
+            protected_fields
+        ))
+    } else {
+        collections
+            .scope_tenant(&cause, &mut task.tenant_id)
+            .and_then(|()| {
+                document_filter(
+                    task.tenant_id.as_deref(),
+                    task.original_document_id,
+                    task.source_url.as_deref(),
+                )
+                .context("Document filter must not be empty")
+            })
+    };
+
+    let update_result = match selection {
+        Ok(filter) => qdrant_call(
+            qdrant_client.set_payload(
+                SetPayloadPointsBuilder::new(
+                    collection_name.as_str(),
+                    qdrant_client::Payload::from(task.payload),
+                )
+                .points_selector(filter)
+                .wait(true),
+            ),
+        )
+        .await
+        .inspect_err(|_| metrics::qdrant_error("set_payload"))
+        .map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+
+    let result = match update_result {
+        Ok(response) => {
+            info!(
+                "[PAYLOAD_UPDATE_HANDLER] Payload update for request_id {} applied. Took: {}s",
+                task.request_id, response.time
+            );
+            VectorPayloadUpdateResult {
+                request_id: task.request_id,
+                error_message: None,
+            }
+        }
+        Err(e) => {
+            let err_msg = format!(
+                "Payload update failed for request_id {}: {:#}",
+                task.request_id, e
+            );
+            error!("[PAYLOAD_UPDATE_HANDLER_FAIL] {}", err_msg);
+            VectorPayloadUpdateResult {
+                request_id: task.request_id,
+                error_message: Some(err_msg),
+            }
+        }
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "PAYLOAD_UPDATE_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn handle_vector_stats_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("stats");
+    let (cause, task) = match Envelope::<VectorStatsTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorStatsTask: {}", e);
+            error!("[STATS_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorStatsResult {
+                request_id: RequestId::default(),
+                collections: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "STATS_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    info!(
+        "[STATS_HANDLER] Processing VectorStatsTask (request_id: {}, model: {:?})",
+        task.request_id, task.model_name
+    );
+
+    let collection_names = match task.model_name.as_deref() {
+        Some(model_name) => Ok(vec![collections.collection_name(model_name)]),
+        None => collections.managed_collections().await,
+    };
+
+    let mut collection_stats = Vec::new();
+    let mut errors = Vec::new();
+
+    match collection_names {
+        Ok(names) => {
+            for collection_name in names {
+                match qdrant_client
+                    .collection_info(collection_name.as_str())
+                    .await
+                {
+                    Ok(response) => match response.result {
+                        Some(info) => collection_stats
+                            .push(collection_stats_from_info(&collection_name, info)),
+                        None => {
+                            errors.push(format!("{}: no collection info returned", collection_name))
+                        }
+                    },
+                    Err(e) => {
+                        metrics::qdrant_error("collection_info");
+                        error!(
+                            "[STATS_HANDLER_QDRANT_FAIL] Failed to get info for collection '{}': {}",
+                            collection_name, e
+                        );
+                        errors.push(format!("{}: {}", collection_name, e));
+                    }
+                }
+            }
+        }
+        Err(e) => {
+            error!("[STATS_HANDLER_QDRANT_FAIL] {:#}", e);
+            errors.push(format!("{:#}", e));
+        }
+    }
+
+    let result = VectorStatsResult {
+        request_id: task.request_id,
+        collections: collection_stats,
+        error_message: if errors.is_empty() {
+            None
+        } else {
+            Some(format!("Stats unavailable for: {}", errors.join("; ")))
+        },
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "STATS_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+async fn create_collection_snapshot(
+    client: &Qdrant,
+    collection_name: &str,
+) -> Result<VectorSnapshotInfo> {
+    let response = client
+        .create_snapshot(collection_name)
+        .await
+        .with_context(|| format!("Failed to create snapshot of '{}'", collection_name))?;
+    let description = response.snapshot_description.with_context(|| {
+        format!(
+            "Qdrant returned no snapshot description for '{}'",
+            collection_name
+        )
+    })?;
+
+    Ok(VectorSnapshotInfo {
+        collection_name: collection_name.to_string(),
+        location: format!(
+            "/collections/{}/snapshots/{}",
+            collection_name, description.name
+        ),
+        snapshot_name: description.name,
+        size_bytes: description.size.max(0) as u64,
+        created_at_ms: description.creation_time.map(|created| {
+            created.seconds.max(0) as u64 * 1000 + created.nanos.max(0) as u64 / 1_000_000
+        }),
+        checksum: description.checksum,
+    })
+}
+
+/// Snapshots the collection for `model_name`, or every managed collection when it is `None`.
+/// Returns the created snapshots and one error per collection that could not be snapshotted.
+async fn snapshot_collections(
+    qdrant_client: &Qdrant,
+    collections: &CollectionRegistry,
+    model_name: Option<&str>,
+) -> (Vec<VectorSnapshotInfo>, Vec<String>) {
+    let collection_names = match model_name {
+        Some(model_name) => vec![collections.collection_name(model_name)],
+        None => match collections.managed_collections().await {
+            Ok(names) => names,
+            Err(e) => return (vec![], vec![format!("{:#}", e)]),
+        },
+    };
+
+    let mut snapshots = Vec::with_capacity(collection_names.len());
+    let mut errors = Vec::new();
+
+    for collection_name in collection_names {
+        match create_collection_snapshot(qdrant_client, &collection_name).await {
+            Ok(snapshot) => {
+                info!(
+                    "[SNAPSHOT] Created snapshot '{}' of collection '{}' ({} bytes).",
+                    snapshot.snapshot_name, snapshot.collection_name, snapshot.size_bytes
+                );
+                snapshots.push(snapshot);
+            }
+            Err(e) => {
+                error!("[SNAPSHOT_FAIL] {:#}", e);
+                errors.push(format!("{}: {:#}", collection_name, e));
+            }
+        }
+    }
+
+    (snapshots, errors)
+}
+
+async fn handle_vector_snapshot_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client_for_reply: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("snapshot");
+    let (cause, task) = match Envelope::<VectorSnapshotTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorSnapshotTask: {}", e);
+            error!("[SNAPSHOT_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorSnapshotResult {
+                request_id: RequestId::default(),
+                snapshots: vec![],
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client_for_reply,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "SNAPSHOT_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    info!(
+        "[SNAPSHOT_HANDLER] Processing VectorSnapshotTask (request_id: {}, model: {:?})",
+        task.request_id, task.model_name
+    );
+
+    let (snapshots, errors) =
+        snapshot_collections(&qdrant_client, &collections, task.model_name.as_deref()).await;
+
+    let result = VectorSnapshotResult {
+        request_id: task.request_id,
+        snapshots,
+        error_message: if errors.is_empty() {
+            None
+        } else {
+            Some(format!("Snapshot failed for: {}", errors.join("; ")))
+        },
+    };
+
+    publish_reply(
+        &nats_client_for_reply,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &result,
+        "SNAPSHOT_HANDLER",
+    )
+    .await;
+
+    Ok(())
+}
+
+/// Periodically snapshots every managed collection. Started only when
+/// `QDRANT_SNAPSHOT_INTERVAL_SECS` is set to a non-zero value.
+async fn run_scheduled_snapshots(
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    interval: Duration,
+) {
+    info!(
+        "[SNAPSHOT_SCHEDULER] Taking scheduled snapshots every {:?}.",
+        interval
+    );
+    let mut ticker = tokio::time::interval(interval);
+    // The first tick completes immediately; skip it so startup does not trigger a snapshot.
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        let (snapshots, errors) = snapshot_collections(&qdrant_client, &collections, None).await;
+        if errors.is_empty() {
+            info!(
+                "[SNAPSHOT_SCHEDULER] Scheduled snapshot run created {} snapshot(s).",
+                snapshots.len()
+            );
+        } else {
+            error!(
+                "[SNAPSHOT_SCHEDULER] Scheduled snapshot run created {} snapshot(s), {} failed: {}",
+                snapshots.len(),
+                errors.len(),
+                errors.join("; ")
+            );
+        }
+    }
+}
+
+async fn count_points(qdrant_client: &Qdrant, collection_name: &str) -> Result<u64> {
+    let response = qdrant_client
+        .count(CountPointsBuilder::new(collection_name).exact(true))
+        .await
+        .with_context(|| format!("Failed to count points in '{}'", collection_name))?;
+    Ok(response.result.map(|r| r.count).unwrap_or(0))
+}
+
+/// Scrolls every point of `source_collection` and asks preprocessing_service to re-embed
+/// the sentences with the target model, one [`ReembedTextTask`] per document and page.
+/// Returns the number of tasks published.
+async fn request_reembedding(
+    qdrant_client: &Qdrant,
+    nats_client: &async_nats::Client,
+    cause: &Envelope<()>,
+    source_collection: &str,
+    task: &VectorReindexTask,
+) -> Result<u64> {
+    let jetstream = jetstream::new(nats_client.clone());
+    REEMBED_TASKS_STREAM.ensure(&jetstream).await?;
+    let mut tasks_published = 0u64;
+    let mut offset: Option<PointId> = None;
+
+    loop {
+        let mut scroll_request = ScrollPointsBuilder::new(source_collection)
+            .limit(REINDEX_SCAN_PAGE_SIZE)
+            .with_payload(true)
+            .with_vectors(false);
+        if let Some(offset) = offset.take() {
+            scroll_request = scroll_request.offset(offset);
+        }
+
+        let response = qdrant_client
+            .scroll(scroll_request)
+            .await
+            .with_context(|| format!("Failed to scroll '{}'", source_collection))?;
+
+        let payloads = response
+            .result
+            .iter()
+            .map(|point| qdrant_payload_from_map(&point.payload));
+        for reembed_task in reembed_tasks(task, payloads) {
+            // A reindex spans every tenant; each task is scoped to its document's.
+            let payload_json = cause
+                .follow_up(SERVICE_NAME, &reembed_task)
+                .with_tenant_id(reembed_task.tenant_id.clone())
+                .to_vec()
+                .context("Failed to serialize ReembedTextTask")?;
+            let mut headers = async_nats::HeaderMap::new();
+            insert_message_id(
+                &mut headers,
+                cause,
+                REEMBED_TEXT_TASK_SUBJECT,
+                &reembed_message_key(&reembed_task),
+            );
+            publish_durable(&jetstream, REEMBED_TEXT_TASK_SUBJECT, headers, payload_json)
+                .await
+                .with_context(|| {
+                    format!(
+                        "Failed to publish re-embedding task for document '{}'",
+                        reembed_task.original_id
+                    )
+                })?;
+            tasks_published += 1;
+        }
+
+        match response.next_page_offset {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(tasks_published)
+}
+
+/// Runs a reindex to completion: requests re-embedding of every stored sentence, waits until
+/// the target collection holds at least as many points as the source, then switches the
+/// active alias. Progress is reported on [`VECTOR_REINDEX_EVENT_SUBJECT`].
+async fn run_reindex(
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client: Arc<async_nats::Client>,
+    cause: &Envelope<()>,
+    task: VectorReindexTask,
+    mut progress: VectorReindexResult,
+) -> VectorReindexResult {
+    let outcome: Result<()> = async {
+        progress.source_points = count_points(&qdrant_client, &progress.source_collection).await?;
+        progress.documents_requested = request_reembedding(
+            &qdrant_client,
+            &nats_client,
+            cause,
+            &progress.source_collection,
+            &task,
+        )
+        .await?;
+        info!(
+            "[REINDEX] Requested re-embedding of {} document batch(es) ({} points) from '{}' into '{}'.",
+            progress.documents_requested,
+            progress.source_points,
+            progress.source_collection,
+            progress.target_collection
+        );
+
+        let timeout = Duration::from_secs(env_parse_or(
+            "QDRANT_REINDEX_TIMEOUT_SECS",
+            DEFAULT_REINDEX_TIMEOUT_SECS,
+        ));
+        let started = std::time::Instant::now();
+        loop {
+            progress.target_points =
+                count_points(&qdrant_client, &progress.target_collection).await?;
+            if progress.target_points >= progress.source_points {
+                break;
+            }
+            if started.elapsed() > timeout {
+                return Err(anyhow::anyhow!(
+                    "Timed out after {:?} with {} of {} points re-embedded",
+                    timeout,
+                    progress.target_points,
+                    progress.source_points
+                ));
+            }
+            tokio::time::sleep(REINDEX_POLL_INTERVAL).await;
+        }
+
+        collections
+            .switch_active_alias(&progress.target_collection)
+            .await
+    }
+    .await;
+
+    match outcome {
+        Ok(()) => {
+            info!(
+                "[REINDEX] Reindex {} completed: '{}' now serves requests without a model name.",
+                task.request_id, progress.target_collection
+            );
+            progress.status = "completed".to_string();
+        }
+        Err(e) => {
+            error!("[REINDEX_FAIL] Reindex {} failed: {:#}", task.request_id, e);
+            progress.status = "failed".to_string();
+            progress.error_message = Some(format!("{:#}", e));
+        }
+    }
+    progress
+}
+
+async fn handle_vector_reindex_task(
+    nats_msg: Message,
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    nats_client: Arc<async_nats::Client>,
+) -> Result<()> {
+    let _in_flight = metrics::track_in_flight("reindex");
+    let (cause, task) = match Envelope::<VectorReindexTask>::from_slice(&nats_msg.payload) {
+        Ok(envelope) => envelope.split(),
+        Err(e) => {
+            let err_msg = format!("Failed to deserialize VectorReindexTask: {}", e);
+            error!("[REINDEX_HANDLER_DESERIALIZE_FAIL] {}", err_msg);
+            let error_result = VectorReindexResult {
+                request_id: RequestId::default(),
+                status: "failed".to_string(),
+                source_collection: String::new(),
+                target_collection: String::new(),
+                documents_requested: 0,
+                source_points: 0,
+                target_points: 0,
+                error_message: Some(err_msg.clone()),
+            };
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                None,
+                SERVICE_NAME,
+                &error_result,
+                "REINDEX_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    info!(
+        "[REINDEX_HANDLER] Processing VectorReindexTask (request_id: {}, source model: {:?}, target model: '{}', target dim: {})",
+        task.request_id, task.source_model_name, task.target_model_name, task.target_vector_dim
+    );
+
+    let mut progress = VectorReindexResult {
+        request_id: task.request_id,
+        status: "started".to_string(),
+        source_collection: String::new(),
+        target_collection: String::new(),
+        documents_requested: 0,
+        source_points: 0,
+        target_points: 0,
+        error_message: None,
+    };
+
+    let prepared: Result<(String, String)> = async {
+        let source_collection = match task.source_model_name.as_deref() {
+            Some(model_name) => collections.collection_name(model_name),
+            None => collections.active_collection().await?.with_context(|| {
+                format!("Alias '{}' does not exist", collections.active_alias())
+            })?,
+        };
+        let target_collection = collections
+            .ensure_for_model(&task.target_model_name, task.target_vector_dim)
+            .await?;
+        if source_collection == target_collection {
+            return Err(anyhow::anyhow!(
+                "Source and target collection are both '{}'",
+                target_collection
+            ));
+        }
+        Ok((source_collection, target_collection))
+    }
+    .await;
+
+    let (source_collection, target_collection) = match prepared {
+        Ok(collections_pair) => collections_pair,
+        Err(e) => {
+            let err_msg = format!("Reindex request_id {} rejected: {:#}", task.request_id, e);
+            error!("[REINDEX_HANDLER_FAIL] {}", err_msg);
+            progress.status = "failed".to_string();
+            progress.error_message = Some(err_msg.clone());
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                Some(&cause),
+                SERVICE_NAME,
+                &progress,
+                "REINDEX_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+    progress.source_collection = source_collection;
+    progress.target_collection = target_collection;
+
+    // Only one migration may run at a time, across every replica.
+    let lock = match acquire_reindex_lock(&jetstream::new((*nats_client).clone()), task.request_id)
+        .await
+    {
+        Ok(Ok(lock)) => Ok(lock),
+        Ok(Err(holder)) => Err(format!(
+            "Reindex request_id {} rejected: reindex {} is already running",
+            task.request_id, holder
+        )),
+        Err(e) => Err(format!(
+            "Reindex request_id {} rejected: {:#}",
+            task.request_id, e
+        )),
+    };
+    let lock = match lock {
+        Ok(lock) => lock,
+        Err(err_msg) => {
+            warn!("[REINDEX_HANDLER] {}", err_msg);
+            progress.status = "failed".to_string();
+            progress.error_message = Some(err_msg.clone());
+            publish_reply(
+                &nats_client,
+                nats_msg.reply,
+                Some(&cause),
+                SERVICE_NAME,
+                &progress,
+                "REINDEX_HANDLER",
+            )
+            .await;
+            return Err(anyhow::anyhow!(err_msg));
+        }
+    };
+
+    publish_reply(
+        &nats_client,
+        nats_msg.reply,
+        Some(&cause),
+        SERVICE_NAME,
+        &progress,
+        "REINDEX_HANDLER",
+    )
+    .await;
+
+    let final_result = run_reindex(
+        Arc::clone(&qdrant_client),
+        Arc::clone(&collections),
+        Arc::clone(&nats_client),
+        &cause,
+        task,
+        progress,
+    )
+    .await;
+    lock.release().await;
+
+    match cause.follow_up(SERVICE_NAME, &final_result).to_vec() {
+        Ok(payload_json) => {
+            if let Err(e) = nats_client
+                .publish(VECTOR_REINDEX_EVENT_SUBJECT, payload_json.into())
+                .await
+            {
+                error!(
+                    "[REINDEX_EVENT_PUBLISH_FAIL] Failed to publish reindex result for request_id {}: {}",
+                    final_result.request_id, e
+                );
+            }
+        }
+        Err(e) => {
+            error!(
+                "[REINDEX_EVENT_SERIALIZE_FAIL] Failed to serialize reindex result for request_id {}: {}",
+                final_result.request_id, e
+            );
+        }
+    }
+
+    match final_result.error_message {
+        Some(err_msg) => Err(anyhow::anyhow!(err_msg)),
+        None => Ok(()),
+    }
+}
+
+/// Times a collection info call against Qdrant on the active collection.
+async fn check_qdrant(qdrant_client: &Qdrant, collections: &CollectionRegistry) -> DependencyCheck {
+    let collection_name = collections.active_alias();
+    let started = std::time::Instant::now();
+    let result = qdrant_client
+        .collection_info(collection_name.as_str())
+        .await
+        .map(|_| ())
+        .map_err(|e| format!("collection info on '{}' failed: {}", collection_name, e));
+    let latency = started.elapsed();
+    if let Err(e) = &result {
+        warn!(
+            "[HEALTH_CHECK] Qdrant check failed after {:?}: {}",
+            latency, e
+        );
+    }
+    DependencyCheck::timed("qdrant", latency, result)
+}
+
+/// Deletes the points of `collection_name` that have outlived their retention window.
+/// Returns the number of deleted points when it is known (per-source rules), or `None`
+/// when a plain filter delete was issued.
+async fn apply_retention(
+    qdrant_client: &Qdrant,
+    collection_name: &str,
+    policy: &RetentionPolicy,
+) -> Result<Option<u64>> {
+    let Some(shortest_max_age) = policy.shortest_max_age() else {
+        return Ok(Some(0));
+    };
+    let now = Timestamp::now();
+    let candidate_cutoff = now.saturating_sub(shortest_max_age);
+    let candidate_filter = Filter::must([Condition::range(
+        "processed_at_ms",
+        Range {
+            lt: Some(candidate_cutoff.as_millis() as f64),
+            ..Default::default()
+        },
+    )]);
+
+    // Without per-source rules every point shares one cutoff, so Qdrant can delete by filter.
+    if policy.rules.is_empty() {
+        qdrant_client
+            .delete_points(
+                DeletePointsBuilder::new(collection_name)
+                    .points(candidate_filter)
+                    .wait(true),
+            )
+            .await
+            .with_context(|| {
+                format!("Failed to delete expired points from '{}'", collection_name)
+            })?;
+        return Ok(None);
+    }
+
+    let mut deleted = 0u64;
+    let mut offset: Option<PointId> = None;
+    loop {
+        let mut scroll_request = ScrollPointsBuilder::new(collection_name)
+            .filter(candidate_filter.clone())
+            .limit(RETENTION_SCAN_PAGE_SIZE)
+            .with_payload(PayloadIncludeSelector::new(vec![
+                "source_url".to_string(),
+                "processed_at_ms".to_string(),
+            ]))
+            .with_vectors(false);
+        if let Some(offset) = offset.take() {
+            scroll_request = scroll_request.offset(offset);
+        }
+
+        let response = qdrant_client
+            .scroll(scroll_request)
+            .await
+            .with_context(|| format!("Failed to scan '{}' for expired points", collection_name))?;
+
+        let expired_ids: Vec<PointId> = response
+            .result
+            .into_iter()
+            .filter(|point| {
+                let source_url = payload_string(&point.payload, "source_url").unwrap_or_default();
+                let processed_at = Timestamp::from_millis(
+                    payload_integer(&point.payload, "processed_at_ms").unwrap_or(0) as u64,
+                );
+                policy.is_expired(&source_url, processed_at, now)
+            })
+            .filter_map(|point| point.id)
+            .collect();
+
+        if !expired_ids.is_empty() {
+            let expired_count = expired_ids.len() as u64;
+            qdrant_client
+                .delete_points(
+                    DeletePointsBuilder::new(collection_name)
+                        .points(expired_ids)
+                        .wait(true),
+                )
+                .await
+                .with_context(|| {
+                    format!("Failed to delete expired points from '{}'", collection_name)
+                })?;
+            deleted += expired_count;
+        }
+
+        match response.next_page_offset {
+            Some(next) => offset = Some(next),
+            None => break,
+        }
+    }
+
+    Ok(Some(deleted))
+}
+
+async fn run_retention_cleanup(
+    qdrant_client: Arc<Qdrant>,
+    collections: Arc<CollectionRegistry>,
+    policy: RetentionPolicy,
+) {
+    info!(
+        "[RETENTION] Applying retention policy every {:?}.",
+        policy.interval
+    );
+    let mut ticker = tokio::time::interval(policy.interval);
+
+    loop {
+        ticker.tick().await;
+        let collection_names = match collections.managed_collections().await {
+            Ok(names) => names,
+            Err(e) => {
+                error!("[RETENTION_FAIL] Could not list collections: {:#}", e);
+                continue;
+            }
+        };
+
+        for collection_name in collection_names {
+            match apply_retention(&qdrant_client, &collection_name, &policy).await {
+                Ok(Some(deleted)) => {
+                    info!(
+                        "[RETENTION] Deleted {} expired point(s) from '{}'.",
+                        deleted, collection_name
+                    );
+                }
+                Ok(None) => {
+                    info!(
+                        "[RETENTION] Deleted expired points from '{}'.",
+                        collection_name
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "[RETENTION_FAIL] Retention cleanup of '{}' failed: {:#}",
+                        collection_name, e
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let settings = Settings::load(SERVICE_NAME)?;
+    let _telemetry = shared_telemetry::init(
+        SERVICE_NAME,
+        "info,vector_memory_service=debug,qdrant_client=info",
+        &settings,
+        metrics::render,
+    )?;
+
+    let drain_timeout = settings.shutdown.drain_timeout();
+    let shutdown = Shutdown::listen();
+
+    let nats_client = Arc::new(shared_nats::connect(&settings.nats).await?);
+    info!("[NATS_CONNECT_SUCCESS] Successfully connected to NATS!");
+
+    // Long enough to cover a full round of upsert retries before the server redelivers.
+    let consumer_config = ConsumerConfig::new(SERVICE_NAME, TEXT_WITH_EMBEDDINGS_SUBJECT)
+        .with_ack_wait(Duration::from_secs(120))
+        .with_env_overrides(&EMBEDDINGS_STREAM);
+    let jetstream = jetstream::new((*nats_client).clone());
+    let mut embeddings_messages =
+        durable_messages(&jetstream, &EMBEDDINGS_STREAM, &consumer_config)
+            .await?
+            .take_until(shutdown.signalled());
+    DEAD_LETTERS_STREAM.ensure(&jetstream).await?;
+
+    let qdrant_uri = &settings.qdrant.uri;
+
+    info!(
+        "[QDRANT_CONNECT] Attempting to connect to Qdrant at URI: {}",
+        qdrant_uri
+    );
+
+    let (qdrant_client, _) = retry_with_backoff(
+        &RetryPolicy::from_env("QDRANT_CONNECT", DEFAULT_CONNECT_RETRY),
+        "Creating the Qdrant client",
+        || async { Qdrant::from_url(qdrant_uri).build() },
+        |_| true,
+    )
+    .await;
+    let qdrant_client_arc = Arc::new(qdrant_client.inspect_err(|e| {
+        error!(
+            "[QDRANT_CONNECT_FATAL] Failed to create the Qdrant client: {}",
+            e
+        );
+    })?);
+    info!("[QDRANT_CONNECT_SUCCESS] Successfully created Qdrant client.");
 
-        tokio::spawn(async move {
-            if let Err(e) =
-                handle_semantic_search_task(message, q_client_clone, n_client_clone).await
+    let collection_config = CollectionConfig::from_env(&settings.qdrant);
+    let upsert_config = UpsertConfig::from_env();
+    let search_settings = SearchSettings::from_env();
+    let default_vector_dim = collection_config.default_vector_dim;
+    let collection_registry = Arc::new(CollectionRegistry::new(
+        Arc::clone(&qdrant_client_arc),
+        collection_config,
+    ));
+
+    match collection_registry
+        .ensure_for_model(DEFAULT_EMBEDDING_MODEL, default_vector_dim)
+        .await
+    {
+        Ok(default_collection) => {
+            if let Err(e) = collection_registry
+                .ensure_active_alias(&default_collection)
+                .await
             {
                 error!(
-                    "[HANDLER_ERROR_SEARCH] Error processing search task: {:?}",
+                    "[QDRANT_SETUP_FATAL] Failed to ensure alias '{}': {:#}. Requests without a model name will fail until it exists.",
+                    collection_registry.active_alias(),
                     e
                 );
             }
-        });
+        }
+        Err(e) => {
+            error!(
+                "[QDRANT_SETUP_FATAL] Failed to ensure Qdrant collection for default model '{}': {}. It will be retried on first ingest.",
+                DEFAULT_EMBEDDING_MODEL, e
+            );
+        }
+    }
+
+    let qdrant_client_for_storage_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_storage_task = Arc::clone(&collection_registry);
+    let nats_client_for_storage_task = Arc::clone(&nats_client);
+    let shutdown_for_storage_task = shutdown.clone();
+    let recent_embeddings = RecentMessages::from_env();
+    let storage_workers =
+        WorkerPool::from_env("embeddings", DEFAULT_STORAGE_WORKERS, OverflowPolicy::Wait);
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_STORAGE] Waiting for messages with text embeddings...");
+
+        while let Some(next) = embeddings_messages.next().await {
+            let message = match next {
+                Ok(message) => message,
+                Err(e) => {
+                    error!(
+                        "[NATS_MSG_RECV_FAIL_STORAGE] Failed to receive message from JetStream consumer: {}",
+                        e
+                    );
+                    continue;
+                }
+            };
+            info!(
+                "[NATS_MSG_RECV_STORAGE] Received message on subject: {}",
+                message.subject
+            );
+
+            let header = |name: &str| {
+                message
+                    .headers
+                    .as_ref()
+                    .and_then(|headers| headers.get(name))
+                    .map(|value| value.as_str())
+            };
+            let decoded = decode_body(&message.payload, header(CONTENT_ENCODING_HEADER))
+                .map_err(|e| e.to_string())
+                .and_then(|body| {
+                    Envelope::<TextWithEmbeddingsMessage>::decode_with_content_type(
+                        &body,
+                        header(CONTENT_TYPE_HEADER),
+                    )
+                    .map_err(|e| e.to_string())
+                });
+            match decoded {
+                Ok(envelope) => {
+                    let (cause, embeddings_msg) = envelope.split();
+                    info!(
+                        "[TASK_DESERIALIZED_STORAGE] Deserialized TextWithEmbeddingsMessage (original_id: {})",
+                        embeddings_msg.original_id
+                    );
+                    let Some(claim) = recent_embeddings.claim_delivery(&message, &cause).await
+                    else {
+                        continue;
+                    };
+                    let Some(worker) = storage_workers.reserve_for(&message).await else {
+                        continue;
+                    };
+                    let qdrant_client_clone = Arc::clone(&qdrant_client_for_storage_task);
+                    let collections_clone = Arc::clone(&collection_registry_for_storage_task);
+                    let nats_client_clone = Arc::clone(&nats_client_for_storage_task);
+                    let span = receive_span(&message.subject, message.headers.as_ref());
+                    let in_flight = shutdown_for_storage_task.track();
+                    worker.spawn(
+                        async move {
+                        let _in_flight = in_flight;
+                        let original_id = embeddings_msg.original_id;
+                        let started = TaskStatusChangedMessage::new(
+                            &cause,
+                            PipelineStage::Storage,
+                            TaskStatus::Started,
+                        )
+                        .with_original_id(original_id);
+                        publish_task_status(&nats_client_clone, &cause, started).await;
+
+                        let status = match handle_text_with_embeddings_message(
+                            embeddings_msg,
+                            cause.clone(),
+                            qdrant_client_clone,
+                            collections_clone,
+                            Arc::clone(&nats_client_clone),
+                            upsert_config,
+                        )
+                        .await
+                        {
+                            Ok(()) => TaskStatusChangedMessage::new(
+                                &cause,
+                                PipelineStage::Storage,
+                                TaskStatus::Completed,
+                            ),
+                            Err(e) => {
+                                error!(
+                                    "[HANDLER_ERROR_STORAGE] Error processing storage message: {:?}",
+                                    e
+                                );
+                                TaskStatusChangedMessage::new(
+                                    &cause,
+                                    PipelineStage::Storage,
+                                    TaskStatus::Failed,
+                                )
+                                .with_detail(format!("{:#}", e))
+                            }
+                        };
+                        publish_task_status(
+                            &nats_client_clone,
+                            &cause,
+                            status.with_original_id(original_id),
+                        )
+                        .await;
+                        // Failed messages have already been dead-lettered by the handler, so
+                        // the delivery is acked either way. Only a crash before this point
+                        // leaves it unacked and gets it redelivered after ack_wait.
+                        if let Err(e) = message.ack().await {
+                            error!(
+                                "[NATS_ACK_FAIL_STORAGE] Failed to ack embeddings message: {}",
+                                e
+                            );
+                        }
+                        claim.handled();
+                    }
+                    .instrument(span),
+                    );
+                }
+                Err(e) => {
+                    warn!(
+                        "[TASK_DESERIALIZE_FAIL_STORAGE] Failed to deserialize TextWithEmbeddingsMessage: {}. Payload (first 100 bytes): {:?}",
+                        e,
+                        message.payload.get(..100)
+                    );
+                    publish_pipeline_error(
+                        &nats_client_for_storage_task,
+                        None,
+                        PipelineErrorMessage::new(
+                            PipelineStage::Storage,
+                            PipelineErrorKind::InvalidMessage,
+                            format!("Invalid TextWithEmbeddingsMessage: {}", e),
+                        ),
+                    )
+                    .await;
+                    let undecoded = UndecodedPayload::new(&message.payload)
+                        .with_header(CONTENT_TYPE_HEADER, header(CONTENT_TYPE_HEADER))
+                        .with_header(CONTENT_ENCODING_HEADER, header(CONTENT_ENCODING_HEADER));
+                    dead_letter_undecodable(
+                        &jetstream::new((*nats_client_for_storage_task).clone()),
+                        SERVICE_NAME,
+                        &message,
+                        undecoded,
+                        e,
+                    )
+                    .await;
+                }
+            }
+        }
+
+        info!("[NATS_LOOP_STORAGE_END] Embeddings storage subscription ended.");
+    });
+
+    // Shared by every request handler. A refused request is left to time out on the
+    // requester's side.
+    let request_workers = WorkerPool::from_env(
+        "vector_requests",
+        DEFAULT_REQUEST_WORKERS,
+        OverflowPolicy::Reject,
+    );
+
+    // Replicas split requests, so each reindex, snapshot or delete runs once and is
+    // answered once.
+    let request_queue_group = queue_group_from_env("VECTOR_MEMORY_QUEUE_GROUP", SERVICE_NAME);
+    info!("[NATS_SUB] Request queue group: {:?}", request_queue_group);
+
+    let mut search_task_subscriber = subscribe_shared(
+        &nats_client,
+        SEMANTIC_SEARCH_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            SEMANTIC_SEARCH_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for semantic search tasks",
+        SEMANTIC_SEARCH_TASK_SUBJECT
+    );
+
+    let mut scroll_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_SCROLL_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_SCROLL_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for scroll tasks",
+        VECTOR_SCROLL_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_scroll_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_scroll_task = Arc::clone(&collection_registry);
+    let nats_client_for_scroll_reply = Arc::clone(&nats_client);
+    let shutdown_for_scroll_task = shutdown.clone();
+    let workers_for_scroll_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_SCROLL] Waiting for scroll tasks...");
+        while let Some(message) = scroll_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_scroll_task);
+            let collections_clone = Arc::clone(&collection_registry_for_scroll_task);
+            let n_client_clone = Arc::clone(&nats_client_for_scroll_reply);
+
+            let in_flight = shutdown_for_scroll_task.track();
+            let _ = workers_for_scroll_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_vector_scroll_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                    )
+                    .await
+                    {
+                        error!(
+                            "[HANDLER_ERROR_SCROLL] Error processing scroll task: {:?}",
+                            e
+                        );
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_SCROLL_END] Scroll subscription ended.");
+    });
+
+    let mut count_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_COUNT_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_COUNT_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for count tasks",
+        VECTOR_COUNT_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_count_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_count_task = Arc::clone(&collection_registry);
+    let nats_client_for_count_reply = Arc::clone(&nats_client);
+    let shutdown_for_count_task = shutdown.clone();
+    let workers_for_count_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_COUNT] Waiting for count tasks...");
+        while let Some(message) = count_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_count_task);
+            let collections_clone = Arc::clone(&collection_registry_for_count_task);
+            let n_client_clone = Arc::clone(&nats_client_for_count_reply);
+
+            let in_flight = shutdown_for_count_task.track();
+            let _ = workers_for_count_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_vector_count_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                    )
+                    .await
+                    {
+                        error!("[HANDLER_ERROR_COUNT] Error processing count task: {:?}", e);
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_COUNT_END] Count subscription ended.");
+    });
+
+    let mut payload_update_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_PAYLOAD_UPDATE_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_PAYLOAD_UPDATE_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for payload update tasks",
+        VECTOR_PAYLOAD_UPDATE_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_payload_update_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_payload_update_task = Arc::clone(&collection_registry);
+    let nats_client_for_payload_update_reply = Arc::clone(&nats_client);
+    let shutdown_for_payload_update_task = shutdown.clone();
+    let workers_for_payload_update_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_PAYLOAD_UPDATE] Waiting for payload update tasks...");
+        while let Some(message) = payload_update_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_payload_update_task);
+            let collections_clone = Arc::clone(&collection_registry_for_payload_update_task);
+            let n_client_clone = Arc::clone(&nats_client_for_payload_update_reply);
+
+            let in_flight = shutdown_for_payload_update_task.track();
+            let _ = workers_for_payload_update_task.spawn_limited(async move {
+                let _in_flight = in_flight;
+                if let Err(e) = handle_vector_payload_update_task(
+                    message,
+                    q_client_clone,
+                    collections_clone,
+                    n_client_clone,
+                )
+                .await
+                {
+                    error!(
+                        "[HANDLER_ERROR_PAYLOAD_UPDATE] Error processing payload update task: {:?}",
+                        e
+                    );
+                }
+            })
+            .await;
+        }
+        info!("[NATS_LOOP_PAYLOAD_UPDATE_END] Payload update subscription ended.");
+    });
+
+    let mut snapshot_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_SNAPSHOT_CONTROL_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_SNAPSHOT_CONTROL_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for snapshot requests",
+        VECTOR_SNAPSHOT_CONTROL_SUBJECT
+    );
+
+    let qdrant_client_for_snapshot_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_snapshot_task = Arc::clone(&collection_registry);
+    let nats_client_for_snapshot_reply = Arc::clone(&nats_client);
+    let shutdown_for_snapshot_task = shutdown.clone();
+    let workers_for_snapshot_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_SNAPSHOT] Waiting for snapshot requests...");
+        while let Some(message) = snapshot_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_snapshot_task);
+            let collections_clone = Arc::clone(&collection_registry_for_snapshot_task);
+            let n_client_clone = Arc::clone(&nats_client_for_snapshot_reply);
+
+            let in_flight = shutdown_for_snapshot_task.track();
+            let _ = workers_for_snapshot_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_vector_snapshot_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                    )
+                    .await
+                    {
+                        error!(
+                            "[HANDLER_ERROR_SNAPSHOT] Error processing snapshot request: {:?}",
+                            e
+                        );
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_SNAPSHOT_END] Snapshot subscription ended.");
+    });
+
+    let mut reindex_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_REINDEX_CONTROL_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_REINDEX_CONTROL_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for reindex requests",
+        VECTOR_REINDEX_CONTROL_SUBJECT
+    );
+
+    let qdrant_client_for_reindex_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_reindex_task = Arc::clone(&collection_registry);
+    let nats_client_for_reindex_task = Arc::clone(&nats_client);
+    let shutdown_for_reindex_task = shutdown.clone();
+    let workers_for_reindex_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_REINDEX] Waiting for reindex requests...");
+        while let Some(message) = reindex_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_reindex_task);
+            let collections_clone = Arc::clone(&collection_registry_for_reindex_task);
+            let n_client_clone = Arc::clone(&nats_client_for_reindex_task);
+
+            let in_flight = shutdown_for_reindex_task.track();
+            let _ = workers_for_reindex_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_vector_reindex_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                    )
+                    .await
+                    {
+                        error!(
+                            "[HANDLER_ERROR_REINDEX] Error processing reindex request: {:?}",
+                            e
+                        );
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_REINDEX_END] Reindex subscription ended.");
+    });
+
+    let mut recommend_task_subscriber = subscribe_shared(
+        &nats_client,
+        RECOMMEND_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            RECOMMEND_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for recommendation tasks",
+        RECOMMEND_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_recommend_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_recommend_task = Arc::clone(&collection_registry);
+    let nats_client_for_recommend_reply = Arc::clone(&nats_client);
+    let shutdown_for_recommend_task = shutdown.clone();
+    let workers_for_recommend_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_RECOMMEND] Waiting for recommendation tasks...");
+        while let Some(message) = recommend_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_recommend_task);
+            let collections_clone = Arc::clone(&collection_registry_for_recommend_task);
+            let n_client_clone = Arc::clone(&nats_client_for_recommend_reply);
+
+            let in_flight = shutdown_for_recommend_task.track();
+            let _ = workers_for_recommend_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_recommend_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                        search_settings,
+                    )
+                    .await
+                    {
+                        error!(
+                            "[HANDLER_ERROR_RECOMMEND] Error processing recommendation task: {:?}",
+                            e
+                        );
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_RECOMMEND_END] Recommendation subscription ended.");
+    });
+
+    let mut search_batch_task_subscriber = subscribe_shared(
+        &nats_client,
+        SEMANTIC_SEARCH_BATCH_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            SEMANTIC_SEARCH_BATCH_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for batched semantic search tasks",
+        SEMANTIC_SEARCH_BATCH_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_search_batch_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_search_batch_task = Arc::clone(&collection_registry);
+    let nats_client_for_search_batch_reply = Arc::clone(&nats_client);
+    let shutdown_for_search_batch_task = shutdown.clone();
+    let workers_for_search_batch_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_SEARCH_BATCH] Waiting for batched semantic search tasks...");
+        while let Some(message) = search_batch_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_search_batch_task);
+            let collections_clone = Arc::clone(&collection_registry_for_search_batch_task);
+            let n_client_clone = Arc::clone(&nats_client_for_search_batch_reply);
+
+            let in_flight = shutdown_for_search_batch_task.track();
+            let _ = workers_for_search_batch_task.spawn_limited(async move {
+                let _in_flight = in_flight;
+                if let Err(e) = handle_semantic_search_batch_task(
+                    message,
+                    q_client_clone,
+                    collections_clone,
+                    n_client_clone,
+                    search_settings,
+                )
+                .await
+                {
+                    error!(
+                        "[HANDLER_ERROR_SEARCH_BATCH] Error processing batched search task: {:?}",
+                        e
+                    );
+                }
+            })
+            .await;
+        }
+        info!("[NATS_LOOP_SEARCH_BATCH_END] Batched semantic search subscription ended.");
+    });
+
+    let mut stats_task_subscriber = subscribe_shared(
+        &nats_client,
+        VECTOR_STATS_TASK_SUBJECT,
+        request_queue_group.as_deref(),
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "Failed to subscribe to NATS subject {}",
+            VECTOR_STATS_TASK_SUBJECT
+        )
+    })?
+    .take_until(shutdown.signalled());
+    info!(
+        "[NATS_SUB_SUCCESS] Subscribed to subject: {} for stats requests",
+        VECTOR_STATS_TASK_SUBJECT
+    );
+
+    let qdrant_client_for_stats_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_stats_task = Arc::clone(&collection_registry);
+    let nats_client_for_stats_reply = Arc::clone(&nats_client);
+    let shutdown_for_stats_task = shutdown.clone();
+    let workers_for_stats_task = request_workers.clone();
+    tokio::spawn(async move {
+        info!("[NATS_LOOP_STATS] Waiting for stats requests...");
+        while let Some(message) = stats_task_subscriber.next().await {
+            let q_client_clone = Arc::clone(&qdrant_client_for_stats_task);
+            let collections_clone = Arc::clone(&collection_registry_for_stats_task);
+            let n_client_clone = Arc::clone(&nats_client_for_stats_reply);
+
+            let in_flight = shutdown_for_stats_task.track();
+            let _ = workers_for_stats_task
+                .spawn_limited(async move {
+                    let _in_flight = in_flight;
+                    if let Err(e) = handle_vector_stats_task(
+                        message,
+                        q_client_clone,
+                        collections_clone,
+                        n_client_clone,
+                    )
+                    .await
+                    {
+                        error!(
+                            "[HANDLER_ERROR_STATS] Error processing stats request: {:?}",
+                            e
+                        );
+                    }
+                })
+                .await;
+        }
+        info!("[NATS_LOOP_STATS_END] Stats subscription ended.");
+    });
+
+    let qdrant_client_for_health = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_health = Arc::clone(&collection_registry);
+    serve_health((*nats_client).clone(), SERVICE_NAME, move || {
+        let qdrant_client = Arc::clone(&qdrant_client_for_health);
+        let collections = Arc::clone(&collection_registry_for_health);
+        async move { vec![check_qdrant(&qdrant_client, &collections).await] }
+    })
+    .await
+    .context("Failed to subscribe to health checks")?;
+
+    let snapshot_interval_secs: u64 = env_parse_or("QDRANT_SNAPSHOT_INTERVAL_SECS", 0);
+    if snapshot_interval_secs > 0 {
+        tokio::spawn(run_scheduled_snapshots(
+            Arc::clone(&qdrant_client_arc),
+            Arc::clone(&collection_registry),
+            Duration::from_secs(snapshot_interval_secs),
+        ));
+    }
+
+    if let Some(retention_policy) = RetentionPolicy::from_env() {
+        tokio::spawn(run_retention_cleanup(
+            Arc::clone(&qdrant_client_arc),
+            Arc::clone(&collection_registry),
+            retention_policy,
+        ));
+    }
+
+    let qdrant_client_for_search_task = Arc::clone(&qdrant_client_arc);
+    let collection_registry_for_search_task = Arc::clone(&collection_registry);
+    let nats_client_for_search_reply = Arc::clone(&nats_client);
+
+    info!("[NATS_LOOP_SEARCH] Waiting for semantic search tasks...");
+    while let Some(message) = search_task_subscriber.next().await {
+        info!(
+            "[NATS_MSG_RECV_SEARCH] Received search task on subject: {}",
+            message.subject
+        );
+        let q_client_clone = Arc::clone(&qdrant_client_for_search_task);
+        let collections_clone = Arc::clone(&collection_registry_for_search_task);
+        let n_client_clone = Arc::clone(&nats_client_for_search_reply);
+        let in_flight = shutdown.track();
+
+        let _ = request_workers
+            .spawn_limited(async move {
+                let _in_flight = in_flight;
+                if let Err(e) = handle_semantic_search_task(
+                    message,
+                    q_client_clone,
+                    collections_clone,
+                    n_client_clone,
+                    search_settings,
+                )
+                .await
+                {
+                    error!(
+                        "[HANDLER_ERROR_SEARCH] Error processing search task: {:?}",
+                        e
+                    );
+                }
+            })
+            .await;
     }
     info!("[NATS_LOOP_SEARCH_END] Semantic search subscription ended.");
 
+    shutdown.drain(&nats_client, drain_timeout).await;
     Ok(())
 }
@@ -0,0 +1,174 @@
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const LATENCY_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug, Clone, Default)]
+struct Histogram {
+    /// Cumulative: `buckets[i]` counts observations `<= LATENCY_BUCKETS[i]`.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        for (bucket, upper_bound) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if secs <= upper_bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+
+    fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        for (count, upper_bound) in self.buckets.iter().zip(LATENCY_BUCKETS) {
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, upper_bound, count
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}{}le=\"+Inf\"}} {}",
+            name, labels, separator, self.count
+        );
+        let braced = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, braced, self.sum);
+        let _ = writeln!(out, "{}_count{} {}", name, braced, self.count);
+    }
+}
+
+#[derive(Default)]
+struct Registry {
+    upsert_duration: Mutex<Histogram>,
+    search_duration: Mutex<BTreeMap<&'static str, Histogram>>,
+    points_written: AtomicU64,
+    qdrant_errors: Mutex<BTreeMap<&'static str, u64>>,
+    in_flight: Mutex<BTreeMap<&'static str, i64>>,
+}
+
+static REGISTRY: LazyLock<Registry> = LazyLock::new(Registry::default);
+
+/// Latency of one Qdrant upsert request (a single batch attempt).
+pub fn observe_upsert(elapsed: Duration) {
+    if let Ok(mut histogram) = REGISTRY.upsert_duration.lock() {
+        histogram.observe(elapsed);
+    }
+}
+
+pub fn add_points_written(count: usize) {
+    REGISTRY
+        .points_written
+        .fetch_add(count as u64, Ordering::Relaxed);
+}
+
+/// Latency of the Qdrant request(s) behind one search-type handler invocation.
+pub fn observe_search(handler: &'static str, elapsed: Duration) {
+    if let Ok(mut histograms) = REGISTRY.search_duration.lock() {
+        histograms.entry(handler).or_default().observe(elapsed);
+    }
+}
+
+pub fn qdrant_error(operation: &'static str) {
+    if let Ok(mut errors) = REGISTRY.qdrant_errors.lock() {
+        *errors.entry(operation).or_default() += 1;
+    }
+}
+
+/// Counts a handler invocation as in flight until the returned guard is dropped.
+pub fn track_in_flight(handler: &'static str) -> InFlightGuard {
+    if let Ok(mut in_flight) = REGISTRY.in_flight.lock() {
+        *in_flight.entry(handler).or_default() += 1;
+    }
+    InFlightGuard { handler }
+}
+
+pub struct InFlightGuard {
+    handler: &'static str,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        if let Ok(mut in_flight) = REGISTRY.in_flight.lock() {
+            *in_flight.entry(self.handler).or_default() -= 1;
+        }
+    }
+}
+
+/// Current values in the Prometheus text exposition format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    out.push_str(
+        "# HELP vector_memory_upsert_duration_seconds Latency of Qdrant upsert requests.\n",
+    );
+    out.push_str("# TYPE vector_memory_upsert_duration_seconds histogram\n");
+    if let Ok(histogram) = REGISTRY.upsert_duration.lock() {
+        histogram.render(&mut out, "vector_memory_upsert_duration_seconds", "");
+    }
+
+    out.push_str("# HELP vector_memory_points_written_total Points upserted into Qdrant.\n");
+    out.push_str("# TYPE vector_memory_points_written_total counter\n");
+    let _ = writeln!(
+        out,
+        "vector_memory_points_written_total {}",
+        REGISTRY.points_written.load(Ordering::Relaxed)
+    );
+
+    out.push_str(
+        "# HELP vector_memory_search_duration_seconds Latency of Qdrant search requests by handler.\n",
+    );
+    out.push_str("# TYPE vector_memory_search_duration_seconds histogram\n");
+    if let Ok(histograms) = REGISTRY.search_duration.lock() {
+        for (handler, histogram) in histograms.iter() {
+            histogram.render(
+                &mut out,
+                "vector_memory_search_duration_seconds",
+                &format!("handler=\"{}\"", handler),
+            );
+        }
+    }
+
+    out.push_str("# HELP vector_memory_qdrant_errors_total Failed Qdrant requests by operation.\n");
+    out.push_str("# TYPE vector_memory_qdrant_errors_total counter\n");
+    if let Ok(errors) = REGISTRY.qdrant_errors.lock() {
+        for (operation, count) in errors.iter() {
+            let _ = writeln!(
+                out,
+                "vector_memory_qdrant_errors_total{{operation=\"{}\"}} {}",
+                operation, count
+            );
+        }
+    }
+
+    out.push_str(
+        "# HELP vector_memory_handlers_in_flight NATS messages currently being handled.\n",
+    );
+    out.push_str("# TYPE vector_memory_handlers_in_flight gauge\n");
+    if let Ok(in_flight) = REGISTRY.in_flight.lock() {
+        for (handler, count) in in_flight.iter() {
+            let _ = writeln!(
+                out,
+                "vector_memory_handlers_in_flight{{handler=\"{}\"}} {}",
+                handler, count
+            );
+        }
+    }
+
+    out
+}
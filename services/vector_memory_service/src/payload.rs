@@ -0,0 +1,117 @@
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::value::Kind;
+use qdrant_client::qdrant::{GroupId, PointId, Value, group_id};
+use shared_models::{DocumentId, DocumentMetadata, QdrantPointPayload};
+use std::collections::HashMap;
+
+pub fn point_id_to_string(point_id: Option<PointId>) -> Option<String> {
+    match point_id?.point_id_options? {
+        PointIdOptions::Uuid(s) => Some(s),
+        PointIdOptions::Num(n) => Some(n.to_string()),
+    }
+}
+
+/// Inverse of [`point_id_to_string`]: numeric strings become numeric IDs, anything else a UUID ID.
+pub fn point_id_from_str(point_id: &str) -> PointId {
+    match point_id.parse::<u64>() {
+        Ok(n) => PointId::from(n),
+        Err(_) => PointId::from(point_id.to_string()),
+    }
+}
+
+pub fn payload_string(payload_map: &HashMap<String, Value>, key: &str) -> Option<String> {
+    payload_map.get(key).and_then(|v| match v.kind.as_ref()? {
+        Kind::StringValue(s) => Some(s.clone()),
+        _ => None,
+    })
+}
+
+pub fn payload_integer(payload_map: &HashMap<String, Value>, key: &str) -> Option<i64> {
+    payload_map.get(key).and_then(|v| match v.kind.as_ref()? {
+        Kind::IntegerValue(i) => Some(*i),
+        _ => None,
+    })
+}
+
+/// Stores the document's metadata as top-level payload fields, so they can be filtered on.
+/// Undeclared fields are left out.
+pub fn insert_document_metadata(payload: &mut HashMap<String, Value>, metadata: &DocumentMetadata) {
+    let fields = [
+        ("title", &metadata.title),
+        ("language", &metadata.language),
+        ("author", &metadata.author),
+        ("published_at", &metadata.published_at),
+        ("content_type", &metadata.content_type),
+        ("canonical_url", &metadata.canonical_url),
+    ];
+    for (key, value) in fields {
+        if let Some(value) = value {
+            payload.insert(key.to_string(), Value::from(value.clone()));
+        }
+    }
+}
+
+pub fn document_metadata_from_map(payload_map: &HashMap<String, Value>) -> DocumentMetadata {
+    DocumentMetadata {
+        title: payload_string(payload_map, "title"),
+        language: payload_string(payload_map, "language"),
+        author: payload_string(payload_map, "author"),
+        published_at: payload_string(payload_map, "published_at"),
+        content_type: payload_string(payload_map, "content_type"),
+        canonical_url: payload_string(payload_map, "canonical_url"),
+    }
+}
+
+/// Stores where a chunked document's points came from. Documents sent whole get no fields.
+pub fn insert_chunk_fields(
+    payload: &mut HashMap<String, Value>,
+    chunk_index: Option<u32>,
+    total_chunks: Option<u32>,
+    parent_document_id: Option<DocumentId>,
+) {
+    if let Some(chunk_index) = chunk_index {
+        payload.insert(
+            "chunk_index".to_string(),
+            Value::from(i64::from(chunk_index)),
+        );
+    }
+    if let Some(total_chunks) = total_chunks {
+        payload.insert(
+            "total_chunks".to_string(),
+            Value::from(i64::from(total_chunks)),
+        );
+    }
+    if let Some(parent_document_id) = parent_document_id {
+        payload.insert(
+            "parent_document_id".to_string(),
+            Value::from(parent_document_id.to_string()),
+        );
+    }
+}
+
+pub fn qdrant_payload_from_map(payload_map: &HashMap<String, Value>) -> QdrantPointPayload {
+    QdrantPointPayload {
+        original_document_id: payload_string(payload_map, "original_document_id")
+            .and_then(|id| id.parse().ok())
+            .unwrap_or_default(),
+        source_url: payload_string(payload_map, "source_url").unwrap_or_default(),
+        sentence_text: payload_string(payload_map, "sentence_text").unwrap_or_default(),
+        sentence_order: payload_integer(payload_map, "sentence_order").unwrap_or(0) as u32,
+        model_name: payload_string(payload_map, "model_name").unwrap_or_default(),
+        processed_at_ms: payload_integer(payload_map, "processed_at_ms").unwrap_or(0) as u64,
+        tenant_id: payload_string(payload_map, "tenant_id"),
+        metadata: document_metadata_from_map(payload_map),
+        chunk_index: payload_integer(payload_map, "chunk_index").map(|index| index as u32),
+        total_chunks: payload_integer(payload_map, "total_chunks").map(|total| total as u32),
+        parent_document_id: payload_string(payload_map, "parent_document_id")
+            .and_then(|id| id.parse().ok()),
+    }
+}
+
+pub fn group_id_to_string(group_id: Option<GroupId>) -> Option<String> {
+    match group_id?.kind? {
+        group_id::Kind::StringValue(s) => Some(s),
+        group_id::Kind::UnsignedValue(n) => Some(n.to_string()),
+        group_id::Kind::IntegerValue(i) => Some(i.to_string()),
+    }
+}
@@ -0,0 +1,109 @@
+use shared_models::{
+    DocumentId, QdrantPointPayload, ReembedSentence, ReembedTextTask, VectorReindexTask,
+};
+use std::collections::BTreeMap;
+
+/// Groups one scroll page of stored points into a [`ReembedTextTask`] per document. Points
+/// are scrolled by id, not by document, so a document can be split over several pages and
+/// get a task from each.
+pub fn reembed_tasks(
+    task: &VectorReindexTask,
+    payloads: impl IntoIterator<Item = QdrantPointPayload>,
+) -> Vec<ReembedTextTask> {
+    let mut documents: BTreeMap<DocumentId, ReembedTextTask> = BTreeMap::new();
+    for payload in payloads {
+        documents
+            .entry(payload.original_document_id)
+            .or_insert_with(|| ReembedTextTask {
+                reindex_id: task.request_id,
+                original_id: payload.original_document_id,
+                source_url: payload.source_url.clone(),
+                model_name: task.target_model_name.clone(),
+                sentences: Vec::new(),
+                processed_at_ms: payload.processed_at_ms,
+                tenant_id: payload.tenant_id.clone(),
+                metadata: payload.metadata.clone(),
+                chunk_index: payload.chunk_index,
+                total_chunks: payload.total_chunks,
+                parent_document_id: payload.parent_document_id,
+            })
+            .sentences
+            .push(ReembedSentence {
+                sentence_text: payload.sentence_text,
+                sentence_order: payload.sentence_order,
+            });
+    }
+    documents.into_values().collect()
+}
+
+/// Key of the `Nats-Msg-Id` of `task`: its document and first sentence. The tasks of one
+/// document from different pages hold different sentences, so the stream does not drop
+/// them as duplicates, while republishing the same page still deduplicates.
+pub fn reembed_message_key(task: &ReembedTextTask) -> String {
+    let first_sentence = task
+        .sentences
+        .iter()
+        .map(|sentence| sentence.sentence_order)
+        .min()
+        .unwrap_or_default();
+    format!("{}/{}", task.original_id, first_sentence)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared_models::{DocumentMetadata, RequestId};
+
+    fn point(document: DocumentId, sentence_order: u32) -> QdrantPointPayload {
+        QdrantPointPayload {
+            original_document_id: document,
+            source_url: "https://example.com".to_string(),
+            sentence_text: format!("Sentence {}.", sentence_order),
+            sentence_order,
+            model_name: "old-model".to_string(),
+            processed_at_ms: 42,
+            tenant_id: Some("acme".to_string()),
+            metadata: DocumentMetadata::default(),
+            chunk_index: None,
+            total_chunks: None,
+            parent_document_id: None,
+        }
+    }
+
+    #[test]
+    fn test_document_spanning_two_pages_gets_distinct_message_ids() {
+        let task = VectorReindexTask {
+            request_id: RequestId::generate(),
+            source_model_name: None,
+            target_model_name: "new-model".to_string(),
+            target_vector_dim: 384,
+        };
+        let document = DocumentId::generate();
+        let other = DocumentId::generate();
+
+        let first_page = reembed_tasks(
+            &task,
+            vec![point(document, 3), point(other, 0), point(document, 0)],
+        );
+        let second_page = reembed_tasks(&task, vec![point(document, 2), point(document, 1)]);
+        assert_eq!(first_page.len(), 2);
+        assert_eq!(second_page.len(), 1);
+
+        let first = first_page
+            .iter()
+            .find(|t| t.original_id == document)
+            .unwrap();
+        let second = &second_page[0];
+        assert_eq!(first.sentences.len(), 2);
+        assert_eq!(second.sentences.len(), 2);
+        assert_eq!(second.model_name, "new-model");
+        assert_eq!(second.tenant_id.as_deref(), Some("acme"));
+        assert_ne!(reembed_message_key(first), reembed_message_key(second));
+
+        let republished = reembed_tasks(&task, vec![point(document, 2), point(document, 1)]);
+        assert_eq!(
+            reembed_message_key(&republished[0]),
+            reembed_message_key(second)
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Lock held by the one reindex allowed to run at a time across every replica, kept in the
+//! [`REINDEX_LOCK_BUCKET`] key-value bucket. The holder refreshes it while it runs; the lock
+//! of a replica that stopped, e.g. one that crashed mid-reindex, expires after [`LOCK_TTL`].
+
+use anyhow::{Context, Result};
+use async_nats::jetstream::{self, kv};
+use log::{debug, warn};
+use shared_models::RequestId;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+pub const REINDEX_LOCK_BUCKET: &str = "VECTOR_REINDEX_LOCK";
+const LOCK_KEY: &str = "reindex";
+const LOCK_TTL: Duration = Duration::from_secs(120);
+const LOCK_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The reindex lock of a running reindex; give it back with [`HeldReindexLock::release`].
+pub struct HeldReindexLock {
+    store: kv::Store,
+    request_id: RequestId,
+    refresher: JoinHandle<()>,
+}
+
+/// Takes the reindex lock for `request_id`: `Ok(Err(holder))` names the reindex that
+/// already holds it.
+pub async fn acquire_reindex_lock(
+    jetstream: &jetstream::Context,
+    request_id: RequestId,
+) -> Result<std::result::Result<HeldReindexLock, String>> {
+    let store = match jetstream.get_key_value(REINDEX_LOCK_BUCKET).await {
+        Ok(store) => store,
+        Err(_) => jetstream
+            .create_key_value(kv::Config {
+                bucket: REINDEX_LOCK_BUCKET.to_string(),
+                history: 1,
+                max_age: LOCK_TTL,
+                ..Default::default()
+            })
+            .await
+            .with_context(|| {
+                format!("Failed to create key-value bucket {}", REINDEX_LOCK_BUCKET)
+            })?,
+    };
+
+    let revision = match store
+        .entry(LOCK_KEY)
+        .await
+        .context("Failed to read the reindex lock")?
+    {
+        Some(entry) if entry.operation == kv::Operation::Put => {
+            return Ok(Err(String::from_utf8_lossy(&entry.value).into_owned()));
+        }
+        Some(entry) => entry.revision,
+        None => 0,
+    };
+    // Fails when another replica took the lock since it was read.
+    let revision = match store
+        .update(LOCK_KEY, request_id.to_string().into(), revision)
+        .await
+    {
+        Ok(revision) => revision,
+        Err(e) => {
+            debug!("[REINDEX_LOCK] Lost the race for the reindex lock: {}", e);
+            let holder = store
+                .get(LOCK_KEY)
+                .await
+                .ok()
+                .flatten()
+                .map(|value| String::from_utf8_lossy(&value).into_owned())
+                .unwrap_or_default();
+            return Ok(Err(holder));
+        }
+    };
+
+    let refresher = tokio::spawn(refresh(store.clone(), request_id, revision));
+    Ok(Ok(HeldReindexLock {
+        store,
+        request_id,
+        refresher,
+    }))
+}
+
+/// Rewrites the lock before it expires, for as long as this replica still holds it.
+async fn refresh(store: kv::Store, request_id: RequestId, mut revision: u64) {
+    loop {
+        tokio::time::sleep(LOCK_REFRESH_INTERVAL).await;
+        match store
+            .update(LOCK_KEY, request_id.to_string().into(), revision)
+            .await
+        {
+            Ok(next) => revision = next,
+            Err(e) => {
+                warn!(
+                    "[REINDEX_LOCK] Failed to refresh the lock of reindex {}: {}",
+                    request_id, e
+                );
+                if e.kind() != kv::UpdateErrorKind::TimedOut {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl HeldReindexLock {
+    pub async fn release(self) {
+        self.refresher.abort();
+        let still_held = self
+            .store
+            .get(LOCK_KEY)
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|value| value.as_ref() == self.request_id.to_string().as_bytes());
+        if !still_held {
+            return;
+        }
+        if let Err(e) = self.store.delete(LOCK_KEY).await {
+            warn!(
+                "[REINDEX_LOCK] Failed to release the lock of reindex {}, it expires in {:?}: {}",
+                self.request_id, LOCK_TTL, e
+            );
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use crate::config::{env_parse_or, env_var};
+use log::{info, warn};
+use shared_models::Timestamp;
+use std::time::Duration;
+
+const DEFAULT_RETENTION_INTERVAL_SECS: u64 = 3600;
+const SECS_PER_HOUR: u64 = 3600;
+
+/// Maximum age for points whose `source_url` matches `pattern` (`*` matches any run of characters).
+#[derive(Debug, Clone)]
+pub struct RetentionRule {
+    pub pattern: String,
+    pub max_age: Duration,
+}
+
+/// How long stored points are kept, based on their `processed_at_ms`.
+///
+/// Rules are checked in order and the first matching one wins; points matching no rule
+/// fall back to `default_max_age`, and are kept forever when that is unset.
+#[derive(Debug, Clone)]
+pub struct RetentionPolicy {
+    pub default_max_age: Option<Duration>,
+    pub rules: Vec<RetentionRule>,
+    pub interval: Duration,
+}
+
+impl RetentionPolicy {
+    /// Reads `QDRANT_RETENTION_MAX_AGE_HOURS` and `QDRANT_RETENTION_RULES`
+    /// (`pattern=hours` pairs separated by commas). Returns `None` when neither is set.
+    pub fn from_env() -> Option<Self> {
+        let default_max_age = env_var("QDRANT_RETENTION_MAX_AGE_HOURS")
+            .and_then(|raw| match raw.trim().parse::<u64>() {
+                Ok(0) => None,
+                Ok(hours) => Some(Duration::from_secs(hours * SECS_PER_HOUR)),
+                Err(_) => {
+                    warn!(
+                        "[CONFIG] Invalid value '{}' for QDRANT_RETENTION_MAX_AGE_HOURS, retention default disabled",
+                        raw
+                    );
+                    None
+                }
+            });
+        let rules = env_var("QDRANT_RETENTION_RULES")
+            .map(|raw| parse_rules(&raw))
+            .unwrap_or_default();
+
+        if default_max_age.is_none() && rules.is_empty() {
+            return None;
+        }
+
+        let policy = RetentionPolicy {
+            default_max_age,
+            rules,
+            interval: Duration::from_secs(
+                env_parse_or(
+                    "QDRANT_RETENTION_INTERVAL_SECS",
+                    DEFAULT_RETENTION_INTERVAL_SECS,
+                )
+                .max(1),
+            ),
+        };
+        info!("[CONFIG] Vector retention policy: {:?}", policy);
+        Some(policy)
+    }
+
+    pub fn max_age_for(&self, source_url: &str) -> Option<Duration> {
+        self.rules
+            .iter()
+            .find(|rule| glob_matches(&rule.pattern, source_url))
+            .map(|rule| rule.max_age)
+            .or(self.default_max_age)
+    }
+
+    /// The shortest retention window of any rule or the default. Points younger than this
+    /// are never expired, so cleanup only has to look at points older than it.
+    pub fn shortest_max_age(&self) -> Option<Duration> {
+        self.rules
+            .iter()
+            .map(|rule| rule.max_age)
+            .chain(self.default_max_age)
+            .min()
+    }
+
+    pub fn is_expired(&self, source_url: &str, processed_at: Timestamp, now: Timestamp) -> bool {
+        self.max_age_for(source_url)
+            .is_some_and(|max_age| processed_at.is_older_than(max_age, now))
+    }
+}
+
+fn parse_rules(raw: &str) -> Vec<RetentionRule> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .filter_map(|entry| {
+            let parsed = entry.rsplit_once('=').and_then(|(pattern, hours)| {
+                let hours: u64 = hours.trim().parse().ok()?;
+                Some((pattern.trim(), hours))
+            });
+            match parsed {
+                Some((pattern, hours)) if !pattern.is_empty() => Some(RetentionRule {
+                    pattern: pattern.to_string(),
+                    max_age: Duration::from_secs(hours * SECS_PER_HOUR),
+                }),
+                _ => {
+                    warn!(
+                        "[CONFIG] Ignoring malformed QDRANT_RETENTION_RULES entry '{}' (expected pattern=hours)",
+                        entry
+                    );
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Wildcard match where `*` matches any (possibly empty) run of characters.
+fn glob_matches(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // No wildcard at all: the pattern must match the whole text.
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
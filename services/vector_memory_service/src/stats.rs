@@ -0,0 +1,60 @@
+use crate::DENSE_VECTOR_NAME;
+use qdrant_client::qdrant::{
+    CollectionInfo, CollectionStatus, PayloadSchemaType, vectors_config::Config,
+};
+use shared_models::{PayloadIndexStats, VectorCollectionStats};
+
+/// Flattens Qdrant's collection info into the stats shape reported over NATS.
+pub fn collection_stats_from_info(
+    collection_name: &str,
+    info: CollectionInfo,
+) -> VectorCollectionStats {
+    let status = CollectionStatus::try_from(info.status)
+        .map(|status| status.as_str_name().to_lowercase())
+        .unwrap_or_else(|_| "unknown".to_string());
+
+    let params = info.config.and_then(|config| config.params);
+    let payload_on_disk = params.as_ref().map(|p| p.on_disk_payload);
+    let default_vector_params =
+        params
+            .and_then(|params| params.vectors_config)
+            .and_then(|vectors_config| match vectors_config.config? {
+                Config::Params(vector_params) => Some(vector_params),
+                Config::ParamsMap(mut params_map) => params_map.map.remove(DENSE_VECTOR_NAME),
+            });
+
+    let points_count = info.points_count.unwrap_or(0);
+    let vector_size = default_vector_params.as_ref().map(|p| p.size);
+
+    let mut payload_indexes: Vec<PayloadIndexStats> = info
+        .payload_schema
+        .into_iter()
+        .map(|(field_name, schema)| PayloadIndexStats {
+            field_name,
+            data_type: PayloadSchemaType::try_from(schema.data_type)
+                .map(|t| t.as_str_name().to_string())
+                .unwrap_or_else(|_| "Unknown".to_string()),
+            indexed_points: schema.points.unwrap_or(0),
+        })
+        .collect();
+    payload_indexes.sort_by(|a, b| a.field_name.cmp(&b.field_name));
+
+    VectorCollectionStats {
+        collection_name: collection_name.to_string(),
+        status,
+        optimizer_ok: info.optimizer_status.as_ref().is_none_or(|s| s.ok),
+        optimizer_error: info
+            .optimizer_status
+            .map(|s| s.error)
+            .filter(|error| !error.is_empty()),
+        points_count,
+        indexed_vectors_count: info.indexed_vectors_count.unwrap_or(0),
+        segments_count: info.segments_count,
+        vector_size,
+        vectors_on_disk: default_vector_params.as_ref().and_then(|p| p.on_disk),
+        payload_on_disk,
+        estimated_vector_bytes: vector_size
+            .map(|size| points_count * size * size_of::<f32>() as u64),
+        payload_indexes,
+    }
+}
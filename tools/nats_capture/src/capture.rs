@@ -0,0 +1,229 @@
+//! Captured messages and the two places they are kept: a JSON-lines file written by a core
+//! NATS subscription, or a JetStream stream sourcing the pipeline streams.
+
+use async_nats::jetstream::{self, consumer, stream};
+use async_nats::{Client, HeaderMap};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use log::{info, warn};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use shared_nats::{
+    DEAD_LETTERS_STREAM, EMBEDDINGS_STREAM, PERCEIVE_TASKS_STREAM, RAW_TEXT_STREAM,
+    REEMBED_TASKS_STREAM, StreamSpec, TOKENIZED_TEXT_STREAM,
+};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+pub type CaptureError = Box<dyn Error + Send + Sync>;
+
+/// Streams a capture stream can source subjects from.
+const SOURCE_STREAMS: &[StreamSpec] = &[
+    PERCEIVE_TASKS_STREAM,
+    RAW_TEXT_STREAM,
+    REEMBED_TASKS_STREAM,
+    EMBEDDINGS_STREAM,
+    TOKENIZED_TEXT_STREAM,
+    DEAD_LETTERS_STREAM,
+];
+
+/// One recorded message, a line of a capture file. The body is base64 in JSON.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct CapturedMessage {
+    /// When the message was received, or stored by the stream it was read from.
+    pub timestamp_ms: u64,
+    pub subject: String,
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    #[serde(with = "base64_body")]
+    pub body_base64: Vec<u8>,
+}
+
+impl CapturedMessage {
+    fn new(timestamp_ms: u64, subject: &str, headers: Option<&HeaderMap>, body: &[u8]) -> Self {
+        let headers = headers
+            .into_iter()
+            .flat_map(HeaderMap::iter)
+            .filter_map(|(name, values)| {
+                let value = values.first()?;
+                Some((name.to_string(), value.as_str().to_string()))
+            })
+            .collect();
+        CapturedMessage {
+            timestamp_ms,
+            subject: subject.to_string(),
+            headers,
+            body_base64: body.to_vec(),
+        }
+    }
+}
+
+impl fmt::Debug for CapturedMessage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CapturedMessage")
+            .field("timestamp_ms", &self.timestamp_ms)
+            .field("subject", &self.subject)
+            .field("headers", &self.headers)
+            .field(
+                "body_base64",
+                &format_args!("[{} bytes]", self.body_base64.len()),
+            )
+            .finish()
+    }
+}
+
+mod base64_body {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(body: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(body))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| serde::de::Error::custom(format!("invalid base64 body: {}", e)))
+    }
+}
+
+/// Appends every message on `subjects` to the file at `path` until `stop` resolves, and
+/// returns how many were written.
+pub async fn record_to_file(
+    client: &Client,
+    path: &Path,
+    subjects: &[String],
+    stop: impl Future<Output = ()>,
+) -> Result<u64, CaptureError> {
+    let mut subscriptions = Vec::new();
+    for subject in subjects {
+        subscriptions.push(client.subscribe(subject.clone()).await?);
+    }
+    let mut messages = futures::stream::select_all(subscriptions).take_until(Box::pin(stop));
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut writer = BufWriter::new(file);
+    info!(
+        "[CAPTURE] Recording {} to {}",
+        subjects.join(", "),
+        path.display()
+    );
+
+    let mut recorded = 0;
+    while let Some(message) = messages.next().await {
+        let captured = CapturedMessage::new(
+            shared_models::current_timestamp_ms(),
+            &message.subject,
+            message.headers.as_ref(),
+            &message.payload,
+        );
+        serde_json::to_writer(&mut writer, &captured)?;
+        writer.write_all(b"\n")?;
+        recorded += 1;
+    }
+    writer.flush()?;
+    Ok(recorded)
+}
+
+/// The messages of a capture file, oldest first.
+pub fn read_file(
+    path: &Path,
+) -> Result<BoxStream<'static, Result<CapturedMessage, CaptureError>>, CaptureError> {
+    let lines = BufReader::new(std::fs::File::open(path)?).lines();
+    let messages = lines.enumerate().filter_map(|(index, line)| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(
+            serde_json::from_str(&line).map_err(|e| format!("line {}: {}", index + 1, e).into()),
+        ),
+        Err(e) => Some(Err(e.into())),
+    });
+    Ok(futures::stream::iter(messages).boxed())
+}
+
+/// The pipeline stream holding `subject`; only subjects captured by one can be sourced.
+fn source_stream(subject: &str) -> Option<&'static StreamSpec> {
+    SOURCE_STREAMS.iter().find(|stream| {
+        stream.subjects.iter().any(|captured| {
+            captured == &subject
+                || captured
+                    .strip_suffix('>')
+                    .is_some_and(|prefix| subject.starts_with(prefix))
+        })
+    })
+}
+
+/// Creates the stream `name`, sourcing the messages published on `subjects` from now on
+/// out of the pipeline streams. The server keeps recording until the stream is deleted.
+pub async fn create_capture_stream(
+    jetstream: &jetstream::Context,
+    name: &str,
+    subjects: &[String],
+) -> Result<(), CaptureError> {
+    let mut sources = Vec::new();
+    for subject in subjects {
+        let spec = source_stream(subject).ok_or_else(|| {
+            format!(
+                "{} is not persisted in a stream; record it to a file instead",
+                subject
+            )
+        })?;
+        let origin = spec.ensure(jetstream).await?;
+        sources.push(stream::Source {
+            name: spec.stream_name(),
+            start_sequence: Some(origin.cached_info().state.last_sequence + 1),
+            filter_subject: Some(subject.clone()),
+            ..Default::default()
+        });
+    }
+    jetstream
+        .create_stream(stream::Config {
+            name: name.to_string(),
+            sources: Some(sources),
+            ..Default::default()
+        })
+        .await?;
+    info!(
+        "[CAPTURE] Stream {} records {} until it is deleted",
+        name,
+        subjects.join(", ")
+    );
+    Ok(())
+}
+
+/// The messages stored in stream `name` when this is called, oldest first.
+pub async fn read_stream(
+    jetstream: &jetstream::Context,
+    name: &str,
+) -> Result<BoxStream<'static, Result<CapturedMessage, CaptureError>>, CaptureError> {
+    let capture = jetstream.get_stream(name).await?;
+    // Replays published into the sourced streams are captured again; stop before them.
+    let stored = capture.cached_info().state.messages;
+    if stored == 0 {
+        warn!("[CAPTURE] Stream {} holds no messages", name);
+    }
+    let consumer = capture
+        .create_consumer(consumer::pull::OrderedConfig::default())
+        .await?;
+    let messages = consumer
+        .messages()
+        .await?
+        .take(stored as usize)
+        .map(|message| {
+            let message = message?;
+            let published = message.info()?.published;
+            Ok(CapturedMessage::new(
+                (published.unix_timestamp_nanos() / 1_000_000) as u64,
+                &message.subject,
+                message.headers.as_ref(),
+                &message.payload,
+            ))
+        });
+    Ok(messages.boxed())
+}
@@ -0,0 +1,197 @@
+//! Records selected subjects and replays them later, e.g. to reprocess documents after a
+//! bug fix or to load-test the pipeline with real traffic.
+//!
+//! A recording goes to a JSON-lines file, one message with its receive time, headers and
+//! base64 body per line, or to a JetStream stream that sources the subjects out of the
+//! pipeline streams and keeps recording server-side until it is deleted. A replay
+//! republishes the messages on their original subjects at the pace they were recorded at,
+//! or `--speed` times faster.
+
+mod capture;
+mod replay;
+
+use async_nats::jetstream;
+use futures::StreamExt;
+use shared_config::Settings;
+use shared_nats::Shutdown;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const SERVICE_NAME: &str = "nats_capture";
+
+const USAGE: &str = "\
+usage: nats_capture record <FILE> <SUBJECT>... [--duration <SECONDS>]
+       nats_capture record --stream <NAME> <SUBJECT>...
+       nats_capture replay <FILE> [--speed <FACTOR>]
+       nats_capture replay --stream <NAME> [--speed <FACTOR>]
+
+A file recording runs until --duration passes or it is interrupted. --speed 1 (the default)
+keeps the recorded timing, 10 replays ten times faster and 0 as fast as possible.";
+
+enum Store {
+    File(PathBuf),
+    Stream(String),
+}
+
+enum Command {
+    Record {
+        store: Store,
+        subjects: Vec<String>,
+        duration: Option<Duration>,
+    },
+    Replay {
+        store: Store,
+        speed: f64,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut settings = match Settings::load(SERVICE_NAME) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("nats_capture: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let command = match parse_args(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("nats_capture: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    // A one-off run has no metrics worth scraping.
+    settings.metrics.addr = String::new();
+    let _telemetry = match shared_telemetry::init(SERVICE_NAME, "info", &settings, String::new) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("nats_capture: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    match run(command, &settings).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("nats_capture: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(command: Command, settings: &Settings) -> Result<(), capture::CaptureError> {
+    let shutdown = Shutdown::listen();
+    let client = shared_nats::connect(&settings.nats).await?;
+    let jetstream = jetstream::new(client.clone());
+    match command {
+        Command::Record {
+            store: Store::File(path),
+            subjects,
+            duration,
+        } => {
+            let stop = async move {
+                match duration {
+                    Some(duration) => {
+                        tokio::select! {
+                            _ = shutdown.signalled() => {}
+                            _ = tokio::time::sleep(duration) => {}
+                        }
+                    }
+                    None => shutdown.signalled().await,
+                }
+            };
+            let recorded = capture::record_to_file(&client, &path, &subjects, stop).await?;
+            println!("Recorded {} message(s) to {}", recorded, path.display());
+        }
+        Command::Record {
+            store: Store::Stream(name),
+            subjects,
+            ..
+        } => {
+            capture::create_capture_stream(&jetstream, &name, &subjects).await?;
+            println!(
+                "Stream {} is recording; replay it with `nats_capture replay --stream {}`",
+                name, name
+            );
+        }
+        Command::Replay { store, speed } => {
+            let messages = match &store {
+                Store::File(path) => capture::read_file(path)?,
+                Store::Stream(name) => capture::read_stream(&jetstream, name).await?,
+            };
+            let messages = messages.take_until(shutdown.signalled()).boxed();
+            let replayed = replay::replay(&client, messages, speed).await?;
+            println!("Replayed {} message(s)", replayed);
+        }
+    }
+    Ok(())
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Command, String> {
+    let command = args.next().ok_or("missing command")?;
+    let mut positional = Vec::new();
+    let mut stream = None;
+    let mut duration = None;
+    let mut speed = 1.0;
+    while let Some(arg) = args.next() {
+        if !arg.starts_with("--") {
+            positional.push(arg);
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        match arg.as_str() {
+            "--stream" => stream = Some(value),
+            "--duration" => {
+                let seconds = value.parse().map_err(|_| {
+                    format!("--duration must be a number of seconds, not '{}'", value)
+                })?;
+                duration = Some(Duration::from_secs(seconds));
+            }
+            "--speed" => {
+                speed = value
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|speed| speed.is_finite() && *speed >= 0.0)
+                    .ok_or_else(|| {
+                        format!("--speed must be a factor of 0 or more, not '{}'", value)
+                    })?;
+            }
+            _ => return Err(format!("unknown option '{}'", arg)),
+        }
+    }
+
+    let store = match stream {
+        Some(name) => Store::Stream(name),
+        None if positional.is_empty() => return Err("missing the capture file".to_string()),
+        None => Store::File(PathBuf::from(positional.remove(0))),
+    };
+    match command.as_str() {
+        "record" => {
+            if positional.is_empty() {
+                return Err("name at least one subject to record".to_string());
+            }
+            if duration.is_some() && matches!(store, Store::Stream(_)) {
+                return Err(
+                    "--duration only applies to files; a stream records until it is deleted"
+                        .to_string(),
+                );
+            }
+            Ok(Command::Record {
+                store,
+                subjects: positional,
+                duration,
+            })
+        }
+        "replay" => {
+            if let Some(extra) = positional.first() {
+                return Err(format!("unexpected argument '{}'", extra));
+            }
+            Ok(Command::Replay { store, speed })
+        }
+        _ => Err(format!("unknown command '{}'", command)),
+    }
+}
@@ -0,0 +1,54 @@
+//! Republishing captured messages on their original subjects, at the pace they were
+//! captured at or faster.
+
+use crate::capture::{CaptureError, CapturedMessage};
+use async_nats::{Client, HeaderMap};
+use futures::StreamExt;
+use futures::stream::BoxStream;
+use log::info;
+use std::time::{Duration, Instant};
+
+/// Whether header `name` is republished. JetStream's own headers are not, as a kept
+/// `Nats-Msg-Id` would have the stream drop the replay as a duplicate, and neither is the
+/// trace context, so a replay starts traces of its own.
+fn is_replayed(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    !name.starts_with("nats-") && name != "traceparent" && name != "tracestate"
+}
+
+/// Publishes `messages` in order and returns how many were sent. With a `speed` above 0
+/// the gaps between their timestamps are kept, divided by `speed`; 0 sends them all at
+/// once.
+pub async fn replay(
+    client: &Client,
+    mut messages: BoxStream<'_, Result<CapturedMessage, CaptureError>>,
+    speed: f64,
+) -> Result<u64, CaptureError> {
+    let started = Instant::now();
+    let mut first_timestamp_ms = None;
+    let mut replayed = 0;
+    while let Some(message) = messages.next().await {
+        let message = message?;
+        let first_ms = *first_timestamp_ms.get_or_insert(message.timestamp_ms);
+        if speed > 0.0 {
+            let offset = Duration::from_millis(message.timestamp_ms.saturating_sub(first_ms));
+            tokio::time::sleep_until((started + offset.div_f64(speed)).into()).await;
+        }
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &message.headers {
+            if is_replayed(name) {
+                headers.insert(name.as_str(), value.as_str());
+            }
+        }
+        client
+            .publish_with_headers(message.subject, headers, message.body_base64.into())
+            .await?;
+        replayed += 1;
+        if replayed % 1000 == 0 {
+            info!("[REPLAY] Replayed {} messages...", replayed);
+        }
+    }
+    client.flush().await?;
+    Ok(replayed)
+}
@@ -0,0 +1,114 @@
+//! End-to-end scenario runner for the pipeline. It submits a URL, or fixture text straight
+//! to preprocessing, follows the submission through the `events.task.status` of each stage
+//! and prints a pass/fail trace with the time each stage took to complete.
+//!
+//! The exit code is 0 when every stage completed, 1 when one failed or timed out and 2 for
+//! bad arguments, so the runner can back automated smoke tests as well as manual checks.
+
+mod scenario;
+
+use scenario::{Scenario, Submission};
+use shared_config::{Settings, env_parse_or};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+const SERVICE_NAME: &str = "nats_tester";
+
+const USAGE: &str = "\
+usage: nats_tester url <URL> [--timeout <SECONDS>]
+       nats_tester text <FILE> [--source-url <URL>] [--timeout <SECONDS>]
+
+Waits at most --timeout seconds (default NATS_TESTER_STEP_TIMEOUT_SECS, or 120) for each stage.";
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let mut settings = match Settings::load(SERVICE_NAME) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("nats_tester: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let scenario = match parse_args(std::env::args().skip(1)) {
+        Ok(scenario) => scenario,
+        Err(e) => {
+            eprintln!("nats_tester: {}\n\n{}", e, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    // A single run has no metrics worth scraping, and only warnings are worth printing
+    // next to the report; the services' own logs tell what went wrong.
+    settings.metrics.addr = String::new();
+    let _telemetry = match shared_telemetry::init(SERVICE_NAME, "warn", &settings, String::new) {
+        Ok(telemetry) => telemetry,
+        Err(e) => {
+            eprintln!("nats_tester: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let client = match shared_nats::connect(&settings.nats).await {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("nats_tester: {}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match scenario.run(&client).await {
+        Ok(report) => {
+            print!("{}", report);
+            if report.passed() {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            }
+        }
+        Err(e) => {
+            eprintln!("nats_tester: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Scenario, String> {
+    let command = args.next().ok_or("missing scenario")?;
+    let input = args
+        .next()
+        .ok_or_else(|| format!("missing the input of the '{}' scenario", command))?;
+    let mut source_url = None;
+    let mut step_timeout = Duration::from_secs(env_parse_or("NATS_TESTER_STEP_TIMEOUT_SECS", 120));
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", flag))?;
+        match flag.as_str() {
+            "--source-url" => source_url = Some(value),
+            "--timeout" => {
+                let seconds = value.parse().map_err(|_| {
+                    format!("--timeout must be a number of seconds, not '{}'", value)
+                })?;
+                step_timeout = Duration::from_secs(seconds);
+            }
+            _ => return Err(format!("unknown option '{}'", flag)),
+        }
+    }
+
+    let submission = match command.as_str() {
+        "url" if source_url.is_none() => Submission::Url(input),
+        "url" => return Err("--source-url only applies to the 'text' scenario".to_string()),
+        "text" => {
+            let path = PathBuf::from(input);
+            let text = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+            let source_url = source_url.unwrap_or_else(|| format!("file://{}", path.display()));
+            Submission::Text { source_url, text }
+        }
+        _ => return Err(format!("unknown scenario '{}'", command)),
+    };
+    Ok(Scenario {
+        submission,
+        step_timeout,
+    })
+}
@@ -0,0 +1,262 @@
+//! Submitting a scenario's input and following it through the pipeline stages.
+
+use crate::SERVICE_NAME;
+use async_nats::{Client, HeaderMap, jetstream};
+use futures::{StreamExt, future};
+use log::warn;
+use serde::de::DeserializeOwned;
+use shared_models::{
+    DocumentId, Envelope, PerceiveUrlTask, PipelineErrorMessage, PipelineStage, RawTextMessage,
+    TaskId, TaskStatus, TaskStatusChangedMessage, Validate,
+};
+use shared_nats::{PERCEIVE_TASKS_STREAM, RAW_TEXT_STREAM, publish_durable};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const PERCEPTION_URL_TASK_SUBJECT: &str = "tasks.perceive.url";
+const RAW_TEXT_DISCOVERED_SUBJECT: &str = "data.raw_text.discovered";
+const TASK_STATUS_EVENT_SUBJECT: &str = "events.task.status";
+const PIPELINE_ERRORS_WILDCARD_SUBJECT: &str = "errors.>";
+
+/// The stages reporting a URL submission's progress, in pipeline order. knowledge_graph_service
+/// does not report status changes, so a run ends once the embeddings are stored.
+const URL_STAGES: &[PipelineStage] = &[
+    PipelineStage::Scraping,
+    PipelineStage::Preprocessing,
+    PipelineStage::Storage,
+];
+/// Fixture text skips scraping.
+const TEXT_STAGES: &[PipelineStage] = &[PipelineStage::Preprocessing, PipelineStage::Storage];
+
+pub enum Submission {
+    /// A URL for perception_service to scrape.
+    Url(String),
+    /// Text handed to preprocessing_service as if it had been scraped from `source_url`.
+    Text { source_url: String, text: String },
+}
+
+pub struct Scenario {
+    pub submission: Submission,
+    /// How long to wait for each stage after the previous one completed.
+    pub step_timeout: Duration,
+}
+
+enum Event {
+    Status(TaskStatusChangedMessage),
+    Error(PipelineErrorMessage),
+}
+
+impl Scenario {
+    fn stages(&self) -> &'static [PipelineStage] {
+        match self.submission {
+            Submission::Url(_) => URL_STAGES,
+            Submission::Text { .. } => TEXT_STAGES,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match &self.submission {
+            Submission::Url(url) => format!("url {}", url),
+            Submission::Text { source_url, text } => {
+                format!("text from {} ({} bytes)", source_url, text.len())
+            }
+        }
+    }
+
+    /// Submits the input and waits for each stage in turn. Only a failure to submit or to
+    /// follow the submission is an error; failed and timed out stages are in the report.
+    pub async fn run(self, client: &Client) -> Result<Report, Box<dyn Error>> {
+        // Subscribed before submitting, so no status change of the submission is missed.
+        let statuses = client
+            .subscribe(TASK_STATUS_EVENT_SUBJECT)
+            .await?
+            .filter_map(|message| future::ready(decode(&message).map(Event::Status)));
+        let errors = client
+            .subscribe(PIPELINE_ERRORS_WILDCARD_SUBJECT)
+            .await?
+            .filter_map(|message| future::ready(decode(&message).map(Event::Error)));
+        let mut events = futures::stream::select(statuses, errors);
+
+        let jetstream = jetstream::new(client.clone());
+        let mut documents = HashSet::new();
+        let (stream, subject, envelope) = match &self.submission {
+            Submission::Url(url) => {
+                let task = PerceiveUrlTask::new(url.trim());
+                task.validate()?;
+                let envelope = Envelope::new(SERVICE_NAME, &task);
+                (
+                    PERCEIVE_TASKS_STREAM,
+                    PERCEPTION_URL_TASK_SUBJECT,
+                    envelope
+                        .to_vec()
+                        .map(|payload| (envelope.correlation_id, payload)),
+                )
+            }
+            Submission::Text { source_url, text } => {
+                let message = RawTextMessage::new(source_url.as_str(), text.as_str());
+                documents.insert(message.id);
+                let envelope = Envelope::new(SERVICE_NAME, &message);
+                (
+                    RAW_TEXT_STREAM,
+                    RAW_TEXT_DISCOVERED_SUBJECT,
+                    envelope
+                        .to_vec()
+                        .map(|payload| (envelope.correlation_id, payload)),
+                )
+            }
+        };
+        let (correlation_id, payload) = envelope?;
+        let task_id: TaskId = correlation_id.parse()?;
+        stream.ensure(&jetstream).await?;
+        let started = Instant::now();
+        publish_durable(&jetstream, subject, HeaderMap::new(), payload).await?;
+
+        let stages = self.stages();
+        let mut progress = Progress {
+            task_id,
+            documents,
+            completed: HashMap::new(),
+            failure: None,
+        };
+        let mut timed_out = None;
+        let mut current = 0;
+        let mut step_started = started;
+        while current < stages.len() && progress.failure.is_none() {
+            if let Some(elapsed) = progress.completed.get(&stages[current]) {
+                step_started = started + *elapsed;
+                current += 1;
+                continue;
+            }
+            let deadline = step_started + self.step_timeout;
+            match tokio::time::timeout_at(deadline.into(), events.next()).await {
+                Ok(Some(event)) => progress.observe(event, started.elapsed()),
+                Ok(None) => return Err("the NATS subscriptions ended".into()),
+                Err(_) => {
+                    timed_out = Some(stages[current]);
+                    break;
+                }
+            }
+        }
+
+        let steps = stages
+            .iter()
+            .map(|&stage| {
+                let outcome = match &progress.failure {
+                    Some((failed, detail)) if *failed == stage => Outcome::Failed(detail.clone()),
+                    _ => match progress.completed.get(&stage) {
+                        Some(elapsed) => Outcome::Completed(*elapsed),
+                        None if timed_out == Some(stage) => Outcome::TimedOut(self.step_timeout),
+                        None => Outcome::NotReached,
+                    },
+                };
+                (stage, outcome)
+            })
+            .collect();
+        Ok(Report {
+            scenario: self.describe(),
+            task_id,
+            steps,
+        })
+    }
+}
+
+fn decode<T: DeserializeOwned>(message: &async_nats::Message) -> Option<T> {
+    match Envelope::<T>::from_slice(&message.payload) {
+        Ok(envelope) => Some(envelope.payload),
+        Err(e) => {
+            warn!(
+                "[SCENARIO] Failed to decode message on {}: {}",
+                message.subject, e
+            );
+            None
+        }
+    }
+}
+
+/// What has been seen of the submission so far.
+struct Progress {
+    task_id: TaskId,
+    /// The documents of the submission, learnt from its status changes as stages assign them.
+    documents: HashSet<DocumentId>,
+    /// When each stage first completed, from the submission.
+    completed: HashMap<PipelineStage, Duration>,
+    failure: Option<(PipelineStage, String)>,
+}
+
+impl Progress {
+    fn observe(&mut self, event: Event, elapsed: Duration) {
+        match event {
+            Event::Status(status) if status.task_id == self.task_id => {
+                self.documents.extend(status.original_id);
+                match status.status {
+                    TaskStatus::Started => {}
+                    TaskStatus::Completed => {
+                        self.completed.entry(status.stage).or_insert(elapsed);
+                    }
+                    TaskStatus::Failed => {
+                        let detail = status.detail.unwrap_or_else(|| "failed".to_string());
+                        self.fail(status.stage, detail);
+                    }
+                }
+            }
+            Event::Error(error)
+                if error.task_id == Some(self.task_id)
+                    || error
+                        .original_id
+                        .is_some_and(|id| self.documents.contains(&id)) =>
+            {
+                let detail = format!("{:?} error: {}", error.error_kind, error.message);
+                self.fail(error.stage, detail);
+            }
+            _ => {}
+        }
+    }
+
+    /// Keeps the first failure, which the later ones usually follow from.
+    fn fail(&mut self, stage: PipelineStage, detail: String) {
+        self.failure.get_or_insert((stage, detail));
+    }
+}
+
+enum Outcome {
+    /// Completed this long after the submission.
+    Completed(Duration),
+    Failed(String),
+    TimedOut(Duration),
+    NotReached,
+}
+
+/// The pass/fail trace of a run, one line per stage.
+pub struct Report {
+    scenario: String,
+    task_id: TaskId,
+    steps: Vec<(PipelineStage, Outcome)>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.steps
+            .iter()
+            .all(|(_, outcome)| matches!(outcome, Outcome::Completed(_)))
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Scenario: {} (task {})", self.scenario, self.task_id)?;
+        for (stage, outcome) in &self.steps {
+            let (verdict, detail) = match outcome {
+                Outcome::Completed(elapsed) => ("PASS", format!("completed after {:.2?}", elapsed)),
+                Outcome::Failed(detail) => ("FAIL", detail.clone()),
+                Outcome::TimedOut(timeout) => {
+                    ("FAIL", format!("no completion within {:?}", timeout))
+                }
+                Outcome::NotReached => ("SKIP", "not reached".to_string()),
+            };
+            writeln!(f, "  {}  {:<16} {}", verdict, stage.as_str(), detail)?;
+        }
+        writeln!(f, "{}", if self.passed() { "PASS" } else { "FAIL" })
+    }
+}